@@ -0,0 +1,22 @@
+use std::env;
+use std::path::PathBuf;
+
+/// Generate the C header for the library into include/sgidisk.h under OUT_DIR, leaving the
+/// source tree untouched
+fn main() {
+  let crate_dir = env::var("CARGO_MANIFEST_DIR").unwrap();
+  let mut header = PathBuf::from(env::var("OUT_DIR").unwrap());
+  header.push("include");
+  header.push("sgidisk.h");
+
+  let config = cbindgen::Config::from_file(PathBuf::from(&crate_dir).join("cbindgen.toml")).unwrap();
+  cbindgen::Builder::new()
+    .with_crate(&crate_dir)
+    .with_config(config)
+    .generate()
+    .expect("Unable to generate C header")
+    .write_to_file(header);
+
+  println!("cargo:rerun-if-changed=src/lib.rs");
+  println!("cargo:rerun-if-changed=cbindgen.toml");
+}
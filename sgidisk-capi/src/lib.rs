@@ -0,0 +1,542 @@
+//! C ABI over sgidisklib, for linking the parser into C/C++ tools. The header `sgidisk.h` is
+//! generated into `include/` under the build script's `OUT_DIR` by cbindgen when the crate is
+//! built.
+//!
+//! Functions returning `int` return 0 on success and -1 on error; functions returning pointers
+//! return NULL on error. NULL arguments and panics parsing damaged images are errors too. After
+//! an error, `sgidisk_last_error()` describes it.
+
+use std::cell::RefCell;
+use std::ffi::{c_void, CStr, CString};
+use std::fs::File;
+use std::io;
+use std::io::{BufWriter, Write};
+use std::os::raw::{c_char, c_int};
+use std::panic;
+use std::panic::AssertUnwindSafe;
+use std::ptr;
+
+use sgidisklib::efs::{Efs, Inode, InodeType};
+use sgidisklib::efs::dir::Directory;
+use sgidisklib::volhdr::{PartitionType, SgidiskVolume};
+
+thread_local! {
+  /// Description of the last error on this thread
+  static LAST_ERROR: RefCell<Option<CString>> = RefCell::new(None);
+}
+
+/// Open disk image and its Volume Header
+pub struct SgidiskImage {
+  file: File,
+  volume: SgidiskVolume,
+}
+
+/// Open EFS filesystem from a partition of a disk image
+pub struct SgidiskEfs {
+  file: File,
+  efs: Efs,
+  /// Path and inode of the file last read by `sgidisk_efs_read()`, which is usually called
+  /// repeatedly for the same file
+  last_read: Option<(String, Inode, )>,
+}
+
+/// Partition table entry
+#[repr(C)]
+pub struct SgidiskPartition {
+  /// Whether the partition is in use
+  pub in_use: bool,
+  /// Partition type ID, as in the Volume Header (e.g. 7 for EFS)
+  pub partition_type: i32,
+  /// First block of partition
+  pub block_start: u64,
+  /// Size of partition in blocks
+  pub block_sz: u64,
+}
+
+/// Volume directory entry
+#[repr(C)]
+pub struct SgidiskVolumeFile {
+  /// Whether the entry is in use
+  pub in_use: bool,
+  /// NUL terminated file name
+  pub file_name: [c_char; 9],
+  /// First block of file
+  pub block_start: u64,
+  /// Size of file in bytes
+  pub file_sz: u64,
+}
+
+/// Type of EFS inode
+#[repr(C)]
+#[derive(Copy, Clone)]
+pub enum SgidiskInodeType {
+  Fifo,
+  CharacterSpecial,
+  CharacterSpecialLink,
+  Directory,
+  BlockSpecial,
+  BlockSpecialLink,
+  RegularFile,
+  SymbolicLink,
+  Socket,
+}
+
+/// Metadata of an EFS entry
+#[repr(C)]
+pub struct SgidiskStat {
+  /// Inode number
+  pub inode: u64,
+  pub inode_type: SgidiskInodeType,
+  /// Permission bits
+  pub mode: u16,
+  pub uid: u16,
+  pub gid: u16,
+  /// Size in bytes
+  pub size: u64,
+  /// Access, modification and creation times, in seconds since the epoch
+  pub atime: i64,
+  pub mtime: i64,
+  pub ctime: i64,
+  /// Device number of device inodes, otherwise 0
+  pub device_major: u32,
+  pub device_minor: u32,
+}
+
+/// Callback for each entry listed by `sgidisk_efs_list()`. Returning non-zero stops the listing.
+pub type SgidiskListCallback = Option<extern "C" fn(name: *const c_char, stat: *const SgidiskStat, user_data: *mut c_void) -> c_int>;
+
+/// Record an error for `sgidisk_last_error()`
+fn set_error(message: String) {
+  let message = CString::new(message).unwrap_or_else(|_| CString::new("Error message contained NUL").unwrap());
+  LAST_ERROR.with(|e| *e.borrow_mut() = Some(message));
+}
+
+/// Record an error, returning -1
+fn fail(message: String) -> c_int {
+  set_error(message);
+  -1
+}
+
+/// Run the body of an entry point, so that a panic parsing a damaged image is recorded as an
+/// error and `failed` returned, rather than unwinding into C
+fn guard<T, F>(failed: T, body: F) -> T
+  where F: FnOnce() -> T {
+  match panic::catch_unwind(AssertUnwindSafe(body)) {
+    Ok(result) => result,
+    Err(payload) => {
+      let message = match (payload.downcast_ref::<&str>(), payload.downcast_ref::<String>(), ) {
+        (Some(message), _, ) => message.to_string(),
+        (_, Some(message), ) => message.clone(),
+        _ => "panic".to_string()
+      };
+      set_error(format!("internal error: {}", message));
+      failed
+    }
+  }
+}
+
+/// Borrow a C string argument as a &str
+unsafe fn str_arg<'a>(s: *const c_char, name: &str) -> Result<&'a str, String> {
+  if s.is_null() {
+    return Err(format!("{} is NULL", name));
+  }
+  match CStr::from_ptr(s).to_str() {
+    Ok(s) => Ok(s),
+    Err(_) => Err(format!("{} is not valid UTF-8", name))
+  }
+}
+
+/// Borrow a pointer argument
+unsafe fn ref_arg<'a, T>(p: *const T, name: &str) -> Result<&'a T, String> {
+  p.as_ref().ok_or_else(|| format!("{} is NULL", name))
+}
+
+/// Mutably borrow a pointer argument
+unsafe fn mut_arg<'a, T>(p: *mut T, name: &str) -> Result<&'a mut T, String> {
+  p.as_mut().ok_or_else(|| format!("{} is NULL", name))
+}
+
+/// Writer into a buffer from C, through a raw pointer as its bytes may be uninitialised
+struct RawBuf {
+  buf: *mut u8,
+  len: usize,
+  /// Number of bytes written
+  pos: usize,
+}
+
+impl Write for RawBuf {
+  fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+    let n = data.len().min(self.len - self.pos);
+    // Safe as the caller of sgidisk_efs_read() gave at least `len` writable bytes
+    unsafe { ptr::copy_nonoverlapping(data.as_ptr(), self.buf.add(self.pos), n) };
+    self.pos += n;
+    Ok(n)
+  }
+
+  fn flush(&mut self) -> io::Result<()> {
+    Ok(())
+  }
+}
+
+/// Stat of an inode
+fn stat(inode_id: u64, inode: &Inode) -> SgidiskStat {
+  SgidiskStat {
+    inode: inode_id,
+    inode_type: match inode.inode_type {
+      InodeType::Fifo => SgidiskInodeType::Fifo,
+      InodeType::CharacterSpecial => SgidiskInodeType::CharacterSpecial,
+      InodeType::CharacterSpecialLink => SgidiskInodeType::CharacterSpecialLink,
+      InodeType::Directory => SgidiskInodeType::Directory,
+      InodeType::BlockSpecial => SgidiskInodeType::BlockSpecial,
+      InodeType::BlockSpecialLink => SgidiskInodeType::BlockSpecialLink,
+      InodeType::RegularFile => SgidiskInodeType::RegularFile,
+      InodeType::SymbolicLink => SgidiskInodeType::SymbolicLink,
+      InodeType::Socket => SgidiskInodeType::Socket
+    },
+    mode: inode.unix_mode,
+    uid: inode.owner_uid,
+    gid: inode.owner_gid,
+    size: inode.size,
+    atime: inode.atime_raw,
+    mtime: inode.mtime_raw,
+    ctime: inode.ctime_raw,
+    device_major: inode.device.map_or(0, |d| d.major),
+    device_minor: inode.device.map_or(0, |d| d.minor),
+  }
+}
+
+/// Description of the last error on the calling thread, or NULL if there hasn't been one. The
+/// string is valid until the next call on this thread.
+#[no_mangle]
+pub extern "C" fn sgidisk_last_error() -> *const c_char {
+  LAST_ERROR.with(|e| match e.borrow().as_ref() {
+    Some(message) => message.as_ptr(),
+    None => ptr::null()
+  })
+}
+
+/// Open a disk image and read its Volume Header. Close with `sgidisk_close()`.
+///
+/// # Safety
+/// `path` must be a valid NUL terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn sgidisk_open(path: *const c_char) -> *mut SgidiskImage {
+  guard(ptr::null_mut(), || {
+    let path = match str_arg(path, "path") {
+      Ok(path) => path,
+      Err(e) => {
+        set_error(e);
+        return ptr::null_mut();
+      }
+    };
+
+    let mut file = match File::open(path) {
+      Ok(file) => file,
+      Err(e) => {
+        set_error(format!("Unable to open disk image '{}': {:?}", path, &e));
+        return ptr::null_mut();
+      }
+    };
+    match SgidiskVolume::read(&mut file) {
+      Ok(volume) => Box::into_raw(Box::new(SgidiskImage { file, volume })),
+      Err(e) => {
+        set_error(format!("Unable to read Volume Header from disk image '{}': {:?}", path, &e));
+        ptr::null_mut()
+      }
+    }
+  })
+}
+
+/// Close a disk image opened by `sgidisk_open()`
+///
+/// # Safety
+/// `image` must be NULL or returned by `sgidisk_open()`, and not already closed.
+#[no_mangle]
+pub unsafe extern "C" fn sgidisk_close(image: *mut SgidiskImage) {
+  guard((), || {
+    if !image.is_null() {
+      drop(Box::from_raw(image));
+    }
+  })
+}
+
+/// Number of entries in the partition table, or 0 if `image` is NULL
+///
+/// # Safety
+/// `image` must be NULL or an open disk image.
+#[no_mangle]
+pub unsafe extern "C" fn sgidisk_partition_count(image: *const SgidiskImage) -> usize {
+  guard(0, || match ref_arg(image, "image") {
+    Ok(image) => image.volume.partitions.len(),
+    Err(e) => {
+      set_error(e);
+      0
+    }
+  })
+}
+
+/// Get a partition table entry
+///
+/// # Safety
+/// `image` must be NULL or an open disk image, and `out` NULL or pointing to an
+/// SgidiskPartition.
+#[no_mangle]
+pub unsafe extern "C" fn sgidisk_partition(image: *const SgidiskImage, id: usize, out: *mut SgidiskPartition) -> c_int {
+  guard(-1, || {
+    let (image, out, ) = match (ref_arg(image, "image"), mut_arg(out, "out"), ) {
+      (Ok(image), Ok(out), ) => (image, out, ),
+      (Err(e), _, ) | (_, Err(e), ) => return fail(e)
+    };
+    let p = match image.volume.partitions.get(id) {
+      Some(p) => p,
+      None => return fail(format!("No partition {}", id))
+    };
+
+    *out = SgidiskPartition {
+      in_use: p.in_use(),
+      partition_type: p.partition_type as i32,
+      block_start: p.block_start,
+      block_sz: p.block_sz,
+    };
+    0
+  })
+}
+
+/// Number of entries in the volume directory, or 0 if `image` is NULL
+///
+/// # Safety
+/// `image` must be NULL or an open disk image.
+#[no_mangle]
+pub unsafe extern "C" fn sgidisk_volume_file_count(image: *const SgidiskImage) -> usize {
+  guard(0, || match ref_arg(image, "image") {
+    Ok(image) => image.volume.files.len(),
+    Err(e) => {
+      set_error(e);
+      0
+    }
+  })
+}
+
+/// Get a volume directory entry
+///
+/// # Safety
+/// `image` must be NULL or an open disk image, and `out` NULL or pointing to an
+/// SgidiskVolumeFile.
+#[no_mangle]
+pub unsafe extern "C" fn sgidisk_volume_file(image: *const SgidiskImage, id: usize, out: *mut SgidiskVolumeFile) -> c_int {
+  guard(-1, || {
+    let (image, out, ) = match (ref_arg(image, "image"), mut_arg(out, "out"), ) {
+      (Ok(image), Ok(out), ) => (image, out, ),
+      (Err(e), _, ) | (_, Err(e), ) => return fail(e)
+    };
+    let f = match image.volume.files.get(id) {
+      Some(f) => f,
+      None => return fail(format!("No volume file {}", id))
+    };
+
+    let mut file_name = [0 as c_char; 9];
+    if let Some(name) = &f.file_name {
+      for (c, b, ) in file_name.iter_mut().zip(name.bytes().take(8)) {
+        *c = b as c_char;
+      }
+    }
+    *out = SgidiskVolumeFile {
+      in_use: f.in_use(),
+      file_name,
+      block_start: f.block_start,
+      file_sz: f.file_sz,
+    };
+    0
+  })
+}
+
+/// Open the EFS filesystem on a partition of a disk image. The image may be closed while the
+/// filesystem is open. Close with `sgidisk_efs_close()`.
+///
+/// # Safety
+/// `image` must be NULL or an open disk image.
+#[no_mangle]
+pub unsafe extern "C" fn sgidisk_efs_open(image: *const SgidiskImage, partition: usize) -> *mut SgidiskEfs {
+  guard(ptr::null_mut(), || {
+    let image = match ref_arg(image, "image") {
+      Ok(image) => image,
+      Err(e) => {
+        set_error(e);
+        return ptr::null_mut();
+      }
+    };
+    let p = match image.volume.partitions.get(partition) {
+      Some(p) if p.in_use() && p.partition_type == PartitionType::Efs => p,
+      _ => {
+        set_error(format!("Partition {} is not an EFS partition", partition));
+        return ptr::null_mut();
+      }
+    };
+
+    let mut file = match image.file.try_clone() {
+      Ok(file) => file,
+      Err(e) => {
+        set_error(format!("Unable to reopen disk image: {:?}", &e));
+        return ptr::null_mut();
+      }
+    };
+    let partition_start = p.byte_start();
+    match Efs::read(&mut file, image.volume.sector_sz as u64, partition_start) {
+      Ok(efs) => Box::into_raw(Box::new(SgidiskEfs { file, efs, last_read: None })),
+      Err(e) => {
+        set_error(format!("Unable to read EFS from partition {}: {:?}", partition, &e));
+        ptr::null_mut()
+      }
+    }
+  })
+}
+
+/// Close an EFS filesystem opened by `sgidisk_efs_open()`
+///
+/// # Safety
+/// `efs` must be NULL or returned by `sgidisk_efs_open()`, and not already closed.
+#[no_mangle]
+pub unsafe extern "C" fn sgidisk_efs_close(efs: *mut SgidiskEfs) {
+  guard((), || {
+    if !efs.is_null() {
+      drop(Box::from_raw(efs));
+    }
+  })
+}
+
+/// Get the metadata of the entry at an absolute path, e.g. "/etc/passwd"
+///
+/// # Safety
+/// `efs` must be NULL or an open filesystem, `path` NULL or a valid NUL terminated string and
+/// `out` NULL or pointing to an SgidiskStat.
+#[no_mangle]
+pub unsafe extern "C" fn sgidisk_efs_stat(efs: *mut SgidiskEfs, path: *const c_char, out: *mut SgidiskStat) -> c_int {
+  guard(-1, || {
+    let (efs, path, out, ) = match (mut_arg(efs, "efs"), str_arg(path, "path"), mut_arg(out, "out"), ) {
+      (Ok(efs), Ok(path), Ok(out), ) => (efs, path, out, ),
+      (Err(e), _, _, ) | (_, Err(e), _, ) | (_, _, Err(e), ) => return fail(e)
+    };
+
+    match efs.efs.lookup_path(&mut efs.file, path) {
+      Ok((inode_id, inode, )) => {
+        *out = stat(inode_id, &inode);
+        0
+      }
+      Err(e) => fail(format!("Error finding '{}': {:?}", path, &e))
+    }
+  })
+}
+
+/// List the entries of the directory at an absolute path, calling `callback` with the name and
+/// metadata of each, and `user_data`
+///
+/// # Safety
+/// `efs` must be NULL or an open filesystem and `path` NULL or a valid NUL terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn sgidisk_efs_list(efs: *mut SgidiskEfs, path: *const c_char, callback: SgidiskListCallback, user_data: *mut c_void) -> c_int {
+  guard(-1, || {
+    let (efs, path, ) = match (mut_arg(efs, "efs"), str_arg(path, "path"), ) {
+      (Ok(efs), Ok(path), ) => (efs, path, ),
+      (Err(e), _, ) | (_, Err(e), ) => return fail(e)
+    };
+    let callback = match callback {
+      Some(callback) => callback,
+      None => return fail("callback is NULL".to_string())
+    };
+
+    let inode_id = match efs.efs.lookup_path(&mut efs.file, path) {
+      Ok((inode_id, _, )) => inode_id,
+      Err(e) => return fail(format!("Error finding '{}': {:?}", path, &e))
+    };
+    let dir = match Directory::read_dir(&mut efs.file, &efs.efs, inode_id) {
+      Ok(dir) => dir,
+      Err(e) => return fail(format!("Unable to read directory '{}': {:?}", path, &e))
+    };
+
+    for (name, (entry_inode_id, entry_inode, ), ) in &dir.entries {
+      let name = match CString::new(name.as_str()) {
+        Ok(name) => name,
+        Err(_) => continue
+      };
+      let entry_stat = stat(*entry_inode_id, entry_inode);
+      if callback(name.as_ptr(), &entry_stat, user_data) != 0 {
+        break;
+      }
+    }
+    0
+  })
+}
+
+/// Read up to `len` bytes of the regular file at an absolute path, starting at `offset`, into
+/// `buf`. Returns the number of bytes read (0 at the end of the file), or -1 on error.
+///
+/// # Safety
+/// `efs` must be NULL or an open filesystem, `path` NULL or a valid NUL terminated string and
+/// `buf` NULL or pointing to at least `len` writable bytes. They needn't be initialised; only
+/// the bytes read are written.
+#[no_mangle]
+pub unsafe extern "C" fn sgidisk_efs_read(efs: *mut SgidiskEfs, path: *const c_char, offset: u64, buf: *mut u8, len: usize) -> i64 {
+  guard(-1, || {
+    let (efs, path, ) = match (mut_arg(efs, "efs"), str_arg(path, "path"), ) {
+      (Ok(efs), Ok(path), ) => (efs, path, ),
+      (Err(e), _, ) | (_, Err(e), ) => return fail(e) as i64
+    };
+    if buf.is_null() {
+      return fail("buf is NULL".to_string()) as i64;
+    }
+
+    let inode = match efs.last_read.take() {
+      Some((last_path, inode, )) if last_path == path => inode,
+      _ => match efs.efs.lookup_path(&mut efs.file, path) {
+        Ok((_, inode, )) => inode,
+        Err(e) => return fail(format!("Error finding '{}': {:?}", path, &e)) as i64
+      }
+    };
+    if inode.inode_type != InodeType::RegularFile {
+      return fail(format!("'{}' is not a regular file", path)) as i64;
+    }
+
+    let mut out = RawBuf { buf, len, pos: 0 };
+    let read = efs.efs.read_range(&mut efs.file, &inode, offset, len as u64, &mut out);
+    efs.last_read = Some((path.to_string(), inode, ));
+    match read {
+      Ok(n) => n as i64,
+      Err(e) => fail(format!("Unable to read '{}': {:?}", path, &e)) as i64
+    }
+  })
+}
+
+/// Extract the regular file at an absolute path to a host file
+///
+/// # Safety
+/// `efs` must be NULL or an open filesystem, and `path` and `dest` NULL or valid NUL terminated
+/// strings.
+#[no_mangle]
+pub unsafe extern "C" fn sgidisk_efs_extract(efs: *mut SgidiskEfs, path: *const c_char, dest: *const c_char) -> c_int {
+  guard(-1, || {
+    let (efs, path, dest, ) = match (mut_arg(efs, "efs"), str_arg(path, "path"), str_arg(dest, "dest"), ) {
+      (Ok(efs), Ok(path), Ok(dest), ) => (efs, path, dest, ),
+      (Err(e), _, _, ) | (_, Err(e), _, ) | (_, _, Err(e), ) => return fail(e)
+    };
+
+    let (_, inode, ) = match efs.efs.lookup_path(&mut efs.file, path) {
+      Ok(found) => found,
+      Err(e) => return fail(format!("Error finding '{}': {:?}", path, &e))
+    };
+    if inode.inode_type != InodeType::RegularFile {
+      return fail(format!("'{}' is not a regular file", path));
+    }
+
+    let mut writer = match File::create(dest) {
+      Ok(file) => BufWriter::new(file),
+      Err(e) => return fail(format!("Unable to create '{}': {:?}", dest, &e))
+    };
+    if let Err(e) = efs.efs.read_file(&mut efs.file, &inode, &mut writer) {
+      return fail(format!("Unable to extract '{}': {:?}", path, &e));
+    }
+    match writer.into_inner() {
+      Ok(_) => 0,
+      Err(e) => fail(format!("Unable to write '{}': {:?}", dest, e.error()))
+    }
+  })
+}
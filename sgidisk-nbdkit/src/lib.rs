@@ -0,0 +1,136 @@
+//! nbdkit plugin exporting one partition of an SGI disk image, read-only.
+//!
+//! ```sh
+//! nbdkit ./libnbdkit_sgidisk_plugin.so file=disk.img partition=7
+//! ```
+//!
+//! nbdkit only runs on Unix, so elsewhere the crate is empty and the workspace still builds.
+
+#![cfg(unix)]
+
+use std::fs::File;
+use std::os::unix::fs::FileExt;
+use std::sync::{Arc, Mutex};
+
+use nbdkit::*;
+
+use sgidisklib::volhdr::SgidiskVolume;
+
+/// Parameters given on the nbdkit command line
+struct PluginConfig {
+  file: Option<String>,
+  partition: Option<usize>,
+}
+
+static CONFIG: Mutex<PluginConfig> = Mutex::new(PluginConfig { file: None, partition: None });
+
+/// Partition found once configuration is complete
+static EXPORT: Mutex<Option<SgidiskPartition>> = Mutex::new(None);
+
+/// Byte range of a disk image exported by a connection
+#[derive(Clone)]
+struct SgidiskPartition {
+  file: Arc<File>,
+  start: u64,
+  len: u64,
+}
+
+/// nbdkit error with EINVAL
+fn config_err(message: String) -> Error {
+  Error::new(libc::EINVAL, message)
+}
+
+impl SgidiskPartition {
+  /// Open a disk image and find a partition in its Volume Header
+  fn open(file_name: &str, partition: usize) -> Result<Self> {
+    let mut file = match File::open(file_name) {
+      Ok(file) => file,
+      Err(e) => return Err(Error::new(e.raw_os_error().unwrap_or(libc::EIO), format!("Unable to open disk image '{}': {:?}", file_name, &e)))
+    };
+    let volume = match SgidiskVolume::read(&mut file) {
+      Ok(volume) => volume,
+      Err(e) => return Err(config_err(format!("Unable to read Volume Header from disk image '{}': {:?}", file_name, &e)))
+    };
+
+    let p = match volume.partitions.get(partition) {
+      Some(p) if p.in_use() => p,
+      _ => return Err(config_err(format!("Partition {} is not in use", partition)))
+    };
+    let range = p.byte_range();
+    let image_len = file.metadata().map(|m| m.len()).unwrap_or(0);
+    if range.end > image_len {
+      return Err(config_err(format!("Partition {} ends at byte {}, past the end of the disk image ({} bytes)", partition, range.end, image_len)));
+    }
+
+    Ok(SgidiskPartition {
+      file: Arc::new(file),
+      start: range.start,
+      len: range.end - range.start,
+    })
+  }
+}
+
+impl Server for SgidiskPartition {
+  fn name() -> &'static str {
+    "sgidisk"
+  }
+
+  fn config(key: &str, value: &str) -> Result<()> {
+    let mut config = CONFIG.lock().unwrap();
+    match key {
+      "file" => config.file = Some(value.to_string()),
+      "partition" => match value.parse() {
+        Ok(id) => config.partition = Some(id),
+        Err(_) => return Err(config_err(format!("Invalid partition '{}'", value)))
+      },
+      _ => return Err(config_err(format!("Unknown parameter '{}'", key)))
+    }
+    Ok(())
+  }
+
+  fn config_complete() -> Result<()> {
+    let config = CONFIG.lock().unwrap();
+    let file_name = match &config.file {
+      Some(file_name) => file_name,
+      None => return Err(config_err("The file parameter is required".to_string()))
+    };
+    let partition = match config.partition {
+      Some(partition) => partition,
+      None => return Err(config_err("The partition parameter is required".to_string()))
+    };
+
+    *EXPORT.lock().unwrap() = Some(SgidiskPartition::open(file_name, partition)?);
+    Ok(())
+  }
+
+  fn thread_model() -> Result<ThreadModel> where Self: Sized {
+    // Reads are positional, so requests can be served in parallel
+    Ok(ThreadModel::Parallel)
+  }
+
+  fn open(_readonly: bool) -> Result<Box<dyn Server>> {
+    match EXPORT.lock().unwrap().as_ref() {
+      Some(export) => Ok(Box::new(export.clone())),
+      None => Err(Error::new(libc::EIO, "Partition not configured"))
+    }
+  }
+
+  fn get_size(&self) -> Result<i64> {
+    Ok(self.len as i64)
+  }
+
+  fn can_write(&self) -> Result<bool> {
+    Ok(false)
+  }
+
+  fn read_at(&self, buf: &mut [u8], offset: u64) -> Result<()> {
+    if offset + buf.len() as u64 > self.len {
+      return Err(Error::new(libc::EINVAL, format!("Read of {} bytes at {} is past the end of the partition", buf.len(), offset)));
+    }
+    // Positional reads don't move a shared file position
+    self.file.read_exact_at(buf, self.start + offset)
+      .map_err(|e| Error::new(e.raw_os_error().unwrap_or(libc::EIO), format!("{:?}", &e)))
+  }
+}
+
+plugin!(SgidiskPartition {config, config_complete, thread_model, can_write});
@@ -0,0 +1,311 @@
+//! Python bindings for sgidisklib, built as the `sgidisk` extension module with maturin.
+//!
+//! ```python
+//! import sgidisk
+//! image = sgidisk.Image("disk.img")
+//! efs = image.efs(7)
+//! for dirpath, dirnames, filenames in efs.walk("/"):
+//!     ...
+//! data = efs.read("/etc/passwd")
+//! ```
+
+use std::collections::HashSet;
+use std::fs::File;
+
+use pyo3::create_exception;
+use pyo3::exceptions::PyException;
+use pyo3::prelude::*;
+use pyo3::types::PyBytes;
+
+use sgidisklib::SgidiskLibReadError;
+use sgidisklib::content::probe_partition;
+use sgidisklib::efs::{Inode, InodeType};
+use sgidisklib::efs::dir::Directory;
+use sgidisklib::volhdr::{PartitionType, SgidiskVolume};
+
+create_exception!(sgidisk, SgidiskError, PyException);
+
+/// Convert a library error to a Python exception
+fn py_err(context: String, e: SgidiskLibReadError) -> PyErr {
+  SgidiskError::new_err(format!("{}: {:?}", context, &e))
+}
+
+/// Disk image and its Volume Header
+#[pyclass(module = "sgidisk")]
+struct Image {
+  file: File,
+  volume: SgidiskVolume,
+}
+
+/// Partition table entry
+#[pyclass(module = "sgidisk")]
+#[derive(Clone)]
+struct Partition {
+  #[pyo3(get)]
+  id: usize,
+  #[pyo3(get)]
+  in_use: bool,
+  /// Partition type, e.g. "Efs"
+  #[pyo3(get)]
+  partition_type: String,
+  /// First block of partition
+  #[pyo3(get)]
+  block_start: u64,
+  /// Size of partition in blocks
+  #[pyo3(get)]
+  block_sz: u64,
+  /// What the partition holds, e.g. "efs", as recognised by a built in or registered content
+  /// handler
+  #[pyo3(get)]
+  content: Option<String>,
+}
+
+/// Volume directory entry
+#[pyclass(module = "sgidisk")]
+#[derive(Clone)]
+struct VolumeFile {
+  #[pyo3(get)]
+  name: String,
+  /// First block of file
+  #[pyo3(get)]
+  block_start: u64,
+  /// Size of file in bytes
+  #[pyo3(get)]
+  size: u64,
+}
+
+/// EFS filesystem on a partition of a disk image
+#[pyclass(module = "sgidisk")]
+struct Efs {
+  file: File,
+  efs: sgidisklib::efs::Efs,
+}
+
+/// Metadata of an EFS entry
+#[pyclass(module = "sgidisk")]
+#[derive(Clone)]
+struct Stat {
+  /// Inode number
+  #[pyo3(get)]
+  inode: u64,
+  /// Inode type, e.g. "RegularFile" or "Directory"
+  #[pyo3(get)]
+  inode_type: String,
+  /// Permission bits
+  #[pyo3(get)]
+  mode: u16,
+  #[pyo3(get)]
+  uid: u16,
+  #[pyo3(get)]
+  gid: u16,
+  /// Size in bytes
+  #[pyo3(get)]
+  size: u64,
+  /// Access, modification and creation times, in seconds since the epoch
+  #[pyo3(get)]
+  atime: i64,
+  #[pyo3(get)]
+  mtime: i64,
+  #[pyo3(get)]
+  ctime: i64,
+  /// (major, minor) device number of device inodes
+  #[pyo3(get)]
+  device: Option<(u32, u32, )>,
+}
+
+impl Stat {
+  fn new(inode_id: u64, inode: &Inode) -> Self {
+    Stat {
+      inode: inode_id,
+      inode_type: format!("{:?}", inode.inode_type),
+      mode: inode.unix_mode,
+      uid: inode.owner_uid,
+      gid: inode.owner_gid,
+      size: inode.size,
+      atime: inode.atime_raw,
+      mtime: inode.mtime_raw,
+      ctime: inode.ctime_raw,
+      device: inode.device.map(|d| (d.major, d.minor, )),
+    }
+  }
+}
+
+#[pymethods]
+impl Image {
+  /// Open a disk image and read its Volume Header
+  #[new]
+  fn new(path: &str) -> PyResult<Self> {
+    let mut file = File::open(path)?;
+    match SgidiskVolume::read(&mut file) {
+      Ok(volume) => Ok(Image { file, volume }),
+      Err(e) => Err(py_err(format!("Unable to read Volume Header from '{}'", path), e))
+    }
+  }
+
+  #[getter]
+  fn sector_sz(&self) -> usize {
+    self.volume.sector_sz
+  }
+
+  #[getter]
+  fn boot_file(&self) -> Option<String> {
+    self.volume.boot_file.clone()
+  }
+
+  /// Partitions in use
+  #[getter]
+  fn partitions(&self) -> Vec<Partition> {
+    self.volume.partitions.iter().enumerate()
+      .filter(|(_, p, )| p.in_use())
+      .map(|(id, p, )| Partition {
+        id,
+        in_use: p.in_use(),
+        partition_type: format!("{:?}", p.partition_type),
+        block_start: p.block_start,
+        block_sz: p.block_sz,
+        content: probe_partition(&mut &self.file, p.byte_start(), p.partition_type).ok()
+          .flatten()
+          .map(|identified| identified.name.to_string()),
+      })
+      .collect()
+  }
+
+  /// Volume directory files in use
+  #[getter]
+  fn files(&self) -> Vec<VolumeFile> {
+    self.volume.files.iter()
+      .filter(|f| f.in_use())
+      .map(|f| VolumeFile {
+        name: f.file_name.clone().unwrap_or_default(),
+        block_start: f.block_start,
+        size: f.file_sz,
+      })
+      .collect()
+  }
+
+  /// Open the EFS filesystem on a partition
+  fn efs(&self, partition: usize) -> PyResult<Efs> {
+    let p = match self.volume.partitions.get(partition) {
+      Some(p) if p.in_use() && p.partition_type == PartitionType::Efs => p,
+      _ => return Err(SgidiskError::new_err(format!("Partition {} is not an EFS partition", partition)))
+    };
+
+    let mut file = self.file.try_clone()?;
+    let partition_start = p.byte_start();
+    match sgidisklib::efs::Efs::read(&mut file, self.volume.sector_sz as u64, partition_start) {
+      Ok(efs) => Ok(Efs { file, efs }),
+      Err(e) => Err(py_err(format!("Unable to read EFS from partition {}", partition), e))
+    }
+  }
+}
+
+impl Efs {
+  /// Find the inode at an absolute path
+  fn lookup(&mut self, path: &str) -> PyResult<(u64, Inode, )> {
+    self.efs.lookup_path(&mut self.file, path)
+      .map_err(|e| py_err(format!("Error finding '{}'", path), e))
+  }
+
+  /// Entries of a directory, without "." and ".."
+  fn entries(&mut self, path: &str, inode_id: u64) -> PyResult<Vec<(String, u64, Inode, )>> {
+    match Directory::read_dir(&mut self.file, &self.efs, inode_id) {
+      Ok(dir) => Ok(dir.entries.into_iter()
+        .filter(|(name, _, )| name != "." && name != "..")
+        .map(|(name, (id, inode, ), )| (name, id, inode, ))
+        .collect()),
+      Err(e) => Err(py_err(format!("Unable to read directory '{}'", path), e))
+    }
+  }
+}
+
+#[pymethods]
+impl Efs {
+  /// Metadata of the entry at an absolute path
+  fn stat(&mut self, path: &str) -> PyResult<Stat> {
+    let (inode_id, inode, ) = self.lookup(path)?;
+    Ok(Stat::new(inode_id, &inode))
+  }
+
+  /// Names of the entries of a directory, like os.listdir()
+  fn listdir(&mut self, path: &str) -> PyResult<Vec<String>> {
+    let (inode_id, _, ) = self.lookup(path)?;
+    Ok(self.entries(path, inode_id)?.into_iter().map(|(name, _, _, )| name).collect())
+  }
+
+  /// Names and metadata of the entries of a directory
+  fn scandir(&mut self, path: &str) -> PyResult<Vec<(String, Stat, )>> {
+    let (inode_id, _, ) = self.lookup(path)?;
+    Ok(self.entries(path, inode_id)?.into_iter()
+      .map(|(name, id, inode, )| (name, Stat::new(id, &inode), ))
+      .collect())
+  }
+
+  /// Walk a directory tree top-down like os.walk(), as a list of (dirpath, dirnames, filenames).
+  /// Symbolic links to directories are listed as files and not followed.
+  fn walk(&mut self, path: &str) -> PyResult<Vec<(String, Vec<String>, Vec<String>, )>> {
+    let (inode_id, _, ) = self.lookup(path)?;
+    let mut walked = Vec::new();
+    let mut visited = HashSet::new();
+    let mut pending = vec![(path.to_string(), inode_id, 0, )];
+    while let Some((dir_path, dir_inode_id, depth, )) = pending.pop() {
+      // A damaged filesystem may link a directory into itself
+      if !visited.insert(dir_inode_id) {
+        continue;
+      }
+      if let Err(e) = self.efs.limits.check_depth(depth) {
+        return Err(py_err(format!("Unable to walk '{}'", dir_path), e));
+      }
+
+      let (mut dir_names, mut file_names, ) = (Vec::new(), Vec::new(), );
+      let mut subdirs = Vec::new();
+      for (name, id, inode, ) in self.entries(&dir_path, dir_inode_id)? {
+        if inode.inode_type == InodeType::Directory {
+          subdirs.push((format!("{}/{}", dir_path.trim_end_matches('/'), name), id, depth + 1, ));
+          dir_names.push(name);
+        } else {
+          file_names.push(name);
+        }
+      }
+      walked.push((dir_path, dir_names, file_names, ));
+      // Visit subdirectories in name order
+      pending.extend(subdirs.into_iter().rev());
+    }
+    Ok(walked)
+  }
+
+  /// Contents of the regular file at an absolute path
+  fn read(&mut self, py: Python, path: &str) -> PyResult<Py<PyBytes>> {
+    let (_, inode, ) = self.lookup(path)?;
+    if inode.inode_type != InodeType::RegularFile {
+      return Err(SgidiskError::new_err(format!("'{}' is not a regular file", path)));
+    }
+
+    let mut contents = Vec::with_capacity(inode.size as usize);
+    if let Err(e) = self.efs.read_file(&mut self.file, &inode, &mut contents) {
+      return Err(py_err(format!("Unable to read '{}'", path), e));
+    }
+    Ok(PyBytes::new(py, &contents).into())
+  }
+
+  /// Target of the symbolic link at an absolute path
+  fn readlink(&mut self, path: &str) -> PyResult<String> {
+    let (_, inode, ) = self.lookup(path)?;
+    if inode.inode_type != InodeType::SymbolicLink {
+      return Err(SgidiskError::new_err(format!("'{}' is not a symbolic link", path)));
+    }
+    self.efs.read_link(&mut self.file, &inode)
+      .map_err(|e| py_err(format!("Unable to read link '{}'", path), e))
+  }
+}
+
+/// Read SGI / IRIX disk images: volume headers and EFS filesystems
+#[pymodule]
+fn sgidisk(py: Python, m: &PyModule) -> PyResult<()> {
+  m.add("SgidiskError", py.get_type::<SgidiskError>())?;
+  m.add_class::<Image>()?;
+  m.add_class::<Partition>()?;
+  m.add_class::<VolumeFile>()?;
+  m.add_class::<Efs>()?;
+  m.add_class::<Stat>()?;
+  Ok(())
+}
@@ -0,0 +1,235 @@
+//! WebAssembly bindings for sgidisklib, for inspecting disk images in a browser. Build with
+//! `wasm-pack build sgidisk-wasm`.
+//!
+//! An image is either held in memory (`Image.fromBytes`), or fetched in ranges as it is read
+//! (`Image.open`), given its size and a function `(offset, length) => Promise<Uint8Array>`,
+//! e.g. making HTTP range requests.
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use js_sys::{Array, Function, Object, Promise, Reflect, Uint8Array};
+use wasm_bindgen::prelude::*;
+use wasm_bindgen_futures::{future_to_promise, JsFuture};
+
+use sgidisklib::SgidiskLibReadError;
+use sgidisklib::efs::{Efs, Inode, InodeType};
+use sgidisklib::efs::dir::Directory;
+use sgidisklib::source::RangeCache;
+use sgidisklib::volhdr::{PartitionType, SgidiskVolume};
+
+/// Disk image and its Volume Header
+#[wasm_bindgen]
+pub struct Image {
+  inner: Rc<RefCell<Inner>>,
+}
+
+/// State shared with pending operations
+struct Inner {
+  /// Cache of fetched ranges, taken by the operation in progress
+  cache: Option<RangeCache>,
+  /// Size of the image, in bytes
+  size: u64,
+  /// Function fetching missing ranges, if the image isn't held in memory
+  fetch: Option<Function>,
+  volume: Rc<SgidiskVolume>,
+}
+
+/// JavaScript Error with a message
+fn js_err(message: String) -> JsValue {
+  js_sys::Error::new(&message).into()
+}
+
+/// JavaScript object with the given properties
+fn object(props: &[(&str, JsValue, )]) -> JsValue {
+  let obj = Object::new();
+  for (name, value, ) in props {
+    let _ = Reflect::set(&obj, &JsValue::from_str(name), value);
+  }
+  obj.into()
+}
+
+/// JavaScript object holding the metadata of an inode
+fn stat(inode_id: u64, inode: &Inode) -> JsValue {
+  let device = match inode.device {
+    Some(d) => Array::of2(&JsValue::from(d.major), &JsValue::from(d.minor)).into(),
+    None => JsValue::NULL
+  };
+  object(&[
+    ("inode", JsValue::from(inode_id as f64), ),
+    ("type", JsValue::from_str(&format!("{:?}", inode.inode_type)), ),
+    ("mode", JsValue::from(inode.unix_mode), ),
+    ("uid", JsValue::from(inode.owner_uid), ),
+    ("gid", JsValue::from(inode.owner_gid), ),
+    ("size", JsValue::from(inode.size as f64), ),
+    ("atime", JsValue::from(inode.atime_raw as f64), ),
+    ("mtime", JsValue::from(inode.mtime_raw as f64), ),
+    ("ctime", JsValue::from(inode.ctime_raw as f64), ),
+    ("device", device, ),
+  ])
+}
+
+/// Run a read against the cache, fetching missing ranges and retrying until it succeeds or
+/// fails for another reason
+async fn fetching<T, F>(cache: &mut RangeCache, fetch: Option<&Function>, mut read: F) -> Result<T, JsValue>
+  where F: FnMut(&mut RangeCache) -> Result<T, SgidiskLibReadError> {
+  loop {
+    let e = match read(cache) {
+      Ok(t) => return Ok(t),
+      Err(e) => e
+    };
+    let (offset, len, ) = match (cache.take_missing(), fetch, ) {
+      (Some(missing), Some(_), ) => missing,
+      _ => return Err(js_err(format!("{:?}", &e)))
+    };
+
+    let promise = fetch.unwrap().call2(&JsValue::NULL, &JsValue::from(offset as f64), &JsValue::from(len as f64))?;
+    let data = Uint8Array::new(&JsFuture::from(Promise::from(promise)).await?).to_vec();
+    // Fetching nothing would retry forever
+    if data.is_empty() {
+      return Err(js_err(format!("Fetch of bytes {}..{} returned no data", offset, offset + len)));
+    }
+    cache.insert(offset, data);
+  }
+}
+
+/// Run an operation on a shared image as a Promise. The cache is taken out of the image while
+/// the operation runs, so operations on the same image must be awaited one at a time.
+fn promise<F>(inner: &Rc<RefCell<Inner>>, read: F) -> Promise
+  where F: 'static + FnMut(&mut RangeCache, &SgidiskVolume) -> Result<JsValue, SgidiskLibReadError> {
+  let inner = inner.clone();
+  future_to_promise(async move {
+    let (mut cache, fetch, volume, ) = {
+      let mut i = inner.borrow_mut();
+      match i.cache.take() {
+        Some(cache) => (cache, i.fetch.clone(), i.volume.clone(), ),
+        None => return Err(js_err("Another operation on this image is in progress".to_string()))
+      }
+    };
+    let mut read = read;
+    let result = fetching(&mut cache, fetch.as_ref(), |cache| read(cache, &volume)).await;
+    inner.borrow_mut().cache = Some(cache);
+    result
+  })
+}
+
+/// Open the EFS filesystem on a partition
+fn open_efs(cache: &mut RangeCache, volume: &SgidiskVolume, partition: usize) -> Result<Efs, SgidiskLibReadError> {
+  match volume.partitions.get(partition) {
+    Some(p) if p.in_use() && p.partition_type == PartitionType::Efs => {
+      Efs::read(cache, volume.sector_sz as u64, p.byte_start())
+    }
+    _ => Err(SgidiskLibReadError::Value(format!("Partition {} is not an EFS partition", partition)))
+  }
+}
+
+#[wasm_bindgen]
+impl Image {
+  /// Image held in memory
+  #[wasm_bindgen(js_name = fromBytes)]
+  pub fn from_bytes(data: &[u8]) -> Result<Image, JsValue> {
+    let mut cache = RangeCache::from_bytes(data.to_vec());
+    match SgidiskVolume::read(&mut cache) {
+      Ok(volume) => Ok(Image::new(cache, None, volume)),
+      Err(e) => Err(js_err(format!("Unable to read Volume Header: {:?}", &e)))
+    }
+  }
+
+  /// Image of `size` bytes, fetched in ranges with `fetch(offset, length)` as it is read
+  pub async fn open(size: f64, fetch: Function) -> Result<Image, JsValue> {
+    let mut cache = RangeCache::new(size as u64);
+    let volume = fetching(&mut cache, Some(&fetch), SgidiskVolume::read).await?;
+    Ok(Image::new(cache, Some(fetch), volume))
+  }
+
+  /// Volume Header: sector size, boot file, partitions and volume directory files in use
+  pub fn info(&self) -> JsValue {
+    let inner = self.inner.borrow();
+    let volume = &inner.volume;
+
+    let partitions = volume.partitions.iter().enumerate()
+      .filter(|(_, p, )| p.in_use())
+      .map(|(id, p, )| object(&[
+        ("id", JsValue::from(id as u32), ),
+        ("type", JsValue::from_str(&format!("{:?}", p.partition_type)), ),
+        ("blockStart", JsValue::from(p.block_start as f64), ),
+        ("blockSize", JsValue::from(p.block_sz as f64), ),
+      ]))
+      .collect::<Array>();
+    let files = volume.files.iter()
+      .filter(|f| f.in_use())
+      .map(|f| object(&[
+        ("name", JsValue::from(f.file_name.clone()), ),
+        ("blockStart", JsValue::from(f.block_start as f64), ),
+        ("size", JsValue::from(f.file_sz as f64), ),
+      ]))
+      .collect::<Array>();
+
+    object(&[
+      ("size", JsValue::from(inner.size as f64), ),
+      ("sectorSize", JsValue::from(volume.sector_sz as u32), ),
+      ("bootFile", JsValue::from(volume.boot_file.clone()), ),
+      ("partitions", partitions.into(), ),
+      ("files", files.into(), ),
+    ])
+  }
+
+  /// Metadata of the entry at an absolute path in an EFS partition
+  pub fn stat(&self, partition: usize, path: String) -> Promise {
+    promise(&self.inner, move |cache, volume| {
+      let efs = open_efs(cache, volume, partition)?;
+      let (inode_id, inode, ) = efs.lookup_path(cache, &path)?;
+      Ok(stat(inode_id, &inode))
+    })
+  }
+
+  /// Entries of the directory at an absolute path in an EFS partition, as an array of
+  /// `{ name, stat }`, without "." and ".."
+  pub fn list(&self, partition: usize, path: String) -> Promise {
+    promise(&self.inner, move |cache, volume| {
+      let efs = open_efs(cache, volume, partition)?;
+      let (inode_id, _, ) = efs.lookup_path(cache, &path)?;
+      let dir = Directory::read_dir(cache, &efs, inode_id)?;
+      Ok(dir.entries.iter()
+        .filter(|(name, _, )| *name != "." && *name != "..")
+        .map(|(name, (id, inode, ), )| object(&[
+          ("name", JsValue::from_str(name), ),
+          ("stat", stat(*id, inode), ),
+        ]))
+        .collect::<Array>()
+        .into())
+    })
+  }
+
+  /// Contents of the regular file at an absolute path in an EFS partition, as a Uint8Array
+  pub fn read(&self, partition: usize, path: String) -> Promise {
+    promise(&self.inner, move |cache, volume| {
+      let efs = open_efs(cache, volume, partition)?;
+      let (_, inode, ) = efs.lookup_path(cache, &path)?;
+      if inode.inode_type != InodeType::RegularFile {
+        return Err(SgidiskLibReadError::Value(format!("'{}' is not a regular file", path)));
+      }
+      let mut contents = Vec::with_capacity(inode.size as usize);
+      efs.read_file(cache, &inode, &mut contents)?;
+      Ok(Uint8Array::from(&contents[..]).into())
+    })
+  }
+
+  /// Target of the symbolic link at an absolute path in an EFS partition
+  #[wasm_bindgen(js_name = readLink)]
+  pub fn read_link(&self, partition: usize, path: String) -> Promise {
+    promise(&self.inner, move |cache, volume| {
+      let efs = open_efs(cache, volume, partition)?;
+      let (_, inode, ) = efs.lookup_path(cache, &path)?;
+      Ok(JsValue::from(efs.read_link(cache, &inode)?))
+    })
+  }
+}
+
+impl Image {
+  fn new(cache: RangeCache, fetch: Option<Function>, volume: SgidiskVolume) -> Self {
+    Image {
+      inner: Rc::new(RefCell::new(Inner { size: cache.size(), cache: Some(cache), fetch, volume: Rc::new(volume) })),
+    }
+  }
+}
@@ -0,0 +1,19 @@
+use std::env;
+use std::fs;
+use std::process::exit;
+
+/// Write the sample test image to the file named on the command line
+fn main() {
+  let file_name = match env::args().nth(1) {
+    Some(file_name) => file_name,
+    None => {
+      eprintln!("Usage: sgidisk-testgen IMAGE");
+      exit(1);
+    }
+  };
+
+  if let Err(e) = fs::write(&file_name, sgidisklib::testgen::sample().build()) {
+    eprintln!("Unable to write '{}': {:?}", file_name, &e);
+    exit(1);
+  }
+}
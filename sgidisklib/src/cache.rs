@@ -0,0 +1,190 @@
+//! Block cache with readahead, for reading disk images from slow media (network, optical) where
+//! the many small reads of a directory walk or file read would each be expensive
+
+use std::collections::{BTreeMap, HashMap};
+use std::io;
+use std::io::{Read, Seek, SeekFrom};
+
+/// Reader wrapper which reads whole blocks of the inner reader, and on a miss the blocks
+/// following it too, keeping the most recently used blocks
+pub struct BlockCache<R> {
+  inner: R,
+  /// Size of a cached block, in bytes
+  block_sz: u64,
+  /// Number of blocks read on a miss, including the missed one
+  readahead: u64,
+  /// Maximum number of blocks kept
+  capacity: usize,
+  /// Cached blocks by block number, with when they were last used. Blocks at the end of the
+  /// inner reader may be short.
+  blocks: HashMap<u64, (Vec<u8>, u64, )>,
+  /// Block numbers by when they were last used, least recently first
+  lru: BTreeMap<u64, u64>,
+  /// Counter of block uses
+  tick: u64,
+  /// Current offset
+  pos: u64,
+}
+
+impl<R: Read + Seek> BlockCache<R> {
+  /// Default size of a cached block
+  pub const DEFAULT_BLOCK_SZ: u64 = 4096;
+
+  /// Wrap a reader, caching up to `capacity` blocks of `block_sz` bytes and reading `readahead`
+  /// blocks at a time
+  pub fn new(inner: R, block_sz: u64, readahead: u64, capacity: usize) -> Self {
+    BlockCache {
+      inner,
+      block_sz: block_sz.max(1),
+      readahead: readahead.max(1),
+      capacity: capacity.max(readahead.max(1) as usize),
+      blocks: HashMap::new(),
+      lru: BTreeMap::new(),
+      tick: 0,
+      pos: 0,
+    }
+  }
+
+  /// Get a reference to the wrapped reader
+  pub fn get_ref(&self) -> &R {
+    &self.inner
+  }
+
+  /// Read a missed block and the uncached blocks following it, up to the readahead
+  fn fill(&mut self, block: u64) -> io::Result<()> {
+    let run = (1..self.readahead)
+      .take_while(|n| !self.blocks.contains_key(&(block + n)))
+      .count() as u64 + 1;
+    self.inner.seek(SeekFrom::Start(block * self.block_sz))?;
+    let mut data = Vec::with_capacity((run * self.block_sz) as usize);
+    (&mut self.inner).take(run * self.block_sz).read_to_end(&mut data)?;
+
+    // Keep even an empty block past the end, so reading there doesn't read again
+    let mut chunks: Vec<Vec<u8>> = data.chunks(self.block_sz as usize).map(|c| c.to_vec()).collect();
+    if chunks.is_empty() {
+      chunks.push(Vec::new());
+    }
+    for (n, chunk, ) in chunks.into_iter().enumerate() {
+      self.insert(block + n as u64, chunk);
+    }
+    Ok(())
+  }
+
+  /// Cache a block as the most recently used, evicting the least recently used if full
+  fn insert(&mut self, block: u64, data: Vec<u8>) {
+    self.tick += 1;
+    if let Some((_, used, )) = self.blocks.insert(block, (data, self.tick, )) {
+      self.lru.remove(&used);
+    }
+    self.lru.insert(self.tick, block);
+    while self.blocks.len() > self.capacity {
+      let (used, evicted, ) = match self.lru.iter().next() {
+        Some((used, evicted, )) => (*used, *evicted, ),
+        None => break
+      };
+      self.lru.remove(&used);
+      self.blocks.remove(&evicted);
+    }
+  }
+
+  /// Mark a cached block as the most recently used
+  fn touch(&mut self, block: u64) {
+    self.tick += 1;
+    if let Some((_, used, )) = self.blocks.get_mut(&block) {
+      self.lru.remove(used);
+      *used = self.tick;
+      self.lru.insert(self.tick, block);
+    }
+  }
+}
+
+impl<R: Read + Seek> Read for BlockCache<R> {
+  fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+    if buf.is_empty() {
+      return Ok(0);
+    }
+    let block = self.pos / self.block_sz;
+    let offset = (self.pos % self.block_sz) as usize;
+    if self.blocks.contains_key(&block) {
+      self.touch(block);
+    } else {
+      self.fill(block)?;
+    }
+
+    let data = match self.blocks.get(&block) {
+      Some((data, _, )) => data,
+      // Evicted straight away, so the cache holds no blocks
+      None => return Ok(0)
+    };
+    if offset >= data.len() {
+      return Ok(0);
+    }
+    let n = buf.len().min(data.len() - offset);
+    buf[..n].copy_from_slice(&data[offset..offset + n]);
+    self.pos += n as u64;
+    Ok(n)
+  }
+}
+
+impl<R: Read + Seek> Seek for BlockCache<R> {
+  fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+    let new_pos = match pos {
+      SeekFrom::Start(offset) => Some(offset),
+      SeekFrom::Current(delta) => u64::try_from(self.pos as i128 + delta as i128).ok(),
+      // The inner reader knows where its end is
+      SeekFrom::End(_) => Some(self.inner.seek(pos)?)
+    };
+    match new_pos {
+      Some(new_pos) => {
+        self.pos = new_pos;
+        Ok(new_pos)
+      }
+      None => Err(io::Error::new(io::ErrorKind::InvalidInput, "Invalid seek to a negative or overflowing position"))
+    }
+  }
+}
+
+impl<R> std::fmt::Debug for BlockCache<R> {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    f.debug_struct("BlockCache")
+      .field("block_sz", &self.block_sz)
+      .field("readahead", &self.readahead)
+      .field("capacity", &self.capacity)
+      .field("cached", &self.blocks.len())
+      .field("pos", &self.pos)
+      .finish()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::io::Cursor;
+
+  use super::*;
+  use crate::metrics::CountingReader;
+
+  #[test]
+  fn reads_match_and_read_ahead() {
+    let data: Vec<u8> = (0..10_000u32).map(|i| (i % 253) as u8).collect();
+    let mut cache = BlockCache::new(CountingReader::new(Cursor::new(data.clone())), 512, 4, 8);
+
+    // Small reads within the first readahead only read it from the inner reader
+    let mut buf = [0u8; 100];
+    for offset in [0u64, 700, 1500, 30] {
+      cache.seek(SeekFrom::Start(offset)).unwrap();
+      cache.read_exact(&mut buf).unwrap();
+      assert_eq!(&buf[..], &data[offset as usize..offset as usize + 100]);
+    }
+    assert_eq!(cache.get_ref().stats().bytes_read, 2048);
+
+    // Reads across blocks, past the capacity, and up to the end
+    let mut all = Vec::new();
+    cache.seek(SeekFrom::Start(0)).unwrap();
+    cache.read_to_end(&mut all).unwrap();
+    assert_eq!(all, data);
+    assert_eq!(cache.seek(SeekFrom::End(-10)).unwrap(), 9990);
+    let mut tail = Vec::new();
+    cache.read_to_end(&mut tail).unwrap();
+    assert_eq!(&tail[..], &data[9990..]);
+  }
+}
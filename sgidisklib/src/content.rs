@@ -0,0 +1,124 @@
+//! Identifying what a partition holds from its first bytes. Handlers for the contents sgidisk
+//! knows about are built in; other crates can register handlers of their own, e.g. for XFS or an
+//! appliance's format, without changes here. Registered handlers are consulted before the built
+//! in ones, the most recently registered first, so they can also take over a built in content.
+
+use std::io;
+use std::io::{Cursor, Read, Seek, SeekFrom};
+use std::sync::{PoisonError, RwLock};
+
+use crate::efs::{Efs, EFS_BLOCK_SZ};
+use crate::volhdr::{PartitionType, SgidiskVolume};
+
+/// Number of bytes at the start of a partition handed to handlers to probe
+pub const PROBE_SZ: usize = 4096;
+
+/// Handler recognising a kind of partition content
+pub trait ContentHandler: Send + Sync {
+  /// Short name of the content, e.g. "efs"
+  fn name(&self) -> &'static str;
+
+  /// Whether partitions of a type may hold the content; by default those of any type
+  fn handles(&self, _partition_type: PartitionType) -> bool {
+    true
+  }
+
+  /// Whether the first bytes of a partition (up to `PROBE_SZ`, fewer if it or the image ends
+  /// sooner) hold the content
+  fn probe(&self, head: &[u8]) -> bool;
+
+  /// One line description of the content the first bytes of a partition hold, e.g. its label
+  fn describe(&self, _head: &[u8]) -> Option<String> {
+    None
+  }
+}
+
+/// Content of a partition, as identified by a handler
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Identified {
+  /// Name of the handler which recognised the content
+  pub name: &'static str,
+  pub description: Option<String>,
+  /// Whether the handler is built in rather than registered
+  pub builtin: bool,
+}
+
+/// A Volume Header, as in a copy of the volume header partition
+struct VolumeHeaderContent;
+
+/// SGI XFS, recognised by its superblock magic only
+struct XfsContent;
+
+/// SGI EFS
+struct EfsContent;
+
+/// Handlers registered by other crates, in the order registered
+static REGISTERED: RwLock<Vec<Box<dyn ContentHandler>>> = RwLock::new(Vec::new());
+
+/// Handlers built in, in the order consulted
+static BUILTIN: [&dyn ContentHandler; 3] = [&VolumeHeaderContent, &XfsContent, &EfsContent];
+
+/// Register a handler, to be consulted before those registered earlier and the built in ones
+pub fn register(handler: Box<dyn ContentHandler>) {
+  REGISTERED.write().unwrap_or_else(PoisonError::into_inner).push(handler);
+}
+
+/// Names of the registered handlers, most recently registered first
+pub fn registered() -> Vec<&'static str> {
+  REGISTERED.read().unwrap_or_else(PoisonError::into_inner).iter().rev().map(|h| h.name()).collect()
+}
+
+/// Identify the content of a partition of a type from its first bytes, if any handler recognises it
+pub fn identify(partition_type: PartitionType, head: &[u8]) -> Option<Identified> {
+  let identified = |handler: &dyn ContentHandler, builtin: bool| Identified {
+    name: handler.name(),
+    description: handler.describe(head),
+    builtin,
+  };
+  let registered = REGISTERED.read().unwrap_or_else(PoisonError::into_inner);
+  if let Some(handler) = registered.iter().rev().find(|h| h.handles(partition_type) && h.probe(head)) {
+    return Some(identified(handler.as_ref(), false));
+  }
+  BUILTIN.iter()
+    .find(|h| h.handles(partition_type) && h.probe(head))
+    .map(|handler| identified(*handler, true))
+}
+
+/// Synchronously read the first bytes of a partition starting at an offset, and identify its content
+pub fn probe_partition<R: ?Sized>(reader: &mut R, start: u64, partition_type: PartitionType) -> io::Result<Option<Identified>>
+  where R: Read + Seek {
+  reader.seek(SeekFrom::Start(start))?;
+  let mut head = Vec::with_capacity(PROBE_SZ);
+  (&mut *reader).take(PROBE_SZ as u64).read_to_end(&mut head)?;
+  Ok(identify(partition_type, &head))
+}
+
+impl ContentHandler for VolumeHeaderContent {
+  fn name(&self) -> &'static str {
+    "volume-header"
+  }
+
+  fn probe(&self, head: &[u8]) -> bool {
+    head.starts_with(&SgidiskVolume::MAGIC)
+  }
+}
+
+impl ContentHandler for XfsContent {
+  fn name(&self) -> &'static str {
+    "xfs"
+  }
+
+  fn probe(&self, head: &[u8]) -> bool {
+    head.starts_with(b"XFSB")
+  }
+}
+
+impl ContentHandler for EfsContent {
+  fn name(&self) -> &'static str {
+    "efs"
+  }
+
+  fn probe(&self, head: &[u8]) -> bool {
+    Efs::read(&mut Cursor::new(head), EFS_BLOCK_SZ as u64, 0).is_ok()
+  }
+}
@@ -0,0 +1,95 @@
+//! Copying sections of a disk image, Volume Header files and EFS files to writers, reporting
+//! progress in bytes copied and stopping if cancelled
+
+use std::io;
+use std::io::{Read, Seek, SeekFrom, Write};
+
+use crate::SgidiskLibReadError;
+use crate::efs::{Efs, Inode};
+use crate::progress::{CancellationToken, Progress};
+use crate::volhdr::VolumeFile;
+
+/// Size of reads when copying
+const COPY_BUF_SZ: usize = 1024 * 64;
+
+/// Copy a section of a reader to a writer at an offset, returning the number of bytes copied,
+/// which is short of `src_len` if the reader ends first. If cancelled, the error wraps
+/// `SgidiskLibReadError::Cancelled`.
+pub fn cp<R: ?Sized, W: ?Sized, P>(src: &mut R, src_start: u64, src_len: u64, dst: &mut W, dst_start: u64, cancel: &CancellationToken, mut progress: P) -> Result<u64, io::Error>
+  where R: Read + Seek, W: Write + Seek, P: FnMut(Progress) {
+  src.seek(SeekFrom::Start(src_start))?;
+  dst.seek(SeekFrom::Start(dst_start))?;
+
+  let mut buf = vec![0u8; COPY_BUF_SZ];
+  let mut copied = 0u64;
+  while copied < src_len {
+    cancel.check_io()?;
+    let want = (src_len - copied).min(COPY_BUF_SZ as u64) as usize;
+    let n = match src.read(&mut buf[..want]) {
+      Ok(0) => break,
+      Ok(n) => n,
+      Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+      Err(e) => return Err(e)
+    };
+    dst.write_all(&buf[..n])?;
+    copied += n as u64;
+    progress(Progress::bytes(copied, Some(src_len)));
+  }
+
+  Ok(copied)
+}
+
+/// Copy a Volume Header file from a disk image to the start of a writer, returning its size
+pub fn volume_file<R: ?Sized, W: ?Sized, P>(reader: &mut R, file: &VolumeFile, writer: &mut W, cancel: &CancellationToken, progress: P) -> Result<u64, SgidiskLibReadError>
+  where R: Read + Seek, W: Write + Seek, P: FnMut(Progress) {
+  let src_start = file.byte_start();
+  let copied = match cp(reader, src_start, file.file_sz, writer, 0, cancel, progress) {
+    Ok(copied) => copied,
+    Err(_) if cancel.is_cancelled() => return Err(SgidiskLibReadError::Cancelled),
+    Err(e) => return Err(e.into())
+  };
+  if copied != file.file_sz {
+    return Err(SgidiskLibReadError::Bounds(format!("Volume file at block {} ended after {} of {} bytes", file.block_start, copied, file.file_sz)));
+  }
+  Ok(copied)
+}
+
+/// Copy the contents of an EFS regular file to a writer, returning its size
+pub fn efs_file<R: ?Sized, W: ?Sized, P>(efs: &Efs, reader: &mut R, inode: &Inode, writer: &mut W, cancel: &CancellationToken, progress: P) -> Result<u64, SgidiskLibReadError>
+  where R: Read + Seek, W: Write, P: FnMut(Progress) {
+  let mut writer = ProgressWriter {
+    inner: writer,
+    written: 0,
+    total: inode.size,
+    cancel,
+    progress,
+  };
+  match efs.read_file(reader, inode, &mut writer) {
+    Err(_) if cancel.is_cancelled() => Err(SgidiskLibReadError::Cancelled),
+    result => result
+  }
+}
+
+/// Writer which reports the progress of everything written through it, failing once cancelled
+struct ProgressWriter<'a, W: ?Sized, P> {
+  inner: &'a mut W,
+  written: u64,
+  total: u64,
+  cancel: &'a CancellationToken,
+  progress: P,
+}
+
+impl<W: ?Sized, P> Write for ProgressWriter<'_, W, P>
+  where W: Write, P: FnMut(Progress) {
+  fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+    self.cancel.check_io()?;
+    let n = self.inner.write(buf)?;
+    self.written += n as u64;
+    (self.progress)(Progress::bytes(self.written, Some(self.total)));
+    Ok(n)
+  }
+
+  fn flush(&mut self) -> io::Result<()> {
+    self.inner.flush()
+  }
+}
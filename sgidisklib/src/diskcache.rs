@@ -0,0 +1,412 @@
+//! Block cache kept on disk between runs, for disk images on slow media (network, optical) which
+//! are browsed again and again. Each image's blocks are kept in a directory named by a hash of
+//! the image's size, modification time and first blocks, so a changed image gets a cache of its
+//! own, and the caches of the least recently used images are removed to keep within a size limit.
+//! Only directories which are caches, named by a key and holding an index, are ever removed.
+//!
+//! An image without a modification time, such as an EWF container or a device, is only told apart
+//! by its size and first blocks, so a change past them isn't noticed; invalidate its cache then.
+
+use std::collections::HashMap;
+use std::fs;
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::hash::{HashAlgorithm, MultiHash};
+
+/// Start of the index file of an image's cache, followed by the block size
+const INDEX_MAGIC: &[u8; 8] = b"SGIDCACH";
+/// Size of an index record: the block number and its length
+const RECORD_SZ: usize = 12;
+/// Number of bytes at the start of an image hashed into its key
+const KEY_HEAD_SZ: u64 = 64 * 1024;
+
+/// Size of the blocks kept, and how much may be kept in all
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct DiskCacheOptions {
+  /// Size of a cached block, in bytes
+  pub block_sz: u64,
+  /// Most bytes kept for all images together; an image's blocks beyond it are read but not kept
+  pub max_bytes: u64,
+}
+
+/// Blocks of one disk image kept in a cache directory
+pub struct CachedBlocks {
+  options: DiskCacheOptions,
+  /// Blocks at their offsets, leaving holes where blocks weren't read
+  data: File,
+  /// Header, then a record of each block kept
+  index: File,
+  /// Length of each block kept, by block number; blocks at the end of the image may be short
+  blocks: HashMap<u64, u32>,
+  /// Bytes kept for this image
+  kept: u64,
+}
+
+/// Reader wrapper which keeps the blocks it reads in a cache directory, and reads them from there
+/// when read again, by this or a later DiskCache of the same image
+pub struct DiskCache<R> {
+  inner: R,
+  blocks: CachedBlocks,
+  /// Current offset
+  pos: u64,
+}
+
+impl Default for DiskCacheOptions {
+  fn default() -> Self {
+    DiskCacheOptions {
+      block_sz: 64 * 1024,
+      max_bytes: 1024 * 1024 * 1024,
+    }
+  }
+}
+
+/// Length of a key, a hex BLAKE3 digest
+const KEY_LEN: usize = 64;
+
+/// Key of a disk image's cache: a hash of its size, modification time if it has one, and first
+/// bytes
+pub fn image_key<R: ?Sized>(reader: &mut R, modified: Option<SystemTime>) -> io::Result<String>
+  where R: Read + Seek {
+  let size = reader.seek(SeekFrom::End(0))?;
+  let mut hash = MultiHash::new(&[HashAlgorithm::Blake3]);
+  hash.update(&size.to_le_bytes());
+  if let Some(modified) = modified.and_then(|m| m.duration_since(UNIX_EPOCH).ok()) {
+    hash.update(&modified.as_nanos().to_le_bytes());
+  }
+  reader.seek(SeekFrom::Start(0))?;
+  io::copy(&mut (&mut *reader).take(KEY_HEAD_SZ), &mut hash)?;
+  reader.seek(SeekFrom::Start(0))?;
+  Ok(hash.finalize().blake3.unwrap_or_default().to_lowercase())
+}
+
+/// Remove the cache of a disk image by key, if there is one
+pub fn invalidate(cache_dir: &Path, key: &str) -> io::Result<()> {
+  match fs::remove_dir_all(cache_dir.join(key)) {
+    Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+    _ => Ok(())
+  }
+}
+
+impl CachedBlocks {
+  /// Open the blocks kept for the image with a key within `cache_dir`, creating the image's
+  /// directory if need be. Blocks kept with another block size are dropped, and the caches of
+  /// other images are removed, least recently used first, until what is kept fits the size limit.
+  pub fn open(cache_dir: &Path, key: &str, options: DiskCacheOptions) -> io::Result<Self> {
+    let options = DiskCacheOptions { block_sz: options.block_sz.max(1), ..options };
+    let dir = cache_dir.join(key);
+    fs::create_dir_all(&dir)?;
+    prune(cache_dir, key, options.max_bytes)?;
+
+    let mut index = OpenOptions::new().read(true).write(true).create(true).open(dir.join("index"))?;
+    let mut records = Vec::new();
+    index.read_to_end(&mut records)?;
+    let header: Vec<u8> = INDEX_MAGIC.iter().copied().chain(options.block_sz.to_le_bytes()).collect();
+    let blocks = if records.starts_with(&header) {
+      // Drop a record cut short by a crash, so those written after it line up
+      let whole = header.len() + (records.len() - header.len()) / RECORD_SZ * RECORD_SZ;
+      index.set_len(whole as u64)?;
+      read_records(&records[header.len()..], options.block_sz)
+    } else {
+      index.set_len(0)?;
+      index.seek(SeekFrom::Start(0))?;
+      index.write_all(&header)?;
+      HashMap::new()
+    };
+    index.seek(SeekFrom::End(0))?;
+    let data = OpenOptions::new().read(true).write(true).create(true).open(dir.join("data"))?;
+    fs::write(dir.join("used"), now().to_string())?;
+
+    let kept = blocks.values().map(|len| *len as u64).sum();
+    Ok(CachedBlocks {
+      options,
+      data,
+      index,
+      blocks,
+      kept,
+    })
+  }
+
+  /// Number of blocks kept
+  pub fn len(&self) -> usize {
+    self.blocks.len()
+  }
+
+  /// Whether no blocks are kept
+  pub fn is_empty(&self) -> bool {
+    self.blocks.is_empty()
+  }
+
+  /// Data of a block, if it is kept. A block missing from a data file cut short is forgotten, to
+  /// be read from the image again.
+  fn get(&mut self, block: u64) -> io::Result<Option<Vec<u8>>> {
+    let len = match self.blocks.get(&block) {
+      Some(len) => *len,
+      None => return Ok(None)
+    };
+    let offset = match block.checked_mul(self.options.block_sz) {
+      Some(offset) => offset,
+      None => return Ok(self.forget(block))
+    };
+    let mut data = vec![0u8; len as usize];
+    self.data.seek(SeekFrom::Start(offset))?;
+    match self.data.read_exact(&mut data) {
+      Ok(()) => Ok(Some(data)),
+      Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => Ok(self.forget(block)),
+      Err(e) => Err(e)
+    }
+  }
+
+  /// Forget a kept block, returning None
+  fn forget(&mut self, block: u64) -> Option<Vec<u8>> {
+    if let Some(len) = self.blocks.remove(&block) {
+      self.kept -= len as u64;
+    }
+    None
+  }
+
+  /// Keep a block, if there is room
+  fn insert(&mut self, block: u64, data: &[u8]) -> io::Result<()> {
+    if self.kept + data.len() as u64 > self.options.max_bytes {
+      return Ok(());
+    }
+    // The data is written before its record, so a record is only ever of data kept
+    self.data.seek(SeekFrom::Start(block * self.options.block_sz))?;
+    self.data.write_all(data)?;
+    let mut record = [0u8; RECORD_SZ];
+    record[..8].copy_from_slice(&block.to_le_bytes());
+    record[8..].copy_from_slice(&(data.len() as u32).to_le_bytes());
+    self.index.write_all(&record)?;
+    self.blocks.insert(block, data.len() as u32);
+    self.kept += data.len() as u64;
+    Ok(())
+  }
+}
+
+impl<R: Read + Seek> DiskCache<R> {
+  /// Wrap a reader positioned at its start, with the blocks kept for it
+  pub fn new(inner: R, blocks: CachedBlocks) -> Self {
+    DiskCache {
+      inner,
+      blocks,
+      pos: 0,
+    }
+  }
+
+  /// Blocks kept for the wrapped reader
+  pub fn blocks(&self) -> &CachedBlocks {
+    &self.blocks
+  }
+
+  /// Get a reference to the wrapped reader
+  pub fn get_ref(&self) -> &R {
+    &self.inner
+  }
+
+  /// Read a block, from the cache if it is kept, otherwise from the wrapped reader, keeping it if
+  /// there is room
+  fn block(&mut self, block: u64) -> io::Result<Vec<u8>> {
+    if let Some(data) = self.blocks.get(block)? {
+      return Ok(data);
+    }
+    let block_sz = self.blocks.options.block_sz;
+    self.inner.seek(SeekFrom::Start(block * block_sz))?;
+    let mut data = Vec::with_capacity(block_sz as usize);
+    (&mut self.inner).take(block_sz).read_to_end(&mut data)?;
+    self.blocks.insert(block, &data)?;
+    Ok(data)
+  }
+}
+
+impl<R: Read + Seek> Read for DiskCache<R> {
+  fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+    if buf.is_empty() {
+      return Ok(0);
+    }
+    let block_sz = self.blocks.options.block_sz;
+    let block = self.pos / block_sz;
+    let offset = (self.pos % block_sz) as usize;
+    let data = self.block(block)?;
+    if offset >= data.len() {
+      return Ok(0);
+    }
+    let n = buf.len().min(data.len() - offset);
+    buf[..n].copy_from_slice(&data[offset..offset + n]);
+    self.pos += n as u64;
+    Ok(n)
+  }
+}
+
+impl<R: Read + Seek> Seek for DiskCache<R> {
+  fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+    let new_pos = match pos {
+      SeekFrom::Start(offset) => Some(offset),
+      SeekFrom::Current(delta) => u64::try_from(self.pos as i128 + delta as i128).ok(),
+      // The inner reader knows where its end is
+      SeekFrom::End(_) => Some(self.inner.seek(pos)?)
+    };
+    match new_pos {
+      Some(new_pos) => {
+        self.pos = new_pos;
+        Ok(new_pos)
+      }
+      None => Err(io::Error::new(io::ErrorKind::InvalidInput, "Invalid seek to a negative or overflowing position"))
+    }
+  }
+}
+
+impl<R> std::fmt::Debug for DiskCache<R> {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    f.debug_struct("DiskCache")
+      .field("block_sz", &self.blocks.options.block_sz)
+      .field("max_bytes", &self.blocks.options.max_bytes)
+      .field("cached", &self.blocks.len())
+      .field("pos", &self.pos)
+      .finish()
+  }
+}
+
+/// Lengths of the blocks in index records, ignoring any record cut short or longer than a block
+fn read_records(records: &[u8], block_sz: u64) -> HashMap<u64, u32> {
+  records.chunks_exact(RECORD_SZ)
+    .map(|r| {
+      let block = u64::from_le_bytes([r[0], r[1], r[2], r[3], r[4], r[5], r[6], r[7]]);
+      (block, u32::from_le_bytes([r[8], r[9], r[10], r[11]]), )
+    })
+    .filter(|(_, len, )| *len as u64 <= block_sz)
+    .collect()
+}
+
+/// Whether a directory name is the key of an image's cache
+fn is_key(name: &str) -> bool {
+  name.len() == KEY_LEN && name.bytes().all(|b| b.is_ascii_digit() || (b'a'..=b'f').contains(&b))
+}
+
+/// Remove the caches of images other than the one with a key, least recently used first, until
+/// the bytes kept for all of them fit a limit. Anything else in the cache directory is left alone,
+/// as it may be a directory of the user's given by mistake.
+fn prune(cache_dir: &Path, key: &str, max_bytes: u64) -> io::Result<()> {
+  let mut caches: Vec<(u64, u64, PathBuf, )> = Vec::new();
+  for entry in fs::read_dir(cache_dir)? {
+    let entry = entry?;
+    if !entry.file_type()?.is_dir() || !entry.file_name().to_str().map_or(false, is_key) {
+      continue;
+    }
+    let dir = entry.path();
+    let index = match fs::read(dir.join("index")) {
+      Ok(index) if index.starts_with(INDEX_MAGIC) && index.len() >= INDEX_MAGIC.len() + 8 => index,
+      _ => continue
+    };
+    let mut block_sz = [0u8; 8];
+    block_sz.copy_from_slice(&index[INDEX_MAGIC.len()..INDEX_MAGIC.len() + 8]);
+    let used = fs::read_to_string(dir.join("used")).ok().and_then(|u| u.trim().parse().ok()).unwrap_or(0);
+    let kept = read_records(&index[INDEX_MAGIC.len() + 8..], u64::from_le_bytes(block_sz)).values().map(|len| *len as u64).sum();
+    caches.push((used, kept, dir, ));
+  }
+
+  let mut total: u64 = caches.iter().map(|(_, kept, _, )| kept).sum();
+  caches.sort_by_key(|(used, _, _, )| *used);
+  for (_, kept, dir, ) in caches {
+    if total <= max_bytes {
+      break;
+    }
+    if dir.file_name().map_or(false, |name| name == key) {
+      continue;
+    }
+    fs::remove_dir_all(&dir)?;
+    total -= kept;
+  }
+  Ok(())
+}
+
+/// Seconds since the epoch
+fn now() -> u64 {
+  SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+  use std::io::Cursor;
+
+  use super::*;
+  use crate::metrics::CountingReader;
+
+  #[test]
+  fn kept_between_runs() {
+    let cache_dir = std::env::temp_dir().join(format!("sgidisk-diskcache-{}", std::process::id()));
+    let options = DiskCacheOptions { block_sz: 512, max_bytes: 16 * 1024 };
+    let data: Vec<u8> = (0..10_000u32).map(|i| (i % 251) as u8).collect();
+
+    // Read through the cache twice; the second time only the key is read from the image
+    for run in 0..2 {
+      let mut reader = CountingReader::new(Cursor::new(data.clone()));
+      let key = image_key(&mut reader, None).unwrap();
+      reader.reset_stats();
+      let mut cache = DiskCache::new(reader, CachedBlocks::open(&cache_dir, &key, options).unwrap());
+      let mut all = Vec::new();
+      cache.read_to_end(&mut all).unwrap();
+      assert_eq!(all, data);
+      cache.seek(SeekFrom::Start(600)).unwrap();
+      let mut buf = [0u8; 100];
+      cache.read_exact(&mut buf).unwrap();
+      assert_eq!(&buf[..], &data[600..700]);
+      let expected = if run == 0 { data.len() as u64 } else { 0 };
+      assert_eq!(cache.get_ref().stats().bytes_read, expected);
+    }
+
+    // A changed image has a cache of its own, and the first image's is removed to make room
+    let mut changed = data.clone();
+    changed[0] ^= 1;
+    let (first, second, ) = (image_key(&mut Cursor::new(&data), None).unwrap(), image_key(&mut Cursor::new(&changed), None).unwrap(), );
+    assert_ne!(first, second);
+    let touched = image_key(&mut Cursor::new(&data), Some(UNIX_EPOCH + std::time::Duration::from_secs(1))).unwrap();
+    assert_ne!(first, touched);
+
+    // Directories which aren't caches are never removed
+    let other = cache_dir.join("not-a-cache");
+    fs::create_dir_all(&other).unwrap();
+    fs::write(other.join("index"), b"precious").unwrap();
+    let blocks = CachedBlocks::open(&cache_dir, &second, DiskCacheOptions { max_bytes: 4096, ..options }).unwrap();
+    assert!(blocks.is_empty());
+    assert!(!cache_dir.join(&first).exists());
+    assert!(other.join("index").exists());
+
+    invalidate(&cache_dir, &second).unwrap();
+    assert!(!cache_dir.join(&second).exists());
+    fs::remove_dir_all(&cache_dir).unwrap();
+  }
+
+  #[test]
+  fn damaged_cache() {
+    let cache_dir = std::env::temp_dir().join(format!("sgidisk-diskcache-damaged-{}", std::process::id()));
+    let options = DiskCacheOptions { block_sz: 512, max_bytes: 16 * 1024 };
+    let data: Vec<u8> = (0..4000u32).map(|i| (i % 251) as u8).collect();
+    let key = image_key(&mut Cursor::new(&data), None).unwrap();
+    let mut cache = DiskCache::new(Cursor::new(data.clone()), CachedBlocks::open(&cache_dir, &key, options).unwrap());
+    cache.read_to_end(&mut Vec::new()).unwrap();
+    assert_eq!(cache.blocks().len(), 8);
+    drop(cache);
+
+    // A record longer than a block is dropped, and blocks past the end of the data file are read
+    // from the image again
+    let mut record = [0u8; RECORD_SZ];
+    record[..8].copy_from_slice(&2u64.to_le_bytes());
+    record[8..].copy_from_slice(&u32::MAX.to_le_bytes());
+    OpenOptions::new().append(true).open(cache_dir.join(&key).join("index")).unwrap().write_all(&record).unwrap();
+    OpenOptions::new().write(true).open(cache_dir.join(&key).join("data")).unwrap().set_len(1000).unwrap();
+    let mut reader = CountingReader::new(Cursor::new(data.clone()));
+    let blocks = CachedBlocks::open(&cache_dir, &key, options).unwrap();
+    assert_eq!(blocks.len(), 8);
+    let mut cache = DiskCache::new(&mut reader, blocks);
+    let mut all = Vec::new();
+    cache.read_to_end(&mut all).unwrap();
+    assert_eq!(all, data);
+    drop(cache);
+    assert_eq!(reader.stats().bytes_read, data.len() as u64 - 512);
+
+    fs::remove_dir_all(&cache_dir).unwrap();
+  }
+}
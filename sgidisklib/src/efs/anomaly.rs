@@ -0,0 +1,80 @@
+//! Checks of inode timestamps for values which a working IRIX system wouldn't have written, which
+//! usually mean a skewed clock or tampering
+
+use std::fmt;
+use std::fmt::Formatter;
+
+use super::Inode;
+
+/// Earliest plausible timestamp, 1980-01-01 00:00:00 UTC: before any system which wrote EFS
+pub const EARLIEST_PLAUSIBLE_TIME: i64 = 315_532_800;
+
+/// Timestamp of an inode
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum TimestampField {
+  Ctime,
+  Mtime,
+  Atime,
+}
+
+/// Suspicious timestamp of an inode
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum TimestampAnomaly {
+  /// Timestamp before EARLIEST_PLAUSIBLE_TIME
+  BeforeEarliest(TimestampField),
+  /// Timestamp after the superblock was last updated
+  AfterSuperblock(TimestampField),
+  /// Last accessed (atime) before the inode last changed (ctime)
+  AccessedBeforeChanged,
+  /// Last modified (mtime) after the inode last changed (ctime), although modifying a file
+  /// changes its inode too
+  ModifiedAfterChanged,
+}
+
+impl Inode {
+  /// Suspicious timestamps of the inode, given when the superblock was last updated
+  /// (`Efs::time_raw`), which isn't compared with if zero
+  pub fn timestamp_anomalies(&self, sb_time: i64) -> Vec<TimestampAnomaly> {
+    let times = [
+      (TimestampField::Ctime, self.ctime_raw, ),
+      (TimestampField::Mtime, self.mtime_raw, ),
+      (TimestampField::Atime, self.atime_raw, ),
+    ];
+    let mut anomalies = Vec::new();
+    for (field, time, ) in times {
+      if time < EARLIEST_PLAUSIBLE_TIME {
+        anomalies.push(TimestampAnomaly::BeforeEarliest(field));
+      } else if sb_time > 0 && time > sb_time {
+        anomalies.push(TimestampAnomaly::AfterSuperblock(field));
+      }
+    }
+    if self.atime_raw < self.ctime_raw {
+      anomalies.push(TimestampAnomaly::AccessedBeforeChanged);
+    }
+    if self.mtime_raw > self.ctime_raw {
+      anomalies.push(TimestampAnomaly::ModifiedAfterChanged);
+    }
+    anomalies
+  }
+}
+
+impl fmt::Display for TimestampField {
+  fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+    match self {
+      TimestampField::Ctime => write!(f, "ctime"),
+      TimestampField::Mtime => write!(f, "mtime"),
+      TimestampField::Atime => write!(f, "atime"),
+    }
+  }
+}
+
+impl fmt::Display for TimestampAnomaly {
+  fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+    match self {
+      TimestampAnomaly::BeforeEarliest(field) => write!(f, "{} before 1980", field),
+      TimestampAnomaly::AfterSuperblock(field) => write!(f, "{} after the superblock was last updated", field),
+      TimestampAnomaly::AccessedBeforeChanged => write!(f, "atime before ctime"),
+      TimestampAnomaly::ModifiedAfterChanged => write!(f, "mtime after ctime"),
+    }
+  }
+}
@@ -0,0 +1,155 @@
+//! The free data block bitmap, and checking it against the blocks which inodes actually use
+
+use std::io::{Read, Seek, SeekFrom};
+use std::ops::Range;
+
+use crate::SgidiskLibReadError;
+
+use super::{Efs, EFS_BLOCK_SZ, Inode};
+use super::raw_inode::EfsInode;
+
+/// Block the bitmap starts at, unless the superblock says otherwise
+pub(crate) const DEFAULT_BITMAP_BLOCK: u64 = 2;
+
+/// Free data block bitmap, where a set bit is a free block
+#[derive(Debug, Clone)]
+pub struct Bitmap {
+  bits: Vec<u8>,
+}
+
+/// Blocks on one side of a disagreement between the bitmap and the inodes
+#[derive(Debug, Default, Clone, Eq, PartialEq)]
+pub struct BlockDiscrepancy {
+  /// Number of blocks
+  pub count: u64,
+  /// The first few block numbers, up to MAX_EXAMPLES
+  pub examples: Vec<u64>,
+}
+
+/// Result of checking the bitmap against the extents of every inode
+#[derive(Debug, Default)]
+pub struct BitmapCheck {
+  /// Number of data blocks the bitmap covers
+  pub data_blocks: u64,
+  /// Number of data blocks the bitmap marks free
+  pub free_blocks: u64,
+  /// Blocks marked free but referenced by an inode, which could be allocated again and overwritten
+  pub free_but_referenced: BlockDiscrepancy,
+  /// Blocks marked used but referenced by no inode, which are lost until the filesystem is checked
+  pub used_but_unreferenced: BlockDiscrepancy,
+  /// Inodes which couldn't be read, so whose blocks aren't counted as referenced
+  pub unreadable_inodes: Vec<(u64, SgidiskLibReadError, )>,
+}
+
+impl BlockDiscrepancy {
+  /// Number of example block numbers kept
+  pub const MAX_EXAMPLES: usize = 10;
+
+  fn push(&mut self, block: u64) {
+    self.count += 1;
+    if self.examples.len() < Self::MAX_EXAMPLES {
+      self.examples.push(block);
+    }
+  }
+}
+
+impl Bitmap {
+  /// Whether a block is marked free
+  pub fn is_free(&self, block: u64) -> bool {
+    match self.bits.get((block / 8) as usize) {
+      Some(byte) => byte & (1 << (block % 8)) != 0,
+      None => false
+    }
+  }
+}
+
+impl Inode {
+  /// Ranges of blocks the inode uses: its contents, and the blocks holding indirect extents
+  pub fn block_ranges(&self) -> impl Iterator<Item=Range<u64>> + '_ {
+    self.extents.iter().chain(&self.indirect_extents)
+      .map(|e| e.ex_bn as u64..e.ex_bn as u64 + e.ex_length as u64)
+  }
+}
+
+impl Efs {
+  /// Ranges of data blocks of each cylinder group, after its inodes
+  pub fn data_block_ranges(&self) -> impl Iterator<Item=Range<u64>> + '_ {
+    let inode_blocks = self.cg_inodes * EfsInode::SIZE as u64 / EFS_BLOCK_SZ as u64;
+    (0..self.cg_count).map(move |cg| {
+      let cg_start = self.cg_start + cg * self.cg_size;
+      cg_start + inode_blocks..cg_start + self.cg_size
+    })
+  }
+
+  /// Byte ranges in the disk image of the data blocks a bitmap marks free, with neighbouring
+  /// blocks merged into one range
+  pub fn free_byte_ranges(&self, bitmap: &Bitmap) -> Vec<Range<u64>> {
+    let mut ranges: Vec<Range<u64>> = Vec::new();
+    for block in self.data_block_ranges().flatten().filter(|&block| bitmap.is_free(block)) {
+      let start = self.block_absolute(block);
+      match ranges.last_mut() {
+        Some(last) if last.end == start => last.end += EFS_BLOCK_SZ as u64,
+        _ => ranges.push(start..start + EFS_BLOCK_SZ as u64)
+      }
+    }
+    ranges
+  }
+
+  /// Synchronously read the free data block bitmap
+  pub fn read_bitmap<R: ?Sized>(&self, reader: &mut R) -> Result<Bitmap, SgidiskLibReadError>
+    where R: Read + Seek {
+    if self.bitmap_sz > self.limits.max_alloc {
+      return Err(SgidiskLibReadError::LimitExceeded { limit: "max_alloc", max: self.limits.max_alloc });
+    }
+    let from = self.block_absolute(self.bitmap_block);
+    self.check_read_absolute(from, self.bitmap_sz)?;
+    reader.seek(SeekFrom::Start(from))?;
+    let mut bits = vec![0u8; self.bitmap_sz as usize];
+    reader.read_exact(&mut bits)?;
+    Ok(Bitmap { bits })
+  }
+
+  /// Synchronously compare the bitmap with the blocks used by every allocated inode, finding
+  /// data blocks marked free which are in use, and those marked used which aren't
+  pub fn check_bitmap<R: ?Sized>(&self, reader: &mut R) -> Result<BitmapCheck, SgidiskLibReadError>
+    where R: Read + Seek {
+    let bitmap = self.read_bitmap(reader)?;
+    let fs_blocks = self.size / EFS_BLOCK_SZ as u64;
+    if fs_blocks / 8 > self.limits.max_alloc {
+      return Err(SgidiskLibReadError::LimitExceeded { limit: "max_alloc", max: self.limits.max_alloc });
+    }
+
+    let mut check = BitmapCheck::default();
+    let mut referenced = vec![false; fs_blocks as usize];
+    for (id, inode, ) in self.inodes(reader) {
+      match inode {
+        Ok(Some(inode)) => {
+          // Blocks past the end of the filesystem can't be read anyway, so aren't counted
+          for range in inode.block_ranges() {
+            for block in range.start.min(fs_blocks)..range.end.min(fs_blocks) {
+              referenced[block as usize] = true;
+            }
+          }
+        }
+        Ok(None) => {}
+        Err(e) => check.unreadable_inodes.push((id, e, ))
+      }
+    }
+
+    for range in self.data_block_ranges() {
+      for block in range.start.min(fs_blocks)..range.end.min(fs_blocks) {
+        check.data_blocks += 1;
+        let free = bitmap.is_free(block);
+        if free {
+          check.free_blocks += 1;
+        }
+        match (free, referenced[block as usize], ) {
+          (true, true, ) => check.free_but_referenced.push(block),
+          (false, false, ) => check.used_but_unreferenced.push(block),
+          _ => {}
+        }
+      }
+    }
+    Ok(check)
+  }
+}
@@ -0,0 +1,104 @@
+//! Consistency of the directory tree as a whole, beyond what reading each directory checks:
+//! names held by more than one entry of a directory, which IRIX never creates but corrupt or
+//! crafted directories can, and link counts which don't match the entries referring to inodes.
+
+use std::collections::HashMap;
+use std::io::{Read, Seek};
+
+use crate::SgidiskLibReadError;
+use crate::progress::CancellationToken;
+
+use super::{Efs, InodeType};
+use super::dir::Directory;
+use super::walk::Walk;
+
+/// Name held by more than one entry of a directory
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct DuplicateName {
+  /// Path of the directory, e.g. "/etc"
+  pub directory: String,
+  pub name: String,
+  /// Inode numbers of the entries by the name, in the order they are stored; lookups find the first
+  pub inode_ids: Vec<u64>,
+}
+
+/// Inode whose link count differs from the number of directory entries referring to it
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct LinkCountMismatch {
+  pub inode_id: u64,
+  /// Link count recorded in the inode
+  pub nlink: i16,
+  /// Entries found referring to the inode, counting "." and ".."
+  pub references: u64,
+}
+
+/// Result of checking every directory of a filesystem
+#[derive(Debug, Default)]
+pub struct DirectoryCheck {
+  /// Outcome of walking the filesystem, with the directories which couldn't be read
+  pub walk: Walk,
+  /// Names held more than once, by directory path
+  pub duplicate_names: Vec<DuplicateName>,
+  /// Allocated inodes whose link count is wrong, by inode number. A count too high leaves the
+  /// inode allocated when its last entry is removed; one too low frees it while entries remain.
+  /// Entries of unreadable directories aren't counted, so these are only reliable if every
+  /// directory was read.
+  pub link_count_mismatches: Vec<LinkCountMismatch>,
+  /// Inodes which couldn't be read, so whose link counts weren't checked
+  pub unreadable_inodes: Vec<(u64, SgidiskLibReadError, )>,
+}
+
+impl Efs {
+  /// Synchronously read every directory, finding names held by more than one entry, then every
+  /// allocated inode, comparing its link count with the entries found referring to it
+  pub fn check_directories<R: ?Sized>(&self, reader: &mut R) -> DirectoryCheck
+    where R: Read + Seek {
+    let mut duplicates: HashMap<u64, Vec<(String, Vec<u64>, )>> = HashMap::new();
+    let mut references: HashMap<u64, u64> = HashMap::new();
+    let mut paths = HashMap::from([(Directory::ROOT_DIRECTORY_INODE, "/".to_string(), )]);
+    let walk = self.walk_with(reader,
+                              |reader, efs, inode| {
+                                let dir = Directory::read_dir(reader, efs, inode)?;
+                                let ids = dir.entries.values().map(|(id, _, )| *id).chain(dir.duplicates.iter().map(|(_, id, )| *id));
+                                for id in ids {
+                                  *references.entry(id).or_default() += 1;
+                                }
+                                for (name, inode_id, ) in &dir.duplicates {
+                                  let names = duplicates.entry(inode).or_default();
+                                  match names.iter_mut().find(|(n, _, )| n == name) {
+                                    Some((_, ids, )) => ids.push(*inode_id),
+                                    None => names.push((name.clone(), vec![dir.entries[name].0, *inode_id], ))
+                                  }
+                                }
+                                Ok(dir)
+                              },
+                              |_, _| true, |_, _| true,
+                              |path, inode_id, inode| if inode.inode_type == InodeType::Directory {
+                                paths.entry(inode_id).or_insert_with(|| path.to_string());
+                              },
+                              &CancellationToken::new(), |_| {});
+
+    let mut duplicate_names: Vec<DuplicateName> = duplicates.into_iter()
+      .flat_map(|(dir_id, names, )| {
+        let directory = paths.get(&dir_id).cloned().unwrap_or_else(|| format!("<inode {}>", dir_id));
+        names.into_iter().map(move |(name, inode_ids, )| DuplicateName { directory: directory.clone(), name, inode_ids })
+      })
+      .collect();
+    duplicate_names.sort_by(|a, b| (&a.directory, &a.name, ).cmp(&(&b.directory, &b.name, )));
+
+    let mut check = DirectoryCheck { walk, duplicate_names, ..Default::default() };
+    for (id, inode, ) in self.inodes(reader) {
+      match inode {
+        Ok(Some(inode)) => {
+          let found = references.get(&id).copied().unwrap_or(0);
+          if inode.nlink as i64 != found as i64 {
+            check.link_count_mismatches.push(LinkCountMismatch { inode_id: id, nlink: inode.nlink, references: found });
+          }
+        }
+        Ok(None) => {}
+        Err(e) => check.unreadable_inodes.push((id, e, ))
+      }
+    }
+    check
+  }
+}
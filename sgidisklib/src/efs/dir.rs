@@ -1,4 +1,4 @@
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, HashMap};
 use std::io::{Read, Seek};
 
 use crate::SgidiskLibReadError;
@@ -11,8 +11,44 @@ use super::raw_dir::DirectoryBlock;
 pub struct Directory {
   /// Inode of this directory
   pub directory_inode: Inode,
-  /// Entries under this directory as (Inode ID, Inode) tuple
+  /// Entries under this directory as (Inode ID, Inode) tuple. Where a corrupt directory holds
+  /// a name more than once, this is the first entry by that name, as IRIX would find.
   pub entries: BTreeMap<String, (u64, Inode)>,
+  /// Entries after the first by the same name, as (name, Inode ID), in the order they are stored
+  pub duplicates: Vec<(String, u64, )>,
+}
+
+/// EFS directory with its entries in the order they are stored, and where each is stored, for
+/// looking at how entries were placed and where removed ones were
+#[derive(Debug)]
+pub struct OrderedDirectory {
+  /// Inode of this directory
+  pub directory_inode: Inode,
+  /// Entries and free slots, by directory block then slot
+  pub slots: Vec<DirectorySlot>,
+}
+
+/// Slot of a directory block, and the entry it points to if it isn't free
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct DirectorySlot {
+  /// Index of the directory block within the directory, from 0
+  pub block_index: usize,
+  /// Basic block the directory block is stored in
+  pub block: u64,
+  /// Index of the slot within the directory block
+  pub slot: usize,
+  /// Entry the slot points to, or None if the slot was freed by removing its entry
+  pub entry: Option<SlottedEntry>,
+}
+
+/// Entry of a directory as stored in a directory block
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct SlottedEntry {
+  /// Byte offset of the entry within its directory block
+  pub offset: usize,
+  /// Name, with any bytes which aren't UTF-8 replaced
+  pub name: String,
+  pub inode_id: u64,
 }
 
 impl Directory {
@@ -23,37 +59,130 @@ impl Directory {
 impl Directory {
   /// Synchronously read a directory listing from a numbered inode in an Efs.
   /// The root directory always starts at inode 2.
+  #[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", skip(reader, efs), err))]
   pub fn read_dir<R: ?Sized>(reader: &mut R, efs: &super::Efs, inode: u64) -> Result<Directory, SgidiskLibReadError>
     where R: Read + Seek {
+    Directory::read_dir_with(reader, efs, inode, |_, _| {})
+  }
+
+  /// Synchronously read a directory's entries from a numbered inode in the order they are stored,
+  /// with free slots. Entries' inodes aren't read, and names which aren't UTF-8 are kept, so this
+  /// reads what a damaged directory still holds where `read_dir` would fail.
+  pub fn read_dir_ordered<R: ?Sized>(reader: &mut R, efs: &super::Efs, inode: u64) -> Result<OrderedDirectory, SgidiskLibReadError>
+    where R: Read + Seek {
+    let directory_inode = efs.read_inode(reader, inode)?;
+    if directory_inode.inode_type != InodeType::Directory {
+      return Err(SgidiskLibReadError::Value(format!("Inode {} is not a directory (is {:#?})", inode, directory_inode.inode_type)));
+    }
+    if directory_inode.size > efs.limits.max_alloc {
+      return Err(SgidiskLibReadError::LimitExceeded { limit: "max_alloc", max: efs.limits.max_alloc });
+    }
+
+    let mut slots = Vec::new();
+    for (block_index, block, ) in directory_inode.iter().enumerate() {
+      efs.check_read_block(block, DirectoryBlock::SIZE as u64)?;
+      efs.seek_block(reader, block)?;
+      let dir_block = DirectoryBlock::read(reader)?;
+      for (slot, slotted, ) in dir_block.dir_slots()?.into_iter().enumerate() {
+        if slots.len() >= efs.limits.max_dir_entries {
+          return Err(SgidiskLibReadError::LimitExceeded { limit: "max_dir_entries", max: efs.limits.max_dir_entries as u64 });
+        }
+        slots.push(DirectorySlot {
+          block_index,
+          block,
+          slot,
+          entry: slotted.map(|(offset, entry, )| SlottedEntry {
+            offset,
+            name: String::from_utf8_lossy(entry.d_name).into_owned(),
+            inode_id: entry.inode as u64,
+          }),
+        });
+      }
+    }
+
+    Ok(OrderedDirectory {
+      directory_inode,
+      slots,
+    })
+  }
+
+  /// Read a directory listing, calling `prefetch` with the inode numbers of its entries before
+  /// reading their inodes. Each inode is read once, however many entries (".", "..", hard links)
+  /// refer to it.
+  pub(crate) fn read_dir_with<R: ?Sized, F>(reader: &mut R, efs: &super::Efs, inode: u64, mut prefetch: F) -> Result<Directory, SgidiskLibReadError>
+    where R: Read + Seek, F: FnMut(&mut R, &[u64]) {
     // Read inode and check for directory
     let directory_inode = efs.read_inode(reader, inode)?;
     if directory_inode.inode_type != InodeType::Directory {
       return Err(SgidiskLibReadError::Value(format!("Inode {} is not a directory (is {:#?})", inode, directory_inode.inode_type)));
     }
 
+    if directory_inode.size > efs.limits.max_alloc {
+      return Err(SgidiskLibReadError::LimitExceeded { limit: "max_alloc", max: efs.limits.max_alloc });
+    }
+
     // Process each block in the inode as a DirectoryBlock
-    let mut entries = BTreeMap::new();
+    let mut entry_ids = Vec::new();
     for block in &directory_inode {
       // Seek to block and read DirectoryBlock
       efs.check_read_block(block, DirectoryBlock::SIZE as u64)?;
       efs.seek_block(reader, block)?;
       let dir_block = DirectoryBlock::read(reader)?;
 
-      // Fetch inode for each directory entry
+      // Collect the name and inode number of each directory entry
       let block_entries = dir_block.dir_entries()?;
+      #[cfg(feature = "tracing")]
+      tracing::trace!(block, entries = block_entries.len(), "Decoded directory block");
       for block_entry in &block_entries {
-        let entry_name = match String::from_utf8(block_entry.d_name.clone()) {
-          Ok(s) => s,
+        let entry_name = match std::str::from_utf8(block_entry.d_name) {
+          Ok(s) => s.to_string(),
           _ => return Err(SgidiskLibReadError::Value(format!("Directory entry (inode {} block {}) name failed UTF8 conversion: {:#?}", inode, block, &block_entry)))
         };
-        let entry_inode_id = block_entry.inode as u64;
-        let entry_inode = efs.read_inode(reader, entry_inode_id)?;
-        entries.insert(entry_name, (entry_inode_id, entry_inode, ));
+        if entry_ids.len() >= efs.limits.max_dir_entries {
+          return Err(SgidiskLibReadError::LimitExceeded { limit: "max_dir_entries", max: efs.limits.max_dir_entries as u64 });
+        }
+        entry_ids.push((entry_name, block_entry.inode as u64, ));
+      }
+    }
+
+    // Fetch inode for each distinct inode number, starting with the directory's own for "."
+    let mut fetched = HashMap::new();
+    fetched.insert(inode, directory_inode.clone());
+    let mut ids: Vec<u64> = entry_ids.iter().map(|(_, id, )| *id).filter(|id| *id != inode).collect();
+    ids.sort_unstable();
+    ids.dedup();
+    prefetch(reader, &ids);
+    for id in ids {
+      fetched.insert(id, efs.read_inode(reader, id)?);
+    }
+    let mut entries = BTreeMap::new();
+    let mut duplicates = Vec::new();
+    for (entry_name, entry_inode_id, ) in entry_ids {
+      if entries.contains_key(&entry_name) {
+        duplicates.push((entry_name, entry_inode_id, ));
+      } else {
+        entries.insert(entry_name, (entry_inode_id, fetched[&entry_inode_id].clone(), ));
       }
     }
     Ok(Directory {
       directory_inode,
       entries,
+      duplicates,
     })
   }
-}
\ No newline at end of file
+}
+impl OrderedDirectory {
+  /// Names held by more than one entry, in the order their first entries are stored
+  pub fn duplicate_names(&self) -> Vec<&str> {
+    let mut seen = HashMap::new();
+    let mut duplicates = Vec::new();
+    for entry in self.slots.iter().filter_map(|s| s.entry.as_ref()) {
+      let count = seen.entry(entry.name.as_str()).or_insert(0);
+      *count += 1;
+      if *count == 2 {
+        duplicates.push(entry.name.as_str());
+      }
+    }
+    duplicates
+  }
+}
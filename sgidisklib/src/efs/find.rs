@@ -0,0 +1,281 @@
+//! Finding entries of a filesystem by glob pattern and metadata: size, type and modification time.
+//! Globs match the way sgidisktool's filters always have, so anything choosing entries by pattern
+//! can share them.
+
+use std::io::{Read, Seek};
+
+use crate::SgidiskLibReadError;
+use crate::progress::CancellationToken;
+
+use super::{Efs, Inode, InodeType};
+use super::walk::Walk;
+
+/// Glob pattern over paths within a filesystem. `*` and `?` match within a name, `[...]` one of a
+/// set of characters and ranges (`[!...]` or `[^...]` one outside it), `**` as a whole component
+/// any number of directories, and `\` escapes the character after it. Wildcards don't match the
+/// leading dot of a name. Globs containing a slash match the whole path from the root, whether or
+/// not they start with one; others match the entry's name.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Glob {
+  pattern: String,
+  /// Whether the glob matches the whole path rather than the name
+  anchored: bool,
+  components: Vec<GlobComponent>,
+}
+
+/// Component of a glob, between slashes
+#[derive(Debug, Clone, Eq, PartialEq)]
+enum GlobComponent {
+  /// `**`: any number of directories
+  AnyDirectories,
+  Name(Vec<GlobToken>),
+}
+
+/// Part of a glob component which matches one character, or with `Star` any number
+#[derive(Debug, Clone, Eq, PartialEq)]
+enum GlobToken {
+  Char(char),
+  AnyChar,
+  Star,
+  /// Characters in any of the inclusive ranges, or outside all of them if negated
+  Class { negated: bool, ranges: Vec<(char, char, )> },
+}
+
+/// Conditions on the entries found, besides their path matching a pattern
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct FindOptions {
+  /// Leave out entries matching any of these, and anything within directories matching them
+  pub exclude: Vec<Glob>,
+  /// Smallest size, in bytes
+  pub min_size: Option<u64>,
+  /// Largest size, in bytes
+  pub max_size: Option<u64>,
+  /// Types of entry found, or any if empty
+  pub types: Vec<InodeType>,
+  /// Earliest modification time, in seconds since the epoch
+  pub newer: Option<i64>,
+  /// Latest modification time, in seconds since the epoch
+  pub older: Option<i64>,
+}
+
+/// Entry found in a filesystem
+#[derive(Debug, Clone)]
+pub struct FoundEntry {
+  /// Absolute path, e.g. "/usr/bin/ls"
+  pub path: String,
+  pub inode_id: u64,
+  pub inode: Inode,
+}
+
+impl Glob {
+  /// Parse a glob, with a `Value` error if a `[` isn't closed
+  pub fn new(pattern: &str) -> Result<Self, SgidiskLibReadError> {
+    let anchored = pattern.contains('/');
+    let components = pattern.split('/')
+      .filter(|c| !c.is_empty())
+      .map(|c| match c {
+        "**" if anchored => Ok(GlobComponent::AnyDirectories),
+        c => parse_component(c)
+          .map(GlobComponent::Name)
+          .ok_or_else(|| SgidiskLibReadError::Value(format!("Unclosed '[' in glob '{}'", pattern)))
+      })
+      .collect::<Result<Vec<_>, _>>()?;
+    Ok(Glob {
+      pattern: pattern.to_string(),
+      anchored,
+      components,
+    })
+  }
+
+  /// The glob as given
+  pub fn as_str(&self) -> &str {
+    &self.pattern
+  }
+
+  /// Whether the glob matches the whole path rather than the name
+  pub fn is_anchored(&self) -> bool {
+    self.anchored
+  }
+
+  /// Whether the glob matches an absolute path: all of it if the glob contains a slash,
+  /// otherwise its last component
+  pub fn matches(&self, path: &str) -> bool {
+    let components: Vec<&str> = path.split('/').filter(|c| !c.is_empty()).collect();
+    if self.anchored {
+      match_components(&self.components, &components)
+    } else {
+      match (self.components.as_slice(), components.last(), ) {
+        ([GlobComponent::Name(tokens)], Some(name), ) => match_name(tokens, name),
+        _ => false
+      }
+    }
+  }
+
+  /// Whether something within a directory could match the glob: anything if it matches names,
+  /// otherwise if the directory's path matches the glob's leading components
+  pub fn leads_to(&self, directory: &str) -> bool {
+    if !self.anchored {
+      return true;
+    }
+    let components: Vec<&str> = directory.split('/').filter(|c| !c.is_empty()).collect();
+    leads_to(&self.components, &components)
+  }
+}
+
+impl FindOptions {
+  /// Whether an entry at a path satisfies every condition
+  pub fn matches(&self, path: &str, inode: &Inode) -> bool {
+    !self.excluded(path)
+      && self.min_size.map_or(true, |min| inode.size >= min)
+      && self.max_size.map_or(true, |max| inode.size <= max)
+      && (self.types.is_empty() || self.types.contains(&inode.inode_type))
+      && self.newer.map_or(true, |newer| inode.mtime_raw >= newer)
+      && self.older.map_or(true, |older| inode.mtime_raw <= older)
+  }
+
+  /// Whether a path or a directory above it matches an exclude glob
+  pub fn excluded(&self, path: &str) -> bool {
+    ancestors(path).any(|p| self.exclude.iter().any(|glob| glob.matches(p)))
+  }
+}
+
+impl Efs {
+  /// Synchronously find the entries whose path matches a glob and which satisfy the options,
+  /// in the order a walk visits them. Only directories which could hold a match are read, so
+  /// e.g. "/usr/bin/*" reads three directories. Returns them with the outcome of the walk, which
+  /// lists any directories which couldn't be read; an invalid glob is an error.
+  pub fn find<R: ?Sized>(&self, reader: &mut R, pattern: &str, options: &FindOptions) -> Result<(Vec<FoundEntry>, Walk, ), SgidiskLibReadError>
+    where R: Read + Seek {
+    let glob = Glob::new(pattern)?;
+    let mut found = Vec::new();
+    let walk = self.walk_filtered(reader,
+                                  |path, _| glob.leads_to(path) && !options.excluded(path),
+                                  |path, inode| glob.matches(path) && options.matches(path, inode),
+                                  |path, inode_id, inode| found.push(FoundEntry { path: path.to_string(), inode_id, inode: inode.clone() }),
+                                  &CancellationToken::new(), |_| {});
+    Ok((found, walk, ))
+  }
+}
+
+impl GlobToken {
+  /// Whether the token matches a character; a star is matched separately
+  fn matches(&self, c: char) -> bool {
+    match self {
+      GlobToken::Char(expected) => c == *expected,
+      GlobToken::AnyChar => true,
+      GlobToken::Star => false,
+      GlobToken::Class { negated, ranges } => ranges.iter().any(|(lo, hi, )| (*lo..=*hi).contains(&c)) != *negated
+    }
+  }
+}
+
+/// Parse one component of a glob into tokens, or None if a `[` isn't closed
+fn parse_component(component: &str) -> Option<Vec<GlobToken>> {
+  let mut tokens = Vec::new();
+  let mut chars = component.chars().peekable();
+  while let Some(c) = chars.next() {
+    let token = match c {
+      '\\' => GlobToken::Char(chars.next().unwrap_or('\\')),
+      '?' => GlobToken::AnyChar,
+      // Consecutive stars match the same as one
+      '*' if tokens.last() == Some(&GlobToken::Star) => continue,
+      '*' => GlobToken::Star,
+      '[' => {
+        let negated = matches!(chars.peek(), Some('!') | Some('^'));
+        if negated {
+          chars.next();
+        }
+        // A ']' first is part of the set
+        let mut ranges = Vec::new();
+        let mut first = true;
+        loop {
+          let lo = match chars.next()? {
+            ']' if !first => break,
+            lo => lo
+          };
+          first = false;
+          let mut lookahead = chars.clone();
+          match (lookahead.next(), lookahead.next(), ) {
+            (Some('-'), Some(hi), ) if hi != ']' => {
+              chars.next();
+              chars.next();
+              ranges.push((lo, hi, ));
+            }
+            _ => ranges.push((lo, lo, ))
+          }
+        }
+        GlobToken::Class { negated, ranges }
+      }
+      c => GlobToken::Char(c)
+    };
+    tokens.push(token);
+  }
+  Some(tokens)
+}
+
+/// Whether glob tokens match a whole name. A star backtracks to match one more character each
+/// time what follows it fails, which only ever needs the last star's position.
+fn match_name(tokens: &[GlobToken], name: &str) -> bool {
+  let name: Vec<char> = name.chars().collect();
+  if name.first() == Some(&'.') && tokens.first() != Some(&GlobToken::Char('.')) {
+    return false;
+  }
+
+  let (mut t, mut n, ) = (0, 0, );
+  let mut star: Option<(usize, usize, )> = None;
+  while n < name.len() {
+    match tokens.get(t) {
+      Some(GlobToken::Star) => {
+        star = Some((t, n, ));
+        t += 1;
+        continue;
+      }
+      Some(token) if token.matches(name[n]) => {
+        t += 1;
+        n += 1;
+        continue;
+      }
+      _ => {}
+    }
+    match star {
+      Some((star_t, star_n, )) => {
+        star = Some((star_t, star_n + 1, ));
+        t = star_t + 1;
+        n = star_n + 1;
+      }
+      None => return false
+    }
+  }
+  tokens[t..].iter().all(|token| *token == GlobToken::Star)
+}
+
+/// Whether glob components match all the components of a path
+fn match_components(glob: &[GlobComponent], path: &[&str]) -> bool {
+  match glob.split_first() {
+    None => path.is_empty(),
+    Some((GlobComponent::AnyDirectories, rest, )) => (0..=path.len()).any(|skip| match_components(rest, &path[skip..])),
+    Some((GlobComponent::Name(tokens), rest, )) => match path.split_first() {
+      Some((name, path_rest, )) => match_name(tokens, name) && match_components(rest, path_rest),
+      None => false
+    }
+  }
+}
+
+/// Whether glob components could match a path within a directory: the directory's components
+/// match leading glob components, with some left over for what is within it
+fn leads_to(glob: &[GlobComponent], directory: &[&str]) -> bool {
+  match (glob.split_first(), directory.split_first(), ) {
+    (Some(_), None, ) => true,
+    (Some((GlobComponent::AnyDirectories, _, )), Some(_), ) => true,
+    (Some((GlobComponent::Name(tokens), rest, )), Some((name, directory_rest, )), ) => match_name(tokens, name) && leads_to(rest, directory_rest),
+    (None, _, ) => false
+  }
+}
+
+/// A path and the directories above it, e.g. `/usr/bin`, then `/usr`
+fn ancestors(path: &str) -> impl Iterator<Item=&str> {
+  std::iter::successors(Some(path), |p| match p.rfind('/') {
+    Some(0) | None => None,
+    Some(i) => Some(&p[..i])
+  })
+}
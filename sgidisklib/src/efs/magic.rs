@@ -0,0 +1,136 @@
+//! Identifying what a file holds from the magic numbers at its start, rather than its name, which
+//! on IRIX often has no extension
+
+use std::cmp::min;
+use std::io::{Read, Seek, SeekFrom};
+
+use crate::SgidiskLibReadError;
+
+use super::{Efs, EFS_BLOCK_SZ, Inode};
+
+/// Type of a file's contents
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum ContentType {
+  /// No contents
+  Empty,
+  /// ELF object, executable or shared library (IRIX 5 and later)
+  Elf,
+  /// MIPS COFF (ECOFF) object or executable (IRIX 4 and earlier, and boot files)
+  MipsCoff,
+  /// tar archive
+  Tar,
+  /// compress(1) output (.Z)
+  Compress,
+  /// gzip output
+  Gzip,
+  /// Text
+  Text,
+  /// Anything else
+  Data,
+}
+
+impl ContentType {
+  /// Number of bytes at the start of a file which are enough to identify it
+  pub const SNIFF_SZ: usize = 512;
+
+  /// Magic numbers of MIPS COFF file headers (MIPS I, II and III), read big endian: those of big
+  /// endian files, then those of little endian files (0x0162, 0x0166 and 0x0142), byte swapped
+  const MIPS_COFF_MAGICS: [u16; 6] = [0x0160, 0x0163, 0x0140, 0x6201, 0x6601, 0x4201];
+
+  /// Identify the contents of a file from its first bytes (up to SNIFF_SZ of them)
+  pub fn sniff(head: &[u8]) -> Self {
+    if head.is_empty() {
+      ContentType::Empty
+    } else if head.starts_with(b"\x7fELF") {
+      ContentType::Elf
+    } else if head.len() >= 2 && Self::MIPS_COFF_MAGICS.contains(&u16::from_be_bytes([head[0], head[1]])) {
+      ContentType::MipsCoff
+    } else if head.starts_with(&[0x1f, 0x9d]) {
+      ContentType::Compress
+    } else if head.starts_with(&[0x1f, 0x8b]) {
+      ContentType::Gzip
+    } else if is_tar(head) {
+      ContentType::Tar
+    } else if is_text(head) {
+      ContentType::Text
+    } else {
+      ContentType::Data
+    }
+  }
+
+  /// Short name, e.g. "mips-coff"
+  pub fn name(&self) -> &'static str {
+    match self {
+      ContentType::Empty => "empty",
+      ContentType::Elf => "elf",
+      ContentType::MipsCoff => "mips-coff",
+      ContentType::Tar => "tar",
+      ContentType::Compress => "compress",
+      ContentType::Gzip => "gzip",
+      ContentType::Text => "text",
+      ContentType::Data => "data",
+    }
+  }
+}
+
+/// Whether a block is a tar header: POSIX (with "ustar" magic), or old style with a valid checksum
+fn is_tar(head: &[u8]) -> bool {
+  if head.len() < ContentType::SNIFF_SZ {
+    return false;
+  }
+  if &head[257..262] == b"ustar" {
+    return true;
+  }
+  // The checksum is the sum of the header's bytes, counting its own field as spaces, in octal
+  let recorded = std::str::from_utf8(&head[148..156]).ok()
+    .map(|s| s.trim_matches(|c: char| c == '\0' || c == ' '))
+    .and_then(|s| u32::from_str_radix(s, 8).ok());
+  let sum: u32 = head[..ContentType::SNIFF_SZ].iter().enumerate()
+    .map(|(i, b, )| if (148..156).contains(&i) { b' ' as u32 } else { *b as u32 })
+    .sum();
+  head[0] != 0 && recorded == Some(sum)
+}
+
+/// Whether bytes look like text: UTF-8 (or ASCII) without control characters other than
+/// whitespace, backspace and escape. A character cut off at the end is allowed.
+fn is_text(head: &[u8]) -> bool {
+  let text = match std::str::from_utf8(head) {
+    Ok(text) => text,
+    Err(e) if e.error_len().is_none() => std::str::from_utf8(&head[..e.valid_up_to()]).unwrap(),
+    Err(_) => return false
+  };
+  !text.chars().any(|c| c.is_control() && !matches!(c, '\t' | '\n' | '\r' | '\x0c' | '\x08' | '\x1b'))
+}
+
+impl Efs {
+  /// Synchronously read up to `len` bytes from the start of a file
+  pub fn read_head<R: ?Sized>(&self, reader: &mut R, inode: &Inode, len: usize) -> Result<Vec<u8>, SgidiskLibReadError>
+    where R: Read + Seek {
+    let want = min(inode.size, len as u64);
+    let mut head = Vec::with_capacity(want as usize);
+    for (i, extent, ) in inode.extents.iter().enumerate() {
+      let remaining = want - head.len() as u64;
+      if remaining == 0 {
+        break;
+      }
+
+      let from = self.block_absolute(extent.ex_bn as u64);
+      let read_sz = min(remaining, extent.ex_length as u64 * EFS_BLOCK_SZ as u64);
+      let out_of_bounds = SgidiskLibReadError::ExtentOutOfBounds { inode: inode.id, extent: i, offset: from };
+      if self.check_read_absolute(from, read_sz).is_err() {
+        return Err(out_of_bounds);
+      }
+      reader.seek(SeekFrom::Start(from))?;
+      if (&mut *reader).take(read_sz).read_to_end(&mut head)? as u64 != read_sz {
+        return Err(out_of_bounds);
+      }
+    }
+    Ok(head)
+  }
+
+  /// Synchronously identify the contents of a file from its first bytes
+  pub fn content_type<R: ?Sized>(&self, reader: &mut R, inode: &Inode) -> Result<ContentType, SgidiskLibReadError>
+    where R: Read + Seek {
+    Ok(ContentType::sniff(&self.read_head(reader, inode, ContentType::SNIFF_SZ)?))
+  }
+}
@@ -1,19 +1,32 @@
 use std::cmp::min;
-use std::io::{Read, Seek, SeekFrom};
+use std::collections::HashSet;
+use std::io;
+use std::io::{Read, Seek, SeekFrom, Write};
 
 use chrono::{DateTime, Local, TimeZone};
 
 use crate::SgidiskLibReadError;
+use crate::limits::Limits;
 
-mod raw_sb;
-mod raw_inode;
-mod raw_dir;
+pub(crate) mod raw_sb;
+pub(crate) mod raw_inode;
+pub(crate) mod raw_dir;
 
+pub mod anomaly;
+pub mod bitmap;
+pub mod consistency;
 pub mod dir;
+pub mod find;
+pub mod magic;
+pub mod release;
+pub mod walk;
 
 /// Canonical "Basic Block" size of everything in EFS
 pub const EFS_BLOCK_SZ: usize = 512;
 
+/// Longest symbolic link target IRIX will store: its MAXPATHLEN
+pub const MAX_LINK_SZ: u64 = 1024;
+
 /// Information about an in-file EFS filesystem
 #[derive(Debug)]
 pub struct Efs {
@@ -31,11 +44,29 @@ pub struct Efs {
   pub cg_inodes: u64,
   /// Number of cylinder groups in the filesystem
   pub cg_count: u64,
+  /// Sectors per track the filesystem was made for, or zero if not recorded
+  pub track_sectors: u16,
+  /// Heads per cylinder the filesystem was made for, or zero if not recorded
+  pub heads: u16,
+  /// Time the superblock was last updated as recorded on disk, in seconds since the epoch
+  pub time_raw: i64,
+  /// Magic number of the superblock, telling filesystems made before IRIX 3.3 from later ones
+  pub magic: SuperblockMagic,
+  /// Block the free data block bitmap starts at
+  pub bitmap_block: u64,
+  /// Size of the free data block bitmap, in bytes
+  pub bitmap_sz: u64,
+  /// Caps on reading the filesystem, which may be changed after reading it
+  pub limits: Limits,
+  /// Whether path lookups match names regardless of case, when no name matches exactly
+  pub ignore_case: bool,
 }
 
 /// Inode, representing an entry in the filesystem
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Inode {
+  /// Inode number
+  pub id: u64,
   /// Type of inode
   pub inode_type: InodeType,
   /// Unix mode of entry
@@ -44,6 +75,8 @@ pub struct Inode {
   pub owner_uid: u16,
   /// Group ID of entry's owner
   pub owner_gid: u16,
+  /// Number of directory entries referring to the inode, as recorded on disk
+  pub nlink: i16,
   /// Size of file in bytes
   pub size: u64,
   /// Creation time
@@ -52,10 +85,33 @@ pub struct Inode {
   pub mtime: DateTime<chrono::Local>,
   /// Access time
   pub atime: DateTime<chrono::Local>,
+  /// Creation time as recorded on disk, in seconds since the epoch
+  pub ctime_raw: i64,
+  /// Modification time as recorded on disk, in seconds since the epoch
+  pub mtime_raw: i64,
+  /// Access time as recorded on disk, in seconds since the epoch
+  pub atime_raw: i64,
   /// Number of extents
   pub num_extents: usize,
+  /// Version of the inode format, as recorded on disk
+  pub version: u8,
+  /// Spare byte, used by AFS, as recorded on disk
+  pub spare: u8,
+  /// Device number, if dev type
+  pub device: Option<DeviceNumber>,
   /// Extents, if not dev type
   pub(crate) extents: Vec<raw_inode::Extent>,
+  /// Extents of the blocks holding the extents, if there were too many to fit in the inode
+  pub(crate) indirect_extents: Vec<raw_inode::Extent>,
+}
+
+/// Magic number of an EFS superblock
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum SuperblockMagic {
+  /// Made before IRIX 3.3, so usable by earlier releases
+  Old,
+  /// Made by IRIX 3.3 or later, whose filesystems earlier releases must not mount
+  New,
 }
 
 /// Inode type
@@ -81,14 +137,23 @@ pub enum InodeType {
   Socket,
 }
 
+/// Device number of a device inode
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct DeviceNumber {
+  /// Major number (device driver)
+  pub major: u32,
+  /// Minor number (device unit)
+  pub minor: u32,
+}
+
 impl Efs {
   /// Check that a read from an absolute offset is within the bounds of the filesystem
   pub(crate) fn check_read_absolute(&self, start: u64, len: u64) -> Result<(), SgidiskLibReadError> {
     if start < self.partition_start {
-      return Err(SgidiskLibReadError::Bounds(format!("Read at {} starts before beginning of filesystem ({})", start, self.partition_start)));
+      return Err(SgidiskLibReadError::ReadOutOfBounds { offset: start, len });
     }
     if start + len > self.partition_start + self.size {
-      return Err(SgidiskLibReadError::Bounds(format!("Read at {} for {} bytes goes past end of filesystem", self.partition_start + start, len)));
+      return Err(SgidiskLibReadError::ReadOutOfBounds { offset: start, len });
     }
 
     Ok(())
@@ -132,10 +197,19 @@ impl Efs {
     if let Some(offset_rel) = self.inode_start_rel(inode) {
       Ok(self.partition_start + offset_rel)
     } else {
-      Err(SgidiskLibReadError::Bounds(format!("Inode {} has invalid offset", inode)))
+      Err(SgidiskLibReadError::InodeOutOfBounds { inode })
     }
   }
 
+  /// Byte ranges (offset, length) of the on-disk inodes of inode numbers, skipping any outside
+  /// the filesystem
+  pub(crate) fn inode_ranges(&self, inodes: &[u64]) -> Vec<(u64, usize, )> {
+    inodes.iter()
+      .filter_map(|inode| self.inode_start(*inode).ok())
+      .map(|offset| (offset, raw_inode::EfsInode::SIZE, ))
+      .collect()
+  }
+
   /// Synchronously read a raw inode from disk
   fn read_raw_inode<R: ?Sized>(&self, reader: &mut R, inode: u64) -> Result<raw_inode::EfsInode, SgidiskLibReadError>
     where R: Read + Seek
@@ -149,15 +223,35 @@ impl Efs {
   }
 
   /// Synchronously read an Inode from the filesystem
+  #[cfg_attr(feature = "tracing", tracing::instrument(level = "trace", skip(self, reader), err))]
   pub fn read_inode<R: ?Sized>(&self, reader: &mut R, inode: u64) -> Result<Inode, SgidiskLibReadError>
     where R: Read + Seek {
     let raw = self.read_raw_inode(reader, inode)?;
+    let id = inode;
     let mut inode = Inode::try_from(&raw)?;
+    inode.id = id;
     inode.normalize_extents(reader, self)?;
     Ok(inode)
   }
 
+  /// Number of inode slots in the filesystem, allocated or not
+  pub fn inode_count(&self) -> u64 {
+    self.cg_count * self.cg_inodes
+  }
+
+  /// Iterator over every inode slot in every cylinder group, allocated or not, as (inode number,
+  /// Inode) tuples. Free slots (with a zero mode) are None.
+  pub fn inodes<'a, R: ?Sized>(&'a self, reader: &'a mut R) -> InodeIter<'a, R>
+    where R: Read + Seek {
+    InodeIter {
+      efs: self,
+      reader,
+      next: 0,
+    }
+  }
+
   /// Synchronously read / deserialize an Efs
+  #[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", skip(reader), err))]
   pub fn read<R: ?Sized>(reader: &mut R, sector_sz: u64, partition_start: u64) -> Result<Self, SgidiskLibReadError>
     where R: Read + Seek {
     // Read raw superblock
@@ -166,9 +260,248 @@ impl Efs {
     // Convert to Efs
     let mut efs = Efs::try_from((&raw, sector_sz, ))?;
     efs.partition_start = partition_start;
+    #[cfg(feature = "tracing")]
+    tracing::debug!(size = efs.size, cg_count = efs.cg_count, cg_inodes = efs.cg_inodes, "Parsed superblock");
     Ok(efs)
   }
 
+  /// Synchronously check the superblock checksum of the filesystem
+  pub fn superblock_checksum_ok<R: ?Sized>(&self, reader: &mut R) -> Result<bool, SgidiskLibReadError>
+    where R: Read + Seek {
+    // The superblock is in basic block 1 of the partition
+    reader.seek(SeekFrom::Start(self.partition_start + EFS_BLOCK_SZ as u64))?;
+    let mut buf = [0u8; raw_sb::EfsSuperblock::SIZE];
+    reader.read_exact(&mut buf)?;
+    let offset = raw_sb::EfsSuperblock::CHECKSUM_OFFSET;
+    let stored = i32::from_be_bytes([buf[offset], buf[offset + 1], buf[offset + 2], buf[offset + 3]]);
+    Ok(raw_sb::EfsSuperblock::checksum(&buf) == stored)
+  }
+
+  /// Synchronously look up an entry by absolute path (e.g. "/etc/passwd"), returning its inode
+  /// number and Inode. The path is normalized first, so "." and ".." components don't depend on
+  /// the directory entries of those names. Symbolic links are not followed; see `resolve_path`.
+  pub fn lookup_path<R: ?Sized>(&self, reader: &mut R, path: &str) -> Result<(u64, Inode), SgidiskLibReadError>
+    where R: Read + Seek {
+    let mut inode_id = dir::Directory::ROOT_DIRECTORY_INODE;
+    let mut inode = self.read_inode(reader, inode_id)?;
+
+    // Descend through each directory named in the path
+    for component in normalize_path(path).split('/').filter(|c| !c.is_empty()) {
+      let mut directory = dir::Directory::read_dir(reader, self, inode_id)?;
+      let (_, entry_inode_id, entry_inode, ) = self.take_entry(&mut directory, component, path)?;
+      inode_id = entry_inode_id;
+      inode = entry_inode;
+    }
+
+    Ok((inode_id, inode))
+  }
+
+  /// Remove an entry from a directory listing by name, or if there is none and case is ignored,
+  /// the one entry whose name differs only in case. Returns the entry's name as well.
+  fn take_entry(&self, directory: &mut dir::Directory, name: &str, path: &str) -> Result<(String, u64, Inode, ), SgidiskLibReadError> {
+    if let Some((id, inode, )) = directory.entries.remove(name) {
+      return Ok((name.to_string(), id, inode, ));
+    }
+    let not_found = || SgidiskLibReadError::NotFound(format!("No entry named '{}' while looking up '{}'", name, path));
+    if !self.ignore_case {
+      return Err(not_found());
+    }
+
+    let lower = name.to_lowercase();
+    let matches: Vec<String> = directory.entries.keys().filter(|k| k.to_lowercase() == lower).cloned().collect();
+    match matches.as_slice() {
+      [found] => match directory.entries.remove(found) {
+        Some((id, inode, )) => Ok((found.clone(), id, inode, )),
+        None => Err(not_found())
+      },
+      [] => Err(not_found()),
+      _ => Err(SgidiskLibReadError::NotFound(format!("Entries {} all match '{}' ignoring case while looking up '{}'",
+                                                     matches.join(", "), name, path)))
+    }
+  }
+
+  /// Synchronously look up an entry by absolute path, following symbolic links anywhere in the
+  /// path as IRIX would. Returns the path with its links resolved, and the inode number and Inode
+  /// it leads to. Following more than `max_symlinks` links is a `LimitExceeded` error, and coming
+  /// back to a link with the same rest of the path to resolve a `SymlinkLoop` error.
+  pub fn resolve_path<R: ?Sized>(&self, reader: &mut R, path: &str) -> Result<(String, u64, Inode, ), SgidiskLibReadError>
+    where R: Read + Seek {
+    let root_id = dir::Directory::ROOT_DIRECTORY_INODE;
+    let mut current = (String::new(), root_id, self.read_inode(reader, root_id)?, );
+    // Directories leading to the current entry, from the root
+    let mut parents: Vec<(String, u64, Inode, )> = Vec::new();
+    // Components still to resolve, last first
+    let mut pending: Vec<String> = path.split('/').rev().filter(|c| !c.is_empty()).map(String::from).collect();
+    // Links followed, by the directory they're in, their inode and the rest of the path
+    let mut followed: HashSet<(u64, u64, Vec<String>, )> = HashSet::new();
+
+    while let Some(component) = pending.pop() {
+      match component.as_str() {
+        "." => continue,
+        ".." => {
+          if let Some(parent) = parents.pop() {
+            current = parent;
+          }
+          continue;
+        }
+        _ => {}
+      }
+
+      let mut directory = dir::Directory::read_dir(reader, self, current.1)?;
+      let (entry_name, entry_id, entry, ) = self.take_entry(&mut directory, &component, path)?;
+      if entry.inode_type != InodeType::SymbolicLink {
+        let entry_path = format!("{}/{}", current.0, entry_name);
+        parents.push(std::mem::replace(&mut current, (entry_path, entry_id, entry, )));
+        continue;
+      }
+
+      if !followed.insert((current.1, entry_id, pending.clone(), )) {
+        return Err(SgidiskLibReadError::SymlinkLoop { path: path.to_string(), inode: entry_id });
+      }
+      if followed.len() > self.limits.max_symlinks {
+        return Err(SgidiskLibReadError::LimitExceeded { limit: "max_symlinks", max: self.limits.max_symlinks as u64 });
+      }
+      let target = self.read_link(reader, &entry)?;
+      if target.starts_with('/') {
+        if let Some(root) = parents.drain(..).next() {
+          current = root;
+        }
+      }
+      pending.extend(target.split('/').rev().filter(|c| !c.is_empty()).map(String::from));
+    }
+
+    if current.0.is_empty() {
+      current.0.push('/');
+    }
+    Ok(current)
+  }
+
+  /// Synchronously read the contents of a file (or any other inode with extents) and write them
+  /// to `writer`. Returns the number of bytes written, which is the size of the file.
+  pub fn read_file<R: ?Sized, W: ?Sized>(&self, reader: &mut R, inode: &Inode, writer: &mut W) -> Result<u64, SgidiskLibReadError>
+    where R: Read + Seek, W: Write {
+    let mut remaining = inode.size;
+
+    // Extents are sorted and contiguous, so can be copied in order
+    for (i, extent, ) in inode.extents.iter().enumerate() {
+      if remaining == 0 {
+        break;
+      }
+
+      // Copy extent, or as much of it as falls within the file size
+      let from = self.block_absolute(extent.ex_bn as u64);
+      let read_sz = min(remaining, extent.ex_length as u64 * EFS_BLOCK_SZ as u64);
+      let out_of_bounds = SgidiskLibReadError::ExtentOutOfBounds { inode: inode.id, extent: i, offset: from };
+      if self.check_read_absolute(from, read_sz).is_err() {
+        return Err(out_of_bounds);
+      }
+      reader.seek(SeekFrom::Start(from))?;
+      let copied = io::copy(&mut (&mut *reader).take(read_sz), writer)?;
+      if copied != read_sz {
+        return Err(out_of_bounds);
+      }
+      remaining -= copied;
+    }
+
+    if remaining > 0 {
+      return Err(SgidiskLibReadError::Value(format!("Extents end {} bytes short of file size {}", remaining, inode.size)));
+    }
+
+    Ok(inode.size)
+  }
+
+  /// Synchronously read part of the contents of a file: `len` bytes from a byte offset, or as
+  /// many as there are before its end. Only the extents overlapping the range are read. Writes
+  /// them to `writer` and returns the number of bytes written.
+  pub fn read_range<R: ?Sized, W: ?Sized>(&self, reader: &mut R, inode: &Inode, offset: u64, len: u64, writer: &mut W) -> Result<u64, SgidiskLibReadError>
+    where R: Read + Seek, W: Write {
+    let end = min(offset.saturating_add(len), inode.size);
+    let mut copied_total = 0;
+
+    // Copy the part of each extent overlapping the range
+    for (i, extent, ) in inode.extents.iter().enumerate() {
+      let ext_start = extent.ex_offset as u64 * EFS_BLOCK_SZ as u64;
+      let ext_end = ext_start + extent.ex_length as u64 * EFS_BLOCK_SZ as u64;
+      let (from, to, ) = (offset.max(ext_start), end.min(ext_end), );
+      if from >= to {
+        continue;
+      }
+
+      let absolute = self.block_absolute(extent.ex_bn as u64) + (from - ext_start);
+      let out_of_bounds = SgidiskLibReadError::ExtentOutOfBounds { inode: inode.id, extent: i, offset: absolute };
+      if self.check_read_absolute(absolute, to - from).is_err() {
+        return Err(out_of_bounds);
+      }
+      reader.seek(SeekFrom::Start(absolute))?;
+      let copied = io::copy(&mut (&mut *reader).take(to - from), writer)?;
+      if copied != to - from {
+        return Err(out_of_bounds);
+      }
+      copied_total += copied;
+    }
+
+    if copied_total < end.saturating_sub(offset) {
+      return Err(SgidiskLibReadError::Value(format!("Extents end short of file size {}", inode.size)));
+    }
+
+    Ok(copied_total)
+  }
+
+  /// Synchronously read the slack of a file: the bytes of its allocated blocks past the end of
+  /// its contents, which may hold remnants of earlier data. Writes them to `writer` and returns
+  /// the number of bytes written.
+  pub fn read_slack<R: ?Sized, W: ?Sized>(&self, reader: &mut R, inode: &Inode, writer: &mut W) -> Result<u64, SgidiskLibReadError>
+    where R: Read + Seek, W: Write {
+    let mut copied_total = 0;
+
+    for (i, extent, ) in inode.extents.iter().enumerate() {
+      // Copy the part of the extent past the end of the file
+      let ext_start = extent.ex_offset as u64 * EFS_BLOCK_SZ as u64;
+      let ext_end = ext_start + extent.ex_length as u64 * EFS_BLOCK_SZ as u64;
+      let skip = inode.size.saturating_sub(ext_start);
+      if skip >= ext_end - ext_start {
+        continue;
+      }
+
+      let from = self.block_absolute(extent.ex_bn as u64) + skip;
+      let read_sz = ext_end - ext_start - skip;
+      let out_of_bounds = SgidiskLibReadError::ExtentOutOfBounds { inode: inode.id, extent: i, offset: from };
+      if self.check_read_absolute(from, read_sz).is_err() {
+        return Err(out_of_bounds);
+      }
+      reader.seek(SeekFrom::Start(from))?;
+      let copied = io::copy(&mut (&mut *reader).take(read_sz), writer)?;
+      if copied != read_sz {
+        return Err(out_of_bounds);
+      }
+      copied_total += copied;
+    }
+
+    Ok(copied_total)
+  }
+
+  /// Synchronously read the target of a symbolic link
+  pub fn read_link<R: ?Sized>(&self, reader: &mut R, inode: &Inode) -> Result<String, SgidiskLibReadError>
+    where R: Read + Seek {
+    if inode.inode_type != InodeType::SymbolicLink {
+      return Err(SgidiskLibReadError::Value(format!("Inode is not a symbolic link (is {:#?})", inode.inode_type)));
+    }
+
+    if inode.size > self.limits.max_alloc {
+      return Err(SgidiskLibReadError::LimitExceeded { limit: "max_alloc", max: self.limits.max_alloc });
+    }
+    if inode.size > MAX_LINK_SZ {
+      return Err(SgidiskLibReadError::Value(format!("Symbolic link target of {} bytes is longer than MAXPATHLEN ({})", inode.size, MAX_LINK_SZ)));
+    }
+
+    let mut buf = Vec::with_capacity(inode.size as usize);
+    self.read_file(reader, inode, &mut buf)?;
+    match String::from_utf8(buf) {
+      Ok(s) => Ok(s),
+      Err(e) => Err(SgidiskLibReadError::Value(format!("Symbolic link target failed UTF8 conversion: {:?}", &e)))
+    }
+  }
+
   /// Absolute offset to block in filesystem
   pub(crate) fn block_absolute(&self, block: u64) -> u64 {
     self.partition_start + block * EFS_BLOCK_SZ as u64
@@ -179,7 +512,7 @@ impl Efs {
     where R: Seek {
     let offset = self.block_absolute(block);
     if offset > self.partition_start + self.size {
-      return Err(SgidiskLibReadError::Bounds(format!("Requested block {} is beyond end of filesystem ({} bytes)", block, self.size)));
+      return Err(SgidiskLibReadError::ReadOutOfBounds { offset, len: EFS_BLOCK_SZ as u64 });
     }
 
     reader.seek(SeekFrom::Start(offset))?;
@@ -197,6 +530,16 @@ impl Inode {
     }
   }
 
+  /// Number of bytes in the blocks allocated to the inode
+  pub fn allocated_sz(&self) -> u64 {
+    self.extents.iter().map(|e| e.ex_length as u64 * EFS_BLOCK_SZ as u64).sum()
+  }
+
+  /// Number of bytes of slack: allocated bytes past the end of the contents
+  pub fn slack_sz(&self) -> u64 {
+    self.allocated_sz().saturating_sub(self.size)
+  }
+
   /// Normalize extents by expanding indirect extents (if applicable) and sorting them by
   /// position into file. Check that the values provided in the extents make sense.
   fn normalize_extents<R: ?Sized>(&mut self, reader: &mut R, efs: &Efs) -> Result<(), SgidiskLibReadError>
@@ -227,6 +570,7 @@ impl Inode {
   ///
   /// If there are few enough extents to fit in one block (i.e. direct extents),
   /// the current list of extents is left untouched.
+  #[cfg_attr(feature = "tracing", tracing::instrument(level = "trace", skip_all, fields(inode = self.id, num_extents = self.num_extents), err))]
   fn expand_extents<R: ?Sized>(&mut self, reader: &mut R, efs: &Efs) -> Result<(), SgidiskLibReadError>
     where R: Read + Seek {
     // If direct extents, nothing to expand
@@ -234,8 +578,12 @@ impl Inode {
       return Ok(());
     }
 
+    if (self.num_extents * raw_inode::Extent::SIZE) as u64 > efs.limits.max_alloc {
+      return Err(SgidiskLibReadError::LimitExceeded { limit: "max_alloc", max: efs.limits.max_alloc });
+    }
     let mut extents = Vec::with_capacity(self.num_extents);
     let mut indirect_remaining = self.num_extents;
+    let mut block_buf = [0u8; EFS_BLOCK_SZ];
 
     // For each direct extent
     for extent in &self.extents {
@@ -249,17 +597,19 @@ impl Inode {
       for _block in 0..extent.ex_length {
         // Read block
         let block_read_sz = min(EFS_BLOCK_SZ, indirect_remaining * raw_inode::Extent::SIZE);
-        let mut buf = vec![0; block_read_sz];
-        reader.read_exact(&mut buf)?;
+        let buf = &mut block_buf[..block_read_sz];
+        reader.read_exact(buf)?;
         // Parse extents
-        let mut block_extents = raw_inode::Extent::parse_extents(&buf)?;
+        let mut block_extents = raw_inode::Extent::parse_extents(buf)?;
         indirect_remaining -= block_extents.len();
         extents.append(&mut block_extents);
       }
     }
 
     // Replace current list of extents
-    self.extents = extents;
+    #[cfg(feature = "tracing")]
+    tracing::trace!(indirect = self.extents.len(), expanded = extents.len(), "Expanded indirect extents");
+    self.indirect_extents = std::mem::replace(&mut self.extents, extents);
     Ok(())
   }
 
@@ -302,6 +652,9 @@ impl TryFrom<(&raw_sb::EfsSuperblock, u64, )> for Efs {
       Ok(v) => v,
       _ => return Err(SgidiskLibReadError::Value(format!("Invalid CG count: {}", sb.fs_size)))
     };
+    // Geometry is only informational, so isn't worth refusing the filesystem over
+    let track_sectors = u16::try_from(sb.fs_sectors).unwrap_or(0);
+    let heads = u16::try_from(sb.fs_heads).unwrap_or(0);
 
     Ok(Self {
       sector_sz,
@@ -312,10 +665,41 @@ impl TryFrom<(&raw_sb::EfsSuperblock, u64, )> for Efs {
       cg_size,
       cg_inodes,
       cg_count,
+      track_sectors,
+      heads,
+      time_raw: sb.fs_time as i64,
+      magic: match sb.fs_magic {
+        raw_sb::EfsSuperblockMagic::OldMagic => SuperblockMagic::Old,
+        raw_sb::EfsSuperblockMagic::NewMagic => SuperblockMagic::New
+      },
+      // The bitmap is only moved from block 2 when the filesystem has been grown
+      bitmap_block: match sb.fs_bmblock {
+        n if n > 0 => n as u64,
+        _ => bitmap::DEFAULT_BITMAP_BLOCK
+      },
+      bitmap_sz: u64::try_from(sb.fs_bmsize).unwrap_or(0),
+      limits: Limits::default(),
+      ignore_case: false,
     })
   }
 }
 
+/// Absolute path with repeated slashes and "." components removed, and ".." components removing
+/// the component before them, e.g. "//usr/./lib/../bin" is "/usr/bin". ".." at the root stays there.
+pub fn normalize_path(path: &str) -> String {
+  let mut components = Vec::new();
+  for component in path.split('/') {
+    match component {
+      "" | "." => {}
+      ".." => {
+        components.pop();
+      }
+      c => components.push(c)
+    }
+  }
+  format!("/{}", components.join("/"))
+}
+
 impl TryFrom<&raw_inode::EfsInode> for Inode {
   type Error = crate::SgidiskLibReadError;
 
@@ -362,17 +746,36 @@ impl TryFrom<&raw_inode::EfsInode> for Inode {
       .filter(|e| e.ex_length > 0)
       .collect();
 
+    // Device inodes hold a device number in place of extents
+    let device = match inode_type {
+      InodeType::CharacterSpecial | InodeType::CharacterSpecialLink | InodeType::BlockSpecial | InodeType::BlockSpecialLink => {
+        let (major, minor, ) = inode.device();
+        Some(DeviceNumber { major, minor })
+      }
+      _ => None
+    };
+
     Ok(Inode {
+      // Inode number must be set by caller, because the raw inode doesn't record it
+      id: 0,
       inode_type,
       unix_mode,
       owner_uid: inode.di_uid,
       owner_gid: inode.di_gid,
+      nlink: inode.di_nlink,
       size,
       ctime,
       mtime,
       atime,
+      ctime_raw: inode.di_ctime as i64,
+      mtime_raw: inode.di_mtime as i64,
+      atime_raw: inode.di_atime as i64,
       num_extents,
+      version: inode.di_version,
+      spare: inode.di_spare,
+      device,
       extents,
+      indirect_extents: Vec::new(),
     })
   }
 }
@@ -415,6 +818,40 @@ impl From<InodeType> for u16 {
   }
 }
 
+/// Iterator over all of the inode slots of an EFS
+pub struct InodeIter<'a, R: ?Sized> {
+  efs: &'a Efs,
+  reader: &'a mut R,
+  /// Next inode number
+  next: u64,
+}
+
+impl<'a, R: ?Sized> Iterator for InodeIter<'a, R>
+  where R: Read + Seek {
+  type Item = (u64, Result<Option<Inode>, SgidiskLibReadError>, );
+
+  /// Read the next inode slot
+  fn next(&mut self) -> Option<Self::Item> {
+    if self.next >= self.efs.inode_count() {
+      return None;
+    }
+    let id = self.next;
+    self.next += 1;
+
+    let inode = self.efs.read_raw_inode(self.reader, id)
+      .and_then(|raw| {
+        if raw.di_mode == 0 {
+          return Ok(None);
+        }
+        let mut inode = Inode::try_from(&raw)?;
+        inode.id = id;
+        inode.normalize_extents(self.reader, self.efs)?;
+        Ok(Some(inode))
+      });
+    Some((id, inode, ))
+  }
+}
+
 /// Iterator of blocks for an EFS Inode
 pub struct InodeBlockIter<'a> {
   inode: &'a Inode,
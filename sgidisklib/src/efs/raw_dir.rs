@@ -18,6 +18,7 @@ use crate::SgidiskLibReadError;
 /// a magic cookie with the following format:
 /// directory-block-number<23:0>|index-into-offsets<7:0>
 #[derive(Debug, DekuRead, DekuWrite)]
+#[cfg_attr(feature = "fuzzing", derive(arbitrary::Arbitrary))]
 // "moo" - IRIX efs_dir.h
 #[deku(magic = b"\xBE\xEF")]
 pub(crate) struct DirectoryBlock {
@@ -40,8 +41,11 @@ impl DirectoryBlock {
   const MAX_ENTRIES: usize = Self::SPACE_SZ / DirectoryEntry::MIN_SIZE;
 }
 
-/// Entry structure
+/// Entry structure, as laid out on disk. Entries are parsed as DirectoryEntryRef, borrowing their
+/// name from the directory block rather than copying it.
+#[allow(dead_code)]
 #[derive(Debug, DekuRead, DekuWrite)]
+#[cfg_attr(feature = "fuzzing", derive(arbitrary::Arbitrary))]
 pub(crate) struct DirectoryEntry {
   /// Inode number
   #[deku(endian = "big")]
@@ -53,17 +57,42 @@ pub(crate) struct DirectoryEntry {
   pub(crate) d_name: Vec<u8>,
 }
 
+/// Directory entry parsed from a directory block, borrowing its name
+#[derive(Debug)]
+pub(crate) struct DirectoryEntryRef<'a> {
+  /// Inode number
+  pub(crate) inode: u32,
+  pub(crate) d_name: &'a [u8],
+}
+
 impl DirectoryEntry {
   /// Each entry is at least:
   /// starting area: 1 byte offset
   /// ending: 4 byte inode + 1 byte strlen + 1 byte name
   /// then, padded to 2 byte half word
   const MIN_SIZE: usize = 8;
+  /// Size of the inode number and name length preceding the name
+  const HEADER_SZ: usize = 5;
+}
+
+impl<'a> DirectoryEntryRef<'a> {
+  /// Parse an entry from the start of a byte slice, borrowing its name
+  fn parse(buf: &'a [u8]) -> Result<Self, SgidiskLibReadError> {
+    if buf.len() < DirectoryEntry::HEADER_SZ {
+      return Err(SgidiskLibReadError::Bounds(format!("Directory entry header runs past end of payload ({} bytes left)", buf.len())));
+    }
+    let inode = u32::from_be_bytes([buf[0], buf[1], buf[2], buf[3]]);
+    let name_end = DirectoryEntry::HEADER_SZ + buf[4] as usize;
+    match buf.get(DirectoryEntry::HEADER_SZ..name_end) {
+      Some(d_name) => Ok(DirectoryEntryRef { inode, d_name }),
+      None => Err(SgidiskLibReadError::Bounds(format!("Directory entry name of {} bytes runs past end of payload", buf[4])))
+    }
+  }
 }
 
 impl DirectoryBlock {
   /// Parse byte buffer into DirectoryBlock
-  fn parse_directory_block(buf: &[u8]) -> Result<Self, SgidiskLibReadError> {
+  pub(crate) fn parse_directory_block(buf: &[u8]) -> Result<Self, SgidiskLibReadError> {
     let (_, db, ) = Self::from_bytes((buf, 0, ))?;
     Ok(db)
   }
@@ -72,13 +101,19 @@ impl DirectoryBlock {
   pub(crate) fn read<R: ?Sized>(reader: &mut R) -> Result<Self, SgidiskLibReadError>
     where R: Read
   {
-    let mut buf = vec![0; super::EFS_BLOCK_SZ];
+    let mut buf = [0u8; Self::SIZE];
     reader.read_exact(&mut buf)?;
     Self::parse_directory_block(&buf)
   }
 
-  /// Get directory entries from a DirectoryBlock
-  pub(crate) fn dir_entries(&self) -> Result<Vec<DirectoryEntry>, SgidiskLibReadError> {
+  /// Get directory entries from a DirectoryBlock, borrowing their names from it
+  pub(crate) fn dir_entries(&self) -> Result<Vec<DirectoryEntryRef<'_>>, SgidiskLibReadError> {
+    Ok(self.dir_slots()?.into_iter().flatten().map(|(_, entry, )| entry).collect())
+  }
+
+  /// Get the entry in each slot of a DirectoryBlock, in slot order, with its byte offset within
+  /// the block. Slots of removed entries hold zero, and are None.
+  pub(crate) fn dir_slots(&self) -> Result<Vec<Option<(usize, DirectoryEntryRef<'_>, )>>, SgidiskLibReadError> {
     // Perform some sanity checking
     let slots = self.slots as usize;
     if slots > DirectoryBlock::MAX_ENTRIES {
@@ -91,6 +126,10 @@ impl DirectoryBlock {
     for slot in 0..slots {
       // Calculate offset to directory entry structure and sanity check
       let compact_offset = self.space[slot] as usize;
+      if compact_offset == 0 {
+        entries.push(None);
+        continue;
+      }
       if compact_offset < DirectoryBlock::HEADER_SZ >> 1 {
         return Err(SgidiskLibReadError::Bounds(format!("Directory entry offset is prior to payload area (compact {})", compact_offset)));
       }
@@ -101,9 +140,7 @@ impl DirectoryBlock {
         return Err(SgidiskLibReadError::Bounds(format!("Directory entry offset is past end of payload, at {}", offset)));
       }
       // Parse DirectoryEntry and add to list
-      let buf = &self.space[offset..];
-      let (_, dent, ) = DirectoryEntry::from_bytes((buf, 0, ))?;
-      entries.push(dent);
+      entries.push(Some((offset + DirectoryBlock::HEADER_SZ, DirectoryEntryRef::parse(&self.space[offset..])?, )));
     }
 
     Ok(entries)
@@ -7,6 +7,7 @@ use crate::SgidiskLibReadError;
 /// Extent based filesystem inode as it appears on disk. The efs inode is
 /// exactly 128 bytes long.
 #[derive(Debug, DekuRead, DekuWrite)]
+#[cfg_attr(feature = "fuzzing", derive(arbitrary::Arbitrary))]
 pub(crate) struct EfsInode {
   /// Mode and type of file
   #[deku(endian = "big")]
@@ -48,7 +49,7 @@ pub(crate) struct EfsInode {
 
 impl EfsInode {
   /// File mode mask
-  pub(crate) const INODE_MODE_MASK: u16 = 0x07777;
+  pub(crate) const INODE_MODE_MASK: u16 = 0o7777;
   /// File types (inode formats)
   pub(crate) const INODE_TYPE_MASK: u16 = 0o170000;
   /// FIFO queue
@@ -79,13 +80,17 @@ impl EfsInode {
   /// Number of directly mappable extents (also in fact number of possible
   /// indirect extents since these live in the direct extent table).
   pub(crate) const EFS_DIRECTEXTENTS: usize = 12;
+
+  /// Old style device number indicating that the new style device number is used
+  pub(crate) const ODEV_NONE: u16 = 0xffff;
 }
 
 /// Layout of an extent, in memory and on disk. This structure is laid out to
 /// take exactly 8 bytes.
 ///
 /// "Magic number MUST BE ZERO"
-#[derive(Debug, DekuRead, DekuWrite)]
+#[derive(Debug, Clone, DekuRead, DekuWrite)]
+#[cfg_attr(feature = "fuzzing", derive(arbitrary::Arbitrary))]
 #[deku(magic = b"\x00")]
 pub(crate) struct Extent {
   /// Basic block number
@@ -116,7 +121,7 @@ impl Extent {
 
 impl EfsInode {
   /// Unpack a byte slice into a raw EfsInode struct
-  fn parse_inode(buf: &[u8]) -> Result<Self, SgidiskLibReadError> {
+  pub(crate) fn parse_inode(buf: &[u8]) -> Result<Self, SgidiskLibReadError> {
     let (_, inode, ) = Self::from_bytes((buf, 0, ))?;
     Ok(inode)
   }
@@ -124,10 +129,23 @@ impl EfsInode {
   /// Synchronously read / deserialize an EfsInode
   pub(crate) fn read<R: ?Sized>(reader: &mut R) -> Result<Self, SgidiskLibReadError>
     where R: Read {
-    let mut buf = vec![0; Self::SIZE];
+    let mut buf = [0u8; Self::SIZE];
     reader.read_exact(&mut buf)?;
     Self::parse_inode(&buf)
   }
+
+  /// Major and minor number of a device inode. The data area holds an old style (8 bit major,
+  /// 8 bit minor) device number, followed by a word aligned new style (14 bit major, 18 bit minor)
+  /// one which is used if the old one is `ODEV_NONE`.
+  pub(crate) fn device(&self) -> (u32, u32, ) {
+    let odev = u16::from_be_bytes([self.data[0], self.data[1]]);
+    if odev != Self::ODEV_NONE {
+      return ((odev >> 8) as u32, (odev & 0xff) as u32, );
+    }
+
+    let ndev = u32::from_be_bytes([self.data[4], self.data[5], self.data[6], self.data[7]]);
+    ((ndev >> 18) & 0x3fff, ndev & 0x3ffff, )
+  }
 }
 
 impl Extent {
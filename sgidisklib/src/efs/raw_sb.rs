@@ -7,6 +7,7 @@ use crate::SgidiskLibReadError;
 
 /// Structure of the super-block for the extent filesystem
 #[derive(Debug, DekuRead, DekuWrite)]
+#[cfg_attr(feature = "fuzzing", derive(arbitrary::Arbitrary))]
 pub(crate) struct EfsSuperblock {
   /// Size of filesystem, in sectors
   #[deku(endian = "big")]
@@ -67,7 +68,29 @@ pub(crate) struct EfsSuperblock {
 
 impl EfsSuperblock {
   /// Size of the EFS Superblock in bytes
-  const SIZE: usize = 92;
+  pub(crate) const SIZE: usize = 92;
+  /// Offset of fs_checksum in the superblock
+  pub(crate) const CHECKSUM_OFFSET: usize = 88;
+  /// Offset of fs_magic in the superblock
+  const MAGIC_OFFSET: usize = 28;
+  /// Magic number of filesystems from before IRIX 3.3
+  const OLD_MAGIC: u32 = 0x0007_2959;
+  /// Magic number of filesystems from IRIX 3.3 on
+  const NEW_MAGIC: u32 = 0x0007_295a;
+
+  /// Checksum of the superblock fields before fs_checksum, as IRIX's efs_checksum()
+  pub(crate) fn checksum(buf: &[u8]) -> i32 {
+    let mut checksum: u32 = 0;
+    for word in buf[..Self::CHECKSUM_OFFSET].chunks(2) {
+      checksum ^= u16::from_be_bytes([word[0], word[1]]) as u32;
+      checksum = checksum.rotate_left(1);
+    }
+    if checksum == u32::MAX {
+      0
+    } else {
+      checksum as i32
+    }
+  }
 }
 
 /// Values for fs_dirty. If a filesystem was cleanly unmounted, and started
@@ -79,6 +102,7 @@ impl EfsSuperblock {
 /// flag gets set to Active. Dirty is a particular value to assign fs_dirty to
 /// when a filesystem is known to be dirty.
 #[derive(Debug, Copy, Clone, Eq, PartialEq, DekuRead, DekuWrite)]
+#[cfg_attr(feature = "fuzzing", derive(arbitrary::Arbitrary))]
 #[deku(type = "i16", endian = "big")]
 pub(crate) enum EfsSuperblockDirty {
   /// Unmounted && clean
@@ -97,6 +121,7 @@ pub(crate) enum EfsSuperblockDirty {
 
 /// Magic number of EFS superblock
 #[derive(Debug, Copy, Clone, Eq, PartialEq, DekuRead, DekuWrite)]
+#[cfg_attr(feature = "fuzzing", derive(arbitrary::Arbitrary))]
 #[deku(type = "i32", endian = "big")]
 pub(crate) enum EfsSuperblockMagic {
   /// Pre-IRIX 3.3 compatible?
@@ -122,7 +147,7 @@ impl EfsSuperblock {
   }
 
   /// Parse byte slice into EfsSuperblock struct
-  fn parse_superblock(buf: &[u8]) -> Result<Self, SgidiskLibReadError> {
+  pub(crate) fn parse_superblock(buf: &[u8]) -> Result<Self, SgidiskLibReadError> {
     let (_, sb, ) = Self::from_bytes((buf, 0, ))?;
     Ok(sb)
   }
@@ -134,8 +159,18 @@ impl EfsSuperblock {
     Self::seek_superblock(reader)?;
 
     // Read superblock
-    let mut buf = vec![0; Self::SIZE];
+    let offset = reader.stream_position()?;
+    let mut buf = [0u8; Self::SIZE];
     reader.read_exact(&mut buf)?;
+    let magic = &buf[Self::MAGIC_OFFSET..Self::MAGIC_OFFSET + 4];
+    let found = u32::from_be_bytes([magic[0], magic[1], magic[2], magic[3]]);
+    if found != Self::OLD_MAGIC && found != Self::NEW_MAGIC {
+      return Err(SgidiskLibReadError::BadMagic {
+        expected: Self::NEW_MAGIC,
+        found,
+        offset: offset + Self::MAGIC_OFFSET as u64,
+      });
+    }
     Self::parse_superblock(&buf)
   }
 }
\ No newline at end of file
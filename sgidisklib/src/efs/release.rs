@@ -0,0 +1,133 @@
+//! Identifying which IRIX release a filesystem holds and what kind of media it is from, by the
+//! well known files found on it: `/etc/issue`, the `eoe` product descriptor of an installed
+//! system or a distribution, and the version string in a kernel. A distribution with a miniroot
+//! to boot from is installation media.
+
+use std::io::{Read, Seek};
+
+use super::{Efs, InodeType};
+
+/// Bytes of a kernel searched for its version string
+const KERNEL_SCAN_SZ: usize = 16 * 1024 * 1024;
+/// Bytes of other files searched for a release
+const FILE_SCAN_SZ: usize = 64 * 1024;
+/// Printable characters allowed between "IRIX" and the release following it
+const VERSION_GAP: usize = 32;
+
+/// Files naming the release, most specific first, with how much of each to search
+const RELEASE_FILES: [(&str, usize, ); 5] = [
+  // Product descriptors of the eoe (execution environment) product, e.g.
+  // "IRIX Execution Environment, 6.5.22m"
+  ("/dist/eoe", FILE_SCAN_SZ, ),
+  ("/var/inst/eoe", FILE_SCAN_SZ, ),
+  ("/usr/lib/inst/eoe", FILE_SCAN_SZ, ),
+  ("/etc/issue", FILE_SCAN_SZ, ),
+  // Kernel, e.g. "IRIX Release 5.3 IP22 Version 11091812 System V"
+  ("/unix", KERNEL_SCAN_SZ, ),
+];
+
+/// Kind of media a filesystem is from
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum MediaType {
+  /// Installed system, with a kernel or a record of installed software
+  SystemDisk,
+  /// Installation media: a distribution with a miniroot to boot
+  InstallationCd,
+  /// Distribution without a miniroot, e.g. an overlay, patch or application CD
+  DistributionCd,
+  /// Anything else, e.g. a user or option disk
+  Other,
+}
+
+/// Release named in a file
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct ReleaseEvidence {
+  pub path: String,
+  /// Release, e.g. "6.5.22m"
+  pub release: String,
+}
+
+/// What a filesystem was identified as
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct ReleaseInfo {
+  /// Release named by the most specific file, if any names one
+  pub release: Option<String>,
+  pub media: MediaType,
+  /// Whether there is a miniroot to boot for installation
+  pub miniroot: bool,
+  /// Each file found naming a release, most specific first
+  pub evidence: Vec<ReleaseEvidence>,
+}
+
+impl Efs {
+  /// Synchronously identify the IRIX release and kind of media from the well known files on the
+  /// filesystem. Files which are missing or can't be read are passed over.
+  pub fn identify_release<R: ?Sized>(&self, reader: &mut R) -> ReleaseInfo
+    where R: Read + Seek {
+    let mut exists = |path: &str, inode_type: InodeType| self.lookup_path(reader, path)
+      .map_or(false, |(_, inode, )| inode.inode_type == inode_type);
+    let miniroot = exists("/dist/miniroot", InodeType::Directory);
+    let media = if miniroot {
+      MediaType::InstallationCd
+    } else if exists("/dist", InodeType::Directory) {
+      MediaType::DistributionCd
+    } else if exists("/unix", InodeType::RegularFile) || exists("/var/inst", InodeType::Directory)
+      || exists("/usr/lib/inst", InodeType::Directory) {
+      MediaType::SystemDisk
+    } else {
+      MediaType::Other
+    };
+
+    let evidence: Vec<ReleaseEvidence> = RELEASE_FILES.iter()
+      .filter_map(|(path, scan_sz, )| {
+        let (_, inode, ) = self.lookup_path(reader, path).ok()?;
+        if inode.inode_type != InodeType::RegularFile {
+          return None;
+        }
+        let contents = self.read_head(reader, &inode, *scan_sz).ok()?;
+        Some(ReleaseEvidence {
+          path: path.to_string(),
+          release: find_release(&contents)?,
+        })
+      })
+      .collect();
+
+    ReleaseInfo {
+      release: evidence.first().map(|e| e.release.clone()),
+      media,
+      miniroot,
+      evidence,
+    }
+  }
+}
+
+/// First release following "IRIX" or "IRIX64" in some text, e.g. "6.5" in "IRIX64 Release 6.5
+/// IP27" or "6.5.22m" in "IRIX Execution Environment, 6.5.22m": digits and dots with at least one
+/// dot, and perhaps a letter for the maintenance (m) or feature (f) stream
+pub fn find_release(contents: &[u8]) -> Option<String> {
+  const MARKER: &[u8] = b"IRIX";
+  (0..contents.len().saturating_sub(MARKER.len()))
+    .filter(|i| contents[*i..].starts_with(MARKER))
+    .find_map(|i| {
+      let after = &contents[i + MARKER.len()..];
+      let after = after.strip_prefix(b"64").unwrap_or(after);
+      let gap = after.iter().take(VERSION_GAP)
+        .position(|b| !(b.is_ascii_graphic() || *b == b' ') || b.is_ascii_digit())?;
+      if !after[gap].is_ascii_digit() {
+        return None;
+      }
+      let version = &after[gap..];
+      let mut len = version.iter().position(|b| !(b.is_ascii_digit() || *b == b'.')).unwrap_or(version.len());
+      while len > 0 && version[len - 1] == b'.' {
+        len -= 1;
+      }
+      if !version[..len].contains(&b'.') {
+        return None;
+      }
+      if version.get(len).map_or(false, |b| b.is_ascii_lowercase())
+        && version.get(len + 1).map_or(true, |b| !b.is_ascii_alphanumeric()) {
+        len += 1;
+      }
+      Some(String::from_utf8_lossy(&version[..len]).to_string())
+    })
+}
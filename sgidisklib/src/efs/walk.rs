@@ -0,0 +1,94 @@
+use std::collections::HashSet;
+use std::io::{Read, Seek};
+
+use crate::SgidiskLibReadError;
+use crate::progress::{CancellationToken, Progress};
+
+use super::{Efs, Inode, InodeType};
+use super::dir::Directory;
+
+/// Outcome of walking a filesystem
+#[derive(Debug, Default)]
+pub struct Walk {
+  /// Number of directories read
+  pub directories: u64,
+  /// Number of entries reached, not counting "." and "..", whether or not a filter passed them on
+  /// to be visited
+  pub entries: u64,
+  /// Paths of directories which couldn't be read, or weren't because a limit was reached, and
+  /// why; their contents weren't visited
+  pub unreadable: Vec<(String, SgidiskLibReadError, )>,
+  /// Whether the walk was cancelled before visiting every entry
+  pub cancelled: bool,
+}
+
+impl Efs {
+  /// Synchronously walk the whole filesystem from the root directory, depth first, calling
+  /// `visit` with the path, inode number and Inode of each entry. Directories are visited before
+  /// their contents, and only descended into once however many times they are reached.
+  /// `progress` is called with the number of entries visited after each directory is read, and
+  /// the walk stops there if cancelled. Directories deeper than the filesystem's `max_depth`
+  /// limit aren't read, and the walk stops once `max_inodes` entries have been visited.
+  pub fn walk<R: ?Sized, F, P>(&self, reader: &mut R, visit: F, cancel: &CancellationToken, progress: P) -> Walk
+    where R: Read + Seek, F: FnMut(&str, u64, &Inode), P: FnMut(Progress) {
+    self.walk_with(reader, |reader, efs, inode| Directory::read_dir(reader, efs, inode), |_, _| true, |_, _| true, visit, cancel, progress)
+  }
+
+  /// Synchronously walk the filesystem like `walk`, but only descending into the directories for
+  /// whose path and Inode `descend` returns true, and only visiting the entries for which `filter`
+  /// does. Directories which aren't descended into aren't read at all, so a walk of part of the
+  /// tree (e.g. skipping /usr/share) costs only what it reads. Filtered out directories are still
+  /// descended into if `descend` says so.
+  pub fn walk_filtered<R: ?Sized, Dd, Fl, F, P>(&self, reader: &mut R, descend: Dd, filter: Fl, visit: F, cancel: &CancellationToken, progress: P) -> Walk
+    where R: Read + Seek, Dd: FnMut(&str, &Inode) -> bool, Fl: FnMut(&str, &Inode) -> bool, F: FnMut(&str, u64, &Inode), P: FnMut(Progress) {
+    self.walk_with(reader, |reader, efs, inode| Directory::read_dir(reader, efs, inode), descend, filter, visit, cancel, progress)
+  }
+
+  /// Walk the filesystem, reading each directory with `read_dir`, descending into those `descend`
+  /// accepts and visiting the entries `filter` accepts
+  #[allow(clippy::too_many_arguments)]
+  pub(crate) fn walk_with<R: ?Sized, D, Dd, Fl, F, P>(&self, reader: &mut R, mut read_dir: D, mut descend: Dd, mut filter: Fl, mut visit: F, cancel: &CancellationToken, mut progress: P) -> Walk
+    where R: Read + Seek, D: FnMut(&mut R, &Efs, u64) -> Result<Directory, SgidiskLibReadError>, Dd: FnMut(&str, &Inode) -> bool,
+          Fl: FnMut(&str, &Inode) -> bool, F: FnMut(&str, u64, &Inode), P: FnMut(Progress) {
+    let mut walk = Walk::default();
+    let mut pending = vec![(String::new(), Directory::ROOT_DIRECTORY_INODE, 0, )];
+    let mut visited = HashSet::new();
+    while let Some((path, dir_id, depth, )) = pending.pop() {
+      if !visited.insert(dir_id) {
+        continue;
+      }
+      let dir = match self.limits.check_depth(depth).and_then(|_| read_dir(reader, self, dir_id)) {
+        Ok(dir) => dir,
+        Err(e) => {
+          walk.unreadable.push((format!("{}/", &path), e, ));
+          continue;
+        }
+      };
+      walk.directories += 1;
+
+      let mut subdirs = Vec::new();
+      for (name, (inode_id, inode, ), ) in dir.entries.iter().filter(|(name, _, )| *name != "." && *name != "..") {
+        let entry_path = format!("{}/{}", &path, name);
+        if walk.entries >= self.limits.max_inodes {
+          walk.unreadable.push((entry_path, SgidiskLibReadError::LimitExceeded { limit: "max_inodes", max: self.limits.max_inodes }, ));
+          return walk;
+        }
+        if filter(&entry_path, inode) {
+          visit(&entry_path, *inode_id, inode);
+        }
+        walk.entries += 1;
+        if inode.inode_type == InodeType::Directory && descend(&entry_path, inode) {
+          subdirs.push((entry_path, *inode_id, depth + 1, ));
+        }
+      }
+      // Descend in name order
+      pending.extend(subdirs.into_iter().rev());
+      progress(Progress::items(walk.entries, None));
+      if cancel.is_cancelled() {
+        walk.cancelled = true;
+        break;
+      }
+    }
+    walk
+  }
+}
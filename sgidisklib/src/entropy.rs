@@ -0,0 +1,144 @@
+//! Shannon entropy of fixed-size windows of data, to tell blank space, filesystem structures and
+//! text from compressed or encrypted data. Like the strings scanner, the scanner is a writer, so a
+//! region of a disk image can be measured as it is copied.
+
+use std::io;
+use std::io::Write;
+
+/// Window size unless told otherwise
+pub const DEFAULT_WINDOW_SZ: u64 = 64 * 1024;
+
+/// Rough kind of data, judged by its entropy
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum EntropyClass {
+  /// Every byte the same, usually zeroes
+  Blank,
+  /// Below 6 bits per byte: text, code, filesystem metadata, sparse data
+  Structured,
+  /// 6 to 7.5 bits per byte: dense binary data, or a mixture
+  Binary,
+  /// 7.5 bits per byte or more: compressed, encrypted or random data
+  Compressed,
+}
+
+/// Entropy of one window
+#[derive(Debug, Clone, PartialEq)]
+pub struct WindowEntropy {
+  /// Offset of the window's first byte
+  pub offset: u64,
+  /// Number of bytes, which is less than the window size for the last window
+  pub len: u64,
+  /// Shannon entropy, in bits per byte from 0 to 8
+  pub entropy: f64,
+  /// Whether every byte is the same
+  pub uniform: bool,
+}
+
+/// Writer measuring the entropy of each window of the bytes written to it
+#[derive(Debug)]
+pub struct EntropyScanner {
+  window_sz: u64,
+  /// Offset of the current window
+  offset: u64,
+  counts: [u64; 256],
+  len: u64,
+  windows: Vec<WindowEntropy>,
+}
+
+impl EntropyClass {
+  /// Class of data with an entropy, and whether its bytes are all the same
+  pub fn of(entropy: f64, uniform: bool) -> Self {
+    if uniform {
+      EntropyClass::Blank
+    } else if entropy < 6.0 {
+      EntropyClass::Structured
+    } else if entropy < 7.5 {
+      EntropyClass::Binary
+    } else {
+      EntropyClass::Compressed
+    }
+  }
+
+  /// Short name, e.g. "compressed"
+  pub fn name(&self) -> &'static str {
+    match self {
+      EntropyClass::Blank => "blank",
+      EntropyClass::Structured => "structured",
+      EntropyClass::Binary => "binary",
+      EntropyClass::Compressed => "compressed"
+    }
+  }
+}
+
+impl WindowEntropy {
+  /// Rough kind of data in the window
+  pub fn class(&self) -> EntropyClass {
+    EntropyClass::of(self.entropy, self.uniform)
+  }
+}
+
+impl EntropyScanner {
+  /// Scanner of windows of `window_sz` bytes, starting at `offset`
+  pub fn new(offset: u64, window_sz: u64) -> Self {
+    EntropyScanner {
+      window_sz: window_sz.max(1),
+      offset,
+      counts: [0; 256],
+      len: 0,
+      windows: Vec::new(),
+    }
+  }
+
+  /// Entropy of each window, in order, including a last partial window
+  pub fn finish(mut self) -> Vec<WindowEntropy> {
+    self.end_window();
+    self.windows
+  }
+
+  /// Record the current window, if it has any bytes, and start the next
+  fn end_window(&mut self) {
+    if self.len == 0 {
+      return;
+    }
+    let len = self.len as f64;
+    let entropy = self.counts.iter()
+      .filter(|&&count| count > 0)
+      .map(|&count| {
+        let p = count as f64 / len;
+        -p * p.log2()
+      })
+      .sum::<f64>();
+    self.windows.push(WindowEntropy {
+      offset: self.offset,
+      len: self.len,
+      // A uniform window's sum is -0.0
+      entropy: entropy.max(0.0),
+      uniform: self.counts.iter().any(|&count| count == self.len),
+    });
+    self.offset += self.len;
+    self.counts = [0; 256];
+    self.len = 0;
+  }
+}
+
+impl Write for EntropyScanner {
+  fn write(&mut self, mut buf: &[u8]) -> io::Result<usize> {
+    let written = buf.len();
+    while !buf.is_empty() {
+      let take = ((self.window_sz - self.len) as usize).min(buf.len());
+      for &b in &buf[..take] {
+        self.counts[b as usize] += 1;
+      }
+      self.len += take as u64;
+      buf = &buf[take..];
+      if self.len == self.window_sz {
+        self.end_window();
+      }
+    }
+    Ok(written)
+  }
+
+  fn flush(&mut self) -> io::Result<()> {
+    Ok(())
+  }
+}
@@ -0,0 +1,400 @@
+//! Read-only access to EnCase EWF (E01) evidence containers, enabled with the `ewf` feature.
+//! Media data is stored in chunks, each optionally zlib compressed, located through the
+//! "table" sections of one or more segment files (`image.E01`, `image.E02`, ...).
+//!
+//! Every count, offset and size is read from the container, so is checked against the segment
+//! holding it before being used, and chunks are limited to a size EWF writers never exceed.
+
+use std::cmp::min;
+use std::fs::File;
+use std::io;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+
+use flate2::read::ZlibDecoder;
+
+use crate::SgidiskLibReadError;
+
+/// Signature at the start of every EWF-E01 segment file
+pub const EWF_SIGNATURE: [u8; 8] = *b"EVF\x09\x0d\x0a\xff\x00";
+
+/// Size of the segment file header: signature, fields start, segment number, fields end
+const FILE_HEADER_SZ: u64 = 13;
+/// Size of a section descriptor: type, next offset, size, padding, checksum
+const SECTION_DESCRIPTOR_SZ: u64 = 76;
+/// Size of a table section's header, before its entries
+const TABLE_HEADER_SZ: u64 = 24;
+/// Flag in a table entry marking the chunk as compressed
+const TABLE_ENTRY_COMPRESSED: u32 = 0x8000_0000;
+/// Maximum number of segment files (E01..E99, then EAA..ZZZ)
+const MAX_SEGMENTS: usize = 14_971;
+/// Largest chunk read; EWF writers use 32 KiB unless told otherwise, and at most a few MiB
+const MAX_CHUNK_SZ: u64 = 64 * 1024 * 1024;
+
+/// Location of a chunk of media data within the segment files
+#[derive(Debug, Copy, Clone)]
+struct Chunk {
+  /// Index of the segment file
+  segment: usize,
+  /// Offset within the segment file
+  offset: u64,
+  /// Stored length, including the checksum of uncompressed chunks
+  len: u64,
+  compressed: bool,
+}
+
+/// Reader over the media data of an EWF evidence container, from its segment files or any other
+/// readers of the segments
+#[derive(Debug)]
+pub struct EwfReader<S = File> {
+  segments: Vec<S>,
+  chunks: Vec<Chunk>,
+  /// Size of a chunk of media data, in bytes
+  chunk_sz: u64,
+  /// Size of the media data, in bytes
+  size: u64,
+  /// Current position in the media data
+  pos: u64,
+  /// Most recently read chunk, by index
+  cached: Option<(usize, Vec<u8>, )>,
+}
+
+impl EwfReader<File> {
+  /// Check whether a file starts with the EWF signature
+  pub fn is_ewf<P: AsRef<Path>>(path: P) -> io::Result<bool> {
+    let mut signature = [0; EWF_SIGNATURE.len()];
+    match File::open(path)?.read_exact(&mut signature) {
+      Ok(_) => Ok(signature == EWF_SIGNATURE),
+      Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => Ok(false),
+      Err(e) => Err(e)
+    }
+  }
+
+  /// Open an EWF evidence container from its first segment file. Later segment files are found
+  /// next to it by extension.
+  pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, SgidiskLibReadError> {
+    let first = path.as_ref();
+    let mut reader = EwfReader::empty();
+
+    // Read segments until one ends with a "done" section
+    loop {
+      if reader.segments.len() >= MAX_SEGMENTS {
+        return Err(SgidiskLibReadError::Value(format!("More than {} EWF segment files", MAX_SEGMENTS)));
+      }
+      let segment_path = segment_path(first, reader.segments.len() + 1)?;
+      let file = match File::open(&segment_path) {
+        Ok(file) => file,
+        Err(e) => return Err(SgidiskLibReadError::Value(format!("Unable to open EWF segment file '{}': {:?}", segment_path.to_string_lossy(), &e)))
+      };
+      reader.segments.push(file);
+      if reader.read_segment(reader.segments.len() - 1)? {
+        break;
+      }
+    }
+    reader.check_chunks()?;
+    Ok(reader)
+  }
+}
+
+impl<S: Read + Seek> EwfReader<S> {
+  /// Reader with no segments read yet
+  fn empty() -> Self {
+    EwfReader {
+      segments: Vec::new(),
+      chunks: Vec::new(),
+      chunk_sz: 0,
+      size: 0,
+      pos: 0,
+      cached: None,
+    }
+  }
+
+  /// Read an EWF evidence container from its segments in order, e.g. held in memory. The last
+  /// must end with a "done" section and the others with a "next" section.
+  pub fn from_segments(segments: Vec<S>) -> Result<Self, SgidiskLibReadError> {
+    let count = segments.len();
+    let mut reader = EwfReader::empty();
+    for segment in segments {
+      reader.segments.push(segment);
+      let done = reader.read_segment(reader.segments.len() - 1)?;
+      if done != (reader.segments.len() == count) {
+        return Err(SgidiskLibReadError::Value(format!("EWF segment {} of {} ends with a {} section", reader.segments.len(), count,
+                                                      if done { "done" } else { "next" })));
+      }
+    }
+    reader.check_chunks()?;
+    Ok(reader)
+  }
+
+  /// Check that the media's geometry was read, and that there are chunks for all of it
+  fn check_chunks(&self) -> Result<(), SgidiskLibReadError> {
+    if self.chunk_sz == 0 {
+      return Err(SgidiskLibReadError::Value("EWF container has no volume section".to_string()));
+    }
+    let expected_chunks = self.size / self.chunk_sz + u64::from(self.size % self.chunk_sz != 0);
+    if (self.chunks.len() as u64) < expected_chunks {
+      return Err(SgidiskLibReadError::Bounds(format!("EWF container has {} chunks, but its media needs {}", self.chunks.len(), expected_chunks)));
+    }
+    Ok(())
+  }
+
+  /// Size of the media data, in bytes
+  pub fn size(&self) -> u64 {
+    self.size
+  }
+
+  /// Read the sections of a segment file, recording its chunks. Returns whether it is the last
+  /// segment.
+  fn read_segment(&mut self, segment: usize) -> Result<bool, SgidiskLibReadError> {
+    let file = &mut self.segments[segment];
+    let segment_len = file.seek(SeekFrom::End(0))?;
+    let mut header = [0; FILE_HEADER_SZ as usize];
+    file.seek(SeekFrom::Start(0))?;
+    file.read_exact(&mut header)?;
+    if header[..EWF_SIGNATURE.len()] != EWF_SIGNATURE {
+      return Err(SgidiskLibReadError::Value(format!("EWF segment {} has no EWF signature", segment + 1)));
+    }
+
+    // End of the last "sectors" section, where the last chunk of a table ends
+    let mut sectors_end = None;
+    let mut offset = FILE_HEADER_SZ;
+    loop {
+      if offset.checked_add(SECTION_DESCRIPTOR_SZ).map_or(true, |end| end > segment_len) {
+        return Err(SgidiskLibReadError::Bounds(format!("EWF segment {} ends without a next or done section", segment + 1)));
+      }
+      let mut descriptor = [0; SECTION_DESCRIPTOR_SZ as usize];
+      let file = &mut self.segments[segment];
+      file.seek(SeekFrom::Start(offset))?;
+      file.read_exact(&mut descriptor)?;
+      let section_type = section_type(&descriptor[..16]);
+      let next = le_u64(&descriptor[16..24]);
+      let size = le_u64(&descriptor[24..32]);
+
+      match section_type.as_str() {
+        "volume" | "disk" => self.read_volume(segment, offset + SECTION_DESCRIPTOR_SZ)?,
+        "sectors" => match offset.checked_add(size) {
+          Some(end) => sectors_end = Some(end),
+          None => return Err(SgidiskLibReadError::Bounds(format!("EWF sectors section at {} in segment {} has size {}", offset, segment + 1, size)))
+        },
+        "table" => self.read_table(segment, offset, segment_len, sectors_end)?,
+        "next" => return Ok(false),
+        "done" => return Ok(true),
+        // Other sections (headers, table2 copies, hashes, errors) aren't needed to read media
+        _ => {}
+      }
+
+      // The next offset must move forward, or the sections loop
+      if next <= offset {
+        return Err(SgidiskLibReadError::Value(format!("EWF {} section at {} in segment {} doesn't lead to a next section", section_type, offset, segment + 1)));
+      }
+      offset = next;
+    }
+  }
+
+  /// Read the media geometry from a volume section's data
+  fn read_volume(&mut self, segment: usize, offset: u64) -> Result<(), SgidiskLibReadError> {
+    let mut volume = [0; 24];
+    let file = &mut self.segments[segment];
+    file.seek(SeekFrom::Start(offset))?;
+    file.read_exact(&mut volume)?;
+    let sectors_per_chunk = le_u32(&volume[8..12]) as u64;
+    let bytes_per_sector = le_u32(&volume[12..16]) as u64;
+    let sector_count = le_u64(&volume[16..24]);
+
+    // Both are 32 bit, so their product can't overflow
+    let chunk_sz = sectors_per_chunk * bytes_per_sector;
+    if chunk_sz == 0 || chunk_sz > MAX_CHUNK_SZ {
+      return Err(SgidiskLibReadError::Value(format!("EWF volume section has {} sectors per chunk of {} bytes", sectors_per_chunk, bytes_per_sector)));
+    }
+    self.size = match sector_count.checked_mul(bytes_per_sector) {
+      Some(size) => size,
+      None => return Err(SgidiskLibReadError::Bounds(format!("EWF volume section has {} sectors of {} bytes", sector_count, bytes_per_sector)))
+    };
+    self.chunk_sz = chunk_sz;
+    Ok(())
+  }
+
+  /// Record the chunks listed in a table section starting at an offset, in a segment of a length
+  fn read_table(&mut self, segment: usize, offset: u64, segment_len: u64, sectors_end: Option<u64>) -> Result<(), SgidiskLibReadError> {
+    let file = &mut self.segments[segment];
+    let mut header = [0; TABLE_HEADER_SZ as usize];
+    // The descriptor was found to lie within the segment, so this can't overflow
+    file.seek(SeekFrom::Start(offset + SECTION_DESCRIPTOR_SZ))?;
+    file.read_exact(&mut header)?;
+    let entry_count = le_u32(&header[0..4]) as u64;
+    let base_offset = le_u64(&header[8..16]);
+
+    // The entries must lie within the segment, which bounds the memory they take
+    let entries_end = (offset + SECTION_DESCRIPTOR_SZ + TABLE_HEADER_SZ).checked_add(entry_count * 4);
+    if entries_end.map_or(true, |end| end > segment_len) {
+      return Err(SgidiskLibReadError::Bounds(format!("EWF table at {} in segment {} has {} entries, more than the segment holds", offset, segment + 1, entry_count)));
+    }
+    let mut entries = vec![0; entry_count as usize * 4];
+    file.read_exact(&mut entries)?;
+    let entries: Vec<u32> = entries.chunks(4).map(le_u32).collect();
+
+    // Each chunk runs to the start of the next one; the last runs to the end of the sectors
+    // section, or to this table if there isn't one before it
+    let end = sectors_end.filter(|end| *end <= offset).unwrap_or(offset);
+    let entry_offset = |entry: u32| match base_offset.checked_add((entry & !TABLE_ENTRY_COMPRESSED) as u64) {
+      Some(entry_offset) => Ok(entry_offset),
+      None => Err(SgidiskLibReadError::Bounds(format!("EWF table at {} in segment {} has base offset {}", offset, segment + 1, base_offset)))
+    };
+    for (n, entry, ) in entries.iter().enumerate() {
+      let chunk_offset = entry_offset(*entry)?;
+      let chunk_end = match entries.get(n + 1) {
+        Some(next) => entry_offset(*next)?,
+        None => end
+      };
+      if chunk_end <= chunk_offset {
+        return Err(SgidiskLibReadError::Bounds(format!("EWF chunk at {} in segment {} ends at {}", chunk_offset, segment + 1, chunk_end)));
+      }
+      self.chunks.push(Chunk {
+        segment,
+        offset: chunk_offset,
+        len: chunk_end - chunk_offset,
+        compressed: entry & TABLE_ENTRY_COMPRESSED != 0,
+      });
+    }
+    Ok(())
+  }
+
+  /// Read and decompress a chunk of media data
+  fn read_chunk(&mut self, index: usize) -> Result<Vec<u8>, SgidiskLibReadError> {
+    let chunk = self.chunks[index];
+    // The last chunk may be short
+    let expected = min(self.chunk_sz, self.size - index as u64 * self.chunk_sz);
+
+    let file = &mut self.segments[chunk.segment];
+    file.seek(SeekFrom::Start(chunk.offset))?;
+    let mut data = Vec::with_capacity(expected as usize);
+    if chunk.compressed {
+      ZlibDecoder::new(file.take(chunk.len)).take(expected).read_to_end(&mut data)?;
+    } else {
+      // Uncompressed chunks are followed by their checksum
+      file.take(min(chunk.len, expected)).read_to_end(&mut data)?;
+    }
+
+    if data.len() as u64 != expected {
+      return Err(SgidiskLibReadError::Bounds(format!("EWF chunk {} has {} bytes, expected {}", index, data.len(), expected)));
+    }
+    Ok(data)
+  }
+}
+
+impl<S: Read + Seek> Read for EwfReader<S> {
+  fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+    if self.pos >= self.size || buf.is_empty() {
+      return Ok(0);
+    }
+
+    let index = (self.pos / self.chunk_sz) as usize;
+    if !matches!(&self.cached, Some((cached, _, )) if *cached == index) {
+      match self.read_chunk(index) {
+        Ok(data) => self.cached = Some((index, data, )),
+        Err(SgidiskLibReadError::Io(e)) => return Err(e),
+        Err(e) => return Err(io::Error::new(io::ErrorKind::InvalidData, format!("{:?}", &e)))
+      }
+    }
+
+    let data = &self.cached.as_ref().unwrap().1;
+    let start = (self.pos - index as u64 * self.chunk_sz) as usize;
+    let len = min(buf.len(), data.len() - start);
+    buf[..len].copy_from_slice(&data[start..start + len]);
+    self.pos += len as u64;
+    Ok(len)
+  }
+}
+
+impl<S: Read + Seek> Seek for EwfReader<S> {
+  fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+    let new_pos = match pos {
+      SeekFrom::Start(offset) => Some(offset),
+      SeekFrom::Current(delta) => offset_by(self.pos, delta),
+      SeekFrom::End(delta) => offset_by(self.size, delta),
+    };
+
+    match new_pos {
+      Some(offset) => {
+        self.pos = offset;
+        Ok(offset)
+      }
+      None => Err(io::Error::new(io::ErrorKind::InvalidInput, "Invalid seek to a negative or overflowing position"))
+    }
+  }
+}
+
+/// Apply a signed offset to a position
+fn offset_by(pos: u64, delta: i64) -> Option<u64> {
+  if delta >= 0 {
+    pos.checked_add(delta as u64)
+  } else {
+    pos.checked_sub(delta.unsigned_abs())
+  }
+}
+
+/// Path of a numbered segment file, from the path of the first. Segments 1 to 99 are E01 to E99,
+/// then EAA to EZZ, FAA and so on, keeping the case of the first segment's extension.
+fn segment_path(first: &Path, number: usize) -> Result<PathBuf, SgidiskLibReadError> {
+  if number == 1 {
+    return Ok(first.to_path_buf());
+  }
+  let first_char = match first.extension().and_then(|e| e.to_str()).and_then(|e| e.chars().next()) {
+    Some(c) if c.is_ascii_alphabetic() => c,
+    _ => return Err(SgidiskLibReadError::Value(format!("Unable to name EWF segment files after '{}'", first.to_string_lossy())))
+  };
+
+  let extension = if number <= 99 {
+    format!("{}{:02}", first_char, number)
+  } else {
+    let n = number - 100;
+    let base = if first_char.is_ascii_uppercase() { b'A' } else { b'a' };
+    let letters = [
+      first_char as u8 + (n / (26 * 26)) as u8,
+      base + ((n / 26) % 26) as u8,
+      base + (n % 26) as u8,
+    ];
+    String::from_utf8_lossy(&letters).to_string()
+  };
+  Ok(first.with_extension(extension))
+}
+
+/// Section type, without its NUL padding
+fn section_type(b: &[u8]) -> String {
+  let len = b.iter().position(|b| *b == 0).unwrap_or(b.len());
+  String::from_utf8_lossy(&b[..len]).to_string()
+}
+
+fn le_u32(b: &[u8]) -> u32 {
+  u32::from_le_bytes([b[0], b[1], b[2], b[3]])
+}
+
+fn le_u64(b: &[u8]) -> u64 {
+  u64::from_le_bytes([b[0], b[1], b[2], b[3], b[4], b[5], b[6], b[7]])
+}
+
+#[cfg(test)]
+mod tests {
+  use std::path::Path;
+
+  use super::{offset_by, segment_path};
+
+  #[test]
+  fn segment_names() {
+    let first = Path::new("disk.E01");
+    assert_eq!(segment_path(first, 1).unwrap(), Path::new("disk.E01"));
+    assert_eq!(segment_path(first, 99).unwrap(), Path::new("disk.E99"));
+    assert_eq!(segment_path(first, 100).unwrap(), Path::new("disk.EAA"));
+    assert_eq!(segment_path(first, 127).unwrap(), Path::new("disk.EBB"));
+    assert_eq!(segment_path(Path::new("disk.e01"), 100).unwrap(), Path::new("disk.eaa"));
+    assert!(segment_path(Path::new("disk"), 2).is_err());
+  }
+
+  #[test]
+  fn offsets() {
+    assert_eq!(offset_by(10, -3), Some(7));
+    assert_eq!(offset_by(3, -10), None);
+    assert_eq!(offset_by(u64::MAX, 1), None);
+    assert_eq!(offset_by(u64::MAX, i64::MIN), Some(u64::MAX - (1 << 63)));
+  }
+}
@@ -0,0 +1,181 @@
+//! Read-only FUSE filesystem over an EFS filesystem, enabled with the `fuse` feature.
+//!
+//! ```ignore
+//! let efs = Efs::read(&mut file, sector_sz, partition_start)?;
+//! let fs = EfsFuse::new(file, efs);
+//! fuser::mount2(fs, mountpoint, &[fuser::MountOption::RO, fuser::MountOption::FSName("efs".into())])?;
+//! ```
+
+use std::cmp::min;
+use std::ffi::OsStr;
+use std::io::{Read, Seek};
+use std::time::{Duration, SystemTime};
+
+use fuser::{FileAttr, FileType, Filesystem, FUSE_ROOT_ID, ReplyAttr, ReplyData, ReplyDirectory, ReplyEntry, Request};
+
+use crate::SgidiskLibReadError;
+use crate::efs::{Efs, EFS_BLOCK_SZ, Inode, InodeType};
+use crate::efs::dir::Directory;
+
+/// How long the kernel may cache attributes and entries; the filesystem is read-only
+const TTL: Duration = Duration::from_secs(60);
+
+/// FUSE filesystem serving an EFS filesystem read from `reader`
+pub struct EfsFuse<R>
+  where R: Read + Seek {
+  reader: R,
+  efs: Efs,
+}
+
+impl<R> EfsFuse<R>
+  where R: Read + Seek {
+  pub fn new(reader: R, efs: Efs) -> Self {
+    EfsFuse { reader, efs }
+  }
+
+  /// Read an inode by FUSE inode number
+  fn inode(&mut self, ino: u64) -> Result<Inode, SgidiskLibReadError> {
+    self.efs.read_inode(&mut self.reader, efs_ino(ino))
+  }
+
+  /// Read part of the contents of a file, starting at a byte offset
+  fn read_range(&mut self, inode: &Inode, offset: u64, len: u64) -> Result<Vec<u8>, SgidiskLibReadError> {
+    let mut data = Vec::with_capacity(min(len, inode.size.saturating_sub(offset)) as usize);
+    self.efs.read_range(&mut self.reader, inode, offset, len, &mut data)?;
+    Ok(data)
+  }
+}
+
+/// EFS inode number of a FUSE inode number. FUSE's root is 1 but EFS's is 2, so they swap.
+fn efs_ino(ino: u64) -> u64 {
+  match ino {
+    FUSE_ROOT_ID => Directory::ROOT_DIRECTORY_INODE,
+    Directory::ROOT_DIRECTORY_INODE => FUSE_ROOT_ID,
+    ino => ino
+  }
+}
+
+/// FUSE inode number of an EFS inode number
+fn fuse_ino(ino: u64) -> u64 {
+  efs_ino(ino)
+}
+
+/// errno for a library error
+fn errno(e: &SgidiskLibReadError) -> i32 {
+  match e {
+    SgidiskLibReadError::NotFound(_) => libc::ENOENT,
+    _ => libc::EIO
+  }
+}
+
+/// FUSE file type of an inode type
+fn file_type(inode_type: InodeType) -> FileType {
+  match inode_type {
+    InodeType::Fifo => FileType::NamedPipe,
+    InodeType::CharacterSpecial | InodeType::CharacterSpecialLink => FileType::CharDevice,
+    InodeType::Directory => FileType::Directory,
+    InodeType::BlockSpecial | InodeType::BlockSpecialLink => FileType::BlockDevice,
+    InodeType::RegularFile => FileType::RegularFile,
+    InodeType::SymbolicLink => FileType::Symlink,
+    InodeType::Socket => FileType::Socket
+  }
+}
+
+/// FUSE attributes of an inode
+fn attr(ino: u64, inode: &Inode) -> FileAttr {
+  // Encode device numbers as Linux does
+  let rdev = match inode.device {
+    Some(d) => ((d.major & 0xfff) << 8) | (d.minor & 0xff) | ((d.minor & !0xff) << 12),
+    None => 0
+  };
+  let ctime = SystemTime::from(inode.ctime);
+  FileAttr {
+    ino,
+    size: inode.size,
+    blocks: (inode.size + 511) / 512,
+    atime: SystemTime::from(inode.atime),
+    mtime: SystemTime::from(inode.mtime),
+    ctime,
+    crtime: ctime,
+    kind: file_type(inode.inode_type),
+    perm: inode.unix_mode & 0o7777,
+    nlink: inode.nlink.max(1) as u32,
+    uid: inode.owner_uid as u32,
+    gid: inode.owner_gid as u32,
+    rdev,
+    blksize: EFS_BLOCK_SZ as u32,
+    flags: 0,
+  }
+}
+
+impl<R> Filesystem for EfsFuse<R>
+  where R: Read + Seek {
+  fn lookup(&mut self, _req: &Request<'_>, parent: u64, name: &OsStr, reply: ReplyEntry) {
+    let name = match name.to_str() {
+      Some(name) => name,
+      None => return reply.error(libc::ENOENT)
+    };
+    match Directory::read_dir(&mut self.reader, &self.efs, efs_ino(parent)) {
+      Ok(dir) => match dir.entries.get(name) {
+        Some((ino, inode, )) => reply.entry(&TTL, &attr(fuse_ino(*ino), inode), 0),
+        None => reply.error(libc::ENOENT)
+      },
+      Err(e) => reply.error(errno(&e))
+    }
+  }
+
+  fn getattr(&mut self, _req: &Request<'_>, ino: u64, reply: ReplyAttr) {
+    match self.inode(ino) {
+      Ok(inode) => reply.attr(&TTL, &attr(ino, &inode)),
+      Err(e) => reply.error(errno(&e))
+    }
+  }
+
+  fn readlink(&mut self, _req: &Request<'_>, ino: u64, reply: ReplyData) {
+    let inode = match self.inode(ino) {
+      Ok(inode) => inode,
+      Err(e) => return reply.error(errno(&e))
+    };
+    if inode.inode_type != InodeType::SymbolicLink {
+      return reply.error(libc::EINVAL);
+    }
+    match self.efs.read_link(&mut self.reader, &inode) {
+      Ok(target) => reply.data(target.as_bytes()),
+      Err(e) => reply.error(errno(&e))
+    }
+  }
+
+  fn read(&mut self, _req: &Request<'_>, ino: u64, _fh: u64, offset: i64, size: u32, _flags: i32, _lock_owner: Option<u64>, reply: ReplyData) {
+    let inode = match self.inode(ino) {
+      Ok(inode) => inode,
+      Err(e) => return reply.error(errno(&e))
+    };
+    if inode.inode_type != InodeType::RegularFile {
+      return reply.error(libc::EISDIR);
+    }
+    match self.read_range(&inode, offset.max(0) as u64, size as u64) {
+      Ok(data) => reply.data(&data),
+      Err(e) => reply.error(errno(&e))
+    }
+  }
+
+  fn readdir(&mut self, _req: &Request<'_>, ino: u64, _fh: u64, offset: i64, mut reply: ReplyDirectory) {
+    match self.inode(ino) {
+      Ok(inode) if inode.inode_type != InodeType::Directory => return reply.error(libc::ENOTDIR),
+      Ok(_) => {}
+      Err(e) => return reply.error(errno(&e))
+    }
+    let dir = match Directory::read_dir(&mut self.reader, &self.efs, efs_ino(ino)) {
+      Ok(dir) => dir,
+      Err(e) => return reply.error(errno(&e))
+    };
+
+    // Offset is the number of entries already returned
+    for (n, (name, (entry_ino, entry_inode, ), ), ) in dir.entries.iter().enumerate().skip(offset.max(0) as usize) {
+      if reply.add(fuse_ino(*entry_ino), (n + 1) as i64, file_type(entry_inode.inode_type), name) {
+        break;
+      }
+    }
+    reply.ok();
+  }
+}
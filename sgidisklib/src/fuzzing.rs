@@ -0,0 +1,51 @@
+//! Entry points for fuzz targets (see `fuzz/`), enabled with the `fuzzing` feature. Each parses
+//! untrusted input the way reading an image would, and must never panic.
+
+use std::io::Cursor;
+#[cfg(feature = "ewf")]
+use std::io::{self, Read};
+
+use arbitrary::{Arbitrary, Unstructured};
+
+use crate::efs::{Efs, Inode};
+use crate::efs::raw_dir::DirectoryBlock;
+use crate::efs::raw_inode::EfsInode;
+use crate::efs::raw_sb::EfsSuperblock;
+use crate::volhdr::SgidiskVolume;
+use crate::volhdr::raw::VolumeHeader;
+
+/// Read a Volume Header from bytes and convert it
+pub fn volume_header(data: &[u8]) {
+  if let Ok(raw) = VolumeHeader::read(&mut Cursor::new(data)) {
+    let _ = SgidiskVolume::try_from(&raw);
+  }
+}
+
+/// Read an EFS superblock from bytes (starting with the unused block 0) and convert it
+pub fn superblock(data: &[u8]) {
+  if let Ok(raw) = EfsSuperblock::read(&mut Cursor::new(data)) {
+    let _ = Efs::try_from((&raw, 512, ));
+  }
+}
+
+/// Read an EFS inode from bytes and convert it
+pub fn inode(data: &[u8]) {
+  if let Ok(raw) = EfsInode::read(&mut Cursor::new(data)) {
+    let _ = Inode::try_from(&raw);
+  }
+}
+
+/// Get the entries of an arbitrary directory block
+pub fn dir_entries(data: &[u8]) {
+  if let Ok(block) = DirectoryBlock::arbitrary(&mut Unstructured::new(data)) {
+    let _ = block.dir_entries();
+  }
+}
+
+/// Read an EWF container from bytes as its only segment, and read its first chunks
+#[cfg(feature = "ewf")]
+pub fn ewf(data: &[u8]) {
+  if let Ok(reader) = crate::ewf::EwfReader::from_segments(vec![Cursor::new(data)]) {
+    let _ = io::copy(&mut reader.take(1024 * 1024), &mut io::sink());
+  }
+}
@@ -0,0 +1,364 @@
+//! Fixity hashing with any selection of algorithms in a single pass, including hashes of ranges
+//! of a stream (e.g. the volume files and partitions of a disk image) while hashing the whole
+
+use std::io;
+use std::io::{Read, Write};
+use std::ops::Range;
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::progress::{CancellationToken, Progress};
+
+/// Size of reads when hashing a reader
+const HASH_BUF_SZ: usize = 1024 * 16;
+
+/// Hash algorithm supported by MultiHash
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "lowercase"))]
+pub enum HashAlgorithm {
+  Sha256,
+  Blake3,
+}
+
+/// Hashes with any selection of BLAKE3, SHA-256
+pub struct MultiHash {
+  blake3: Option<blake3::Hasher>,
+  sha256: Option<Sha256>,
+}
+
+/// Results from MultiHash hashes, as upper case hex; algorithms which were not selected are None
+#[derive(Debug, Clone, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct MultiHashResult {
+  #[cfg_attr(feature = "serde", serde(skip_serializing_if = "Option::is_none"))]
+  pub blake3: Option<String>,
+  #[cfg_attr(feature = "serde", serde(skip_serializing_if = "Option::is_none"))]
+  pub sha256: Option<String>,
+}
+
+/// Hash of a range of bytes of a stream
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct RangeHash {
+  /// Range which was to be hashed
+  pub range: Range<u64>,
+  /// Number of bytes of the range which were in the stream, and so hashed
+  pub hashed: u64,
+  pub hash: MultiHashResult,
+}
+
+impl HashAlgorithm {
+  /// All supported algorithms, in display order
+  pub const ALL: [HashAlgorithm; 2] = [HashAlgorithm::Sha256, HashAlgorithm::Blake3];
+
+  /// Parse algorithm from its name
+  pub fn from_name(name: &str) -> Option<Self> {
+    Self::ALL.iter()
+      .find(|a| a.name() == name)
+      .copied()
+  }
+
+  /// Short lower case name, e.g. `sha256`
+  pub fn name(&self) -> &'static str {
+    match self {
+      HashAlgorithm::Sha256 => "sha256",
+      HashAlgorithm::Blake3 => "blake3",
+    }
+  }
+
+  /// Name for display, e.g. `SHA-256`
+  pub fn display_name(&self) -> &'static str {
+    match self {
+      HashAlgorithm::Sha256 => "SHA-256",
+      HashAlgorithm::Blake3 => "BLAKE3",
+    }
+  }
+}
+
+impl MultiHash {
+  /// Create a new MultiHash hasher for the selected algorithms
+  pub fn new(algorithms: &[HashAlgorithm]) -> Self {
+    let blake3 = if algorithms.contains(&HashAlgorithm::Blake3) {
+      Some(blake3::Hasher::new())
+    } else {
+      None
+    };
+    let sha256 = if algorithms.contains(&HashAlgorithm::Sha256) {
+      Some(Sha256::new())
+    } else {
+      None
+    };
+
+    MultiHash {
+      blake3,
+      sha256,
+    }
+  }
+
+  /// Update hash with data
+  pub fn update(&mut self, b: &[u8]) {
+    if let Some(h) = self.blake3.as_mut() {
+      h.update(b);
+    }
+    if let Some(h) = self.sha256.as_mut() {
+      h.update(b);
+    }
+  }
+
+  /// Hash everything from a reader until EOF, returning the number of bytes read and the hashes
+  pub fn hash_reader<R: ?Sized>(reader: &mut R, algorithms: &[HashAlgorithm]) -> Result<(u64, MultiHashResult, ), io::Error>
+    where R: Read {
+    let mut hash = MultiHash::new(algorithms);
+    let mut buf = [0u8; HASH_BUF_SZ];
+    let mut len = 0u64;
+    loop {
+      match reader.read(&mut buf)? {
+        0 => break,
+        n => {
+          hash.update(&buf[0..n]);
+          len += n as u64;
+        }
+      }
+    }
+    Ok((len, hash.finalize(), ))
+  }
+
+  /// Finalize hash and populate results
+  pub fn finalize(self) -> MultiHashResult {
+    MultiHashResult {
+      blake3: self.blake3.map(|h| Self::bytes_to_hex(h.finalize().as_bytes())),
+      sha256: self.sha256.map(|h| Self::bytes_to_hex(&h.finalize()[..])),
+    }
+  }
+
+  /// Format byte slice as hex, perhaps somewhat inefficiently
+  fn bytes_to_hex(b: &[u8]) -> String {
+    b.iter()
+      .map(|b| format!("{:02X}", b))
+      .collect::<Vec<String>>()
+      .concat()
+  }
+}
+
+/// Hashes everything written, e.g. file contents read from an image
+impl Write for MultiHash {
+  fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+    self.update(buf);
+    Ok(buf.len())
+  }
+
+  fn flush(&mut self) -> io::Result<()> {
+    Ok(())
+  }
+}
+
+/// Writer which hashes everything written through it, e.g. to verify a copy later
+pub struct HashingWriter<W> {
+  inner: W,
+  hash: MultiHash,
+}
+
+impl<W: Write> HashingWriter<W> {
+  pub fn new(inner: W, algorithms: &[HashAlgorithm]) -> Self {
+    HashingWriter {
+      inner,
+      hash: MultiHash::new(algorithms),
+    }
+  }
+
+  /// Inner writer, and the hashes of everything written
+  pub fn into_parts(self) -> (W, MultiHashResult, ) {
+    (self.inner, self.hash.finalize(), )
+  }
+}
+
+impl<W: Write> Write for HashingWriter<W> {
+  fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+    let n = self.inner.write(buf)?;
+    self.hash.update(&buf[..n]);
+    Ok(n)
+  }
+
+  fn flush(&mut self) -> io::Result<()> {
+    self.inner.flush()
+  }
+}
+
+impl MultiHashResult {
+  /// List of (algorithm, hash) for the algorithms which were computed, in display order
+  pub fn entries(self) -> Vec<(HashAlgorithm, String, )> {
+    let mut entries = Vec::with_capacity(HashAlgorithm::ALL.len());
+    if let Some(h) = self.sha256 {
+      entries.push((HashAlgorithm::Sha256, h, ));
+    }
+    if let Some(h) = self.blake3 {
+      entries.push((HashAlgorithm::Blake3, h, ));
+    }
+    entries
+  }
+}
+
+impl RangeHash {
+  /// Number of bytes of the range which were past the end of the stream, if any
+  pub fn short_by(&self) -> Option<u64> {
+    let sz = self.range.end - self.range.start;
+    if self.hashed < sz {
+      Some(sz - self.hashed)
+    } else {
+      None
+    }
+  }
+}
+
+/// Hash a whole stream from a reader positioned at its start, and ranges of bytes within it, in
+/// a single sequential pass. Returns the hash of the whole stream, and the hashes of the ranges
+/// in the order given. Ranges may overlap, and there may be many of them (e.g. fixed-size chunks
+/// of each partition) as only those overlapping each read are updated. `progress` is called with the number of bytes hashed after each read. If
+/// cancelled, the error wraps `SgidiskLibReadError::Cancelled`.
+pub fn hash_ranges<R: ?Sized, P>(reader: &mut R, ranges: &[Range<u64>], algorithms: &[HashAlgorithm], cancel: &CancellationToken, progress: P) -> Result<(MultiHashResult, Vec<RangeHash>, ), io::Error>
+  where R: Read, P: FnMut(Progress) {
+  let (image_hash, range_hashes, _, ) = hash_ranges_and_areas(reader, ranges, &[], algorithms, cancel, progress)?;
+  Ok((image_hash, range_hashes, ))
+}
+
+/// Hash a stream and ranges within it like `hash_ranges`, and also hash only the bytes within a
+/// set of areas (e.g. `SgidiskVolume::defined_areas`), as one stream in order. Bytes outside the
+/// areas, such as padding, don't change the areas hash. Overlapping areas are merged.
+pub fn hash_ranges_and_areas<R: ?Sized, P>(reader: &mut R, ranges: &[Range<u64>], areas: &[Range<u64>], algorithms: &[HashAlgorithm], cancel: &CancellationToken, mut progress: P) -> Result<(MultiHashResult, Vec<RangeHash>, MultiHashResult, ), io::Error>
+  where R: Read, P: FnMut(Progress) {
+  let areas = merge_ranges(areas);
+  let mut areas_hash = MultiHash::new(algorithms);
+  let mut image_hash = MultiHash::new(algorithms);
+  let mut range_hashes: Vec<(u64, MultiHash, )> = ranges.iter()
+    .map(|_| (0, MultiHash::new(algorithms), ))
+    .collect();
+
+  // Ranges by where they start, and those which started but haven't ended yet
+  let mut by_start: Vec<usize> = (0..ranges.len()).collect();
+  by_start.sort_by_key(|i| ranges[*i].start);
+  let mut next_start = 0;
+  let mut active: Vec<usize> = Vec::new();
+
+  let mut buf = [0u8; HASH_BUF_SZ];
+  let mut pos = 0u64;
+  loop {
+    cancel.check_io()?;
+    let n = reader.read(&mut buf)?;
+    if n == 0 {
+      break;
+    }
+    let end = pos + n as u64;
+    image_hash.update(&buf[..n]);
+
+    // Update each range overlapping this window of the stream
+    while next_start < by_start.len() && ranges[by_start[next_start]].start < end {
+      active.push(by_start[next_start]);
+      next_start += 1;
+    }
+    for i in &active {
+      if let Some(overlap) = window_overlap(&ranges[*i], pos, end) {
+        let (hashed, hash, ) = &mut range_hashes[*i];
+        *hashed += overlap.len() as u64;
+        hash.update(&buf[overlap]);
+      }
+    }
+    active.retain(|i| ranges[*i].end > end);
+    // Areas are in order and don't overlap, so are hashed in stream order
+    for area in &areas {
+      if let Some(overlap) = window_overlap(area, pos, end) {
+        areas_hash.update(&buf[overlap]);
+      }
+    }
+    pos = end;
+    progress(Progress::bytes(pos, None));
+  }
+
+  let range_hashes = ranges.iter().zip(range_hashes)
+    .map(|(range, (hashed, hash, ), )| RangeHash {
+      range: range.clone(),
+      hashed,
+      hash: hash.finalize(),
+    })
+    .collect();
+  Ok((image_hash.finalize(), range_hashes, areas_hash.finalize(), ))
+}
+
+/// Sort ranges and merge those which overlap or touch
+pub(crate) fn merge_ranges(ranges: &[Range<u64>]) -> Vec<Range<u64>> {
+  let mut sorted: Vec<Range<u64>> = ranges.iter().filter(|r| !r.is_empty()).cloned().collect();
+  sorted.sort_by_key(|r| r.start);
+  let mut merged: Vec<Range<u64>> = Vec::with_capacity(sorted.len());
+  for range in sorted {
+    match merged.last_mut() {
+      Some(last) if range.start <= last.end => last.end = last.end.max(range.end),
+      _ => merged.push(range)
+    }
+  }
+  merged
+}
+
+/// Overlap of a range with the window of the stream from start to end, as a range of offsets into
+/// the window
+fn window_overlap(range: &Range<u64>, start: u64, end: u64) -> Option<Range<usize>> {
+  if range.end <= start || range.start >= end {
+    return None;
+  }
+  let overlap_start = range.start.saturating_sub(start) as usize;
+  let overlap_end = (range.end.min(end) - start) as usize;
+  Some(overlap_start..overlap_end)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn ranges_match_separate_hashes() {
+    let data: Vec<u8> = (0..100_000u32).map(|i| (i % 251) as u8).collect();
+    let ranges = [0..512, 1000..40_000, 99_000..101_000];
+    let (whole, hashes, ) = hash_ranges(&mut &data[..], &ranges, &HashAlgorithm::ALL, &CancellationToken::new(), |_| {}).unwrap();
+
+    let (_, expected, ) = MultiHash::hash_reader(&mut &data[..], &HashAlgorithm::ALL).unwrap();
+    assert_eq!(whole, expected);
+    for h in &hashes[..2] {
+      let range = h.range.start as usize..h.range.end as usize;
+      let (_, expected, ) = MultiHash::hash_reader(&mut &data[range], &HashAlgorithm::ALL).unwrap();
+      assert_eq!(h.hash, expected);
+      assert_eq!(h.short_by(), None);
+    }
+    assert_eq!(hashes[2].hashed, 1000);
+    assert_eq!(hashes[2].short_by(), Some(1000));
+  }
+
+  #[test]
+  fn chunks_and_overlapping_ranges() {
+    let data: Vec<u8> = (0..70_000u32).map(|i| (i % 241) as u8).collect();
+    let mut ranges: Vec<Range<u64>> = (0..70_000).step_by(5000).map(|start| start..start + 5000).collect();
+    ranges.insert(0, 2500..60_000);
+    let (_, hashes, ) = hash_ranges(&mut &data[..], &ranges, &[HashAlgorithm::Sha256], &CancellationToken::new(), |_| {}).unwrap();
+    for h in &hashes {
+      let range = h.range.start as usize..h.range.end as usize;
+      let (_, expected, ) = MultiHash::hash_reader(&mut &data[range], &[HashAlgorithm::Sha256]).unwrap();
+      assert_eq!(h.hash, expected);
+    }
+  }
+
+  #[test]
+  fn areas_ignore_bytes_outside() {
+    let mut data: Vec<u8> = (0..50_000u32).map(|i| (i % 251) as u8).collect();
+    let areas = [30_000..40_000, 0..100, 50..20_000];
+    let hash_areas = |data: &[u8]| hash_ranges_and_areas(&mut &data[..], &[], &areas, &HashAlgorithm::ALL, &CancellationToken::new(), |_| {}).unwrap().2;
+    let before = hash_areas(&data);
+
+    let mut expected = data[..20_000].to_vec();
+    expected.extend_from_slice(&data[30_000..40_000]);
+    assert_eq!(before, MultiHash::hash_reader(&mut &expected[..], &HashAlgorithm::ALL).unwrap().1);
+
+    // Padding between and after the areas doesn't change the hash
+    data[25_000] ^= 0xff;
+    data.extend_from_slice(&[0u8; 4096]);
+    assert_eq!(hash_areas(&data), before);
+  }
+}
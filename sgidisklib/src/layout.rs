@@ -0,0 +1,194 @@
+//! Map of what each byte range of a disk image holds: the Volume Header and its files, the
+//! partitions, and within EFS partitions the superblock, bitmap, inode tables and data blocks in
+//! use and free. Ranges overlap, as partitions contain filesystems and usually each other; gaps
+//! outside every partition, and overlaps between partitions which shouldn't share space, are
+//! regions of their own.
+
+use std::ops::Range;
+
+use crate::efs::{Efs, EFS_BLOCK_SZ};
+use crate::efs::bitmap::Bitmap;
+use crate::efs::raw_inode::EfsInode;
+use crate::volhdr::{PartitionType, SgidiskVolume};
+
+/// What a region holds
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Ord, PartialOrd)]
+pub enum RegionKind {
+  VolumeHeader,
+  VolumeFile,
+  Partition,
+  EfsSuperblock,
+  EfsBitmap,
+  EfsInodes,
+  /// Data blocks in use, by the bitmap
+  EfsAllocated,
+  /// Data blocks free, by the bitmap
+  EfsFree,
+  /// Bytes of the image in no partition other than the entire volume
+  Unpartitioned,
+  /// Bytes in two partitions which shouldn't share space
+  Overlap,
+}
+
+/// Byte range of the disk image and what it holds
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Region {
+  pub kind: RegionKind,
+  /// Description, e.g. "partition 7 (EFS)"
+  pub label: String,
+  /// Partition the region is, or is part of the filesystem of
+  pub partition: Option<usize>,
+  pub range: Range<u64>,
+}
+
+impl RegionKind {
+  /// Short name, e.g. "efs-free"
+  pub fn name(&self) -> &'static str {
+    match self {
+      RegionKind::VolumeHeader => "volume-header",
+      RegionKind::VolumeFile => "volume-file",
+      RegionKind::Partition => "partition",
+      RegionKind::EfsSuperblock => "efs-superblock",
+      RegionKind::EfsBitmap => "efs-bitmap",
+      RegionKind::EfsInodes => "efs-inodes",
+      RegionKind::EfsAllocated => "efs-allocated",
+      RegionKind::EfsFree => "efs-free",
+      RegionKind::Unpartitioned => "unpartitioned",
+      RegionKind::Overlap => "overlap"
+    }
+  }
+}
+
+impl SgidiskVolume {
+  /// Regions of the Volume Header, its files and the partitions, with the gaps between partitions
+  /// and the overlaps of partitions other than the entire volume and volume header partitions
+  pub fn regions(&self, image_len: u64) -> Vec<Region> {
+    let mut regions = vec![Region {
+      kind: RegionKind::VolumeHeader,
+      label: "volume header".to_string(),
+      partition: None,
+      range: 0..SgidiskVolume::SIZE as u64,
+    }];
+    for f in self.files.iter().filter(|f| f.in_use()) {
+      regions.push(Region {
+        kind: RegionKind::VolumeFile,
+        label: format!("volume file {}", f.file_name.as_deref().unwrap_or_default()),
+        partition: None,
+        range: f.byte_range(),
+      });
+    }
+    for (id, p, ) in self.partitions.iter().enumerate().filter(|(_, p, )| p.in_use()) {
+      regions.push(Region {
+        kind: RegionKind::Partition,
+        label: format!("partition {} ({})", id, p.partition_type),
+        partition: Some(id),
+        range: p.byte_range(),
+      });
+    }
+
+    // The volume header partition holds the volume files, and the entire volume holds everything
+    let partitioned: Vec<(usize, Range<u64>, )> = self.partitions.iter().enumerate()
+      .filter(|(_, p, )| p.in_use() && p.partition_type != PartitionType::EntireVolume)
+      .map(|(id, p, )| (id, p.byte_range(), ))
+      .collect();
+    let covered = crate::hash::merge_ranges(&partitioned.iter().map(|(_, r, )| r.clone()).collect::<Vec<_>>());
+    let mut next = 0;
+    for range in covered.iter().chain(std::iter::once(&(image_len..image_len))) {
+      if range.start > next {
+        regions.push(Region {
+          kind: RegionKind::Unpartitioned,
+          label: "unpartitioned".to_string(),
+          partition: None,
+          range: next..range.start,
+        });
+      }
+      next = next.max(range.end);
+    }
+
+    let exclusive: Vec<&(usize, Range<u64>, )> = partitioned.iter()
+      .filter(|(id, _, )| self.partitions[*id].partition_type != PartitionType::VolumeHeader)
+      .collect();
+    for (i, (a_id, a, )) in exclusive.iter().enumerate() {
+      for (b_id, b, ) in &exclusive[i + 1..] {
+        let overlap = a.start.max(b.start)..a.end.min(b.end);
+        if !overlap.is_empty() {
+          regions.push(Region {
+            kind: RegionKind::Overlap,
+            label: format!("partitions {} and {} overlap", a_id, b_id),
+            partition: None,
+            range: overlap,
+          });
+        }
+      }
+    }
+    regions
+  }
+}
+
+impl Efs {
+  /// Regions of the superblock, bitmap and inode tables, and the data blocks in use and free by
+  /// the bitmap if there is one, of the filesystem in a numbered partition
+  pub fn regions(&self, bitmap: Option<&Bitmap>, partition_id: usize) -> Vec<Region> {
+    let block_sz = EFS_BLOCK_SZ as u64;
+    let prefix = format!("partition {}", partition_id);
+    let partition = Some(partition_id);
+    let mut regions = vec![
+      Region {
+        kind: RegionKind::EfsSuperblock,
+        label: format!("{} superblock", prefix),
+        partition,
+        range: self.block_absolute(1)..self.block_absolute(2),
+      },
+      Region {
+        kind: RegionKind::EfsBitmap,
+        label: format!("{} bitmap", prefix),
+        partition,
+        range: self.block_absolute(self.bitmap_block)..self.block_absolute(self.bitmap_block) + self.bitmap_sz,
+      },
+    ];
+
+    let inode_blocks = self.cg_inodes * EfsInode::SIZE as u64 / block_sz;
+    for cg in 0..self.cg_count {
+      let cg_start = self.cg_start + cg * self.cg_size;
+      regions.push(Region {
+        kind: RegionKind::EfsInodes,
+        label: format!("{} inodes of cylinder group {}", prefix, cg),
+        partition,
+        range: self.block_absolute(cg_start)..self.block_absolute(cg_start + inode_blocks),
+      });
+    }
+
+    // Runs of data blocks in use or free, each run ending where the other kind or a cylinder
+    // group's inodes start
+    if let Some(bitmap) = bitmap {
+      let mut run: Option<(bool, Range<u64>, )> = None;
+      for block in self.data_block_ranges().flatten() {
+        let free = bitmap.is_free(block);
+        if let Some((run_free, range, )) = &mut run {
+          if *run_free == free && range.end == block {
+            range.end += 1;
+            continue;
+          }
+        }
+        if let Some(ended) = run.replace((free, block..block + 1, )) {
+          regions.push(self.data_region(ended, partition_id));
+        }
+      }
+      if let Some(ended) = run {
+        regions.push(self.data_region(ended, partition_id));
+      }
+    }
+    regions
+  }
+
+  /// Region of a run of data blocks, free or not
+  fn data_region(&self, (free, blocks, ): (bool, Range<u64>, ), partition_id: usize) -> Region {
+    let (kind, what, ) = if free { (RegionKind::EfsFree, "free", ) } else { (RegionKind::EfsAllocated, "allocated", ) };
+    Region {
+      kind,
+      label: format!("partition {} {} blocks {}-{}", partition_id, what, blocks.start, blocks.end - 1),
+      partition: Some(partition_id),
+      range: self.block_absolute(blocks.start)..self.block_absolute(blocks.end),
+    }
+  }
+}
@@ -2,6 +2,36 @@ use thiserror::Error;
 
 pub mod volhdr;
 pub mod efs;
+pub mod entropy;
+pub mod copy;
+pub mod cache;
+pub mod content;
+#[cfg(feature = "diskcache")]
+pub mod diskcache;
+pub mod hash;
+pub mod layout;
+pub mod limits;
+pub mod metrics;
+pub mod pc;
+pub mod progress;
+pub mod rescue;
+pub mod source;
+pub mod strings;
+#[cfg(feature = "ewf")]
+pub mod ewf;
+#[cfg(feature = "fuse")]
+pub mod fuse;
+#[cfg(feature = "fuzzing")]
+#[doc(hidden)]
+pub mod fuzzing;
+#[cfg(feature = "testgen")]
+pub mod testgen;
+#[cfg(all(feature = "io-uring", target_os = "linux"))]
+pub mod uring;
+#[cfg(feature = "mmap")]
+pub mod mmap;
+#[cfg(test)]
+mod roundtrip;
 
 /// SGI Disk Library related errors
 #[derive(Debug, Error)]
@@ -14,6 +44,22 @@ pub enum SgidiskLibReadError {
   Value(String),
   #[error("File system points to something out of listed bounds")]
   Bounds(String),
+  #[error("No such file or directory")]
+  NotFound(String),
+  #[error("Bad magic number at offset {offset}: expected {expected:#010X}, found {found:#010X}")]
+  BadMagic { expected: u32, found: u32, offset: u64 },
+  #[error("Read at offset {offset} for {len} bytes is outside the filesystem")]
+  ReadOutOfBounds { offset: u64, len: u64 },
+  #[error("Inode {inode} is outside the filesystem")]
+  InodeOutOfBounds { inode: u64 },
+  #[error("Extent {extent} of inode {inode} at offset {offset} is outside the filesystem or image")]
+  ExtentOutOfBounds { inode: u64, extent: usize, offset: u64 },
+  #[error("Operation was cancelled")]
+  Cancelled,
+  #[error("Limit {limit} of {max} was exceeded")]
+  LimitExceeded { limit: &'static str, max: u64 },
+  #[error("Symbolic link loop at inode {inode} while resolving '{path}'")]
+  SymlinkLoop { path: String, inode: u64 },
 }
 
 /// Convert a C string to Rust String
@@ -0,0 +1,57 @@
+//! Caps on the work done parsing and walking a filesystem, so that a crafted or corrupted image
+//! can't make reading it use unbounded memory or time
+
+use crate::SgidiskLibReadError;
+
+/// Caps enforced by the parsers and walkers of a filesystem. Exceeding one is an
+/// `SgidiskLibReadError::LimitExceeded` error.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct Limits {
+  /// Maximum number of entries in one directory
+  pub max_dir_entries: usize,
+  /// Maximum depth below the root directory that a walk descends to
+  pub max_depth: usize,
+  /// Maximum number of inodes a walk visits
+  pub max_inodes: u64,
+  /// Maximum number of symbolic links followed resolving one path
+  pub max_symlinks: usize,
+  /// Maximum number of bytes allocated parsing one structure, e.g. a directory or the indirect
+  /// extents of an inode
+  pub max_alloc: u64,
+}
+
+impl Default for Limits {
+  /// Limits well beyond anything IRIX could create
+  fn default() -> Self {
+    Limits {
+      max_dir_entries: 1 << 20,
+      // Paths are at most 1024 bytes long, so can't have more components than this
+      max_depth: 512,
+      max_inodes: 1 << 26,
+      // IRIX's MAXSYMLINKS
+      max_symlinks: 30,
+      max_alloc: 256 * 1024 * 1024,
+    }
+  }
+}
+
+impl Limits {
+  /// No limits
+  pub fn unlimited() -> Self {
+    Limits {
+      max_dir_entries: usize::MAX,
+      max_depth: usize::MAX,
+      max_inodes: u64::MAX,
+      max_symlinks: usize::MAX,
+      max_alloc: u64::MAX,
+    }
+  }
+
+  /// Check that a walk may read a directory this far below the one it started from
+  pub fn check_depth(&self, depth: usize) -> Result<(), SgidiskLibReadError> {
+    if depth > self.max_depth {
+      return Err(SgidiskLibReadError::LimitExceeded { limit: "max_depth", max: self.max_depth as u64 });
+    }
+    Ok(())
+  }
+}
@@ -0,0 +1,94 @@
+//! Counting the I/O made on a disk image, to measure how much reading a traversal or extraction
+//! takes
+
+use std::io;
+use std::io::{Read, Seek, SeekFrom};
+use std::ops::Sub;
+
+/// Snapshot of the I/O counted by a CountingReader. Subtracting an earlier snapshot from a later
+/// one gives the I/O of the operation in between.
+#[derive(Debug, Default, Copy, Clone, Eq, PartialEq)]
+pub struct IoStats {
+  /// Number of seeks, including those which didn't move
+  pub seeks: u64,
+  /// Number of seeks which moved to a different offset
+  pub seeks_moved: u64,
+  /// Number of calls to read
+  pub reads: u64,
+  /// Number of bytes read
+  pub bytes_read: u64,
+}
+
+/// Reader wrapper which counts the seeks, reads and bytes read made through it
+pub struct CountingReader<R> {
+  inner: R,
+  /// Current offset
+  pos: u64,
+  stats: IoStats,
+}
+
+impl<R> CountingReader<R> {
+  /// Wrap a reader positioned at its start, counting from zero
+  pub fn new(inner: R) -> Self {
+    CountingReader {
+      inner,
+      pos: 0,
+      stats: IoStats::default(),
+    }
+  }
+
+  /// I/O counted so far
+  pub fn stats(&self) -> IoStats {
+    self.stats
+  }
+
+  /// Start counting from zero again
+  pub fn reset_stats(&mut self) {
+    self.stats = IoStats::default();
+  }
+
+  /// Get a reference to the wrapped reader
+  pub fn get_ref(&self) -> &R {
+    &self.inner
+  }
+
+  /// Unwrap the reader
+  pub fn into_inner(self) -> R {
+    self.inner
+  }
+}
+
+impl<R: Read> Read for CountingReader<R> {
+  fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+    let n = self.inner.read(buf)?;
+    self.stats.reads += 1;
+    self.stats.bytes_read += n as u64;
+    self.pos += n as u64;
+    Ok(n)
+  }
+}
+
+impl<R: Seek> Seek for CountingReader<R> {
+  fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+    let offset = self.inner.seek(pos)?;
+    self.stats.seeks += 1;
+    if offset != self.pos {
+      self.stats.seeks_moved += 1;
+    }
+    self.pos = offset;
+    Ok(offset)
+  }
+}
+
+impl Sub for IoStats {
+  type Output = IoStats;
+
+  fn sub(self, earlier: IoStats) -> IoStats {
+    IoStats {
+      seeks: self.seeks - earlier.seeks,
+      seeks_moved: self.seeks_moved - earlier.seeks_moved,
+      reads: self.reads - earlier.reads,
+      bytes_read: self.bytes_read - earlier.bytes_read,
+    }
+  }
+}
@@ -0,0 +1,25 @@
+//! Disk images read through a memory map, so that reading them goes through the page cache
+//! without a read system call per structure. Reads still copy from the mapped pages into the
+//! parsers' buffers, as with a file.
+
+use std::fs::File;
+use std::io;
+use std::io::Cursor;
+use std::path::Path;
+
+use memmap2::Mmap;
+
+/// Disk image mapped read-only into memory, readable with Read and Seek like a file
+pub type MappedImage = Cursor<Mmap>;
+
+/// Map a disk image read-only
+///
+/// # Safety
+/// The image must not be truncated or written to, by this process or any other, while it is
+/// mapped. Reading the map afterwards is undefined behaviour, and a truncated file makes the
+/// process crash with SIGBUS.
+pub unsafe fn map_image<P: AsRef<Path>>(path: P) -> io::Result<MappedImage> {
+  let file = File::open(path)?;
+  let map = Mmap::map(&file)?;
+  Ok(Cursor::new(map))
+}
@@ -0,0 +1,184 @@
+//! PC partition tables (MBR and GPT) found where a Volume Header should be. Disks moved to PCs,
+//! or images made of a whole USB or SD card holding an SGI disk image in one partition, start with
+//! one of these instead of the SGI magic. Finding a Volume Header at the start of one of their
+//! partitions gives the offset to read the SGI disk at.
+
+use std::io::{Read, Seek, SeekFrom};
+
+use crate::SgidiskLibReadError;
+use crate::volhdr::SgidiskVolume;
+
+/// Size of the sectors PC partition tables count in
+const PC_SECTOR_SZ: u64 = 512;
+/// Boot signature ending an MBR
+const MBR_SIGNATURE: [u8; 2] = [0x55, 0xaa];
+/// Offset of the four MBR partition entries
+const MBR_ENTRIES_OFFSET: usize = 446;
+/// MBR partition type of the protective partition in front of a GPT
+const GPT_PROTECTIVE_TYPE: u8 = 0xee;
+/// Signature of a GPT header
+const GPT_SIGNATURE: &[u8] = b"EFI PART";
+/// Most GPT partition entries read, as the header's count isn't trusted
+const MAX_GPT_ENTRIES: u32 = 256;
+/// Size of a GPT partition entry, of which the specification allows any multiple
+const GPT_ENTRY_SZ: usize = 128;
+/// Largest GPT partition entry read, as no writer uses more than 128 bytes
+const MAX_GPT_ENTRY_SZ: usize = 4096;
+
+/// Kind of PC partition table
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum PcScheme {
+  Mbr,
+  Gpt,
+}
+
+/// Partition of a PC partition table
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct PcPartition {
+  /// Number of the partition, from 1
+  pub number: usize,
+  /// MBR type as two hex digits, or GPT type GUID
+  pub type_id: String,
+  /// GPT partition name, if any
+  pub name: Option<String>,
+  pub start_byte: u64,
+  pub size_bytes: u64,
+  /// Whether the partition starts with a valid Volume Header
+  pub has_volume_header: bool,
+}
+
+/// PC partition table at the start of a disk image
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct PcPartitionTable {
+  pub scheme: PcScheme,
+  pub partitions: Vec<PcPartition>,
+}
+
+impl PcScheme {
+  /// Short name, e.g. "GPT"
+  pub fn name(&self) -> &'static str {
+    match self {
+      PcScheme::Mbr => "MBR",
+      PcScheme::Gpt => "GPT"
+    }
+  }
+}
+
+impl PcPartitionTable {
+  /// Synchronously read the MBR, and the GPT it protects if there is one, from the start of a
+  /// disk image. Returns None if the image doesn't start with an MBR.
+  pub fn read<R: ?Sized>(reader: &mut R) -> Result<Option<Self>, SgidiskLibReadError>
+    where R: Read + Seek {
+    let mut mbr = [0u8; PC_SECTOR_SZ as usize];
+    reader.seek(SeekFrom::Start(0))?;
+    reader.read_exact(&mut mbr)?;
+    if !is_mbr(&mbr) {
+      return Ok(None);
+    }
+
+    let entries: Vec<&[u8]> = mbr[MBR_ENTRIES_OFFSET..510].chunks(16).collect();
+    let mut table = if entries.iter().any(|e| e[4] == GPT_PROTECTIVE_TYPE) {
+      match Self::read_gpt(reader)? {
+        Some(gpt) => gpt,
+        None => return Ok(None)
+      }
+    } else {
+      let partitions = entries.iter().enumerate()
+        .filter(|(_, e, )| e[4] != 0)
+        .map(|(i, e, )| PcPartition {
+          number: i + 1,
+          type_id: format!("{:02x}", e[4]),
+          name: None,
+          start_byte: u32::from_le_bytes([e[8], e[9], e[10], e[11]]) as u64 * PC_SECTOR_SZ,
+          size_bytes: u32::from_le_bytes([e[12], e[13], e[14], e[15]]) as u64 * PC_SECTOR_SZ,
+          has_volume_header: false,
+        })
+        .collect();
+      PcPartitionTable { scheme: PcScheme::Mbr, partitions }
+    };
+
+    for p in &mut table.partitions {
+      p.has_volume_header = has_volume_header(reader, p.start_byte);
+    }
+    Ok(Some(table))
+  }
+
+  /// Offset of the first partition starting with a Volume Header
+  pub fn volume_header_offset(&self) -> Option<u64> {
+    self.partitions.iter().find(|p| p.has_volume_header).map(|p| p.start_byte)
+  }
+
+  /// Read the GPT header in sector 1 and its partition entries
+  fn read_gpt<R: ?Sized>(reader: &mut R) -> Result<Option<Self>, SgidiskLibReadError>
+    where R: Read + Seek {
+    let mut header = [0u8; PC_SECTOR_SZ as usize];
+    reader.seek(SeekFrom::Start(PC_SECTOR_SZ))?;
+    reader.read_exact(&mut header)?;
+    if !header.starts_with(GPT_SIGNATURE) {
+      return Ok(None);
+    }
+    let u32_at = |b: &[u8], o: usize| u32::from_le_bytes([b[o], b[o + 1], b[o + 2], b[o + 3]]);
+    let u64_at = |b: &[u8], o: usize| u32_at(b, o) as u64 | (u32_at(b, o + 4) as u64) << 32;
+    let entries_lba = u64_at(&header, 72);
+    let entry_count = u32_at(&header, 80).min(MAX_GPT_ENTRIES);
+    let entry_sz = u32_at(&header, 84) as usize;
+    if entry_sz < GPT_ENTRY_SZ || entry_sz > MAX_GPT_ENTRY_SZ || entry_sz % GPT_ENTRY_SZ != 0 {
+      return Err(SgidiskLibReadError::Value(format!("GPT partition entries of {} bytes aren't a multiple of {} up to {}", entry_sz, GPT_ENTRY_SZ, MAX_GPT_ENTRY_SZ)));
+    }
+    let entries_start = match entries_lba.checked_mul(PC_SECTOR_SZ) {
+      Some(start) => start,
+      None => return Err(SgidiskLibReadError::Bounds(format!("GPT partition entries start at LBA {}", entries_lba)))
+    };
+
+    let mut entries = vec![0u8; entry_count as usize * entry_sz];
+    reader.seek(SeekFrom::Start(entries_start))?;
+    reader.read_exact(&mut entries)?;
+    let partitions = entries.chunks(entry_sz).enumerate()
+      .filter(|(_, e, )| e[..16].iter().any(|b| *b != 0))
+      .map(|(i, e, )| {
+        let (first, last, ) = (u64_at(e, 32), u64_at(e, 40), );
+        let (start_byte, end_byte, ) = match (first.checked_mul(PC_SECTOR_SZ), last.checked_add(1).and_then(|end| end.checked_mul(PC_SECTOR_SZ)), ) {
+          (Some(start_byte), Some(end_byte), ) => (start_byte, end_byte, ),
+          _ => return Err(SgidiskLibReadError::Bounds(format!("GPT partition {} runs from LBA {} to {}", i + 1, first, last)))
+        };
+        let name: String = char::decode_utf16(e[56..128].chunks(2).map(|c| u16::from_le_bytes([c[0], c[1]])))
+          .map(|c| c.unwrap_or(char::REPLACEMENT_CHARACTER))
+          .take_while(|c| *c != '\0')
+          .collect();
+        Ok(PcPartition {
+          number: i + 1,
+          type_id: guid(&e[..16]),
+          name: if name.is_empty() { None } else { Some(name) },
+          start_byte,
+          size_bytes: end_byte.saturating_sub(start_byte),
+          has_volume_header: false,
+        })
+      })
+      .collect::<Result<Vec<PcPartition>, SgidiskLibReadError>>()?;
+    Ok(Some(PcPartitionTable { scheme: PcScheme::Gpt, partitions }))
+  }
+}
+
+/// Whether the first sector of a disk image is an MBR: it ends with the boot signature, and every
+/// partition entry has a boot flag of 0 or 0x80. An SGI Volume Header has no boot signature, but
+/// its magic is checked in case one happens to.
+pub fn is_mbr(sector: &[u8]) -> bool {
+  sector.len() >= PC_SECTOR_SZ as usize
+    && sector[510..512] == MBR_SIGNATURE
+    && !sector.starts_with(&SgidiskVolume::MAGIC)
+    && sector[MBR_ENTRIES_OFFSET..510].chunks(16).all(|e| e[0] == 0 || e[0] == 0x80)
+}
+
+/// Whether a valid Volume Header starts at an offset
+fn has_volume_header<R: ?Sized>(reader: &mut R, offset: u64) -> bool
+  where R: Read + Seek {
+  let mut header = vec![0u8; SgidiskVolume::SIZE];
+  let read = reader.seek(SeekFrom::Start(offset)).and_then(|_| reader.read_exact(&mut header));
+  read.is_ok() && SgidiskVolume::checksum_ok(&header) && SgidiskVolume::read(&mut header.as_slice()).is_ok()
+}
+
+/// GUID in its usual text form, whose first three fields are stored little endian
+fn guid(b: &[u8]) -> String {
+  format!("{:02x}{:02x}{:02x}{:02x}-{:02x}{:02x}-{:02x}{:02x}-{:02x}{:02x}-{:02x}{:02x}{:02x}{:02x}{:02x}{:02x}",
+          b[3], b[2], b[1], b[0], b[5], b[4], b[7], b[6], b[8], b[9], b[10], b[11], b[12], b[13], b[14], b[15])
+}
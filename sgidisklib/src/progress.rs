@@ -0,0 +1,74 @@
+//! Progress of long library operations, reported to a callback so that applications can display
+//! it, and cancellation of them from another thread
+
+use std::io;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use crate::SgidiskLibReadError;
+
+/// Progress of an operation so far
+#[derive(Debug, Default, Copy, Clone, Eq, PartialEq)]
+pub struct Progress {
+  /// Number of items (e.g. directory entries) processed
+  pub items: u64,
+  /// Total number of items, if known
+  pub items_total: Option<u64>,
+  /// Number of bytes processed
+  pub bytes: u64,
+  /// Total number of bytes, if known
+  pub bytes_total: Option<u64>,
+}
+
+/// Token which cancels the operations it is passed to, checked each time they report progress.
+/// Clones share the same state, so one can be kept to cancel from another thread.
+#[derive(Debug, Default, Clone)]
+pub struct CancellationToken {
+  cancelled: Arc<AtomicBool>,
+}
+
+impl Progress {
+  /// Progress counted in items
+  pub fn items(items: u64, items_total: Option<u64>) -> Self {
+    Progress {
+      items,
+      items_total,
+      ..Default::default()
+    }
+  }
+
+  /// Progress counted in bytes
+  pub fn bytes(bytes: u64, bytes_total: Option<u64>) -> Self {
+    Progress {
+      bytes,
+      bytes_total,
+      ..Default::default()
+    }
+  }
+}
+
+impl CancellationToken {
+  /// Token which hasn't been cancelled
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  /// Cancel the operations using this token
+  pub fn cancel(&self) {
+    self.cancelled.store(true, Ordering::Relaxed);
+  }
+
+  /// Whether the operations using this token have been cancelled
+  pub fn is_cancelled(&self) -> bool {
+    self.cancelled.load(Ordering::Relaxed)
+  }
+
+  /// Error if cancelled, for operations returning I/O errors
+  pub(crate) fn check_io(&self) -> io::Result<()> {
+    if self.is_cancelled() {
+      Err(io::Error::new(io::ErrorKind::Other, SgidiskLibReadError::Cancelled))
+    } else {
+      Ok(())
+    }
+  }
+}
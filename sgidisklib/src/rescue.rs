@@ -0,0 +1,219 @@
+//! Reading damaged media the way ddrescue does: failed reads are narrowed down to the sector that
+//! failed and retried, and sectors which still can't be read may be skipped as zeros, so that a
+//! partially unreadable drive or disc can still be read as completely as possible. Every sector
+//! read or given up on is recorded in a map, which can be written as a ddrescue mapfile.
+
+use std::io;
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::ops::Range;
+
+/// How failed reads are handled
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct RescueOptions {
+  /// Number of times a sector which failed to read is retried
+  pub retries: u32,
+  /// Whether sectors which can't be read are read as zeros, rather than failing the read
+  pub skip_unreadable: bool,
+  /// Size of the sectors failed reads are narrowed down to, in bytes
+  pub sector_sz: u64,
+}
+
+/// Map of the byte ranges read and those which couldn't be
+#[derive(Debug, Default, Clone, Eq, PartialEq)]
+pub struct RescueMap {
+  /// Ranges read, merged and in order
+  pub read: Vec<Range<u64>>,
+  /// Ranges of the sectors which couldn't be read, merged and in order
+  pub bad: Vec<Range<u64>>,
+  /// Number of retries made
+  pub retries: u64,
+  /// Number of sectors read after failing at first
+  pub recovered: u64,
+}
+
+/// Reader wrapper which retries failed reads a sector at a time, optionally reading sectors which
+/// can't be read as zeros, and maps what it read
+#[derive(Debug)]
+pub struct RescueReader<R> {
+  inner: R,
+  options: RescueOptions,
+  /// Current offset
+  pos: u64,
+  map: RescueMap,
+}
+
+impl Default for RescueOptions {
+  fn default() -> Self {
+    RescueOptions {
+      retries: 3,
+      skip_unreadable: false,
+      sector_sz: 512,
+    }
+  }
+}
+
+impl RescueMap {
+  /// Number of bytes which couldn't be read
+  pub fn bad_bytes(&self) -> u64 {
+    self.bad.iter().map(|r| r.end - r.start).sum()
+  }
+
+  /// Write the map as a ddrescue mapfile of a disk image of a given size: '+' for ranges read,
+  /// '-' for bad sectors and '?' for the rest, which weren't tried
+  pub fn write_mapfile<W: Write>(&self, w: &mut W, size: u64) -> io::Result<()> {
+    let mut ranges: Vec<(Range<u64>, char, )> = self.read.iter().map(|r| (r.clone(), '+', ))
+      .chain(self.bad.iter().map(|r| (r.clone(), '-', )))
+      .map(|(r, status, )| (r.start.min(size)..r.end.min(size), status, ))
+      .filter(|(r, _, )| !r.is_empty())
+      .collect();
+    ranges.sort_by_key(|(r, _, )| r.start);
+
+    writeln!(w, "# Mapfile. Created by sgidisk")?;
+    writeln!(w, "# current_pos  current_status  current_pass")?;
+    writeln!(w, "0x{:08X}     +               1", 0)?;
+    writeln!(w, "#      pos        size  status")?;
+    let mut next = 0;
+    for (range, status, ) in ranges {
+      // Ranges only overlap where a sector read once failed later, and are mapped as first read
+      let start = range.start.max(next);
+      if start >= range.end {
+        continue;
+      }
+      if start > next {
+        writeln!(w, "0x{:08X}  0x{:08X}  ?", next, start - next)?;
+      }
+      writeln!(w, "0x{:08X}  0x{:08X}  {}", start, range.end - start, status)?;
+      next = range.end;
+    }
+    if next < size {
+      writeln!(w, "0x{:08X}  0x{:08X}  ?", next, size - next)?;
+    }
+    Ok(())
+  }
+}
+
+impl<R> RescueReader<R> {
+  /// Wrap a reader positioned at its start
+  pub fn new(inner: R, options: RescueOptions) -> Self {
+    RescueReader {
+      inner,
+      options: RescueOptions { sector_sz: options.sector_sz.max(1), ..options },
+      pos: 0,
+      map: RescueMap::default(),
+    }
+  }
+
+  /// How failed reads are handled
+  pub fn options(&self) -> &RescueOptions {
+    &self.options
+  }
+
+  /// Map of what has been read so far
+  pub fn map(&self) -> &RescueMap {
+    &self.map
+  }
+
+  /// Get a reference to the wrapped reader
+  pub fn get_ref(&self) -> &R {
+    &self.inner
+  }
+
+  /// Unwrap the reader
+  pub fn into_inner(self) -> R {
+    self.inner
+  }
+}
+
+impl<R: Read + Seek> RescueReader<R> {
+  /// Read what is left of the sector at the current offset, retrying if it fails. Returns the
+  /// number of bytes read, and whether they are zeros in place of a sector which couldn't be.
+  fn read_sector(&mut self, buf: &mut [u8]) -> io::Result<(usize, bool, )> {
+    let sector_end = (self.pos / self.options.sector_sz + 1) * self.options.sector_sz;
+    let len = buf.len().min((sector_end - self.pos) as usize);
+    let mut attempt = 0;
+    loop {
+      let read = self.inner.seek(SeekFrom::Start(self.pos))
+        .and_then(|_| self.inner.read(&mut buf[..len]));
+      match read {
+        Ok(n) => {
+          if attempt > 0 {
+            self.map.recovered += 1;
+          }
+          return Ok((n, false, ));
+        }
+        Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+        Err(_) if attempt < self.options.retries => {
+          attempt += 1;
+          self.map.retries += 1;
+        }
+        Err(e) => {
+          insert_range(&mut self.map.bad, self.pos..sector_end);
+          if !self.options.skip_unreadable {
+            return Err(e);
+          }
+          #[cfg(feature = "tracing")]
+          tracing::warn!("Unable to read bytes {}..{} after {} attempts, reading them as zeros: {:?}", self.pos, sector_end, attempt + 1, &e);
+          buf[..len].fill(0);
+          // Leave the wrapped reader after the sector, where reading continues
+          let _ = self.inner.seek(SeekFrom::Start(self.pos + len as u64));
+          return Ok((len, true, ));
+        }
+      }
+    }
+  }
+}
+
+impl<R: Read + Seek> Read for RescueReader<R> {
+  fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+    if buf.is_empty() {
+      return Ok(0);
+    }
+    // Read as asked, narrowing down to one sector only when that fails
+    let (n, skipped, ) = match self.inner.read(buf) {
+      Ok(n) => (n, false, ),
+      Err(e) if e.kind() == io::ErrorKind::Interrupted => return Err(e),
+      Err(_) => self.read_sector(buf)?
+    };
+    if !skipped {
+      let range = self.pos..self.pos + n as u64;
+      // A sector which failed before has now been read
+      remove_range(&mut self.map.bad, &range);
+      insert_range(&mut self.map.read, range);
+    }
+    self.pos += n as u64;
+    Ok(n)
+  }
+}
+
+impl<R: Seek> Seek for RescueReader<R> {
+  fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+    self.pos = self.inner.seek(pos)?;
+    Ok(self.pos)
+  }
+}
+
+/// Add a range to ranges kept merged and in order
+fn insert_range(ranges: &mut Vec<Range<u64>>, range: Range<u64>) {
+  if range.is_empty() {
+    return;
+  }
+  let first = ranges.partition_point(|r| r.end < range.start);
+  let last = ranges.partition_point(|r| r.start <= range.end);
+  if first == last {
+    ranges.insert(first, range);
+  } else {
+    let merged = ranges[first].start.min(range.start)..ranges[last - 1].end.max(range.end);
+    ranges.splice(first..last, std::iter::once(merged));
+  }
+}
+
+/// Remove a range from ranges kept merged and in order
+fn remove_range(ranges: &mut Vec<Range<u64>>, range: &Range<u64>) {
+  if ranges.iter().all(|r| r.end <= range.start || r.start >= range.end) {
+    return;
+  }
+  *ranges = ranges.iter()
+    .flat_map(|r| [r.start..r.end.min(range.start), r.start.max(range.end)..r.end])
+    .filter(|r| !r.is_empty())
+    .collect();
+}
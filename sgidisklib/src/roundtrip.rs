@@ -0,0 +1,99 @@
+//! Round trip tests of the on-disk structures: bytes parsed and written again must be unchanged.
+//! Padding is zeroed in the input, since it isn't kept when parsing.
+
+use std::ops::Range;
+
+use deku::prelude::*;
+use proptest::collection::vec;
+use proptest::prelude::*;
+
+use crate::efs::raw_dir::{DirectoryBlock, DirectoryEntry};
+use crate::efs::raw_inode::{EfsInode, Extent};
+use crate::efs::raw_sb::EfsSuperblock;
+use crate::volhdr::raw::{PartitionTable, VolumeHeader};
+
+/// Offset of the partition table in a Volume Header
+const VH_PARTITIONS_OFFSET: usize = 312;
+/// Size of a partition table entry
+const PARTITION_TABLE_SZ: usize = 12;
+/// Padding in a Volume Header: in the device parameters, and after the checksum
+const VH_PADDING: [Range<usize>; 5] = [24..28, 30..32, 35..38, 42..44, 48..68];
+const VH_TRAILING_PADDING: Range<usize> = 508..512;
+/// Padding in a superblock, after fs_dirty
+const SB_PADDING: Range<usize> = 22..24;
+/// Valid fs_dirty and fs_magic values
+const SB_DIRTY: [i16; 4] = [0x0000, 0x0BAD, 0x7777, 0x1234];
+const SB_MAGIC: [i32; 2] = [0x00072959, 0x0007295a];
+
+/// Check that parsing bytes and writing them again gives the same bytes
+fn round_trip<'a, T>(bytes: &'a [u8]) -> Result<(), TestCaseError>
+  where T: DekuContainerRead<'a> + DekuContainerWrite {
+  let ((rest, _, ), parsed, ) = match T::from_bytes((bytes, 0, )) {
+    Ok(parsed) => parsed,
+    Err(e) => return Err(TestCaseError::fail(format!("Parse failed: {:?}", &e)))
+  };
+  prop_assert!(rest.is_empty());
+  prop_assert_eq!(parsed.to_bytes().unwrap(), bytes.to_vec());
+  Ok(())
+}
+
+/// Zero ranges of bytes
+fn zero(bytes: &mut [u8], ranges: &[Range<usize>]) {
+  for range in ranges {
+    bytes[range.clone()].fill(0);
+  }
+}
+
+proptest! {
+  #[test]
+  fn volume_header(mut bytes in vec(any::<u8>(), VolumeHeader::SIZE), types in vec(0i32..15, VolumeHeader::N_PAR_TAB)) {
+    bytes[0..4].copy_from_slice(b"\x0B\xE5\xA9\x41");
+    zero(&mut bytes, &VH_PADDING);
+    zero(&mut bytes, &[VH_TRAILING_PADDING]);
+    for (n, partition_type, ) in types.iter().enumerate() {
+      let at = VH_PARTITIONS_OFFSET + n * PARTITION_TABLE_SZ + 8;
+      bytes[at..at + 4].copy_from_slice(&partition_type.to_be_bytes());
+    }
+    round_trip::<VolumeHeader>(&bytes)?;
+  }
+
+  #[test]
+  fn partition_table(mut bytes in vec(any::<u8>(), PARTITION_TABLE_SZ), partition_type in 0i32..15) {
+    bytes[8..12].copy_from_slice(&partition_type.to_be_bytes());
+    round_trip::<PartitionTable>(&bytes)?;
+  }
+
+  #[test]
+  fn superblock(mut bytes in vec(any::<u8>(), 92), dirty in 0usize..4, magic in 0usize..2) {
+    bytes[20..22].copy_from_slice(&SB_DIRTY[dirty].to_be_bytes());
+    zero(&mut bytes, &[SB_PADDING]);
+    bytes[28..32].copy_from_slice(&SB_MAGIC[magic].to_be_bytes());
+    round_trip::<EfsSuperblock>(&bytes)?;
+  }
+
+  #[test]
+  fn inode(bytes in vec(any::<u8>(), EfsInode::SIZE)) {
+    round_trip::<EfsInode>(&bytes)?;
+  }
+
+  #[test]
+  fn extent(mut bytes in vec(any::<u8>(), Extent::SIZE)) {
+    // "Magic number MUST BE ZERO"
+    bytes[0] = 0;
+    round_trip::<Extent>(&bytes)?;
+  }
+
+  #[test]
+  fn directory_block(mut bytes in vec(any::<u8>(), DirectoryBlock::SIZE)) {
+    bytes[0..2].copy_from_slice(b"\xBE\xEF");
+    round_trip::<DirectoryBlock>(&bytes)?;
+  }
+
+  #[test]
+  fn directory_entry(inode in any::<u32>(), name in vec(any::<u8>(), 0..=255)) {
+    let mut bytes = inode.to_be_bytes().to_vec();
+    bytes.push(name.len() as u8);
+    bytes.extend(&name);
+    round_trip::<DirectoryEntry>(&bytes)?;
+  }
+}
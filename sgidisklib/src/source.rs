@@ -0,0 +1,143 @@
+use std::collections::BTreeMap;
+use std::io;
+use std::io::{Read, Seek, SeekFrom};
+
+/// Disk image held as ranges of bytes fetched so far, for reading images which can only be
+/// fetched asynchronously (e.g. over HTTP in a browser). Reading outside the fetched ranges
+/// fails, recording the missing range; the caller fetches it, inserts it and retries.
+#[derive(Debug, Default)]
+pub struct RangeCache {
+  /// Size of the whole disk image, in bytes
+  size: u64,
+  /// Fetched ranges by starting offset; ranges don't overlap
+  ranges: BTreeMap<u64, Vec<u8>>,
+  /// Current position
+  pos: u64,
+  /// Range (offset, length) that a read needed but wasn't fetched
+  missing: Option<(u64, u64, )>,
+}
+
+impl RangeCache {
+  /// Minimum length of range to fetch, so reading a file doesn't need a fetch per read
+  pub const FETCH_SZ: u64 = 1024 * 1024;
+
+  /// Empty cache of a disk image of the given size
+  pub fn new(size: u64) -> Self {
+    RangeCache {
+      size,
+      ..Default::default()
+    }
+  }
+
+  /// Cache holding a whole disk image
+  pub fn from_bytes(data: Vec<u8>) -> Self {
+    let mut cache = RangeCache::new(data.len() as u64);
+    cache.insert(0, data);
+    cache
+  }
+
+  /// Size of the disk image, in bytes
+  pub fn size(&self) -> u64 {
+    self.size
+  }
+
+  /// Add a fetched range starting at an offset. Bytes already cached are kept.
+  pub fn insert(&mut self, offset: u64, data: Vec<u8>) {
+    let mut offset = offset;
+    let mut data = &data[..];
+    while !data.is_empty() {
+      // Skip bytes already cached, then store up to the next cached range
+      if let Some(cached_len) = self.cached_len(offset) {
+        let skip = min_len(cached_len, data.len());
+        offset += skip as u64;
+        data = &data[skip..];
+        continue;
+      }
+      let until_next = match self.ranges.range(offset..).next() {
+        Some((next, _, )) => min_len(next - offset, data.len()),
+        None => data.len()
+      };
+      self.ranges.insert(offset, data[..until_next].to_vec());
+      offset += until_next as u64;
+      data = &data[until_next..];
+    }
+  }
+
+  /// Take the range (offset, length) which the last failed read needed
+  pub fn take_missing(&mut self) -> Option<(u64, u64, )> {
+    self.missing.take()
+  }
+
+  /// Number of bytes cached from an offset to the end of the range holding it
+  fn cached_len(&self, offset: u64) -> Option<u64> {
+    let (start, data, ) = self.ranges.range(..=offset).next_back()?;
+    let end = start + data.len() as u64;
+    if offset < end {
+      Some(end - offset)
+    } else {
+      None
+    }
+  }
+}
+
+/// Lesser of a byte count and a slice length
+fn min_len(count: u64, len: usize) -> usize {
+  if count < len as u64 {
+    count as usize
+  } else {
+    len
+  }
+}
+
+impl Read for RangeCache {
+  fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+    if buf.is_empty() || self.pos >= self.size {
+      return Ok(0);
+    }
+
+    // Read from the range holding the position, if any
+    if let Some((start, data, )) = self.ranges.range(..=self.pos).next_back() {
+      let from = (self.pos - start) as usize;
+      if from < data.len() {
+        let n = min_len((data.len() - from) as u64, buf.len());
+        buf[..n].copy_from_slice(&data[from..from + n]);
+        self.pos += n as u64;
+        return Ok(n);
+      }
+    }
+
+    // Record what needs fetching, up to the next cached range or the end of the image
+    let mut len = (buf.len() as u64).max(Self::FETCH_SZ).min(self.size - self.pos);
+    if let Some((next, _, )) = self.ranges.range(self.pos..).next() {
+      len = len.min(next - self.pos);
+    }
+    self.missing = Some((self.pos, len, ));
+    Err(io::Error::new(io::ErrorKind::Other, format!("Bytes {}..{} of disk image not fetched", self.pos, self.pos + len)))
+  }
+}
+
+impl Seek for RangeCache {
+  fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+    let pos = match pos {
+      SeekFrom::Start(offset) => Some(offset),
+      SeekFrom::End(offset) => checked_offset(self.size, offset),
+      SeekFrom::Current(offset) => checked_offset(self.pos, offset)
+    };
+    match pos {
+      Some(pos) => {
+        self.pos = pos;
+        Ok(pos)
+      }
+      None => Err(io::Error::new(io::ErrorKind::InvalidInput, "Seek to a negative or overflowing position"))
+    }
+  }
+}
+
+/// Add a signed offset to a position
+fn checked_offset(base: u64, offset: i64) -> Option<u64> {
+  if offset >= 0 {
+    base.checked_add(offset as u64)
+  } else {
+    base.checked_sub(offset.unsigned_abs())
+  }
+}
@@ -0,0 +1,89 @@
+//! Finding runs of printable text in binary data, as `strings(1)` does, with the offset of each.
+//! The scanner is a writer, so that anything which can be copied (a region of a disk image, a
+//! volume file or the contents of an EFS file) can be scanned as it is read.
+
+use std::io;
+use std::io::Write;
+
+/// Number of printable characters making a string unless told otherwise, as strings(1) uses
+pub const DEFAULT_MIN_LEN: usize = 4;
+
+/// Longest string kept whole; longer runs of text are split into strings of this length
+pub const MAX_STRING_LEN: usize = 4096;
+
+/// Run of printable characters
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct FoundString {
+  /// Offset of the first character
+  pub offset: u64,
+  pub text: String,
+}
+
+/// Writer collecting the strings of at least a minimum length in the bytes written to it
+#[derive(Debug)]
+pub struct StringScanner {
+  min_len: usize,
+  /// Offset of the next byte written
+  offset: u64,
+  /// Printable characters seen since the last unprintable one
+  pending: String,
+  found: Vec<FoundString>,
+}
+
+impl StringScanner {
+  /// Scanner of strings of at least `min_len` characters in bytes starting at `offset`
+  pub fn new(offset: u64, min_len: usize) -> Self {
+    StringScanner {
+      min_len: min_len.max(1),
+      offset,
+      pending: String::new(),
+      found: Vec::new(),
+    }
+  }
+
+  /// Continue with bytes from another offset, so that no string runs across the gap
+  pub fn seek(&mut self, offset: u64) {
+    self.end_string();
+    self.offset = offset;
+  }
+
+  /// Strings found, in order, including one running to the end of the bytes written
+  pub fn finish(mut self) -> Vec<FoundString> {
+    self.end_string();
+    self.found
+  }
+
+  /// Keep the pending string if it is long enough, and start another
+  fn end_string(&mut self) {
+    if self.pending.len() >= self.min_len {
+      self.found.push(FoundString {
+        offset: self.offset - self.pending.len() as u64,
+        text: std::mem::take(&mut self.pending),
+      });
+    }
+    self.pending.clear();
+  }
+}
+
+impl Write for StringScanner {
+  fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+    for &b in buf {
+      // Printable ASCII and tab, as strings(1) accepts
+      if b == b'\t' || (0x20..0x7f).contains(&b) {
+        self.pending.push(b as char);
+        self.offset += 1;
+        if self.pending.len() == MAX_STRING_LEN {
+          self.end_string();
+        }
+      } else {
+        self.end_string();
+        self.offset += 1;
+      }
+    }
+    Ok(buf.len())
+  }
+
+  fn flush(&mut self) -> io::Result<()> {
+    Ok(())
+  }
+}
@@ -0,0 +1,730 @@
+//! Builders for tiny, valid disk images (a Volume Header with volume files, and an EFS partition
+//! with known contents), for tests which can't depend on real images. Enabled with the `testgen`
+//! feature; `sgidisk-testgen` writes `sample()` to a file.
+
+use std::collections::BTreeMap;
+
+use deku::prelude::*;
+
+use crate::efs::EFS_BLOCK_SZ;
+use crate::efs::dir::Directory;
+use crate::efs::raw_dir::DirectoryBlock;
+use crate::efs::raw_inode::{EfsInode, Extent};
+use crate::efs::raw_sb::{EfsSuperblock, EfsSuperblockDirty, EfsSuperblockMagic};
+use crate::volhdr::PartitionType;
+use crate::volhdr::raw::{PartitionTable, VolumeDeviceParameters, VolumeDirectory, VolumeHeader};
+
+/// Partition holding the EFS filesystem, as on IRIX system disks
+pub const EFS_PARTITION: usize = 7;
+/// Partition covering the Volume Header
+pub const VOLUME_HEADER_PARTITION: usize = 8;
+/// Partition covering the entire volume
+pub const ENTIRE_VOLUME_PARTITION: usize = 10;
+/// Access, modification and creation time of every inode (1993-08-30)
+pub const TIMESTAMP: i32 = 746_668_800;
+
+/// Longest extent written, in blocks (as IRIX's EFS_MAXEXTENTLEN)
+const MAX_EXTENT_LEN: usize = 248;
+/// Bytes of a directory block available for entries and their offsets
+const DIR_SPACE_SZ: usize = EFS_BLOCK_SZ - 4;
+
+/// Contents of an entry in an EfsBuilder
+#[derive(Debug, Clone)]
+enum Contents {
+  Directory,
+  File { data: Vec<u8>, fragmented: bool },
+  Symlink(String),
+  CharDevice(u32, u32),
+  BlockDevice(u32, u32),
+  Fifo,
+  Socket,
+}
+
+/// Entry in an EfsBuilder
+#[derive(Debug, Clone)]
+struct Entry {
+  contents: Contents,
+  mode: u16,
+  uid: u16,
+  gid: u16,
+}
+
+/// Builder of an EFS filesystem. Entries are added by absolute path; parent directories are
+/// created as needed.
+#[derive(Debug, Clone)]
+pub struct EfsBuilder {
+  /// Entries by path without leading '/'; the root directory is ""
+  entries: BTreeMap<String, Entry>,
+  cylinder_groups: usize,
+  old_magic: bool,
+  /// Names written in place of the last component of entries' paths, which may hold '/'
+  disk_names: BTreeMap<String, String>,
+}
+
+/// Builder of a disk image with a Volume Header and, optionally, an EFS partition
+#[derive(Debug, Clone, Default)]
+pub struct ImageBuilder {
+  boot_file: Option<String>,
+  volume_files: Vec<(String, Vec<u8>, )>,
+  efs: Option<EfsBuilder>,
+}
+
+/// Deterministic, non-repeating-looking contents of a given length, so misplaced blocks show
+pub fn pattern(len: usize, seed: u8) -> Vec<u8> {
+  (0..len)
+    .map(|i| (i as u32).wrapping_mul(2_654_435_761).rotate_left(seed as u32 % 32) as u8 ^ seed)
+    .collect()
+}
+
+/// Number of basic blocks holding a number of bytes
+fn blocks(len: usize) -> usize {
+  (len + EFS_BLOCK_SZ - 1) / EFS_BLOCK_SZ
+}
+
+/// Parent path of a path, and the entry's name
+fn split_path(path: &str) -> (&str, &str, ) {
+  match path.rsplit_once('/') {
+    Some((parent, name, )) => (parent, name, ),
+    None => ("", path, )
+  }
+}
+
+impl Default for EfsBuilder {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+impl EfsBuilder {
+  /// Filesystem with only a root directory
+  pub fn new() -> Self {
+    let mut entries = BTreeMap::new();
+    entries.insert(String::new(), Entry { contents: Contents::Directory, mode: 0o755, uid: 0, gid: 0 });
+    EfsBuilder {
+      entries,
+      cylinder_groups: 1,
+      old_magic: false,
+      disk_names: BTreeMap::new(),
+    }
+  }
+
+  /// Spread inodes and data over a number of cylinder groups
+  pub fn cylinder_groups(&mut self, count: usize) -> &mut Self {
+    self.cylinder_groups = count.max(1);
+    self
+  }
+
+  /// Add a directory
+  pub fn dir(&mut self, path: &str) -> &mut Self {
+    self.add(path, Contents::Directory, 0o755)
+  }
+
+  /// Add a regular file stored in as few extents as possible
+  pub fn file(&mut self, path: &str, data: &[u8]) -> &mut Self {
+    self.add(path, Contents::File { data: data.to_vec(), fragmented: false }, 0o644)
+  }
+
+  /// Add a regular file with a free block between each of its blocks, so each block is an extent
+  pub fn fragmented_file(&mut self, path: &str, data: &[u8]) -> &mut Self {
+    self.add(path, Contents::File { data: data.to_vec(), fragmented: true }, 0o644)
+  }
+
+  /// Add a symbolic link
+  pub fn symlink(&mut self, path: &str, target: &str) -> &mut Self {
+    self.add(path, Contents::Symlink(target.to_string()), 0o777)
+  }
+
+  /// Add a character device
+  pub fn char_device(&mut self, path: &str, major: u32, minor: u32) -> &mut Self {
+    self.add(path, Contents::CharDevice(major, minor), 0o666)
+  }
+
+  /// Add a block device
+  pub fn block_device(&mut self, path: &str, major: u32, minor: u32) -> &mut Self {
+    self.add(path, Contents::BlockDevice(major, minor), 0o600)
+  }
+
+  /// Add a FIFO
+  pub fn fifo(&mut self, path: &str) -> &mut Self {
+    self.add(path, Contents::Fifo, 0o644)
+  }
+
+  /// Add a socket
+  pub fn socket(&mut self, path: &str) -> &mut Self {
+    self.add(path, Contents::Socket, 0o755)
+  }
+
+  /// Set the permission bits of an added entry
+  pub fn mode(&mut self, path: &str, mode: u16) -> &mut Self {
+    if let Some(entry) = self.entries.get_mut(path.trim_matches('/')) {
+      entry.mode = mode & EfsInode::INODE_MODE_MASK;
+    }
+    self
+  }
+
+  /// Set the owner and group of an added entry
+  pub fn owner(&mut self, path: &str, uid: u16, gid: u16) -> &mut Self {
+    if let Some(entry) = self.entries.get_mut(path.trim_matches('/')) {
+      entry.uid = uid;
+      entry.gid = gid;
+    }
+    self
+  }
+
+  /// Use the superblock magic number of filesystems made before IRIX 3.3
+  pub fn old_magic(&mut self) -> &mut Self {
+    self.old_magic = true;
+    self
+  }
+
+  /// Write an added entry into its directory under another name, which may be one IRIX would never
+  /// create, e.g. "../x" or "a/b"
+  pub fn name_on_disk(&mut self, path: &str, name: &str) -> &mut Self {
+    self.disk_names.insert(path.trim_matches('/').to_string(), name.to_string());
+    self
+  }
+
+  /// Add an entry, and any missing parent directories
+  fn add(&mut self, path: &str, contents: Contents, mode: u16) -> &mut Self {
+    let path = path.trim_matches('/');
+    let mut parent = String::new();
+    for component in path.split('/').take(path.split('/').count() - 1) {
+      if !parent.is_empty() {
+        parent.push('/');
+      }
+      parent.push_str(component);
+      self.entries.entry(parent.clone())
+        .or_insert(Entry { contents: Contents::Directory, mode: 0o755, uid: 0, gid: 0 });
+    }
+    self.entries.insert(path.to_string(), Entry { contents, mode, uid: 0, gid: 0 });
+    self
+  }
+
+  /// Build the filesystem, as the bytes of its partition
+  pub fn build(&self) -> Vec<u8> {
+    // Number inodes: root is 2, then the other entries in path order
+    let mut inodes = BTreeMap::new();
+    inodes.insert(String::new(), Directory::ROOT_DIRECTORY_INODE as u32);
+    for (n, path, ) in self.entries.keys().filter(|p| !p.is_empty()).enumerate() {
+      inodes.insert(path.clone(), Directory::ROOT_DIRECTORY_INODE as u32 + 1 + n as u32);
+    }
+
+    // Contents of each entry held in data blocks
+    let data: BTreeMap<&str, Vec<u8>> = self.entries.iter()
+      .map(|(path, entry, )| {
+        let data = match &entry.contents {
+          Contents::Directory => self.dir_data(path, &inodes),
+          Contents::File { data, .. } => data.clone(),
+          Contents::Symlink(target) => target.as_bytes().to_vec(),
+          _ => Vec::new()
+        };
+        (path.as_str(), data, )
+      })
+      .collect();
+
+    // Lay out cylinder groups big enough for every inode and block, with gaps between the
+    // blocks of fragmented files and indirect extent blocks
+    let ncg = self.cylinder_groups;
+    let inode_count = inodes.len() + Directory::ROOT_DIRECTORY_INODE as usize;
+    let inodes_per_bb = EFS_BLOCK_SZ / EfsInode::SIZE;
+    let cgisize = ((inode_count + ncg - 1) / ncg + inodes_per_bb - 1) / inodes_per_bb;
+    let cg_inodes = cgisize * inodes_per_bb;
+    let data_blocks: usize = self.entries.iter()
+      .map(|(path, entry, )| {
+        let n = blocks(data[path.as_str()].len());
+        let (used, max_extents, ) = match entry.contents {
+          Contents::File { fragmented: true, .. } => ((2 * n).saturating_sub(1), n + ncg, ),
+          _ => (n, (n + MAX_EXTENT_LEN - 1) / MAX_EXTENT_LEN + ncg, )
+        };
+        let indirect = if max_extents > EfsInode::EFS_DIRECTEXTENTS { blocks(max_extents * Extent::SIZE) } else { 0 };
+        used + indirect
+      })
+      .sum();
+    let cgfsize = cgisize + (data_blocks + ncg - 1) / ncg + 1;
+
+    // Bitmap (from block 2) covers the whole filesystem, and the first cylinder group follows it
+    let mut bitmap_blocks = 1;
+    let (firstcg, fs_size, ) = loop {
+      let firstcg = 2 + bitmap_blocks;
+      let fs_size = firstcg + ncg * cgfsize;
+      let needed = blocks((fs_size + 7) / 8);
+      if needed <= bitmap_blocks {
+        break (firstcg, fs_size, );
+      }
+      bitmap_blocks = needed;
+    };
+
+    let mut image = vec![0; fs_size * EFS_BLOCK_SZ];
+    let mut allocator = Allocator {
+      firstcg,
+      cgfsize,
+      cgisize,
+      ncg,
+      cg: 0,
+      next: firstcg + cgisize,
+      used: vec![false; fs_size],
+    };
+
+    // Write each entry's data and inode
+    for (path, entry, ) in &self.entries {
+      let ino = inodes[path] as usize;
+      let data = &data[path.as_str()];
+      let fragmented = matches!(entry.contents, Contents::File { fragmented: true, .. });
+
+      // Allocate and fill data blocks, in order through the file
+      let mut file_blocks = Vec::with_capacity(blocks(data.len()));
+      for (n, chunk, ) in data.chunks(EFS_BLOCK_SZ).enumerate() {
+        if fragmented && n > 0 {
+          allocator.skip();
+        }
+        let block = allocator.alloc();
+        image[block * EFS_BLOCK_SZ..block * EFS_BLOCK_SZ + chunk.len()].copy_from_slice(chunk);
+        file_blocks.push(block);
+      }
+      let file_extents = extents(&file_blocks);
+
+      // Inodes with too many extents for the inode hold indirect extents, pointing to blocks
+      // holding the extents
+      let direct = if file_extents.len() > EfsInode::EFS_DIRECTEXTENTS {
+        let extent_bytes: Vec<u8> = file_extents.iter().flat_map(|e| e.to_bytes().unwrap()).collect();
+        let indirect_blocks: Vec<usize> = (0..blocks(extent_bytes.len())).map(|_| allocator.alloc()).collect();
+        for (chunk, block, ) in extent_bytes.chunks(EFS_BLOCK_SZ).zip(&indirect_blocks) {
+          image[block * EFS_BLOCK_SZ..block * EFS_BLOCK_SZ + chunk.len()].copy_from_slice(chunk);
+        }
+        let mut indirect = extents(&indirect_blocks);
+        // "total number of indirect extents" is held in the first one's offset
+        indirect[0].ex_offset = indirect.len() as u32;
+        indirect
+      } else {
+        file_extents.clone()
+      };
+
+      let raw = self.raw_inode(path, entry, data.len(), file_extents.len(), &direct);
+      let offset = (firstcg + (ino / cg_inodes) * cgfsize) * EFS_BLOCK_SZ + (ino % cg_inodes) * EfsInode::SIZE;
+      image[offset..offset + EfsInode::SIZE].copy_from_slice(&raw.to_bytes().unwrap());
+    }
+
+    // Bitmap, where 1 is a free data block
+    let mut free_blocks = 0;
+    for cg in 0..ncg {
+      let data_start = firstcg + cg * cgfsize + cgisize;
+      for block in data_start..data_start + cgfsize - cgisize {
+        if !allocator.used[block] {
+          image[2 * EFS_BLOCK_SZ + block / 8] |= 1 << (block % 8);
+          free_blocks += 1;
+        }
+      }
+    }
+
+    let mut fname = [0; 6];
+    fname[..6].copy_from_slice(b"testfs");
+    let mut fpack = [0; 6];
+    fpack[..4].copy_from_slice(b"test");
+    let mut sb = EfsSuperblock {
+      fs_size: fs_size as i32,
+      fs_firstcg: firstcg as i32,
+      fs_cgfsize: cgfsize as i32,
+      fs_cgisize: cgisize as i16,
+      fs_sectors: 32,
+      fs_heads: 1,
+      fs_ncg: ncg as i16,
+      fs_dirty: EfsSuperblockDirty::Clean,
+      fs_time: TIMESTAMP,
+      fs_magic: match self.old_magic {
+        true => EfsSuperblockMagic::OldMagic,
+        false => EfsSuperblockMagic::NewMagic
+      },
+      fs_fname: fname,
+      fs_fpack: fpack,
+      fs_bmsize: ((fs_size + 7) / 8) as i32,
+      fs_tfree: free_blocks as i32,
+      fs_tinode: (ncg * cg_inodes - inode_count) as i32,
+      fs_bmblock: 0,
+      fs_replsb: 0,
+      fs_lastialloc: (inode_count - 1) as i32,
+      fs_spare: [0; 20],
+      fs_checksum: 0,
+    };
+    sb.fs_checksum = EfsSuperblock::checksum(&sb.to_bytes().unwrap());
+    let sb_bytes = sb.to_bytes().unwrap();
+    image[EFS_BLOCK_SZ..EFS_BLOCK_SZ + sb_bytes.len()].copy_from_slice(&sb_bytes);
+
+    image
+  }
+
+  /// Directory blocks of a directory: ".", ".." and its children
+  fn dir_data(&self, path: &str, inodes: &BTreeMap<String, u32>) -> Vec<u8> {
+    let parent = if path.is_empty() { path } else { split_path(path).0 };
+    let mut entries = vec![(".", inodes[path], ), ("..", inodes[parent], )];
+    for child in self.entries.keys().filter(|p| !p.is_empty() && split_path(p).0 == path) {
+      let name = self.disk_names.get(child).map_or(split_path(child).1, |name| name.as_str());
+      entries.push((name, inodes[child], ));
+    }
+
+    // Fill each block with as many entries (and their one byte offsets) as fit
+    let mut data = Vec::new();
+    let mut block_entries = Vec::new();
+    let mut used = 0;
+    for (name, ino, ) in entries {
+      let entry_sz = (4 + 1 + name.len() + 1) & !1;
+      if used + entry_sz + 1 > DIR_SPACE_SZ {
+        data.extend(dir_block(&block_entries));
+        block_entries.clear();
+        used = 0;
+      }
+      block_entries.push((name, ino, ));
+      used += entry_sz + 1;
+    }
+    data.extend(dir_block(&block_entries));
+    data
+  }
+
+  /// Raw inode of an entry
+  fn raw_inode(&self, path: &str, entry: &Entry, size: usize, num_extents: usize, direct: &[Extent]) -> EfsInode {
+    let (type_bits, device, ) = match entry.contents {
+      Contents::Directory => (EfsInode::INODE_TYPE_DIR, None, ),
+      Contents::File { .. } => (EfsInode::INODE_TYPE_REG, None, ),
+      Contents::Symlink(_) => (EfsInode::INODE_TYPE_LNK, None, ),
+      Contents::CharDevice(major, minor) => (EfsInode::INODE_TYPE_FCHR, Some((major, minor, )), ),
+      Contents::BlockDevice(major, minor) => (EfsInode::INODE_TYPE_BLK, Some((major, minor, )), ),
+      Contents::Fifo => (EfsInode::INODE_TYPE_FIFO, None, ),
+      Contents::Socket => (EfsInode::INODE_TYPE_SOCK, None, )
+    };
+
+    let mut data = [0; EfsInode::EXTENT_DATA_AREA_SZ];
+    match device {
+      // Old style device number if it fits, otherwise new style
+      Some((major, minor, )) if major <= 0xff && minor <= 0xff => {
+        data[0..2].copy_from_slice(&((major << 8 | minor) as u16).to_be_bytes());
+      }
+      Some((major, minor, )) => {
+        data[0..2].copy_from_slice(&EfsInode::ODEV_NONE.to_be_bytes());
+        data[4..8].copy_from_slice(&(major << 18 | minor).to_be_bytes());
+      }
+      None => {
+        for (n, extent, ) in direct.iter().enumerate() {
+          data[n * Extent::SIZE..(n + 1) * Extent::SIZE].copy_from_slice(&extent.to_bytes().unwrap());
+        }
+      }
+    }
+
+    // Directories are linked from their parent, "." and each subdirectory's ".."
+    let nlink = match entry.contents {
+      Contents::Directory => 2 + self.entries.iter()
+        .filter(|(p, e, )| !p.is_empty() && split_path(p).0 == path && matches!(e.contents, Contents::Directory))
+        .count() as i16,
+      _ => 1
+    };
+
+    EfsInode {
+      di_mode: type_bits | entry.mode,
+      di_nlink: nlink,
+      di_uid: entry.uid,
+      di_gid: entry.gid,
+      di_size: size as i32,
+      di_atime: TIMESTAMP,
+      di_mtime: TIMESTAMP,
+      di_ctime: TIMESTAMP,
+      di_gen: 0,
+      di_numextents: num_extents as i16,
+      di_version: 0,
+      di_spare: 0,
+      data,
+    }
+  }
+}
+
+/// Allocator of data blocks, in order through the cylinder groups
+struct Allocator {
+  firstcg: usize,
+  cgfsize: usize,
+  cgisize: usize,
+  ncg: usize,
+  /// Current cylinder group
+  cg: usize,
+  /// Next block to allocate
+  next: usize,
+  /// Whether each block of the filesystem is allocated
+  used: Vec<bool>,
+}
+
+impl Allocator {
+  /// Allocate the next free block
+  fn alloc(&mut self) -> usize {
+    let block = self.next_block();
+    self.used[block] = true;
+    block
+  }
+
+  /// Leave the next free block free
+  fn skip(&mut self) {
+    self.next_block();
+  }
+
+  /// Take the next block, moving on to the next cylinder group's data blocks at the end of one
+  fn next_block(&mut self) -> usize {
+    if self.next >= self.firstcg + (self.cg + 1) * self.cgfsize {
+      self.cg += 1;
+      assert!(self.cg < self.ncg, "Filesystem layout has too few data blocks");
+      self.next = self.firstcg + self.cg * self.cgfsize + self.cgisize;
+    }
+    self.next += 1;
+    self.next - 1
+  }
+}
+
+/// Extents covering a list of blocks, in order through a file
+fn extents(blocks: &[usize]) -> Vec<Extent> {
+  let mut extents: Vec<Extent> = Vec::new();
+  for (n, block, ) in blocks.iter().enumerate() {
+    match extents.last_mut() {
+      Some(e) if e.ex_bn as usize + e.ex_length as usize == *block && (e.ex_length as usize) < MAX_EXTENT_LEN => {
+        e.ex_length += 1;
+      }
+      _ => extents.push(Extent { ex_bn: *block as u32, ex_length: 1, ex_offset: n as u32 })
+    }
+  }
+  extents
+}
+
+/// One directory block holding entries. Entries fill the block from its end, and their offsets
+/// (halved) fill the start of the space after the header.
+fn dir_block(entries: &[(&str, u32, )]) -> Vec<u8> {
+  let mut space = [0; DIR_SPACE_SZ];
+  let mut end = EFS_BLOCK_SZ;
+  for (slot, (name, ino, ), ) in entries.iter().enumerate() {
+    end -= (4 + 1 + name.len() + 1) & !1;
+    let at = end - (EFS_BLOCK_SZ - DIR_SPACE_SZ);
+    space[at..at + 4].copy_from_slice(&ino.to_be_bytes());
+    space[at + 4] = name.len() as u8;
+    space[at + 5..at + 5 + name.len()].copy_from_slice(name.as_bytes());
+    space[slot] = (end >> 1) as u8;
+  }
+
+  DirectoryBlock {
+    firstused: (end >> 1) as u8,
+    slots: entries.len() as u8,
+    space,
+  }.to_bytes().unwrap()
+}
+
+impl ImageBuilder {
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  /// Set the boot file named in the Volume Header
+  pub fn boot_file(&mut self, name: &str) -> &mut Self {
+    self.boot_file = Some(name.to_string());
+    self
+  }
+
+  /// Add a file to the volume directory (name up to 8 bytes)
+  pub fn volume_file(&mut self, name: &str, data: &[u8]) -> &mut Self {
+    self.volume_files.push((name.to_string(), data.to_vec(), ));
+    self
+  }
+
+  /// Add an EFS partition (partition 7)
+  pub fn efs(&mut self, efs: EfsBuilder) -> &mut Self {
+    self.efs = Some(efs);
+    self
+  }
+
+  /// Build the disk image
+  pub fn build(&self) -> Vec<u8> {
+    // Volume files follow the Volume Header (block 0) and a spare block
+    let mut next_block = 2;
+    let mut volume_dir = Vec::with_capacity(VolumeHeader::N_VOL_DIR);
+    for (name, data, ) in &self.volume_files {
+      let mut vd_name = [0; 8];
+      vd_name[..name.len().min(8)].copy_from_slice(&name.as_bytes()[..name.len().min(8)]);
+      volume_dir.push(VolumeDirectory { vd_name, vd_lbn: next_block as i32, vd_nbytes: data.len() as i32 });
+      next_block += blocks(data.len()).max(1);
+    }
+    volume_dir.resize_with(VolumeHeader::N_VOL_DIR, || VolumeDirectory { vd_name: [0; 8], vd_lbn: 0, vd_nbytes: 0 });
+
+    // The EFS partition starts on a multiple of 8 blocks after the volume header
+    let vh_blocks = (next_block + 7) / 8 * 8;
+    let efs = self.efs.as_ref().map(|efs| efs.build()).unwrap_or_default();
+    let total_blocks = vh_blocks + blocks(efs.len());
+
+    let mut partitions: Vec<PartitionTable> = (0..VolumeHeader::N_PAR_TAB)
+      .map(|_| PartitionTable { pt_nblks: 0, pt_firstlbn: 0, pt_type: PartitionType::VolumeHeader })
+      .collect();
+    partitions[VOLUME_HEADER_PARTITION] = PartitionTable { pt_nblks: vh_blocks as u32, pt_firstlbn: 0, pt_type: PartitionType::VolumeHeader };
+    if !efs.is_empty() {
+      partitions[EFS_PARTITION] = PartitionTable { pt_nblks: blocks(efs.len()) as u32, pt_firstlbn: vh_blocks as u32, pt_type: PartitionType::Efs };
+    }
+    partitions[ENTIRE_VOLUME_PARTITION] = PartitionTable { pt_nblks: total_blocks as u32, pt_firstlbn: 0, pt_type: PartitionType::EntireVolume };
+
+    let mut bootfile = [0; 16];
+    if let Some(name) = &self.boot_file {
+      bootfile[..name.len().min(16)].copy_from_slice(&name.as_bytes()[..name.len().min(16)]);
+    }
+    let mut vh = VolumeHeader {
+      vh_rootpt: 0,
+      vh_swappt: 1,
+      vh_bootfile: bootfile,
+      vh_dp: VolumeDeviceParameters {
+        dp_cylinders: 1,
+        dp_heads: 1,
+        dp_ctq_depth: 0,
+        dp_sect: 32,
+        dp_secbytes: EFS_BLOCK_SZ as u16,
+        dp_flags: 0,
+        dp_drivecap: total_blocks as u32,
+      },
+      vh_vd: volume_dir.try_into().unwrap(),
+      vh_pt: partitions.try_into().unwrap(),
+      vh_csum: 0,
+    };
+    // The checksum makes the sum of the header's words zero
+    let sum = vh.to_bytes().unwrap()
+      .chunks(4)
+      .fold(0i32, |sum, word| sum.wrapping_add(i32::from_be_bytes([word[0], word[1], word[2], word[3]])));
+    vh.vh_csum = sum.wrapping_neg();
+
+    let mut image = vec![0; vh_blocks * EFS_BLOCK_SZ];
+    let vh_bytes = vh.to_bytes().unwrap();
+    image[..vh_bytes.len()].copy_from_slice(&vh_bytes);
+    for ((_, data, ), vd, ) in self.volume_files.iter().zip(&vh.vh_vd) {
+      let start = vd.vd_lbn as usize * EFS_BLOCK_SZ;
+      image[start..start + data.len()].copy_from_slice(data);
+    }
+    image.extend(efs);
+    image
+  }
+}
+
+/// Image with a bit of everything: volume files; and an EFS partition with text files, a
+/// multi-block file, a fragmented file needing indirect extents, absolute and relative
+/// symbolic links, old and new style device numbers, a FIFO, a socket, a deep directory tree, a
+/// directory needing several blocks, an empty file and a file with an owner.
+pub fn sample() -> ImageBuilder {
+  let mut efs = EfsBuilder::new();
+  efs.file("/etc/passwd", b"root::0:0:Super-User:/:/bin/csh\nguest::998:998:Guest Account:/usr/people/guest:/bin/csh\n")
+    .file("/etc/motd", b"Welcome to IRIX\n")
+    .file("/unix", &pattern(3000, 1))
+    .fragmented_file("/usr/share/fragmented", &pattern(40 * EFS_BLOCK_SZ, 2))
+    .file("/usr/bin/ls", &pattern(700, 3))
+    .symlink("/bin", "usr/bin")
+    .symlink("/usr/lib/sh", "/usr/bin/ls")
+    .char_device("/dev/null", 1, 2)
+    .block_device("/dev/dsk/dks0d1s0", 128, 16)
+    .char_device("/dev/big", 300, 70000)
+    .fifo("/dev/fifo")
+    .socket("/tmp/.socket")
+    .file("/empty", b"")
+    .file("/usr/people/guest/.login", b"set path = (/usr/bin /bin)\n")
+    .owner("/usr/people/guest/.login", 998, 998)
+    .mode("/usr/people/guest/.login", 0o600)
+    .owner("/usr/people/guest", 998, 998);
+
+  let deep = (1..=20).map(|n| format!("d{}", n)).collect::<Vec<String>>().join("/");
+  efs.file(&format!("/deep/{}/leaf", deep), b"leaf\n");
+  for n in 0..100 {
+    efs.file(&format!("/many/file{:03}", n), format!("{}\n", n).as_bytes());
+  }
+
+  let mut image = ImageBuilder::new();
+  image.boot_file("/unix")
+    .volume_file("sgilabel", &pattern(600, 4))
+    .volume_file("ide", &pattern(1500, 5))
+    .efs(efs);
+  image
+}
+
+/// Image whose EFS has entries named to escape a destination they are extracted to: "../../escaped",
+/// "/etc/escaped", and "link/passwd" beside a symbolic link "link" to /etc
+pub fn hostile_names() -> ImageBuilder {
+  let mut efs = EfsBuilder::new();
+  efs.file("/dir/parent", b"parent\n")
+    .name_on_disk("/dir/parent", "../../escaped")
+    .file("/dir/absolute", b"absolute\n")
+    .name_on_disk("/dir/absolute", "/etc/escaped")
+    .symlink("/dir/link", "/etc")
+    .file("/dir/through", b"through\n")
+    .name_on_disk("/dir/through", "link/passwd");
+
+  let mut image = ImageBuilder::new();
+  image.efs(efs);
+  image
+}
+
+/// EnCase EWF (E01) container of media data padded to whole sectors, in one segment file with
+/// uncompressed chunks of 32 KiB: a volume section, a sectors section holding the chunks, a table
+/// section locating them and a done section
+pub fn ewf(media: &[u8]) -> Vec<u8> {
+  const DESCRIPTOR_SZ: usize = 76;
+  const SECTOR_SZ: usize = 512;
+  const SECTORS_PER_CHUNK: usize = 64;
+
+  let mut media = media.to_vec();
+  media.resize((media.len() + SECTOR_SZ - 1) / SECTOR_SZ * SECTOR_SZ, 0);
+  let chunks: Vec<&[u8]> = media.chunks(SECTORS_PER_CHUNK * SECTOR_SZ).collect();
+
+  // Each section's descriptor leads to the next, just past its data
+  let descriptor = |out: &mut Vec<u8>, section_type: &str, data_sz: usize| {
+    let mut d = [0u8; DESCRIPTOR_SZ];
+    d[..section_type.len()].copy_from_slice(section_type.as_bytes());
+    d[16..24].copy_from_slice(&((out.len() + DESCRIPTOR_SZ + data_sz) as u64).to_le_bytes());
+    d[24..32].copy_from_slice(&((DESCRIPTOR_SZ + data_sz) as u64).to_le_bytes());
+    out.extend(d);
+  };
+
+  let mut out = b"EVF\x09\x0d\x0a\xff\x00\x01\x01\x00\x00\x00".to_vec();
+  let mut volume = [0u8; 94];
+  volume[0] = 1;
+  volume[4..8].copy_from_slice(&(chunks.len() as u32).to_le_bytes());
+  volume[8..12].copy_from_slice(&(SECTORS_PER_CHUNK as u32).to_le_bytes());
+  volume[12..16].copy_from_slice(&(SECTOR_SZ as u32).to_le_bytes());
+  volume[16..24].copy_from_slice(&((media.len() / SECTOR_SZ) as u64).to_le_bytes());
+  descriptor(&mut out, "volume", volume.len());
+  out.extend(volume);
+
+  // Uncompressed chunks are each followed by their Adler-32 checksum
+  descriptor(&mut out, "sectors", media.len() + chunks.len() * 4);
+  let mut offsets = Vec::new();
+  for chunk in &chunks {
+    offsets.push(out.len() as u32);
+    out.extend_from_slice(chunk);
+    out.extend(adler32(chunk).to_le_bytes());
+  }
+
+  descriptor(&mut out, "table", 24 + offsets.len() * 4 + 4);
+  let mut header = [0u8; 24];
+  header[..4].copy_from_slice(&(offsets.len() as u32).to_le_bytes());
+  out.extend(header);
+  for offset in offsets {
+    out.extend(offset.to_le_bytes());
+  }
+  out.extend([0u8; 4]);
+
+  descriptor(&mut out, "done", 0);
+  out
+}
+
+/// Adler-32 checksum, as EWF keeps of uncompressed chunks
+fn adler32(data: &[u8]) -> u32 {
+  let (a, b, ) = data.iter().fold((1u32, 0u32, ), |(a, b, ), byte| {
+    let a = (a + *byte as u32) % 65_521;
+    (a, (b + a) % 65_521, )
+  });
+  (b << 16) | a
+}
+
+/// Image whose EFS has relative symbolic links which each stay within /d but chain to leave it:
+/// "/d/a" to "..", and "/d/b" to "a/../..", which once extracted to a directory of /d resolves to
+/// the directory's grandparent. "/d/c" to "e/f" and "/d/e/f" itself stay within it.
+pub fn escaping_links() -> ImageBuilder {
+  let mut efs = EfsBuilder::new();
+  efs.symlink("/d/a", "..")
+    .symlink("/d/b", "a/../..")
+    .symlink("/d/c", "e/f")
+    .file("/d/e/f", b"f\n");
+
+  let mut image = ImageBuilder::new();
+  image.efs(efs);
+  image
+}
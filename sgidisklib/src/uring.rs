@@ -0,0 +1,226 @@
+//! io_uring backed disk image reader (Linux), which submits the many small inode reads of a
+//! directory listing or walk as a single batch, rather than one synchronous read after another
+
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io;
+use std::io::{Read, Seek, SeekFrom};
+use std::os::unix::fs::FileExt;
+use std::os::unix::io::AsRawFd;
+
+use io_uring::{opcode, types, IoUring};
+
+use crate::SgidiskLibReadError;
+use crate::efs::{Efs, Inode};
+use crate::efs::dir::Directory;
+use crate::efs::walk::Walk;
+use crate::progress::{CancellationToken, Progress};
+
+/// Disk image file read with io_uring. Reads through Read and Seek are served from the ranges
+/// last prefetched where possible, and are otherwise synchronous.
+pub struct UringFile {
+  file: File,
+  ring: IoUring,
+  /// Maximum number of reads in flight at once
+  queue_depth: u32,
+  /// Ranges read by the last prefetch, by starting offset
+  prefetched: BTreeMap<u64, Vec<u8>>,
+  /// Number of the current call to read_batch, in the top half of each read's user data, so
+  /// completions left over from an earlier call are told apart
+  generation: u32,
+  /// Current offset
+  pos: u64,
+}
+
+impl UringFile {
+  /// Default number of reads in flight at once
+  pub const DEFAULT_QUEUE_DEPTH: u32 = 64;
+
+  /// Open a disk image, with the default queue depth
+  pub fn open(path: &str) -> io::Result<Self> {
+    UringFile::new(File::open(path)?, UringFile::DEFAULT_QUEUE_DEPTH)
+  }
+
+  /// Read a disk image file with up to `queue_depth` reads in flight at once
+  pub fn new(file: File, queue_depth: u32) -> io::Result<Self> {
+    let queue_depth = queue_depth.max(1);
+    Ok(UringFile {
+      file,
+      ring: IoUring::new(queue_depth)?,
+      queue_depth,
+      prefetched: BTreeMap::new(),
+      generation: 0,
+      pos: 0,
+    })
+  }
+
+  /// Get a reference to the disk image file
+  pub fn get_ref(&self) -> &File {
+    &self.file
+  }
+
+  /// Read each (offset, length) range, submitting up to the queue depth at once. A range past
+  /// the end of the file is read short.
+  ///
+  /// Every read submitted is waited for before returning, even after an error, as the kernel
+  /// writes into its buffer until it completes. Should waiting fail, the buffers are leaked
+  /// rather than freed under it.
+  pub fn read_batch(&mut self, ranges: &[(u64, usize, )]) -> io::Result<Vec<Vec<u8>>> {
+    self.generation = self.generation.wrapping_add(1);
+    let generation = self.generation;
+    let mut bufs: Vec<Vec<u8>> = ranges.iter().map(|(_, len, )| vec![0u8; *len]).collect();
+    let fd = types::Fd(self.file.as_raw_fd());
+    let mut first_error = None;
+    for batch_start in (0..ranges.len()).step_by(self.queue_depth as usize) {
+      let batch_end = (batch_start + self.queue_depth as usize).min(ranges.len());
+      let mut in_flight = 0;
+      for i in batch_start..batch_end {
+        let buf = &mut bufs[i];
+        let entry = opcode::Read::new(fd, buf.as_mut_ptr(), buf.len() as u32)
+          .offset(ranges[i].0)
+          .build()
+          .user_data((generation as u64) << 32 | i as u64);
+        // Safe because each buffer is neither moved nor freed until its completion is reaped
+        // below, or leaked if it can't be
+        if unsafe { self.ring.submission().push(&entry) }.is_err() {
+          first_error.get_or_insert_with(|| io::Error::new(io::ErrorKind::Other, "io_uring submission queue is full"));
+          break;
+        }
+        in_flight += 1;
+      }
+
+      while in_flight > 0 {
+        match self.ring.submit_and_wait(1) {
+          Ok(_) => {}
+          Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+          Err(e) => {
+            std::mem::forget(bufs);
+            return Err(e);
+          }
+        }
+        let completions: Vec<(u64, i32, )> = self.ring.completion()
+          .map(|cqe| (cqe.user_data(), cqe.result(), ))
+          .collect();
+        for (user_data, result, ) in completions {
+          let i = (user_data & 0xffff_ffff) as usize;
+          if (user_data >> 32) as u32 != generation || !(batch_start..batch_end).contains(&i) {
+            continue;
+          }
+          in_flight -= 1;
+          let read = match result {
+            result if result < 0 => Err(io::Error::from_raw_os_error(-result)),
+            result => self.complete_short(&mut bufs[i], ranges[i].0, result as usize)
+          };
+          if let Err(e) = read {
+            first_error.get_or_insert(e);
+          }
+        }
+      }
+      if first_error.is_some() {
+        break;
+      }
+    }
+    match first_error {
+      Some(e) => Err(e),
+      None => Ok(bufs)
+    }
+  }
+
+  /// Finish a read which io_uring returned short, synchronously, truncating the buffer if the
+  /// file ends first
+  fn complete_short(&self, buf: &mut Vec<u8>, offset: u64, mut filled: usize) -> io::Result<()> {
+    while filled < buf.len() {
+      match self.file.read_at(&mut buf[filled..], offset + filled as u64)? {
+        0 => break,
+        n => filled += n
+      }
+    }
+    buf.truncate(filled);
+    Ok(())
+  }
+
+  /// Read ranges in a batch, replacing those last prefetched, so that reads of them through Read
+  /// don't wait on the disk. Ranges which can't be read are left to fail when read.
+  pub fn prefetch(&mut self, ranges: &[(u64, usize, )]) {
+    self.prefetched.clear();
+    if let Ok(bufs) = self.read_batch(ranges) {
+      self.prefetched = ranges.iter().map(|(offset, _, )| *offset).zip(bufs).collect();
+    }
+  }
+
+  /// Prefetched bytes from the current offset, if any
+  fn prefetched_at_pos(&self) -> Option<&[u8]> {
+    let (start, data, ) = self.prefetched.range(..=self.pos).next_back()?;
+    let offset = (self.pos - start) as usize;
+    if offset < data.len() {
+      Some(&data[offset..])
+    } else {
+      None
+    }
+  }
+}
+
+impl Read for UringFile {
+  fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+    let n = match self.prefetched_at_pos() {
+      Some(data) => {
+        let n = buf.len().min(data.len());
+        buf[..n].copy_from_slice(&data[..n]);
+        n
+      }
+      None => self.file.read_at(buf, self.pos)?
+    };
+    self.pos += n as u64;
+    Ok(n)
+  }
+}
+
+impl Seek for UringFile {
+  fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+    self.pos = match pos {
+      SeekFrom::Start(offset) => offset,
+      _ => {
+        // Let the file resolve offsets relative to the current position or its end
+        self.file.seek(SeekFrom::Start(self.pos))?;
+        self.file.seek(pos)?
+      }
+    };
+    Ok(self.pos)
+  }
+}
+
+impl std::fmt::Debug for UringFile {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    f.debug_struct("UringFile")
+      .field("file", &self.file)
+      .field("queue_depth", &self.queue_depth)
+      .field("prefetched", &self.prefetched.len())
+      .field("pos", &self.pos)
+      .finish()
+  }
+}
+
+impl Efs {
+  /// Read several inodes, reading their on-disk inodes as one batch. Results are in the order of
+  /// the inode numbers given.
+  pub fn read_inodes(&self, file: &mut UringFile, inodes: &[u64]) -> Vec<Result<Inode, SgidiskLibReadError>> {
+    file.prefetch(&self.inode_ranges(inodes));
+    inodes.iter()
+      .map(|inode| self.read_inode(file, *inode))
+      .collect()
+  }
+
+  /// Walk the whole filesystem like `walk`, reading the inodes of each directory's entries as one
+  /// batch
+  pub fn walk_batched<F, P>(&self, file: &mut UringFile, visit: F, cancel: &CancellationToken, progress: P) -> Walk
+    where F: FnMut(&str, u64, &Inode), P: FnMut(Progress) {
+    self.walk_with(file, |file, efs, inode| Directory::read_dir_batched(file, efs, inode), |_, _| true, |_, _| true, visit, cancel, progress)
+  }
+}
+
+impl Directory {
+  /// Read a directory listing like `read_dir`, reading the inodes of its entries as one batch
+  pub fn read_dir_batched(file: &mut UringFile, efs: &Efs, inode: u64) -> Result<Directory, SgidiskLibReadError> {
+    Directory::read_dir_with(file, efs, inode, |file, inodes| file.prefetch(&efs.inode_ranges(inodes)))
+  }
+}
@@ -0,0 +1,115 @@
+//! Repacking the volume directory's files to the start of the volume header partition, closing
+//! the gaps left by deleted files so that the space can be used for new boot files
+
+use std::io::{Read, Seek, SeekFrom, Write};
+
+use deku::prelude::*;
+
+use crate::SgidiskLibReadError;
+
+use super::{raw, SgidiskVolume};
+
+/// Size of each read and write when moving a file
+const MOVE_CHUNK_SZ: usize = 64 * 1024;
+
+/// Volume file moved to close a gap before it
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct MovedFile {
+  /// Index of the file's volume directory entry
+  pub index: usize,
+  pub file_name: String,
+  /// Block the file started at
+  pub from_block: u64,
+  /// Block the file starts at once moved
+  pub to_block: u64,
+  /// File size (in bytes)
+  pub file_sz: u64,
+}
+
+/// Files moved by a compaction, and the space it frees
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct Compaction {
+  /// Files moved, in the order they are moved
+  pub moves: Vec<MovedFile>,
+  /// Number of blocks after the last file which become free
+  pub reclaimed_blocks: u64,
+}
+
+impl SgidiskVolume {
+  /// Plan moving the volume files towards the first of them, in order, so that each starts where
+  /// the one before ends. Files overlapping each other are refused, since moving one would
+  /// overwrite the other.
+  pub fn plan_compaction(&self) -> Result<Compaction, SgidiskLibReadError> {
+    let block_sz = self.block_sz();
+    let mut files: Vec<(usize, &super::VolumeFile, )> = self.files.iter().enumerate()
+      .filter(|(_, f, )| f.in_use() && f.file_sz > 0)
+      .collect();
+    files.sort_by_key(|(_, f, )| f.block_start);
+
+    let blocks = |f: &super::VolumeFile| (f.file_sz + block_sz - 1) / block_sz;
+    let mut compaction = Compaction::default();
+    let mut next = match files.first() {
+      Some((_, f, )) => f.block_start,
+      None => return Ok(compaction)
+    };
+    let mut prev: Option<&super::VolumeFile> = None;
+    for (index, file, ) in &files {
+      if let Some(prev) = prev {
+        if file.block_start < prev.block_start + blocks(prev) {
+          return Err(SgidiskLibReadError::Value(format!("Volume files {} and {} overlap",
+            prev.file_name.as_deref().unwrap_or(""), file.file_name.as_deref().unwrap_or(""))));
+        }
+      }
+      if file.block_start > next {
+        compaction.moves.push(MovedFile {
+          index: *index,
+          file_name: file.file_name.clone().unwrap_or_default(),
+          from_block: file.block_start,
+          to_block: next,
+          file_sz: file.file_sz,
+        });
+      }
+      next += blocks(file);
+      prev = Some(file);
+    }
+    compaction.reclaimed_blocks = prev.map_or(0, |last| last.block_start + blocks(last)) - next;
+    Ok(compaction)
+  }
+}
+
+/// Synchronously compact the volume files of a disk image in place: move each file's contents,
+/// then rewrite the Volume Header with their new locations and checksum. The header is only
+/// written once every file has been moved.
+pub fn compact<F: ?Sized>(file: &mut F) -> Result<Compaction, SgidiskLibReadError>
+  where F: Read + Write + Seek {
+  file.seek(SeekFrom::Start(0))?;
+  let mut vh = raw::VolumeHeader::read(file)?;
+  let volume = SgidiskVolume::try_from(&vh)?;
+  let compaction = volume.plan_compaction()?;
+  let block_sz = volume.block_sz();
+
+  // Files only ever move towards the start, so copying each from its start can't overwrite
+  // anything not yet copied
+  let mut buf = vec![0u8; MOVE_CHUNK_SZ];
+  for moved in &compaction.moves {
+    let mut offset = 0;
+    while offset < moved.file_sz {
+      let len = MOVE_CHUNK_SZ.min((moved.file_sz - offset) as usize);
+      file.seek(SeekFrom::Start(moved.from_block * block_sz + offset))?;
+      file.read_exact(&mut buf[..len])?;
+      file.seek(SeekFrom::Start(moved.to_block * block_sz + offset))?;
+      file.write_all(&buf[..len])?;
+      offset += len as u64;
+    }
+    vh.vh_vd[moved.index].vd_lbn = moved.to_block as i32;
+  }
+
+  if !compaction.moves.is_empty() {
+    let mut header = vh.to_bytes()?;
+    SgidiskVolume::fix_checksum(&mut header);
+    file.seek(SeekFrom::Start(0))?;
+    file.write_all(&header)?;
+    file.flush()?;
+  }
+  Ok(compaction)
+}
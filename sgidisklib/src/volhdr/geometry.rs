@@ -0,0 +1,98 @@
+//! Disk geometry from the Volume Header's device parameters: conversion between cylinder, head,
+//! sector addresses and logical block numbers, and the cylinder alignment partitions should have
+
+use std::fmt;
+use std::fmt::Formatter;
+
+use super::SgidiskVolume;
+
+/// Cylinders, heads and sectors per track, as recorded for backwards compatibility
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct Geometry {
+  pub cylinders: u16,
+  pub heads: u16,
+  /// Sectors per track
+  pub sectors: u16,
+}
+
+/// Cylinder, head, sector address. All three count from 0.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Ord, PartialOrd)]
+pub struct Chs {
+  pub cylinder: u64,
+  pub head: u64,
+  pub sector: u64,
+}
+
+impl Geometry {
+  /// Number of blocks (sectors) in each cylinder
+  pub fn blocks_per_cylinder(&self) -> u64 {
+    self.heads as u64 * self.sectors as u64
+  }
+
+  /// Number of blocks described by the geometry, which can be less than the disk's capacity
+  pub fn capacity_blocks(&self) -> u64 {
+    self.cylinders as u64 * self.blocks_per_cylinder()
+  }
+
+  /// Logical block number of a CHS address, or None if its head or sector is out of range
+  pub fn chs_to_lba(&self, chs: Chs) -> Option<u64> {
+    if chs.head >= self.heads as u64 || chs.sector >= self.sectors as u64 {
+      return None;
+    }
+    Some((chs.cylinder * self.heads as u64 + chs.head) * self.sectors as u64 + chs.sector)
+  }
+
+  /// CHS address of a logical block number. Blocks past the last cylinder get cylinders past it.
+  pub fn lba_to_chs(&self, lba: u64) -> Chs {
+    let sectors = self.sectors as u64;
+    let track = lba / sectors;
+    Chs {
+      cylinder: track / self.heads as u64,
+      head: track % self.heads as u64,
+      sector: lba % sectors,
+    }
+  }
+
+  /// Whether a block starts a cylinder
+  pub fn is_aligned(&self, lba: u64) -> bool {
+    lba % self.blocks_per_cylinder() == 0
+  }
+
+  /// First block of the cylinder holding a block
+  pub fn align_down(&self, lba: u64) -> u64 {
+    lba - lba % self.blocks_per_cylinder()
+  }
+
+  /// First block of the first cylinder starting at or after a block: where a partition placed
+  /// after it should start
+  pub fn align_up(&self, lba: u64) -> u64 {
+    self.align_down(lba + self.blocks_per_cylinder() - 1)
+  }
+}
+
+impl SgidiskVolume {
+  /// Geometry from the device parameters, or None if heads or sectors per track are left zero,
+  /// as they often are by later tools
+  pub fn geometry(&self) -> Option<Geometry> {
+    if self.compat_heads == 0 || self.compat_sect == 0 {
+      return None;
+    }
+    Some(Geometry {
+      cylinders: self.compat_cylinders,
+      heads: self.compat_heads,
+      sectors: self.compat_sect,
+    })
+  }
+}
+
+impl fmt::Display for Geometry {
+  fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+    write!(f, "{} cylinders, {} heads, {} sectors per track", self.cylinders, self.heads, self.sectors)
+  }
+}
+
+impl fmt::Display for Chs {
+  fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+    write!(f, "{}/{}/{}", self.cylinder, self.head, self.sector)
+  }
+}
@@ -1,13 +1,18 @@
 use std::io::Read;
 use std::fmt;
 use std::fmt::Formatter;
+use std::ops::Range;
 
 use deku::prelude::*;
 
 use crate::SgidiskLibReadError;
+use crate::efs::EFS_BLOCK_SZ;
 use crate::volhdr::raw::{VolumeDeviceParameters, VolumeDirectory};
 
-mod raw;
+pub(crate) mod raw;
+pub mod compact;
+pub mod geometry;
+pub mod size;
 
 /// SGI Disk Volume Header, located at the beginning of all IRIX disks
 #[derive(Debug)]
@@ -45,10 +50,13 @@ pub struct Partition {
   pub block_sz: u64,
   /// Partition offset from beginning of disk, in blocks
   pub block_start: u64,
+  /// Size of a block, in bytes (the Volume Header's sector size)
+  pub sector_sz: u64,
 }
 
 /// Partition Type ID for PartitionTable
 #[derive(Debug, Copy, Clone, Eq, PartialEq, DekuRead, DekuWrite)]
+#[cfg_attr(feature = "fuzzing", derive(arbitrary::Arbitrary))]
 #[deku(type = "i32", endian = "big")]
 pub enum PartitionType {
   /// Partition is volume header
@@ -83,6 +91,21 @@ pub enum PartitionType {
   Vxvm = 14,
 }
 
+/// Space for volume files in the volume header partition
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct VolumeDirectoryCapacity {
+  /// Number of volume directory entries in use
+  pub slots_used: usize,
+  /// Number of volume directory entries
+  pub slots_total: usize,
+  /// Size of the volume header partition, in blocks
+  pub partition_blocks: u64,
+  /// Blocks holding the Volume Header itself and the volume files
+  pub used_blocks: u64,
+  /// Runs of free blocks within the partition, in order
+  pub free_runs: Vec<Range<u64>>,
+}
+
 /// Volume directory file entry
 #[derive(Debug)]
 pub struct VolumeFile {
@@ -91,10 +114,90 @@ pub struct VolumeFile {
   pub block_start: u64,
   /// File size (in bytes)
   pub file_sz: u64,
+  /// Size of a block, in bytes (the Volume Header's sector size)
+  pub sector_sz: u64,
 }
 
 impl SgidiskVolume {
+  /// On-disk size of the Volume Header in bytes
+  pub const SIZE: usize = raw::VolumeHeader::SIZE;
+  /// Magic number at the start of the Volume Header
+  pub const MAGIC: [u8; 4] = [0x0B, 0xE5, 0xA9, 0x41];
+
+  /// Check the checksum of a raw Volume Header: the sum of its big endian 32 bit words is zero
+  pub fn checksum_ok(header: &[u8]) -> bool {
+    header.len() >= Self::SIZE && header[..Self::SIZE]
+      .chunks(4)
+      .fold(0i32, |sum, word| sum.wrapping_add(i32::from_be_bytes([word[0], word[1], word[2], word[3]]))) == 0
+  }
+
+  /// Rewrite the checksum of a raw Volume Header so that it is correct, leaving the rest as is
+  pub fn fix_checksum(header: &mut [u8]) {
+    let csum = raw::VolumeHeader::CSUM_OFFSET;
+    if header.len() < Self::SIZE {
+      return;
+    }
+    header[csum..csum + 4].fill(0);
+    let sum = header[..Self::SIZE]
+      .chunks(4)
+      .fold(0i32, |sum, word| sum.wrapping_add(i32::from_be_bytes([word[0], word[1], word[2], word[3]])));
+    header[csum..csum + 4].copy_from_slice(&sum.wrapping_neg().to_be_bytes());
+  }
+
+  /// Ranges of bytes of the disk image which the Volume Header defines: itself, the volume files
+  /// and the partitions in use, merged and in order. Anything else (gaps between partitions,
+  /// trailing padding) isn't part of the disk's logical contents.
+  pub fn defined_areas(&self) -> Vec<Range<u64>> {
+    let areas: Vec<Range<u64>> = std::iter::once(0..Self::SIZE as u64)
+      .chain(self.files.iter().filter(|f| f.in_use()).map(|f| f.byte_range()))
+      .chain(self.partitions.iter().filter(|p| p.in_use()).map(|p| p.byte_range()))
+      .collect();
+    crate::hash::merge_ranges(&areas)
+  }
+
+  /// Size of the blocks which partitions and volume files are addressed in: the sector size, or
+  /// 512 bytes if the Volume Header leaves it zero
+  pub fn block_sz(&self) -> u64 {
+    block_sz(self.sector_sz as u64)
+  }
+
+  /// Space used and free in the volume header partition and the volume directory, or None if
+  /// there is no volume header partition
+  pub fn directory_capacity(&self) -> Option<VolumeDirectoryCapacity> {
+    let block_sz = self.block_sz();
+    let partition = self.partitions.iter()
+      .find(|p| p.in_use() && p.partition_type == PartitionType::VolumeHeader)?;
+    let partition_range = partition.block_start..partition.block_start + partition.block_sz;
+
+    // Files are in blocks, and the header itself takes the first
+    let used: Vec<Range<u64>> = std::iter::once(0..1)
+      .chain(self.files.iter()
+        .filter(|f| f.in_use())
+        .map(|f| f.block_start..f.block_start + (f.file_sz + block_sz - 1) / block_sz))
+      .map(|r| r.start.max(partition_range.start)..r.end.min(partition_range.end))
+      .collect();
+    let used = crate::hash::merge_ranges(&used);
+
+    let mut free_runs = Vec::new();
+    let mut next = partition_range.start;
+    for range in used.iter().chain(std::iter::once(&(partition_range.end..partition_range.end))) {
+      if range.start > next {
+        free_runs.push(next..range.start);
+      }
+      next = next.max(range.end);
+    }
+
+    Some(VolumeDirectoryCapacity {
+      slots_used: self.files.iter().filter(|f| f.in_use()).count(),
+      slots_total: raw::VolumeHeader::N_VOL_DIR,
+      partition_blocks: partition.block_sz,
+      used_blocks: used.iter().map(|r| r.end - r.start).sum(),
+      free_runs,
+    })
+  }
+
   /// Synchronously read / deserialize a SgidiskVolume
+  #[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", skip_all, err))]
   pub fn read<R: ?Sized>(reader: &mut R) -> Result<Self, SgidiskLibReadError>
     where R: Read {
     Self::try_from(&raw::VolumeHeader::read(reader)?)
@@ -107,6 +210,21 @@ impl Partition {
   pub fn in_use(&self) -> bool {
     self.block_sz > 0
   }
+
+  /// Offset of the first byte of the partition in the disk image
+  pub fn byte_start(&self) -> u64 {
+    self.block_start * self.sector_sz
+  }
+
+  /// Size of the partition in bytes
+  pub fn byte_len(&self) -> u64 {
+    self.block_sz * self.sector_sz
+  }
+
+  /// Range of bytes of the disk image holding the partition
+  pub fn byte_range(&self) -> Range<u64> {
+    self.byte_start()..self.byte_start() + self.byte_len()
+  }
 }
 
 impl TryFrom<&raw::VolumeHeader> for SgidiskVolume {
@@ -124,18 +242,19 @@ impl TryFrom<&raw::VolumeHeader> for SgidiskVolume {
       _ => return Err(SgidiskLibReadError::Value(format!("Invalid swap partition index: {}", vh.vh_swappt)))
     };
 
+    let sector_sz = block_sz(vh.vh_dp.dp_secbytes as u64);
     let ctq_enabled = vh.vh_dp.dp_flags & VolumeDeviceParameters::DP_CTQ_EN == VolumeDeviceParameters::DP_CTQ_EN;
 
     // Convert partition table
     let partitions = vh.vh_pt.iter()
-      .map(|pt| Partition::from(pt))
+      .map(|pt| Partition::from((pt, sector_sz, )))
       .collect();
 
     let boot_file = crate::bytes_to_string(&vh.vh_bootfile)?;
 
     // Convert volume directory entries
     let files = vh.vh_vd.iter()
-      .map(|vd| VolumeFile::try_from(vd))
+      .map(|vd| VolumeFile::try_from((vd, sector_sz, )))
       .collect::<Result<Vec<VolumeFile>, SgidiskLibReadError>>()?;
 
     Ok(Self {
@@ -155,13 +274,15 @@ impl TryFrom<&raw::VolumeHeader> for SgidiskVolume {
   }
 }
 
-impl From<&raw::PartitionTable> for Partition {
-  /// Convert from raw PartitionTable to Partition struct
-  fn from(pt: &raw::PartitionTable) -> Self {
+impl From<(&raw::PartitionTable, u64, )> for Partition {
+  /// Convert from raw PartitionTable, with blocks of a sector size, to Partition struct
+  fn from(value: (&raw::PartitionTable, u64, )) -> Self {
+    let (pt, sector_sz, ) = value;
     Self {
       partition_type: pt.pt_type,
       block_sz: pt.pt_nblks as u64,
       block_start: pt.pt_firstlbn as u64,
+      sector_sz,
     }
   }
 }
@@ -172,17 +293,41 @@ impl fmt::Display for PartitionType {
   }
 }
 
+impl VolumeDirectoryCapacity {
+  /// Number of free blocks, whether or not they are contiguous
+  pub fn free_blocks(&self) -> u64 {
+    self.free_runs.iter().map(|r| r.end - r.start).sum()
+  }
+
+  /// Number of blocks in the largest run of free blocks: the largest file which fits without
+  /// compacting the volume files
+  pub fn largest_free_blocks(&self) -> u64 {
+    self.free_runs.iter().map(|r| r.end - r.start).max().unwrap_or(0)
+  }
+}
+
 impl VolumeFile {
   pub fn in_use(&self) -> bool {
     self.file_name.is_some()
   }
+
+  /// Offset of the first byte of the file in the disk image
+  pub fn byte_start(&self) -> u64 {
+    self.block_start * self.sector_sz
+  }
+
+  /// Range of bytes of the disk image holding the file
+  pub fn byte_range(&self) -> Range<u64> {
+    self.byte_start()..self.byte_start() + self.file_sz
+  }
 }
 
-impl TryFrom<&raw::VolumeDirectory> for VolumeFile {
+impl TryFrom<(&raw::VolumeDirectory, u64, )> for VolumeFile {
   type Error = SgidiskLibReadError;
 
-  /// Convert from raw VolumeDirectory to VolumeFile struct
-  fn try_from(vd: &VolumeDirectory) -> Result<Self, Self::Error> {
+  /// Convert from raw VolumeDirectory, with blocks of a sector size, to VolumeFile struct
+  fn try_from(value: (&VolumeDirectory, u64, )) -> Result<Self, Self::Error> {
+    let (vd, sector_sz, ) = value;
     let file_name = crate::bytes_to_string(&vd.vd_name)?;
     let block_start = if vd.vd_lbn == -1 {
       // Special case, older EFS systems seem to fill in w/ -1 instead of 0?
@@ -202,6 +347,15 @@ impl TryFrom<&raw::VolumeDirectory> for VolumeFile {
       file_name,
       block_start,
       file_sz,
+      sector_sz,
     })
   }
-}
\ No newline at end of file
+}
+
+/// Size of the blocks of a Volume Header with a sector size, which older tools may leave zero
+fn block_sz(sector_sz: u64) -> u64 {
+  match sector_sz {
+    0 => EFS_BLOCK_SZ as u64,
+    sector_sz => sector_sz
+  }
+}
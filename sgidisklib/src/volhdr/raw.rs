@@ -35,6 +35,7 @@ use crate::SgidiskLibReadError;
 /// The amount of space allocated to the volume header, replacement blocks
 /// and other tables is user defined when the device is formatted.
 #[derive(Debug, DekuRead, DekuWrite)]
+#[cfg_attr(feature = "fuzzing", derive(arbitrary::Arbitrary))]
 #[deku(magic = b"\x0B\xE5\xA9\x41")]
 pub(crate) struct VolumeHeader {
   /// Root partition number
@@ -58,7 +59,9 @@ pub(crate) struct VolumeHeader {
 
 impl VolumeHeader {
   /// On-disk size of VolumeHeader in bytes
-  const SIZE: usize = 512;
+  pub(crate) const SIZE: usize = 512;
+  /// Offset of vh_csum, after which there are only 4 bytes of padding
+  pub(crate) const CSUM_OFFSET: usize = 504;
 
   /// 16 unix partitions
   pub(crate) const N_PAR_TAB: usize = 16;
@@ -72,6 +75,7 @@ impl VolumeHeader {
 /// logical block numbers to physical device addresses alignment of fields
 /// has to remain as it used to be, so old drive headers still match.
 #[derive(Debug, DekuRead, DekuWrite)]
+#[cfg_attr(feature = "fuzzing", derive(arbitrary::Arbitrary))]
 #[deku(endian = "big")]
 pub(crate) struct VolumeDeviceParameters {
   #[deku(pad_bytes_before = "4")]
@@ -110,6 +114,7 @@ impl VolumeDeviceParameters {
 /// Boot blocks, bad sector tables, and the error summary table, are located
 /// via the volume_directory.
 #[derive(Debug, DekuRead, DekuWrite)]
+#[cfg_attr(feature = "fuzzing", derive(arbitrary::Arbitrary))]
 #[deku(endian = "big")]
 pub(crate) struct VolumeDirectory {
   /// Name
@@ -131,6 +136,7 @@ impl VolumeDirectory {
 ///
 /// NOTE: pt_firstlbn SHOULD BE CYLINDER ALIGNED
 #[derive(Debug, DekuRead, DekuWrite)]
+#[cfg_attr(feature = "fuzzing", derive(arbitrary::Arbitrary))]
 pub(crate) struct PartitionTable {
   /// Number of logical blocks in partition
   #[deku(endian = "big")]
@@ -144,7 +150,7 @@ pub(crate) struct PartitionTable {
 
 impl VolumeHeader {
   /// Parse byte slice into VolumeHeader struct
-  fn parse_volume_header(buf: &[u8]) -> Result<Self, SgidiskLibReadError> {
+  pub(crate) fn parse_volume_header(buf: &[u8]) -> Result<Self, SgidiskLibReadError> {
     let (_, vh, ) = Self::from_bytes((buf, 0, ))?;
     Ok(vh)
   }
@@ -153,8 +159,15 @@ impl VolumeHeader {
   pub(crate) fn read<R: ?Sized>(reader: &mut R) -> Result<Self, SgidiskLibReadError>
     where R: Read
   {
-    let mut buf = vec![0; Self::SIZE];
+    let mut buf = [0u8; Self::SIZE];
     reader.read_exact(&mut buf)?;
+    if buf[0..4] != super::SgidiskVolume::MAGIC {
+      return Err(SgidiskLibReadError::BadMagic {
+        expected: u32::from_be_bytes(super::SgidiskVolume::MAGIC),
+        found: u32::from_be_bytes([buf[0], buf[1], buf[2], buf[3]]),
+        offset: 0,
+      });
+    }
     Self::parse_volume_header(&buf)
   }
 }
\ No newline at end of file
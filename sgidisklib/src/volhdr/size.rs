@@ -0,0 +1,81 @@
+//! Comparison of the volume size the Volume Header describes, by its recorded drive capacity and
+//! partitions, with the length of the disk image actually holding it
+
+use super::{PartitionType, SgidiskVolume};
+
+/// How the disk image's length compares with the volume the label describes
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum SizeVerdict {
+  /// The image ends exactly where the volume does
+  Exact,
+  /// The image ends before the volume does: usually an incomplete dump
+  Truncated { missing_bytes: u64 },
+  /// The image runs past the end of the volume: padding, or a dump of a larger disk
+  Padded { extra_bytes: u64 },
+  /// The label gives no volume size to compare: no entire volume partition, drive capacity or
+  /// partitions
+  Unknown,
+}
+
+/// Sizes described by the Volume Header, against the disk image's length
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct SizeCheck {
+  /// Length of the disk image, in bytes
+  pub image_bytes: u64,
+  /// Drive capacity in the device parameters, in bytes, or None if left zero
+  pub drivecap_bytes: Option<u64>,
+  /// End of the entire volume partition, in bytes, if there is one
+  pub entire_volume_bytes: Option<u64>,
+  /// End of the last partition of any type, in bytes
+  pub partitions_end_bytes: u64,
+  /// Size of the volume as the label describes it: the entire volume partition, or failing that
+  /// the drive capacity, or failing that the end of the last partition
+  pub volume_bytes: Option<u64>,
+  /// Image length against the volume size
+  pub verdict: SizeVerdict,
+  /// Bytes by which the partitions run past the recorded drive capacity, if they do: the label
+  /// was written for a larger disk, or the capacity is wrong
+  pub oversized_label: Option<u64>,
+}
+
+impl SgidiskVolume {
+  /// Compare the drive capacity, entire volume partition and other partitions with each other and
+  /// with the length of the disk image
+  pub fn check_size(&self, image_bytes: u64) -> SizeCheck {
+    let drivecap_bytes = match self.compat_drivecap {
+      0 => None,
+      drivecap => Some(drivecap as u64 * self.block_sz())
+    };
+    let entire_volume_bytes = self.partitions.iter()
+      .find(|p| p.in_use() && p.partition_type == PartitionType::EntireVolume)
+      .map(|p| p.byte_range().end);
+    let partitions_end_bytes = self.partitions.iter()
+      .filter(|p| p.in_use())
+      .map(|p| p.byte_range().end)
+      .max()
+      .unwrap_or(0);
+    let volume_bytes = entire_volume_bytes
+      .or(drivecap_bytes)
+      .or(Some(partitions_end_bytes).filter(|&end| end > 0));
+
+    let verdict = match volume_bytes {
+      None => SizeVerdict::Unknown,
+      Some(volume) if image_bytes < volume => SizeVerdict::Truncated { missing_bytes: volume - image_bytes },
+      Some(volume) if image_bytes > volume => SizeVerdict::Padded { extra_bytes: image_bytes - volume },
+      Some(_) => SizeVerdict::Exact
+    };
+    let oversized_label = drivecap_bytes
+      .filter(|&drivecap| partitions_end_bytes > drivecap)
+      .map(|drivecap| partitions_end_bytes - drivecap);
+
+    SizeCheck {
+      image_bytes,
+      drivecap_bytes,
+      entire_volume_bytes,
+      partitions_end_bytes,
+      volume_bytes,
+      verdict,
+      oversized_label,
+    }
+  }
+}
@@ -0,0 +1,1194 @@
+use std::io::{Cursor, Read, Seek, SeekFrom, Write};
+
+use sgidisklib::content::{self, ContentHandler, Identified};
+use sgidisklib::efs::{Efs, EFS_BLOCK_SZ, InodeType, MAX_LINK_SZ, normalize_path, SuperblockMagic};
+use sgidisklib::entropy::{EntropyClass, EntropyScanner};
+use sgidisklib::efs::anomaly::{TimestampAnomaly, TimestampField};
+use sgidisklib::efs::consistency::{DuplicateName, LinkCountMismatch};
+use sgidisklib::efs::dir::Directory;
+use sgidisklib::efs::find::{FindOptions, Glob};
+use sgidisklib::efs::magic::ContentType;
+use sgidisklib::efs::release::{find_release, MediaType};
+use sgidisklib::testgen::{self, EfsBuilder, ImageBuilder};
+use sgidisklib::volhdr::{PartitionType, SgidiskVolume};
+use sgidisklib::volhdr::compact::compact;
+use sgidisklib::volhdr::geometry::{Chs, Geometry};
+use sgidisklib::volhdr::size::SizeVerdict;
+use sgidisklib::{copy, SgidiskLibReadError};
+use sgidisklib::layout::{Region, RegionKind};
+use sgidisklib::limits::Limits;
+use sgidisklib::metrics::{CountingReader, IoStats};
+use sgidisklib::pc::{PcPartitionTable, PcScheme};
+use sgidisklib::progress::{CancellationToken, Progress};
+use sgidisklib::rescue::{RescueOptions, RescueReader};
+use sgidisklib::strings::{FoundString, StringScanner};
+
+/// Open the sample image's Volume Header and EFS partition
+fn open_sample() -> (Cursor<Vec<u8>>, SgidiskVolume, Efs, ) {
+  open(&testgen::sample())
+}
+
+fn open(image: &ImageBuilder) -> (Cursor<Vec<u8>>, SgidiskVolume, Efs, ) {
+  let mut reader = Cursor::new(image.build());
+  let volume = SgidiskVolume::read(&mut reader).unwrap();
+  let partition = &volume.partitions[testgen::EFS_PARTITION];
+  let efs = Efs::read(&mut reader, volume.sector_sz as u64, partition.byte_start()).unwrap();
+  (reader, volume, efs, )
+}
+
+/// Contents of the file at a path
+fn read(reader: &mut Cursor<Vec<u8>>, efs: &Efs, path: &str) -> Vec<u8> {
+  let (_, inode, ) = efs.lookup_path(reader, path).unwrap();
+  let mut contents = Vec::new();
+  efs.read_file(reader, &inode, &mut contents).unwrap();
+  contents
+}
+
+#[test]
+fn volume_header() {
+  let (reader, volume, _, ) = open_sample();
+
+  assert_eq!(volume.sector_sz, EFS_BLOCK_SZ);
+  assert_eq!(volume.boot_file.as_deref(), Some("/unix"));
+
+  let efs = &volume.partitions[testgen::EFS_PARTITION];
+  assert_eq!(efs.partition_type, PartitionType::Efs);
+  let entire = &volume.partitions[testgen::ENTIRE_VOLUME_PARTITION];
+  assert_eq!(entire.partition_type, PartitionType::EntireVolume);
+  assert_eq!(entire.byte_len(), reader.get_ref().len() as u64);
+  assert_eq!(efs.byte_range().end, entire.byte_range().end);
+  assert_eq!(volume.partitions.iter().filter(|p| p.in_use()).count(), 3);
+}
+
+#[test]
+fn volume_header_sector_size() {
+  let image = testgen::sample().build();
+  let (_, sample, _, ) = open_sample();
+  let efs = &sample.partitions[testgen::EFS_PARTITION];
+  let file = &sample.files[0];
+
+  // Partitions and volume files are addressed in the Volume Header's sector size (dp_secbytes)
+  let mut cd = image.clone();
+  cd[40..42].copy_from_slice(&2048u16.to_be_bytes());
+  let volume = SgidiskVolume::read(&mut Cursor::new(cd)).unwrap();
+  assert_eq!(volume.block_sz(), 2048);
+  assert_eq!(volume.partitions[testgen::EFS_PARTITION].byte_start(), efs.block_start * 2048);
+  assert_eq!(volume.partitions[testgen::EFS_PARTITION].byte_len(), efs.block_sz * 2048);
+  assert_eq!(volume.files[0].byte_range(), file.block_start * 2048..file.block_start * 2048 + file.file_sz);
+
+  // A zero sector size falls back to 512 byte blocks
+  let mut unset = image;
+  unset[40..42].copy_from_slice(&[0, 0]);
+  let volume = SgidiskVolume::read(&mut Cursor::new(unset)).unwrap();
+  assert_eq!(volume.sector_sz, 0);
+  assert_eq!(volume.partitions[testgen::EFS_PARTITION].byte_range(), efs.byte_range());
+}
+
+#[test]
+fn volume_header_checksum() {
+  let image = testgen::sample().build();
+  let sum = image[..512].chunks(4)
+    .fold(0i32, |sum, word| sum.wrapping_add(i32::from_be_bytes([word[0], word[1], word[2], word[3]])));
+  assert_eq!(sum, 0);
+  assert!(SgidiskVolume::checksum_ok(&image));
+
+  let mut corrupt = image.clone();
+  corrupt[20] ^= 0x01;
+  assert!(!SgidiskVolume::checksum_ok(&corrupt));
+
+  SgidiskVolume::fix_checksum(&mut corrupt);
+  assert!(SgidiskVolume::checksum_ok(&corrupt));
+  assert_eq!(corrupt[20], image[20] ^ 0x01);
+}
+
+#[test]
+fn superblock_checksum() {
+  let (mut reader, _, efs, ) = open_sample();
+  assert!(efs.superblock_checksum_ok(&mut reader).unwrap());
+
+  // Corrupt the filesystem pack name
+  let name_offset = (efs.partition_start + EFS_BLOCK_SZ as u64) as usize + 40;
+  reader.get_mut()[name_offset] ^= 0x01;
+  assert!(!efs.superblock_checksum_ok(&mut reader).unwrap());
+}
+
+#[test]
+fn efs_geometry() {
+  let (_, volume, efs, ) = open_sample();
+  assert_eq!((efs.track_sectors, efs.heads, ), (32, 1, ));
+  assert_eq!((volume.compat_sect, volume.compat_heads, ), (efs.track_sectors, efs.heads, ));
+  assert!(efs.size <= volume.partitions[testgen::EFS_PARTITION].byte_len());
+}
+
+#[test]
+fn efs_bitmap() {
+  let (mut reader, _, efs, ) = open_sample();
+  let check = efs.check_bitmap(&mut reader).unwrap();
+  assert!(check.free_blocks > 0 && check.free_blocks < check.data_blocks);
+  assert_eq!(check.free_but_referenced.count, 0);
+  assert_eq!(check.used_but_unreferenced.count, 0);
+  assert!(check.unreadable_inodes.is_empty());
+
+  // Mark a block of /etc/motd free, and a free block used
+  let (_, motd, ) = efs.lookup_path(&mut reader, "/etc/motd").unwrap();
+  let motd_block = motd.block_ranges().next().unwrap().start;
+  let bitmap = efs.read_bitmap(&mut reader).unwrap();
+  let free_block = efs.data_block_ranges().flatten().find(|b| bitmap.is_free(*b)).unwrap();
+  let bitmap_start = (efs.partition_start + efs.bitmap_block * EFS_BLOCK_SZ as u64) as usize;
+  let image = reader.get_mut();
+  image[bitmap_start + (motd_block / 8) as usize] |= 1 << (motd_block % 8);
+  image[bitmap_start + (free_block / 8) as usize] &= !(1 << (free_block % 8));
+
+  let check = efs.check_bitmap(&mut reader).unwrap();
+  assert_eq!((check.free_but_referenced.count, &check.free_but_referenced.examples[..], ), (1, &[motd_block][..], ));
+  assert_eq!((check.used_but_unreferenced.count, &check.used_but_unreferenced.examples[..], ), (1, &[free_block][..], ));
+}
+
+#[test]
+fn strings_in_files_and_free_space() {
+  let mut scanner = StringScanner::new(0, 4);
+  scanner.write_all(b"\x00\x01MIPS\x00ab\x00\x7fIRIX 6.5\tok").unwrap();
+  let found = scanner.finish();
+  assert_eq!(found, vec![
+    FoundString { offset: 2, text: "MIPS".to_string() },
+    FoundString { offset: 11, text: "IRIX 6.5\tok".to_string() },
+  ]);
+
+  // Text left in a free block is found at its offset in the image
+  let (mut reader, _, efs, ) = open_sample();
+  let bitmap = efs.read_bitmap(&mut reader).unwrap();
+  let free = efs.free_byte_ranges(&bitmap);
+  assert!(!free.is_empty());
+  let start = free[0].start as usize;
+  reader.get_mut()[start + 100..start + 113].copy_from_slice(b"deleted notes");
+  let mut scanner = StringScanner::new(0, 8);
+  for range in &free {
+    scanner.seek(range.start);
+    scanner.write_all(&reader.get_ref()[range.start as usize..range.end as usize]).unwrap();
+  }
+  assert!(scanner.finish().contains(&FoundString { offset: start as u64 + 100, text: "deleted notes".to_string() }));
+}
+
+#[test]
+fn entropy_windows() {
+  let mut scanner = EntropyScanner::new(1024, 256);
+  scanner.write_all(&[0; 256]).unwrap();
+  scanner.write_all(&b"the quick brown fox jumps over the lazy dog ".repeat(6)[..256]).unwrap();
+  scanner.write_all(&(0..=255).collect::<Vec<u8>>()).unwrap();
+  scanner.write_all(&[1, 2, 3]).unwrap();
+  let windows = scanner.finish();
+
+  let summary: Vec<(u64, u64, EntropyClass, )> = windows.iter().map(|w| (w.offset, w.len, w.class(), )).collect();
+  assert_eq!(summary, vec![
+    (1024, 256, EntropyClass::Blank, ),
+    (1280, 256, EntropyClass::Structured, ),
+    (1536, 256, EntropyClass::Compressed, ),
+    (1792, 3, EntropyClass::Structured, ),
+  ]);
+  assert_eq!(windows[0].entropy, 0.0);
+  assert!((windows[2].entropy - 8.0).abs() < 1e-9);
+}
+
+#[test]
+fn layout_regions() {
+  let (mut reader, volume, efs, ) = open_sample();
+  let len = reader.get_ref().len() as u64;
+  let regions = volume.regions(len);
+  assert_eq!(regions[0].kind, RegionKind::VolumeHeader);
+  assert_eq!(regions.iter().filter(|r| r.kind == RegionKind::VolumeFile).count(), 2);
+  assert!(!regions.iter().any(|r| matches!(r.kind, RegionKind::Unpartitioned | RegionKind::Overlap)));
+
+  // Data blocks in use and free add up to every data block, in order
+  let bitmap = efs.read_bitmap(&mut reader).unwrap();
+  let efs_regions = efs.regions(Some(&bitmap), testgen::EFS_PARTITION);
+  let data: Vec<&Region> = efs_regions.iter().filter(|r| matches!(r.kind, RegionKind::EfsAllocated | RegionKind::EfsFree)).collect();
+  let data_bytes: u64 = data.iter().map(|r| r.range.end - r.range.start).sum();
+  let data_blocks: u64 = efs.data_block_ranges().map(|r| r.end - r.start).sum();
+  assert_eq!(data_bytes, data_blocks * EFS_BLOCK_SZ as u64);
+  // Neighbouring runs of the same kind are merged
+  assert!(data.windows(2).all(|w| w[0].range.end < w[1].range.start || (w[0].range.end == w[1].range.start && w[0].kind != w[1].kind)));
+  let free: u64 = data.iter().filter(|r| r.kind == RegionKind::EfsFree).map(|r| r.range.end - r.range.start).sum();
+  assert_eq!(free, efs.free_byte_ranges(&bitmap).iter().map(|r| r.end - r.start).sum());
+
+  // An image longer than its partitions ends in an unpartitioned gap
+  let unpartitioned = volume.regions(len + 4096);
+  assert_eq!(unpartitioned.iter().find(|r| r.kind == RegionKind::Unpartitioned).map(|r| r.range.clone()), Some(len..len + 4096));
+}
+
+#[test]
+fn volume_files() {
+  let (reader, volume, _, ) = open_sample();
+  let files: Vec<_> = volume.files.iter().filter(|f| f.in_use()).collect();
+  assert_eq!(files.len(), 2);
+
+  assert_eq!(files[0].file_name.as_deref(), Some("sgilabel"));
+  let start = files[0].block_start as usize * EFS_BLOCK_SZ;
+  assert_eq!(&reader.get_ref()[start..start + files[0].file_sz as usize], &testgen::pattern(600, 4)[..]);
+
+  assert_eq!(files[1].file_name.as_deref(), Some("ide"));
+  let start = files[1].block_start as usize * EFS_BLOCK_SZ;
+  assert_eq!(&reader.get_ref()[start..start + files[1].file_sz as usize], &testgen::pattern(1500, 5)[..]);
+}
+
+#[test]
+fn compact_volume_files() {
+  let mut image = ImageBuilder::new();
+  image.volume_file("a", &testgen::pattern(600, 6))
+    .volume_file("b", &testgen::pattern(1500, 7))
+    .volume_file("c", &testgen::pattern(700, 8));
+  let mut image = image.build();
+
+  // Delete "b", the second volume directory entry, leaving a gap of 3 blocks
+  image[88..104].fill(0);
+  image[504..508].fill(0);
+  let sum = image[..SgidiskVolume::SIZE].chunks(4)
+    .fold(0i32, |sum, word| sum.wrapping_add(i32::from_be_bytes([word[0], word[1], word[2], word[3]])));
+  image[504..508].copy_from_slice(&sum.wrapping_neg().to_be_bytes());
+  let volume = SgidiskVolume::read(&mut Cursor::new(&image)).unwrap();
+  let planned = volume.plan_compaction().unwrap();
+  assert_eq!(planned.reclaimed_blocks, 3);
+
+  let mut reader = Cursor::new(image);
+  let compaction = compact(&mut reader).unwrap();
+  assert_eq!(compaction, planned);
+  assert_eq!(compaction.moves.len(), 1);
+  assert_eq!((compaction.moves[0].file_name.as_str(), compaction.moves[0].from_block, compaction.moves[0].to_block, ), ("c", 7, 4, ));
+
+  let image = reader.into_inner();
+  assert!(SgidiskVolume::checksum_ok(&image));
+  let volume = SgidiskVolume::read(&mut Cursor::new(&image)).unwrap();
+  let c = volume.files.iter().find(|f| f.file_name.as_deref() == Some("c")).unwrap();
+  assert_eq!(c.block_start, 4);
+  assert_eq!(&image[c.byte_range().start as usize..c.byte_range().end as usize], &testgen::pattern(700, 8)[..]);
+
+  // Already packed
+  assert!(volume.plan_compaction().unwrap().moves.is_empty());
+}
+
+#[test]
+fn geometry_conversions() {
+  let geometry = Geometry { cylinders: 100, heads: 4, sectors: 32 };
+  assert_eq!(geometry.blocks_per_cylinder(), 128);
+  let chs = Chs { cylinder: 2, head: 3, sector: 5 };
+  assert_eq!(geometry.chs_to_lba(chs), Some(2 * 128 + 3 * 32 + 5));
+  assert_eq!(geometry.lba_to_chs(2 * 128 + 3 * 32 + 5), chs);
+  assert_eq!(geometry.chs_to_lba(Chs { cylinder: 0, head: 4, sector: 0 }), None);
+  assert!(geometry.is_aligned(256) && !geometry.is_aligned(257));
+  assert_eq!((geometry.align_down(300), geometry.align_up(300), geometry.align_up(256), ), (256, 384, 256, ));
+
+  // The sample records 1 head and 32 sectors per track
+  let (_, volume, _, ) = open_sample();
+  assert_eq!(volume.geometry().unwrap().blocks_per_cylinder(), 32);
+}
+
+#[test]
+fn image_size_check() {
+  let image = testgen::sample().build();
+  let volume = SgidiskVolume::read(&mut Cursor::new(&image)).unwrap();
+  let len = image.len() as u64;
+  let check = volume.check_size(len);
+  assert_eq!((check.volume_bytes, check.drivecap_bytes, check.entire_volume_bytes, ), (Some(len), Some(len), Some(len), ));
+  assert_eq!((check.verdict, check.oversized_label, ), (SizeVerdict::Exact, None, ));
+  assert_eq!(volume.check_size(len - 512).verdict, SizeVerdict::Truncated { missing_bytes: 512 });
+  assert_eq!(volume.check_size(len + 1024).verdict, SizeVerdict::Padded { extra_bytes: 1024 });
+}
+
+#[test]
+fn volume_directory_capacity() {
+  let mut image = ImageBuilder::new();
+  image.volume_file("a", &testgen::pattern(600, 6))
+    .volume_file("b", &testgen::pattern(1500, 7));
+  let volume = SgidiskVolume::read(&mut Cursor::new(image.build())).unwrap();
+
+  // Header in block 0, "a" in 2..4 and "b" in 4..7, in a partition of 8 blocks
+  let capacity = volume.directory_capacity().unwrap();
+  assert_eq!((capacity.slots_used, capacity.slots_total, ), (2, 15, ));
+  assert_eq!((capacity.partition_blocks, capacity.used_blocks, ), (8, 6, ));
+  assert_eq!(capacity.free_runs, vec![1..2, 7..8]);
+  assert_eq!((capacity.free_blocks(), capacity.largest_free_blocks(), ), (2, 1, ));
+}
+
+#[test]
+fn copy_volume_files_and_efs_files() {
+  let (mut reader, volume, efs, ) = open_sample();
+  let file = volume.files.iter().find(|f| f.file_name.as_deref() == Some("ide")).unwrap();
+  let mut dest = Cursor::new(Vec::new());
+  let mut last = Progress::default();
+  assert_eq!(copy::volume_file(&mut reader, file, &mut dest, &CancellationToken::new(), |progress| last = progress).unwrap(), 1500);
+  assert_eq!(dest.into_inner(), testgen::pattern(1500, 5));
+  assert_eq!(last, Progress::bytes(1500, Some(1500)));
+
+  let (_, inode, ) = efs.lookup_path(&mut reader, "/usr/share/fragmented").unwrap();
+  let mut dest = Vec::new();
+  let mut updates = 0;
+  copy::efs_file(&efs, &mut reader, &inode, &mut dest, &CancellationToken::new(), |progress| {
+    assert!(progress.bytes <= progress.bytes_total.unwrap());
+    updates += 1;
+  }).unwrap();
+  assert_eq!(dest, testgen::pattern(40 * EFS_BLOCK_SZ, 2));
+  assert!(updates >= 40);
+
+  // Copying past the end of the image is short
+  let len = reader.get_ref().len() as u64;
+  let mut dest = Cursor::new(Vec::new());
+  assert_eq!(copy::cp(&mut reader, len - 100, 1000, &mut dest, 10, &CancellationToken::new(), |_| {}).unwrap(), 100);
+  assert_eq!(dest.get_ref().len(), 110);
+}
+
+#[test]
+fn io_stats() {
+  let image = testgen::sample().build();
+  let mut reader = CountingReader::new(Cursor::new(image));
+  let volume = SgidiskVolume::read(&mut reader).unwrap();
+  assert_eq!(reader.stats(), IoStats { seeks: 0, seeks_moved: 0, reads: 1, bytes_read: 512 });
+
+  let partition = &volume.partitions[testgen::EFS_PARTITION];
+  let efs = Efs::read(&mut reader, volume.sector_sz as u64, partition.byte_start()).unwrap();
+  let before = reader.stats();
+  let (_, inode, ) = efs.lookup_path(&mut reader, "/unix").unwrap();
+  efs.read_file(&mut reader, &inode, &mut Vec::new()).unwrap();
+  let read = reader.stats() - before;
+  assert!(read.seeks >= read.seeks_moved);
+  assert!(read.bytes_read >= 3000);
+
+  reader.reset_stats();
+  assert_eq!(reader.stats(), IoStats::default());
+}
+
+#[test]
+fn root_directory() {
+  let (mut reader, _, efs, ) = open_sample();
+  let root = Directory::read_dir(&mut reader, &efs, Directory::ROOT_DIRECTORY_INODE).unwrap();
+  let names: Vec<&str> = root.entries.keys().map(|n| n.as_str()).collect();
+  assert_eq!(names, vec![".", "..", "bin", "deep", "dev", "empty", "etc", "many", "tmp", "unix", "usr"]);
+  assert_eq!(root.entries["."].0, Directory::ROOT_DIRECTORY_INODE);
+  assert_eq!(root.entries[".."].0, Directory::ROOT_DIRECTORY_INODE);
+}
+
+#[test]
+fn directory_inodes_read_once() {
+  let (reader, _, efs, ) = open_sample();
+  let mut reader = CountingReader::new(reader);
+  let (tmp_id, _, ) = efs.lookup_path(&mut reader, "/tmp").unwrap();
+
+  // The directory's own inode is reused for ".", leaving the parent's for ".." and the socket's
+  let before = reader.stats();
+  let tmp = Directory::read_dir(&mut reader, &efs, tmp_id).unwrap();
+  let read = reader.stats() - before;
+  assert_eq!(tmp.entries.len(), 3);
+  assert_eq!(tmp.entries["."].1.id, tmp_id);
+  assert_eq!(tmp.entries[".."].0, Directory::ROOT_DIRECTORY_INODE);
+  assert_eq!(read.bytes_read, 3 * 128 + tmp.directory_inode.size);
+}
+
+#[test]
+fn ordered_directory() {
+  let (mut reader, _, efs, ) = open_sample();
+  let (many_id, _, ) = efs.lookup_path(&mut reader, "/many").unwrap();
+  let many = Directory::read_dir_ordered(&mut reader, &efs, many_id).unwrap();
+  let names: Vec<&str> = many.slots.iter().map(|s| s.entry.as_ref().unwrap().name.as_str()).collect();
+  assert_eq!(names.len(), 102);
+  assert_eq!(&names[..3], &[".", "..", "file000"]);
+  assert_eq!(names[101], "file099");
+  assert!(many.slots.last().unwrap().block_index > 0);
+  // Slots count from 0 in each directory block
+  assert!(many.slots.windows(2).all(|w| [(w[0].block_index, w[0].slot + 1, ), (w[0].block_index + 1, 0, )].contains(&(w[1].block_index, w[1].slot, ))));
+
+  // Free an entry's slot, as IRIX does when removing it
+  let (etc_id, _, ) = efs.lookup_path(&mut reader, "/etc").unwrap();
+  let etc = Directory::read_dir_ordered(&mut reader, &efs, etc_id).unwrap();
+  let freed = etc.slots.iter().find(|s| s.entry.as_ref().map_or(false, |e| e.name == "motd")).unwrap().clone();
+  let slot_byte = (efs.partition_start + freed.block * EFS_BLOCK_SZ as u64) as usize + 4 + freed.slot;
+  reader.get_mut()[slot_byte] = 0;
+
+  let etc = Directory::read_dir_ordered(&mut reader, &efs, etc_id).unwrap();
+  let slot = etc.slots.iter().find(|s| (s.block, s.slot, ) == (freed.block, freed.slot, )).unwrap();
+  assert_eq!(slot.entry, None);
+  let listed = Directory::read_dir(&mut reader, &efs, etc_id).unwrap();
+  assert!(!listed.entries.contains_key("motd"));
+  assert!(listed.entries.contains_key("passwd"));
+}
+
+#[test]
+fn duplicate_names() {
+  let (mut reader, _, efs, ) = open_sample();
+  assert!(efs.check_directories(&mut reader).duplicate_names.is_empty());
+
+  let mut dup = EfsBuilder::new();
+  dup.file("/d/aaaa", b"first").file("/d/bbbb", b"second");
+  let mut image = ImageBuilder::new();
+  image.efs(dup);
+  let (mut reader, _, efs, ) = open(&image);
+  let (d_id, _, ) = efs.lookup_path(&mut reader, "/d").unwrap();
+  let (a_id, _, ) = efs.lookup_path(&mut reader, "/d/aaaa").unwrap();
+  let (b_id, _, ) = efs.lookup_path(&mut reader, "/d/bbbb").unwrap();
+
+  // Rename the second entry to the first's name
+  let d = Directory::read_dir_ordered(&mut reader, &efs, d_id).unwrap();
+  let slot = d.slots.iter().find(|s| s.entry.as_ref().map_or(false, |e| e.name == "bbbb")).unwrap();
+  let name_start = (efs.partition_start + slot.block * EFS_BLOCK_SZ as u64) as usize + slot.entry.as_ref().unwrap().offset + 5;
+  reader.get_mut()[name_start..name_start + 4].copy_from_slice(b"aaaa");
+
+  let d = Directory::read_dir(&mut reader, &efs, d_id).unwrap();
+  assert_eq!(d.entries["aaaa"].0, a_id);
+  assert!(!d.entries.contains_key("bbbb"));
+  assert_eq!(d.duplicates, vec![("aaaa".to_string(), b_id, )]);
+  assert_eq!(Directory::read_dir_ordered(&mut reader, &efs, d_id).unwrap().duplicate_names(), vec!["aaaa"]);
+  assert_eq!(read(&mut reader, &efs, "/d/aaaa"), b"first");
+
+  let check = efs.check_directories(&mut reader);
+  assert!(check.walk.unreadable.is_empty());
+  assert_eq!(check.duplicate_names, vec![DuplicateName { directory: "/d".to_string(), name: "aaaa".to_string(), inode_ids: vec![a_id, b_id] }]);
+}
+
+#[test]
+fn link_counts() {
+  let (mut reader, _, efs, ) = open_sample();
+  let root = efs.read_inode(&mut reader, Directory::ROOT_DIRECTORY_INODE).unwrap();
+  // ".", "..", and each subdirectory's ".."
+  assert_eq!(root.nlink, 2 + 6);
+  let check = efs.check_directories(&mut reader);
+  assert!(check.link_count_mismatches.is_empty());
+  assert!(check.unreadable_inodes.is_empty());
+
+  let (motd_id, motd, ) = efs.lookup_path(&mut reader, "/etc/motd").unwrap();
+  assert_eq!(motd.nlink, 1);
+  let cg = motd_id / efs.cg_inodes;
+  let inode_start = efs.partition_start + (efs.cg_start + cg * efs.cg_size) * EFS_BLOCK_SZ as u64 + (motd_id % efs.cg_inodes) * 128;
+  reader.get_mut()[inode_start as usize + 2..inode_start as usize + 4].copy_from_slice(&3i16.to_be_bytes());
+  let check = efs.check_directories(&mut reader);
+  assert_eq!(check.link_count_mismatches, vec![LinkCountMismatch { inode_id: motd_id, nlink: 3, references: 1 }]);
+}
+
+#[test]
+fn small_files() {
+  let (mut reader, _, efs, ) = open_sample();
+  assert_eq!(read(&mut reader, &efs, "/etc/motd"), b"Welcome to IRIX\n");
+  assert!(read(&mut reader, &efs, "/etc/passwd").starts_with(b"root::0:0:"));
+  assert!(read(&mut reader, &efs, "/empty").is_empty());
+}
+
+#[test]
+fn multi_block_file() {
+  let (mut reader, _, efs, ) = open_sample();
+  let (_, inode, ) = efs.lookup_path(&mut reader, "/unix").unwrap();
+  assert_eq!(inode.size, 3000);
+  assert_eq!(inode.num_extents, 1);
+  assert_eq!(read(&mut reader, &efs, "/unix"), testgen::pattern(3000, 1));
+}
+
+#[test]
+fn file_ranges() {
+  let (mut reader, _, efs, ) = open_sample();
+  let (_, inode, ) = efs.lookup_path(&mut reader, "/usr/share/fragmented").unwrap();
+  let contents = testgen::pattern(40 * EFS_BLOCK_SZ, 2);
+  for (offset, len, ) in [(0, 10, ), (500, 30, ), (EFS_BLOCK_SZ * 3 - 7, EFS_BLOCK_SZ * 2 + 20, ), (EFS_BLOCK_SZ * 39, 1000, ), (EFS_BLOCK_SZ * 40, 5, )] {
+    let mut range = Vec::new();
+    let n = efs.read_range(&mut reader, &inode, offset as u64, len as u64, &mut range).unwrap();
+    let expected = &contents[offset.min(contents.len())..(offset + len).min(contents.len())];
+    assert_eq!(n, expected.len() as u64);
+    assert_eq!(range, expected);
+  }
+  assert_eq!(efs.read_range(&mut reader, &inode, u64::MAX - 1, 10, &mut Vec::new()).unwrap(), 0);
+}
+
+#[test]
+fn fragmented_file_with_indirect_extents() {
+  let (mut reader, _, efs, ) = open_sample();
+  let (_, inode, ) = efs.lookup_path(&mut reader, "/usr/share/fragmented").unwrap();
+  assert_eq!(inode.num_extents, 40);
+  assert_eq!(inode.iter().count(), 40);
+  assert_eq!(read(&mut reader, &efs, "/usr/share/fragmented"), testgen::pattern(40 * EFS_BLOCK_SZ, 2));
+}
+
+#[test]
+fn symbolic_links() {
+  let (mut reader, _, efs, ) = open_sample();
+  let (_, bin, ) = efs.lookup_path(&mut reader, "/bin").unwrap();
+  assert_eq!(bin.inode_type, InodeType::SymbolicLink);
+  assert_eq!(efs.read_link(&mut reader, &bin).unwrap(), "usr/bin");
+
+  let (_, sh, ) = efs.lookup_path(&mut reader, "/usr/lib/sh").unwrap();
+  assert_eq!(efs.read_link(&mut reader, &sh).unwrap(), "/usr/bin/ls");
+}
+
+#[test]
+fn path_normalization() {
+  assert_eq!(normalize_path("//usr/./lib/../bin/"), "/usr/bin");
+  assert_eq!(normalize_path("/../.."), "/");
+
+  let (mut reader, _, mut efs, ) = open_sample();
+  let (ls_id, _, ) = efs.lookup_path(&mut reader, "/usr/bin/ls").unwrap();
+  for path in ["//usr//bin/ls", "/usr/./bin/ls", "/etc/../usr/bin/ls", "/../usr/bin/ls"] {
+    assert_eq!(efs.lookup_path(&mut reader, path).unwrap().0, ls_id, "{}", path);
+  }
+
+  assert!(matches!(efs.lookup_path(&mut reader, "/USR/Bin/LS"), Err(SgidiskLibReadError::NotFound(_))));
+  efs.ignore_case = true;
+  assert_eq!(efs.lookup_path(&mut reader, "/USR/Bin/LS").unwrap().0, ls_id);
+  assert_eq!(efs.resolve_path(&mut reader, "/BIN/Ls").unwrap().0, "/usr/bin/ls");
+
+  // Names differing only in case are ambiguous, unless one matches exactly
+  let mut cased = EfsBuilder::new();
+  cased.file("/README", b"upper").file("/readme", b"lower");
+  let mut image = ImageBuilder::new();
+  image.efs(cased);
+  let (mut reader, _, mut efs, ) = open(&image);
+  efs.ignore_case = true;
+  assert_eq!(read(&mut reader, &efs, "/readme"), b"lower");
+  assert!(matches!(efs.lookup_path(&mut reader, "/ReadMe"), Err(SgidiskLibReadError::NotFound(_))));
+}
+
+#[test]
+fn filtered_walk() {
+  let (mut reader, _, efs, ) = open_sample();
+  let mut visited = Vec::new();
+  let walk = efs.walk_filtered(&mut reader,
+                               |path, _| !path.starts_with("/usr") && path != "/many",
+                               |_, inode| inode.inode_type == InodeType::RegularFile,
+                               |path, _, _| visited.push(path.to_string()),
+                               &CancellationToken::new(), |_| {});
+  assert!(visited.contains(&"/etc/passwd".to_string()));
+  assert!(visited.iter().any(|p| p.ends_with("/d20/leaf")));
+  assert!(!visited.iter().any(|p| p.starts_with("/usr/") || p.starts_with("/many/")));
+  assert!(!visited.contains(&"/usr".to_string()) && !visited.contains(&"/bin".to_string()));
+
+  // Pruned directories aren't read
+  let full = efs.walk(&mut reader, |_, _, _| {}, &CancellationToken::new(), |_| {});
+  assert!(walk.directories < full.directories);
+  assert!(walk.entries < full.entries);
+}
+
+#[test]
+fn globs() {
+  let glob = |p: &str| Glob::new(p).unwrap();
+  assert!(glob("*.c").matches("/usr/src/main.c"));
+  assert!(!glob("*.c").matches("/usr/src/.hidden.c"));
+  assert!(glob(".*").matches("/usr/src/.hidden.c"));
+  assert!(glob("[a-c]?[!x]").matches("/bin/b1y"));
+  assert!(!glob("[a-c]?[!x]").matches("/bin/b1x"));
+  assert!(glob("a\\*").matches("/a*") && !glob("a\\*").matches("/ab"));
+  assert!(glob("usr/*/ls").matches("/usr/bin/ls"));
+  assert!(!glob("/usr/*").matches("/usr/bin/ls"));
+  assert!(glob("/usr/**/ls").matches("/usr/ls") && glob("/usr/**/ls").matches("/usr/a/b/ls"));
+  assert!(glob("/usr/*/ls").leads_to("/usr/bin"));
+  assert!(!glob("/usr/*/ls").leads_to("/usr/bin/ls"));
+  assert!(!glob("/usr/*/ls").leads_to("/etc"));
+  assert!(matches!(Glob::new("[ab"), Err(SgidiskLibReadError::Value(_))));
+}
+
+#[test]
+fn find_entries() {
+  let (mut reader, _, efs, ) = open_sample();
+  let paths = |options: &FindOptions, pattern: &str| {
+    let (found, walk, ) = efs.find(&mut open_sample().0, pattern, options).unwrap();
+    assert!(walk.unreadable.is_empty());
+    found.into_iter().map(|f| f.path).collect::<Vec<_>>()
+  };
+  assert_eq!(paths(&FindOptions::default(), "/etc/*"), vec!["/etc/motd", "/etc/passwd"]);
+  assert_eq!(paths(&FindOptions::default(), "ls"), vec!["/usr/bin/ls"]);
+
+  let links = FindOptions { types: vec![InodeType::SymbolicLink], ..FindOptions::default() };
+  assert_eq!(paths(&links, "*"), vec!["/bin", "/usr/lib/sh"]);
+  let big = FindOptions { min_size: Some(3000), types: vec![InodeType::RegularFile], ..FindOptions::default() };
+  assert_eq!(paths(&big, "*"), vec!["/unix", "/usr/share/fragmented"]);
+  let excluded = FindOptions { exclude: vec![Glob::new("/usr").unwrap()], ..FindOptions::default() };
+  assert!(paths(&excluded, "/**").iter().all(|p| !p.starts_with("/usr")));
+
+  let (found, _, ) = efs.find(&mut reader, "/usr/bin/ls", &FindOptions::default()).unwrap();
+  assert_eq!(found[0].inode_id, efs.lookup_path(&mut reader, "/usr/bin/ls").unwrap().0);
+  assert!(efs.find(&mut reader, "[", &FindOptions::default()).is_err());
+}
+
+#[test]
+fn symbolic_link_resolution() {
+  let (mut reader, _, efs, ) = open_sample();
+  let (ls_id, _, ) = efs.lookup_path(&mut reader, "/usr/bin/ls").unwrap();
+  for path in ["/bin/ls", "/usr/lib/sh", "/bin/../lib/sh", "/usr/lib/../../bin/./ls"] {
+    let (resolved, id, _, ) = efs.resolve_path(&mut reader, path).unwrap();
+    assert_eq!((resolved.as_str(), id, ), ("/usr/bin/ls", ls_id, ), "{}", path);
+  }
+  assert_eq!(efs.resolve_path(&mut reader, "/").unwrap().0, "/");
+
+  let mut looped = EfsBuilder::new();
+  looped.symlink("/a", "b")
+    .symlink("/b", "/a")
+    .symlink("/c", "c/d")
+    .file("/target", b"x")
+    .symlink("/l3", "l2")
+    .symlink("/l2", "l1")
+    .symlink("/l1", "target");
+  let mut image = ImageBuilder::new();
+  image.efs(looped);
+  let (mut reader, _, mut efs, ) = open(&image);
+  assert!(matches!(efs.resolve_path(&mut reader, "/a"), Err(SgidiskLibReadError::SymlinkLoop { .. })));
+  // A link to itself with more path each time never repeats, so stops at the limit
+  assert!(matches!(efs.resolve_path(&mut reader, "/c"), Err(SgidiskLibReadError::LimitExceeded { limit: "max_symlinks", .. })));
+  assert_eq!(efs.resolve_path(&mut reader, "/l3").unwrap().0, "/target");
+  efs.limits.max_symlinks = 2;
+  assert!(matches!(efs.resolve_path(&mut reader, "/l3"), Err(SgidiskLibReadError::LimitExceeded { limit: "max_symlinks", max: 2 })));
+}
+
+#[test]
+fn devices() {
+  let (mut reader, _, efs, ) = open_sample();
+  let expected = [
+    ("/dev/null", InodeType::CharacterSpecial, 1, 2, ),
+    ("/dev/dsk/dks0d1s0", InodeType::BlockSpecial, 128, 16, ),
+    // Too big for an old style device number
+    ("/dev/big", InodeType::CharacterSpecial, 300, 70000, ),
+  ];
+  for (path, inode_type, major, minor, ) in expected {
+    let (_, inode, ) = efs.lookup_path(&mut reader, path).unwrap();
+    assert_eq!(inode.inode_type, inode_type, "{}", path);
+    let device = inode.device.unwrap();
+    assert_eq!((device.major, device.minor, ), (major, minor, ), "{}", path);
+  }
+
+  let (_, fifo, ) = efs.lookup_path(&mut reader, "/dev/fifo").unwrap();
+  assert_eq!(fifo.inode_type, InodeType::Fifo);
+  assert!(fifo.device.is_none());
+  let (_, socket, ) = efs.lookup_path(&mut reader, "/tmp/.socket").unwrap();
+  assert_eq!(socket.inode_type, InodeType::Socket);
+}
+
+#[test]
+fn deep_tree() {
+  let (mut reader, _, efs, ) = open_sample();
+  let deep = (1..=20).map(|n| format!("d{}", n)).collect::<Vec<String>>().join("/");
+  assert_eq!(read(&mut reader, &efs, &format!("/deep/{}/leaf", deep)), b"leaf\n");
+}
+
+#[test]
+fn walk() {
+  let (mut reader, _, efs, ) = open_sample();
+  let mut paths = Vec::new();
+  let mut last = Progress::default();
+  let walk = efs.walk(&mut reader, |path, _, _| paths.push(path.to_string()), &CancellationToken::new(), |progress| last = progress);
+  assert!(walk.unreadable.is_empty());
+  assert_eq!(walk.entries, paths.len() as u64);
+  assert_eq!(last.items, walk.entries);
+
+  // Directories come before their contents, in name order
+  let pos = |path: &str| paths.iter().position(|p| p == path).unwrap();
+  assert!(pos("/bin") < pos("/etc"));
+  assert!(pos("/etc") < pos("/etc/motd"));
+  assert!(pos("/etc/passwd") < pos("/many"));
+  assert!(pos("/usr") < pos("/usr/share/fragmented"));
+}
+
+#[cfg(all(feature = "io-uring", target_os = "linux"))]
+#[test]
+fn uring_walk_matches_walk() {
+  use sgidisklib::uring::UringFile;
+
+  let (mut reader, _, efs, ) = open_sample();
+  let path = std::env::temp_dir().join(format!("sgidisklib-uring-{}.img", std::process::id()));
+  std::fs::write(&path, reader.get_ref()).unwrap();
+  let mut file = UringFile::new(std::fs::File::open(&path).unwrap(), 8).unwrap();
+
+  let mut expected = Vec::new();
+  efs.walk(&mut reader, |path, id, inode| expected.push((path.to_string(), id, inode.size, )), &CancellationToken::new(), |_| {});
+  let mut batched = Vec::new();
+  let walk = efs.walk_batched(&mut file, |path, id, inode| batched.push((path.to_string(), id, inode.size, )), &CancellationToken::new(), |_| {});
+  assert!(walk.unreadable.is_empty());
+  assert_eq!(batched, expected);
+
+  let ids: Vec<u64> = expected.iter().map(|(_, id, _, )| *id).collect();
+  let inodes = efs.read_inodes(&mut file, &ids);
+  assert!(inodes.iter().zip(&expected).all(|(inode, (_, _, size, ), )| inode.as_ref().unwrap().size == *size));
+  std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn cancellation() {
+  let (mut reader, _, efs, ) = open_sample();
+  let cancel = CancellationToken::new();
+  let walk = efs.walk(&mut reader, |_, _, _| {}, &cancel, |_| cancel.cancel());
+  assert!(walk.cancelled);
+  assert_eq!(walk.directories, 1);
+
+  let (_, inode, ) = efs.lookup_path(&mut reader, "/usr/share/fragmented").unwrap();
+  let cancel = CancellationToken::new();
+  let copied = copy::efs_file(&efs, &mut reader, &inode, &mut Vec::new(), &cancel, |progress| if progress.bytes >= 1024 { cancel.cancel() });
+  assert!(matches!(copied, Err(SgidiskLibReadError::Cancelled)));
+}
+
+#[test]
+fn limits() {
+  let (mut reader, _, mut efs, ) = open_sample();
+  efs.limits.max_dir_entries = 4;
+  assert!(matches!(Directory::read_dir(&mut reader, &efs, Directory::ROOT_DIRECTORY_INODE),
+    Err(SgidiskLibReadError::LimitExceeded { limit: "max_dir_entries", max: 4 })));
+
+  efs.limits = Limits { max_depth: 1, ..Limits::default() };
+  assert!(efs.limits.check_depth(1).is_ok());
+  assert!(matches!(efs.limits.check_depth(2), Err(SgidiskLibReadError::LimitExceeded { limit: "max_depth", max: 1 })));
+  let mut deepest = 0;
+  let walk = efs.walk(&mut reader, |path, _, _| deepest = deepest.max(path.matches('/').count()), &CancellationToken::new(), |_| {});
+  assert_eq!(deepest, 2);
+  assert!(walk.unreadable.iter().all(|(_, e, )| matches!(e, SgidiskLibReadError::LimitExceeded { limit: "max_depth", .. })));
+  assert!(!walk.unreadable.is_empty());
+
+  efs.limits = Limits { max_inodes: 5, ..Limits::default() };
+  let walk = efs.walk(&mut reader, |_, _, _| {}, &CancellationToken::new(), |_| {});
+  assert_eq!(walk.entries, 5);
+  assert_eq!(walk.unreadable.len(), 1);
+
+  // Link sizes are checked before anything is allocated for the target
+  efs.limits = Limits::default();
+  let (_, mut bin, ) = efs.lookup_path(&mut reader, "/bin").unwrap();
+  bin.size = u64::MAX;
+  assert!(matches!(efs.read_link(&mut reader, &bin), Err(SgidiskLibReadError::LimitExceeded { limit: "max_alloc", .. })));
+  bin.size = MAX_LINK_SZ + 1;
+  assert!(matches!(efs.read_link(&mut reader, &bin), Err(SgidiskLibReadError::Value(_))));
+}
+
+#[test]
+fn directory_spanning_blocks() {
+  let (mut reader, _, efs, ) = open_sample();
+  let (many_id, many, ) = efs.lookup_path(&mut reader, "/many").unwrap();
+  assert!(many.size > EFS_BLOCK_SZ as u64);
+
+  let dir = Directory::read_dir(&mut reader, &efs, many_id).unwrap();
+  assert_eq!(dir.entries.len(), 102);
+  assert_eq!(read(&mut reader, &efs, "/many/file099"), b"99\n");
+}
+
+#[test]
+fn owner_and_mode() {
+  let (mut reader, _, efs, ) = open_sample();
+  let (_, login, ) = efs.lookup_path(&mut reader, "/usr/people/guest/.login").unwrap();
+  assert_eq!((login.owner_uid, login.owner_gid, login.unix_mode, ), (998, 998, 0o600, ));
+  assert_eq!(login.mtime.timestamp(), testgen::TIMESTAMP as i64);
+  assert_eq!((login.ctime_raw, login.mtime_raw, login.atime_raw, ), (login.ctime.timestamp(), login.mtime.timestamp(), login.atime.timestamp(), ));
+}
+
+#[test]
+fn timestamp_anomalies() {
+  let (mut reader, _, efs, ) = open_sample();
+  let (_, mut login, ) = efs.lookup_path(&mut reader, "/usr/people/guest/.login").unwrap();
+  assert_eq!(efs.time_raw, testgen::TIMESTAMP as i64);
+  assert!(login.timestamp_anomalies(efs.time_raw).is_empty());
+
+  login.mtime_raw += 60;
+  login.atime_raw = 0;
+  assert_eq!(login.timestamp_anomalies(efs.time_raw), vec![
+    TimestampAnomaly::AfterSuperblock(TimestampField::Mtime),
+    TimestampAnomaly::BeforeEarliest(TimestampField::Atime),
+    TimestampAnomaly::AccessedBeforeChanged,
+    TimestampAnomaly::ModifiedAfterChanged,
+  ]);
+  // An unset superblock time isn't compared with
+  assert_eq!(login.timestamp_anomalies(0).len(), 3);
+}
+
+#[test]
+fn content_types() {
+  let (mut reader, _, efs, ) = open_sample();
+  for (path, expected, ) in [("/etc/passwd", ContentType::Text, ), ("/unix", ContentType::Data, ), ("/empty", ContentType::Empty, )] {
+    let (_, inode, ) = efs.lookup_path(&mut reader, path).unwrap();
+    assert_eq!(efs.content_type(&mut reader, &inode).unwrap(), expected, "{}", path);
+  }
+  let (_, unix, ) = efs.lookup_path(&mut reader, "/unix").unwrap();
+  assert_eq!(efs.read_head(&mut reader, &unix, 100).unwrap(), testgen::pattern(100, 1));
+
+  assert_eq!(ContentType::sniff(b"\x7fELF\x01\x02\x01"), ContentType::Elf);
+  assert_eq!(ContentType::sniff(&[0x01, 0x60, 0x00, 0x03]), ContentType::MipsCoff);
+  assert_eq!(ContentType::sniff(&[0x62, 0x01, 0x03, 0x00]), ContentType::MipsCoff);
+  assert_eq!(ContentType::sniff(&[0x1f, 0x9d, 0x90]), ContentType::Compress);
+  assert_eq!(ContentType::sniff(&[0x1f, 0x8b, 0x08]), ContentType::Gzip);
+  assert_eq!(ContentType::sniff("#!/bin/sh\necho d\u{e9}j\u{e0}".as_bytes()), ContentType::Text);
+  assert_eq!(ContentType::sniff(b"abc\x00def"), ContentType::Data);
+
+  // Old style tar header, identified by its checksum
+  let mut header = [0u8; 512];
+  header[..8].copy_from_slice(b"etc/motd");
+  header[148..156].copy_from_slice(b"        ");
+  let sum: u32 = header.iter().map(|b| *b as u32).sum();
+  header[148..155].copy_from_slice(format!("{:06o}\0", sum).as_bytes());
+  assert_eq!(ContentType::sniff(&header), ContentType::Tar);
+  header[0] = b'E';
+  assert_eq!(ContentType::sniff(&header), ContentType::Data);
+}
+
+#[test]
+fn missing_entry() {
+  let (mut reader, _, efs, ) = open_sample();
+  assert!(matches!(efs.lookup_path(&mut reader, "/etc/shadow"), Err(SgidiskLibReadError::NotFound(_))));
+}
+
+#[test]
+fn structured_errors() {
+  let mut image = testgen::sample().build();
+  image[0] ^= 0x01;
+  assert!(matches!(SgidiskVolume::read(&mut Cursor::new(&image)),
+    Err(SgidiskLibReadError::BadMagic { expected: 0x0BE5A941, found: 0x0AE5A941, offset: 0 })));
+
+  let (mut reader, _, efs, ) = open_sample();
+  let magic_offset = efs.partition_start + EFS_BLOCK_SZ as u64 + 28;
+  reader.get_mut()[magic_offset as usize + 3] = 0;
+  assert!(matches!(Efs::read(&mut reader, EFS_BLOCK_SZ as u64, efs.partition_start),
+    Err(SgidiskLibReadError::BadMagic { offset, .. }) if offset == magic_offset));
+
+  // Truncate the image at the start of a file's contents
+  let (mut reader, _, efs, ) = open_sample();
+  let (inode_id, unix, ) = efs.lookup_path(&mut reader, "/unix").unwrap();
+  assert_eq!(unix.id, inode_id);
+  let first_block = efs.partition_start + unix.iter().next().unwrap() * EFS_BLOCK_SZ as u64;
+  reader.get_mut().truncate(first_block as usize);
+  assert!(matches!(efs.read_file(&mut reader, &unix, &mut Vec::new()),
+    Err(SgidiskLibReadError::ExtentOutOfBounds { inode, extent: 0, offset }) if inode == inode_id && offset == first_block));
+}
+
+#[test]
+fn all_inodes() {
+  let (mut reader, _, efs, ) = open_sample();
+  let (motd_id, _, ) = efs.lookup_path(&mut reader, "/etc/motd").unwrap();
+  let inodes: Vec<_> = efs.inodes(&mut reader).collect();
+  assert_eq!(inodes.len() as u64, efs.inode_count());
+
+  // Inodes 0 and 1 are never used
+  assert!(matches!(inodes[0], (0, Ok(None), )));
+  assert!(matches!(inodes[1], (1, Ok(None), )));
+  let root = inodes[Directory::ROOT_DIRECTORY_INODE as usize].1.as_ref().unwrap().as_ref().unwrap();
+  assert_eq!(root.inode_type, InodeType::Directory);
+  let motd = inodes[motd_id as usize].1.as_ref().unwrap().as_ref().unwrap();
+  assert_eq!((motd.id, motd.size, ), (motd_id, 16, ));
+  assert!(inodes.iter().any(|(_, inode, )| matches!(inode, Ok(None))));
+}
+
+#[test]
+fn cylinder_groups() {
+  let mut efs = EfsBuilder::new();
+  efs.cylinder_groups(4);
+  for n in 0..30 {
+    efs.file(&format!("/dir{}/file", n % 7), &testgen::pattern(1000 + n * 100, n as u8));
+  }
+  let mut image = ImageBuilder::new();
+  image.efs(efs);
+
+  let (mut reader, _, efs, ) = open(&image);
+  assert_eq!(efs.cg_count, 4);
+  for n in 0..30 {
+    // Later files overwrite earlier ones at the same path
+    if n + 7 >= 30 {
+      assert_eq!(read(&mut reader, &efs, &format!("/dir{}/file", n % 7)), testgen::pattern(1000 + n * 100, n as u8));
+    }
+  }
+}
+
+#[test]
+fn slack() {
+  let (mut reader, _, efs, ) = open_sample();
+  let (_, unix, ) = efs.lookup_path(&mut reader, "/unix").unwrap();
+  assert_eq!(unix.allocated_sz(), 3072);
+  assert_eq!(unix.slack_sz(), 72);
+
+  let mut slack = Vec::new();
+  assert_eq!(efs.read_slack(&mut reader, &unix, &mut slack).unwrap(), 72);
+  assert_eq!(slack.len(), 72);
+
+  let (_, fragmented, ) = efs.lookup_path(&mut reader, "/usr/share/fragmented").unwrap();
+  assert_eq!(fragmented.slack_sz(), 0);
+  assert_eq!(efs.read_slack(&mut reader, &fragmented, &mut Vec::new()).unwrap(), 0);
+}
+
+/// Sample image in the second partition of a PC disk, with an MBR or a protective MBR and GPT
+fn pc_disk(gpt: bool) -> Vec<u8> {
+  const START: u64 = 64;
+  let sgi = testgen::sample().build();
+  let sectors = sgi.len() as u64 / 512;
+  let mut disk = vec![0u8; START as usize * 512];
+  disk[510..512].copy_from_slice(&[0x55, 0xaa]);
+  if gpt {
+    disk[446 + 4] = 0xee;
+    disk[446 + 8..446 + 12].copy_from_slice(&1u32.to_le_bytes());
+    disk[512..520].copy_from_slice(b"EFI PART");
+    disk[512 + 72..512 + 80].copy_from_slice(&2u64.to_le_bytes());
+    disk[512 + 80..512 + 84].copy_from_slice(&128u32.to_le_bytes());
+    disk[512 + 84..512 + 88].copy_from_slice(&128u32.to_le_bytes());
+    // An empty first partition, then the SGI disk
+    let entry = 1024 + 128;
+    disk[entry..entry + 16].copy_from_slice(&[0x11; 16]);
+    disk[entry + 32..entry + 40].copy_from_slice(&START.to_le_bytes());
+    disk[entry + 40..entry + 48].copy_from_slice(&(START + sectors - 1).to_le_bytes());
+    for (i, c, ) in "IRIX".encode_utf16().enumerate() {
+      disk[entry + 56 + i * 2..entry + 58 + i * 2].copy_from_slice(&c.to_le_bytes());
+    }
+  } else {
+    disk[446 + 4] = 0x0c;
+    disk[446 + 8..446 + 12].copy_from_slice(&1u32.to_le_bytes());
+    disk[446 + 12..446 + 16].copy_from_slice(&1u32.to_le_bytes());
+    disk[462 + 4] = 0x83;
+    disk[462 + 8..462 + 12].copy_from_slice(&(START as u32).to_le_bytes());
+    disk[462 + 12..462 + 16].copy_from_slice(&(sectors as u32).to_le_bytes());
+  }
+  disk.extend_from_slice(&sgi);
+  disk
+}
+
+#[test]
+fn pc_partition_tables() {
+  assert_eq!(PcPartitionTable::read(&mut Cursor::new(testgen::sample().build())).unwrap(), None);
+
+  let mbr = PcPartitionTable::read(&mut Cursor::new(pc_disk(false))).unwrap().unwrap();
+  assert_eq!(mbr.scheme, PcScheme::Mbr);
+  assert_eq!(mbr.partitions.iter().map(|p| (p.number, p.type_id.as_str(), p.has_volume_header, )).collect::<Vec<_>>(),
+             vec![(1, "0c", false, ), (2, "83", true, )]);
+  assert_eq!(mbr.volume_header_offset(), Some(64 * 512));
+
+  let gpt = PcPartitionTable::read(&mut Cursor::new(pc_disk(true))).unwrap().unwrap();
+  assert_eq!(gpt.scheme, PcScheme::Gpt);
+  assert_eq!(gpt.partitions.len(), 1);
+  assert_eq!((gpt.partitions[0].number, gpt.partitions[0].name.as_deref(), ), (2, Some("IRIX"), ));
+  assert_eq!(gpt.partitions[0].type_id, "11111111-1111-1111-1111-111111111111");
+  assert_eq!(gpt.volume_header_offset(), Some(64 * 512));
+
+  // The nested disk reads as if it were the whole image
+  let disk = pc_disk(true);
+  let nested = &disk[gpt.volume_header_offset().unwrap() as usize..];
+  let vh = SgidiskVolume::read(&mut &nested[..]).unwrap();
+  let efs = Efs::read(&mut Cursor::new(nested.to_vec()), vh.sector_sz as u64, vh.partitions[testgen::EFS_PARTITION].byte_start()).unwrap();
+  assert!(efs.lookup_path(&mut Cursor::new(nested.to_vec()), "/unix").is_ok());
+
+  // Hostile GPT headers and entries are refused, rather than allocated for or overflowing
+  let patches: [(usize, Vec<u8>, ); 5] = [
+    // Entry size, too large and not a multiple of 128
+    (512 + 84, u32::MAX.to_le_bytes().to_vec(), ),
+    (512 + 84, 200u32.to_le_bytes().to_vec(), ),
+    // LBA of the entries
+    (512 + 72, u64::MAX.to_le_bytes().to_vec(), ),
+    // First and last LBA of the SGI disk's entry
+    (1024 + 128 + 32, u64::MAX.to_le_bytes().to_vec(), ),
+    (1024 + 128 + 40, u64::MAX.to_le_bytes().to_vec(), ),
+  ];
+  for (at, bytes, ) in patches {
+    let mut hostile = pc_disk(true);
+    hostile[at..at + bytes.len()].copy_from_slice(&bytes);
+    assert!(PcPartitionTable::read(&mut Cursor::new(hostile)).is_err(), "patched at {}", at);
+  }
+}
+
+/// Reader which fails to read some sectors, each a number of times before it can be read
+struct FlakyReader {
+  inner: Cursor<Vec<u8>>,
+  /// Sectors which fail, and how many more times
+  failing: Vec<(u64, u32, )>,
+}
+
+impl Read for FlakyReader {
+  fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+    let (start, end, ) = (self.inner.position() / 512, (self.inner.position() + buf.len() as u64 + 511) / 512);
+    if let Some((_, failures, )) = self.failing.iter_mut().find(|(sector, failures, )| (start..end).contains(sector) && *failures > 0) {
+      *failures -= 1;
+      return Err(std::io::Error::new(std::io::ErrorKind::Other, "Unrecovered read error"));
+    }
+    self.inner.read(buf)
+  }
+}
+
+impl Seek for FlakyReader {
+  fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+    self.inner.seek(pos)
+  }
+}
+
+#[test]
+fn rescue_reads() {
+  let data: Vec<u8> = (0..4096u32).map(|n| (n % 251) as u8 + 1).collect();
+  let flaky = || FlakyReader { inner: Cursor::new(data.clone()), failing: vec![(1, u32::MAX, ), (3, 3, )] };
+
+  // Sector 1 never reads, and sector 3 reads on the second attempt at it alone
+  let mut reader = RescueReader::new(flaky(), RescueOptions { skip_unreadable: true, ..Default::default() });
+  let mut read = vec![0xff; data.len()];
+  reader.read_exact(&mut read).unwrap();
+  assert!(read[512..1024].iter().all(|b| *b == 0));
+  assert_eq!(&read[..512], &data[..512]);
+  assert_eq!(&read[1024..], &data[1024..]);
+  let map = reader.map();
+  assert_eq!(map.bad, vec![512..1024]);
+  assert_eq!(map.read, vec![0..512, 1024..4096]);
+  assert_eq!((map.retries, map.recovered, ), (3 + 1, 1, ));
+  let mut mapfile = Vec::new();
+  map.write_mapfile(&mut mapfile, 8192).unwrap();
+  let lines: Vec<&str> = std::str::from_utf8(&mapfile).unwrap().lines().filter(|l| !l.starts_with('#')).skip(1).collect();
+  assert_eq!(lines, vec!["0x00000000  0x00000200  +", "0x00000200  0x00000200  -", "0x00000400  0x00000C00  +", "0x00001000  0x00001000  ?"]);
+
+  // Without skipping, the read fails once the sector has been retried
+  let mut reader = RescueReader::new(flaky(), RescueOptions { retries: 1, ..Default::default() });
+  assert!(reader.read_exact(&mut vec![0; data.len()]).is_err());
+  assert_eq!(reader.map().bad, vec![512..1024]);
+
+  // A filesystem can still be read around a bad sector
+  let (mut sample, _, efs, ) = open_sample();
+  let (_, motd, ) = efs.lookup_path(&mut sample, "/etc/motd").unwrap();
+  let motd_sector = (efs.partition_start + motd.block_ranges().next().unwrap().start * EFS_BLOCK_SZ as u64) / 512;
+  let flaky = FlakyReader { inner: sample, failing: vec![(motd_sector, u32::MAX, )] };
+  let mut reader = RescueReader::new(flaky, RescueOptions { skip_unreadable: true, ..Default::default() });
+  let (_, motd, ) = efs.lookup_path(&mut reader, "/etc/motd").unwrap();
+  let mut contents = Vec::new();
+  efs.read_file(&mut reader, &motd, &mut contents).unwrap();
+  assert_eq!(contents, vec![0; 16]);
+  let (_, passwd, ) = efs.lookup_path(&mut reader, "/etc/passwd").unwrap();
+  let mut contents = Vec::new();
+  efs.read_file(&mut reader, &passwd, &mut contents).unwrap();
+  assert!(contents.starts_with(b"root::0:0:"));
+}
+
+#[test]
+fn content_handlers() {
+  let (mut reader, vh, _, ) = open_sample();
+  let probe = |reader: &mut Cursor<Vec<u8>>, id: usize| {
+    let p = &vh.partitions[id];
+    content::probe_partition(reader, p.byte_start(), p.partition_type).unwrap().map(|i| i.name)
+  };
+  assert_eq!(probe(&mut reader, testgen::EFS_PARTITION), Some("efs"));
+  assert_eq!(probe(&mut reader, testgen::VOLUME_HEADER_PARTITION), Some("volume-header"));
+
+  /// Format of an imaginary appliance, kept in raw partitions
+  struct Appliance;
+  impl ContentHandler for Appliance {
+    fn name(&self) -> &'static str {
+      "appliance"
+    }
+
+    fn handles(&self, partition_type: PartitionType) -> bool {
+      partition_type == PartitionType::Raw
+    }
+
+    fn probe(&self, head: &[u8]) -> bool {
+      head.starts_with(b"APPLIANCE")
+    }
+
+    fn describe(&self, head: &[u8]) -> Option<String> {
+      head.get(9..12).map(|version| String::from_utf8_lossy(version).to_string())
+    }
+  }
+  content::register(Box::new(Appliance));
+  assert!(content::registered().contains(&"appliance"));
+
+  let head = b"APPLIANCE2.1\0\0\0\0";
+  assert_eq!(content::identify(PartitionType::Raw, head), Some(Identified {
+    name: "appliance",
+    description: Some("2.1".to_string()),
+    builtin: false,
+  }));
+  // Only raw partitions are handled
+  assert_eq!(content::identify(PartitionType::Efs, head), None);
+  // Built in handlers are still consulted
+  assert_eq!(probe(&mut reader, testgen::EFS_PARTITION), Some("efs"));
+}
+
+#[test]
+fn release_identification() {
+  assert_eq!(find_release(b"IRIX Release 5.3 IP22 Version 11091812 System V").as_deref(), Some("5.3"));
+  assert_eq!(find_release(b"\0IRIX64 Release 6.5 IP27\0").as_deref(), Some("6.5"));
+  assert_eq!(find_release(b"IRIX Execution Environment, 6.5.22m\n").as_deref(), Some("6.5.22m"));
+  assert_eq!(find_release(b"IRIX 6\nIRIX\0 5.3"), None);
+
+  let (mut reader, _, efs, ) = open_sample();
+  let sample = efs.identify_release(&mut reader);
+  assert_eq!(sample.media, MediaType::SystemDisk);
+  assert_eq!(sample.release, None);
+
+  let mut cd = EfsBuilder::new();
+  cd.file("/dist/eoe", b"pd\0IRIX Execution Environment, 6.5.22m\0")
+    .dir("/dist/miniroot")
+    .file("/etc/issue", b"IRIX 6.5\n");
+  let mut image = ImageBuilder::new();
+  image.efs(cd);
+  let (mut reader, _, efs, ) = open(&image);
+  let identified = efs.identify_release(&mut reader);
+  assert_eq!(identified.media, MediaType::InstallationCd);
+  assert!(identified.miniroot);
+  assert_eq!(identified.release.as_deref(), Some("6.5.22m"));
+  assert_eq!(identified.evidence.iter().map(|e| e.path.as_str()).collect::<Vec<&str>>(), vec!["/dist/eoe", "/etc/issue"]);
+
+  let mut disk = EfsBuilder::new();
+  disk.file("/unix", b"\x7fELF...IRIX Release 5.3 IP22 Version 11091812 System V");
+  let mut image = ImageBuilder::new();
+  image.efs(disk);
+  let (mut reader, _, efs, ) = open(&image);
+  let identified = efs.identify_release(&mut reader);
+  assert_eq!(identified.media, MediaType::SystemDisk);
+  assert_eq!(identified.release.as_deref(), Some("5.3"));
+}
+
+#[test]
+fn filesystem_generation() {
+  let (mut reader, _, efs, ) = open_sample();
+  assert_eq!(efs.magic, SuperblockMagic::New);
+  let (_, inode, ) = efs.lookup_path(&mut reader, "/etc/passwd").unwrap();
+  assert_eq!((inode.version, inode.spare, ), (0, 0, ));
+
+  let mut old = EfsBuilder::new();
+  old.old_magic().file("/unix", b"kernel");
+  let mut image = ImageBuilder::new();
+  image.efs(old);
+  let (mut reader, _, efs, ) = open(&image);
+  assert_eq!(efs.magic, SuperblockMagic::Old);
+  assert!(efs.superblock_checksum_ok(&mut reader).unwrap());
+}
+
+#[test]
+fn hostile_names() {
+  let (mut reader, _, efs, ) = open(&testgen::hostile_names());
+  let (dir_id, _, ) = efs.lookup_path(&mut reader, "/dir").unwrap();
+  let dir = Directory::read_dir(&mut reader, &efs, dir_id).unwrap();
+  let names: Vec<&str> = dir.entries.keys().map(|name| name.as_str()).collect();
+  assert_eq!(names, vec![".", "..", "../../escaped", "/etc/escaped", "link", "link/passwd"]);
+  let (_, inode, ) = &dir.entries["../../escaped"];
+  let mut contents = Vec::new();
+  efs.read_file(&mut reader, inode, &mut contents).unwrap();
+  assert_eq!(contents, b"parent\n");
+}
+
+#[cfg(feature = "ewf")]
+#[test]
+fn ewf_containers() {
+  use sgidisklib::ewf::EwfReader;
+
+  let media = testgen::sample().build();
+  let container = testgen::ewf(&media);
+  let mut reader = EwfReader::from_segments(vec![Cursor::new(container.clone())]).unwrap();
+  assert_eq!(reader.size(), media.len() as u64);
+  let mut read_back = Vec::new();
+  reader.read_to_end(&mut read_back).unwrap();
+  assert_eq!(read_back, media);
+
+  // Hostile counts, sizes and offsets are refused, rather than allocated for or overflowing
+  let section = |name: &[u8]| container.windows(16)
+    .position(|w| w.starts_with(name) && w[name.len()..].iter().all(|b| *b == 0))
+    .unwrap();
+  let (volume, sectors, table, ) = (section(b"volume") + 76, section(b"sectors"), section(b"table") + 76, );
+  let patches: [(usize, Vec<u8>, ); 5] = [
+    // Entry count of the table
+    (table, u32::MAX.to_le_bytes().to_vec(), ),
+    // Base offset of the table's entries
+    (table + 8, u64::MAX.to_le_bytes().to_vec(), ),
+    // Sectors per chunk
+    (volume + 8, u32::MAX.to_le_bytes().to_vec(), ),
+    // Sector count
+    (volume + 16, u64::MAX.to_le_bytes().to_vec(), ),
+    // Size of the sectors section
+    (sectors + 24, u64::MAX.to_le_bytes().to_vec(), ),
+  ];
+  for (at, bytes, ) in patches {
+    let mut hostile = container.clone();
+    hostile[at..at + bytes.len()].copy_from_slice(&bytes);
+    assert!(EwfReader::from_segments(vec![Cursor::new(hostile)]).is_err(), "patched at {}", at);
+  }
+  let truncated = container[..container.len() - 40].to_vec();
+  assert!(matches!(EwfReader::from_segments(vec![Cursor::new(truncated)]), Err(SgidiskLibReadError::Bounds(_))));
+}
+
+#[test]
+fn escaping_links() {
+  // Within the EFS, ".." at the root is the root, so the chain leads back to it
+  let (mut reader, _, efs, ) = open(&testgen::escaping_links());
+  let (path, _, inode, ) = efs.resolve_path(&mut reader, "/d/b").unwrap();
+  assert_eq!((path.as_str(), inode.inode_type, ), ("/", InodeType::Directory, ));
+  let (path, _, _, ) = efs.resolve_path(&mut reader, "/d/c").unwrap();
+  assert_eq!(path, "/d/e/f");
+}
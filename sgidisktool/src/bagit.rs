@@ -0,0 +1,192 @@
+use std::fs;
+use std::fs::File;
+use std::io;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::process::exit;
+
+use clap::ArgMatches;
+use serde::Serialize;
+
+use sgidisklib::ewf::EwfReader;
+
+use crate::config::Config;
+use crate::hash::{HashAlgorithm, MultiHash};
+
+/// Directory of a bag holding its payload
+pub(crate) const DATA_DIR: &str = "data";
+
+/// Tag file of a raw image bag holding the hashes of the image, its volume files and volumes
+const HASHES_TAG_FILE: &str = "sgidisk-hashes.json";
+
+/// JSON representation of a finished bag
+#[derive(Serialize)]
+pub(crate) struct JsonBag {
+  bag: String,
+  payload_files: usize,
+  payload_bytes: u64,
+}
+
+/// Bag tool entry point: package the raw disk image, with its hashes, as a BagIt bag
+pub(crate) fn subcommand(config: &Config, disk_file_name: &str, cli_matches: &ArgMatches) {
+  let json = config.json(cli_matches);
+  let dest = Path::new(cli_matches.value_of("dest").unwrap());
+  let algorithms = config.hash.algorithms.clone();
+  if disk_file_name == crate::STDIN_FILE_NAME {
+    eprintln!("The disk image is copied into the bag, so it can't be read from stdin");
+    exit(crate::exit_codes::CLI_ARG_ERROR);
+  }
+
+  // Only the one file is copied, so devices and the further segments of EWF containers would be
+  // missing from the bag
+  let raw_file = fs::metadata(disk_file_name)
+    .and_then(|metadata| Ok(metadata.is_file() && !EwfReader::is_ewf(disk_file_name)?));
+  match raw_file {
+    Ok(true) => {}
+    Ok(false) => {
+      eprintln!("The disk image is copied into the bag as it is, so it must be a raw image in a regular file, not a device or EWF container");
+      exit(crate::exit_codes::CLI_ARG_ERROR);
+    }
+    Err(e) => {
+      eprintln!("Unable to open disk image '{}': {:?}", disk_file_name, &e);
+      exit(crate::exit_codes::IO_ERR);
+    }
+  }
+
+  // Hash the image, its volume files and volumes, as the hash tool does
+  let mut vol = crate::StreamVolume::open_or_quit(disk_file_name);
+  let hashes = match crate::hash::hash_volume(&mut vol.reader, &vol.volume_header, &algorithms, &Default::default()) {
+    Ok(hashes) => hashes,
+    Err(e) => {
+      eprintln!("Error while reading disk image: {:?}", &e);
+      exit(crate::exit_codes::IO_ERR);
+    }
+  };
+  let hashes = serde_json::to_vec_pretty(&hashes.into_json()).unwrap();
+
+  // Copy the image into the payload
+  let data_dir = create_bag(dest).unwrap_or_else(|e| quit_bag_error(dest, &e));
+  let image_name = Path::new(disk_file_name).file_name().unwrap_or_default();
+  if let Err(e) = fs::copy(disk_file_name, data_dir.join(image_name)) {
+    eprintln!("Error copying disk image into bag {}: {:?}", dest.to_string_lossy(), &e);
+    exit(crate::exit_codes::IO_ERR);
+  }
+
+  let bag = finish_bag(dest, &algorithms, &[(HASHES_TAG_FILE, &hashes[..], )])
+    .unwrap_or_else(|e| quit_bag_error(dest, &e));
+  print_bag(&bag, json);
+}
+
+/// Print a finished bag
+pub(crate) fn print_bag(bag: &JsonBag, json: bool) {
+  if json {
+    crate::output::print_json("bag", bag);
+  } else {
+    println!("Bag {}: {} payload files, {} bytes", &bag.bag, bag.payload_files, bag.payload_bytes);
+  }
+}
+
+/// Quit after an error writing a bag
+pub(crate) fn quit_bag_error(bag_dir: &Path, e: &io::Error) -> ! {
+  eprintln!("Error writing bag {}: {:?}", bag_dir.to_string_lossy(), e);
+  exit(crate::exit_codes::IO_ERR);
+}
+
+/// Create an empty bag directory, returning its payload directory. The bag directory must not
+/// exist, or be empty.
+pub(crate) fn create_bag(bag_dir: &Path) -> io::Result<PathBuf> {
+  if bag_dir.exists() && fs::read_dir(bag_dir)?.next().is_some() {
+    return Err(io::Error::new(io::ErrorKind::AlreadyExists, "Bag directory is not empty"));
+  }
+  let data_dir = bag_dir.join(DATA_DIR);
+  fs::create_dir_all(&data_dir)?;
+  Ok(data_dir)
+}
+
+/// Finish a bag whose payload has been written: hash the payload and write the payload
+/// manifests, bag declaration, bag metadata, extra tag files and tag manifests
+pub(crate) fn finish_bag(bag_dir: &Path, algorithms: &[HashAlgorithm], tag_files: &[(&str, &[u8], )]) -> io::Result<JsonBag> {
+  let mut payload = Vec::new();
+  payload_files(&bag_dir.join(DATA_DIR), DATA_DIR, &mut payload)?;
+
+  // Payload manifests, one per algorithm
+  let mut manifests = Vec::new();
+  let mut payload_bytes = 0;
+  let mut hashed = Vec::with_capacity(payload.len());
+  for (path, name, ) in &payload {
+    let (len, hash, ) = MultiHash::hash_reader(&mut File::open(path)?, algorithms)?;
+    payload_bytes += len;
+    hashed.push((name, hash.entries(), ));
+  }
+  for algorithm in algorithms {
+    let file_name = format!("manifest-{}.txt", algorithm.name());
+    write_manifest(&bag_dir.join(&file_name), hashed.iter()
+      .flat_map(|(name, hashes, )| hashes.iter()
+        .filter(|(a, _, )| a == algorithm)
+        .map(move |(_, hash, )| (hash.as_str(), name.as_str(), ))))?;
+    manifests.push(file_name);
+  }
+
+  // Bag declaration and metadata
+  fs::write(bag_dir.join("bagit.txt"), "BagIt-Version: 1.0\nTag-File-Character-Encoding: UTF-8\n")?;
+  let bag_info = format!("Bag-Software-Agent: {} {}\nBagging-Date: {}\nPayload-Oxum: {}.{}\n",
+                         env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION"), chrono::Local::now().format("%Y-%m-%d"), payload_bytes, payload.len());
+  fs::write(bag_dir.join("bag-info.txt"), bag_info)?;
+  for (name, contents, ) in tag_files {
+    fs::write(bag_dir.join(name), contents)?;
+  }
+
+  // Tag manifests, listing every tag file other than themselves
+  let mut tags: Vec<String> = vec!["bagit.txt".to_string(), "bag-info.txt".to_string()];
+  tags.extend(manifests);
+  tags.extend(tag_files.iter().map(|(name, _, )| name.to_string()));
+  let mut tag_hashes = Vec::with_capacity(tags.len());
+  for tag in &tags {
+    let (_, hash, ) = MultiHash::hash_reader(&mut File::open(bag_dir.join(tag))?, algorithms)?;
+    tag_hashes.push((tag, hash.entries(), ));
+  }
+  for algorithm in algorithms {
+    write_manifest(&bag_dir.join(format!("tagmanifest-{}.txt", algorithm.name())), tag_hashes.iter()
+      .flat_map(|(name, hashes, )| hashes.iter()
+        .filter(|(a, _, )| a == algorithm)
+        .map(move |(_, hash, )| (hash.as_str(), name.as_str(), ))))?;
+  }
+
+  Ok(JsonBag {
+    bag: bag_dir.to_string_lossy().to_string(),
+    payload_files: payload.len(),
+    payload_bytes,
+  })
+}
+
+/// Regular files under a directory, recursively, with their paths relative to the bag using `/`
+/// as separator, in sorted order
+fn payload_files(dir: &Path, name: &str, files: &mut Vec<(PathBuf, String, )>) -> io::Result<()> {
+  let mut entries = fs::read_dir(dir)?.collect::<io::Result<Vec<_>>>()?;
+  entries.sort_by_key(|e| e.file_name());
+  for entry in entries {
+    let entry_name = format!("{}/{}", name, entry.file_name().to_string_lossy());
+    let file_type = entry.file_type()?;
+    if file_type.is_dir() {
+      payload_files(&entry.path(), &entry_name, files)?;
+    } else if file_type.is_file() {
+      files.push((entry.path(), entry_name, ));
+    }
+  }
+  Ok(())
+}
+
+/// Write a manifest of (checksum, path) lines
+fn write_manifest<'a, I>(path: &Path, lines: I) -> io::Result<()>
+  where I: Iterator<Item = (&'a str, &'a str, )> {
+  let mut writer = BufWriter::new(File::create(path)?);
+  for (hash, name, ) in lines {
+    writeln!(writer, "{}  {}", hash.to_lowercase(), manifest_escape(name))?;
+  }
+  writer.flush()
+}
+
+/// Percent-encode the characters which can't appear in a manifest path
+fn manifest_escape(name: &str) -> String {
+  name.replace('%', "%25").replace('\r', "%0D").replace('\n', "%0A")
+}
@@ -0,0 +1,259 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::Path;
+use std::process::exit;
+
+use clap::ArgMatches;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::config::Config;
+use crate::efs::OpenEfs;
+use crate::efs::cp::{ExtractOptions, ResumeCheck, SymlinkPolicy};
+use crate::efs::filter::{EntryFilter, FilterSpec};
+use crate::hash::HashAlgorithm;
+use crate::{OpenVolume, STDIN_FILE_NAME};
+
+/// One operation in a batch, as read from JSON
+#[derive(Debug, Deserialize)]
+#[serde(tag = "op", rename_all = "lowercase", deny_unknown_fields)]
+enum BatchOp {
+  /// Volume Header information, as `vh info`
+  Info {
+    image: Option<String>,
+  },
+  /// Hashes of disk image, volume files and volumes, as `hash`
+  Hash {
+    image: Option<String>,
+    algorithms: Option<Vec<HashAlgorithm>>,
+    #[serde(default)]
+    defined_areas: bool,
+  },
+  /// Copy volume header files, as `vh cp`
+  Cp {
+    image: Option<String>,
+    src: String,
+    dest: String,
+    #[serde(default)]
+    verify: bool,
+  },
+  /// Extract from an EFS partition, as `efs cp`
+  Extract {
+    image: Option<String>,
+    partition: usize,
+    src: String,
+    dest: String,
+    #[serde(default)]
+    recursive: bool,
+    numeric_owner: Option<bool>,
+    symlinks: Option<SymlinkPolicy>,
+    sparse: Option<bool>,
+    #[serde(default)]
+    verify: bool,
+    /// Which entries within directories are extracted
+    filter: Option<FilterSpec>,
+    /// Leave files already extracted with the same size and modification time
+    #[serde(default)]
+    resume: bool,
+  },
+}
+
+/// JSON result of one batch operation
+#[derive(Serialize)]
+struct JsonBatchResult<'a> {
+  /// Version of the output structure (JSON_SCHEMA_VERSION)
+  schema_version: u32,
+  /// Position of operation in the batch, from 0
+  index: usize,
+  op: &'static str,
+  image: Option<&'a str>,
+  result: Option<Value>,
+  error: Option<String>,
+}
+
+/// Batch tool entry point
+pub(crate) fn subcommand(config: &Config, default_disk_file_name: Option<&str>, cli_matches: &ArgMatches) {
+  let ops_file_name = cli_matches.value_of("operations").unwrap_or(STDIN_FILE_NAME);
+  let ops = match read_ops(ops_file_name) {
+    Ok(ops) => ops,
+    Err(e) => {
+      eprintln!("Error reading batch operations from '{}': {}", ops_file_name, &e);
+      exit(crate::exit_codes::CLI_ARG_ERROR);
+    }
+  };
+
+  // Disk images stay open between operations
+  let mut volumes: HashMap<&str, OpenVolume> = HashMap::new();
+  let mut failed = false;
+  let force = cli_matches.is_present("force");
+  for (index, op, ) in ops.iter().enumerate() {
+    let image = op.image().or(default_disk_file_name);
+    let outcome = match image {
+      Some(image) => run_op(config, &mut volumes, image, op, force),
+      None => Err("No disk image given for operation, or with -f/--file".to_string())
+    };
+
+    let (result, error, ) = match outcome {
+      Ok((result, error, )) => (Some(result), error, ),
+      Err(e) => (None, Some(e), )
+    };
+    failed |= error.is_some();
+    let json_result = JsonBatchResult {
+      schema_version: crate::output::JSON_SCHEMA_VERSION,
+      index,
+      op: op.name(),
+      image,
+      result,
+      error,
+    };
+    match serde_json::to_string(&json_result) {
+      Ok(line) => println!("{}", line),
+      Err(e) => {
+        eprintln!("Error serializing batch result: {:?}", &e);
+        exit(crate::exit_codes::IO_ERR);
+      }
+    }
+  }
+
+  if failed {
+    exit(crate::exit_codes::BATCH_ERR);
+  }
+}
+
+/// Read operations from a file (or stdin) holding either a JSON array of operations, or
+/// newline-delimited JSON with one operation per line
+fn read_ops(file_name: &str) -> Result<Vec<BatchOp>, String> {
+  let mut contents = String::new();
+  let read = if file_name == STDIN_FILE_NAME {
+    io::stdin().read_to_string(&mut contents)
+  } else {
+    fs::File::open(file_name).and_then(|mut f| f.read_to_string(&mut contents))
+  };
+  if let Err(e) = read {
+    return Err(format!("{:?}", &e));
+  }
+
+  if contents.trim_start().starts_with('[') {
+    serde_json::from_str(&contents).map_err(|e| e.to_string())
+  } else {
+    contents.lines()
+      .enumerate()
+      .filter(|(_, line, )| !line.trim().is_empty())
+      .map(|(n, line, )| serde_json::from_str(line).map_err(|e| format!("Line {}: {}", n + 1, e)))
+      .collect()
+  }
+}
+
+/// Run one operation against a disk image, opening it if it isn't open already. An operation
+/// which partly failed (e.g. some files couldn't be extracted) has a result and an error.
+fn run_op<'a>(config: &Config, volumes: &mut HashMap<&'a str, OpenVolume<'a>>, image: &'a str, op: &BatchOp, force: bool) -> Result<(Value, Option<String>, ), String> {
+  // Batches can't ask for confirmation, so overwriting devices or disk images needs --force
+  if let Some(dest) = op.dest() {
+    crate::device::check_writable(dest)?;
+    let warnings = crate::confirm::destination_warnings(Path::new(dest), image);
+    if !force && !warnings.is_empty() {
+      return Err(format!("{}, use --force to write to it", warnings.join("; ")));
+    }
+  }
+
+  if !volumes.contains_key(image) {
+    volumes.insert(image, OpenVolume::open(image).map_err(|e| e.message)?);
+  }
+  let vol = volumes.get_mut(image).unwrap();
+
+  match op {
+    BatchOp::Info { .. } => {
+      let disk_file_sz = match vol.disk_file.get_ref().len() {
+        Ok(sz) => sz,
+        Err(e) => return Err(format!("Error getting disk image size: {:?}", &e))
+      };
+      Ok((to_value(crate::vh::info::JsonVolumeInfo::from(&vol.volume_header, disk_file_sz))?, None, ))
+    }
+    BatchOp::Hash { algorithms, defined_areas, .. } => {
+      let algorithms = algorithms.as_ref().unwrap_or(&config.hash.algorithms);
+      let opts = crate::hash::HashOptions {
+        defined_areas: *defined_areas,
+        ..Default::default()
+      };
+      if let Err(e) = vol.disk_file.seek(SeekFrom::Start(0)) {
+        return Err(format!("Error seeking disk image: {:?}", &e));
+      }
+      match crate::hash::hash_volume(&mut vol.disk_file, &vol.volume_header, algorithms, &opts) {
+        Ok(hashes) => Ok((to_value(hashes.into_json())?, None, )),
+        Err(e) => Err(format!("Error while reading disk image: {:?}", &e))
+      }
+    }
+    BatchOp::Cp { src, dest, verify, .. } => {
+      let verify = if *verify { Some(config.hash.algorithms.as_slice()) } else { None };
+      let copied = crate::vh::cp::copy_matching(vol, src, dest, false, verify, false).map_err(|e| e.message)?;
+      let failures = copied.iter().filter(|c| c.error.is_some() || c.verified == Some(false)).count();
+      Ok((to_value(copied)?, partial_error(failures), ))
+    }
+    BatchOp::Extract { partition, src, dest, recursive, numeric_owner, symlinks, sparse, verify, filter, resume, .. } => {
+      let mut opts = ExtractOptions::new(config);
+      opts.recursive = *recursive;
+      opts.numeric_owner = numeric_owner.unwrap_or(opts.numeric_owner);
+      opts.symlinks = symlinks.unwrap_or(opts.symlinks);
+      opts.sparse = sparse.unwrap_or(opts.sparse);
+      if let Some(filter) = filter {
+        opts.filter = EntryFilter::new(filter)?;
+      }
+      if *resume {
+        opts.resume = Some(ResumeCheck::Mtime);
+      }
+      if *verify {
+        opts.verify = Some(config.hash.algorithms.clone());
+      }
+
+      let mut efs_vol = OpenEfs::open(vol, *partition).map_err(|e| e.message)?;
+      let state = crate::efs::cp::extract_path(&mut efs_vol, src, dest, &opts).map_err(|e| e.message)?;
+      let failures = state.results.iter().filter(|r| r.error.is_some() || r.verified == Some(false)).count();
+      Ok((to_value(state.results)?, partial_error(failures), ))
+    }
+  }
+}
+
+/// Error for an operation where some of the entries it handled failed
+fn partial_error(failures: usize) -> Option<String> {
+  match failures {
+    0 => None,
+    n => Some(format!("{} entries failed", n))
+  }
+}
+
+/// Convert a result to a JSON value
+fn to_value<T: Serialize>(result: T) -> Result<Value, String> {
+  serde_json::to_value(result).map_err(|e| format!("{:?}", &e))
+}
+
+impl BatchOp {
+  /// Name of operation, as used in JSON
+  fn name(&self) -> &'static str {
+    match self {
+      BatchOp::Info { .. } => "info",
+      BatchOp::Hash { .. } => "hash",
+      BatchOp::Cp { .. } => "cp",
+      BatchOp::Extract { .. } => "extract"
+    }
+  }
+
+  /// Destination the operation writes to
+  fn dest(&self) -> Option<&str> {
+    match self {
+      BatchOp::Info { .. } | BatchOp::Hash { .. } => None,
+      BatchOp::Cp { dest, .. } | BatchOp::Extract { dest, .. } => Some(dest)
+    }
+  }
+
+  /// Disk image named by the operation
+  fn image(&self) -> Option<&str> {
+    match self {
+      BatchOp::Info { image } => image.as_deref(),
+      BatchOp::Hash { image, .. } => image.as_deref(),
+      BatchOp::Cp { image, .. } => image.as_deref(),
+      BatchOp::Extract { image, .. } => image.as_deref()
+    }
+  }
+}
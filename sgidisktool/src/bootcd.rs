@@ -0,0 +1,155 @@
+use serde::Serialize;
+
+use sgidisklib::efs::{Efs, InodeType};
+use sgidisklib::efs::dir::Directory;
+use sgidisklib::volhdr::PartitionType;
+
+use crate::color::{paint, Style};
+use crate::validate::{Report, Status};
+use crate::OpenVolume;
+
+/// Standalone shells in the volume directory which the PROM can boot an installation from
+const SASH_FILES: [&str; 3] = ["sash", "sashARCS", "sash64"];
+
+/// Directory of an installation CD holding the miniroot kernels, named `unix.<board>`
+const MINIROOT_DIR: &str = "/dist/miniroot";
+
+/// CPU boards IRIX runs on, and the machines built around them
+const BOARDS: [(&str, &str, ); 11] = [
+  ("IP19", "Challenge, Onyx (R4400)"),
+  ("IP20", "Indigo (R4000)"),
+  ("IP21", "Power Challenge, Power Onyx (R8000)"),
+  ("IP22", "Indy, Indigo2"),
+  ("IP25", "Power Challenge, Power Onyx (R10000)"),
+  ("IP26", "Power Indigo2 (R8000)"),
+  ("IP27", "Origin 200, Origin 2000, Onyx2"),
+  ("IP28", "Indigo2 (R10000)"),
+  ("IP30", "Octane"),
+  ("IP32", "O2"),
+  ("IP35", "Origin 300, Origin 3000, Fuel, Tezro"),
+];
+
+/// Boards whose PROMs boot 64-bit standalone programs, so need sash64
+const BOARDS_64: [&str; 6] = ["IP21", "IP25", "IP26", "IP27", "IP28", "IP30"];
+
+/// JSON representation of a board a CD's miniroot has a kernel for
+#[derive(Serialize)]
+pub(crate) struct JsonBoard {
+  /// Board, e.g. "IP22"
+  pub(crate) board: String,
+  /// Machines built around the board, if known
+  pub(crate) machines: Option<&'static str>,
+}
+
+/// JSON representation of whether an installation CD should boot, and on what
+#[derive(Serialize)]
+pub(crate) struct JsonCdVerdict {
+  /// Whether no installation CD check failed
+  pub(crate) bootable: bool,
+  /// Boards with a miniroot kernel and, if they need it, sash64
+  pub(crate) boards: Vec<JsonBoard>,
+}
+
+/// Run the checks particular to an IRIX installation CD: a standalone shell in the volume
+/// directory which the boot file names, and an EFS partition with the distribution, standalone
+/// tools and a miniroot kernel for each board it installs on
+pub(crate) fn run_checks(vol: &mut OpenVolume, report: &mut Report) -> JsonCdVerdict {
+  let failed_before = report.checks.iter().filter(|c| c.status == Status::Fail).count();
+  let vh = &vol.volume_header;
+  let sashes: Vec<&str> = SASH_FILES.iter()
+    .copied()
+    .filter(|sash| vh.files.iter().any(|f| f.in_use() && f.file_name.as_deref() == Some(*sash)))
+    .collect();
+  match sashes.is_empty() {
+    true => report.record("cd-sash", "volume header", Status::Fail, Some(format!("None of {} in the volume directory", SASH_FILES.join(", ")))),
+    false => report.record("cd-sash", "volume header", Status::Pass, Some(sashes.join(", ")))
+  }
+
+  match vh.boot_file.as_deref() {
+    None => report.record("cd-boot-file", "volume header", Status::Fail, Some("No boot file named; the PROM won't know to load sash".to_string())),
+    Some(boot_file) if sashes.iter().any(|sash| *sash == boot_file) => report.record("cd-boot-file", "volume header", Status::Pass, Some(boot_file.to_string())),
+    Some(boot_file) => report.record("cd-boot-file", "volume header", Status::Warn,
+                                     Some(format!("Boot file is {}, not a standalone shell in the volume directory", boot_file)))
+  }
+
+  let boards = check_efs(vol, report);
+  let needs_64 = boards.iter().filter(|b| BOARDS_64.contains(&b.board.as_str())).map(|b| b.board.as_str()).collect::<Vec<&str>>();
+  if !needs_64.is_empty() && !sashes.contains(&"sash64") {
+    report.record("cd-sash64", "volume header", Status::Warn, Some(format!("No sash64, which {} need", needs_64.join(", "))));
+  }
+  let boards = boards.into_iter()
+    .filter(|b| sashes.contains(&"sash64") || !BOARDS_64.contains(&b.board.as_str()))
+    .collect();
+
+  JsonCdVerdict {
+    bootable: report.checks.iter().filter(|c| c.status == Status::Fail).count() == failed_before,
+    boards,
+  }
+}
+
+/// Check the EFS partition of a CD, the root partition if it is one or else the first, has the
+/// distribution, standalone tools and miniroot, returning the boards with a miniroot kernel
+fn check_efs(vol: &mut OpenVolume, report: &mut Report) -> Vec<JsonBoard> {
+  let vh = &vol.volume_header;
+  let partition = vh.partitions.get(vh.root_partition)
+    .filter(|p| p.in_use() && p.partition_type == PartitionType::Efs)
+    .map(|_| vh.root_partition)
+    .or_else(|| vh.partitions.iter().position(|p| p.in_use() && p.partition_type == PartitionType::Efs));
+  let partition = match partition {
+    Some(partition) => partition,
+    None => {
+      report.record("cd-efs", "volume header", Status::Fail, Some("No EFS partition".to_string()));
+      return Vec::new();
+    }
+  };
+  let target = format!("partition {}", partition);
+  let efs = match Efs::read(&mut vol.disk_file, vh.sector_sz as u64, vh.partitions[partition].byte_start()) {
+    Ok(efs) => efs,
+    Err(e) => {
+      report.record("cd-efs", &target, Status::Fail, Some(format!("Unable to read: {:?}", &e)));
+      return Vec::new();
+    }
+  };
+  report.record("cd-efs", &target, Status::Pass, None);
+
+  for (check, dir, status, ) in [("cd-dist", "/dist", Status::Fail, ), ("cd-stand", "/stand", Status::Warn, )] {
+    match efs.lookup_path(&mut vol.disk_file, dir) {
+      Ok((_, inode, )) if inode.inode_type == InodeType::Directory => report.record(check, &target, Status::Pass, None),
+      _ => report.record(check, &target, status, Some(format!("No {} directory", dir)))
+    }
+  }
+
+  let kernels = efs.lookup_path(&mut vol.disk_file, MINIROOT_DIR)
+    .and_then(|(inode_id, _, )| Directory::read_dir(&mut vol.disk_file, &efs, inode_id));
+  let boards: Vec<JsonBoard> = match kernels {
+    Ok(dir) => dir.entries.keys()
+      .filter_map(|name| name.strip_prefix("unix."))
+      .map(|board| JsonBoard {
+        board: board.to_string(),
+        machines: BOARDS.iter().find(|(b, _, )| *b == board).map(|(_, machines, )| *machines),
+      })
+      .collect(),
+    Err(e) => {
+      report.record("cd-miniroot", &target, Status::Fail, Some(format!("Unable to read {}: {:?}", MINIROOT_DIR, &e)));
+      return Vec::new();
+    }
+  };
+  match boards.is_empty() {
+    true => report.record("cd-miniroot", &target, Status::Fail, Some(format!("No unix.<board> kernels in {}", MINIROOT_DIR))),
+    false => report.record("cd-miniroot", &target, Status::Pass,
+                           Some(boards.iter().map(|b| b.board.as_str()).collect::<Vec<&str>>().join(", ")))
+  }
+  boards
+}
+
+/// Print whether an installation CD should boot, and on what
+pub(crate) fn print_verdict(verdict: &JsonCdVerdict) {
+  if !verdict.bootable || verdict.boards.is_empty() {
+    println!("Installation CD: {}", paint("should not boot", Style::Error));
+    return;
+  }
+  println!("Installation CD: {}", paint("should boot on", Style::Good));
+  for board in &verdict.boards {
+    println!("  {} ({})", board.board, board.machines.unwrap_or("unknown machines"));
+  }
+}
@@ -0,0 +1,150 @@
+use std::env;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use atty::Stream;
+use serde::Deserialize;
+
+use sgidisklib::volhdr::PartitionType;
+
+/// Whether to color output written to stdout
+static STDOUT_COLOR: AtomicBool = AtomicBool::new(false);
+/// Whether to color output written to stderr
+static STDERR_COLOR: AtomicBool = AtomicBool::new(false);
+
+/// When to color output
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum ColorChoice {
+  /// Color if writing to a terminal and NO_COLOR is not set
+  Auto,
+  /// Always color
+  Always,
+  /// Never color
+  Never,
+}
+
+/// Text styles used in human readable output
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub(crate) enum Style {
+  /// Section headings
+  Heading,
+  /// Errors and failures
+  Error,
+  /// Warnings, e.g. short hashes or items past the end of the image
+  Warning,
+  /// Positive results
+  Good,
+  /// Filesystem partitions
+  Filesystem,
+  /// Volume Header and whole-volume partitions
+  Volume,
+  /// Other partitions and unremarkable items
+  Dim,
+}
+
+impl ColorChoice {
+  /// Parse from the name used on the CLI and in the config file
+  pub(crate) fn from_name(name: &str) -> Option<Self> {
+    match name {
+      "auto" => Some(ColorChoice::Auto),
+      "always" => Some(ColorChoice::Always),
+      "never" => Some(ColorChoice::Never),
+      _ => None
+    }
+  }
+
+  /// Whether to color output to a stream
+  fn enabled(&self, stream: Stream) -> bool {
+    match self {
+      ColorChoice::Always => true,
+      ColorChoice::Never => false,
+      ColorChoice::Auto => env::var_os("NO_COLOR").is_none() && atty::is(stream),
+    }
+  }
+}
+
+impl Default for ColorChoice {
+  fn default() -> Self {
+    ColorChoice::Auto
+  }
+}
+
+impl Style {
+  /// ANSI SGR parameters for style
+  fn sgr(&self) -> &'static str {
+    match self {
+      Style::Heading => "1",
+      Style::Error => "1;31",
+      Style::Warning => "33",
+      Style::Good => "32",
+      Style::Filesystem => "36",
+      Style::Volume => "35",
+      Style::Dim => "2",
+    }
+  }
+
+  /// Style used to highlight a partition type
+  pub(crate) fn for_partition_type(partition_type: PartitionType) -> Self {
+    match partition_type {
+      PartitionType::Efs | PartitionType::Xfs => Style::Filesystem,
+      PartitionType::VolumeHeader | PartitionType::EntireVolume => Style::Volume,
+      _ => Style::Dim,
+    }
+  }
+}
+
+/// Decide whether stdout and stderr will be colored
+pub(crate) fn init(choice: ColorChoice) {
+  STDOUT_COLOR.store(choice.enabled(Stream::Stdout), Ordering::Relaxed);
+  STDERR_COLOR.store(choice.enabled(Stream::Stderr), Ordering::Relaxed);
+}
+
+/// Decide whether stdout will be colored, e.g. not when a report is written to a file instead
+pub(crate) fn init_stdout(enabled: bool) {
+  STDOUT_COLOR.store(enabled, Ordering::Relaxed);
+}
+
+/// Wrap text in ANSI escapes for style
+fn escape(text: &str, style: Style) -> String {
+  format!("\x1b[{}m{}\x1b[0m", style.sgr(), text)
+}
+
+/// Style text to be printed to stdout
+pub(crate) fn paint(text: &str, style: Style) -> String {
+  if STDOUT_COLOR.load(Ordering::Relaxed) {
+    escape(text, style)
+  } else {
+    text.to_string()
+  }
+}
+
+/// Style text to be printed to stderr
+pub(crate) fn paint_err(text: &str, style: Style) -> String {
+  if STDERR_COLOR.load(Ordering::Relaxed) {
+    escape(text, style)
+  } else {
+    text.to_string()
+  }
+}
+
+/// Style whole data rows of a rendered table, leaving borders and header untouched. Painting rows
+/// after rendering keeps escape codes from upsetting the table's column width calculations.
+pub(crate) fn paint_table_rows(table: &str, row_styles: &[Option<Style>]) -> String {
+  // Rendered tables have a top border, header and header separator before the data rows
+  const DATA_ROW_START: usize = 3;
+
+  table.lines()
+    .enumerate()
+    .map(|(i, line, )| {
+      let style = i.checked_sub(DATA_ROW_START)
+        .and_then(|row| row_styles.get(row))
+        .copied()
+        .flatten();
+      match style {
+        Some(style) => paint(line, style),
+        None => line.to_string()
+      }
+    })
+    .map(|line| line + "\n")
+    .collect()
+}
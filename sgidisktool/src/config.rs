@@ -0,0 +1,185 @@
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+use std::process::exit;
+
+use clap::ArgMatches;
+use serde::Deserialize;
+
+use crate::color::ColorChoice;
+use crate::efs::cp::SymlinkPolicy;
+use crate::efs::sanitize::SanitizeMode;
+use crate::hash::HashAlgorithm;
+use crate::output::{DateFormat, OutputFormat};
+
+/// User configuration, read from a TOML file. Configured values are defaults which are applied
+/// before (and so overridden by) CLI flags.
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct Config {
+  /// Produce JSON output by default
+  pub(crate) json: bool,
+  /// When to color output
+  pub(crate) color: ColorChoice,
+  /// Treat warnings as failures
+  pub(crate) strict: bool,
+  /// strftime format of dates in human readable output
+  pub(crate) date_format: String,
+  /// Write dates in UTC rather than the local time zone
+  pub(crate) utc: bool,
+  /// Hash tool defaults
+  pub(crate) hash: HashConfig,
+  /// EFS extraction defaults
+  pub(crate) extract: ExtractConfig,
+}
+
+/// Hash tool configuration
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct HashConfig {
+  /// Hash algorithms to compute when none are given on the CLI
+  pub(crate) algorithms: Vec<HashAlgorithm>,
+}
+
+/// EFS extraction configuration
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct ExtractConfig {
+  /// Set owner and group to the numeric IDs recorded in the image
+  pub(crate) numeric_owner: bool,
+  /// How to extract symbolic links when not given on the CLI
+  pub(crate) symlinks: SymlinkPolicy,
+  /// When to rename entries whose names are invalid on the host
+  pub(crate) sanitize_names: SanitizeMode,
+  /// Leave holes in extracted files instead of writing runs of zeros
+  pub(crate) sparse: bool,
+  /// Number of threads extracting file contents
+  pub(crate) threads: usize,
+}
+
+impl Default for Config {
+  fn default() -> Self {
+    Config {
+      json: false,
+      color: ColorChoice::default(),
+      strict: false,
+      date_format: crate::output::DEFAULT_DATE_FORMAT.to_string(),
+      utc: false,
+      hash: HashConfig::default(),
+      extract: ExtractConfig::default(),
+    }
+  }
+}
+
+impl Default for HashConfig {
+  fn default() -> Self {
+    HashConfig {
+      algorithms: HashAlgorithm::ALL.to_vec(),
+    }
+  }
+}
+
+impl Default for ExtractConfig {
+  fn default() -> Self {
+    ExtractConfig {
+      numeric_owner: false,
+      symlinks: SymlinkPolicy::Literal,
+      sanitize_names: SanitizeMode::Auto,
+      sparse: false,
+      threads: 1,
+    }
+  }
+}
+
+impl Config {
+  /// Default location of the configuration file, `$XDG_CONFIG_HOME/sgidisktool/config.toml`
+  /// falling back to `~/.config/sgidisktool/config.toml`
+  fn default_path() -> Option<PathBuf> {
+    let config_dir = match env::var_os("XDG_CONFIG_HOME") {
+      Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+      _ => {
+        let mut dir = PathBuf::from(env::var_os("HOME")?);
+        dir.push(".config");
+        dir
+      }
+    };
+
+    let mut path = config_dir;
+    path.push("sgidisktool");
+    path.push("config.toml");
+    Some(path)
+  }
+
+  /// Load configuration from an explicitly named file, which must exist, or otherwise from the
+  /// default location if there is a file there
+  pub(crate) fn load(config_file_name: Option<&str>) -> Result<Self, String> {
+    let path = match config_file_name {
+      Some(name) => PathBuf::from(name),
+      None => match Self::default_path() {
+        Some(path) if path.is_file() => path,
+        _ => return Ok(Config::default())
+      }
+    };
+
+    let contents = match fs::read_to_string(&path) {
+      Ok(contents) => contents,
+      Err(e) => return Err(format!("Unable to read config file {:?}: {:?}", &path, &e))
+    };
+    match toml::from_str(&contents) {
+      Ok(config) => Ok(config),
+      Err(e) => Err(format!("Unable to parse config file {:?}: {}", &path, &e))
+    }
+  }
+
+  /// Load configuration, or quit if there is an error
+  pub(crate) fn load_or_quit(config_file_name: Option<&str>) -> Self {
+    match Self::load(config_file_name) {
+      Ok(config) => config,
+      Err(e) => {
+        eprintln!("Error: {}", &e);
+        exit(crate::exit_codes::CONFIG_ERR);
+      }
+    }
+  }
+
+  /// Whether to produce structured (JSON or YAML) output, from the CLI flags or configured default
+  pub(crate) fn json(&self, cli_matches: &ArgMatches) -> bool {
+    self.format(cli_matches).is_structured()
+  }
+
+  /// Format of output, from the CLI flags or configured default
+  pub(crate) fn format(&self, cli_matches: &ArgMatches) -> OutputFormat {
+    match cli_matches.value_of("format") {
+      Some(name) => OutputFormat::from_name(name).unwrap(),
+      None if cli_matches.is_present("porcelain") => OutputFormat::Porcelain,
+      None if self.json || cli_matches.is_present("json") => OutputFormat::Json,
+      None => OutputFormat::Text
+    }
+  }
+
+  /// Whether to treat warnings as failures, from the CLI flag or configured default
+  pub(crate) fn strict(&self, cli_matches: &ArgMatches) -> bool {
+    self.strict || cli_matches.is_present("strict")
+  }
+
+  /// How to write dates in human readable output, from the CLI flags or configured defaults,
+  /// or quit if the format is invalid
+  pub(crate) fn date_format(&self, cli_matches: &ArgMatches) -> DateFormat {
+    let format = cli_matches.value_of("date-format").unwrap_or(&self.date_format);
+    match DateFormat::new(format, self.utc || cli_matches.is_present("utc")) {
+      Ok(date_format) => date_format,
+      Err(e) => {
+        eprintln!("Error: {}", &e);
+        exit(crate::exit_codes::CLI_ARG_ERROR);
+      }
+    }
+  }
+
+  /// When to color output, from the CLI flag or configured default
+  pub(crate) fn color(&self, cli_matches: &ArgMatches) -> ColorChoice {
+    match cli_matches.value_of("color") {
+      Some(name) => ColorChoice::from_name(name).unwrap(),
+      None => self.color
+    }
+  }
+}
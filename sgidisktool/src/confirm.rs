@@ -0,0 +1,64 @@
+use std::fs;
+use std::io;
+use std::io::Write;
+use std::path::Path;
+use std::process::exit;
+
+use atty::Stream;
+
+use crate::color::{paint_err, Style};
+
+/// Warnings about a write destination which would overwrite data that can't be recovered: a
+/// raw block or character device, or the disk image being read
+pub(crate) fn destination_warnings(dest: &Path, disk_file_name: &str) -> Vec<String> {
+  let mut warnings = Vec::new();
+
+  if let Ok(meta) = fs::metadata(dest) {
+    if crate::device::is_device(&meta) {
+      warnings.push(format!("'{}' looks like a raw device; writing to it overwrites the data on the device", dest.to_string_lossy()));
+    }
+  }
+
+  if let (Ok(dest), Ok(disk_file), ) = (fs::canonicalize(dest), fs::canonicalize(disk_file_name), ) {
+    if dest == disk_file {
+      warnings.push(format!("'{}' is the disk image being read", dest.to_string_lossy()));
+    }
+  }
+
+  warnings
+}
+
+/// Quit if a write destination is a device, unless --force-device was given
+pub(crate) fn check_device_or_quit(dest: &Path) {
+  if let Err(message) = crate::device::check_writable(dest) {
+    eprintln!("{} {}", paint_err("Error:", Style::Error), message);
+    exit(crate::exit_codes::DEVICE_ERR);
+  }
+}
+
+/// Confirm a destructive operation described by a summary and warnings. Unless forced, the user
+/// is asked to confirm at a terminal; otherwise (e.g. in a script) --force is required. Quit if
+/// the operation is not confirmed.
+pub(crate) fn confirm_or_quit(summary: &str, warnings: &[String], force: bool) {
+  if force {
+    return;
+  }
+
+  eprintln!("{}", summary);
+  for warning in warnings {
+    eprintln!("{} {}", paint_err("Warning:", Style::Warning), warning);
+  }
+
+  if !atty::is(Stream::Stdin) {
+    eprintln!("{} Not running interactively, use --force to continue", paint_err("Error:", Style::Error));
+    exit(crate::exit_codes::NOT_CONFIRMED_ERR);
+  }
+
+  eprint!("Continue? [y/N] ");
+  let _ = io::stderr().flush();
+  let mut answer = String::new();
+  if io::stdin().read_line(&mut answer).is_err() || !matches!(answer.trim().to_lowercase().as_str(), "y" | "yes") {
+    eprintln!("Not confirmed, nothing was written");
+    exit(crate::exit_codes::NOT_CONFIRMED_ERR);
+  }
+}
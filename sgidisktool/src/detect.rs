@@ -0,0 +1,518 @@
+use std::fs::File;
+use std::io;
+use std::io::{Read, Seek, SeekFrom};
+use std::process::exit;
+
+use clap::ArgMatches;
+use serde::Serialize;
+use tabled::{Table, Tabled};
+
+use sgidisklib::content::{Identified, PROBE_SZ};
+use sgidisklib::efs::Efs;
+use sgidisklib::efs::release::{MediaType, ReleaseInfo};
+use sgidisklib::ewf::EWF_SIGNATURE;
+use sgidisklib::pc::PcPartitionTable;
+use sgidisklib::volhdr::{PartitionType, SgidiskVolume};
+
+use crate::color::{paint, paint_table_rows, Style};
+use crate::config::Config;
+use crate::image::DiskImage;
+
+/// Bytes at the start of the image searched for a Volume Header, if there isn't one at the start
+const VH_SCAN_SZ: u64 = 64 * 1024;
+/// Alignment of Volume Headers searched for
+const VH_SCAN_ALIGN: usize = 512;
+
+/// Signature of a MAME Compressed Hunks of Data container
+const CHD_SIGNATURE: &[u8] = b"MComprHD";
+/// Signature of a QEMU copy-on-write (qcow / qcow2) image
+const QCOW_SIGNATURE: &[u8] = b"QFI\xfb";
+/// Sync pattern at the start of every sector of a raw (2352 byte sector) CD image
+const RAW_CD_SYNC: [u8; 12] = [0x00, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x00];
+/// Size of a raw CD sector
+const RAW_CD_SECTOR_SZ: u64 = 2352;
+/// Size of the user data of a raw CD sector
+const RAW_CD_DATA_SZ: u64 = 2048;
+
+/// Kind of container a disk image is stored in
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) enum Container {
+  /// Plain sector-by-sector image
+  Raw,
+  /// Expert Witness Format (E01) evidence container
+  Ewf,
+  /// MAME Compressed Hunks of Data
+  Chd,
+  /// QEMU copy-on-write image
+  Qcow2,
+  /// CD image with raw 2352 byte sectors, e.g. a .bin from a .bin/.cue pair
+  RawCd,
+}
+
+/// What a partition appears to contain, from the first bytes of its contents
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) enum Content {
+  VolumeHeader,
+  Efs,
+  Xfs,
+  /// Recognised by a content handler registered by another crate
+  Registered,
+  /// Only zeros
+  Empty,
+  Unknown,
+  /// Partition starts past the end of the image
+  PastEnd,
+}
+
+/// JSON representation of what was detected in a disk image
+#[derive(Serialize)]
+pub(crate) struct JsonDetect {
+  pub(crate) container: Container,
+  /// Whether sgidisktool can read the contents of the container
+  pub(crate) supported: bool,
+  /// Size of the media in the container, in bytes
+  pub(crate) media_bytes: Option<u64>,
+  pub(crate) volume_header: Option<JsonDetectedVh>,
+  pub(crate) partitions: Vec<JsonDetectedPartition>,
+  /// PC partition table the media starts with instead of a Volume Header
+  pub(crate) pc_partition_table: Option<JsonPcPartitionTable>,
+  /// IRIX release and kind of media, from the files on its EFS partitions
+  pub(crate) release: Option<JsonRelease>,
+}
+
+/// JSON representation of the IRIX release and kind of media identified
+#[derive(Serialize)]
+pub(crate) struct JsonRelease {
+  /// EFS partition identified from
+  pub(crate) partition: usize,
+  /// Release, e.g. "6.5.22m", if a file names one
+  pub(crate) release: Option<String>,
+  /// "system-disk", "installation-cd", "distribution-cd" or "other"
+  pub(crate) media: &'static str,
+  pub(crate) miniroot: bool,
+  /// Files naming a release, most specific first
+  pub(crate) evidence: Vec<JsonReleaseEvidence>,
+}
+
+/// JSON representation of a file naming a release
+#[derive(Serialize)]
+pub(crate) struct JsonReleaseEvidence {
+  pub(crate) path: String,
+  pub(crate) release: String,
+}
+
+/// JSON representation of a Volume Header found in a disk image
+#[derive(Serialize)]
+pub(crate) struct JsonDetectedVh {
+  /// Offset of the Volume Header in the media, in bytes
+  pub(crate) offset: u64,
+  pub(crate) sector_sz: usize,
+  /// Number of the PC partition the Volume Header starts, if it is in one
+  pub(crate) pc_partition: Option<usize>,
+}
+
+/// JSON representation of a PC partition table (MBR or GPT)
+#[derive(Serialize)]
+pub(crate) struct JsonPcPartitionTable {
+  /// "MBR" or "GPT"
+  pub(crate) scheme: &'static str,
+  pub(crate) partitions: Vec<JsonPcPartition>,
+}
+
+/// JSON representation of a partition of a PC partition table
+#[derive(Serialize)]
+pub(crate) struct JsonPcPartition {
+  pub(crate) number: usize,
+  /// MBR type as two hex digits, or GPT type GUID
+  pub(crate) type_id: String,
+  pub(crate) name: Option<String>,
+  pub(crate) offset: u64,
+  pub(crate) size_bytes: u64,
+  pub(crate) volume_header: bool,
+}
+
+/// JSON representation of a partition and what it appears to contain
+#[derive(Serialize)]
+pub(crate) struct JsonDetectedPartition {
+  pub(crate) id: usize,
+  #[serde(skip)]
+  pub(crate) kind: PartitionType,
+  pub(crate) partition_type: String,
+  pub(crate) start_block: u64,
+  pub(crate) size_bytes: u64,
+  pub(crate) content: Content,
+  /// Name of the content handler which recognised the contents, if any
+  pub(crate) content_name: Option<&'static str>,
+  /// Description of the contents from their handler, e.g. a label
+  pub(crate) description: Option<String>,
+}
+
+/// Detect tool entry point: identify the container of a disk image, find its Volume Header and
+/// probe what each partition contains
+pub(crate) fn subcommand(config: &Config, disk_file_name: &str, cli_matches: &ArgMatches) {
+  let json = config.json(cli_matches);
+  if disk_file_name == crate::STDIN_FILE_NAME {
+    eprintln!("Detection needs to seek within the disk image, so it can't be read from stdin");
+    exit(crate::exit_codes::CLI_ARG_ERROR);
+  }
+
+  let detected = match detect(disk_file_name) {
+    Ok(detected) => detected,
+    Err(e) => {
+      eprintln!("Error while reading disk image '{}': {:?}", disk_file_name, &e);
+      exit(crate::exit_codes::IO_ERR);
+    }
+  };
+
+  if json {
+    crate::output::print_json("detect", &detected);
+  } else {
+    print_detected(&detected);
+  }
+}
+
+/// Identify the container of a disk image and, if its contents can be read, probe them
+pub(crate) fn detect(disk_file_name: &str) -> io::Result<JsonDetect> {
+  let mut file = File::open(disk_file_name)?;
+  let mut signature = Vec::with_capacity(RAW_CD_SYNC.len());
+  // A device holds the disk itself, and may not allow reading its signature unaligned
+  let is_device = crate::device::is_device_path(disk_file_name);
+  if !is_device {
+    (&mut file).take(RAW_CD_SYNC.len() as u64).read_to_end(&mut signature)?;
+  }
+  let container = if is_device {
+    Container::Raw
+  } else if signature.starts_with(&EWF_SIGNATURE) {
+    Container::Ewf
+  } else if signature.starts_with(CHD_SIGNATURE) {
+    Container::Chd
+  } else if signature.starts_with(QCOW_SIGNATURE) {
+    Container::Qcow2
+  } else if signature == RAW_CD_SYNC {
+    Container::RawCd
+  } else {
+    Container::Raw
+  };
+
+  let mut detected = JsonDetect {
+    container,
+    supported: true,
+    media_bytes: None,
+    volume_header: None,
+    partitions: Vec::new(),
+    pc_partition_table: None,
+    release: None,
+  };
+  match container {
+    Container::Chd | Container::Qcow2 => detected.supported = false,
+    Container::RawCd => {
+      let mut reader = RawCdReader::new(file)?;
+      probe(&mut reader, &mut detected)?;
+    }
+    Container::Raw | Container::Ewf => {
+      let mut reader = DiskImage::open(disk_file_name)
+        .map_err(|message| io::Error::new(io::ErrorKind::InvalidData, message))?;
+      probe(&mut reader, &mut detected)?;
+    }
+  }
+  Ok(detected)
+}
+
+/// Search the media for a Volume Header, at its start or in a partition of a PC partition table,
+/// and probe the contents of its partitions
+fn probe<R>(reader: &mut R, detected: &mut JsonDetect) -> io::Result<()>
+  where R: Read + Seek {
+  let media_sz = reader.seek(SeekFrom::End(0))?;
+  detected.media_bytes = Some(media_sz);
+
+  // The Volume Header is normally first, but may follow some other header
+  reader.seek(SeekFrom::Start(0))?;
+  let mut buf = Vec::new();
+  reader.by_ref().take(VH_SCAN_SZ).read_to_end(&mut buf)?;
+  let found = (0..buf.len()).step_by(VH_SCAN_ALIGN)
+    .filter(|offset| buf[*offset..].starts_with(&SgidiskVolume::MAGIC))
+    .find_map(|offset| SgidiskVolume::read(&mut &buf[offset..]).ok().map(|vh| (offset as u64, vh, None, )));
+  let found = match found {
+    Some(found) => Some(found),
+    None => probe_pc_partitions(reader, detected)?
+  };
+  let (vh_offset, vh, pc_partition, ) = match found {
+    Some(found) => found,
+    None => return Ok(())
+  };
+  detected.volume_header = Some(JsonDetectedVh {
+    offset: vh_offset,
+    sector_sz: vh.sector_sz,
+    pc_partition,
+  });
+
+  for (id, p, ) in vh.partitions.iter().enumerate().filter(|(_, p, )| p.in_use()) {
+    let start = vh_offset + p.byte_start();
+    let (content, identified, ) = if start >= media_sz {
+      (Content::PastEnd, None, )
+    } else {
+      probe_content(reader, start, p.partition_type)?
+    };
+    detected.partitions.push(JsonDetectedPartition {
+      id,
+      kind: p.partition_type,
+      partition_type: p.partition_type.to_string(),
+      start_block: p.block_start,
+      size_bytes: p.byte_len(),
+      content,
+      content_name: identified.as_ref().map(|i| i.name),
+      description: identified.and_then(|i| i.description),
+    });
+  }
+  detected.release = identify_release(reader, &vh, vh_offset, &detected.partitions);
+  Ok(())
+}
+
+/// Identify the IRIX release and kind of media from the EFS partitions found, preferring the first
+/// naming a release, then the first recognised as some kind of media
+fn identify_release<R>(reader: &mut R, vh: &SgidiskVolume, vh_offset: u64, partitions: &[JsonDetectedPartition]) -> Option<JsonRelease>
+  where R: Read + Seek {
+  let identified: Vec<(usize, ReleaseInfo, )> = partitions.iter()
+    .filter(|p| p.content == Content::Efs)
+    .filter_map(|p| {
+      let efs = Efs::read(reader, vh.sector_sz as u64, vh_offset + vh.partitions[p.id].byte_start()).ok()?;
+      Some((p.id, efs.identify_release(reader), ))
+    })
+    .collect();
+  let best = identified.iter().position(|(_, info, )| info.release.is_some())
+    .or_else(|| identified.iter().position(|(_, info, )| info.media != MediaType::Other))
+    .unwrap_or(0);
+  let (partition, info, ) = identified.into_iter().nth(best)?;
+  Some(JsonRelease {
+    partition,
+    release: info.release,
+    media: match info.media {
+      MediaType::SystemDisk => "system-disk",
+      MediaType::InstallationCd => "installation-cd",
+      MediaType::DistributionCd => "distribution-cd",
+      MediaType::Other => "other"
+    },
+    miniroot: info.miniroot,
+    evidence: info.evidence.into_iter()
+      .map(|e| JsonReleaseEvidence {
+        path: e.path,
+        release: e.release,
+      })
+      .collect(),
+  })
+}
+
+/// Read the PC partition table the media starts with, if any, and the Volume Header in the first
+/// of its partitions which has one, with its offset and the partition's number
+fn probe_pc_partitions<R>(reader: &mut R, detected: &mut JsonDetect) -> io::Result<Option<(u64, SgidiskVolume, Option<usize>, )>>
+  where R: Read + Seek {
+  let table = match PcPartitionTable::read(reader) {
+    Ok(Some(table)) => table,
+    _ => return Ok(None)
+  };
+  detected.pc_partition_table = Some(JsonPcPartitionTable {
+    scheme: table.scheme.name(),
+    partitions: table.partitions.iter()
+      .map(|p| JsonPcPartition {
+        number: p.number,
+        type_id: p.type_id.clone(),
+        name: p.name.clone(),
+        offset: p.start_byte,
+        size_bytes: p.size_bytes,
+        volume_header: p.has_volume_header,
+      })
+      .collect(),
+  });
+
+  let p = match table.partitions.iter().find(|p| p.has_volume_header) {
+    Some(p) => p,
+    None => return Ok(None)
+  };
+  reader.seek(SeekFrom::Start(p.start_byte))?;
+  match SgidiskVolume::read(reader) {
+    Ok(vh) => Ok(Some((p.start_byte, vh, Some(p.number), ))),
+    Err(_) => Ok(None)
+  }
+}
+
+/// Identify what a partition of a type starting at an offset in the media contains, with the
+/// content handler which recognised it
+fn probe_content<R>(reader: &mut R, start: u64, partition_type: PartitionType) -> io::Result<(Content, Option<Identified>, )>
+  where R: Read + Seek {
+  reader.seek(SeekFrom::Start(start))?;
+  let mut buf = Vec::new();
+  reader.by_ref().take(PROBE_SZ as u64).read_to_end(&mut buf)?;
+
+  let identified = sgidisklib::content::identify(partition_type, &buf);
+  let content = match &identified {
+    Some(Identified { builtin: true, name: "volume-header", .. }) => Content::VolumeHeader,
+    Some(Identified { builtin: true, name: "xfs", .. }) => Content::Xfs,
+    Some(Identified { builtin: true, name: "efs", .. }) => Content::Efs,
+    Some(_) => Content::Registered,
+    None if buf.iter().all(|b| *b == 0) => Content::Empty,
+    None => Content::Unknown
+  };
+  Ok((content, identified, ))
+}
+
+/// Print what was detected
+pub(crate) fn print_detected(detected: &JsonDetect) {
+  let container = format!("{:?}", detected.container);
+  if detected.supported {
+    println!("Container: {}", paint(&container, Style::Good));
+  } else {
+    println!("Container: {} (not supported; convert to a raw image to read it)", paint(&container, Style::Warning));
+    return;
+  }
+  if let Some(media_bytes) = detected.media_bytes {
+    println!("Media size: {} bytes", media_bytes);
+  }
+
+  if let Some(table) = &detected.pc_partition_table {
+    println!("{}", paint(&format!("PC partition table: {}, instead of an SGI Volume Header", table.scheme), Style::Warning));
+    for p in &table.partitions {
+      println!("  Partition {}: type {}{}, at offset {} for {} bytes{}", p.number, p.type_id,
+               p.name.as_ref().map(|name| format!(" ('{}')", name)).unwrap_or_default(), p.offset, p.size_bytes,
+               if p.volume_header { ", holds an SGI Volume Header" } else { "" });
+    }
+  }
+
+  let vh = match &detected.volume_header {
+    Some(vh) => vh,
+    None => {
+      println!("{}", paint("No SGI Volume Header found", Style::Warning));
+      return;
+    }
+  };
+  match vh.pc_partition {
+    Some(number) => println!("Volume Header: at offset {} in PC partition {} ({} byte sectors); read automatically, or give --offset {}",
+                             vh.offset, number, vh.sector_sz, vh.offset),
+    None => println!("Volume Header: at offset {} ({} byte sectors)", vh.offset, vh.sector_sz)
+  }
+
+  #[derive(Tabled)]
+  struct DisplayPartition {
+    #[header("Id")]
+    id: usize,
+    #[header("Partition Type")]
+    partition_type: String,
+    #[header("Start Block")]
+    start_block: u64,
+    #[header("Size (bytes)")]
+    size_bytes: u64,
+    #[header("Contents")]
+    content: String,
+  }
+
+  // Highlight partitions whose contents don't match their type
+  let styles = detected.partitions.iter()
+    .map(|p| match (p.content, p.kind, ) {
+      (Content::PastEnd, _, ) => Some(Style::Warning),
+      (Content::Efs, PartitionType::Efs, ) | (Content::Xfs, PartitionType::Xfs, ) => Some(Style::Filesystem),
+      (Content::Efs, _, ) | (Content::Xfs, _, ) => Some(Style::Warning),
+      _ => None
+    })
+    .collect::<Vec<Option<Style>>>();
+  let tab = detected.partitions.iter()
+    .map(|p| DisplayPartition {
+      id: p.id,
+      partition_type: p.partition_type.clone(),
+      start_block: p.start_block,
+      size_bytes: p.size_bytes,
+      content: match (p.content, p.content_name, &p.description, ) {
+        (Content::Registered, Some(name), Some(description), ) => format!("{} ({})", name, description),
+        (Content::Registered, Some(name), None, ) => name.to_string(),
+        (content, _, Some(description), ) => format!("{:?} ({})", content, description),
+        (content, _, None, ) => format!("{:?}", content)
+      },
+    })
+    .collect::<Vec<DisplayPartition>>();
+
+  println!();
+  println!("{}", paint("Partitions:", Style::Heading));
+  let table = Table::new(tab).with(crate::table_fmt()).to_string();
+  print!("{}", paint_table_rows(&table, &styles));
+
+  if let Some(release) = &detected.release {
+    println!();
+    println!("IRIX release: {} ({}, from partition {}{})", release.release.as_deref().unwrap_or("unknown"),
+             release.media.replace('-', " "), release.partition, if release.miniroot { ", with a miniroot" } else { "" });
+    for e in &release.evidence {
+      println!("  {}: {}", e.path, e.release);
+    }
+  }
+}
+
+/// Reader over the user data of a raw CD image, skipping the sync pattern, header and error
+/// correction data of each 2352 byte sector
+struct RawCdReader<R> {
+  inner: R,
+  /// Offset of user data within each sector: 16 for mode 1, 24 for mode 2 form 1
+  data_offset: u64,
+  /// Size of the user data in bytes
+  len: u64,
+  /// Position within the user data
+  pos: u64,
+}
+
+impl<R> RawCdReader<R>
+  where R: Read + Seek {
+  /// Read the sector mode of a raw CD image, from its first sector
+  fn new(mut inner: R) -> io::Result<Self> {
+    let mut header = [0u8; 16];
+    inner.seek(SeekFrom::Start(0))?;
+    inner.read_exact(&mut header)?;
+    let data_offset = if header[15] == 2 { 24 } else { 16 };
+    let len = inner.seek(SeekFrom::End(0))? / RAW_CD_SECTOR_SZ * RAW_CD_DATA_SZ;
+    Ok(RawCdReader {
+      inner,
+      data_offset,
+      len,
+      pos: 0,
+    })
+  }
+}
+
+impl<R> Read for RawCdReader<R>
+  where R: Read + Seek {
+  fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+    if self.pos >= self.len {
+      return Ok(0);
+    }
+    let (sector, within, ) = (self.pos / RAW_CD_DATA_SZ, self.pos % RAW_CD_DATA_SZ, );
+    let n = buf.len().min((RAW_CD_DATA_SZ - within) as usize);
+    self.inner.seek(SeekFrom::Start(sector * RAW_CD_SECTOR_SZ + self.data_offset + within))?;
+    let read = self.inner.read(&mut buf[..n])?;
+    self.pos += read as u64;
+    Ok(read)
+  }
+}
+
+impl<R> Seek for RawCdReader<R>
+  where R: Read + Seek {
+  fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+    let new_pos = match pos {
+      SeekFrom::Start(offset) => Some(offset),
+      SeekFrom::End(offset) => offset_by(self.len, offset),
+      SeekFrom::Current(offset) => offset_by(self.pos, offset)
+    };
+    match new_pos {
+      Some(new_pos) => {
+        self.pos = new_pos;
+        Ok(new_pos)
+      }
+      None => Err(io::Error::new(io::ErrorKind::InvalidInput, "Seek before start of CD image"))
+    }
+  }
+}
+
+/// Position moved by a signed offset, unless it would be before the start
+fn offset_by(pos: u64, offset: i64) -> Option<u64> {
+  if offset < 0 {
+    pos.checked_sub(offset.unsigned_abs())
+  } else {
+    pos.checked_add(offset as u64)
+  }
+}
@@ -0,0 +1,220 @@
+//! Raw disk devices (e.g. /dev/sdb, /dev/rdisk2, \\.\PhysicalDrive1), for reading disks pulled
+//! from SGI machines. Devices are only ever opened read-only, and are read in whole sectors since
+//! raw devices on some systems refuse unaligned reads. Writing to a device is refused unless
+//! --force-device is given.
+
+use std::fs;
+use std::fs::File;
+use std::io;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use sgidisklib::cache::BlockCache;
+
+/// Whether --force-device was given, allowing devices to be written to
+static FORCE_DEVICE: AtomicBool = AtomicBool::new(false);
+
+/// Sector size assumed when the device can't be asked for it
+const DEFAULT_SECTOR_SZ: u64 = 512;
+/// Size of each read from a device
+const DEVICE_READ_SZ: u64 = 64 * 1024;
+/// Number of sectors kept in memory after reading them
+const DEVICE_CACHE_SECTORS: usize = 1024;
+
+/// Raw disk device opened read-only
+#[derive(Debug)]
+pub(crate) struct Device {
+  /// Device read in whole sectors
+  reader: BlockCache<File>,
+  /// Size of a sector, as reported by the device
+  sector_sz: u64,
+  /// Size of the media, in bytes
+  size: u64,
+}
+
+/// Set up whether devices may be written to
+pub(crate) fn init(force_device: bool) {
+  FORCE_DEVICE.store(force_device, Ordering::Relaxed);
+}
+
+/// Whether a path names a block or character device
+pub(crate) fn is_device_path<P: AsRef<Path>>(path: P) -> bool {
+  #[cfg(windows)]
+  if path.as_ref().to_string_lossy().starts_with(r"\\.\") {
+    return true;
+  }
+  fs::metadata(path).map(|meta| is_device(&meta)).unwrap_or(false)
+}
+
+/// Whether file metadata is that of a block or character device
+#[cfg(unix)]
+pub(crate) fn is_device(meta: &fs::Metadata) -> bool {
+  use std::os::unix::fs::FileTypeExt;
+  meta.file_type().is_block_device() || meta.file_type().is_char_device()
+}
+
+/// Whether file metadata is that of a block or character device
+#[cfg(not(unix))]
+pub(crate) fn is_device(_meta: &fs::Metadata) -> bool {
+  false
+}
+
+/// Check that a write destination may be written to: anything but a device, or a device if
+/// --force-device was given
+pub(crate) fn check_writable<P: AsRef<Path>>(dest: P) -> Result<(), String> {
+  if is_device_path(&dest) && !FORCE_DEVICE.load(Ordering::Relaxed) {
+    return Err(format!("'{}' is a device; refusing to write to it without --force-device", dest.as_ref().to_string_lossy()));
+  }
+  Ok(())
+}
+
+impl Device {
+  /// Open a device read-only, asking it for its sector and media sizes
+  pub(crate) fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+    let mut file = File::open(path)?;
+    let sector_sz = sector_size(&file).unwrap_or(DEFAULT_SECTOR_SZ);
+    let size = match media_size(&file) {
+      Some(size) => size,
+      None => file.seek(SeekFrom::End(0))?
+    };
+    file.seek(SeekFrom::Start(0))?;
+
+    let readahead = (DEVICE_READ_SZ / sector_sz).max(1);
+    Ok(Device {
+      reader: BlockCache::new(file, sector_sz, readahead, DEVICE_CACHE_SECTORS),
+      sector_sz,
+      size,
+    })
+  }
+
+  /// Size of a sector, in bytes
+  pub(crate) fn sector_sz(&self) -> u64 {
+    self.sector_sz
+  }
+
+  /// Size of the media, in bytes
+  pub(crate) fn size(&self) -> u64 {
+    self.size
+  }
+}
+
+impl Read for Device {
+  fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+    self.reader.read(buf)
+  }
+}
+
+impl Seek for Device {
+  fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+    match pos {
+      // Raw devices don't all report where they end when seeking
+      SeekFrom::End(delta) => match crate::positional::offset_by(self.size, delta) {
+        Some(offset) => self.reader.seek(SeekFrom::Start(offset)),
+        None => Err(io::Error::new(io::ErrorKind::InvalidInput, "Invalid seek to a negative or overflowing position"))
+      },
+      pos => self.reader.seek(pos)
+    }
+  }
+}
+
+/// Logical sector size of a block device
+#[cfg(target_os = "linux")]
+fn sector_size(file: &File) -> Option<u64> {
+  use std::os::unix::io::AsRawFd;
+  /// ioctl getting the logical sector size, as an int
+  const BLKSSZGET: libc::c_ulong = 0x1268;
+  let mut sector_sz: libc::c_int = 0;
+  match unsafe { libc::ioctl(file.as_raw_fd(), BLKSSZGET as _, &mut sector_sz) } {
+    0 if sector_sz > 0 => Some(sector_sz as u64),
+    _ => None
+  }
+}
+
+/// Size of a block device, in bytes
+#[cfg(target_os = "linux")]
+pub(crate) fn media_size(file: &File) -> Option<u64> {
+  use std::os::unix::io::AsRawFd;
+  /// ioctl getting the device size in bytes, as a u64
+  const BLKGETSIZE64: libc::c_ulong = 0x8008_1272;
+  let mut size: u64 = 0;
+  match unsafe { libc::ioctl(file.as_raw_fd(), BLKGETSIZE64 as _, &mut size) } {
+    0 => Some(size),
+    _ => None
+  }
+}
+
+/// Block size of a disk device
+#[cfg(target_os = "macos")]
+fn sector_size(file: &File) -> Option<u64> {
+  use std::os::unix::io::AsRawFd;
+  /// ioctl getting the block size, as a u32
+  const DKIOCGETBLOCKSIZE: libc::c_ulong = 0x4004_6418;
+  let mut sector_sz: u32 = 0;
+  match unsafe { libc::ioctl(file.as_raw_fd(), DKIOCGETBLOCKSIZE, &mut sector_sz) } {
+    0 if sector_sz > 0 => Some(sector_sz as u64),
+    _ => None
+  }
+}
+
+/// Size of a disk device, in bytes
+#[cfg(target_os = "macos")]
+pub(crate) fn media_size(file: &File) -> Option<u64> {
+  use std::os::unix::io::AsRawFd;
+  /// ioctl getting the number of blocks, as a u64
+  const DKIOCGETBLOCKCOUNT: libc::c_ulong = 0x4008_6419;
+  let mut blocks: u64 = 0;
+  match unsafe { libc::ioctl(file.as_raw_fd(), DKIOCGETBLOCKCOUNT, &mut blocks) } {
+    0 => sector_size(file).map(|sector_sz| blocks * sector_sz),
+    _ => None
+  }
+}
+
+/// Bytes per sector of a physical drive
+#[cfg(windows)]
+fn sector_size(file: &File) -> Option<u64> {
+  use windows_sys::Win32::System::Ioctl::{DISK_GEOMETRY, IOCTL_DISK_GET_DRIVE_GEOMETRY};
+  let geometry: DISK_GEOMETRY = device_io_control(file, IOCTL_DISK_GET_DRIVE_GEOMETRY)?;
+  match geometry.BytesPerSector {
+    0 => None,
+    sector_sz => Some(sector_sz as u64)
+  }
+}
+
+/// Size of a physical drive, in bytes
+#[cfg(windows)]
+pub(crate) fn media_size(file: &File) -> Option<u64> {
+  use windows_sys::Win32::System::Ioctl::{GET_LENGTH_INFORMATION, IOCTL_DISK_GET_LENGTH_INFO};
+  let length: GET_LENGTH_INFORMATION = device_io_control(file, IOCTL_DISK_GET_LENGTH_INFO)?;
+  u64::try_from(length.Length).ok()
+}
+
+/// Make a DeviceIoControl request with no input, returning its output structure
+#[cfg(windows)]
+fn device_io_control<T>(file: &File, code: u32) -> Option<T> {
+  use std::os::windows::io::AsRawHandle;
+  use windows_sys::Win32::System::IO::DeviceIoControl;
+  let mut out = std::mem::MaybeUninit::<T>::zeroed();
+  let mut returned = 0u32;
+  let ok = unsafe {
+    DeviceIoControl(file.as_raw_handle() as _, code, std::ptr::null(), 0,
+      out.as_mut_ptr() as _, std::mem::size_of::<T>() as u32, &mut returned, std::ptr::null_mut())
+  };
+  if ok != 0 && returned as usize == std::mem::size_of::<T>() {
+    Some(unsafe { out.assume_init() })
+  } else {
+    None
+  }
+}
+
+/// Sector size isn't known on other systems
+#[cfg(not(any(target_os = "linux", target_os = "macos", windows)))]
+fn sector_size(_file: &File) -> Option<u64> {
+  None
+}
+
+/// Media size is found by seeking to the end on other systems
+#[cfg(not(any(target_os = "linux", target_os = "macos", windows)))]
+pub(crate) fn media_size(_file: &File) -> Option<u64> {
+  None
+}
@@ -0,0 +1,269 @@
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::Path;
+use std::process::exit;
+
+use clap::ArgMatches;
+use serde::Serialize;
+
+use sgidisklib::volhdr::SgidiskVolume;
+use sgidisklib::volhdr::size::SizeVerdict;
+
+use crate::color::{paint, Style};
+use crate::config::Config;
+use crate::detect::{Container, JsonDetect};
+use crate::exit_codes::CommandError;
+use crate::validate::{JsonCheck, Status};
+use crate::OpenVolume;
+
+/// Fix which can safely be applied in place
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+enum Fix {
+  /// Rewrite the Volume Header's checksum, leaving the rest of it as is
+  VhChecksum,
+  /// Extend a truncated image with zeros to the size of the volume
+  PadImage { missing_bytes: u64 },
+}
+
+/// JSON representation of a problem found and what to do about it
+#[derive(Serialize)]
+struct JsonRemedy {
+  /// Name of the validator which found the problem, e.g. "vh-checksum"
+  check: &'static str,
+  target: String,
+  status: Status,
+  problem: String,
+  suggestion: String,
+  /// Fix which --fix would apply, if any
+  fix: Option<Fix>,
+}
+
+/// JSON representation of a diagnosis
+#[derive(Serialize)]
+struct JsonDoctor {
+  /// Worst outcome of the problems not fixed
+  status: Status,
+  remedies: Vec<JsonRemedy>,
+  /// Fixes applied, or which would have been on a dry run
+  fixes: Vec<Fix>,
+  dry_run: bool,
+}
+
+/// Doctor tool entry point: detect and validate the disk image, suggest what to do about each
+/// problem found and, with --fix, apply the fixes which are safe
+pub(crate) fn subcommand(config: &Config, disk_file_name: &str, cli_matches: &ArgMatches) {
+  let json = config.json(cli_matches);
+  let dry_run = cli_matches.is_present("dry-run");
+  let detected = match crate::detect::detect(disk_file_name) {
+    Ok(detected) => detected,
+    Err(e) => {
+      eprintln!("Error while reading disk image '{}': {:?}", disk_file_name, &e);
+      exit(crate::exit_codes::IO_ERR);
+    }
+  };
+
+  let mut remedies = Vec::new();
+  let mut vol = match OpenVolume::open(disk_file_name) {
+    Ok(vol) => Some(vol),
+    Err(e) => {
+      remedies.push(unopened_remedy(&detected, &e));
+      None
+    }
+  };
+  if let Some(vol) = &mut vol {
+    let file_sz = match vol.disk_file.get_ref().len() {
+      Ok(sz) => sz,
+      Err(e) => {
+        eprintln!("Error while reading disk image: {:?}", &e);
+        exit(crate::exit_codes::IO_ERR);
+      }
+    };
+    let report = crate::validate::run_checks(vol, file_sz);
+    let is_device = crate::device::is_device_path(disk_file_name);
+    remedies.extend(report.checks.iter()
+      .filter(|c| c.status != Status::Pass)
+      .map(|c| remedy(c, &vol.volume_header, file_sz, is_device)));
+  }
+
+  // Only a plain image (or device) with the Volume Header at its start can be fixed in place
+  let in_place = detected.container == Container::Raw && image_offset(&detected) == Some(0) && crate::image::offset() == 0;
+  let mut fixes: Vec<Fix> = Vec::new();
+  if cli_matches.is_present("fix") {
+    fixes = remedies.iter().filter_map(|r| r.fix).collect();
+    if !fixes.is_empty() && !in_place {
+      eprintln!("'{}' isn't a raw disk image starting with a Volume Header, so it can't be fixed in place", disk_file_name);
+      exit(crate::exit_codes::CLI_ARG_ERROR);
+    }
+    if !fixes.is_empty() && !dry_run {
+      drop(vol);
+      apply_or_quit(disk_file_name, &fixes, cli_matches.is_present("force"));
+    }
+  }
+
+  let status = remedies.iter()
+    .filter(|r| dry_run || r.fix.map_or(true, |fix| !fixes.contains(&fix)))
+    .map(|r| r.status)
+    .max()
+    .unwrap_or(Status::Pass);
+  if json {
+    crate::output::print_json("doctor", &JsonDoctor {
+      status,
+      remedies,
+      fixes,
+      dry_run,
+    });
+  } else {
+    print_diagnosis(&remedies, &fixes, dry_run);
+  }
+
+  match status {
+    Status::Fail => exit(crate::exit_codes::VALIDATE_ERR),
+    Status::Warn if config.strict(cli_matches) => exit(crate::exit_codes::STRICT_ERR),
+    _ => {}
+  }
+}
+
+/// Offset of the Volume Header detected, if there is one
+fn image_offset(detected: &JsonDetect) -> Option<u64> {
+  detected.volume_header.as_ref().map(|vh| vh.offset)
+}
+
+/// What to do about a disk image whose Volume Header couldn't be read
+fn unopened_remedy(detected: &JsonDetect, e: &CommandError) -> JsonRemedy {
+  let suggestion = if !detected.supported {
+    format!("{:?} containers can't be read; convert the image to a raw one first", detected.container)
+  } else if detected.volume_header.is_none() && detected.pc_partition_table.is_some() {
+    "The PC partition table has no partition holding an SGI Volume Header; if the SGI disk is elsewhere in the image, give its offset with --offset".to_string()
+  } else if detected.volume_header.is_none() && crate::image::offset() == 0 {
+    "No SGI Volume Header was found; if the image has a header of its own or the disk starts later in it, give the offset of the Volume Header with --offset".to_string()
+  } else {
+    "If the media has read errors, retry them with --retries, or read what can be with --skip-unreadable and record the rest with --bad-block-map".to_string()
+  };
+  JsonRemedy {
+    check: "volume-header",
+    target: "volume header".to_string(),
+    status: Status::Fail,
+    problem: e.message.clone(),
+    suggestion,
+    fix: None,
+  }
+}
+
+/// What to do about a check which didn't pass
+fn remedy(check: &JsonCheck, vh: &SgidiskVolume, file_sz: u64, is_device: bool) -> JsonRemedy {
+  let fail = check.status == Status::Fail;
+  let mut fix = None;
+  let suggestion = match check.check {
+    "vh-checksum" => {
+      fix = Some(Fix::VhChecksum);
+      "Checksum invalid; if the partitions and volume files listed by `vh info` look right, rewrite it with `doctor --fix`, otherwise the Volume Header itself is damaged".to_string()
+    }
+    "image-size" => match vh.check_size(file_sz).verdict {
+      SizeVerdict::Truncated { missing_bytes } => {
+        // A device is as long as it is
+        if !is_device {
+          fix = Some(Fix::PadImage { missing_bytes });
+        }
+        format!("EntireVolume extends {} bytes past EOF; the image is likely truncated, so re-image the media if possible, or pad it with zeros with `doctor --fix` so it has the volume's size",
+                missing_bytes)
+      }
+      SizeVerdict::Padded { extra_bytes } =>
+        format!("The image is {} bytes longer than the volume, usually padding added when imaging; `hash --defined-areas` gives a hash which ignores it", extra_bytes),
+      _ => "The label describes a larger disk than the drive holds, and may have been copied from another disk; check the partitions with `vh info`".to_string()
+    },
+    "partition-layout" if fail => "The partition runs past the end of the image, which is likely truncated; its contents past the end can't be read".to_string(),
+    "partition-layout" => "The partition table is unusual, perhaps edited by hand or copied from another disk; check it with `vh info`".to_string(),
+    "partition-alignment" => "Harmless when reading; if old firmware is to boot the disk, repartition it with fx on IRIX".to_string(),
+    "volume-file-bounds" if fail => "The volume file is cut off by the end of the image, which is likely truncated; copy out what remains with `vh cp`".to_string(),
+    "volume-file-bounds" => "The volume file lies outside the volume header partition, where IRIX may overwrite it; copy it out with `vh cp`".to_string(),
+    "boot-file" if fail => "The disk won't boot; add the boot file, or name an existing one, with dvhtool on IRIX".to_string(),
+    "boot-file" => "sash loads the boot file from the root partition; check it is there with `efs ls`".to_string(),
+    "boot-executable" => "The PROM can't load the boot file; replace it with dvhtool on IRIX".to_string(),
+    "root-partition" | "swap-partition" if fail => "Correct the partition named in the Volume Header with dvhtool on IRIX".to_string(),
+    "root-partition" | "swap-partition" => "Harmless when reading, though IRIX may not boot from or swap to it".to_string(),
+    "efs-superblock" => "The superblock is damaged or the partition doesn't hold EFS; check what it holds with `detect`, and otherwise run fsck on IRIX, which can use the replica superblock".to_string(),
+    "efs-geometry" => "The filesystem was made for another partition or disk; harmless when reading, but don't grow it on IRIX".to_string(),
+    "efs-bitmap" if fail => "Blocks in use are marked free, so writing to the filesystem can overwrite files; run fsck on IRIX before mounting it read-write. Reading is unaffected".to_string(),
+    "efs-bitmap" => "Space lost in a crash, which fsck on IRIX reclaims; reading is unaffected".to_string(),
+    "efs-directories" => "If the media has read errors, retry them with --retries, or read what can be with --skip-unreadable and record the rest with --bad-block-map; otherwise run fsck on IRIX".to_string(),
+    "efs-duplicate-names" => "Only the first entry of each name can be opened; list every entry with `efs ls --sort disk`, and run fsck on IRIX".to_string(),
+    "efs-link-counts" if fail => "Inodes with too low a link count are freed while still in use; run fsck on IRIX before writing to the filesystem".to_string(),
+    "efs-link-counts" => "fsck on IRIX corrects the counts; reading is unaffected".to_string(),
+    _ => "See `validate` for details".to_string()
+  };
+  JsonRemedy {
+    check: check.check,
+    target: check.target.clone(),
+    status: check.status,
+    problem: check.detail.clone().unwrap_or_else(|| format!("{} check didn't pass", check.check)),
+    suggestion,
+    fix,
+  }
+}
+
+/// Apply fixes to the disk image in place, after confirmation
+fn apply_or_quit(disk_file_name: &str, fixes: &[Fix], force: bool) {
+  crate::confirm::check_device_or_quit(Path::new(disk_file_name));
+  let warnings: Vec<String> = fixes.iter().map(|fix| fix.describe()).collect();
+  crate::confirm::confirm_or_quit(&format!("Fixing '{}' in place", disk_file_name), &warnings, force);
+  let applied = OpenOptions::new().read(true).write(true).open(disk_file_name)
+    .and_then(|mut file| fixes.iter().try_for_each(|fix| fix.apply(&mut file)));
+  if let Err(e) = applied {
+    eprintln!("Error fixing disk image '{}': {:?}", disk_file_name, &e);
+    exit(crate::exit_codes::IO_ERR);
+  }
+}
+
+impl Fix {
+  /// What the fix changes
+  fn describe(&self) -> String {
+    match self {
+      Fix::VhChecksum => "Rewrite the Volume Header checksum".to_string(),
+      Fix::PadImage { missing_bytes } => format!("Pad the image with {} bytes of zeros", missing_bytes)
+    }
+  }
+
+  /// Apply the fix to a disk image
+  fn apply(&self, file: &mut File) -> io::Result<()> {
+    match self {
+      Fix::VhChecksum => {
+        let mut header = vec![0; SgidiskVolume::SIZE];
+        file.seek(SeekFrom::Start(0))?;
+        file.read_exact(&mut header)?;
+        SgidiskVolume::fix_checksum(&mut header);
+        file.seek(SeekFrom::Start(0))?;
+        file.write_all(&header)?;
+      }
+      Fix::PadImage { missing_bytes } => {
+        let len = file.metadata()?.len();
+        file.set_len(len + missing_bytes)?;
+      }
+    }
+    file.flush()
+  }
+}
+
+/// Print each problem with its suggested remedy, followed by the fixes applied
+fn print_diagnosis(remedies: &[JsonRemedy], fixes: &[Fix], dry_run: bool) {
+  if remedies.is_empty() {
+    println!("{}", paint("No problems found", Style::Good));
+    return;
+  }
+  for r in remedies {
+    let style = if r.status == Status::Fail { Style::Error } else { Style::Warning };
+    println!("{} {} ({}): {}", paint(&format!("{:?}", r.status).to_lowercase(), style), r.target, r.check, r.problem);
+    println!("  {}", r.suggestion);
+  }
+
+  let fixable = remedies.iter().filter(|r| r.fix.is_some()).count();
+  if !fixes.is_empty() {
+    println!("{}", paint(if dry_run { "Would fix:" } else { "Fixed:" }, Style::Heading));
+    for fix in fixes {
+      println!("  {}", fix.describe());
+    }
+  } else if fixable > 0 {
+    println!("{} of these can be fixed in place with --fix", fixable);
+  }
+}
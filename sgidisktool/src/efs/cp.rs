@@ -0,0 +1,968 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::{BufWriter, ErrorKind, Read, Seek, Write};
+use std::path::{Path, PathBuf};
+use std::process::exit;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+
+use clap::ArgMatches;
+use filetime::FileTime;
+use serde::{Deserialize, Serialize};
+
+use sgidisklib::efs::{Efs, Inode, InodeType, normalize_path};
+use sgidisklib::efs::dir::Directory;
+use sgidisklib::progress::CancellationToken;
+use sgidisklib::SgidiskLibReadError;
+
+use crate::config::Config;
+use crate::efs::OpenEfs;
+use crate::exit_codes::CommandError;
+use crate::efs::filter::{EntryFilter, FilterSpec};
+use crate::efs::manifest::{ManifestEntry, ManifestFormat, write_manifest};
+use crate::efs::sanitize::{confined_join, host_names, JsonRename, SanitizeMode, write_rename_log};
+use crate::efs::sparse::SparseWriter;
+use crate::logging::TracingReader;
+use crate::hash::{HashAlgorithm, HashingWriter, MultiHash, MultiHashResult};
+use crate::positional::PositionalReader;
+use crate::sidecar::{Sidecar, SidecarParameters};
+use crate::sqlite::Record;
+
+/// Options controlling extraction of EFS entries to the host filesystem
+pub(crate) struct ExtractOptions {
+  /// Copy directories and their contents
+  pub(crate) recursive: bool,
+  /// Set owner and group to the numeric IDs recorded in the image
+  pub(crate) numeric_owner: bool,
+  /// How to extract symbolic links
+  pub(crate) symlinks: SymlinkPolicy,
+  /// When to rename entries whose names are invalid on the host
+  pub(crate) sanitize: SanitizeMode,
+  /// Leave holes in files instead of writing runs of zeros
+  pub(crate) sparse: bool,
+  /// Number of threads extracting file contents. With more than one, file contents and
+  /// directory metadata are deferred until the directory tree has been created.
+  pub(crate) threads: usize,
+  /// Which entries within directories are extracted
+  pub(crate) filter: EntryFilter,
+  /// Leave files which a previous extraction already completed, as found by this check
+  pub(crate) resume: Option<ResumeCheck>,
+  /// Walk the entries which would be extracted without writing anything
+  pub(crate) dry_run: bool,
+  /// Print each extracted entry
+  pub(crate) verbose: bool,
+  /// Hash file contents while extracting them with these algorithms, then re-read the extracted
+  /// files and compare
+  pub(crate) verify: Option<Vec<HashAlgorithm>>,
+}
+
+/// How symbolic links are extracted
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum SymlinkPolicy {
+  /// Don't extract symbolic links
+  Skip,
+  /// Recreate symbolic links with their original target, unless the target would point outside
+  /// of the destination directory
+  Literal,
+  /// Extract whatever the symbolic link points to in the EFS in place of the link
+  Follow,
+}
+
+/// How a file already at the destination is found to be completely extracted, when resuming
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub(crate) enum ResumeCheck {
+  /// Same size and modification time, which is set once a file's contents are written
+  Mtime,
+  /// Same size and contents, by BLAKE3 hash
+  Hash,
+}
+
+/// State carried through a recursive extraction
+pub(crate) struct ExtractState {
+  /// Destination directory which extracted entries must stay within
+  root: PathBuf,
+  /// Inode numbers of the directories currently being extracted, from the top down
+  ancestors: Vec<u64>,
+  /// Result of each extracted entry
+  pub(crate) results: Vec<JsonExtractedFile>,
+  /// Metadata of each entry that was skipped
+  pub(crate) skipped: Vec<ManifestEntry>,
+  /// Each entry that was renamed
+  pub(crate) renames: Vec<JsonRename>,
+  /// Regular files whose contents are still to be extracted
+  deferred_files: Vec<DeferredEntry>,
+  /// Directories whose metadata is still to be applied, deepest first
+  deferred_dirs: Vec<DeferredEntry>,
+  /// Hashes of extracted file contents as read from the image, by result index, if verifying
+  image_hashes: Vec<(usize, MultiHashResult, )>,
+}
+
+/// Entry whose extraction has been deferred
+struct DeferredEntry {
+  /// Index of the entry's result
+  result_idx: usize,
+  src: String,
+  inode: Inode,
+  dest: PathBuf,
+}
+
+/// Outcome of extracting one entry
+enum Extracted {
+  /// Entry was extracted
+  Done,
+  /// Entry was deliberately not extracted, for the given reason
+  Skipped(String),
+  /// Directory was left out, as the filters left nothing within it to extract
+  Filtered,
+}
+
+/// JSON representation of one extracted entry
+#[derive(Serialize)]
+pub(crate) struct JsonExtractedFile {
+  src: String,
+  dest: String,
+  inode: u64,
+  inode_type: String,
+  size_bytes: u64,
+  skipped: Option<String>,
+  /// Whether the file was already extracted, so left as it was when resuming
+  resumed: bool,
+  pub(crate) error: Option<String>,
+  /// Whether the extracted file's contents matched the image, if verified
+  pub(crate) verified: Option<bool>,
+}
+
+/// EFS file copy entry point
+pub(crate) fn subcommand(config: &Config, mut efs_vol: OpenEfs, cli_matches: &ArgMatches) {
+  let json = config.json(cli_matches);
+
+  // Options from CLI take precedence over configured ones
+  let mut opts = ExtractOptions::new(config);
+  opts.recursive = cli_matches.is_present("recursive");
+  opts.numeric_owner |= cli_matches.is_present("numeric-owner");
+  if let Some(name) = cli_matches.value_of("symlinks") {
+    opts.symlinks = SymlinkPolicy::from_name(name).unwrap();
+  }
+  if let Some(name) = cli_matches.value_of("sanitize-names") {
+    opts.sanitize = SanitizeMode::from_name(name).unwrap();
+  }
+  opts.sparse |= cli_matches.is_present("sparse");
+  if let Some(n) = cli_matches.value_of("threads") {
+    opts.threads = match n.parse::<usize>() {
+      Ok(n) => resolve_threads(n),
+      Err(_) => {
+        eprintln!("Invalid number of threads: {}", n);
+        exit(crate::exit_codes::CLI_ARG_ERROR);
+      }
+    };
+  }
+  opts.filter = match EntryFilter::new(&filter_spec(cli_matches)) {
+    Ok(filter) => filter,
+    Err(e) => {
+      eprintln!("{}", e);
+      exit(crate::exit_codes::CLI_ARG_ERROR);
+    }
+  };
+  if cli_matches.is_present("resume") {
+    opts.resume = Some(match cli_matches.value_of("resume") {
+      Some("hash") => ResumeCheck::Hash,
+      _ => ResumeCheck::Mtime
+    });
+  }
+  opts.dry_run = cli_matches.is_present("dry-run");
+  if cli_matches.is_present("verify") {
+    opts.verify = Some(config.hash.algorithms.clone());
+  }
+  // A dry run always lists what would be extracted
+  opts.verbose = (crate::logging::verbosity(cli_matches) > 0 || opts.dry_run) && !json;
+
+  let src = cli_matches.value_of("src").unwrap();
+  let bag_dir = cli_matches.value_of("dest").filter(|_| cli_matches.is_present("bag") && !opts.dry_run).map(PathBuf::from);
+  let data_dir = bag_dir.as_ref().map(|bag_dir| crate::bagit::create_bag(bag_dir).unwrap_or_else(|e| crate::bagit::quit_bag_error(bag_dir, &e)));
+  // A bag's payload goes in its data directory
+  let dest = match &data_dir {
+    Some(data_dir) => data_dir.to_str().unwrap(),
+    None => cli_matches.value_of("dest").unwrap()
+  };
+
+  // Refuse to write to devices without --force-device, and confirm before overwriting devices or
+  // the disk image
+  crate::confirm::check_device_or_quit(Path::new(dest));
+  let warnings = crate::confirm::destination_warnings(Path::new(dest), efs_vol.vol.disk_file_name);
+  if !opts.dry_run && !warnings.is_empty() {
+    let summary = format!("Extracting '{}' from partition {} to '{}'", src, efs_vol.partition_id, dest);
+    crate::confirm::confirm_or_quit(&summary, &warnings, cli_matches.is_present("force"));
+  }
+
+  // Extract
+  let state = match extract_path(&mut efs_vol, src, dest, &opts) {
+    Ok(state) => state,
+    Err(e) => e.quit()
+  };
+
+  // Record anything which couldn't be extracted
+  let dry_run = opts.dry_run;
+  crate::sqlite::record_or_quit(cli_matches, "efs cp", efs_vol.vol.disk_file_name, Record::Manifest(&state.skipped));
+  if let Some(manifest_file_name) = cli_matches.value_of("manifest").filter(|_| !dry_run) {
+    let format = ManifestFormat::from_name(cli_matches.value_of("manifest-format").unwrap_or("json")).unwrap();
+    if let Err(e) = write_manifest(manifest_file_name, format, &state.skipped) {
+      eprintln!("Error writing manifest {}: {:?}", manifest_file_name, &e);
+      exit(crate::exit_codes::IO_ERR);
+    }
+  }
+  if let Some(rename_log_file_name) = cli_matches.value_of("rename-log").filter(|_| !dry_run) {
+    if let Err(e) = write_rename_log(rename_log_file_name, &state.renames) {
+      eprintln!("Error writing rename log {}: {:?}", rename_log_file_name, &e);
+      exit(crate::exit_codes::IO_ERR);
+    }
+  }
+
+  if let Some(sidecar_file_name) = cli_matches.value_of("sidecar").filter(|_| !dry_run) {
+    if let Err(e) = write_sidecar(config, &mut efs_vol, src, dest, &opts, &state, sidecar_file_name) {
+      eprintln!("Error writing sidecar {}: {:?}", sidecar_file_name, &e);
+      exit(crate::exit_codes::IO_ERR);
+    }
+  }
+
+  // Payload is complete, so the bag can be finished
+  if let Some(bag_dir) = &bag_dir {
+    let bag = crate::bagit::finish_bag(bag_dir, &config.hash.algorithms, &[])
+      .unwrap_or_else(|e| crate::bagit::quit_bag_error(bag_dir, &e));
+    if !json {
+      crate::bagit::print_bag(&bag, false);
+    }
+  }
+
+  if json {
+    crate::output::print_json("efs cp", &state.results);
+  }
+
+  // Fail if any extracted file did not verify
+  if state.results.iter().any(|r| r.verified == Some(false)) {
+    exit(crate::exit_codes::VERIFY_ERR);
+  }
+  let failed = state.results.iter().filter(|r| r.error.is_some()).count();
+  if let Some(exit_code) = crate::exit_codes::for_failures(failed, state.results.len()) {
+    exit(exit_code);
+  }
+  if config.strict(cli_matches) && !state.skipped.is_empty() {
+    eprintln!("{} entries were skipped", state.skipped.len());
+    exit(crate::exit_codes::STRICT_ERR);
+  }
+}
+
+/// Filters given on the CLI
+pub(crate) fn filter_spec(cli_matches: &ArgMatches) -> FilterSpec {
+  let values = |name: &str| cli_matches.values_of(name).map(|v| v.map(|s| s.to_string()).collect()).unwrap_or_default();
+  let value = |name: &str| cli_matches.value_of(name).map(|s| s.to_string());
+  FilterSpec {
+    include: values("include"),
+    exclude: values("exclude"),
+    min_size: value("min-size"),
+    max_size: value("max-size"),
+    types: values("type"),
+    newer: value("newer"),
+    older: value("older"),
+  }
+}
+
+/// Write a fixity sidecar of an extraction, with the digests of the image and of each regular
+/// file extracted, and the IRIX release identified on the filesystem
+fn write_sidecar(config: &Config, efs_vol: &mut OpenEfs, src: &str, dest: &str, opts: &ExtractOptions, state: &ExtractState, sidecar_file_name: &str) -> std::io::Result<()> {
+  let mut options = BTreeMap::new();
+  options.insert("recursive".to_string(), opts.recursive.to_string());
+  options.insert("numeric_owner".to_string(), opts.numeric_owner.to_string());
+  options.insert("symlinks".to_string(), format!("{:?}", opts.symlinks).to_lowercase());
+  options.insert("sanitize_names".to_string(), format!("{:?}", opts.sanitize).to_lowercase());
+  options.insert("sparse".to_string(), opts.sparse.to_string());
+  let parameters = SidecarParameters {
+    command: "efs cp".to_string(),
+    partition: efs_vol.partition_id,
+    src: src.to_string(),
+    dest: dest.to_string(),
+    options,
+  };
+
+  let files: Vec<(&str, &str, )> = state.results.iter()
+    // Files extracted in place of followed links are included, links recreated as links aren't
+    .filter(|r| r.skipped.is_none() && r.error.is_none())
+    .filter(|r| fs::symlink_metadata(&r.dest).map_or(false, |m| m.is_file()))
+    .map(|r| (r.src.as_str(), r.dest.as_str(), ))
+    .collect();
+  let irix_release = efs_vol.efs.identify_release(&mut efs_vol.vol.disk_file).release;
+  Sidecar::new(efs_vol.vol.disk_file_name, &config.hash.algorithms, parameters, &files, irix_release)?.write(sidecar_file_name)
+}
+
+/// Extract an EFS path to a destination file or directory. Errors with individual entries are
+/// recorded in the returned state rather than failing the whole extraction.
+pub(crate) fn extract_path(efs_vol: &mut OpenEfs, src: &str, dest: &str, opts: &ExtractOptions) -> Result<ExtractState, CommandError> {
+  // Find source entry
+  let (inode_id, inode, ) = match efs_vol.efs.lookup_path(&mut efs_vol.vol.disk_file, src) {
+    Ok(found) => found,
+    Err(e) => return Err(CommandError::new(crate::exit_codes::for_lib_error(&e), format!("Error finding '{}': {:?}", src, &e)))
+  };
+  if inode.inode_type == InodeType::Directory && !opts.recursive {
+    return Err(CommandError::new(crate::exit_codes::CLI_ARG_ERROR, format!("'{}' is a directory, use --recursive to copy it", src)));
+  }
+
+  // If destination is an existing directory then copy into it, otherwise use dest verbatim.
+  // Extracted entries are confined to the destination directory, or to the copied directory.
+  let mut dest_path = PathBuf::from(dest);
+  let root = if dest_path.is_dir() {
+    let root = dest_path.clone();
+    if let Some(name) = src.rsplit('/').find(|c| !c.is_empty()) {
+      dest_path.push(&host_names(&[name], opts.sanitize)[0]);
+    }
+    root
+  } else if inode.inode_type == InodeType::Directory {
+    dest_path.clone()
+  } else {
+    match dest_path.parent() {
+      Some(parent) => parent.to_path_buf(),
+      None => PathBuf::new()
+    }
+  };
+
+  // Extract
+  let mut state = ExtractState {
+    root,
+    ancestors: Vec::new(),
+    results: Vec::new(),
+    skipped: Vec::new(),
+    renames: Vec::new(),
+    deferred_files: Vec::new(),
+    deferred_dirs: Vec::new(),
+    image_hashes: Vec::new(),
+  };
+  extract(efs_vol, src, inode_id, &inode, &dest_path, opts, &mut state);
+  extract_deferred(efs_vol, opts, &mut state);
+  verify_extracted(opts, &mut state);
+
+  Ok(state)
+}
+
+/// Number of extraction threads, where 0 means one per CPU
+fn resolve_threads(threads: usize) -> usize {
+  match threads {
+    0 => thread::available_parallelism().map_or(1, |n| n.get()),
+    n => n
+  }
+}
+
+/// Extract an entry (and the contents of directories, recursively) to a host path, recording
+/// the result of each extracted entry
+fn extract(efs_vol: &mut OpenEfs, src: &str, inode_id: u64, inode: &Inode, dest: &Path, opts: &ExtractOptions, state: &mut ExtractState) {
+  if opts.verbose {
+    println!("{} -> {}", src, dest.to_string_lossy());
+  }
+
+  // Record result before descending, so that directories are listed before their contents
+  let result_idx = state.results.len();
+  state.results.push(JsonExtractedFile {
+    src: src.to_string(),
+    dest: dest.to_string_lossy().to_string(),
+    inode: inode_id,
+    inode_type: format!("{:?}", inode.inode_type),
+    size_bytes: inode.size,
+    skipped: None,
+    resumed: false,
+    error: None,
+    verified: None,
+  });
+
+  match extract_entry(efs_vol, src, inode_id, inode, dest, opts, state) {
+    Ok(Extracted::Done) => {}
+    Ok(Extracted::Filtered) => state.results.truncate(result_idx),
+    Ok(Extracted::Skipped(reason)) => {
+      eprintln!("Skipped: {}: {}", src, &reason);
+      let link = match inode.inode_type {
+        InodeType::SymbolicLink => read_link(efs_vol, inode).ok(),
+        _ => None
+      };
+      let path = dest.strip_prefix(&state.root).unwrap_or(dest);
+      let mut entry = ManifestEntry::new(&efs_vol.efs, src, &path.to_string_lossy(), inode_id, inode, link, &reason);
+      if inode.inode_type == InodeType::RegularFile {
+        entry.content_type = efs_vol.efs.content_type(&mut efs_vol.vol.disk_file, inode).ok().map(|t| t.name().to_string());
+      }
+      state.skipped.push(entry);
+      state.results[result_idx].skipped = Some(reason);
+    }
+    Err(e) => {
+      eprintln!("Error: {} -> {}: {}", src, dest.to_string_lossy(), &e);
+      state.results[result_idx].error = Some(e);
+    }
+  }
+}
+
+/// Create an entry on the host, extract the contents of directories, then apply its metadata
+fn extract_entry(efs_vol: &mut OpenEfs, src: &str, inode_id: u64, inode: &Inode, dest: &Path, opts: &ExtractOptions, state: &mut ExtractState) -> Result<Extracted, String> {
+  // The entry being extracted is the last one recorded, until its directory contents are
+  let result_idx = state.results.len() - 1;
+  // Only raw disk images can be read from several threads
+  let defer = opts.threads > 1 && !opts.dry_run && efs_vol.vol.disk_file.get_ref().file().is_some();
+
+  match inode.inode_type {
+    InodeType::RegularFile if opts.resume.map_or(false, |check| already_extracted(efs_vol, inode, dest, check)) => {
+      state.results[result_idx].resumed = true;
+    }
+    InodeType::RegularFile if defer => {
+      state.deferred_files.push(DeferredEntry {
+        result_idx,
+        src: src.to_string(),
+        inode: inode.clone(),
+        dest: dest.to_path_buf(),
+      });
+      return Ok(Extracted::Done);
+    }
+    InodeType::RegularFile if opts.dry_run => {}
+    InodeType::RegularFile => {
+      if let Some(hash) = extract_file(&efs_vol.efs, &mut efs_vol.vol.disk_file, inode, dest, opts)? {
+        state.image_hashes.push((result_idx, hash, ));
+      }
+    }
+    InodeType::SymbolicLink => return extract_symlink(efs_vol, src, inode, dest, opts, state),
+    // A damaged filesystem may link a directory into one of its own subdirectories
+    InodeType::Directory if state.ancestors.contains(&inode_id) =>
+      return Ok(Extracted::Skipped("Directory which contains it; the filesystem is damaged".to_string())),
+    InodeType::Directory => {
+      if !opts.dry_run {
+        create_dir(dest)?;
+      }
+      let dir = match efs_vol.efs.limits.check_depth(state.ancestors.len())
+        .and_then(|_| Directory::read_dir(&mut efs_vol.vol.disk_file, &efs_vol.efs, inode_id)) {
+        Ok(dir) => dir,
+        Err(e) => return Err(format!("Unable to read directory: {:?}", &e))
+      };
+      let entries: Vec<_> = dir.entries.iter()
+        .filter(|(name, _, )| *name != "." && *name != "..")
+        .collect();
+      let names: Vec<&str> = entries.iter().map(|(name, _, )| name.as_str()).collect();
+      let dest_names = host_names(&names, opts.sanitize);
+
+      state.ancestors.push(inode_id);
+      for ((name, (entry_inode_id, entry_inode, ), ), dest_name, ) in entries.into_iter().zip(dest_names) {
+        let entry_src = child_path(src, name);
+        let included = match entry_inode.inode_type {
+          InodeType::Directory => opts.filter.descends(&entry_src),
+          _ => opts.filter.matches(&entry_src, entry_inode)
+        };
+        if !included {
+          continue;
+        }
+        let entry_dest = match confined_join(&state.root, dest, &dest_name) {
+          Ok(entry_dest) => entry_dest,
+          Err(e) => {
+            eprintln!("Error: {}: {}", &entry_src, &e);
+            state.results.push(JsonExtractedFile {
+              src: entry_src,
+              dest: String::new(),
+              inode: *entry_inode_id,
+              inode_type: format!("{:?}", entry_inode.inode_type),
+              size_bytes: entry_inode.size,
+              skipped: None,
+              resumed: false,
+              error: Some(e),
+              verified: None,
+            });
+            continue;
+          }
+        };
+        if *name != dest_name {
+          eprintln!("Renamed: {} -> {}", &entry_src, entry_dest.to_string_lossy());
+          state.renames.push(JsonRename {
+            src: entry_src.clone(),
+            dest: entry_dest.to_string_lossy().to_string(),
+          });
+        }
+        extract(efs_vol, &entry_src, *entry_inode_id, entry_inode, &entry_dest, opts, state);
+      }
+      state.ancestors.pop();
+
+      if state.results.len() == result_idx + 1 && !opts.filter.keeps_empty(src) {
+        if !opts.dry_run {
+          fs::remove_dir(dest).map_err(|e| format!("Unable to remove empty directory: {}", &e))?;
+        }
+        return Ok(Extracted::Filtered);
+      }
+      if defer {
+        state.deferred_dirs.push(DeferredEntry {
+          result_idx,
+          src: src.to_string(),
+          inode: inode.clone(),
+          dest: dest.to_path_buf(),
+        });
+        return Ok(Extracted::Done);
+      }
+    }
+    other => return Ok(Extracted::Skipped(format!("{:?} can't be extracted", other)))
+  }
+
+  // Apply metadata last, so that writing directory contents doesn't disturb timestamps
+  if !opts.dry_run {
+    apply_metadata(inode, dest, opts)?;
+  }
+  Ok(Extracted::Done)
+}
+
+/// Path of a directory entry within the EFS
+fn child_path(dir: &str, name: &str) -> String {
+  if dir.ends_with('/') {
+    format!("{}{}", dir, name)
+  } else {
+    format!("{}/{}", dir, name)
+  }
+}
+
+/// Whether a file at the destination is the completely extracted file from the image
+fn already_extracted(efs_vol: &mut OpenEfs, inode: &Inode, dest: &Path, check: ResumeCheck) -> bool {
+  let meta = match fs::symlink_metadata(dest) {
+    Ok(meta) if meta.is_file() && meta.len() == inode.size => meta,
+    _ => return false
+  };
+  match check {
+    ResumeCheck::Mtime => FileTime::from_last_modification_time(&meta).unix_seconds() == inode.mtime_raw,
+    ResumeCheck::Hash => {
+      let host_hash = match fs::File::open(dest).and_then(|mut f| MultiHash::hash_reader(&mut f, &[HashAlgorithm::Blake3])) {
+        Ok((_, hash, )) => hash,
+        Err(_) => return false
+      };
+      let mut image_hash = MultiHash::new(&[HashAlgorithm::Blake3]);
+      efs_vol.efs.read_file(&mut efs_vol.vol.disk_file, inode, &mut image_hash).is_ok() && image_hash.finalize() == host_hash
+    }
+  }
+}
+
+/// Extract the contents of deferred files using several threads, then apply the metadata of
+/// deferred directories
+fn extract_deferred(efs_vol: &OpenEfs, opts: &ExtractOptions, state: &mut ExtractState) {
+  if state.deferred_files.is_empty() && state.deferred_dirs.is_empty() {
+    return;
+  }
+
+  // Files are handed out in the order of their first block, so that the threads between them
+  // read the disk mostly sequentially
+  let mut files = std::mem::take(&mut state.deferred_files);
+  files.sort_by_key(|file| file.inode.iter().next().unwrap_or(0));
+
+  let next_file = AtomicUsize::new(0);
+  let errors = Mutex::new(Vec::new());
+  let hashes = Mutex::new(Vec::new());
+  let disk_file = match efs_vol.vol.disk_file.get_ref().file() {
+    Some(disk_file) => disk_file,
+    None => return
+  };
+  thread::scope(|scope| {
+    for _ in 0..opts.threads {
+      scope.spawn(|| {
+        let mut reader = TracingReader::new(PositionalReader::new(disk_file));
+        while let Some(file) = files.get(next_file.fetch_add(1, Ordering::Relaxed)) {
+          let extracted = extract_file(&efs_vol.efs, &mut reader, &file.inode, &file.dest, opts)
+            .and_then(|hash| apply_metadata(&file.inode, &file.dest, opts).map(|_| hash));
+          match extracted {
+            Ok(Some(hash)) => hashes.lock().unwrap().push((file.result_idx, hash, )),
+            Ok(None) => {}
+            Err(e) => errors.lock().unwrap().push((file, e, ))
+          }
+        }
+      });
+    }
+  });
+
+  state.image_hashes.append(&mut hashes.into_inner().unwrap());
+
+  // Directories' contents are now complete
+  let dirs = std::mem::take(&mut state.deferred_dirs);
+  let mut errors = errors.into_inner().unwrap();
+  for dir in &dirs {
+    if let Err(e) = apply_metadata(&dir.inode, &dir.dest, opts) {
+      errors.push((dir, e, ));
+    }
+  }
+
+  for (entry, e, ) in errors {
+    eprintln!("Error: {} -> {}: {}", &entry.src, entry.dest.to_string_lossy(), &e);
+    state.results[entry.result_idx].error = Some(e);
+  }
+}
+
+/// Extract the contents of a regular file, returning the hashes of the contents read from the
+/// image if verifying
+fn extract_file<R: ?Sized>(efs: &Efs, reader: &mut R, inode: &Inode, dest: &Path, opts: &ExtractOptions) -> Result<Option<MultiHashResult>, String>
+  where R: Read + Seek {
+  let dest_file = match fs::File::create(dest) {
+    Ok(f) => f,
+    Err(e) => return Err(format!("Unable to create file: {:?}", &e))
+  };
+  let algorithms = opts.verify.as_deref().unwrap_or(&[]);
+
+  let hash = if opts.sparse {
+    let mut writer = HashingWriter::new(SparseWriter::new(dest_file), algorithms);
+    if let Err(e) = sgidisklib::copy::efs_file(efs, reader, inode, &mut writer, &CancellationToken::new(), |_| {}) {
+      return Err(format!("Unable to read file: {:?}", &e));
+    }
+    let (writer, hash, ) = writer.into_parts();
+    if let Err(e) = writer.finish() {
+      return Err(format!("Unable to write file: {:?}", &e));
+    }
+    hash
+  } else {
+    let mut writer = HashingWriter::new(BufWriter::new(dest_file), algorithms);
+    if let Err(e) = sgidisklib::copy::efs_file(efs, reader, inode, &mut writer, &CancellationToken::new(), |_| {}) {
+      return Err(format!("Unable to read file: {:?}", &e));
+    }
+    if let Err(e) = writer.flush() {
+      return Err(format!("Unable to write file: {:?}", &e));
+    }
+    writer.into_parts().1
+  };
+
+  Ok(opts.verify.as_ref().map(|_| hash))
+}
+
+/// Re-read extracted files and compare their hashes with those of the contents read from the
+/// image, recording whether each one matched
+fn verify_extracted(opts: &ExtractOptions, state: &mut ExtractState) {
+  let algorithms = match &opts.verify {
+    Some(algorithms) => algorithms,
+    None => return
+  };
+
+  for (result_idx, image_hash, ) in std::mem::take(&mut state.image_hashes) {
+    let result = &mut state.results[result_idx];
+    if result.error.is_some() {
+      continue;
+    }
+    match fs::File::open(&result.dest).and_then(|mut f| MultiHash::hash_reader(&mut f, algorithms)) {
+      Ok((_, host_hash, )) => {
+        let matched = host_hash == image_hash;
+        if !matched {
+          eprintln!("Verification failed: {} -> {}", &result.src, &result.dest);
+        }
+        result.verified = Some(matched);
+      }
+      Err(e) => {
+        eprintln!("Error: {} -> {}: unable to re-read for verification: {:?}", &result.src, &result.dest, &e);
+        result.verified = Some(false);
+      }
+    }
+  }
+}
+
+/// Extract a symbolic link according to the symlink policy
+fn extract_symlink(efs_vol: &mut OpenEfs, src: &str, inode: &Inode, dest: &Path, opts: &ExtractOptions, state: &mut ExtractState) -> Result<Extracted, String> {
+  if opts.symlinks == SymlinkPolicy::Skip {
+    return Ok(Extracted::Skipped("Symbolic link".to_string()));
+  }
+
+  let target = read_link(efs_vol, inode)?;
+  match opts.symlinks {
+    SymlinkPolicy::Literal => {
+      // Refuse links which could be used to reach outside the destination
+      let depth = match dest.parent().map(|parent| parent.strip_prefix(&state.root)) {
+        Some(Ok(rel)) => rel.components().count(),
+        _ => 0
+      };
+      if target_escapes(efs_vol, src, &target, depth) {
+        return Ok(Extracted::Skipped(format!("Symbolic link target '{}' points outside of destination directory", &target)));
+      }
+
+      if opts.dry_run {
+        return Ok(Extracted::Done);
+      }
+      let resumed = opts.resume.is_some() && fs::read_link(dest).map_or(false, |t| t == Path::new(&target));
+      if !resumed {
+        if let Err(e) = create_symlink(&target, dest) {
+          return Err(format!("Unable to create symbolic link to '{}': {:?}", &target, &e));
+        }
+      }
+      apply_metadata(inode, dest, opts)?;
+      Ok(Extracted::Done)
+    }
+    _ => {
+      // Extract the link target in place of the link, taking care not to copy a directory into
+      // itself
+      let (target_path, target_inode_id, target_inode, ) = resolve_symlink(efs_vol, src, &target)?;
+      if target_inode.inode_type == InodeType::Directory {
+        if !opts.recursive {
+          return Ok(Extracted::Skipped(format!("Symbolic link to directory '{}', use --recursive to copy it", &target_path)));
+        }
+        if state.ancestors.contains(&target_inode_id) {
+          return Ok(Extracted::Skipped(format!("Symbolic link to directory '{}' which contains it", &target_path)));
+        }
+      }
+      extract_entry(efs_vol, &target_path, target_inode_id, &target_inode, dest, opts, state)
+    }
+  }
+}
+
+/// Read the target of a symbolic link
+fn read_link(efs_vol: &mut OpenEfs, inode: &Inode) -> Result<String, String> {
+  match efs_vol.efs.read_link(&mut efs_vol.vol.disk_file, inode) {
+    Ok(target) => Ok(target),
+    Err(e) => Err(format!("Unable to read symbolic link: {:?}", &e))
+  }
+}
+
+/// Follow a symbolic link (and any links it leads to) within the EFS, returning the path, inode
+/// number and Inode which it finally points to
+fn resolve_symlink(efs_vol: &mut OpenEfs, link_path: &str, target: &str) -> Result<(String, u64, Inode, ), String> {
+  let path = link_target_path(link_path, target);
+  match efs_vol.efs.resolve_path(&mut efs_vol.vol.disk_file, &path) {
+    Ok(resolved) => Ok(resolved),
+    Err(e @ SgidiskLibReadError::SymlinkLoop { .. }) | Err(e @ SgidiskLibReadError::LimitExceeded { .. }) =>
+      Err(format!("Too many levels of symbolic links following '{}': {}", link_path, &e)),
+    Err(e) => Err(format!("Unable to find symbolic link target '{}': {:?}", &path, &e))
+  }
+}
+
+/// Absolute EFS path of a symbolic link target, which is relative to the directory containing
+/// the link unless it is absolute
+fn link_target_path(link_path: &str, target: &str) -> String {
+  if target.starts_with('/') {
+    normalize_path(target)
+  } else {
+    normalize_path(&format!("{}/../{}", link_path, target))
+  }
+}
+
+/// Whether a symbolic link target could point outside of the destination directory, given how
+/// many directories below the destination the link is. The target is followed as the host would
+/// follow it once extracted, through the links it leads to within the EFS, which the extracted
+/// tree mirrors; otherwise links which each stay within the destination, such as `d/a -> ..` and
+/// `d/b -> a/../..`, could be chained to leave it. Components which don't exist are taken as
+/// directories, and following too many links counts as escaping.
+fn target_escapes(efs_vol: &mut OpenEfs, link_path: &str, target: &str, depth: usize) -> bool {
+  if target.starts_with('/') {
+    return true;
+  }
+
+  let mut level = depth;
+  // EFS path of the directory reached, alongside how far below the destination it is
+  let mut efs_dir: Vec<String> = link_path.split('/').filter(|c| !c.is_empty()).map(String::from).collect();
+  efs_dir.pop();
+  // Components still to follow, last first
+  let mut pending: Vec<String> = target.split('/').rev().map(String::from).collect();
+  let mut followed = 0;
+
+  while let Some(component) = pending.pop() {
+    match component.as_str() {
+      "" | "." => continue,
+      ".." => match level.checked_sub(1) {
+        Some(l) => {
+          level = l;
+          efs_dir.pop();
+          continue;
+        }
+        None => return true
+      },
+      _ => {}
+    }
+    level += 1;
+    efs_dir.push(component);
+
+    let inode = match efs_vol.efs.lookup_path(&mut efs_vol.vol.disk_file, &format!("/{}", efs_dir.join("/"))) {
+      Ok((_, inode, )) if inode.inode_type == InodeType::SymbolicLink => inode,
+      _ => continue
+    };
+    followed += 1;
+    let link_target = match efs_vol.efs.read_link(&mut efs_vol.vol.disk_file, &inode) {
+      Ok(link_target) if followed <= efs_vol.efs.limits.max_symlinks && !link_target.starts_with('/') => link_target,
+      _ => return true
+    };
+    // The link's target replaces it, relative to the directory holding it
+    level -= 1;
+    efs_dir.pop();
+    pending.extend(link_target.split('/').rev().map(String::from));
+  }
+
+  false
+}
+
+/// Create a directory, which may already exist
+fn create_dir(dest: &Path) -> Result<(), String> {
+  match fs::create_dir(dest) {
+    Ok(_) => Ok(()),
+    Err(e) if e.kind() == ErrorKind::AlreadyExists && dest.is_dir() => Ok(()),
+    Err(e) => Err(format!("Unable to create directory: {:?}", &e))
+  }
+}
+
+/// Apply ownership (if requested), permissions and timestamps from an inode to an extracted entry
+fn apply_metadata(inode: &Inode, dest: &Path, opts: &ExtractOptions) -> Result<(), String> {
+  let is_symlink = inode.inode_type == InodeType::SymbolicLink;
+
+  // Ownership goes first, as changing it may clear setuid/setgid bits. As with tar and cp -p,
+  // setuid, setgid and sticky bits are only kept along with the image's owners, so that an image
+  // can't hand out setuid programs owned by whoever extracts it.
+  let mut mode = inode.unix_mode & 0o777;
+  if opts.numeric_owner {
+    if let Err(e) = set_owner(dest, inode.owner_uid, inode.owner_gid, is_symlink) {
+      return Err(format!("Unable to set owner {}:{}: {:?}", inode.owner_uid, inode.owner_gid, &e));
+    }
+    mode = inode.unix_mode & 0o7777;
+  }
+
+  // Symbolic links have no permissions of their own
+  if !is_symlink {
+    if let Err(e) = set_mode(dest, mode) {
+      return Err(format!("Unable to set permissions {:o}: {:?}", mode, &e));
+    }
+  }
+
+  let atime = FileTime::from_unix_time(inode.atime_raw, 0);
+  let mtime = FileTime::from_unix_time(inode.mtime_raw, 0);
+  let set_times = if is_symlink {
+    filetime::set_symlink_file_times(dest, atime, mtime)
+  } else {
+    filetime::set_file_times(dest, atime, mtime)
+  };
+  if let Err(e) = set_times {
+    return Err(format!("Unable to set timestamps: {:?}", &e));
+  }
+
+  Ok(())
+}
+
+/// Set numeric owner and group of a host path
+#[cfg(unix)]
+fn set_owner(dest: &Path, uid: u16, gid: u16, is_symlink: bool) -> Result<(), std::io::Error> {
+  if is_symlink {
+    std::os::unix::fs::lchown(dest, Some(uid as u32), Some(gid as u32))
+  } else {
+    std::os::unix::fs::chown(dest, Some(uid as u32), Some(gid as u32))
+  }
+}
+
+/// Numeric ownership can't be set on this host
+#[cfg(not(unix))]
+fn set_owner(_dest: &Path, _uid: u16, _gid: u16, _is_symlink: bool) -> Result<(), std::io::Error> {
+  Ok(())
+}
+
+/// Set Unix permission bits of a host path
+#[cfg(unix)]
+fn set_mode(dest: &Path, mode: u16) -> Result<(), std::io::Error> {
+  use std::os::unix::fs::PermissionsExt;
+  fs::set_permissions(dest, fs::Permissions::from_mode(mode as u32))
+}
+
+/// Unix permission bits can't be set on this host
+#[cfg(not(unix))]
+fn set_mode(_dest: &Path, _mode: u16) -> Result<(), std::io::Error> {
+  Ok(())
+}
+
+/// Create a symbolic link on the host
+#[cfg(unix)]
+fn create_symlink(target: &str, dest: &Path) -> Result<(), std::io::Error> {
+  std::os::unix::fs::symlink(target, dest)
+}
+
+/// Create a symbolic link on the host
+#[cfg(windows)]
+fn create_symlink(target: &str, dest: &Path) -> Result<(), std::io::Error> {
+  std::os::windows::fs::symlink_file(target, dest)
+}
+
+/// Symbolic links can't be created on this host
+#[cfg(not(any(unix, windows)))]
+fn create_symlink(_target: &str, _dest: &Path) -> Result<(), std::io::Error> {
+  Err(std::io::Error::new(std::io::ErrorKind::Unsupported, "Symbolic links are not supported on this host"))
+}
+
+impl ExtractOptions {
+  /// Options from the configured defaults, not recursive or verbose
+  pub(crate) fn new(config: &Config) -> Self {
+    ExtractOptions {
+      recursive: false,
+      numeric_owner: config.extract.numeric_owner,
+      symlinks: config.extract.symlinks,
+      sanitize: config.extract.sanitize_names,
+      sparse: config.extract.sparse,
+      threads: resolve_threads(config.extract.threads),
+      filter: EntryFilter::default(),
+      resume: None,
+      dry_run: false,
+      verbose: false,
+      verify: None,
+    }
+  }
+}
+
+impl SymlinkPolicy {
+  /// Parse from the name used on the CLI and in the config file
+  pub(crate) fn from_name(name: &str) -> Option<Self> {
+    match name {
+      "skip" => Some(SymlinkPolicy::Skip),
+      "literal" => Some(SymlinkPolicy::Literal),
+      "follow" => Some(SymlinkPolicy::Follow),
+      _ => None
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::fs;
+
+  use sgidisklib::testgen;
+
+  use crate::config::Config;
+  use crate::efs::OpenEfs;
+  use crate::OpenVolume;
+
+  use super::{extract_path, target_escapes, ExtractOptions};
+
+  #[test]
+  fn chained_links_escape() {
+    let image = std::env::temp_dir().join(format!("sgidisktool-links-{}.img", std::process::id()));
+    fs::write(&image, testgen::escaping_links().build()).unwrap();
+    let image_name = image.to_string_lossy().to_string();
+    let mut vol = OpenVolume::open(&image_name).map_err(|e| e.message).unwrap();
+    let mut efs_vol = OpenEfs::open(&mut vol, testgen::EFS_PARTITION).map_err(|e| e.message).unwrap();
+
+    // Copying /d into a directory puts its links one below the destination
+    assert!(!target_escapes(&mut efs_vol, "/d/a", "..", 1));
+    assert!(target_escapes(&mut efs_vol, "/d/b", "a/../..", 1));
+    assert!(!target_escapes(&mut efs_vol, "/d/c", "e/f", 1));
+    assert!(target_escapes(&mut efs_vol, "/d/c", "/d/e/f", 1));
+    // Copying /d as the destination puts them right in it
+    assert!(target_escapes(&mut efs_vol, "/d/a", "..", 0));
+    assert!(!target_escapes(&mut efs_vol, "/d/c", "e/f", 0));
+
+    drop(efs_vol);
+    drop(vol);
+    fs::remove_file(&image).unwrap();
+  }
+
+  #[test]
+  #[cfg(unix)]
+  fn setuid_needs_numeric_owner() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let mut efs = testgen::EfsBuilder::new();
+    efs.file("/suid", b"#!/bin/sh\n").mode("/suid", 0o4755);
+    let mut builder = testgen::ImageBuilder::new();
+    builder.efs(efs);
+    let dir = std::env::temp_dir().join(format!("sgidisktool-setuid-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let image = dir.join("image.img");
+    fs::write(&image, builder.build()).unwrap();
+    let image_name = image.to_string_lossy().to_string();
+    let mut vol = OpenVolume::open(&image_name).map_err(|e| e.message).unwrap();
+    let mut efs_vol = OpenEfs::open(&mut vol, testgen::EFS_PARTITION).map_err(|e| e.message).unwrap();
+
+    let mut opts = ExtractOptions::new(&Config::default());
+    opts.numeric_owner = false;
+    let dest = dir.join("suid");
+    let state = extract_path(&mut efs_vol, "/suid", &dest.to_string_lossy(), &opts).map_err(|e| e.message).unwrap();
+    assert!(state.results.iter().all(|r| r.error.is_none()));
+    assert_eq!(fs::metadata(&dest).unwrap().permissions().mode() & 0o7777, 0o755);
+
+    drop(efs_vol);
+    drop(vol);
+    fs::remove_dir_all(&dir).unwrap();
+  }
+}
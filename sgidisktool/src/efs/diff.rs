@@ -0,0 +1,310 @@
+use std::collections::{BTreeSet, HashSet};
+use std::fs;
+use std::fs::File;
+use std::io;
+use std::io::Write;
+use std::path::Path;
+use std::process::exit;
+
+use clap::ArgMatches;
+use serde::Serialize;
+
+use sgidisklib::efs::{Inode, InodeType};
+use sgidisklib::efs::dir::Directory;
+
+use crate::color::{paint, Style};
+use crate::config::Config;
+use crate::efs::OpenEfs;
+use crate::efs::sanitize::{host_names, SanitizeMode};
+use crate::hash::{HashAlgorithm, MultiHash, MultiHashResult};
+use crate::output::ReportOutput;
+
+/// Difference between an entry in the EFS and on the host
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+enum DiffStatus {
+  /// Entry is in the EFS but not on the host
+  Missing,
+  /// Entry is on the host but not in the EFS
+  Extra,
+  /// Entry is a different type of entry (e.g. a directory instead of a file)
+  Type,
+  Size,
+  /// Permissions differ
+  Mode,
+  /// Contents of a file, or target of a symbolic link, differ
+  Content,
+  /// Entry couldn't be compared
+  Error,
+}
+
+/// JSON representation of one difference
+#[derive(Serialize)]
+struct JsonDiff {
+  /// Path within the EFS
+  path: String,
+  status: DiffStatus,
+  detail: Option<String>,
+}
+
+/// JSON representation of a comparison
+#[derive(Serialize)]
+struct JsonDiffResult {
+  /// Number of entries compared
+  compared: usize,
+  differences: Vec<JsonDiff>,
+}
+
+/// Kind of entry, as comparable between the EFS and the host
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+enum EntryKind {
+  Directory,
+  File,
+  SymbolicLink,
+  /// Devices, FIFOs and sockets
+  Other,
+}
+
+/// Directory tree comparison
+struct Diff<'a> {
+  algorithms: &'a [HashAlgorithm],
+  sanitize: SanitizeMode,
+  compared: usize,
+  differences: Vec<JsonDiff>,
+}
+
+/// EFS diff entry point: compare a directory tree in the EFS with one on the host
+pub(crate) fn subcommand(config: &Config, mut efs_vol: OpenEfs, cli_matches: &ArgMatches) {
+  let json = config.json(cli_matches);
+  let src = cli_matches.value_of("src").unwrap_or("/");
+  let local_dir = Path::new(cli_matches.value_of("local-dir").unwrap());
+
+  let (inode_id, inode, ) = match efs_vol.efs.lookup_path(&mut efs_vol.vol.disk_file, src) {
+    Ok(found) => found,
+    Err(e) => {
+      eprintln!("Error finding '{}': {:?}", src, &e);
+      exit(crate::exit_codes::for_lib_error(&e));
+    }
+  };
+  if inode.inode_type != InodeType::Directory {
+    eprintln!("'{}' is not a directory", src);
+    exit(crate::exit_codes::CLI_ARG_ERROR);
+  }
+  if !local_dir.is_dir() {
+    eprintln!("'{}' is not a directory", local_dir.to_string_lossy());
+    exit(crate::exit_codes::CLI_ARG_ERROR);
+  }
+
+  let mut diff = Diff {
+    algorithms: &config.hash.algorithms,
+    sanitize: match cli_matches.value_of("sanitize-names") {
+      Some(name) => SanitizeMode::from_name(name).unwrap(),
+      None => config.extract.sanitize_names
+    },
+    compared: 0,
+    differences: Vec::new(),
+  };
+  diff.compare_tree(&mut efs_vol, src, inode_id, local_dir);
+
+  let errors = diff.differences.iter().filter(|d| d.status == DiffStatus::Error).count();
+  let differences = diff.differences.len();
+  let mut out = ReportOutput::open_or_quit(cli_matches);
+  let compared = diff.compared;
+  if let Err(e) = write_diff(diff, json, &mut out) {
+    out.quit_write_error(&e);
+  }
+  out.finish_or_quit();
+
+  if let Some(exit_code) = crate::exit_codes::for_failures(errors, compared) {
+    exit(exit_code);
+  }
+  if differences > 0 {
+    exit(crate::exit_codes::DIFF_ERR);
+  }
+}
+
+/// Write the differences found by a comparison
+fn write_diff<W: ?Sized>(diff: Diff, json: bool, writer: &mut W) -> io::Result<()>
+  where W: Write {
+  if json {
+    return crate::output::write_json(writer, "efs diff", &JsonDiffResult {
+      compared: diff.compared,
+      differences: diff.differences,
+    });
+  }
+  for d in &diff.differences {
+    let status = format!("{:?}", d.status).to_lowercase();
+    let line = match &d.detail {
+      Some(detail) => format!("{:<8} {}: {}", status, &d.path, detail),
+      None => format!("{:<8} {}", status, &d.path)
+    };
+    let style = if d.status == DiffStatus::Error { Style::Error } else { Style::Warning };
+    writeln!(writer, "{}", paint(&line, style))?;
+  }
+  writeln!(writer, "{} entries compared, {} differences", diff.compared, diff.differences.len())
+}
+
+impl<'a> Diff<'a> {
+  /// Record a difference
+  fn differ(&mut self, path: &str, status: DiffStatus, detail: Option<String>) {
+    self.differences.push(JsonDiff {
+      path: path.to_string(),
+      status,
+      detail,
+    });
+  }
+
+  /// Compare a directory in the EFS with one on the host, and their subdirectories
+  fn compare_tree(&mut self, efs_vol: &mut OpenEfs, src: &str, dir_id: u64, host_dir: &Path) {
+    let mut pending = vec![(src.trim_end_matches('/').to_string(), dir_id, host_dir.to_path_buf(), 0, )];
+    let mut visited = HashSet::new();
+
+    while let Some((path, dir_id, host_dir, depth, )) = pending.pop() {
+      if !visited.insert(dir_id) {
+        continue;
+      }
+      let dir = match efs_vol.efs.limits.check_depth(depth).and_then(|_| Directory::read_dir(&mut efs_vol.vol.disk_file, &efs_vol.efs, dir_id)) {
+        Ok(dir) => dir,
+        Err(e) => {
+          self.differ(&format!("{}/", &path), DiffStatus::Error, Some(format!("Unable to read directory: {:?}", &e)));
+          continue;
+        }
+      };
+      let mut host_entries = match host_entry_names(&host_dir) {
+        Ok(names) => names,
+        Err(e) => {
+          self.differ(&format!("{}/", &path), DiffStatus::Error, Some(format!("Unable to read host directory: {:?}", &e)));
+          continue;
+        }
+      };
+
+      // Match EFS entries with host entries, by the names they would be extracted as
+      let entries: Vec<_> = dir.entries.into_iter().filter(|(name, _, )| *name != "." && *name != "..").collect();
+      let names: Vec<&str> = entries.iter().map(|(name, _, )| name.as_str()).collect();
+      for ((name, (entry_id, entry, ), ), host_name, ) in entries.iter().zip(host_names(&names, self.sanitize)) {
+        let entry_path = format!("{}/{}", &path, name);
+        self.compared += 1;
+        let host_path = host_dir.join(&host_name);
+        if !host_entries.remove(&host_name) {
+          self.differ(&entry_path, DiffStatus::Missing, None);
+          continue;
+        }
+        if self.compare_entry(efs_vol, &entry_path, entry, &host_path) && entry.inode_type == InodeType::Directory {
+          pending.push((entry_path, *entry_id, host_path, depth + 1, ));
+        }
+      }
+
+      for host_name in &host_entries {
+        self.compared += 1;
+        self.differ(&format!("{}/{}", &path, host_name), DiffStatus::Extra, None);
+      }
+    }
+  }
+
+  /// Compare an EFS entry with a host entry, returning whether they are the same kind of entry
+  fn compare_entry(&mut self, efs_vol: &mut OpenEfs, path: &str, inode: &Inode, host_path: &Path) -> bool {
+    let meta = match fs::symlink_metadata(host_path) {
+      Ok(meta) => meta,
+      Err(e) => {
+        self.differ(path, DiffStatus::Error, Some(format!("Unable to get host metadata: {:?}", &e)));
+        return false;
+      }
+    };
+    let kind = efs_kind(inode.inode_type);
+    let host_kind = host_kind(&meta.file_type());
+    if kind != host_kind {
+      self.differ(path, DiffStatus::Type, Some(format!("{:?} in image, {:?} on host", kind, host_kind)));
+      return false;
+    }
+
+    // Symbolic links have no permissions of their own
+    if kind != EntryKind::SymbolicLink {
+      if let Some(host_mode) = host_mode(&meta) {
+        let mode = inode.unix_mode as u32 & 0o7777;
+        if mode != host_mode {
+          self.differ(path, DiffStatus::Mode, Some(format!("{:04o} in image, {:04o} on host", mode, host_mode)));
+        }
+      }
+    }
+
+    match kind {
+      EntryKind::File if inode.size != meta.len() => {
+        self.differ(path, DiffStatus::Size, Some(format!("{} bytes in image, {} bytes on host", inode.size, meta.len())));
+      }
+      EntryKind::File => match self.same_contents(efs_vol, inode, host_path) {
+        Ok(true) => {}
+        Ok(false) => self.differ(path, DiffStatus::Content, None),
+        Err(e) => self.differ(path, DiffStatus::Error, Some(e))
+      },
+      EntryKind::SymbolicLink => {
+        let target = efs_vol.efs.read_link(&mut efs_vol.vol.disk_file, inode);
+        let host_target = fs::read_link(host_path);
+        match (target, host_target, ) {
+          (Ok(target), Ok(host_target), ) if Path::new(&target) == host_target => {}
+          (Ok(target), Ok(host_target), ) => self.differ(path, DiffStatus::Content, Some(format!("'{}' in image, '{}' on host", target, host_target.to_string_lossy()))),
+          (Err(e), _, ) => self.differ(path, DiffStatus::Error, Some(format!("Unable to read symbolic link: {:?}", &e))),
+          (_, Err(e), ) => self.differ(path, DiffStatus::Error, Some(format!("Unable to read host symbolic link: {:?}", &e)))
+        }
+      }
+      EntryKind::Directory | EntryKind::Other => {}
+    }
+    true
+  }
+
+  /// Compare the hashes of a file in the EFS and on the host
+  fn same_contents(&self, efs_vol: &mut OpenEfs, inode: &Inode, host_path: &Path) -> Result<bool, String> {
+    let mut hash = MultiHash::new(self.algorithms);
+    if let Err(e) = efs_vol.efs.read_file(&mut efs_vol.vol.disk_file, inode, &mut hash) {
+      return Err(format!("Unable to read file: {:?}", &e));
+    }
+    let host_hash: MultiHashResult = match File::open(host_path).and_then(|mut f| MultiHash::hash_reader(&mut f, self.algorithms)) {
+      Ok((_, host_hash, )) => host_hash,
+      Err(e) => return Err(format!("Unable to read host file: {:?}", &e))
+    };
+    Ok(hash.finalize() == host_hash)
+  }
+}
+
+/// Names of the entries of a host directory
+fn host_entry_names(dir: &Path) -> std::io::Result<BTreeSet<String>> {
+  let mut names = BTreeSet::new();
+  for entry in fs::read_dir(dir)? {
+    names.insert(entry?.file_name().to_string_lossy().to_string());
+  }
+  Ok(names)
+}
+
+/// Kind of an EFS entry
+fn efs_kind(inode_type: InodeType) -> EntryKind {
+  match inode_type {
+    InodeType::Directory => EntryKind::Directory,
+    InodeType::RegularFile => EntryKind::File,
+    InodeType::SymbolicLink => EntryKind::SymbolicLink,
+    _ => EntryKind::Other
+  }
+}
+
+/// Kind of a host entry
+fn host_kind(file_type: &fs::FileType) -> EntryKind {
+  if file_type.is_dir() {
+    EntryKind::Directory
+  } else if file_type.is_file() {
+    EntryKind::File
+  } else if file_type.is_symlink() {
+    EntryKind::SymbolicLink
+  } else {
+    EntryKind::Other
+  }
+}
+
+/// Permission bits of a host entry, where the host has them
+#[cfg(unix)]
+fn host_mode(meta: &fs::Metadata) -> Option<u32> {
+  use std::os::unix::fs::PermissionsExt;
+  Some(meta.permissions().mode() & 0o7777)
+}
+
+#[cfg(not(unix))]
+fn host_mode(_meta: &fs::Metadata) -> Option<u32> {
+  None
+}
@@ -0,0 +1,122 @@
+use std::collections::BTreeMap;
+use std::process::exit;
+
+use clap::ArgMatches;
+use serde::Serialize;
+
+use sgidisklib::efs::{Inode, InodeType};
+use sgidisklib::hash::{HashAlgorithm, MultiHash};
+use sgidisklib::progress::CancellationToken;
+
+use crate::color::{paint, Style};
+use crate::config::Config;
+use crate::efs::OpenEfs;
+
+/// JSON representation of the duplicate file report
+#[derive(Serialize)]
+struct JsonDupes {
+  sets: Vec<JsonDuplicateSet>,
+  /// Bytes which keeping one copy of each set would save
+  reclaimable_bytes: u64,
+}
+
+/// JSON representation of a set of files with the same contents
+#[derive(Serialize)]
+struct JsonDuplicateSet {
+  /// BLAKE3 hash of the contents
+  hash: String,
+  size_bytes: u64,
+  /// Bytes which keeping one copy would save; hard links to the same inode don't count
+  reclaimable_bytes: u64,
+  /// Paths of the files, in order; hard links are listed under each of their paths
+  paths: Vec<String>,
+}
+
+/// EFS dupes entry point: hash the contents of every regular file, and report the sets of files
+/// with the same contents
+pub(crate) fn subcommand(config: &Config, mut efs_vol: OpenEfs, cli_matches: &ArgMatches) {
+  let format = config.format(cli_matches);
+
+  // Group files by inode, so that hard links are hashed once, then inodes by size, so that only
+  // files which could be duplicates are hashed
+  let mut inodes: BTreeMap<u64, (Inode, Vec<String>, )> = BTreeMap::new();
+  let walk = efs_vol.efs.walk(&mut efs_vol.vol.disk_file, |path, inode_id, inode| {
+    if inode.inode_type == InodeType::RegularFile && inode.size > 0 {
+      inodes.entry(inode_id).or_insert_with(|| (inode.clone(), Vec::new(), )).1.push(path.to_string());
+    }
+  }, &CancellationToken::new(), |_| {});
+  for (path, e, ) in &walk.unreadable {
+    eprintln!("Error: {}: {:?}", path, e);
+  }
+  let mut by_size: BTreeMap<u64, Vec<u64>> = BTreeMap::new();
+  for (inode_id, (inode, _, )) in &inodes {
+    by_size.entry(inode.size).or_default().push(*inode_id);
+  }
+
+  // Hash the files of each size shared by more than one inode
+  let mut failed = 0;
+  let mut by_hash: BTreeMap<(u64, String, ), Vec<u64>> = BTreeMap::new();
+  for (size, inode_ids, ) in by_size.into_iter().filter(|(_, ids, )| ids.len() > 1) {
+    for inode_id in inode_ids {
+      let (inode, paths, ) = &inodes[&inode_id];
+      let mut hash = MultiHash::new(&[HashAlgorithm::Blake3]);
+      if let Err(e) = efs_vol.efs.read_file(&mut efs_vol.vol.disk_file, inode, &mut hash) {
+        eprintln!("Error reading '{}': {:?}", &paths[0], &e);
+        failed += 1;
+        continue;
+      }
+      by_hash.entry((size, hash.finalize().blake3.unwrap(), )).or_default().push(inode_id);
+    }
+  }
+
+  let mut sets: Vec<JsonDuplicateSet> = by_hash.into_iter()
+    .filter(|(_, ids, )| ids.len() > 1)
+    .map(|((size, hash, ), ids, )| {
+      let mut paths: Vec<String> = ids.iter().flat_map(|id| inodes[id].1.iter().cloned()).collect();
+      paths.sort();
+      JsonDuplicateSet {
+        hash,
+        size_bytes: size,
+        reclaimable_bytes: size * (ids.len() as u64 - 1),
+        paths,
+      }
+    })
+    .collect();
+  // Largest savings first
+  sets.sort_by(|a, b| b.reclaimable_bytes.cmp(&a.reclaimable_bytes).then_with(|| a.paths.cmp(&b.paths)));
+  let dupes = JsonDupes {
+    reclaimable_bytes: sets.iter().map(|s| s.reclaimable_bytes).sum(),
+    sets,
+  };
+
+  if format.is_structured() {
+    crate::output::print_json("efs dupes", &dupes);
+  } else if format.is_tabular() {
+    let rows: Vec<Vec<String>> = dupes.sets.iter()
+      .flat_map(|s| s.paths.iter().map(move |path| vec![s.hash.clone(), s.size_bytes.to_string(), path.clone()]))
+      .collect();
+    crate::output::print_table(format, &["hash", "size_bytes", "path"], &rows);
+  } else {
+    print_dupes(&dupes);
+  }
+
+  let failed = failed + walk.unreadable.len();
+  if let Some(exit_code) = crate::exit_codes::for_failures(failed, failed + inodes.len()) {
+    exit(exit_code);
+  }
+}
+
+/// Print each set of duplicates with its paths, then the total which could be saved
+fn print_dupes(dupes: &JsonDupes) {
+  for set in &dupes.sets {
+    println!("{}", paint(&format!("{} files of {} bytes ({} bytes reclaimable), BLAKE3 {}:",
+                                  set.paths.len(), set.size_bytes, set.reclaimable_bytes, &set.hash), Style::Heading));
+    for path in &set.paths {
+      println!("  {}", path);
+    }
+  }
+  if !dupes.sets.is_empty() {
+    println!();
+  }
+  println!("{} duplicate sets, {} bytes reclaimable", dupes.sets.len(), dupes.reclaimable_bytes);
+}
@@ -0,0 +1,142 @@
+//! Filters choosing which entries of a directory tree are extracted: include and exclude globs,
+//! and size, type and modification time ranges
+
+use chrono::{NaiveDate, TimeZone, Utc};
+use serde::Deserialize;
+
+use sgidisklib::efs::{Inode, InodeType};
+use sgidisklib::efs::find::{FindOptions, Glob};
+use sgidisklib::SgidiskLibReadError;
+
+/// Filters as given on the CLI or in a batch operation, before parsing
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct FilterSpec {
+  pub(crate) include: Vec<String>,
+  pub(crate) exclude: Vec<String>,
+  pub(crate) min_size: Option<String>,
+  pub(crate) max_size: Option<String>,
+  /// Types of entry, as `find -type` letters
+  pub(crate) types: Vec<String>,
+  pub(crate) newer: Option<String>,
+  pub(crate) older: Option<String>,
+}
+
+/// Parsed filters. Globs containing a slash match the whole path from the root of the EFS, others
+/// match the entry's name. Other filters apply to everything but directories, which are descended
+/// unless excluded, and left out if nothing within them is extracted.
+#[derive(Debug, Default)]
+pub(crate) struct EntryFilter {
+  include: Vec<Glob>,
+  /// Exclude globs, and the size, type and modification time ranges
+  options: FindOptions,
+}
+
+impl EntryFilter {
+  /// Parse filters, with an error naming any which is invalid
+  pub(crate) fn new(spec: &FilterSpec) -> Result<Self, String> {
+    let types = spec.types.iter()
+      .flat_map(|t| t.split(',').map(|t| t.trim().to_string()).collect::<Vec<_>>())
+      .map(|t| inode_types(&t).ok_or_else(|| format!("Invalid entry type '{}', expected f, l, c, b, p or s", t)))
+      .collect::<Result<Vec<Vec<InodeType>>, String>>()?;
+    Ok(EntryFilter {
+      include: spec.include.iter().map(|p| parse_glob(p)).collect::<Result<_, _>>()?,
+      options: FindOptions {
+        exclude: spec.exclude.iter().map(|p| parse_glob(p)).collect::<Result<_, _>>()?,
+        min_size: spec.min_size.as_deref().map(parse_size).transpose()?,
+        max_size: spec.max_size.as_deref().map(parse_size).transpose()?,
+        types: types.concat(),
+        newer: spec.newer.as_deref().map(parse_time).transpose()?,
+        older: spec.older.as_deref().map(parse_time).transpose()?,
+      },
+    })
+  }
+
+  /// Exclude globs, and the size, type and modification time ranges
+  pub(crate) fn find_options(&self) -> &FindOptions {
+    &self.options
+  }
+
+  /// Whether any filter is set
+  pub(crate) fn is_active(&self) -> bool {
+    !self.include.is_empty() || self.options != FindOptions::default()
+  }
+
+  /// Whether an entry (other than a directory) at a path within the EFS is extracted
+  pub(crate) fn matches(&self, path: &str, inode: &Inode) -> bool {
+    self.options.matches(path, inode) && (self.include.is_empty() || self.included(path))
+  }
+
+  /// Whether a directory at a path within the EFS is descended: unless excluded, if it or a
+  /// directory above it is included, or an include glob could match something within it
+  pub(crate) fn descends(&self, path: &str) -> bool {
+    !self.options.excluded(path) && (self.include.is_empty() || self.included(path) || self.include.iter().any(|glob| glob.leads_to(path)))
+  }
+
+  /// Whether a directory is kept even if nothing within it is extracted, as it was included itself
+  pub(crate) fn keeps_empty(&self, path: &str) -> bool {
+    !self.is_active() || self.included(path)
+  }
+
+  /// Whether a path or a directory above it matches an include glob
+  fn included(&self, path: &str) -> bool {
+    ancestors(path).any(|p| self.include.iter().any(|glob| glob.matches(p)))
+  }
+}
+
+/// Parse a glob, with an error naming it if it is invalid
+fn parse_glob(glob: &str) -> Result<Glob, String> {
+  match Glob::new(glob) {
+    Ok(glob) => Ok(glob),
+    Err(SgidiskLibReadError::Value(message)) => Err(message),
+    Err(e) => Err(format!("Invalid glob '{}': {:?}", glob, e))
+  }
+}
+
+/// Types of entry for a letter, as `find -type` takes
+fn inode_types(letter: &str) -> Option<Vec<InodeType>> {
+  match letter {
+    "f" => Some(vec![InodeType::RegularFile]),
+    "l" => Some(vec![InodeType::SymbolicLink]),
+    "c" => Some(vec![InodeType::CharacterSpecial, InodeType::CharacterSpecialLink]),
+    "b" => Some(vec![InodeType::BlockSpecial, InodeType::BlockSpecialLink]),
+    "p" => Some(vec![InodeType::Fifo]),
+    "s" => Some(vec![InodeType::Socket]),
+    _ => None
+  }
+}
+
+/// A path and the directories above it, e.g. `/usr/bin`, then `/usr`
+fn ancestors(path: &str) -> impl Iterator<Item=&str> {
+  std::iter::successors(Some(path), |p| match p.rfind('/') {
+    Some(0) | None => None,
+    Some(i) => Some(&p[..i])
+  })
+}
+
+/// Parse a size in bytes, with an optional binary unit suffix, e.g. `512`, `64K` or `2M`
+pub(crate) fn parse_size(size: &str) -> Result<u64, String> {
+  let (digits, multiplier, ) = match size.chars().last().map(|c| c.to_ascii_uppercase()) {
+    Some('K') => (&size[..size.len() - 1], 1u64 << 10, ),
+    Some('M') => (&size[..size.len() - 1], 1u64 << 20, ),
+    Some('G') => (&size[..size.len() - 1], 1u64 << 30, ),
+    _ => (size, 1, )
+  };
+  digits.parse::<u64>().ok()
+    .and_then(|n| n.checked_mul(multiplier))
+    .ok_or_else(|| format!("Invalid size '{}'", size))
+}
+
+/// Parse a time as seconds since the epoch, an RFC 3339 time, or a date (midnight UTC)
+fn parse_time(time: &str) -> Result<i64, String> {
+  if let Ok(secs) = time.parse::<i64>() {
+    return Ok(secs);
+  }
+  if let Ok(t) = chrono::DateTime::parse_from_rfc3339(time) {
+    return Ok(t.timestamp());
+  }
+  NaiveDate::parse_from_str(time, "%Y-%m-%d").ok()
+    .and_then(|d| d.and_hms_opt(0, 0, 0))
+    .map(|t| Utc.from_utc_datetime(&t).timestamp())
+    .ok_or_else(|| format!("Invalid time '{}', expected seconds since the epoch, YYYY-MM-DD or RFC 3339", time))
+}
@@ -0,0 +1,64 @@
+use std::process::exit;
+
+use clap::ArgMatches;
+use serde::Serialize;
+
+use crate::config::Config;
+use crate::efs::OpenEfs;
+use crate::efs::filter::EntryFilter;
+
+/// JSON representation of an entry found
+#[derive(Serialize)]
+struct JsonFound {
+  path: String,
+  inode: u64,
+  inode_type: String,
+  size_bytes: u64,
+  mtime: i64,
+}
+
+/// EFS find entry point: list the paths of entries matching a glob and the filters
+pub(crate) fn subcommand(config: &Config, mut efs_vol: OpenEfs, cli_matches: &ArgMatches) {
+  let format = config.format(cli_matches);
+  let pattern = cli_matches.value_of("pattern").unwrap();
+  let filter = match EntryFilter::new(&super::cp::filter_spec(cli_matches)) {
+    Ok(filter) => filter,
+    Err(e) => {
+      eprintln!("{}", e);
+      exit(crate::exit_codes::CLI_ARG_ERROR);
+    }
+  };
+
+  let (found, walk, ) = match efs_vol.efs.find(&mut efs_vol.vol.disk_file, pattern, filter.find_options()) {
+    Ok(found) => found,
+    Err(e) => {
+      eprintln!("Invalid pattern '{}': {:?}", pattern, &e);
+      exit(crate::exit_codes::CLI_ARG_ERROR);
+    }
+  };
+  for (path, e, ) in &walk.unreadable {
+    eprintln!("Unable to read directory '{}': {:?}", path, e);
+  }
+
+  if format.is_structured() {
+    let json_found: Vec<JsonFound> = found.into_iter()
+      .map(|f| JsonFound {
+        path: f.path,
+        inode: f.inode_id,
+        inode_type: format!("{:?}", f.inode.inode_type),
+        size_bytes: f.inode.size,
+        mtime: f.inode.mtime_raw,
+      })
+      .collect();
+    crate::output::print_json("efs find", &json_found);
+  } else if format.is_tabular() {
+    let rows: Vec<Vec<String>> = found.iter()
+      .map(|f| vec![f.path.clone(), f.inode_id.to_string(), format!("{:?}", f.inode.inode_type), f.inode.size.to_string(), f.inode.mtime_raw.to_string()])
+      .collect();
+    crate::output::print_table(format, &["path", "inode", "inode_type", "size_bytes", "mtime"], &rows);
+  } else {
+    for f in &found {
+      println!("{}", f.path);
+    }
+  }
+}
@@ -0,0 +1,255 @@
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::process::exit;
+
+use clap::ArgMatches;
+use serde::Serialize;
+
+use sgidisklib::SgidiskLibReadError;
+use sgidisklib::efs::{Inode, InodeType};
+use sgidisklib::efs::dir::Directory;
+use sgidisklib::efs::find::Glob;
+
+use crate::color::{paint, Style};
+use crate::config::Config;
+use crate::efs::OpenEfs;
+use crate::output::DateFormat;
+
+/// Key to sort a listing by
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+enum SortKey {
+  Name,
+  /// Largest first
+  Size,
+  /// Newest first
+  Mtime,
+  /// As stored in the directory
+  Disk,
+}
+
+/// JSON representation of a listed entry
+#[derive(Serialize)]
+struct JsonEntry {
+  name: String,
+  inode: u64,
+  inode_type: String,
+  mode: u16,
+  uid: u16,
+  gid: u16,
+  size_bytes: u64,
+  mtime: i64,
+  /// Target of a symbolic link
+  link: Option<String>,
+  /// What a regular file's contents are, with --content-type
+  #[serde(skip_serializing_if = "Option::is_none")]
+  content_type: Option<String>,
+}
+
+/// EFS ls entry point: list a directory, a single entry, or the entries of a directory matching
+/// a glob pattern
+pub(crate) fn subcommand(config: &Config, mut efs_vol: OpenEfs, cli_matches: &ArgMatches) {
+  let format = config.format(cli_matches);
+  let path = cli_matches.value_of("pattern").unwrap_or("/");
+  let sniff = cli_matches.is_present("content-type");
+  let sort = match cli_matches.value_of("sort") {
+    Some("size") => SortKey::Size,
+    Some("mtime") => SortKey::Mtime,
+    Some("disk") => SortKey::Disk,
+    _ => SortKey::Name
+  };
+
+  let mut entries = match list(&mut efs_vol, path, sort == SortKey::Disk) {
+    Ok(entries) => entries,
+    Err(e) => {
+      eprintln!("Error listing '{}': {:?}", path, &e);
+      exit(crate::exit_codes::for_lib_error(&e));
+    }
+  };
+  if sort != SortKey::Disk {
+    entries.sort_by(|(a_name, _, a, ), (b_name, _, b, )| match sort {
+      SortKey::Size => b.size.cmp(&a.size),
+      SortKey::Mtime => b.mtime.cmp(&a.mtime),
+      SortKey::Name | SortKey::Disk => Ordering::Equal
+    }.then_with(|| a_name.cmp(b_name)));
+  }
+  if cli_matches.is_present("reverse") {
+    entries.reverse();
+  }
+
+  let entries: Vec<_> = entries.into_iter()
+    .map(|(name, inode_id, inode, )| {
+      let link = match inode.inode_type {
+        InodeType::SymbolicLink => efs_vol.efs.read_link(&mut efs_vol.vol.disk_file, &inode).ok(),
+        _ => None
+      };
+      (name, inode_id, inode, link, )
+    })
+    .collect();
+  let content_types: Vec<Option<&'static str>> = entries.iter()
+    .map(|(_, _, inode, _, )| match inode.inode_type {
+      InodeType::RegularFile if sniff => efs_vol.efs.content_type(&mut efs_vol.vol.disk_file, inode).ok().map(|t| t.name()),
+      _ => None
+    })
+    .collect();
+
+  if format.is_tabular() {
+    // The content type column is only added when asked for, so existing columns never change
+    let mut header = vec!["name", "inode", "inode_type", "mode", "uid", "gid", "size_bytes", "mtime", "link"];
+    if sniff {
+      header.push("content_type");
+    }
+    let rows: Vec<Vec<String>> = entries.iter().zip(&content_types)
+      .map(|((name, inode_id, inode, link, ), content_type, )| {
+        let mut row = vec![
+          name.clone(), inode_id.to_string(), format!("{:?}", inode.inode_type), format!("{:04o}", inode.unix_mode),
+          inode.owner_uid.to_string(), inode.owner_gid.to_string(), inode.size.to_string(),
+          inode.mtime_raw.to_string(), link.clone().unwrap_or_default()];
+        if sniff {
+          row.push(content_type.unwrap_or_default().to_string());
+        }
+        row
+      })
+      .collect();
+    crate::output::print_table(format, &header, &rows);
+  } else if format.is_structured() {
+    let json_entries: Vec<JsonEntry> = entries.into_iter().zip(content_types)
+      .map(|((name, inode_id, inode, link, ), content_type, )| JsonEntry {
+        name,
+        inode: inode_id,
+        inode_type: format!("{:?}", inode.inode_type),
+        mode: inode.unix_mode,
+        uid: inode.owner_uid,
+        gid: inode.owner_gid,
+        size_bytes: inode.size,
+        mtime: inode.mtime_raw,
+        link,
+        content_type: content_type.map(|t| t.to_string()),
+      })
+      .collect();
+    crate::output::print_json("efs ls", &json_entries);
+  } else {
+    print_entries(&entries, &content_types, &config.date_format(cli_matches), cli_matches.is_present("inode"), cli_matches.is_present("human-readable"));
+  }
+}
+
+/// Entries to list for a path: a directory's entries, the entry itself, or the entries of its
+/// parent directory whose names match its last component as a glob pattern. A directory's entries
+/// are in name order, or in the order they are stored if `disk_order` is set.
+fn list(efs_vol: &mut OpenEfs, path: &str, disk_order: bool) -> Result<Vec<(String, u64, Inode, )>, SgidiskLibReadError> {
+  let lookup_err = match efs_vol.efs.lookup_path(&mut efs_vol.vol.disk_file, path) {
+    Ok((inode_id, inode, )) if inode.inode_type == InodeType::Directory => {
+      let dir = Directory::read_dir(&mut efs_vol.vol.disk_file, &efs_vol.efs, inode_id)?;
+      let entries = dir.entries.into_iter()
+        .filter(|(name, _, )| *name != "." && *name != "..")
+        .map(|(name, (inode_id, inode, ), )| (name, inode_id, inode, ))
+        .collect();
+      return match disk_order {
+        true => in_disk_order(efs_vol, inode_id, entries),
+        false => Ok(entries)
+      };
+    }
+    Ok((inode_id, inode, )) => return Ok(vec![(path.to_string(), inode_id, inode, )]),
+    Err(e) => e
+  };
+
+  // Not found: try the last component as a pattern
+  let (parent, pattern, ) = match path.rsplit_once('/') {
+    Some((parent, pattern, )) => (if parent.is_empty() { "/" } else { parent }, pattern, ),
+    None => ("/", path, )
+  };
+  let pattern = match Glob::new(pattern) {
+    Ok(pattern) => pattern,
+    Err(_) => return Err(lookup_err)
+  };
+  let (parent_id, parent_inode, ) = efs_vol.efs.lookup_path(&mut efs_vol.vol.disk_file, parent)?;
+  if parent_inode.inode_type != InodeType::Directory {
+    return Err(lookup_err);
+  }
+  let dir = Directory::read_dir(&mut efs_vol.vol.disk_file, &efs_vol.efs, parent_id)?;
+  let matched: Vec<_> = dir.entries.into_iter()
+    .filter(|(name, _, )| *name != "." && *name != ".." && pattern.matches(name))
+    .map(|(name, (inode_id, inode, ), )| (name, inode_id, inode, ))
+    .collect();
+  if matched.is_empty() {
+    return Err(lookup_err);
+  }
+  match disk_order {
+    true => in_disk_order(efs_vol, parent_id, matched),
+    false => Ok(matched)
+  }
+}
+
+/// Entries of a directory put in the order they are stored in it
+fn in_disk_order(efs_vol: &mut OpenEfs, dir_id: u64, mut entries: Vec<(String, u64, Inode, )>) -> Result<Vec<(String, u64, Inode, )>, SgidiskLibReadError> {
+  let ordered = Directory::read_dir_ordered(&mut efs_vol.vol.disk_file, &efs_vol.efs, dir_id)?;
+  let positions: HashMap<String, usize> = ordered.slots.into_iter()
+    .filter_map(|slot| slot.entry)
+    .enumerate()
+    .map(|(position, entry, )| (entry.name, position, ))
+    .collect();
+  entries.sort_by_key(|(name, _, _, )| positions.get(name).copied().unwrap_or(usize::MAX));
+  Ok(entries)
+}
+
+/// Print entries in the style of `ls -l`
+fn print_entries(entries: &[(String, u64, Inode, Option<String>, )], content_types: &[Option<&str>], date_format: &DateFormat, show_inode: bool, human_readable: bool) {
+  let sniffed = content_types.iter().any(|t| t.is_some());
+  let rows: Vec<Vec<String>> = entries.iter().zip(content_types)
+    .map(|((_, inode_id, inode, _, ), content_type, )| {
+      let mut row = Vec::with_capacity(6);
+      if show_inode {
+        row.push(inode_id.to_string());
+      }
+      row.push(format!("{}{}", super::type_char(inode.inode_type), super::permissions_string(inode.unix_mode)));
+      row.push(inode.owner_uid.to_string());
+      row.push(inode.owner_gid.to_string());
+      row.push(if human_readable { human_size(inode.size) } else { inode.size.to_string() });
+      row.push(date_format.format(&inode.mtime));
+      if sniffed {
+        row.push(content_type.unwrap_or("-").to_string());
+      }
+      row
+    })
+    .collect();
+
+  // Left-align the mode and date, right-align numbers, like ls does
+  let columns = rows.first().map(|r| r.len()).unwrap_or(0);
+  let widths: Vec<usize> = (0..columns)
+    .map(|c| rows.iter().map(|r| r[c].len()).max().unwrap_or(0))
+    .collect();
+  let mode_column = if show_inode { 1 } else { 0 };
+  for (row, (name, _, inode, link, ), ) in rows.iter().zip(entries) {
+    let mut line = String::new();
+    for (c, (field, width, ), ) in row.iter().zip(&widths).enumerate() {
+      if c == mode_column || c == columns - 1 {
+        line.push_str(&format!("{:<w$} ", field, w = *width));
+      } else {
+        line.push_str(&format!("{:>w$} ", field, w = *width));
+      }
+    }
+    let name = if inode.inode_type == InodeType::Directory { paint(name, Style::Heading) } else { name.clone() };
+    match link {
+      Some(target) => println!("{}{} -> {}", line, name, target),
+      None => println!("{}{}", line, name)
+    }
+  }
+}
+
+/// Size with a binary unit suffix, as `ls -h` shows it, e.g. `1.5K` or `12M`
+fn human_size(size: u64) -> String {
+  const UNITS: [char; 6] = ['K', 'M', 'G', 'T', 'P', 'E'];
+  if size < 1024 {
+    return size.to_string();
+  }
+  let mut value = size as f64 / 1024.0;
+  let mut unit = 0;
+  while value >= 1024.0 && unit < UNITS.len() - 1 {
+    value /= 1024.0;
+    unit += 1;
+  }
+  if value < 10.0 {
+    format!("{:.1}{}", value, UNITS[unit])
+  } else {
+    format!("{:.0}{}", value, UNITS[unit])
+  }
+}
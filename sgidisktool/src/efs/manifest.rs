@@ -0,0 +1,289 @@
+use std::io;
+use std::io::Write;
+
+use chrono::{DateTime, Local};
+use serde::Serialize;
+
+use sgidisklib::efs::{Efs, EFS_BLOCK_SZ, Inode, InodeType};
+
+use crate::output::AtomicFile;
+
+/// Format of a manifest of skipped entries
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub(crate) enum ManifestFormat {
+  /// JSON object with the schema version and an array of entries
+  Json,
+  /// CSV table of entries
+  Csv,
+  /// BSD mtree specification
+  Mtree,
+  /// Digital Forensics XML, with the byte runs of each entry's contents in the disk image
+  Dfxml,
+}
+
+/// Run of contiguous bytes of an entry's contents within the disk image
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub(crate) struct ByteRun {
+  /// Offset into the entry's contents
+  pub(crate) file_offset: u64,
+  /// Offset into the disk image
+  pub(crate) img_offset: u64,
+  pub(crate) len: u64,
+}
+
+/// JSON manifest of skipped entries
+#[derive(Serialize)]
+struct JsonManifest<'a> {
+  /// Version of the manifest structure (JSON_SCHEMA_VERSION)
+  schema_version: u32,
+  entries: &'a [ManifestEntry],
+}
+
+/// Metadata of an entry which was not extracted, so that it isn't lost
+#[derive(Serialize)]
+pub(crate) struct ManifestEntry {
+  /// Path within the EFS
+  pub(crate) src: String,
+  /// Path relative to the destination directory where the entry would have been extracted
+  pub(crate) path: String,
+  pub(crate) inode: u64,
+  #[serde(skip)]
+  pub(crate) kind: InodeType,
+  pub(crate) inode_type: String,
+  /// Permissions, in octal
+  pub(crate) mode: String,
+  pub(crate) uid: u16,
+  pub(crate) gid: u16,
+  pub(crate) size_bytes: u64,
+  /// Modification time, in seconds since the epoch
+  pub(crate) mtime: i64,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub(crate) device_major: Option<u32>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub(crate) device_minor: Option<u32>,
+  /// Target of a symbolic link
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub(crate) link: Option<String>,
+  /// What a regular file's contents are, from their magic number, e.g. "elf"
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub(crate) content_type: Option<String>,
+  /// Why the entry was not extracted
+  pub(crate) reason: String,
+  /// Change, modification and access times, in ISO 8601 UTC
+  #[serde(skip)]
+  pub(crate) times: [String; 3],
+  #[serde(skip)]
+  pub(crate) byte_runs: Vec<ByteRun>,
+}
+
+impl ManifestFormat {
+  /// Parse from the name used on the CLI
+  pub(crate) fn from_name(name: &str) -> Option<Self> {
+    match name {
+      "json" => Some(ManifestFormat::Json),
+      "csv" => Some(ManifestFormat::Csv),
+      "mtree" => Some(ManifestFormat::Mtree),
+      "dfxml" => Some(ManifestFormat::Dfxml),
+      _ => None
+    }
+  }
+}
+
+impl ManifestEntry {
+  /// Create an entry from an inode which was not extracted
+  pub(crate) fn new(efs: &Efs, src: &str, path: &str, inode_id: u64, inode: &Inode, link: Option<String>, reason: &str) -> Self {
+    ManifestEntry {
+      src: src.to_string(),
+      path: path.to_string(),
+      inode: inode_id,
+      kind: inode.inode_type,
+      inode_type: format!("{:?}", inode.inode_type),
+      mode: format!("{:04o}", inode.unix_mode),
+      uid: inode.owner_uid,
+      gid: inode.owner_gid,
+      size_bytes: inode.size,
+      mtime: inode.mtime_raw,
+      device_major: inode.device.map(|d| d.major),
+      device_minor: inode.device.map(|d| d.minor),
+      link,
+      content_type: None,
+      reason: reason.to_string(),
+      times: [iso8601(&inode.ctime), iso8601(&inode.mtime), iso8601(&inode.atime)],
+      byte_runs: byte_runs(efs, inode),
+    }
+  }
+
+  /// mtree type keyword for an inode type
+  fn mtree_type(inode_type: InodeType) -> &'static str {
+    match inode_type {
+      InodeType::Fifo => "fifo",
+      InodeType::CharacterSpecial | InodeType::CharacterSpecialLink => "char",
+      InodeType::Directory => "dir",
+      InodeType::BlockSpecial | InodeType::BlockSpecialLink => "block",
+      InodeType::RegularFile => "file",
+      InodeType::SymbolicLink => "link",
+      InodeType::Socket => "socket"
+    }
+  }
+
+  /// DFXML meta_type (as used by The Sleuth Kit) for an inode type
+  fn dfxml_meta_type(inode_type: InodeType) -> u8 {
+    match inode_type {
+      InodeType::RegularFile => 1,
+      InodeType::Directory => 2,
+      InodeType::Fifo => 3,
+      InodeType::CharacterSpecial | InodeType::CharacterSpecialLink => 4,
+      InodeType::BlockSpecial | InodeType::BlockSpecialLink => 5,
+      InodeType::SymbolicLink => 6,
+      InodeType::Socket => 8
+    }
+  }
+
+  /// Write as a DFXML fileobject
+  fn write_dfxml<W: ?Sized>(&self, writer: &mut W) -> io::Result<()>
+    where W: Write {
+    let [ctime, mtime, atime] = &self.times;
+    writeln!(writer, "  <fileobject>")?;
+    writeln!(writer, "    <filename>{}</filename>", xml_escape(&self.src))?;
+    writeln!(writer, "    <filesize>{}</filesize>", self.size_bytes)?;
+    writeln!(writer, "    <inode>{}</inode>", self.inode)?;
+    writeln!(writer, "    <meta_type>{}</meta_type>", Self::dfxml_meta_type(self.kind))?;
+    writeln!(writer, "    <mode>{}</mode>", u32::from_str_radix(&self.mode, 8).unwrap_or(0))?;
+    writeln!(writer, "    <uid>{}</uid>", self.uid)?;
+    writeln!(writer, "    <gid>{}</gid>", self.gid)?;
+    writeln!(writer, "    <mtime>{}</mtime>", mtime)?;
+    writeln!(writer, "    <ctime>{}</ctime>", ctime)?;
+    writeln!(writer, "    <atime>{}</atime>", atime)?;
+    if let Some(link) = &self.link {
+      writeln!(writer, "    <link_target>{}</link_target>", xml_escape(link))?;
+    }
+    if !self.byte_runs.is_empty() {
+      writeln!(writer, "    <byte_runs>")?;
+      for run in &self.byte_runs {
+        writeln!(writer, "      <byte_run file_offset=\"{}\" img_offset=\"{}\" len=\"{}\"/>", run.file_offset, run.img_offset, run.len)?;
+      }
+      writeln!(writer, "    </byte_runs>")?;
+    }
+    writeln!(writer, "  </fileobject>")
+  }
+
+  /// Header of a CSV manifest
+  const CSV_HEADER: [&'static str; 14] = ["src", "path", "inode", "inode_type", "mode", "uid", "gid", "size_bytes", "mtime",
+                                          "device_major", "device_minor", "link", "reason", "content_type"];
+
+  /// Fields of a CSV manifest record, in the order of the header
+  fn csv_record(&self) -> Vec<String> {
+    let opt = |v: Option<u32>| v.map(|v| v.to_string()).unwrap_or_default();
+    vec![self.src.clone(), self.path.clone(), self.inode.to_string(), self.inode_type.clone(), self.mode.clone(),
+         self.uid.to_string(), self.gid.to_string(), self.size_bytes.to_string(), self.mtime.to_string(),
+         opt(self.device_major), opt(self.device_minor), self.link.clone().unwrap_or_default(), self.reason.clone(),
+         self.content_type.clone().unwrap_or_default()]
+  }
+
+  /// Write as a line of an mtree specification
+  fn write_mtree<W: ?Sized>(&self, writer: &mut W) -> io::Result<()>
+    where W: Write {
+    write!(writer, "{} type={} mode={} uid={} gid={} size={} time={}.0",
+           mtree_escape(&format!("./{}", &self.path)), Self::mtree_type(self.kind), &self.mode, self.uid, self.gid, self.size_bytes, self.mtime)?;
+    if let (Some(major), Some(minor), ) = (self.device_major, self.device_minor, ) {
+      write!(writer, " device=native,{},{}", major, minor)?;
+    }
+    if let Some(link) = &self.link {
+      write!(writer, " link={}", mtree_escape(link))?;
+    }
+    writeln!(writer)
+  }
+}
+
+/// Escape whitespace, backslashes, comment markers and non-printable characters in an mtree
+/// path, as octal escapes
+fn mtree_escape(s: &str) -> String {
+  let mut escaped = String::with_capacity(s.len());
+  for b in s.bytes() {
+    if b.is_ascii_graphic() && b != b'\\' && b != b'#' {
+      escaped.push(b as char);
+    } else {
+      escaped.push_str(&format!("\\{:03o}", b));
+    }
+  }
+  escaped
+}
+
+/// Time in ISO 8601 UTC, as DFXML expects
+fn iso8601(t: &DateTime<Local>) -> String {
+  t.naive_utc().format("%Y-%m-%dT%H:%M:%SZ").to_string()
+}
+
+/// Runs of an inode's contents within the disk image, merging contiguous blocks
+fn byte_runs(efs: &Efs, inode: &Inode) -> Vec<ByteRun> {
+  let block_sz = EFS_BLOCK_SZ as u64;
+  let mut runs: Vec<ByteRun> = Vec::new();
+  for (n, block, ) in inode.iter().enumerate() {
+    let file_offset = n as u64 * block_sz;
+    if file_offset >= inode.size {
+      break;
+    }
+    let img_offset = efs.partition_start + block * block_sz;
+    let len = block_sz.min(inode.size - file_offset);
+    match runs.last_mut() {
+      Some(run) if run.img_offset + run.len == img_offset => run.len += len,
+      _ => runs.push(ByteRun { file_offset, img_offset, len })
+    }
+  }
+  runs
+}
+
+/// Escape text or an attribute value for XML
+fn xml_escape(s: &str) -> String {
+  let mut escaped = String::with_capacity(s.len());
+  for c in s.chars() {
+    match c {
+      '&' => escaped.push_str("&amp;"),
+      '<' => escaped.push_str("&lt;"),
+      '>' => escaped.push_str("&gt;"),
+      '"' => escaped.push_str("&quot;"),
+      '\'' => escaped.push_str("&apos;"),
+      // Control characters can't appear in XML 1.0
+      c if c.is_control() && c != '\t' && c != '\n' => escaped.push_str(&format!("\\x{:02x}", c as u32)),
+      c => escaped.push(c)
+    }
+  }
+  escaped
+}
+
+/// Write a manifest of entries which were not extracted to a file, atomically
+pub(crate) fn write_manifest(file_name: &str, format: ManifestFormat, entries: &[ManifestEntry]) -> io::Result<()> {
+  let mut writer = AtomicFile::create(file_name)?;
+  match format {
+    ManifestFormat::Json => {
+      serde_json::to_writer_pretty(&mut writer, &JsonManifest {
+        schema_version: crate::output::JSON_SCHEMA_VERSION,
+        entries,
+      })?;
+      writeln!(writer)?;
+    }
+    ManifestFormat::Csv => {
+      let rows: Vec<Vec<String>> = entries.iter().map(|e| e.csv_record()).collect();
+      crate::output::write_csv(&mut writer, &ManifestEntry::CSV_HEADER, &rows)?;
+    }
+    ManifestFormat::Mtree => {
+      writeln!(writer, "#mtree")?;
+      for entry in entries {
+        entry.write_mtree(&mut writer)?;
+      }
+    }
+    ManifestFormat::Dfxml => {
+      writeln!(writer, "<?xml version=\"1.0\" encoding=\"UTF-8\"?>")?;
+      writeln!(writer, "<dfxml xmlns=\"http://www.forensicswiki.org/wiki/Category:Digital_Forensics_XML\" version=\"1.0\">")?;
+      writeln!(writer, "  <creator>")?;
+      writeln!(writer, "    <program>{}</program>", env!("CARGO_PKG_NAME"))?;
+      writeln!(writer, "    <version>{}</version>", env!("CARGO_PKG_VERSION"))?;
+      writeln!(writer, "  </creator>")?;
+      for entry in entries {
+        entry.write_dfxml(&mut writer)?;
+      }
+      writeln!(writer, "</dfxml>")?;
+    }
+  }
+  writer.commit()
+}
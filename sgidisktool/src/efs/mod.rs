@@ -1,14 +1,96 @@
 use std::process::exit;
 use clap::ArgMatches;
+use tracing::debug;
 
-/// Volume Header tool entry point
-pub(crate) fn subcommand(disk_file_name: &str, cli_matches: &ArgMatches) {
-  let partition_id = cli_matches.
-  let mut vol = crate::OpenVolume::open_or_quit(disk_file_name);
+use sgidisklib::efs::{Efs, InodeType};
+use sgidisklib::volhdr::PartitionType;
 
+use crate::color::{paint_err, Style};
+use crate::config::Config;
+use crate::exit_codes::CommandError;
+use crate::OpenVolume;
+
+pub(crate) mod cp;
+mod diff;
+mod dupes;
+pub(crate) mod filter;
+mod find;
+mod ls;
+pub(crate) mod manifest;
+pub(crate) mod sanitize;
+mod slack;
+mod sparse;
+mod timeline;
+mod timestamps;
+#[cfg(feature = "webdav")]
+mod webdav;
+
+/// EFS tool entry point
+pub(crate) fn subcommand(config: &Config, disk_file_name: &str, cli_matches: &ArgMatches) {
+  let partition = cli_matches.value_of("partition").unwrap();
+  let partition_id = match partition.parse::<usize>() {
+    Ok(id) => id,
+    Err(_) => {
+      eprintln!("Invalid partition ID '{}'", partition);
+      exit(super::exit_codes::CLI_ARG_ERROR);
+    }
+  };
 
   match cli_matches.subcommand_name() {
-    // EFS tool
+    // List files
+    Some("ls") => {
+      let mut vol = OpenVolume::open_or_quit(disk_file_name);
+      ls::subcommand(config, open_efs(&mut vol, partition_id, cli_matches), cli_matches.subcommand_matches("ls").unwrap())
+    }
+    // Find entries by glob and filters
+    Some("find") => {
+      let mut vol = OpenVolume::open_or_quit(disk_file_name);
+      find::subcommand(config, open_efs(&mut vol, partition_id, cli_matches), cli_matches.subcommand_matches("find").unwrap())
+    }
+    // Copy / extract files
+    Some("cp") => {
+      let mut vol = OpenVolume::open_or_quit(disk_file_name);
+      cp::subcommand(config, open_efs(&mut vol, partition_id, cli_matches), cli_matches.subcommand_matches("cp").unwrap())
+    }
+    // Compare with a host directory
+    Some("diff") => {
+      let mut vol = OpenVolume::open_or_quit(disk_file_name);
+      diff::subcommand(config, open_efs(&mut vol, partition_id, cli_matches), cli_matches.subcommand_matches("diff").unwrap())
+    }
+    // Files with the same contents
+    Some("dupes") => {
+      let mut vol = OpenVolume::open_or_quit(disk_file_name);
+      dupes::subcommand(config, open_efs(&mut vol, partition_id, cli_matches), cli_matches.subcommand_matches("dupes").unwrap())
+    }
+    // Extract slack space of files
+    Some("slack") => {
+      let mut vol = OpenVolume::open_or_quit(disk_file_name);
+      slack::subcommand(config, open_efs(&mut vol, partition_id, cli_matches), cli_matches.subcommand_matches("slack").unwrap())
+    }
+    // Timeline of entries' timestamps
+    Some("timeline") => {
+      let mut vol = OpenVolume::open_or_quit(disk_file_name);
+      timeline::subcommand(open_efs(&mut vol, partition_id, cli_matches), cli_matches.subcommand_matches("timeline").unwrap())
+    }
+    // Entries with suspicious timestamps
+    Some("timestamps") => {
+      let mut vol = OpenVolume::open_or_quit(disk_file_name);
+      timestamps::subcommand(config, open_efs(&mut vol, partition_id, cli_matches), cli_matches.subcommand_matches("timestamps").unwrap())
+    }
+    // Serve read-only over WebDAV
+    Some("serve-webdav") => {
+      #[cfg(feature = "webdav")]
+      {
+        let mut vol = OpenVolume::open_or_quit(disk_file_name);
+        webdav::subcommand(open_efs(&mut vol, partition_id, cli_matches), cli_matches.subcommand_matches("serve-webdav").unwrap())
+      }
+      #[cfg(not(feature = "webdav"))]
+      {
+        eprintln!("This build of sgidisktool can't serve WebDAV; rebuild it with the webdav feature");
+        exit(super::exit_codes::CLI_ARG_ERROR);
+      }
+    }
+
     // Unimplemented / unknown sub-command
     Some(subcommand_name) => {
       eprintln!("Unimplemented sub-command: {}", subcommand_name);
@@ -23,4 +105,90 @@ pub(crate) fn subcommand(disk_file_name: &str, cli_matches: &ArgMatches) {
   }
 }
 
-pub(crate)
\ No newline at end of file
+/// Read the EFS filesystem from the numbered partition of an open disk image, with the options
+/// common to every EFS sub-command, or quit if there is an error
+fn open_efs<'v, 'a>(vol: &'v mut OpenVolume<'a>, partition_id: usize, cli_matches: &ArgMatches) -> OpenEfs<'v, 'a> {
+  let mut efs_vol = OpenEfs::open_or_quit(vol, partition_id);
+  efs_vol.efs.ignore_case = cli_matches.is_present("ignore-case");
+  efs_vol
+}
+
+/// Open disk image with an EFS filesystem read from one of its partitions
+pub(crate) struct OpenEfs<'v, 'a> {
+  pub(crate) vol: &'v mut OpenVolume<'a>,
+  /// Index of EFS partition in the partition table
+  pub(crate) partition_id: usize,
+  pub(crate) efs: Efs,
+}
+
+impl<'v, 'a> OpenEfs<'v, 'a> {
+  /// Read the EFS filesystem from the numbered partition of an open disk image
+  pub(crate) fn open(vol: &'v mut OpenVolume<'a>, partition_id: usize) -> Result<Self, CommandError> {
+    let disk_file_name = vol.disk_file_name;
+
+    // Check that the partition is an EFS partition
+    let p = match vol.volume_header.partitions.get(partition_id) {
+      Some(p) if p.in_use() => p,
+      _ => return Err(CommandError::new(crate::exit_codes::EFS_OPEN_ERR, format!("Partition {} is not in use in disk image '{}'", partition_id, disk_file_name)))
+    };
+    if p.partition_type != PartitionType::Efs {
+      return Err(CommandError::new(crate::exit_codes::EFS_OPEN_ERR, format!("Partition {} is {}, not EFS", partition_id, p.partition_type)));
+    }
+
+    // Read superblock
+    let partition_start = p.byte_start();
+    let sector_sz = vol.volume_header.sector_sz as u64;
+    let efs = match Efs::read(&mut vol.disk_file, sector_sz, partition_start) {
+      Ok(efs) => efs,
+      Err(e) => return Err(CommandError::new(crate::exit_codes::for_lib_error(&e), format!("Unable to read EFS from partition {} of disk image '{}': {:?}", partition_id, disk_file_name, &e)))
+    };
+    debug!("EFS on partition {}: {:?}", partition_id, &efs);
+
+    Ok(Self {
+      vol,
+      partition_id,
+      efs,
+    })
+  }
+
+  /// Read the EFS filesystem from an open disk image, or quit if there is an error
+  pub(crate) fn open_or_quit(vol: &'v mut OpenVolume<'a>, partition_id: usize) -> Self {
+    match Self::open(vol, partition_id) {
+      Ok(efs_vol) => efs_vol,
+      Err(e) => {
+        eprintln!("{} {}", paint_err("Error:", Style::Error), &e.message);
+        exit(e.exit_code);
+      }
+    }
+  }
+}
+
+/// Type of an inode as `ls -l` shows it, e.g. `-` for regular files and `d` for directories
+pub(crate) fn type_char(inode_type: InodeType) -> char {
+  match inode_type {
+    InodeType::RegularFile => '-',
+    InodeType::Directory => 'd',
+    InodeType::SymbolicLink => 'l',
+    InodeType::CharacterSpecial | InodeType::CharacterSpecialLink => 'c',
+    InodeType::BlockSpecial | InodeType::BlockSpecialLink => 'b',
+    InodeType::Fifo => 'p',
+    InodeType::Socket => 's'
+  }
+}
+
+/// Permission bits of a Unix mode as `ls -l` shows them, e.g. `rwxr-sr-x`
+pub(crate) fn permissions_string(mode: u16) -> String {
+  let mut s = String::with_capacity(9);
+  for (shift, special, special_char, ) in [(6, 0o4000, 's', ), (3, 0o2000, 's', ), (0, 0o1000, 't', )] {
+    let bits = (mode >> shift) & 0o7;
+    s.push(if bits & 0o4 != 0 { 'r' } else { '-' });
+    s.push(if bits & 0o2 != 0 { 'w' } else { '-' });
+    s.push(match (bits & 0o1 != 0, mode & special != 0, ) {
+      (true, true, ) => special_char,
+      (false, true, ) => special_char.to_ascii_uppercase(),
+      (true, false, ) => 'x',
+      (false, false, ) => '-'
+    });
+  }
+  s
+}
@@ -0,0 +1,198 @@
+use std::collections::HashSet;
+use std::fs::File;
+use std::io;
+use std::io::{BufWriter, Write};
+use std::path::{Component, Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+/// Names which Windows reserves for devices, with or without an extension
+const WINDOWS_RESERVED_NAMES: [&str; 22] = [
+  "CON", "PRN", "AUX", "NUL",
+  "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8", "COM9",
+  "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+/// Characters which can't appear in Windows file names
+const WINDOWS_INVALID_CHARS: [char; 9] = ['<', '>', ':', '"', '/', '\\', '|', '?', '*'];
+
+/// Character substituted for invalid characters
+const REPLACEMENT_CHAR: char = '_';
+
+/// When to rename extracted entries whose names are invalid on the host
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum SanitizeMode {
+  /// Sanitize names for the host the tool is running on
+  Auto,
+  /// Sanitize names as for Windows, whatever the host
+  Windows,
+  /// Never rename entries, except those whose names would escape the directory holding them
+  Never,
+}
+
+/// JSON representation of an entry which was renamed on extraction
+#[derive(Serialize)]
+pub(crate) struct JsonRename {
+  pub(crate) src: String,
+  pub(crate) dest: String,
+}
+
+impl SanitizeMode {
+  /// Parse from the name used on the CLI and in the config file
+  pub(crate) fn from_name(name: &str) -> Option<Self> {
+    match name {
+      "auto" => Some(SanitizeMode::Auto),
+      "windows" => Some(SanitizeMode::Windows),
+      "never" => Some(SanitizeMode::Never),
+      _ => None
+    }
+  }
+
+  /// Whether Windows file name rules apply
+  fn windows_rules(self) -> bool {
+    match self {
+      SanitizeMode::Auto => cfg!(windows),
+      SanitizeMode::Windows => true,
+      SanitizeMode::Never => false
+    }
+  }
+}
+
+/// Host name for an EFS name which is a path separator or NUL away from being written outside the
+/// directory holding it, or None if it is already confined. The directory parser takes names as
+/// they are on disk, so a damaged or crafted filesystem can have entries named e.g. "../../x",
+/// "/etc/x" or "link/x", the last writing through a symbolic link extracted beside it.
+fn confine_name(name: &str) -> Option<String> {
+  if name.is_empty() || name == "." || name == ".." {
+    return Some(REPLACEMENT_CHAR.to_string().repeat(name.len().max(1)));
+  }
+  match name.contains(['/', '\0']) {
+    true => Some(name.replace(['/', '\0'], &REPLACEMENT_CHAR.to_string())),
+    false => None
+  }
+}
+
+/// Host name for an EFS name, or None if it is already valid
+fn sanitize_name(name: &str) -> Option<String> {
+  if name.is_empty() {
+    return Some(REPLACEMENT_CHAR.to_string());
+  }
+  let mut sanitized: String = name.chars()
+    .map(|c| if c.is_control() || WINDOWS_INVALID_CHARS.contains(&c) { REPLACEMENT_CHAR } else { c })
+    .collect();
+
+  // Trailing dots and spaces are silently dropped by Windows
+  let trimmed_len = sanitized.trim_end_matches(|c| c == '.' || c == ' ').len();
+  if trimmed_len < sanitized.len() {
+    let trailing = sanitized.len() - trimmed_len;
+    sanitized.truncate(trimmed_len);
+    sanitized.extend(std::iter::repeat(REPLACEMENT_CHAR).take(trailing));
+  }
+
+  // Reserved device names are reserved with any extension too
+  let stem = sanitized.split('.').next().unwrap_or("");
+  if WINDOWS_RESERVED_NAMES.iter().any(|r| r.eq_ignore_ascii_case(stem)) {
+    sanitized.insert(0, REPLACEMENT_CHAR);
+  }
+
+  if sanitized == name {
+    None
+  } else {
+    Some(sanitized)
+  }
+}
+
+/// Name with a numbered suffix inserted before any extension, e.g. "file~1.txt"
+fn numbered_name(name: &str, n: usize) -> String {
+  match name.rfind('.') {
+    Some(dot) if dot > 0 => format!("{}~{}{}", &name[..dot], n, &name[dot..]),
+    _ => format!("{}~{}", name, n)
+  }
+}
+
+/// Host names for the entries of one directory, in the same order as the EFS names. Names which
+/// would escape the directory are always renamed, names which are invalid on the host are
+/// sanitized, and names which would then collide (ignoring case, as Windows does) are given a
+/// numbered suffix.
+pub(crate) fn host_names(names: &[&str], mode: SanitizeMode) -> Vec<String> {
+  let windows = mode.windows_rules();
+  let sanitize = |name: &str| if windows { sanitize_name(name) } else { confine_name(name) };
+  let key = |name: &str| if windows { name.to_lowercase() } else { name.to_string() };
+
+  // Reserve names which can be used as they are, so that they keep their names
+  let mut taken = HashSet::new();
+  let mut host_names: Vec<Option<String>> = names.iter()
+    .map(|name| match sanitize(name) {
+      None if taken.insert(key(name)) => Some(name.to_string()),
+      _ => None
+    })
+    .collect();
+
+  // Then find free names for the rest
+  for (host_name, name, ) in host_names.iter_mut().zip(names) {
+    if host_name.is_some() {
+      continue;
+    }
+    let sanitized = sanitize(name).unwrap_or_else(|| name.to_string());
+    let mut candidate = sanitized.clone();
+    let mut n = 1;
+    while !taken.insert(key(&candidate)) {
+      candidate = numbered_name(&sanitized, n);
+      n += 1;
+    }
+    *host_name = Some(candidate);
+  }
+
+  host_names.into_iter().map(|host_name| host_name.unwrap()).collect()
+}
+
+/// Host path of an entry within a directory, given its name from `host_names`, or an error if it
+/// wouldn't lie within the root of the extraction
+pub(crate) fn confined_join(root: &Path, dir: &Path, host_name: &str) -> Result<PathBuf, String> {
+  let path = dir.join(host_name);
+  let single = matches!(Path::new(host_name).components().collect::<Vec<Component>>().as_slice(), [Component::Normal(_)]);
+  if !single || !path.starts_with(root) {
+    return Err(format!("Name {:?} would be written outside {}", host_name, root.to_string_lossy()));
+  }
+  Ok(path)
+}
+
+/// Write a log of renamed entries to a file, as JSON
+pub(crate) fn write_rename_log(file_name: &str, renames: &[JsonRename]) -> io::Result<()> {
+  let mut writer = BufWriter::new(File::create(file_name)?);
+  serde_json::to_writer_pretty(&mut writer, renames)?;
+  writeln!(writer)?;
+  writer.flush()
+}
+
+#[cfg(test)]
+mod tests {
+  use std::path::Path;
+
+  use super::{confined_join, host_names, SanitizeMode};
+
+  #[test]
+  fn escaping_names_are_renamed_in_every_mode() {
+    // Names in sgidisklib::testgen::hostile_names
+    let names = ["../../escaped", "/etc/escaped", "link", "link/passwd", "..", "", "a\0b"];
+    for mode in [SanitizeMode::Auto, SanitizeMode::Windows, SanitizeMode::Never] {
+      let host = host_names(&names, mode);
+      assert_eq!(host[2], "link");
+      for host_name in &host {
+        assert!(confined_join(Path::new("out"), Path::new("out/dir"), host_name).is_ok(), "{:?} in {:?}", host_name, mode);
+      }
+    }
+    assert_eq!(host_names(&names, SanitizeMode::Never), vec![".._.._escaped", "_etc_escaped", "link", "link_passwd", "__", "_", "a_b"]);
+  }
+
+  #[test]
+  fn joins_outside_the_root_are_refused() {
+    let root = Path::new("out");
+    assert_eq!(confined_join(root, Path::new("out/dir"), "file").unwrap(), Path::new("out/dir/file"));
+    for name in ["..", "../x", "/etc/x", "a/b", "."] {
+      assert!(confined_join(root, Path::new("out/dir"), name).is_err(), "{}", name);
+    }
+    assert!(confined_join(root, Path::new("elsewhere"), "file").is_err());
+  }
+}
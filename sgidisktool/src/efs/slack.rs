@@ -0,0 +1,174 @@
+use std::collections::HashSet;
+use std::fs;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::process::exit;
+
+use clap::ArgMatches;
+use serde::Serialize;
+
+use sgidisklib::efs::{Inode, InodeType};
+use sgidisklib::efs::dir::Directory;
+
+use crate::config::Config;
+use crate::efs::OpenEfs;
+use crate::efs::sanitize::{confined_join, host_names, SanitizeMode};
+
+/// Extension of files holding the slack of a file extracted from a directory tree
+const SLACK_EXTENSION: &str = "slack";
+
+/// JSON representation of the slack extracted from one file
+#[derive(Serialize)]
+struct JsonSlack {
+  src: String,
+  dest: String,
+  inode: u64,
+  size_bytes: u64,
+  slack_bytes: u64,
+  error: Option<String>,
+}
+
+/// EFS slack entry point: extract the slack of a file, or of every file in a directory tree
+pub(crate) fn subcommand(config: &Config, mut efs_vol: OpenEfs, cli_matches: &ArgMatches) {
+  let json = config.json(cli_matches);
+  let dry_run = cli_matches.is_present("dry-run");
+  let verbose = (crate::logging::verbosity(cli_matches) > 0 || dry_run) && !json;
+  let src = cli_matches.value_of("src").unwrap();
+  let dest = cli_matches.value_of("dest").unwrap();
+
+  let (inode_id, inode, ) = match efs_vol.efs.lookup_path(&mut efs_vol.vol.disk_file, src) {
+    Ok(found) => found,
+    Err(e) => {
+      eprintln!("Error finding '{}': {:?}", src, &e);
+      exit(crate::exit_codes::for_lib_error(&e));
+    }
+  };
+  let recursive = inode.inode_type == InodeType::Directory;
+  if recursive && !cli_matches.is_present("recursive") {
+    eprintln!("'{}' is a directory, use --recursive to extract the slack of the files in it", src);
+    exit(crate::exit_codes::CLI_ARG_ERROR);
+  }
+
+  // Refuse to write to devices without --force-device, and confirm before overwriting devices or
+  // the disk image
+  crate::confirm::check_device_or_quit(Path::new(dest));
+  let warnings = crate::confirm::destination_warnings(Path::new(dest), efs_vol.vol.disk_file_name);
+  if !dry_run && !warnings.is_empty() {
+    let summary = format!("Extracting slack of '{}' from partition {} to '{}'", src, efs_vol.partition_id, dest);
+    crate::confirm::confirm_or_quit(&summary, &warnings, cli_matches.is_present("force"));
+  }
+
+  // Find files, and where their slack goes
+  let files = if recursive {
+    let sanitize = match cli_matches.value_of("sanitize-names") {
+      Some(name) => SanitizeMode::from_name(name).unwrap(),
+      None => config.extract.sanitize_names
+    };
+    walk_files(&mut efs_vol, src, inode_id, Path::new(dest), sanitize)
+  } else {
+    vec![(src.to_string(), inode_id, inode, PathBuf::from(dest), )]
+  };
+
+  let mut results = Vec::new();
+  for (file_src, file_id, file_inode, file_dest, ) in files {
+    let slack_bytes = file_inode.slack_sz();
+    // In a directory tree, files without slack are left out
+    if recursive && slack_bytes == 0 {
+      continue;
+    }
+    if verbose {
+      println!("{} -> {} ({} bytes)", &file_src, file_dest.to_string_lossy(), slack_bytes);
+    }
+
+    let error = if dry_run {
+      None
+    } else {
+      extract_slack(&mut efs_vol, &file_inode, &file_dest).err()
+    };
+    if let Some(e) = &error {
+      eprintln!("Error: {} -> {}: {}", &file_src, file_dest.to_string_lossy(), e);
+    }
+    results.push(JsonSlack {
+      src: file_src,
+      dest: file_dest.to_string_lossy().to_string(),
+      inode: file_id,
+      size_bytes: file_inode.size,
+      slack_bytes,
+      error,
+    });
+  }
+
+  if json {
+    crate::output::print_json("efs slack", &results);
+  }
+
+  let failed = results.iter().filter(|r| r.error.is_some()).count();
+  if let Some(exit_code) = crate::exit_codes::for_failures(failed, results.len()) {
+    exit(exit_code);
+  }
+}
+
+/// Regular files in a directory tree, with the host path of each one's slack under `dest`
+fn walk_files(efs_vol: &mut OpenEfs, src: &str, dir_id: u64, dest: &Path, sanitize: SanitizeMode) -> Vec<(String, u64, Inode, PathBuf, )> {
+  let mut files = Vec::new();
+  let mut pending = vec![(src.trim_end_matches('/').to_string(), dir_id, dest.to_path_buf(), 0, )];
+  let mut visited = HashSet::new();
+
+  while let Some((path, dir_id, dir_dest, depth, )) = pending.pop() {
+    if !visited.insert(dir_id) {
+      continue;
+    }
+    let dir = match efs_vol.efs.limits.check_depth(depth).and_then(|_| Directory::read_dir(&mut efs_vol.vol.disk_file, &efs_vol.efs, dir_id)) {
+      Ok(dir) => dir,
+      Err(e) => {
+        eprintln!("Error: {}/: unable to read directory: {:?}", &path, &e);
+        continue;
+      }
+    };
+
+    let entries: Vec<_> = dir.entries.into_iter().filter(|(name, _, )| *name != "." && *name != "..").collect();
+    let names: Vec<&str> = entries.iter().map(|(name, _, )| name.as_str()).collect();
+    let host = host_names(&names, sanitize);
+    for ((name, (entry_id, entry, ), ), host_name, ) in entries.iter().zip(host) {
+      let entry_path = format!("{}/{}", &path, name);
+      let host_name = match entry.inode_type {
+        InodeType::RegularFile => format!("{}.{}", host_name, SLACK_EXTENSION),
+        _ => host_name
+      };
+      let entry_dest = match confined_join(dest, &dir_dest, &host_name) {
+        Ok(entry_dest) => entry_dest,
+        Err(e) => {
+          eprintln!("Error: {}: {}", &entry_path, &e);
+          continue;
+        }
+      };
+      match entry.inode_type {
+        InodeType::Directory => pending.push((entry_path, *entry_id, entry_dest, depth + 1, )),
+        InodeType::RegularFile => files.push((entry_path, *entry_id, entry.clone(), entry_dest, )),
+        _ => {}
+      }
+    }
+  }
+
+  files
+}
+
+/// Write the slack of a file to a host path, creating its parent directories
+fn extract_slack(efs_vol: &mut OpenEfs, inode: &Inode, dest: &Path) -> Result<(), String> {
+  if let Some(parent) = dest.parent().filter(|p| !p.as_os_str().is_empty()) {
+    if let Err(e) = fs::create_dir_all(parent) {
+      return Err(format!("Unable to create directory: {:?}", &e));
+    }
+  }
+  let mut writer = match fs::File::create(dest) {
+    Ok(f) => BufWriter::new(f),
+    Err(e) => return Err(format!("Unable to create file: {:?}", &e))
+  };
+  if let Err(e) = efs_vol.efs.read_slack(&mut efs_vol.vol.disk_file, inode, &mut writer) {
+    return Err(format!("Unable to read slack: {:?}", &e));
+  }
+  if let Err(e) = writer.flush() {
+    return Err(format!("Unable to write file: {:?}", &e));
+  }
+  Ok(())
+}
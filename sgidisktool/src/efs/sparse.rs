@@ -0,0 +1,50 @@
+use std::fs::File;
+use std::io;
+use std::io::{Seek, SeekFrom, Write};
+
+/// Size of the runs of zeros which are skipped rather than written. Runs are only checked at
+/// this granularity within each write, which suits the block aligned writes made when copying
+/// from an EFS.
+const SPARSE_BLOCK_SZ: usize = 4096;
+
+/// Writer for a file which seeks past runs of zeros instead of writing them, so that the host
+/// filesystem can leave holes there
+pub(crate) struct SparseWriter {
+  file: File,
+  /// Current position in the file
+  pos: u64,
+}
+
+impl SparseWriter {
+  /// Wrap a newly created (empty) file
+  pub(crate) fn new(file: File) -> Self {
+    SparseWriter {
+      file,
+      pos: 0,
+    }
+  }
+
+  /// Set the length of the file to the position after the last write, which is needed if it
+  /// ended in a run of zeros that was skipped
+  pub(crate) fn finish(self) -> io::Result<()> {
+    self.file.set_len(self.pos)
+  }
+}
+
+impl Write for SparseWriter {
+  fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+    for chunk in buf.chunks(SPARSE_BLOCK_SZ) {
+      if chunk.len() == SPARSE_BLOCK_SZ && chunk.iter().all(|b| *b == 0) {
+        self.file.seek(SeekFrom::Current(chunk.len() as i64))?;
+      } else {
+        self.file.write_all(chunk)?;
+      }
+      self.pos += chunk.len() as u64;
+    }
+    Ok(buf.len())
+  }
+
+  fn flush(&mut self) -> io::Result<()> {
+    self.file.flush()
+  }
+}
@@ -0,0 +1,113 @@
+use std::collections::HashSet;
+use std::io;
+use std::io::{BufWriter, Write};
+use std::process::exit;
+
+use clap::ArgMatches;
+
+use sgidisklib::efs::{Inode, InodeType};
+use sgidisklib::efs::dir::Directory;
+
+use crate::efs::OpenEfs;
+use crate::output::ReportOutput;
+
+/// EFS timeline entry point: write a mactime body file of every entry's timestamps
+pub(crate) fn subcommand(mut efs_vol: OpenEfs, cli_matches: &ArgMatches) {
+  let mut writer = BufWriter::new(ReportOutput::open_or_quit(cli_matches));
+
+  let (entries, failed, ) = match write_body(&mut efs_vol, &mut writer) {
+    Ok(counts) => counts,
+    Err(e) => writer.into_parts().0.quit_write_error(&e)
+  };
+  match writer.into_inner() {
+    Ok(out) => out.finish_or_quit(),
+    Err(e) => {
+      let (e, out, ) = e.into_parts();
+      out.quit_write_error(&e)
+    }
+  }
+  if let Some(exit_code) = crate::exit_codes::for_failures(failed, entries) {
+    exit(exit_code);
+  }
+}
+
+/// Walk the EFS from the root directory, writing a body file line for each entry. Returns the
+/// number of entries, and the number of directories which couldn't be read.
+fn write_body<W: ?Sized>(efs_vol: &mut OpenEfs, writer: &mut W) -> io::Result<(usize, usize, )>
+  where W: Write {
+  let root_id = Directory::ROOT_DIRECTORY_INODE;
+  let root = match efs_vol.efs.read_inode(&mut efs_vol.vol.disk_file, root_id) {
+    Ok(root) => root,
+    Err(e) => {
+      eprintln!("Error: unable to read root directory: {:?}", &e);
+      return Ok((0, 1, ));
+    }
+  };
+  write_line(efs_vol, writer, "/", root_id, &root)?;
+  let (mut entries, mut failed, ) = (1, 0, );
+
+  // Directories still to be listed, and those already listed so that loops end
+  let mut pending = vec![(String::new(), root_id, 0, )];
+  let mut visited = HashSet::new();
+  while let Some((path, dir_id, depth, )) = pending.pop() {
+    if !visited.insert(dir_id) {
+      continue;
+    }
+    let dir = match efs_vol.efs.limits.check_depth(depth).and_then(|_| Directory::read_dir(&mut efs_vol.vol.disk_file, &efs_vol.efs, dir_id)) {
+      Ok(dir) => dir,
+      Err(e) => {
+        eprintln!("Error: {}/: {:?}", &path, &e);
+        failed += 1;
+        continue;
+      }
+    };
+
+    for (name, (inode_id, inode, ), ) in dir.entries.iter().filter(|(name, _, )| *name != "." && *name != "..") {
+      let entry_path = format!("{}/{}", &path, name);
+      write_line(efs_vol, writer, &entry_path, *inode_id, inode)?;
+      entries += 1;
+      if inode.inode_type == InodeType::Directory {
+        pending.push((entry_path, *inode_id, depth + 1, ));
+      }
+    }
+  }
+
+  Ok((entries, failed, ))
+}
+
+/// Write the body file line of an entry:
+/// `MD5|name|inode|mode_as_string|UID|GID|size|atime|mtime|ctime|crtime`.
+/// EFS has no creation time, so crtime is 0, and contents aren't hashed, so MD5 is 0.
+fn write_line<W: ?Sized>(efs_vol: &mut OpenEfs, writer: &mut W, path: &str, inode_id: u64, inode: &Inode) -> io::Result<()>
+  where W: Write {
+  let name = match inode.inode_type {
+    InodeType::SymbolicLink => match efs_vol.efs.read_link(&mut efs_vol.vol.disk_file, inode) {
+      Ok(target) => format!("{} -> {}", path, target),
+      Err(_) => path.to_string()
+    },
+    _ => path.to_string()
+  };
+  writeln!(writer, "0|{}|{}|{}|{}|{}|{}|{}|{}|{}|0",
+           body_escape(&name), inode_id, mode_string(inode), inode.owner_uid, inode.owner_gid, inode.size,
+           inode.atime_raw, inode.mtime_raw, inode.ctime_raw)
+}
+
+/// Mode as The Sleuth Kit writes it, e.g. `r/rrw-r--r--`
+fn mode_string(inode: &Inode) -> String {
+  let t = match inode.inode_type {
+    InodeType::RegularFile => 'r',
+    InodeType::Directory => 'd',
+    InodeType::SymbolicLink => 'l',
+    InodeType::CharacterSpecial | InodeType::CharacterSpecialLink => 'c',
+    InodeType::BlockSpecial | InodeType::BlockSpecialLink => 'b',
+    InodeType::Fifo => 'p',
+    InodeType::Socket => 's'
+  };
+
+  format!("{}/{}{}", t, t, super::permissions_string(inode.unix_mode))
+}
+
+/// Escape the field separator and line breaks in a body file name
+fn body_escape(s: &str) -> String {
+  s.replace('\\', "\\\\").replace('|', "\\|").replace('\n', "\\n")
+}
@@ -0,0 +1,138 @@
+use std::process::exit;
+
+use chrono::{Local, TimeZone};
+use clap::ArgMatches;
+use serde::Serialize;
+use tabled::{Table, Tabled};
+
+use sgidisklib::efs::Inode;
+use sgidisklib::efs::dir::Directory;
+use sgidisklib::progress::CancellationToken;
+
+use crate::color::{paint, Style};
+use crate::config::Config;
+use crate::efs::OpenEfs;
+use crate::output::DateFormat;
+
+/// JSON representation of the timestamp anomaly report
+#[derive(Serialize)]
+struct JsonTimestampReport {
+  /// Time the superblock was last updated, in seconds since the epoch
+  superblock_time: i64,
+  entries: Vec<JsonTimestampEntry>,
+}
+
+/// JSON representation of an entry with suspicious timestamps
+#[derive(Serialize)]
+struct JsonTimestampEntry {
+  path: String,
+  inode: u64,
+  ctime: i64,
+  mtime: i64,
+  atime: i64,
+  /// What is suspicious, e.g. "mtime after ctime"
+  anomalies: Vec<String>,
+}
+
+/// EFS timestamps entry point: report entries whose timestamps a working system wouldn't have
+/// written (before 1980, after the superblock was last updated, atime before ctime, mtime after
+/// ctime), which usually mean a skewed clock or tampering
+pub(crate) fn subcommand(config: &Config, mut efs_vol: OpenEfs, cli_matches: &ArgMatches) {
+  let format = config.format(cli_matches);
+  let sb_time = efs_vol.efs.time_raw;
+
+  let mut entries = Vec::new();
+  let mut check = |path: &str, inode_id: u64, inode: &Inode| {
+    let anomalies = inode.timestamp_anomalies(sb_time);
+    if !anomalies.is_empty() {
+      entries.push(JsonTimestampEntry {
+        path: path.to_string(),
+        inode: inode_id,
+        ctime: inode.ctime_raw,
+        mtime: inode.mtime_raw,
+        atime: inode.atime_raw,
+        anomalies: anomalies.iter().map(|a| a.to_string()).collect(),
+      });
+    }
+  };
+  match efs_vol.efs.read_inode(&mut efs_vol.vol.disk_file, Directory::ROOT_DIRECTORY_INODE) {
+    Ok(root) => check("/", Directory::ROOT_DIRECTORY_INODE, &root),
+    Err(e) => {
+      eprintln!("Error: unable to read root directory: {:?}", &e);
+      exit(crate::exit_codes::for_lib_error(&e));
+    }
+  }
+  let walk = efs_vol.efs.walk(&mut efs_vol.vol.disk_file, |path, inode_id, inode| check(path, inode_id, inode), &CancellationToken::new(), |_| {});
+  for (path, e, ) in &walk.unreadable {
+    eprintln!("Error: {}: {:?}", path, e);
+  }
+
+  let anomalous = entries.len();
+  if format.is_structured() {
+    crate::output::print_json("efs timestamps", &JsonTimestampReport {
+      superblock_time: sb_time,
+      entries,
+    });
+  } else if format.is_tabular() {
+    let rows: Vec<Vec<String>> = entries.iter()
+      .flat_map(|e| e.anomalies.iter().map(move |anomaly| vec![
+        e.path.clone(), e.inode.to_string(), e.ctime.to_string(), e.mtime.to_string(), e.atime.to_string(), anomaly.clone()]))
+      .collect();
+    crate::output::print_table(format, &["path", "inode", "ctime", "mtime", "atime", "anomaly"], &rows);
+  } else {
+    print_entries(entries, sb_time, &config.date_format(cli_matches));
+  }
+
+  let failed = walk.unreadable.len();
+  if let Some(exit_code) = crate::exit_codes::for_failures(failed, failed + walk.directories as usize) {
+    exit(exit_code);
+  }
+  if config.strict(cli_matches) && anomalous > 0 {
+    eprintln!("{} entries have suspicious timestamps", anomalous);
+    exit(crate::exit_codes::STRICT_ERR);
+  }
+}
+
+/// Print the entries with suspicious timestamps as a table
+fn print_entries(entries: Vec<JsonTimestampEntry>, sb_time: i64, date_format: &DateFormat) {
+  #[derive(Tabled)]
+  struct DisplayEntry {
+    #[header("Path")]
+    path: String,
+    #[header("Inode")]
+    inode: u64,
+    #[header("Ctime")]
+    ctime: String,
+    #[header("Mtime")]
+    mtime: String,
+    #[header("Atime")]
+    atime: String,
+    #[header("Anomalies")]
+    anomalies: String,
+  }
+
+  println!("Superblock last updated: {}", format_time(sb_time, date_format));
+  if entries.is_empty() {
+    println!("{}", paint("No suspicious timestamps", Style::Good));
+    return;
+  }
+  let tab = entries.into_iter()
+    .map(|e| DisplayEntry {
+      path: e.path,
+      inode: e.inode,
+      ctime: format_time(e.ctime, date_format),
+      mtime: format_time(e.mtime, date_format),
+      atime: format_time(e.atime, date_format),
+      anomalies: e.anomalies.join(", "),
+    })
+    .collect::<Vec<DisplayEntry>>();
+  print!("{}", Table::new(tab).with(crate::table_fmt()));
+}
+
+/// Timestamp in the date format, or as seconds since the epoch if it isn't a valid local time
+fn format_time(time: i64, date_format: &DateFormat) -> String {
+  match Local.timestamp_opt(time, 0).single() {
+    Some(t) => date_format.format(&t),
+    None => time.to_string()
+  }
+}
@@ -0,0 +1,306 @@
+//! Read-only WebDAV server over an EFS filesystem, so the image can be mounted as a network drive
+//! by Windows Explorer, macOS Finder or davfs2 without FUSE or kernel drivers. Only regular files
+//! and directories are served; symbolic links, devices, FIFOs and sockets are left out of listings.
+
+use std::cmp::min;
+use std::io::{self, Read};
+use std::process::exit;
+
+use chrono::Utc;
+use clap::ArgMatches;
+use tiny_http::{Header, Method, Request, Response, Server, StatusCode};
+use tracing::{debug, warn};
+
+use sgidisklib::SgidiskLibReadError;
+use sgidisklib::efs::{Inode, InodeType};
+use sgidisklib::efs::dir::Directory;
+
+use crate::efs::OpenEfs;
+
+/// Address to listen on without --listen: local connections only
+const DEFAULT_LISTEN: &str = "127.0.0.1:8080";
+
+/// Methods answered, for OPTIONS and 405 responses
+const ALLOW: &str = "OPTIONS, GET, HEAD, PROPFIND";
+
+/// EFS WebDAV entry point: serve the filesystem until interrupted
+pub(crate) fn subcommand(mut efs_vol: OpenEfs, cli_matches: &ArgMatches) {
+  let listen = cli_matches.value_of("listen").unwrap_or(DEFAULT_LISTEN);
+  let server = match Server::http(listen) {
+    Ok(server) => server,
+    Err(e) => {
+      eprintln!("Unable to listen on {}: {}", listen, e);
+      exit(crate::exit_codes::IO_ERR);
+    }
+  };
+  eprintln!("Serving partition {} of '{}' read-only over WebDAV at http://{}/", efs_vol.partition_id, efs_vol.vol.disk_file_name, listen);
+
+  // Requests are answered one at a time, as they all read from the one disk image
+  for request in server.incoming_requests() {
+    debug!("{} {}", request.method(), request.url());
+    if let Err(e) = respond(&mut efs_vol, request) {
+      warn!("Error sending response: {:?}", &e);
+    }
+  }
+}
+
+/// Answer one request
+fn respond(efs_vol: &mut OpenEfs, request: Request) -> std::io::Result<()> {
+  let path = match percent_decode(request.url().split('?').next().unwrap_or("/")) {
+    Some(path) => path,
+    None => return request.respond(Response::empty(400)),
+  };
+
+  let method = request.method().clone();
+  match method {
+    Method::Options => request.respond(Response::empty(200)
+      .with_header(header("DAV", "1"))
+      .with_header(header("MS-Author-Via", "DAV"))
+      .with_header(header("Allow", ALLOW))),
+    Method::Get | Method::Head => get(efs_vol, request, &path),
+    Method::NonStandard(ref name) if name.as_str() == "PROPFIND" => {
+      let depth = request.headers().iter()
+        .find(|h| h.field.equiv("Depth"))
+        .map(|h| h.value.as_str().to_string());
+      request.respond(propfind(efs_vol, &path, depth.as_deref() == Some("0")))
+    }
+    _ => request.respond(Response::empty(405).with_header(header("Allow", ALLOW)))
+  }
+}
+
+/// Answer GET or HEAD with the contents of a file, or the part of them in a byte range. Contents
+/// are read from the image as they are sent, and not at all for HEAD.
+fn get(efs_vol: &mut OpenEfs, request: Request, path: &str) -> io::Result<()> {
+  let inode = match lookup(efs_vol, path) {
+    Ok(inode) => inode,
+    Err(status) => return request.respond(Response::empty(status)),
+  };
+  if inode.inode_type == InodeType::Directory {
+    // Browsers get a plain listing; WebDAV clients use PROPFIND
+    return request.respond(listing(efs_vol, path, &inode));
+  }
+
+  let mut headers = vec![header("Last-Modified", &http_date(&inode)), header("Accept-Ranges", "bytes")];
+  let (status, start, end, ) = match range(&request).map(|range| parse_range(&range, inode.size)) {
+    None => (200, 0, inode.size, ),
+    Some(Some((start, end, ))) => {
+      headers.push(header("Content-Range", &format!("bytes {}-{}/{}", start, end - 1, inode.size)));
+      (206, start, end, )
+    }
+    Some(None) => return request.respond(Response::empty(416)
+      .with_header(header("Content-Range", &format!("bytes */{}", inode.size))))
+  };
+
+  // The body of a response to HEAD is left out when sending it, but its length is given
+  let len = Some((end - start) as usize);
+  if *request.method() == Method::Head {
+    return request.respond(Response::new(StatusCode(status), headers, io::empty(), len, None));
+  }
+  let body = FileRange { efs_vol, inode, pos: start, end };
+  request.respond(Response::new(StatusCode(status), headers, body, len, None))
+}
+
+/// Reader of a byte range of a file, reading only the extents under each buffer it fills
+struct FileRange<'a> {
+  efs_vol: &'a mut OpenEfs,
+  inode: Inode,
+  /// Offset into the file of the next byte to read
+  pos: u64,
+  /// Offset into the file of the end of the range
+  end: u64,
+}
+
+impl Read for FileRange<'_> {
+  fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+    let len = min(buf.len() as u64, self.end.saturating_sub(self.pos));
+    let mut out = &mut buf[..len as usize];
+    let read = self.efs_vol.efs.read_range(&mut self.efs_vol.vol.disk_file, &self.inode, self.pos, len, &mut out)
+      .map_err(|e| io::Error::new(io::ErrorKind::Other, format!("Unable to read inode {}: {:?}", self.inode.id, &e)))?;
+    self.pos += read;
+    Ok(read as usize)
+  }
+}
+
+/// Simple HTML listing of a directory
+fn listing(efs_vol: &mut OpenEfs, path: &str, inode: &Inode) -> Response<std::io::Cursor<Vec<u8>>> {
+  let dir = match Directory::read_dir(&mut efs_vol.vol.disk_file, &efs_vol.efs, inode.id) {
+    Ok(dir) => dir,
+    Err(e) => {
+      warn!("Unable to read {}: {:?}", path, &e);
+      return Response::from_data(Vec::new()).with_status_code(500);
+    }
+  };
+  let base = collection_href(path);
+  let mut html = format!("<!DOCTYPE html>\n<html><head><title>{}</title></head><body><h1>{}</h1><ul>\n", xml_escape(path), xml_escape(path));
+  for (name, (_, entry, ), ) in served_entries(&dir) {
+    let suffix = if entry.inode_type == InodeType::Directory { "/" } else { "" };
+    html.push_str(&format!("<li><a href=\"{}{}{}\">{}{}</a></li>\n", base, percent_encode(name), suffix, xml_escape(name), suffix));
+  }
+  html.push_str("</ul></body></html>\n");
+  Response::from_data(html.into_bytes()).with_header(header("Content-Type", "text/html; charset=utf-8"))
+}
+
+/// Properties of an entry, and of its children if it is a directory and depth allows
+fn propfind(efs_vol: &mut OpenEfs, path: &str, depth_zero: bool) -> Response<std::io::Cursor<Vec<u8>>> {
+  let inode = match lookup(efs_vol, path) {
+    Ok(inode) => inode,
+    Err(status) => return Response::from_data(Vec::new()).with_status_code(status),
+  };
+
+  let mut xml = String::from("<?xml version=\"1.0\" encoding=\"utf-8\"?>\n<D:multistatus xmlns:D=\"DAV:\">\n");
+  let (href, name, ) = if inode.inode_type == InodeType::Directory {
+    (collection_href(path), path.trim_end_matches('/').rsplit('/').next().unwrap_or_default(), )
+  } else {
+    (percent_encode(path), path.rsplit('/').next().unwrap_or_default(), )
+  };
+  xml.push_str(&prop_response(&href, name, &inode));
+
+  // Depth "infinity" is answered as depth 1, as many servers do
+  if inode.inode_type == InodeType::Directory && !depth_zero {
+    let dir = match Directory::read_dir(&mut efs_vol.vol.disk_file, &efs_vol.efs, inode.id) {
+      Ok(dir) => dir,
+      Err(e) => {
+        warn!("Unable to read {}: {:?}", path, &e);
+        return Response::from_data(Vec::new()).with_status_code(500);
+      }
+    };
+    for (child, (_, entry, ), ) in served_entries(&dir) {
+      let suffix = if entry.inode_type == InodeType::Directory { "/" } else { "" };
+      xml.push_str(&prop_response(&format!("{}{}{}", href, percent_encode(child), suffix), child, entry));
+    }
+  }
+  xml.push_str("</D:multistatus>\n");
+
+  Response::from_data(xml.into_bytes()).with_status_code(207)
+    .with_header(header("Content-Type", "application/xml; charset=utf-8"))
+}
+
+/// One <D:response> element of a PROPFIND reply
+fn prop_response(href: &str, name: &str, inode: &Inode) -> String {
+  let props = if inode.inode_type == InodeType::Directory {
+    "<D:resourcetype><D:collection/></D:resourcetype>".to_string()
+  } else {
+    format!("<D:resourcetype/><D:getcontentlength>{}</D:getcontentlength><D:getcontenttype>application/octet-stream</D:getcontenttype>", inode.size)
+  };
+  format!("<D:response><D:href>{}</D:href><D:propstat><D:prop><D:displayname>{}</D:displayname>{}\
+           <D:getlastmodified>{}</D:getlastmodified><D:creationdate>{}</D:creationdate></D:prop>\
+           <D:status>HTTP/1.1 200 OK</D:status></D:propstat></D:response>\n",
+          href, xml_escape(name), props, http_date(inode), inode.ctime.with_timezone(&Utc).to_rfc3339())
+}
+
+/// Inode at a path, or the HTTP status to answer with: 404 for missing and unserved entries
+fn lookup(efs_vol: &mut OpenEfs, path: &str) -> Result<Inode, u16> {
+  match efs_vol.efs.lookup_path(&mut efs_vol.vol.disk_file, path) {
+    Ok((_, inode, )) if matches!(inode.inode_type, InodeType::Directory | InodeType::RegularFile) => Ok(inode),
+    Ok(_) | Err(SgidiskLibReadError::NotFound(_)) => Err(404),
+    Err(e) => {
+      warn!("Unable to look up {}: {:?}", path, &e);
+      Err(500)
+    }
+  }
+}
+
+/// Entries of a directory which are served: regular files and directories, other than . and ..
+fn served_entries(dir: &Directory) -> impl Iterator<Item = (&String, &(u64, Inode), )> {
+  dir.entries.iter()
+    .filter(|(name, _, )| *name != "." && *name != "..")
+    .filter(|(_, (_, inode, ), )| matches!(inode.inode_type, InodeType::Directory | InodeType::RegularFile))
+}
+
+/// Value of the Range header, if any
+fn range(request: &Request) -> Option<String> {
+  request.headers().iter()
+    .find(|h| h.field.equiv("Range"))
+    .map(|h| h.value.as_str().to_string())
+}
+
+/// Byte range of a single range "bytes=start-end", "bytes=start-" or "bytes=-suffix" as a
+/// half-open range, or None if it can't be satisfied. Multiple ranges aren't supported.
+fn parse_range(range: &str, len: u64) -> Option<(u64, u64, )> {
+  let (start, end, ) = range.strip_prefix("bytes=")?.split_once('-')?;
+  let (start, end, ) = match (start.trim(), end.trim(), ) {
+    ("", suffix, ) => (len.saturating_sub(suffix.parse().ok()?), len, ),
+    (start, "", ) => (start.parse().ok()?, len, ),
+    (start, end, ) => (start.parse().ok()?, end.parse::<u64>().ok()?.saturating_add(1).min(len), )
+  };
+  if start < end { Some((start, end, )) } else { None }
+}
+
+/// Last modified time in the HTTP date format
+fn http_date(inode: &Inode) -> String {
+  inode.mtime.with_timezone(&Utc).format("%a, %d %b %Y %H:%M:%S GMT").to_string()
+}
+
+/// Href of a directory, ending in a slash
+fn collection_href(path: &str) -> String {
+  let href = percent_encode(path);
+  if href.ends_with('/') { href } else { format!("{}/", href) }
+}
+
+/// Decode %XX escapes in a URL path, or None if they are malformed or not UTF-8
+fn percent_decode(path: &str) -> Option<String> {
+  let bytes = path.as_bytes();
+  let mut decoded = Vec::with_capacity(bytes.len());
+  let mut i = 0;
+  while i < bytes.len() {
+    if bytes[i] == b'%' {
+      let hex = std::str::from_utf8(bytes.get(i + 1..i + 3)?).ok()?;
+      decoded.push(u8::from_str_radix(hex, 16).ok()?);
+      i += 3;
+    } else {
+      decoded.push(bytes[i]);
+      i += 1;
+    }
+  }
+  String::from_utf8(decoded).ok()
+}
+
+/// Escape the bytes of a path which aren't unreserved URL characters, keeping slashes
+fn percent_encode(path: &str) -> String {
+  path.bytes()
+    .map(|b| match b {
+      b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' | b'/' => (b as char).to_string(),
+      b => format!("%{:02X}", b)
+    })
+    .collect()
+}
+
+/// Escape text for XML and HTML
+fn xml_escape(s: &str) -> String {
+  s.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+/// Response header from a name and value which are known to be valid
+fn header(name: &str, value: &str) -> Header {
+  Header::from_bytes(name.as_bytes(), value.as_bytes()).unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+  use super::{parse_range, percent_decode};
+
+  #[test]
+  fn ranges() {
+    assert_eq!(parse_range("bytes=0-99", 1000), Some((0, 100, )));
+    assert_eq!(parse_range("bytes=900-", 1000), Some((900, 1000, )));
+    assert_eq!(parse_range("bytes=-100", 1000), Some((900, 1000, )));
+    assert_eq!(parse_range("bytes=-5000", 1000), Some((0, 1000, )));
+    assert_eq!(parse_range("bytes=990-2000", 1000), Some((990, 1000, )));
+    assert_eq!(parse_range("bytes=1000-", 1000), None);
+    assert_eq!(parse_range("bytes=50-10", 1000), None);
+    assert_eq!(parse_range("bytes=0-18446744073709551615", 1000), Some((0, 1000, )));
+    assert_eq!(parse_range("bytes=0-1,5-9", 1000), None);
+    assert_eq!(parse_range("items=0-1", 1000), None);
+    assert_eq!(parse_range("bytes=-0", 1000), None);
+  }
+
+  #[test]
+  fn decoding() {
+    assert_eq!(percent_decode("/usr/bin/ls").as_deref(), Some("/usr/bin/ls"));
+    assert_eq!(percent_decode("/My%20Files/a%2Fb").as_deref(), Some("/My Files/a/b"));
+    assert_eq!(percent_decode("/%C3%A9t%C3%A9").as_deref(), Some("/\u{e9}t\u{e9}"));
+    assert_eq!(percent_decode("/bad%2"), None);
+    assert_eq!(percent_decode("/bad%zz"), None);
+    assert_eq!(percent_decode("/%FF"), None);
+  }
+}
@@ -0,0 +1,161 @@
+use std::io;
+use std::io::{Read, Seek, SeekFrom};
+use std::process::exit;
+
+use clap::ArgMatches;
+use serde::Serialize;
+use tabled::{Table, Tabled};
+
+use sgidisklib::entropy::{DEFAULT_WINDOW_SZ, EntropyScanner, WindowEntropy};
+
+use crate::config::Config;
+use crate::OpenVolume;
+
+/// Characters of the sparkline, from no entropy to 8 bits per byte
+const SPARK_CHARS: &[u8] = b" .:-=+*#%@";
+
+/// Number of windows on each line of the sparkline
+const SPARK_WIDTH: usize = 64;
+
+/// JSON representation of the entropy of a region
+#[derive(Serialize)]
+struct JsonEntropy {
+  /// What was measured, e.g. "partition 7"
+  source: String,
+  window_sz: u64,
+  windows: Vec<JsonWindow>,
+}
+
+/// JSON representation of the entropy of one window
+#[derive(Serialize)]
+struct JsonWindow {
+  offset: u64,
+  len: u64,
+  /// Bits per byte, from 0 to 8
+  entropy: f64,
+  /// "blank", "structured", "binary" or "compressed"
+  class: &'static str,
+}
+
+/// Entropy entry point: measure the entropy of each window of a partition or the whole image
+pub(crate) fn subcommand(config: &Config, disk_file_name: &str, cli_matches: &ArgMatches) {
+  let format = config.format(cli_matches);
+  let window_sz = match cli_matches.value_of("window").map(crate::efs::filter::parse_size) {
+    None => DEFAULT_WINDOW_SZ,
+    Some(Ok(sz)) if sz > 0 => sz,
+    Some(_) => {
+      eprintln!("Invalid window size: {}", cli_matches.value_of("window").unwrap());
+      exit(crate::exit_codes::CLI_ARG_ERROR);
+    }
+  };
+
+  let mut vol = OpenVolume::open_or_quit(disk_file_name);
+  let (source, start, len, ) = match cli_matches.value_of("partition") {
+    Some(partition) => {
+      let partition_id = match partition.parse::<usize>() {
+        Ok(id) => id,
+        Err(_) => {
+          eprintln!("Invalid partition ID '{}'", partition);
+          exit(crate::exit_codes::CLI_ARG_ERROR);
+        }
+      };
+      match vol.volume_header.partitions.get(partition_id) {
+        Some(p) if p.in_use() => (format!("partition {}", partition_id), p.byte_start(), p.byte_len(), ),
+        _ => {
+          eprintln!("Partition {} is not in use", partition_id);
+          exit(crate::exit_codes::CLI_ARG_ERROR);
+        }
+      }
+    }
+    None => match vol.disk_file.get_ref().len() {
+      Ok(len) => ("disk image".to_string(), 0, len, ),
+      Err(e) => {
+        eprintln!("Error while reading disk image: {:?}", &e);
+        exit(crate::exit_codes::IO_ERR);
+      }
+    }
+  };
+
+  let windows = match measure(&mut vol.disk_file, start, len, window_sz) {
+    Ok(windows) => windows,
+    Err(e) => {
+      eprintln!("Error reading disk image: {:?}", &e);
+      exit(crate::exit_codes::IO_ERR);
+    }
+  };
+
+  if format.is_structured() {
+    crate::output::print_json("entropy", &JsonEntropy {
+      source,
+      window_sz,
+      windows: windows.iter()
+        .map(|w| JsonWindow {
+          offset: w.offset,
+          len: w.len,
+          entropy: w.entropy,
+          class: w.class().name(),
+        })
+        .collect(),
+    });
+  } else if format.is_tabular() {
+    let rows: Vec<Vec<String>> = windows.iter()
+      .map(|w| vec![w.offset.to_string(), (w.offset + w.len).to_string(), format!("{:.3}", w.entropy), w.class().name().to_string()])
+      .collect();
+    crate::output::print_table(format, &["start", "end", "entropy", "class"], &rows);
+  } else if cli_matches.is_present("sparkline") {
+    print_sparkline(&source, &windows, window_sz);
+  } else {
+    print_windows(&windows);
+  }
+}
+
+/// Measure the entropy of each window of a region of the disk image. A region running past the
+/// end of the image is measured as far as the image goes.
+fn measure<R: ?Sized>(reader: &mut R, start: u64, len: u64, window_sz: u64) -> io::Result<Vec<WindowEntropy>>
+  where R: Read + Seek {
+  let mut scanner = EntropyScanner::new(start, window_sz);
+  reader.seek(SeekFrom::Start(start))?;
+  io::copy(&mut (&mut *reader).take(len), &mut scanner)?;
+  Ok(scanner.finish())
+}
+
+/// Print a table of windows
+fn print_windows(windows: &[WindowEntropy]) {
+  #[derive(Tabled)]
+  struct DisplayWindow {
+    #[header("Start")]
+    start: u64,
+    #[header("End")]
+    end: u64,
+    #[header("Entropy (bits/byte)")]
+    entropy: String,
+    #[header("Class")]
+    class: &'static str,
+  }
+
+  let tab = windows.iter()
+    .map(|w| DisplayWindow {
+      start: w.offset,
+      end: w.offset + w.len,
+      entropy: format!("{:.3}", w.entropy),
+      class: w.class().name(),
+    })
+    .collect::<Vec<DisplayWindow>>();
+  print!("{}", Table::new(tab).with(crate::table_fmt()));
+}
+
+/// Print a character per window, denser for higher entropy, a line at a time starting with the
+/// offset of its first window
+fn print_sparkline(source: &str, windows: &[WindowEntropy], window_sz: u64) {
+  println!("Entropy of {}, {} bytes per character: '{}' for blank, to '{}' for 8 bits per byte",
+           source, window_sz, SPARK_CHARS[0] as char, SPARK_CHARS[SPARK_CHARS.len() - 1] as char);
+  for line in windows.chunks(SPARK_WIDTH) {
+    let spark: String = line.iter()
+      .map(|w| {
+        let level = (w.entropy / 8.0 * (SPARK_CHARS.len() - 1) as f64).round() as usize;
+        SPARK_CHARS[level.min(SPARK_CHARS.len() - 1)] as char
+      })
+      .collect();
+    println!("{:>12} |{}|", line[0].offset, spark);
+  }
+}
@@ -1,3 +1,5 @@
+use sgidisklib::SgidiskLibReadError;
+
 /// CLI argument error
 pub(crate) const CLI_ARG_ERROR: i32 = 1;
 /// Volume Header open/read error
@@ -5,4 +7,75 @@ pub(crate) const VH_OPEN_ERR: i32 = 2;
 /// Disk IO error
 pub(crate) const IO_ERR: i32 = 3;
 /// Glob pattern error
-pub(crate) const GLOB_ERR: i32 = 4;
\ No newline at end of file
+pub(crate) const GLOB_ERR: i32 = 4;
+/// Configuration file error
+pub(crate) const CONFIG_ERR: i32 = 5;
+/// Copy verification failed
+pub(crate) const VERIFY_ERR: i32 = 6;
+/// EFS filesystem open/read error
+pub(crate) const EFS_OPEN_ERR: i32 = 7;
+/// One or more batch operations failed
+pub(crate) const BATCH_ERR: i32 = 8;
+/// Disk image structure could not be parsed
+pub(crate) const PARSE_ERR: i32 = 9;
+/// Disk image structure points outside of the image or filesystem
+pub(crate) const BOUNDS_ERR: i32 = 10;
+/// Some entries were handled successfully, but others failed
+pub(crate) const PARTIAL_ERR: i32 = 11;
+/// Warnings were treated as failures because of --strict
+pub(crate) const STRICT_ERR: i32 = 12;
+/// Destructive operation was not confirmed
+pub(crate) const NOT_CONFIRMED_ERR: i32 = 13;
+/// Compared trees differ
+pub(crate) const DIFF_ERR: i32 = 14;
+/// Validation found failures
+pub(crate) const VALIDATE_ERR: i32 = 15;
+/// Refused to write to a device without --force-device
+pub(crate) const DEVICE_ERR: i32 = 16;
+/// Script failed to compile or run
+pub(crate) const SCRIPT_ERR: i32 = 17;
+
+/// Exit code for an error reading a disk image with the library
+pub(crate) fn for_lib_error(e: &SgidiskLibReadError) -> i32 {
+  match e {
+    SgidiskLibReadError::Unpack(_) | SgidiskLibReadError::Value(_) | SgidiskLibReadError::BadMagic { .. } => PARSE_ERR,
+    SgidiskLibReadError::Bounds(_) | SgidiskLibReadError::ReadOutOfBounds { .. } | SgidiskLibReadError::InodeOutOfBounds { .. }
+    | SgidiskLibReadError::ExtentOutOfBounds { .. } | SgidiskLibReadError::LimitExceeded { .. }
+    | SgidiskLibReadError::SymlinkLoop { .. } => BOUNDS_ERR,
+    SgidiskLibReadError::Io(_) | SgidiskLibReadError::Cancelled => IO_ERR,
+    SgidiskLibReadError::NotFound(_) => CLI_ARG_ERROR
+  }
+}
+
+/// Exit code for a command which handled a number of entries, if any of them failed
+pub(crate) fn for_failures(failed: usize, total: usize) -> Option<i32> {
+  if failed == 0 {
+    None
+  } else if failed < total {
+    Some(PARTIAL_ERR)
+  } else {
+    Some(IO_ERR)
+  }
+}
+
+/// Error which ends a command, with the exit code to quit with when run from the CLI
+#[derive(Debug)]
+pub(crate) struct CommandError {
+  pub(crate) exit_code: i32,
+  pub(crate) message: String,
+}
+
+impl CommandError {
+  pub(crate) fn new(exit_code: i32, message: String) -> Self {
+    CommandError {
+      exit_code,
+      message,
+    }
+  }
+
+  /// Print the error and quit with its exit code
+  pub(crate) fn quit(&self) -> ! {
+    eprintln!("{}", &self.message);
+    std::process::exit(self.exit_code);
+  }
+}
@@ -1,37 +1,160 @@
 use std::collections::BTreeMap;
-use std::io::{Read, Seek, SeekFrom};
+use std::fs;
+use std::io;
+use std::io::{Read, Write};
 use std::ops::Range;
 use std::process::exit;
 
-use blake3;
 use clap::ArgMatches;
 use serde::Serialize;
-use serde_json;
-use sha2::{Digest, Sha256};
+use serde_json::Value;
 use tabled::{Table, Tabled};
 
+use sgidisklib::hash::RangeHash;
+use sgidisklib::progress::CancellationToken;
+pub(crate) use sgidisklib::hash::{HashAlgorithm, HashingWriter, MultiHash, MultiHashResult};
 use sgidisklib::volhdr::SgidiskVolume;
 
-use crate::OpenVolume;
-
-const HASH_BUF_SZ: usize = 1024 * 16;
+use crate::color::{paint, paint_table_rows, Style};
+use crate::config::Config;
+use crate::output::{OutputFormat, ReportOutput};
+use crate::sqlite::Record;
 
 /// Hash tool entry point
-pub(crate) fn subcommand(disk_file_name: &str, cli_matches: &ArgMatches) {
-  let mut vol = crate::OpenVolume::open_or_quit(disk_file_name);
+pub(crate) fn subcommand(config: &Config, disk_file_name: &str, cli_matches: &ArgMatches) {
+  let (algorithms, opts, ) = hash_options(config, cli_matches);
+
+  // Earlier hash report to compare with
+  let previous = cli_matches.value_of("compare").map(|path| match read_report(path) {
+    Ok(previous) => (path, previous, ),
+    Err(message) => {
+      eprintln!("Unable to read hash report '{}': {}", path, message);
+      exit(crate::exit_codes::CLI_ARG_ERROR);
+    }
+  });
+
+  let mut vol = crate::StreamVolume::open_or_quit(disk_file_name);
+  let hashes = match hash_volume(&mut vol.reader, &vol.volume_header, &algorithms, &opts) {
+    Ok(hashes) => hashes,
+    Err(e) => {
+      eprintln!("Error while reading disk image: {:?}", &e);
+      exit(crate::exit_codes::IO_ERR);
+    }
+  };
+  crate::sqlite::record_or_quit(cli_matches, "hash", disk_file_name, Record::Hashes(&hashes.rows()));
+
+  let mut out = ReportOutput::open_or_quit(cli_matches);
+  let (short, changed, ) = match write_report(hashes, previous.as_ref(), config.format(cli_matches), &mut out) {
+    Ok(counts) => counts,
+    Err(e) => out.quit_write_error(&e)
+  };
+  out.finish_or_quit();
+  if changed > 0 {
+    eprintln!("{} ranges of the disk image changed since the earlier hash report", changed);
+    exit(crate::exit_codes::VERIFY_ERR);
+  }
+  if config.strict(cli_matches) && short > 0 {
+    eprintln!("{} volume files or volumes were short of their listed size", short);
+    exit(crate::exit_codes::STRICT_ERR);
+  }
+}
+
+/// Hash algorithms and options asked for, or quit if they are invalid
+pub(crate) fn hash_options(config: &Config, cli_matches: &ArgMatches) -> (Vec<HashAlgorithm>, HashOptions, ) {
+  // Hash algorithms from CLI take precedence over configured ones
+  let algorithms = match cli_matches.values_of("algorithm") {
+    Some(names) => names
+      .map(|name| HashAlgorithm::from_name(name).unwrap())
+      .collect::<Vec<HashAlgorithm>>(),
+    None => config.hash.algorithms.clone()
+  };
+  if algorithms.is_empty() {
+    eprintln!("No hash algorithms selected");
+    exit(crate::exit_codes::CLI_ARG_ERROR);
+  }
 
-  let json = cli_matches.is_present("json");
-  print_hashes(&mut vol, json);
+  let chunk_sz = match cli_matches.value_of("chunk-size").map(|kib| kib.parse::<u64>()) {
+    Some(Ok(kib)) if kib > 0 => Some(kib * 1024),
+    Some(_) => {
+      eprintln!("Invalid chunk size: {}", cli_matches.value_of("chunk-size").unwrap());
+      exit(crate::exit_codes::CLI_ARG_ERROR);
+    }
+    None => None
+  };
+  let opts = HashOptions {
+    defined_areas: cli_matches.is_present("defined-areas"),
+    chunk_sz,
+  };
+  (algorithms, opts, )
 }
 
-/// Print hashes of volume files and volumes in disk image
-fn print_hashes(vol: &mut OpenVolume, json: bool) {
-  let mut items = hashed_items(&vol.volume_header);
+/// Read the result of a hash report written as JSON, with or without its envelope. Reports with
+/// a newer schema version than this tool writes are refused, as their structure may differ.
+fn read_report(path: &str) -> Result<Value, String> {
+  let report = fs::read_to_string(path).map_err(|e| format!("{:?}", &e))?;
+  let mut report: Value = serde_json::from_str(&report).map_err(|e| e.to_string())?;
+  if let Some(version) = report.get("schema_version").and_then(Value::as_u64) {
+    if version > crate::output::JSON_SCHEMA_VERSION as u64 {
+      return Err(format!("Report has schema version {}, but this tool reads up to version {}", version, crate::output::JSON_SCHEMA_VERSION));
+    }
+  }
+  match report.get_mut("result") {
+    Some(result) => Ok(result.take()),
+    None => Ok(report)
+  }
+}
 
-  // Fill hashes and collect/print whole image hash
-  let image_hash = fill_hashes(vol, &mut items);
+/// What to hash besides the whole image, volume files and volumes
+#[derive(Debug, Default, Clone)]
+pub(crate) struct HashOptions {
+  /// Also hash only the areas the Volume Header defines (itself, volume files and partitions),
+  /// giving a hash of the logical contents which padding and gaps don't change
+  pub(crate) defined_areas: bool,
+  /// Also hash each volume file and volume in chunks of this many bytes, so that a change can
+  /// be narrowed down to the chunks which differ
+  pub(crate) chunk_sz: Option<u64>,
+}
 
-  // Sort hashable items into files and volumes and collect/print hashes
+/// Hashes of a whole disk image, and of the volume files and volumes in it
+pub(crate) struct VolumeHashes {
+  image_hash: MultiHashResult,
+  /// Hash of the areas the Volume Header defines, if asked for
+  defined_areas_hash: Option<MultiHashResult>,
+  file_items: Vec<HashItem>,
+  vol_items: Vec<HashItem>,
+}
+
+/// Hash a disk image, and the volume files and volumes in it, reading sequentially from a reader
+/// positioned at the beginning of the image
+pub(crate) fn hash_volume<R: ?Sized>(reader: &mut R, vh: &SgidiskVolume, algorithms: &[HashAlgorithm], opts: &HashOptions) -> Result<VolumeHashes, io::Error>
+  where R: Read {
+  let mut items = hashed_items(vh);
+
+  // Hash the whole image, every item, their chunks and the defined areas in one pass
+  let mut ranges: Vec<Range<u64>> = items.iter().map(|item| item.range.clone()).collect();
+  let chunk_counts: Vec<usize> = items.iter()
+    .map(|item| match opts.chunk_sz {
+      Some(chunk_sz) => {
+        let before = ranges.len();
+        ranges.extend((item.range.start..item.range.end).step_by(chunk_sz as usize)
+          .map(|start| start..item.range.end.min(start + chunk_sz)));
+        ranges.len() - before
+      }
+      None => 0
+    })
+    .collect();
+  let areas = if opts.defined_areas { vh.defined_areas() } else { Vec::new() };
+  let (image_hash, range_hashes, areas_hash, ) = sgidisklib::hash::hash_ranges_and_areas(reader, &ranges, &areas, algorithms, &CancellationToken::new(), |_| {})?;
+  let defined_areas_hash = if opts.defined_areas { Some(areas_hash) } else { None };
+  let mut range_hashes = range_hashes.into_iter();
+  for item in items.iter_mut() {
+    item.result = range_hashes.next();
+  }
+  for (item, chunk_count, ) in items.iter_mut().zip(chunk_counts) {
+    item.chunks = range_hashes.by_ref().take(chunk_count).collect();
+  }
+
+  // Sort hashable items into files and volumes
   let (file_items, vol_items) = items.into_iter()
     .fold((Vec::new(), Vec::new(), ),
           |(mut file_items, mut vol_items, ), h| {
@@ -42,91 +165,157 @@ fn print_hashes(vol: &mut OpenVolume, json: bool) {
             (file_items, vol_items, )
           });
 
-  if json {
-    let json_display = JsonHashDisplay::new(image_hash, file_items, vol_items);
-    println!("{}", serde_json::to_string(&json_display).unwrap());
-  } else {
-    let image_hash_display = ImageHashDisplayTable::from(image_hash);
-    let file_hashes = HashDisplayTable::from(file_items);
-    let vol_hashes = HashDisplayTable::from(vol_items);
-    println!("Disk image hash:");
-    image_hash_display.print();
-    println!();
-    println!("Volume file hashes:");
-    file_hashes.print();
-    println!();
-    println!("Volume hashes:");
-    vol_hashes.print();
-  }
+  Ok(VolumeHashes {
+    image_hash,
+    defined_areas_hash,
+    file_items,
+    vol_items,
+  })
 }
 
-/// Fill hash data by reading over disk image, and return a hash for the whole image
-fn fill_hashes(vol: &mut OpenVolume, items: &mut Vec<HashItem>) -> MultiHashResult {
-  let len = items.len();
-  let mut finished = vec![false; len];
+impl VolumeHashes {
+  /// Header of a table of hashes
+  const TABLE_HEADER: [&'static str; 5] = ["item_type", "item", "algorithm", "hash", "short"];
 
-  // Return to beginning of file
-  if let Err(e) = vol.disk_file.seek(SeekFrom::Start(0)) {
-    eprintln!("Failed to seek: {:?}", &e);
-    exit(crate::exit_codes::IO_ERR);
+  /// Number of volume files and volumes which were shorter than listed, i.e. ran past the end
+  /// of the disk image
+  pub(crate) fn short_count(&self) -> usize {
+    self.file_items.iter().chain(&self.vol_items)
+      .filter(|item| item.short_by().is_some())
+      .count()
   }
-  let mut pos = 0u64;
-
-  // Read entire image in chunks
-  let mut image_hash = MultiHash::new();
-  let mut fh = &vol.disk_file;
-  let mut buf = [0u8; HASH_BUF_SZ];
-  loop {
-    match fh.read(&mut buf) {
-      // End of file
-      Ok(0) => break,
-
-      // Successful read
-      Ok(n) => {
-        // Update whole file hash
-        image_hash.update(&buf[0..n]);
-
-        // Read window from pos to end
-        let end = pos + n as u64;
-
-        // For each hashable item...
-        for i in 0..len {
-          // Skip completed hashes
-          if finished[i] {
-            continue;
-          }
-          // If we have moved past its end, mark it complete
-          if (items[i].end as u64) < pos {
-            finished[i] = true;
-            continue;
-          }
-          // If we have overlap...
-          if let Some(overlap) = items[i].window_overlap(pos as i64, end as i64) {
-            // Update the item's hash with the overlapping bytes
-            items[i].hashed += (overlap.end - overlap.start) as u64;
-            match items[i].hash.as_mut() {
-              Some(h) => h.update(&buf[overlap]),
-              _ => panic!("Missing hash entry")
-            }
+
+  /// Records of a table with a record per hashed item and algorithm, in the order of `TABLE_HEADER`
+  pub(crate) fn rows(&self) -> Vec<Vec<String>> {
+    let mut rows: Vec<Vec<String>> = self.image_hash.clone().entries().into_iter()
+      .map(|(algorithm, hash, )| vec!["image".to_string(), String::new(), algorithm.name().to_string(), hash, String::new()])
+      .collect();
+    if let Some(areas_hash) = &self.defined_areas_hash {
+      rows.extend(areas_hash.clone().entries().into_iter()
+        .map(|(algorithm, hash, )| vec!["defined_areas".to_string(), String::new(), algorithm.name().to_string(), hash, String::new()]));
+    }
+    for (item_type, items, ) in [("volume_file", &self.file_items, ), ("volume", &self.vol_items, )] {
+      let mut items: Vec<&HashItem> = items.iter().collect();
+      items.sort_by(|h1, h2| h1.name_json.cmp(&h2.name_json));
+      for item in items {
+        let short = item.short_by().map(|b| b.to_string()).unwrap_or_default();
+        for (algorithm, hash, ) in item.result.as_ref().unwrap().hash.clone().entries() {
+          rows.push(vec![item_type.to_string(), item.name_json.clone(), algorithm.name().to_string(), hash, short.clone()]);
+        }
+        for chunk in &item.chunks {
+          let chunk_short = chunk.short_by().map(|b| b.to_string()).unwrap_or_default();
+          let chunk_name = format!("{}:{}..{}", &item.name_json, chunk.range.start, chunk.range.end);
+          for (algorithm, hash, ) in chunk.hash.clone().entries() {
+            rows.push(vec![format!("{}_chunk", item_type), chunk_name.clone(), algorithm.name().to_string(), hash, chunk_short.clone()]);
           }
         }
-
-        pos = end;
       }
+    }
+    rows
+  }
+
+  /// Write as a CSV or porcelain table, with a record per hashed item and algorithm
+  fn write_table<W: ?Sized>(&self, writer: &mut W, format: OutputFormat) -> io::Result<()>
+    where W: Write {
+    crate::output::write_table(writer, format, &Self::TABLE_HEADER, &self.rows())
+  }
 
-      // IO error
-      Err(e) => {
-        eprintln!("Error while reading disk image: {:?}", &e);
-        exit(crate::exit_codes::IO_ERR);
+  /// Ranges of the disk image whose hashes differ from those in an earlier hash report: the
+  /// chunks which differ if both have chunk hashes, otherwise whole volume files and volumes
+  fn changed_ranges(&self, previous: &Value) -> Vec<JsonChangedRange> {
+    let mut changed = Vec::new();
+    for (key, item_type, items, ) in [("volume_files", "volume_file", &self.file_items, ), ("volumes", "volume", &self.vol_items, )] {
+      for item in items {
+        let earlier = &previous[key][&item.name_json];
+        let earlier_chunks: BTreeMap<u64, &Value> = earlier["chunks"].as_array()
+          .map(|chunks| chunks.iter()
+            .filter_map(|chunk| Some((chunk["offset"].as_u64()?, &chunk["hash"], )))
+            .collect())
+          .unwrap_or_default();
+
+        let ranges: Vec<Range<u64>> = if !item.chunks.is_empty() && !earlier_chunks.is_empty() {
+          item.chunks.iter()
+            .filter(|chunk| earlier_chunks.get(&chunk.range.start).map_or(true, |hash| hash_differs(&chunk.hash, hash)))
+            .map(|chunk| chunk.range.clone())
+            .collect()
+        } else if earlier.is_null() || hash_differs(&item.result.as_ref().unwrap().hash, &earlier["hash"]) {
+          vec![item.range.clone()]
+        } else {
+          Vec::new()
+        };
+        changed.extend(ranges.into_iter().map(|range| JsonChangedRange {
+          item_type,
+          item: item.name_json.clone(),
+          start: range.start,
+          end: range.end,
+        }));
       }
     }
+    changed
   }
 
-  // Finalize hashes
-  items.iter_mut().for_each(|i| i.finalize());
+  /// JSON representation of the hashes
+  pub(crate) fn into_json(self) -> JsonHashDisplay {
+    JsonHashDisplay::new(self.image_hash, self.defined_areas_hash, self.file_items, self.vol_items)
+  }
+}
 
-  // Return whole image hash
-  image_hash.finalize()
+/// Whether a hash differs from the hash of the same algorithm in a hash report. Algorithms only
+/// in one of them aren't compared.
+fn hash_differs(hash: &MultiHashResult, earlier: &Value) -> bool {
+  hash.clone().entries().into_iter()
+    .any(|(algorithm, hash, )| earlier[algorithm.name()].as_str().map_or(false, |earlier| earlier != hash))
+}
+
+/// Write hashes, and the ranges which changed since an earlier hash report (file name and
+/// result) if given, returning how many items were short and how many ranges changed
+pub(crate) fn write_report<W: ?Sized>(hashes: VolumeHashes, previous: Option<&(&str, Value, )>, format: OutputFormat, writer: &mut W) -> io::Result<(usize, usize, )>
+  where W: Write {
+  let short = hashes.short_count();
+  let changed = previous.map(|(_, previous, )| hashes.changed_ranges(previous));
+  let changed_count = changed.as_ref().map_or(0, |changed| changed.len());
+
+  if format.is_structured() {
+    let mut json = hashes.into_json();
+    json.changed = changed;
+    crate::output::write_json(writer, "hash", &json)?;
+  } else if format.is_tabular() {
+    hashes.write_table(writer, format)?;
+  } else {
+    let chunk_hashes = ChunkHashDisplayTable::from(&hashes);
+    let image_hash_display = ImageHashDisplayTable::from(hashes.image_hash);
+    let file_hashes = HashDisplayTable::from(hashes.file_items);
+    let vol_hashes = HashDisplayTable::from(hashes.vol_items);
+    writeln!(writer, "{}", paint("Disk image hash:", Style::Heading))?;
+    image_hash_display.write(writer)?;
+    writeln!(writer)?;
+    if let Some(areas_hash) = hashes.defined_areas_hash {
+      writeln!(writer, "{}", paint("Defined areas hash:", Style::Heading))?;
+      ImageHashDisplayTable::from(areas_hash).write(writer)?;
+      writeln!(writer)?;
+    }
+    writeln!(writer, "{}", paint("Volume file hashes:", Style::Heading))?;
+    file_hashes.write(writer)?;
+    writeln!(writer)?;
+    writeln!(writer, "{}", paint("Volume hashes:", Style::Heading))?;
+    vol_hashes.write(writer)?;
+    if !chunk_hashes.0.is_empty() {
+      writeln!(writer)?;
+      writeln!(writer, "{}", paint("Chunk hashes:", Style::Heading))?;
+      chunk_hashes.write(writer)?;
+    }
+    if let (Some((path, _, )), Some(changed), ) = (previous, changed) {
+      writeln!(writer)?;
+      writeln!(writer, "{}", paint(&format!("Changed since '{}':", path), Style::Heading))?;
+      if changed.is_empty() {
+        writeln!(writer, "Nothing")?;
+      } else {
+        ChangedDisplayTable::from(changed).write(writer)?;
+      }
+    }
+  }
+
+  Ok((short, changed_count, ))
 }
 
 /// Compile a list of items to hash out of volume files and partitions
@@ -137,17 +326,14 @@ fn hashed_items(vh: &SgidiskVolume) -> Vec<HashItem> {
   items.append(&mut vh.files.iter()
     .filter(|f| f.in_use())
     .map(|f| {
-      let start = f.block_start as i64 * sgidisklib::efs::EFS_BLOCK_SZ as i64;
       let name = f.file_name.as_ref().unwrap();
       HashItem {
         name_display: name.clone(),
         name_json: name.clone(),
         item_type: HashItemType::VolumeFile,
-        start,
-        end: start + f.file_sz as i64,
-        hashed: 0,
-        hash: Some(MultiHash::new()),
-        hash_result: None,
+        range: f.byte_range(),
+        result: None,
+        chunks: Vec::new(),
       }
     })
     .collect::<Vec<HashItem>>());
@@ -160,25 +346,28 @@ fn hashed_items(vh: &SgidiskVolume) -> Vec<HashItem> {
       name_display: format!("{:>2} ({})", id, p.partition_type),
       name_json: id.to_string(),
       item_type: HashItemType::Partition,
-      start: p.block_start as i64 * sgidisklib::efs::EFS_BLOCK_SZ as i64,
-      end: (p.block_start + p.block_sz) as i64 * sgidisklib::efs::EFS_BLOCK_SZ as i64,
-      hashed: 0,
-      hash: Some(MultiHash::new()),
-      hash_result: None,
+      range: p.byte_range(),
+      result: None,
+      chunks: Vec::new(),
     })
     .collect::<Vec<HashItem>>());
 
-  items.sort_by_key(|h| -h.end);
+  items.sort_by_key(|h| std::cmp::Reverse(h.range.end));
 
   items
 }
 
 /// JSON structure for hash display
 #[derive(Serialize)]
-struct JsonHashDisplay {
+pub(crate) struct JsonHashDisplay {
   image: MultiHashResult,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  defined_areas: Option<MultiHashResult>,
   volume_files: JsonHashItems,
   volumes: JsonHashItems,
+  /// Ranges which changed since an earlier hash report, if compared with one
+  #[serde(skip_serializing_if = "Option::is_none")]
+  changed: Option<Vec<JsonChangedRange>>,
 }
 
 type JsonHashItems = BTreeMap<String, JsonHashElement>;
@@ -187,19 +376,44 @@ type JsonHashItems = BTreeMap<String, JsonHashElement>;
 #[derive(Serialize)]
 struct JsonHashElement {
   hash: MultiHashResult,
-  short: Option<i64>,
+  short: Option<u64>,
+  #[serde(skip_serializing_if = "Vec::is_empty")]
+  chunks: Vec<JsonChunkHash>,
+}
+
+/// JSON display entry for one chunk of a hashable item
+#[derive(Serialize)]
+struct JsonChunkHash {
+  /// Offset of the chunk in the disk image, in bytes
+  offset: u64,
+  len: u64,
+  hash: MultiHashResult,
+  short: Option<u64>,
+}
+
+/// JSON representation of a range of the disk image which changed since an earlier hash report
+#[derive(Serialize)]
+struct JsonChangedRange {
+  item_type: &'static str,
+  item: String,
+  /// Offsets in the disk image, in bytes
+  start: u64,
+  end: u64,
 }
 
 impl JsonHashDisplay {
-  /// Create a JsonHashDisplay from a whole image hash, volume files hash set, and volume hash set
-  fn new(image: MultiHashResult, file_items: Vec<HashItem>, vol_items: Vec<HashItem>) -> Self {
+  /// Create a JsonHashDisplay from a whole image hash, defined areas hash, volume files hash set,
+  /// and volume hash set
+  fn new(image: MultiHashResult, defined_areas: Option<MultiHashResult>, file_items: Vec<HashItem>, vol_items: Vec<HashItem>) -> Self {
     let volume_files = Self::items(file_items);
     let volumes = Self::items(vol_items);
 
     JsonHashDisplay {
       image,
+      defined_areas,
       volume_files,
       volumes,
+      changed: None,
     }
   }
 
@@ -208,10 +422,19 @@ impl JsonHashDisplay {
     items.into_iter()
       .map(|item| {
         let short = item.short_by();
+        let chunks = item.chunks.into_iter()
+          .map(|chunk| JsonChunkHash {
+            offset: chunk.range.start,
+            len: chunk.range.end - chunk.range.start,
+            short: chunk.short_by(),
+            hash: chunk.hash,
+          })
+          .collect();
         (item.name_json,
          JsonHashElement {
-           hash: item.hash_result.unwrap(),
+           hash: item.result.unwrap().hash,
            short,
+           chunks,
          }, )
       })
       .collect::<BTreeMap<String, JsonHashElement>>()
@@ -232,26 +455,23 @@ struct ImageHashDisplayTableEntry {
 }
 
 impl ImageHashDisplayTable {
-  /// Print formatted table to stdout
-  fn print(&self) {
-    print!("{}", Table::new(&self.0)
-      .with(crate::table_fmt()));
+  /// Write formatted table
+  fn write<W: ?Sized>(&self, writer: &mut W) -> io::Result<()>
+    where W: Write {
+    write!(writer, "{}", Table::new(&self.0)
+      .with(crate::table_fmt()))
   }
 }
 
 impl From<MultiHashResult> for ImageHashDisplayTable {
   /// Convert a single MultiHashResult to a printable image hash table
   fn from(h: MultiHashResult) -> Self {
-    let tab = vec![
-      ImageHashDisplayTableEntry {
-        hash_type: "SHA-256",
-        hash_value: h.sha256,
-      },
-      ImageHashDisplayTableEntry {
-        hash_type: "BLAKE3",
-        hash_value: h.blake3,
-      },
-    ];
+    let tab = h.entries().into_iter()
+      .map(|(algorithm, hash_value, )| ImageHashDisplayTableEntry {
+        hash_type: algorithm.display_name(),
+        hash_value,
+      })
+      .collect::<Vec<ImageHashDisplayTableEntry>>();
 
     Self(tab)
   }
@@ -275,10 +495,21 @@ struct HashDisplayTableEntry {
 }
 
 impl HashDisplayTable {
-  /// Print formatted table to stdout
-  fn print(&self) {
-    print!("{}", Table::new(&self.0)
-      .with(crate::table_fmt()));
+  /// Write formatted table, highlighting items which were short
+  fn write<W: ?Sized>(&self, writer: &mut W) -> io::Result<()>
+    where W: Write {
+    let styles = self.0.iter()
+      .map(|entry| if entry.short != HashItem::NOT_SHORT {
+        Some(Style::Warning)
+      } else {
+        None
+      })
+      .collect::<Vec<Option<Style>>>();
+
+    let table = Table::new(&self.0)
+      .with(crate::table_fmt())
+      .to_string();
+    write!(writer, "{}", paint_table_rows(&table, &styles))
   }
 }
 
@@ -290,21 +521,15 @@ impl From<Vec<HashItem>> for HashDisplayTable {
       .map(|h| {
         let short = h.short_by_str();
         let item = h.name_display;
-        let hash_result = h.hash_result.unwrap();
-        vec![
-          HashDisplayTableEntry {
+        let hash_result = h.result.unwrap().hash;
+        hash_result.entries().into_iter()
+          .map(|(algorithm, hash, )| HashDisplayTableEntry {
             item: item.clone(),
-            hash_type: "SHA-256",
-            hash: hash_result.sha256,
+            hash_type: algorithm.display_name(),
+            hash,
             short: short.clone(),
-          },
-          HashDisplayTableEntry {
-            item,
-            hash_type: "BLAKE3",
-            hash: hash_result.blake3,
-            short,
-          },
-        ]
+          })
+          .collect::<Vec<HashDisplayTableEntry>>()
       })
       .flatten()
       .collect::<Vec<HashDisplayTableEntry>>();
@@ -313,6 +538,94 @@ impl From<Vec<HashItem>> for HashDisplayTable {
   }
 }
 
+/// A printable table of the chunk hashes of hashed items
+struct ChunkHashDisplayTable(Vec<ChunkHashDisplayTableEntry>);
+
+/// Printable chunk hash table entry
+#[derive(Tabled)]
+struct ChunkHashDisplayTableEntry {
+  #[header("Item")]
+  item: String,
+  #[header("Bytes")]
+  bytes: String,
+  #[header("Hash Type")]
+  hash_type: &'static str,
+  #[header("Hash")]
+  hash: String,
+}
+
+impl ChunkHashDisplayTable {
+  /// Write formatted table
+  fn write<W: ?Sized>(&self, writer: &mut W) -> io::Result<()>
+    where W: Write {
+    write!(writer, "{}", Table::new(&self.0)
+      .with(crate::table_fmt()))
+  }
+}
+
+impl From<&VolumeHashes> for ChunkHashDisplayTable {
+  /// Chunks of volume files, then of volumes, each in order of name and offset
+  fn from(hashes: &VolumeHashes) -> Self {
+    let mut tab = Vec::new();
+    for items in [&hashes.file_items, &hashes.vol_items] {
+      let mut items: Vec<&HashItem> = items.iter().collect();
+      items.sort_by(|h1, h2| h1.name_display.cmp(&h2.name_display));
+      for item in items {
+        for chunk in &item.chunks {
+          tab.extend(chunk.hash.clone().entries().into_iter()
+            .map(|(algorithm, hash, )| ChunkHashDisplayTableEntry {
+              item: item.name_display.clone(),
+              bytes: format!("{}..{}", chunk.range.start, chunk.range.end),
+              hash_type: algorithm.display_name(),
+              hash,
+            }));
+        }
+      }
+    }
+    ChunkHashDisplayTable(tab)
+  }
+}
+
+/// A printable table of ranges which changed since an earlier hash report
+struct ChangedDisplayTable(Vec<ChangedDisplayTableEntry>);
+
+/// Printable changed range table entry
+#[derive(Tabled)]
+struct ChangedDisplayTableEntry {
+  #[header("Item")]
+  item: String,
+  #[header("Bytes")]
+  bytes: String,
+  #[header("Blocks")]
+  blocks: String,
+}
+
+impl ChangedDisplayTable {
+  /// Write formatted table, highlighting every row
+  fn write<W: ?Sized>(&self, writer: &mut W) -> io::Result<()>
+    where W: Write {
+    let styles = vec![Some(Style::Warning); self.0.len()];
+    let table = Table::new(&self.0)
+      .with(crate::table_fmt())
+      .to_string();
+    write!(writer, "{}", paint_table_rows(&table, &styles))
+  }
+}
+
+impl From<Vec<JsonChangedRange>> for ChangedDisplayTable {
+  fn from(changed: Vec<JsonChangedRange>) -> Self {
+    let block_sz = sgidisklib::efs::EFS_BLOCK_SZ as u64;
+    let tab = changed.into_iter()
+      .map(|range| ChangedDisplayTableEntry {
+        item: format!("{} {}", range.item_type.replace('_', " "), range.item),
+        bytes: format!("{}..{}", range.start, range.end),
+        blocks: format!("{}..{}", range.start / block_sz, (range.end + block_sz - 1) / block_sz),
+      })
+      .collect();
+    ChangedDisplayTable(tab)
+  }
+}
+
 /// Range based hashed item
 struct HashItem {
   /// Display name of hashed item
@@ -321,16 +634,12 @@ struct HashItem {
   name_json: String,
   /// Type of hashed item
   item_type: HashItemType,
-  /// Start of hashed range (bytes)
-  start: i64,
-  /// End of hashed range (bytes)
-  end: i64,
-  /// Number of bytes hashed
-  hashed: u64,
-  /// Hash value tracking
-  hash: Option<MultiHash>,
+  /// Range of the disk image hashed (bytes)
+  range: Range<u64>,
   /// Hash result
-  hash_result: Option<MultiHashResult>,
+  result: Option<RangeHash>,
+  /// Hashes of fixed-size chunks of the range, if asked for
+  chunks: Vec<RangeHash>,
 }
 
 #[derive(Debug, Copy, Clone)]
@@ -339,95 +648,20 @@ enum HashItemType {
   VolumeFile,
 }
 
-/// Hashes with BLAKE2b, SHA-256
-pub(crate) struct MultiHash {
-  blake3: blake3::Hasher,
-  sha256: Sha256,
-}
-
-/// Results from MultiHash hashes
-#[derive(Debug, Serialize)]
-pub(crate) struct MultiHashResult {
-  pub(crate) blake3: String,
-  pub(crate) sha256: String,
-}
-
 impl HashItem {
-  fn finalize(&mut self) {
-    let hash = self.hash.take().unwrap();
-    self.hash_result = Some(hash.finalize());
-  }
-
-  /// Determine the overlap of our hashed item window into a supplied buffer window, as a range of bytes
-  fn window_overlap(&self, start: i64, end: i64) -> Option<Range<usize>> {
-    // No overlap case
-    if self.end <= start || self.start >= end {
-      return None;
-    }
-
-    // Overlap start into block
-    let ovr_start = if self.start > start {
-      self.start - start
-    } else {
-      0
-    } as usize;
-    // Overlap end into block
-    let ovr_end = (self.end.min(end) - start) as usize;
-
-    Some(ovr_start..ovr_end)
-  }
+  /// Table value for an item which was hashed completely
+  const NOT_SHORT: &'static str = "No";
 
   /// Determine whether we're short on bytes hashed
-  fn short_by(&self) -> Option<i64> {
-    let sz = self.end - self.start;
-    let hashed = self.hashed as i64;
-    if hashed != sz {
-      Some(sz - hashed)
-    } else {
-      None
-    }
+  fn short_by(&self) -> Option<u64> {
+    self.result.as_ref().and_then(|r| r.short_by())
   }
 
   /// Return a convenient table string based on short_by()
   fn short_by_str(&self) -> String {
     match self.short_by() {
-      None => "No".to_string(),
+      None => Self::NOT_SHORT.to_string(),
       Some(n) => format!("Yes ({})", n)
     }
   }
 }
-
-impl MultiHash {
-  /// Create a new MultiHash hasher
-  pub fn new() -> Self {
-    let blake3 = blake3::Hasher::new();
-    let sha256 = Sha256::new();
-
-    MultiHash {
-      blake3,
-      sha256,
-    }
-  }
-
-  /// Update hash with data
-  pub fn update(&mut self, b: &[u8]) {
-    self.blake3.update(b);
-    self.sha256.update(b);
-  }
-
-  /// Finalize hash and populate results
-  pub fn finalize(self) -> MultiHashResult {
-    MultiHashResult {
-      blake3: Self::bytes_to_hex(self.blake3.finalize().as_bytes()),
-      sha256: Self::bytes_to_hex(&self.sha256.finalize()[..]),
-    }
-  }
-
-  /// Format byte slice as hex, perhaps somewhat inefficiently
-  fn bytes_to_hex(b: &[u8]) -> String {
-    b.iter()
-      .map(|b| format!("{:02X}", b))
-      .collect::<Vec<String>>()
-      .concat()
-  }
-}
\ No newline at end of file
@@ -0,0 +1,293 @@
+use std::fs::File;
+use std::io;
+use std::io::{Read, Seek, SeekFrom};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+use tracing::warn;
+
+use sgidisklib::cache::BlockCache;
+use sgidisklib::diskcache;
+use sgidisklib::diskcache::{CachedBlocks, DiskCache, DiskCacheOptions};
+use sgidisklib::ewf::EwfReader;
+use sgidisklib::rescue::{RescueOptions, RescueReader};
+
+use crate::device::Device;
+use crate::optical::OpticalDrive;
+
+/// Number of bytes read ahead through a block cache when opening disk images for random access,
+/// or 0 to read them directly
+static READAHEAD: AtomicU64 = AtomicU64::new(0);
+
+/// Offset of the SGI disk within disk images opened for random access, e.g. when it is in a
+/// partition of a PC partition table
+static OFFSET: AtomicU64 = AtomicU64::new(0);
+
+/// How failed reads of disk images are retried and skipped, and where the map of what was read is
+/// written, if they are to be read as damaged media
+static RESCUE: Mutex<Option<(RescueOptions, Option<String>, )>> = Mutex::new(None);
+
+/// Directory in which blocks of disk images are kept between runs, and how much is kept, if given
+static DISK_CACHE: Mutex<Option<(PathBuf, DiskCacheOptions, )>> = Mutex::new(None);
+
+/// Minimum number of blocks kept by the block cache
+const MIN_CACHE_BLOCKS: usize = 16 * 1024;
+
+/// Disk image opened for random access: either a raw image, a raw disk device, a CD-ROM drive, or
+/// the media data of an EWF (E01) evidence container
+#[derive(Debug)]
+pub(crate) enum DiskImage {
+  Raw(File),
+  Ewf(EwfReader),
+  Device(Box<Device>),
+  Optical(Box<OpticalDrive>),
+  /// Another disk image read through a block cache with readahead
+  Cached(Box<BlockCache<DiskImage>>),
+  /// Another disk image read from an offset, as if it started there
+  Offset(Box<DiskImage>, u64),
+  /// Another disk image read as damaged media, retrying and perhaps skipping failed reads
+  Rescue(Box<Rescue>),
+  /// Another disk image whose blocks are kept on disk between runs
+  DiskCached(Box<DiskCache<DiskImage>>),
+}
+
+/// Disk image read as damaged media, writing the map of what was read to a file if asked to
+#[derive(Debug)]
+pub(crate) struct Rescue {
+  reader: RescueReader<DiskImage>,
+  map_path: Option<String>,
+  /// Number of bytes which couldn't be read when the map was last written
+  mapped_bad: u64,
+}
+
+/// Set up reading ahead, in KiB, when disk images are opened for random access
+pub(crate) fn init(readahead_kib: u64) {
+  READAHEAD.store(readahead_kib * 1024, Ordering::Relaxed);
+}
+
+/// Set up the offset, in bytes, at which disk images opened for random access start
+pub(crate) fn init_offset(offset: u64) {
+  OFFSET.store(offset, Ordering::Relaxed);
+}
+
+/// Set up reading disk images as damaged media, writing the map of what was read to a file if one
+/// is given
+pub(crate) fn init_rescue(options: RescueOptions, map_path: Option<String>) {
+  *RESCUE.lock().unwrap() = Some((options, map_path, ));
+}
+
+/// Set up keeping blocks of disk images in a directory between runs
+pub(crate) fn init_disk_cache(dir: PathBuf, options: DiskCacheOptions) {
+  *DISK_CACHE.lock().unwrap() = Some((dir, options, ));
+}
+
+/// Whether disk images are read as damaged media
+pub(crate) fn rescuing() -> bool {
+  RESCUE.lock().unwrap().is_some()
+}
+
+/// Offset at which disk images opened for random access start, if one was given
+pub(crate) fn offset() -> u64 {
+  OFFSET.load(Ordering::Relaxed)
+}
+
+impl DiskImage {
+  /// Open a disk image, as an EWF container if it starts with the EWF signature
+  pub(crate) fn open(disk_file_name: &str) -> Result<Self, String> {
+    // Devices are never containers, and may not allow reading their signature unaligned
+    if crate::device::is_device_path(disk_file_name) && crate::optical::is_optical_drive(disk_file_name) {
+      return match OpticalDrive::open(disk_file_name) {
+        Ok(drive) => {
+          tracing::debug!("Opened optical drive '{}' read-only ({:?} sectors, {} bytes)", disk_file_name, drive.mode(), drive.size());
+          Ok(DiskImage::Optical(Box::new(drive)))
+        }
+        Err(e) => Err(format!("Unable to open optical drive '{}': {:?}", disk_file_name, &e))
+      };
+    }
+    if crate::device::is_device_path(disk_file_name) {
+      return match Device::open(disk_file_name) {
+        Ok(device) => {
+          tracing::debug!("Opened device '{}' read-only ({} byte sectors, {} bytes)", disk_file_name, device.sector_sz(), device.size());
+          Ok(DiskImage::Device(Box::new(device)))
+        }
+        Err(e) => Err(format!("Unable to open device '{}': {:?}", disk_file_name, &e))
+      };
+    }
+    match EwfReader::is_ewf(disk_file_name) {
+      Ok(true) => match EwfReader::open(disk_file_name) {
+        Ok(ewf) => Ok(DiskImage::Ewf(ewf)),
+        Err(e) => Err(format!("Unable to open EWF container '{}': {:?}", disk_file_name, &e))
+      },
+      Ok(false) => match File::open(disk_file_name) {
+        Ok(file) => Ok(DiskImage::Raw(file)),
+        Err(e) => Err(format!("Unable to open disk image '{}': {:?}", disk_file_name, &e))
+      },
+      Err(e) => Err(format!("Unable to open disk image '{}': {:?}", disk_file_name, &e))
+    }
+  }
+
+  /// Read the disk image through a block cache, if reading ahead was set up
+  pub(crate) fn with_readahead(self) -> Self {
+    let readahead = READAHEAD.load(Ordering::Relaxed);
+    if readahead == 0 {
+      return self;
+    }
+    let block_sz = BlockCache::<DiskImage>::DEFAULT_BLOCK_SZ;
+    let readahead_blocks = (readahead + block_sz - 1) / block_sz;
+    let capacity = MIN_CACHE_BLOCKS.max(readahead_blocks as usize * 4);
+    DiskImage::Cached(Box::new(BlockCache::new(self, block_sz, readahead_blocks, capacity)))
+  }
+
+  /// Read the disk image as damaged media, if that was set up
+  pub(crate) fn with_rescue(self) -> Self {
+    match RESCUE.lock().unwrap().clone() {
+      Some((options, map_path, )) => DiskImage::Rescue(Box::new(Rescue {
+        reader: RescueReader::new(self, options),
+        map_path,
+        mapped_bad: 0,
+      })),
+      None => self
+    }
+  }
+
+  /// Keep the disk image's blocks in the cache directory, if one was set up. Images read with
+  /// unreadable sectors skipped aren't, so the zeros read in their place aren't kept.
+  pub(crate) fn with_disk_cache(self) -> Self {
+    let (dir, options, ) = match DISK_CACHE.lock().unwrap().clone() {
+      Some(cache) => cache,
+      None => return self
+    };
+    if let DiskImage::Rescue(rescue) = &self {
+      if rescue.reader.options().skip_unreadable {
+        warn!("Not caching the disk image in '{}', as unreadable sectors are read as zeros", dir.display());
+        return self;
+      }
+    }
+    let mut image = self;
+    let modified = image.file().and_then(|file| file.metadata().ok()).and_then(|meta| meta.modified().ok());
+    match diskcache::image_key(&mut image, modified).and_then(|key| CachedBlocks::open(&dir, &key, options)) {
+      Ok(blocks) => DiskImage::DiskCached(Box::new(DiskCache::new(image, blocks))),
+      Err(e) => {
+        warn!("Unable to use the cache directory '{}', reading without it: {:?}", dir.display(), &e);
+        image
+      }
+    }
+  }
+
+  /// Read the disk image from an offset, unless it is 0
+  pub(crate) fn with_offset(self, offset: u64) -> Self {
+    if offset == 0 {
+      return self;
+    }
+    DiskImage::Offset(Box::new(self), offset)
+  }
+
+  /// Size of the disk image in bytes; for an EWF container, the size of its media
+  pub(crate) fn len(&self) -> io::Result<u64> {
+    match self {
+      DiskImage::Raw(file) => Ok(file.metadata()?.len()),
+      DiskImage::Ewf(ewf) => Ok(ewf.size()),
+      DiskImage::Device(device) => Ok(device.size()),
+      DiskImage::Optical(drive) => Ok(drive.size()),
+      DiskImage::Cached(cache) => cache.get_ref().len(),
+      DiskImage::Offset(image, offset) => Ok(image.len()?.saturating_sub(*offset)),
+      DiskImage::Rescue(rescue) => rescue.reader.get_ref().len(),
+      DiskImage::DiskCached(cache) => cache.get_ref().len()
+    }
+  }
+
+  /// File of a raw disk image, which can be read from several threads at once. An image read from
+  /// an offset has none, as the file's offsets aren't the image's.
+  pub(crate) fn file(&self) -> Option<&File> {
+    match self {
+      DiskImage::Raw(file) => Some(file),
+      DiskImage::Ewf(_) | DiskImage::Device(_) | DiskImage::Optical(_) | DiskImage::Cached(_) | DiskImage::Offset(..) | DiskImage::Rescue(_)
+        | DiskImage::DiskCached(_) => None
+    }
+  }
+}
+
+impl Read for DiskImage {
+  fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+    match self {
+      DiskImage::Raw(file) => file.read(buf),
+      DiskImage::Ewf(ewf) => ewf.read(buf),
+      DiskImage::Device(device) => device.read(buf),
+      DiskImage::Optical(drive) => drive.read(buf),
+      DiskImage::Cached(cache) => cache.read(buf),
+      DiskImage::Offset(image, _) => image.read(buf),
+      DiskImage::Rescue(rescue) => rescue.read(buf),
+      DiskImage::DiskCached(cache) => cache.read(buf)
+    }
+  }
+}
+
+impl Seek for DiskImage {
+  fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+    match self {
+      DiskImage::Raw(file) => file.seek(pos),
+      DiskImage::Ewf(ewf) => ewf.seek(pos),
+      DiskImage::Device(device) => device.seek(pos),
+      DiskImage::Optical(drive) => drive.seek(pos),
+      DiskImage::Cached(cache) => cache.seek(pos),
+      DiskImage::Rescue(rescue) => rescue.reader.seek(pos),
+      DiskImage::DiskCached(cache) => cache.seek(pos),
+      DiskImage::Offset(image, offset) => {
+        let pos = match pos {
+          SeekFrom::Start(pos) => image.seek(SeekFrom::Start(pos.saturating_add(*offset)))?,
+          pos => image.seek(pos)?
+        };
+        match pos.checked_sub(*offset) {
+          Some(pos) => Ok(pos),
+          None => {
+            image.seek(SeekFrom::Start(*offset))?;
+            Err(io::Error::new(io::ErrorKind::InvalidInput, "Seek before start of disk image"))
+          }
+        }
+      }
+    }
+  }
+}
+
+impl Rescue {
+  /// Write the map of what was read, if asked to
+  fn write_map(&mut self) {
+    self.mapped_bad = self.reader.map().bad_bytes();
+    let path = match &self.map_path {
+      Some(path) => path,
+      None => return
+    };
+    let written = self.reader.get_ref().len()
+      .and_then(|size| {
+        let mut file = File::create(path)?;
+        self.reader.map().write_mapfile(&mut file, size)
+      });
+    if let Err(e) = written {
+      warn!("Unable to write the bad block map to '{}': {:?}", path, &e);
+    }
+  }
+}
+
+impl Read for Rescue {
+  /// Read, writing the map each time another sector can't be read, so it is up to date however
+  /// the command ends
+  fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+    let read = self.reader.read(buf);
+    if self.reader.map().bad_bytes() != self.mapped_bad {
+      self.write_map();
+    }
+    read
+  }
+}
+
+impl Drop for Rescue {
+  /// Write the final map, and warn of what couldn't be read
+  fn drop(&mut self) {
+    self.write_map();
+    let map = self.reader.map();
+    if !map.bad.is_empty() {
+      warn!("{} bytes of the disk image in {} ranges couldn't be read", map.bad_bytes(), map.bad.len());
+    }
+  }
+}
@@ -0,0 +1,388 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::fs::File;
+use std::process::exit;
+
+use clap::ArgMatches;
+use serde::{Deserialize, Serialize};
+use tabled::{Table, Tabled};
+
+use crate::color::{paint, paint_table_rows, Style};
+use crate::config::Config;
+use crate::hash::{HashAlgorithm, MultiHash, MultiHashResult};
+use crate::sidecar::SIDECAR_SCHEMA;
+
+/// Release shown for images whose sidecars don't record one
+const UNKNOWN_RELEASE: &str = "unknown";
+
+/// Fixity sidecar, as written by `efs cp --sidecar`, with the fields an inventory needs
+#[derive(Deserialize)]
+struct ManifestSidecar {
+  schema: String,
+  image: ManifestImage,
+  files: Vec<ManifestFile>,
+}
+
+/// Disk image a sidecar records
+#[derive(Deserialize)]
+struct ManifestImage {
+  file: String,
+  size_bytes: u64,
+  hash: MultiHashResult,
+  /// IRIX release identified on the image, if the sidecar records one
+  #[serde(default)]
+  irix_release: Option<String>,
+}
+
+/// Extracted file a sidecar records
+#[derive(Deserialize)]
+struct ManifestFile {
+  src: String,
+  dest: String,
+  size_bytes: u64,
+  hash: MultiHashResult,
+}
+
+/// Whether a disk image and the files extracted from it still match their sidecars
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Serialize)]
+#[serde(rename_all = "lowercase")]
+enum Fixity {
+  Ok,
+  /// The image or an extracted file is gone
+  Missing,
+  /// The image or an extracted file has a different size or digest
+  Changed,
+}
+
+/// JSON representation of one disk image of the collection
+#[derive(Serialize)]
+struct JsonInventoryImage {
+  image: String,
+  size_bytes: u64,
+  irix_release: Option<String>,
+  /// Sidecars recording the image
+  manifests: Vec<String>,
+  /// Number of files extracted from the image
+  files: usize,
+  fixity: Fixity,
+  /// What is missing or changed
+  problems: Vec<String>,
+}
+
+/// JSON representation of a file found in one of the disk images
+#[derive(Clone, Eq, PartialEq, Ord, PartialOrd, Serialize)]
+struct JsonFileCopy {
+  image: String,
+  /// Path within the image's filesystem
+  src: String,
+}
+
+/// JSON representation of a file extracted from more than one disk image
+#[derive(Serialize)]
+struct JsonDuplicate {
+  /// Digest of the file's contents, as "algorithm:hex"
+  digest: String,
+  size_bytes: u64,
+  copies: Vec<JsonFileCopy>,
+}
+
+/// JSON representation of the collection as a whole
+#[derive(Serialize)]
+struct JsonInventorySummary {
+  images: usize,
+  manifests: usize,
+  files: usize,
+  /// Bytes in the files extracted
+  size_bytes: u64,
+  /// Number of images of each IRIX release, "unknown" if not recorded
+  irix_releases: BTreeMap<String, usize>,
+  /// Number of images with each fixity status
+  fixity: BTreeMap<String, usize>,
+  duplicate_files: usize,
+  /// Whether digests were recomputed rather than only presence and sizes checked
+  verified: bool,
+}
+
+/// JSON representation of a collection inventory
+#[derive(Serialize)]
+struct JsonInventory {
+  summary: JsonInventorySummary,
+  images: Vec<JsonInventoryImage>,
+  duplicates: Vec<JsonDuplicate>,
+}
+
+/// Inventory tool entry point: merge the fixity sidecars of many disk images into a report on the
+/// whole collection, with the IRIX releases held, files extracted from more than one image and
+/// whether the images and extracted files still match their sidecars
+pub(crate) fn subcommand(config: &Config, cli_matches: &ArgMatches) {
+  let verify = cli_matches.is_present("verify");
+  let manifest_names = manifest_file_names(cli_matches.values_of("manifests").into_iter().flatten());
+
+  // Sidecars of the same image, e.g. from several extractions, are merged
+  let mut sidecars: BTreeMap<String, Vec<(String, ManifestSidecar, )>> = BTreeMap::new();
+  for name in &manifest_names {
+    let sidecar = read_sidecar_or_quit(name);
+    sidecars.entry(sidecar.image.file.clone()).or_default().push((name.clone(), sidecar, ));
+  }
+
+  let mut images = Vec::with_capacity(sidecars.len());
+  let mut copies: BTreeMap<String, (u64, BTreeSet<JsonFileCopy>, )> = BTreeMap::new();
+  let mut size_bytes = 0;
+  for (image, records) in &sidecars {
+    let mut problems = Vec::new();
+    let mut fixity = check_image(&records[0].1.image, verify, &mut problems);
+    let mut files = BTreeSet::new();
+    for (_, sidecar, ) in records {
+      for file in &sidecar.files {
+        if !files.insert(&file.dest) {
+          continue;
+        }
+        size_bytes += file.size_bytes;
+        fixity = fixity.max(check_file(file, verify, &mut problems));
+        if let Some(digest) = digest_key(&file.hash) {
+          copies.entry(digest).or_insert_with(|| (file.size_bytes, BTreeSet::new(), )).1.insert(JsonFileCopy {
+            image: image.clone(),
+            src: file.src.clone(),
+          });
+        }
+      }
+    }
+    images.push(JsonInventoryImage {
+      image: image.clone(),
+      size_bytes: records[0].1.image.size_bytes,
+      irix_release: records.iter().find_map(|(_, s, )| s.image.irix_release.clone()),
+      manifests: records.iter().map(|(name, _, )| name.clone()).collect(),
+      files: files.len(),
+      fixity,
+      problems,
+    });
+  }
+
+  // Files are duplicates if the same contents were extracted from more than one image
+  let duplicates: Vec<JsonDuplicate> = copies.into_iter()
+    .filter(|(_, (_, copies, ), )| copies.iter().map(|c| &c.image).collect::<BTreeSet<_>>().len() > 1)
+    .map(|(digest, (size_bytes, copies, ), )| JsonDuplicate {
+      digest,
+      size_bytes,
+      copies: copies.into_iter().collect(),
+    })
+    .collect();
+
+  let mut irix_releases = BTreeMap::new();
+  let mut fixity_counts = BTreeMap::new();
+  for image in &images {
+    *irix_releases.entry(image.irix_release.clone().unwrap_or_else(|| UNKNOWN_RELEASE.to_string())).or_insert(0) += 1;
+    *fixity_counts.entry(format!("{:?}", image.fixity).to_lowercase()).or_insert(0) += 1;
+  }
+  let failed = images.iter().filter(|i| i.fixity != Fixity::Ok).count();
+  let total = images.len();
+  let inventory = JsonInventory {
+    summary: JsonInventorySummary {
+      images: images.len(),
+      manifests: manifest_names.len(),
+      files: images.iter().map(|i| i.files).sum(),
+      size_bytes,
+      irix_releases,
+      fixity: fixity_counts,
+      duplicate_files: duplicates.len(),
+      verified: verify,
+    },
+    images,
+    duplicates,
+  };
+
+  if config.json(cli_matches) {
+    crate::output::print_json("inventory", &inventory);
+  } else {
+    print_inventory(&inventory);
+  }
+  if let Some(exit_code) = crate::exit_codes::for_failures(failed, total) {
+    exit(exit_code);
+  }
+}
+
+/// Sidecar files named, expanding globs, or quit if a glob is invalid or matches nothing
+fn manifest_file_names<'a, I>(names: I) -> Vec<String>
+  where I: Iterator<Item = &'a str> {
+  let mut file_names = Vec::new();
+  for name in names {
+    let paths = match glob::glob_with(name, crate::GLOB_OPT) {
+      Ok(paths) => paths,
+      Err(e) => {
+        eprintln!("Error compiling glob pattern from '{}': {:?}", name, e);
+        exit(crate::exit_codes::GLOB_ERR);
+      }
+    };
+    let before = file_names.len();
+    file_names.extend(paths.filter_map(Result::ok).map(|path| path.to_string_lossy().to_string()));
+    if file_names.len() == before {
+      eprintln!("No sidecars match '{}'", name);
+      exit(crate::exit_codes::GLOB_ERR);
+    }
+  }
+  file_names
+}
+
+/// Read a fixity sidecar, or quit if it can't be read or isn't one
+fn read_sidecar_or_quit(file_name: &str) -> ManifestSidecar {
+  let sidecar = match fs::read_to_string(file_name) {
+    Ok(sidecar) => sidecar,
+    Err(e) => {
+      eprintln!("Error reading sidecar {}: {:?}", file_name, &e);
+      exit(crate::exit_codes::IO_ERR);
+    }
+  };
+  match serde_json::from_str::<ManifestSidecar>(&sidecar) {
+    Ok(sidecar) if sidecar.schema == SIDECAR_SCHEMA => sidecar,
+    Ok(sidecar) => {
+      eprintln!("{} has schema '{}', but this tool reads '{}'", file_name, sidecar.schema, SIDECAR_SCHEMA);
+      exit(crate::exit_codes::PARSE_ERR);
+    }
+    Err(e) => {
+      eprintln!("{} is not a fixity sidecar: {}", file_name, &e);
+      exit(crate::exit_codes::PARSE_ERR);
+    }
+  }
+}
+
+/// Digest identifying contents across sidecars, preferring SHA-256 as every sidecar written by
+/// default has it
+fn digest_key(hash: &MultiHashResult) -> Option<String> {
+  hash.clone().entries().into_iter().next()
+    .map(|(algorithm, digest, )| format!("{}:{}", algorithm.name(), digest))
+}
+
+/// Algorithms a sidecar recorded digests with
+fn algorithms(hash: &MultiHashResult) -> Vec<HashAlgorithm> {
+  hash.clone().entries().into_iter().map(|(algorithm, _, )| algorithm).collect()
+}
+
+/// Check a disk image is still there and, if verifying, that its digests still match
+fn check_image(image: &ManifestImage, verify: bool, problems: &mut Vec<String>) -> Fixity {
+  if !verify {
+    if let Err(e) = fs::metadata(&image.file) {
+      problems.push(format!("image: {:?}", e.kind()));
+      return Fixity::Missing;
+    }
+    return Fixity::Ok;
+  }
+  let mut vol = match crate::StreamVolume::open(&image.file) {
+    Ok(vol) => vol,
+    Err(e) => {
+      problems.push(format!("image: {}", e.message));
+      return Fixity::Missing;
+    }
+  };
+  match MultiHash::hash_reader(&mut vol.reader, &algorithms(&image.hash)) {
+    Ok((size_bytes, hash, )) if size_bytes == image.size_bytes && hash == image.hash => Fixity::Ok,
+    Ok(_) => {
+      problems.push("image: digest differs".to_string());
+      Fixity::Changed
+    }
+    Err(e) => {
+      problems.push(format!("image: {:?}", e.kind()));
+      Fixity::Missing
+    }
+  }
+}
+
+/// Check an extracted file is still there with its size and, if verifying, its digests
+fn check_file(file: &ManifestFile, verify: bool, problems: &mut Vec<String>) -> Fixity {
+  let result = if verify {
+    File::open(&file.dest).and_then(|mut f| MultiHash::hash_reader(&mut f, &algorithms(&file.hash)))
+      .map(|(size_bytes, hash, )| size_bytes == file.size_bytes && hash == file.hash)
+  } else {
+    fs::metadata(&file.dest).map(|meta| meta.len() == file.size_bytes)
+  };
+  match result {
+    Ok(true) => Fixity::Ok,
+    Ok(false) => {
+      problems.push(format!("{}: {} differs", file.dest, if verify { "digest" } else { "size" }));
+      Fixity::Changed
+    }
+    Err(e) => {
+      problems.push(format!("{}: {:?}", file.dest, e.kind()));
+      Fixity::Missing
+    }
+  }
+}
+
+/// Print an inventory as tables of releases, images and duplicate files
+fn print_inventory(inventory: &JsonInventory) {
+  #[derive(Tabled)]
+  struct DisplayRelease {
+    #[header("IRIX release")]
+    release: String,
+    #[header("Images")]
+    images: usize,
+  }
+
+  #[derive(Tabled)]
+  struct DisplayImage {
+    #[header("Image")]
+    image: String,
+    #[header("Release")]
+    release: String,
+    #[header("Files")]
+    files: usize,
+    #[header("Fixity")]
+    fixity: String,
+    #[header("Problems")]
+    problems: String,
+  }
+
+  #[derive(Tabled)]
+  struct DisplayDuplicate {
+    #[header("Digest")]
+    digest: String,
+    #[header("Size")]
+    size_bytes: u64,
+    #[header("Copies")]
+    copies: String,
+  }
+
+  let summary = &inventory.summary;
+  println!("{} {} disk images from {} sidecars, {} files extracted ({} bytes)", paint("Collection:", Style::Heading),
+           summary.images, summary.manifests, summary.files, summary.size_bytes);
+
+  let tab = summary.irix_releases.iter()
+    .map(|(release, images, )| DisplayRelease {
+      release: release.clone(),
+      images: *images,
+    })
+    .collect::<Vec<DisplayRelease>>();
+  println!("{}", Table::new(tab).with(crate::table_fmt()));
+
+  let styles = inventory.images.iter()
+    .map(|i| if i.fixity == Fixity::Ok { None } else { Some(Style::Error) })
+    .collect::<Vec<Option<Style>>>();
+  let tab = inventory.images.iter()
+    .map(|i| DisplayImage {
+      image: i.image.clone(),
+      release: i.irix_release.clone().unwrap_or_else(|| UNKNOWN_RELEASE.to_string()),
+      files: i.files,
+      fixity: format!("{:?}", i.fixity).to_lowercase(),
+      problems: i.problems.join("\n"),
+    })
+    .collect::<Vec<DisplayImage>>();
+  let table = Table::new(tab).with(crate::table_fmt()).to_string();
+  println!("{}", paint_table_rows(&table, &styles));
+
+  if inventory.duplicates.is_empty() {
+    println!("No files were extracted from more than one image");
+  } else {
+    println!("{}", paint(&format!("Files extracted from more than one image: {}", inventory.duplicates.len()), Style::Heading));
+    let tab = inventory.duplicates.iter()
+      .map(|d| DisplayDuplicate {
+        digest: d.digest.clone(),
+        size_bytes: d.size_bytes,
+        copies: d.copies.iter().map(|c| format!("{}:{}", c.image, c.src)).collect::<Vec<String>>().join("\n"),
+      })
+      .collect::<Vec<DisplayDuplicate>>();
+    println!("{}", Table::new(tab).with(crate::table_fmt()));
+  }
+
+  let checked = if summary.verified { "digests verified" } else { "presence and sizes checked; use --verify to recompute digests" };
+  let fixity = summary.fixity.iter().map(|(status, n, )| format!("{} {}", n, status)).collect::<Vec<String>>().join(", ");
+  println!("Fixity: {} ({})", fixity, checked);
+}
@@ -0,0 +1,89 @@
+use std::io;
+use std::io::{Read, Seek, SeekFrom};
+
+use clap::ArgMatches;
+use tracing::{debug, Level};
+
+/// Set up logging to stderr, with verbosity chosen by CLI flags: --quiet logs errors only, the
+/// default logs warnings, and each -v adds a level (info, debug, trace)
+pub(crate) fn init(cli_matches: &ArgMatches) {
+  let level = if cli_matches.is_present("quiet") {
+    Level::ERROR
+  } else {
+    match cli_matches.occurrences_of("verbose") {
+      0 => Level::WARN,
+      1 => Level::INFO,
+      2 => Level::DEBUG,
+      _ => Level::TRACE,
+    }
+  };
+
+  tracing_subscriber::fmt()
+    .with_max_level(level)
+    .with_writer(io::stderr)
+    .init();
+}
+
+/// Number of -v flags given, or zero if --quiet
+pub(crate) fn verbosity(cli_matches: &ArgMatches) -> u64 {
+  if cli_matches.is_present("quiet") {
+    0
+  } else {
+    cli_matches.occurrences_of("verbose")
+  }
+}
+
+/// Reader wrapper which logs every seek and read made on the disk image, so that it is possible to
+/// see where parsing of a problematic image goes wrong
+pub(crate) struct TracingReader<R> {
+  inner: R,
+  /// Current offset into the disk image
+  pos: u64,
+}
+
+impl<R> TracingReader<R> {
+  /// Wrap a reader positioned at the beginning of the disk image
+  pub(crate) fn new(inner: R) -> Self {
+    TracingReader {
+      inner,
+      pos: 0,
+    }
+  }
+
+  /// Get a reference to the wrapped reader
+  pub(crate) fn get_ref(&self) -> &R {
+    &self.inner
+  }
+}
+
+impl<R: Read> Read for TracingReader<R> {
+  fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+    match self.inner.read(buf) {
+      Ok(n) => {
+        debug!("Read {} of {} bytes at offset {}", n, buf.len(), self.pos);
+        self.pos += n as u64;
+        Ok(n)
+      }
+      Err(e) => {
+        debug!("Read of {} bytes at offset {} failed: {:?}", buf.len(), self.pos, &e);
+        Err(e)
+      }
+    }
+  }
+}
+
+impl<R: Seek> Seek for TracingReader<R> {
+  fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+    match self.inner.seek(pos) {
+      Ok(offset) => {
+        debug!("Seek {:?} from offset {} to offset {}", pos, self.pos, offset);
+        self.pos = offset;
+        Ok(offset)
+      }
+      Err(e) => {
+        debug!("Seek {:?} from offset {} failed: {:?}", pos, self.pos, &e);
+        Err(e)
+      }
+    }
+  }
+}
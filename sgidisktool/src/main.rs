@@ -1,13 +1,51 @@
 use std::fs;
 use std::fs::File;
-use std::io::{BufReader, Read, Seek, SeekFrom};
+use std::io;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
 use std::process::exit;
 
 use clap::{App, load_yaml};
 use glob::MatchOptions;
 use tabled::Style;
+use tracing::debug;
 
+use sgidisklib::pc::PcPartitionTable;
+use sgidisklib::diskcache::DiskCacheOptions;
+use sgidisklib::rescue::RescueOptions;
+
+use crate::color::Style as ColorStyle;
+use crate::exit_codes::CommandError;
+use crate::hash::{HashAlgorithm, MultiHash};
+use crate::image::DiskImage;
+use crate::logging::TracingReader;
+
+mod bagit;
+mod batch;
+mod bootcd;
+mod color;
+mod config;
+mod confirm;
+mod detect;
+mod device;
+mod doctor;
+mod entropy;
 mod exit_codes;
+mod image;
+mod inventory;
+mod logging;
+mod map;
+mod multi;
+mod optical;
+mod output;
+mod positional;
+mod validate;
+mod sidecar;
+mod sqlite;
+mod strings;
+mod summary;
+#[cfg(feature = "scripting")]
+mod script;
 mod hash;
 mod vh;
 mod efs;
@@ -19,21 +57,159 @@ pub(crate) const GLOB_OPT: MatchOptions = MatchOptions {
   require_literal_leading_dot: true,
 };
 
+/// Disk image file name which indicates reading the image from stdin
+pub(crate) const STDIN_FILE_NAME: &str = "-";
+
 /// Main sgidisktool CLI entry point
 fn main() {
   // Parse CLI arguments
   let cli_yaml = load_yaml!("cli.yaml");
   let cli_matches = App::from_yaml(cli_yaml).get_matches();
+  logging::init(&cli_matches);
+
+  // Load configuration defaults
+  let config = config::Config::load_or_quit(cli_matches.value_of("config"));
+  color::init(config.color(&cli_matches));
+  output::init(config.format(&cli_matches));
+  device::init(cli_matches.is_present("force-device"));
+  let cd_mode = match cli_matches.value_of("cd-mode") {
+    Some("raw") => Some(optical::SectorMode::Raw),
+    Some(_) => Some(optical::SectorMode::Cooked),
+    None => None
+  };
+  let cd_retries = match cli_matches.value_of("cd-retries").map(|retries| retries.parse::<u32>()) {
+    Some(Ok(retries)) => retries,
+    Some(Err(_)) => {
+      eprintln!("Invalid number of retries: {}", cli_matches.value_of("cd-retries").unwrap());
+      exit(exit_codes::CLI_ARG_ERROR);
+    }
+    None => optical::DEFAULT_RETRIES
+  };
+  optical::init(cd_mode, cd_retries);
+  if let Some(readahead) = cli_matches.value_of("readahead") {
+    match readahead.parse::<u64>() {
+      Ok(readahead_kib) => image::init(readahead_kib),
+      Err(_) => {
+        eprintln!("Invalid readahead size: {}", readahead);
+        exit(exit_codes::CLI_ARG_ERROR);
+      }
+    }
+  }
+  let rescue_retries = match cli_matches.value_of("retries").map(|retries| retries.parse::<u32>()) {
+    Some(Ok(retries)) => Some(retries),
+    Some(Err(_)) => {
+      eprintln!("Invalid number of retries: {}", cli_matches.value_of("retries").unwrap());
+      exit(exit_codes::CLI_ARG_ERROR);
+    }
+    None => None
+  };
+  let bad_block_map = cli_matches.value_of("bad-block-map").map(|path| path.to_string());
+  if rescue_retries.is_some() || cli_matches.is_present("skip-unreadable") || bad_block_map.is_some() {
+    let options = RescueOptions {
+      retries: rescue_retries.unwrap_or(RescueOptions::default().retries),
+      skip_unreadable: cli_matches.is_present("skip-unreadable"),
+      ..Default::default()
+    };
+    image::init_rescue(options, bad_block_map);
+  }
+  if let Some(cache_dir) = cli_matches.value_of("cache-dir") {
+    let max_bytes = match cli_matches.value_of("cache-size").map(|size| size.parse::<u64>()) {
+      Some(Ok(size_mib)) => size_mib * 1024 * 1024,
+      Some(Err(_)) => {
+        eprintln!("Invalid cache size: {}", cli_matches.value_of("cache-size").unwrap());
+        exit(exit_codes::CLI_ARG_ERROR);
+      }
+      None => DiskCacheOptions::default().max_bytes
+    };
+    image::init_disk_cache(PathBuf::from(cache_dir), DiskCacheOptions { max_bytes, ..Default::default() });
+  }
+  if let Some(offset) = cli_matches.value_of("offset") {
+    match offset.parse::<u64>() {
+      Ok(offset) => image::init_offset(offset),
+      Err(_) => {
+        eprintln!("Invalid offset: {}", offset);
+        exit(exit_codes::CLI_ARG_ERROR);
+      }
+    }
+  }
+
+  // Batch operations can name their own disk images
+  if let Some(batch_matches) = cli_matches.subcommand_matches("batch") {
+    batch::subcommand(&config, cli_matches.value_of("file"), batch_matches);
+    return;
+  }
+
+  // Inventories read sidecars rather than a disk image
+  if let Some(inventory_matches) = cli_matches.subcommand_matches("inventory") {
+    inventory::subcommand(&config, inventory_matches);
+    return;
+  }
+
+  // Several disk images, a glob of them or a list of them, are each processed in turn
+  if let Some(disk_file_names) = multi::disk_file_names(cli_matches.values_of("file"), cli_matches.value_of("files-from")) {
+    multi::subcommand(&config, &disk_file_names, cli_matches);
+    return;
+  }
+
+  // Detection can name its disk image as an argument
+  if let Some(detect_matches) = cli_matches.subcommand_matches("detect") {
+    match detect_matches.value_of("image").or_else(|| cli_matches.value_of("file")) {
+      Some(disk_file_name) => detect::subcommand(&config, disk_file_name, detect_matches),
+      None => {
+        eprintln!("A disk image must be given, as an argument or with -f/--file");
+        exit(exit_codes::CLI_ARG_ERROR);
+      }
+    }
+    return;
+  }
+
+  // Scripts can name their disk image as an argument
+  if let Some(script_matches) = cli_matches.subcommand_matches("script") {
+    match script_matches.value_of("image").or_else(|| cli_matches.value_of("file")) {
+      #[cfg(feature = "scripting")]
+      Some(disk_file_name) => script::subcommand(disk_file_name, script_matches),
+      #[cfg(not(feature = "scripting"))]
+      Some(_) => {
+        eprintln!("This build of sgidisktool can't run scripts; rebuild it with the scripting feature");
+        exit(exit_codes::CLI_ARG_ERROR);
+      }
+      None => {
+        eprintln!("A disk image must be given, as an argument or with -f/--file");
+        exit(exit_codes::CLI_ARG_ERROR);
+      }
+    }
+    return;
+  }
 
   // Open disk image
-  let disk_file_name = cli_matches.value_of("file").unwrap();
+  let disk_file_name = match cli_matches.value_of("file") {
+    Some(disk_file_name) => disk_file_name,
+    None => {
+      eprintln!("A disk image must be given with -f/--file");
+      exit(exit_codes::CLI_ARG_ERROR);
+    }
+  };
   match cli_matches.subcommand_name() {
     // Volume Header tool
-    Some("vh") => vh::subcommand(disk_file_name, cli_matches.subcommand_matches("vh").unwrap()),
+    Some("vh") => vh::subcommand(&config, disk_file_name, cli_matches.subcommand_matches("vh").unwrap()),
     // Hash tool
-    Some("hash") => hash::subcommand(disk_file_name, cli_matches.subcommand_matches("hash").unwrap()),
+    Some("hash") => hash::subcommand(&config, disk_file_name, cli_matches.subcommand_matches("hash").unwrap()),
+    // Overview of the whole image
+    Some("info") => summary::subcommand(&config, disk_file_name, cli_matches.subcommand_matches("info").unwrap()),
+    // Run every validator
+    Some("validate") => validate::subcommand(&config, disk_file_name, cli_matches.subcommand_matches("validate").unwrap()),
+    // Suggest remedies for the problems found
+    Some("doctor") => doctor::subcommand(&config, disk_file_name, cli_matches.subcommand_matches("doctor").unwrap()),
+    // Package disk image as a BagIt bag
+    Some("bag") => bagit::subcommand(&config, disk_file_name, cli_matches.subcommand_matches("bag").unwrap()),
+    // Printable strings in a region
+    Some("strings") => strings::subcommand(&config, disk_file_name, cli_matches.subcommand_matches("strings").unwrap()),
+    // Entropy of each window of a region
+    Some("entropy") => entropy::subcommand(&config, disk_file_name, cli_matches.subcommand_matches("entropy").unwrap()),
+    // Map of the regions of the image
+    Some("map") => map::subcommand(&config, disk_file_name, cli_matches.subcommand_matches("map").unwrap()),
     // Efs tool
-    Some("efs") => efs::subcommand(disk_file_name, cli_matches.subcommand_matches("efs").unwrap()),
+    Some("efs") => efs::subcommand(&config, disk_file_name, cli_matches.subcommand_matches("efs").unwrap()),
 
     // Unimplemented / unknown sub-command
     Some(subcommand_name) => {
@@ -53,47 +229,86 @@ fn main() {
 #[derive(Debug)]
 pub(crate) struct OpenVolume<'a> {
   pub(crate) disk_file_name: &'a str,
-  pub(crate) disk_file_meta: fs::Metadata,
-  pub(crate) disk_file: fs::File,
+  pub(crate) disk_file: TracingReader<DiskImage>,
   pub(crate) volume_header: sgidisklib::volhdr::SgidiskVolume,
 }
 
 impl<'a> OpenVolume<'a> {
-  /// Open a disk image and read the Volume Header
-  pub(crate) fn open(disk_file_name: &'a str) -> Result<Self, String> {
-    // Read metadata of file
-    let disk_file_meta = match fs::metadata(disk_file_name) {
-      Ok(disk_file_meta) => disk_file_meta,
-      Err(e) => return Err(format!("Unable to get file metadata for disk image '{}': {:?}", disk_file_name, &e))
+  /// Open a disk image (raw, or an EWF container) and read the Volume Header. If there is no
+  /// Volume Header at the start but a PC partition table (MBR or GPT) whose partition holds one,
+  /// that partition is read instead, unless an offset was given.
+  pub(crate) fn open(disk_file_name: &'a str) -> Result<Self, CommandError> {
+    // Random access is not possible on a stream
+    if disk_file_name == STDIN_FILE_NAME {
+      return Err(CommandError::new(exit_codes::CLI_ARG_ERROR, "This sub-command needs to seek within the disk image, so it can't be read from stdin".to_string()));
+    }
+
+    // Read metadata of file; devices may not have any
+    let disk_file_sz = if device::is_device_path(disk_file_name) {
+      None
+    } else {
+      match fs::metadata(disk_file_name) {
+        Ok(disk_file_meta) => Some(disk_file_meta.len()),
+        Err(e) => return Err(CommandError::new(exit_codes::VH_OPEN_ERR, format!("Unable to get file metadata for disk image '{}': {:?}", disk_file_name, &e)))
+      }
     };
 
     // Open file
-    let mut disk_file = match fs::File::open(disk_file_name) {
-      Ok(disk_file) => disk_file,
-      Err(e) => return Err(format!("Unable to open disk image '{}': {:?}", disk_file_name, &e))
-    };
+    let offset = image::offset();
+    let mut disk_file = Self::open_image(disk_file_name, offset)?;
+    debug!("Opened disk image '{}' ({:?} bytes, {:?} bytes of media, from offset {})", disk_file_name, disk_file_sz, disk_file.get_ref().len().ok(), offset);
 
-    // Read volume header
+    // Read volume header, or find it in a PC partition
     let volume_header = match sgidisklib::volhdr::SgidiskVolume::read(&mut disk_file) {
       Ok(volume_header) => volume_header,
-      Err(e) => return Err(format!("Unable to read Volume Header from disk image '{}': {:?}", disk_file_name, &e))
+      Err(e) => {
+        let message = format!("Unable to read Volume Header from disk image '{}': {:?}", disk_file_name, &e);
+        let table = match offset {
+          0 => PcPartitionTable::read(&mut disk_file).ok().flatten(),
+          _ => None
+        };
+        let (table, nested_offset, ) = match table {
+          Some(table) => match table.volume_header_offset() {
+            Some(nested_offset) => (table, nested_offset, ),
+            None => return Err(CommandError::new(exit_codes::VH_OPEN_ERR, format!(
+              "Disk image '{}' starts with a PC partition table ({}), not an SGI Volume Header, and none of its {} partitions holds one",
+              disk_file_name, table.scheme.name(), table.partitions.len())))
+          },
+          None => return Err(CommandError::new(exit_codes::for_lib_error(&e), message))
+        };
+        eprintln!("{} disk image '{}' starts with a PC partition table ({}); reading the SGI disk in its partition at offset {} (give --offset {} to skip this check)",
+                  color::paint_err("Warning:", ColorStyle::Warning), disk_file_name, table.scheme.name(), nested_offset, nested_offset);
+        disk_file = Self::open_image(disk_file_name, nested_offset)?;
+        match sgidisklib::volhdr::SgidiskVolume::read(&mut disk_file) {
+          Ok(volume_header) => volume_header,
+          Err(e) => return Err(CommandError::new(exit_codes::for_lib_error(&e), format!("Unable to read Volume Header from disk image '{}' at offset {}: {:?}", disk_file_name, nested_offset, &e)))
+        }
+      }
     };
+    log_volume_header(&volume_header);
 
     Ok(Self {
       disk_file_name,
-      disk_file_meta,
       disk_file,
       volume_header,
     })
   }
 
+  /// Open a disk image for random access from an offset
+  fn open_image(disk_file_name: &str, offset: u64) -> Result<TracingReader<DiskImage>, CommandError> {
+    match DiskImage::open(disk_file_name) {
+      Ok(disk_file) => Ok(TracingReader::new(disk_file.with_rescue().with_disk_cache().with_offset(offset).with_readahead())),
+      Err(message) => Err(CommandError::new(exit_codes::VH_OPEN_ERR, message))
+    }
+  }
+
   /// Open a disk image and read the Volume Header, or quit if there is an error
   pub(crate) fn open_or_quit(disk_file_name: &'a str) -> Self {
     let vol = match Self::open(disk_file_name) {
       Ok(vol) => vol,
       Err(e) => {
-        eprintln!("Error: {}", &e);
-        exit(crate::exit_codes::VH_OPEN_ERR);
+        eprintln!("{} {}", color::paint_err("Error:", ColorStyle::Error), &e.message);
+        exit(e.exit_code);
       }
     };
 
@@ -101,30 +316,144 @@ impl<'a> OpenVolume<'a> {
   }
 }
 
-/// Standard table formatting
-pub(crate) fn table_fmt() -> Style {
-  Style::pseudo_clean()
+/// Disk image opened for sequential reading only, which may be a non-seekable stream such as stdin
+pub(crate) struct StreamVolume {
+  /// Size of disk image in bytes, if known without reading the whole stream
+  disk_file_sz: Option<u64>,
+  /// Reader over the whole disk image, starting from its first byte
+  pub(crate) reader: Box<dyn Read>,
+  pub(crate) volume_header: sgidisklib::volhdr::SgidiskVolume,
 }
 
-/// Copy one section of a File to another File
-pub(crate) fn cp(src: &mut File, src_start: u64, src_len: u64, dst: &mut File, dst_start: u64) -> Result<(), std::io::Error> {
-  // Seek to start of read
-  if let Err(e) = src.seek(SeekFrom::Start(src_start)) {
-    eprintln!("cp: Error seeking to beginning of src read: {:?}", &e);
-    return Err(e);
+impl StreamVolume {
+  /// Open a disk image (or stdin) for streaming and read the Volume Header. The Volume Header
+  /// sector is buffered and replayed in front of the rest of the stream, so the reader still
+  /// covers the whole image, from the offset if one was given. A stream can't be searched for a
+  /// Volume Header in a PC partition, so one starting with an MBR needs an offset.
+  pub(crate) fn open(disk_file_name: &str) -> Result<Self, CommandError> {
+    let is_stdin = disk_file_name == STDIN_FILE_NAME;
+
+    // Size of a regular file is known up front
+    let mut disk_file_sz = if is_stdin || device::is_device_path(disk_file_name) {
+      None
+    } else {
+      match fs::metadata(disk_file_name) {
+        Ok(disk_file_meta) => Some(disk_file_meta.len()),
+        Err(e) => return Err(CommandError::new(exit_codes::VH_OPEN_ERR, format!("Unable to get file metadata for disk image '{}': {:?}", disk_file_name, &e)))
+      }
+    };
+
+    // Open stream; an EWF container streams its media, whose size is known from the container, and
+    // a device's size is asked of the device
+    let mut stream: Box<dyn Read> = if is_stdin {
+      Box::new(io::stdin())
+    } else {
+      match DiskImage::open(disk_file_name) {
+        Ok(disk_file) if image::rescuing() => {
+          disk_file_sz = disk_file.len().ok().or(disk_file_sz);
+          Box::new(disk_file.with_rescue())
+        }
+        Ok(DiskImage::Ewf(ewf)) => {
+          disk_file_sz = Some(ewf.size());
+          Box::new(ewf)
+        }
+        Ok(DiskImage::Device(device)) => {
+          disk_file_sz = Some(device.size());
+          Box::new(device)
+        }
+        Ok(DiskImage::Optical(drive)) => {
+          disk_file_sz = Some(drive.size());
+          Box::new(drive)
+        }
+        Ok(disk_file) => Box::new(disk_file),
+        Err(message) => return Err(CommandError::new(exit_codes::VH_OPEN_ERR, message))
+      }
+    };
+    debug!("Opened disk image '{}' for streaming ({:?} bytes)", disk_file_name, disk_file_sz);
+
+    // Skip to the offset
+    let offset = image::offset();
+    if offset > 0 {
+      match io::copy(&mut (&mut stream).take(offset), &mut io::sink()) {
+        Ok(skipped) if skipped == offset => disk_file_sz = disk_file_sz.map(|sz| sz.saturating_sub(offset)),
+        Ok(_) => return Err(CommandError::new(exit_codes::VH_OPEN_ERR, format!("Disk image '{}' is shorter than the offset {}", disk_file_name, offset))),
+        Err(e) => return Err(CommandError::new(exit_codes::IO_ERR, format!("Unable to read disk image '{}': {:?}", disk_file_name, &e)))
+      }
+    }
+
+    // Buffer and read volume header
+    let mut header_buf = vec![0; sgidisklib::volhdr::SgidiskVolume::SIZE];
+    if let Err(e) = stream.read_exact(&mut header_buf) {
+      return Err(CommandError::new(exit_codes::IO_ERR, format!("Unable to read Volume Header from disk image '{}': {:?}", disk_file_name, &e)));
+    }
+    let volume_header = match sgidisklib::volhdr::SgidiskVolume::read(&mut header_buf.as_slice()) {
+      Ok(volume_header) => volume_header,
+      Err(_) if offset == 0 && sgidisklib::pc::is_mbr(&header_buf) => return Err(CommandError::new(exit_codes::VH_OPEN_ERR, format!(
+        "Disk image '{}' starts with a PC partition table (MBR or GPT), not an SGI Volume Header; run detect on it to find the offset of an SGI disk in its partitions, and give it with --offset",
+        disk_file_name))),
+      Err(e) => return Err(CommandError::new(exit_codes::for_lib_error(&e), format!("Unable to read Volume Header from disk image '{}': {:?}", disk_file_name, &e)))
+    };
+    log_volume_header(&volume_header);
+
+    Ok(Self {
+      disk_file_sz,
+      reader: Box::new(TracingReader::new(io::Cursor::new(header_buf).chain(stream))),
+      volume_header,
+    })
   }
-  // Seek to start of write
-  if let Err(e) = dst.seek(SeekFrom::Start(dst_start)) {
-    eprintln!("cp: Error seeking to beginning of dst write: {:?}", &e);
-    return Err(e);
+
+  /// Open a disk image for streaming and read the Volume Header, or quit if there is an error
+  pub(crate) fn open_or_quit(disk_file_name: &str) -> Self {
+    match Self::open(disk_file_name) {
+      Ok(vol) => vol,
+      Err(e) => {
+        eprintln!("{} {}", color::paint_err("Error:", ColorStyle::Error), &e.message);
+        exit(e.exit_code);
+      }
+    }
   }
 
-  // Use BufReader to stream copy
-  let mut read = BufReader::new(src).take(src_len);
-  if let Err(e) = std::io::copy(&mut read, dst) {
-    eprintln!("cp: Error copying from src to dst: {:?}", &e);
-    return Err(e);
+  /// Size of the disk image in bytes. If the size is not known (i.e. reading from stdin), the
+  /// rest of the stream is consumed to count it.
+  pub(crate) fn disk_file_sz(&mut self) -> Result<u64, io::Error> {
+    if let Some(sz) = self.disk_file_sz {
+      return Ok(sz);
+    }
+
+    let sz = io::copy(&mut self.reader, &mut io::sink())?;
+    self.disk_file_sz = Some(sz);
+    Ok(sz)
   }
+}
 
-  Ok(())
-}
\ No newline at end of file
+/// Log the parsed contents of a Volume Header
+fn log_volume_header(vh: &sgidisklib::volhdr::SgidiskVolume) {
+  debug!("Volume Header: sector size {}, root partition {}, swap partition {}, boot file {:?}",
+         vh.sector_sz, vh.root_partition, vh.swap_partition, &vh.boot_file);
+  for (id, p, ) in vh.partitions.iter().enumerate().filter(|(_, p, )| p.in_use()) {
+    debug!("Partition {}: {} at block {} for {} blocks", id, p.partition_type, p.block_start, p.block_sz);
+  }
+  for f in vh.files.iter().filter(|f| f.in_use()) {
+    debug!("Volume file {:?} at block {} for {} bytes", &f.file_name, f.block_start, f.file_sz);
+  }
+}
+
+/// Standard table formatting
+pub(crate) fn table_fmt() -> Style {
+  Style::pseudo_clean()
+}
+
+/// Verify a copy of a section of a disk image by re-reading the source section and the destination file and
+/// comparing their hashes. Returns whether they matched.
+pub(crate) fn verify_copy<R>(src: &mut R, src_start: u64, src_len: u64, dst_path: &Path, algorithms: &[HashAlgorithm]) -> Result<bool, std::io::Error>
+  where R: Read + Seek {
+  // Hash source section
+  src.seek(SeekFrom::Start(src_start))?;
+  let (src_sz, src_hash, ) = MultiHash::hash_reader(&mut src.take(src_len), algorithms)?;
+
+  // Hash destination file
+  let mut dst = File::open(dst_path)?;
+  let (dst_sz, dst_hash, ) = MultiHash::hash_reader(&mut dst, algorithms)?;
+
+  Ok(src_sz == dst_sz && src_hash == dst_hash)
+}
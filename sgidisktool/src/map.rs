@@ -0,0 +1,167 @@
+use std::process::exit;
+
+use clap::ArgMatches;
+use serde::Serialize;
+
+use sgidisklib::efs::Efs;
+use sgidisklib::layout::{Region, RegionKind};
+use sgidisklib::volhdr::PartitionType;
+
+use crate::color::{paint, Style};
+use crate::config::Config;
+use crate::OpenVolume;
+
+/// Columns of the map, unless told otherwise
+const DEFAULT_WIDTH: usize = 64;
+
+/// JSON representation of the layout of a disk image
+#[derive(Serialize)]
+struct JsonMap {
+  image_bytes: u64,
+  regions: Vec<JsonRegion>,
+}
+
+/// JSON representation of one region
+#[derive(Serialize)]
+struct JsonRegion {
+  /// e.g. "volume-file" or "efs-free"
+  kind: &'static str,
+  label: String,
+  partition: Option<usize>,
+  start: u64,
+  end: u64,
+  size_bytes: u64,
+}
+
+/// Row of the map: regions shown on one line
+struct MapRow {
+  kind: RegionKind,
+  label: String,
+  ranges: Vec<std::ops::Range<u64>>,
+}
+
+/// Layout map entry point: what each byte range of the image holds, as a map or a list of regions
+pub(crate) fn subcommand(config: &Config, disk_file_name: &str, cli_matches: &ArgMatches) {
+  let format = config.format(cli_matches);
+  let width = match cli_matches.value_of("width").map(|w| w.parse::<usize>()) {
+    None => DEFAULT_WIDTH,
+    Some(Ok(w)) if w > 0 => w,
+    Some(_) => {
+      eprintln!("Invalid map width: {}", cli_matches.value_of("width").unwrap());
+      exit(crate::exit_codes::CLI_ARG_ERROR);
+    }
+  };
+
+  let mut vol = OpenVolume::open_or_quit(disk_file_name);
+  let image_bytes = match vol.disk_file.get_ref().len() {
+    Ok(len) => len,
+    Err(e) => {
+      eprintln!("Error while reading disk image: {:?}", &e);
+      exit(crate::exit_codes::IO_ERR);
+    }
+  };
+  let regions = regions(&mut vol, image_bytes);
+
+  if format.is_structured() {
+    crate::output::print_json("map", &JsonMap {
+      image_bytes,
+      regions: regions.iter()
+        .map(|r| JsonRegion {
+          kind: r.kind.name(),
+          label: r.label.clone(),
+          partition: r.partition,
+          start: r.range.start,
+          end: r.range.end,
+          size_bytes: r.range.end - r.range.start,
+        })
+        .collect(),
+    });
+  } else if format.is_tabular() {
+    let rows: Vec<Vec<String>> = regions.iter()
+      .map(|r| vec![r.kind.name().to_string(), r.label.clone(), r.partition.map(|p| p.to_string()).unwrap_or_default(),
+                    r.range.start.to_string(), r.range.end.to_string(), (r.range.end - r.range.start).to_string()])
+      .collect();
+    crate::output::print_table(format, &["kind", "label", "partition", "start", "end", "size_bytes"], &rows);
+  } else {
+    print_map(disk_file_name, &regions, image_bytes, width);
+  }
+}
+
+/// Regions of the Volume Header and partitions, and of each EFS which can be read. Filesystems
+/// whose bitmap can't be read are mapped without their data blocks.
+fn regions(vol: &mut OpenVolume, image_bytes: u64) -> Vec<Region> {
+  let mut regions = vol.volume_header.regions(image_bytes);
+  let sector_sz = vol.volume_header.sector_sz as u64;
+  let efs_partitions: Vec<(usize, u64, )> = vol.volume_header.partitions.iter().enumerate()
+    .filter(|(_, p, )| p.in_use() && p.partition_type == PartitionType::Efs)
+    .map(|(id, p, )| (id, p.byte_start(), ))
+    .collect();
+  for (id, start, ) in efs_partitions {
+    match Efs::read(&mut vol.disk_file, sector_sz, start) {
+      Ok(efs) => {
+        let bitmap = efs.read_bitmap(&mut vol.disk_file);
+        if let Err(e) = &bitmap {
+          eprintln!("Unable to read the free block bitmap of partition {}: {:?}", id, e);
+        }
+        regions.extend(efs.regions(bitmap.ok().as_ref(), id));
+      }
+      Err(e) => eprintln!("Unable to read EFS from partition {}: {:?}", id, &e)
+    }
+  }
+  regions
+}
+
+/// Print a row per region, or per kind of region within a filesystem, with a bar across the
+/// image: '#' where a column is wholly in the region and ':' where it is partly
+fn print_map(disk_file_name: &str, regions: &[Region], image_bytes: u64, width: usize) {
+  let column_bytes = ((image_bytes + width as u64 - 1) / width as u64).max(1);
+  println!("{}", paint(&format!("Map of '{}', {} bytes, {} bytes per column:", disk_file_name, image_bytes, column_bytes), Style::Heading));
+
+  let rows = map_rows(regions);
+  let label_width = rows.iter().map(|r| r.label.len()).max().unwrap_or(0);
+  for row in &rows {
+    let bar: String = (0..width as u64)
+      .map(|column| {
+        let cell = column * column_bytes..((column + 1) * column_bytes).min(image_bytes);
+        let covered: u64 = row.ranges.iter()
+          .map(|r| r.end.min(cell.end).saturating_sub(r.start.max(cell.start)))
+          .sum();
+        if cell.is_empty() || covered == 0 {
+          ' '
+        } else if covered >= cell.end - cell.start {
+          '#'
+        } else {
+          ':'
+        }
+      })
+      .collect();
+    let line = format!("{:<width$} |{}|", row.label, bar, width = label_width);
+    match row.kind {
+      RegionKind::Unpartitioned | RegionKind::Overlap => println!("{}", paint(&line, Style::Warning)),
+      _ => println!("{}", line)
+    }
+  }
+}
+
+/// Rows of the map. Each filesystem's inode tables, allocated and free blocks are a row each
+/// however many runs they have, as are the gaps between partitions.
+fn map_rows(regions: &[Region]) -> Vec<MapRow> {
+  let mut rows: Vec<MapRow> = Vec::new();
+  for region in regions {
+    let grouped_label = match (region.kind, region.partition, ) {
+      (RegionKind::EfsInodes, Some(id), ) => Some(format!("partition {} inodes", id)),
+      (RegionKind::EfsAllocated, Some(id), ) => Some(format!("partition {} allocated", id)),
+      (RegionKind::EfsFree, Some(id), ) => Some(format!("partition {} free", id)),
+      (RegionKind::Unpartitioned, _, ) => Some(region.label.clone()),
+      _ => None
+    };
+    match grouped_label {
+      Some(label) => match rows.iter_mut().find(|row| row.kind == region.kind && row.label == label) {
+        Some(row) => row.ranges.push(region.range.clone()),
+        None => rows.push(MapRow { kind: region.kind, label, ranges: vec![region.range.clone()] })
+      },
+      None => rows.push(MapRow { kind: region.kind, label: region.label.clone(), ranges: vec![region.range.clone()] })
+    }
+  }
+  rows
+}
@@ -0,0 +1,273 @@
+use std::fs;
+use std::io;
+use std::process::exit;
+
+use clap::{ArgMatches, Values};
+use serde::Serialize;
+use serde_json::Value;
+
+use crate::color::{paint, Style};
+use crate::config::Config;
+use crate::hash::{HashAlgorithm, HashOptions};
+use crate::output::OutputFormat;
+use crate::sqlite::Record;
+use crate::validate::{JsonValidation, Status};
+use crate::OpenVolume;
+
+/// How a command went on one disk image of several
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+enum Outcome {
+  Ok,
+  /// Suspicious, e.g. short volume files or no Volume Header found
+  Warn,
+  /// Validation failed
+  Fail,
+  /// The command couldn't be run on the image
+  Error,
+}
+
+/// JSON representation of the result for one disk image
+#[derive(Serialize)]
+struct JsonImageResult {
+  image: String,
+  outcome: Outcome,
+  result: Option<Value>,
+  error: Option<String>,
+}
+
+/// JSON representation of the outcomes across every disk image
+#[derive(Default, Serialize)]
+struct JsonImagesSummary {
+  images: usize,
+  ok: usize,
+  warn: usize,
+  fail: usize,
+  error: usize,
+}
+
+/// JSON representation of a command run on several disk images
+#[derive(Serialize)]
+struct JsonImages {
+  images: Vec<JsonImageResult>,
+  summary: JsonImagesSummary,
+}
+
+/// Disk images named by -f/--file and listed in a --files-from file, if there are several, a glob
+/// naming any number of them or a list, or quit if a glob is invalid or matches nothing
+pub(crate) fn disk_file_names(file_names: Option<Values>, files_from: Option<&str>) -> Option<Vec<String>> {
+  let file_names: Vec<&str> = file_names.into_iter().flatten().collect();
+  let is_glob = |name: &str| name != crate::STDIN_FILE_NAME && name.contains(['*', '?', '[']);
+  if files_from.is_none() && file_names.len() < 2 && !file_names.iter().any(|name| is_glob(name)) {
+    return None;
+  }
+
+  let mut disk_file_names = Vec::new();
+  for name in file_names {
+    if !is_glob(name) {
+      disk_file_names.push(name.to_string());
+      continue;
+    }
+    let paths = match glob::glob_with(name, crate::GLOB_OPT) {
+      Ok(paths) => paths,
+      Err(e) => {
+        eprintln!("Error compiling glob pattern from '{}': {:?}", name, e);
+        exit(crate::exit_codes::GLOB_ERR);
+      }
+    };
+    let before = disk_file_names.len();
+    disk_file_names.extend(paths.filter_map(Result::ok).map(|path| path.to_string_lossy().to_string()));
+    if disk_file_names.len() == before {
+      eprintln!("No disk images match '{}'", name);
+      exit(crate::exit_codes::GLOB_ERR);
+    }
+  }
+  if let Some(list_file_name) = files_from {
+    disk_file_names.extend(read_list_or_quit(list_file_name));
+  }
+  if files_from == Some(crate::STDIN_FILE_NAME) && disk_file_names.iter().any(|name| name == crate::STDIN_FILE_NAME) {
+    eprintln!("Disk images can't be read from stdin when it lists them");
+    exit(crate::exit_codes::CLI_ARG_ERROR);
+  }
+  Some(disk_file_names)
+}
+
+/// Disk images listed one per line in a file, or stdin if it's -, skipping blank lines, or quit
+/// if it can't be read or lists none
+fn read_list_or_quit(list_file_name: &str) -> Vec<String> {
+  let list = if list_file_name == crate::STDIN_FILE_NAME {
+    io::read_to_string(io::stdin())
+  } else {
+    fs::read_to_string(list_file_name)
+  };
+  let list = match list {
+    Ok(list) => list,
+    Err(e) => {
+      eprintln!("Error reading list of disk images {}: {:?}", list_file_name, &e);
+      exit(crate::exit_codes::IO_ERR);
+    }
+  };
+  let disk_file_names: Vec<String> = list.lines()
+    .filter(|line| !line.trim().is_empty())
+    .map(|line| line.to_string())
+    .collect();
+  if disk_file_names.is_empty() {
+    eprintln!("No disk images listed in {}", list_file_name);
+    exit(crate::exit_codes::CLI_ARG_ERROR);
+  }
+  disk_file_names
+}
+
+/// Run a sub-command on each of several disk images, with a section of output per image followed
+/// by a summary of how it went on all of them
+pub(crate) fn subcommand(config: &Config, disk_file_names: &[String], cli_matches: &ArgMatches) {
+  let (name, sub_matches, ) = cli_matches.subcommand();
+  let sub_matches = match sub_matches {
+    Some(sub_matches) => sub_matches,
+    None => {
+      eprintln!("Unimplemented CLI combination: {:?}", &cli_matches);
+      exit(crate::exit_codes::CLI_ARG_ERROR);
+    }
+  };
+  let format = config.format(sub_matches);
+  if format.is_tabular() {
+    eprintln!("CSV and porcelain output can't hold several disk images; use text, JSON or YAML");
+    exit(crate::exit_codes::CLI_ARG_ERROR);
+  }
+  let text = format == OutputFormat::Text;
+
+  let hash_options = match name {
+    "hash" if sub_matches.is_present("compare") || sub_matches.is_present("output") => {
+      eprintln!("--compare and --output take a single disk image");
+      exit(crate::exit_codes::CLI_ARG_ERROR);
+    }
+    "hash" => Some(crate::hash::hash_options(config, sub_matches)),
+    "detect" if sub_matches.is_present("image") => {
+      eprintln!("Give several disk images with -f/--file only");
+      exit(crate::exit_codes::CLI_ARG_ERROR);
+    }
+    "detect" | "validate" => None,
+    _ => {
+      eprintln!("Only detect, validate and hash can be run on several disk images at once");
+      exit(crate::exit_codes::CLI_ARG_ERROR);
+    }
+  };
+
+  let mut results = Vec::new();
+  let mut summary = JsonImagesSummary::default();
+  for disk_file_name in disk_file_names {
+    if text {
+      println!("{}", paint(&format!("==> {} <==", disk_file_name), Style::Heading));
+    }
+    let run = match &hash_options {
+      Some((algorithms, opts, )) => run_hash(disk_file_name, algorithms, opts, text, sub_matches),
+      None if name == "detect" => run_detect(disk_file_name, text),
+      None => run_validate(disk_file_name, text, sub_matches)
+    };
+    let (outcome, result, error, ) = match run {
+      Ok((outcome, result, )) => (outcome, result, None, ),
+      Err(message) => (Outcome::Error, None, Some(message), )
+    };
+    if let (true, Some(message), ) = (text, &error, ) {
+      println!("{}", paint(&format!("Error: {}", message), Style::Error));
+    }
+    if text {
+      println!();
+    }
+
+    summary.images += 1;
+    match outcome {
+      Outcome::Ok => summary.ok += 1,
+      Outcome::Warn => summary.warn += 1,
+      Outcome::Fail => summary.fail += 1,
+      Outcome::Error => summary.error += 1
+    }
+    results.push(JsonImageResult {
+      image: disk_file_name.clone(),
+      outcome,
+      result,
+      error,
+    });
+  }
+
+  if text {
+    println!("{} {} disk images: {} ok, {} warn, {} fail, {} error", paint("Summary:", Style::Heading),
+             summary.images, summary.ok, summary.warn, summary.fail, summary.error);
+  }
+  let failed = summary.fail + summary.error + if config.strict(sub_matches) { summary.warn } else { 0 };
+  let exit_code = crate::exit_codes::for_failures(failed, summary.images);
+  if !text {
+    crate::output::print_json(name, &JsonImages {
+      images: results,
+      summary,
+    });
+  }
+  if let Some(exit_code) = exit_code {
+    exit(exit_code);
+  }
+}
+
+/// Detect what a disk image holds, as `detect`. An image without a Volume Header is a warning.
+fn run_detect(disk_file_name: &str, text: bool) -> Result<(Outcome, Option<Value>, ), String> {
+  let detected = crate::detect::detect(disk_file_name)
+    .map_err(|e| format!("Error while reading disk image: {:?}", &e))?;
+  let outcome = if detected.supported && detected.volume_header.is_some() { Outcome::Ok } else { Outcome::Warn };
+  if text {
+    crate::detect::print_detected(&detected);
+    return Ok((outcome, None, ));
+  }
+  Ok((outcome, Some(to_value(detected)?), ))
+}
+
+/// Run every validator over a disk image, as `validate`
+fn run_validate(disk_file_name: &str, text: bool, cli_matches: &ArgMatches) -> Result<(Outcome, Option<Value>, ), String> {
+  let mut vol = OpenVolume::open(disk_file_name).map_err(|e| e.message)?;
+  let file_sz = vol.disk_file.get_ref().len()
+    .map_err(|e| format!("Error while reading disk image: {:?}", &e))?;
+  let mut report = crate::validate::run_checks(&mut vol, file_sz);
+  let install_cd = match cli_matches.is_present("install-cd") {
+    true => Some(crate::bootcd::run_checks(&mut vol, &mut report)),
+    false => None
+  };
+  crate::sqlite::record_or_quit(cli_matches, "validate", disk_file_name, Record::Findings(&report.checks));
+  let status = report.status();
+  let outcome = match status {
+    Status::Pass => Outcome::Ok,
+    Status::Warn => Outcome::Warn,
+    Status::Fail => Outcome::Fail
+  };
+  if text {
+    crate::validate::print_report(report.checks, status);
+    if let Some(verdict) = &install_cd {
+      crate::bootcd::print_verdict(verdict);
+    }
+    return Ok((outcome, None, ));
+  }
+  Ok((outcome, Some(to_value(JsonValidation {
+    status,
+    checks: report.checks,
+    install_cd,
+  })?), ))
+}
+
+/// Hash a disk image, its volume files and volumes, as `hash`. Short volume files or volumes are
+/// a warning.
+fn run_hash(disk_file_name: &str, algorithms: &[HashAlgorithm], opts: &HashOptions, text: bool, cli_matches: &ArgMatches) -> Result<(Outcome, Option<Value>, ), String> {
+  let mut vol = crate::StreamVolume::open(disk_file_name).map_err(|e| e.message)?;
+  let hashes = crate::hash::hash_volume(&mut vol.reader, &vol.volume_header, algorithms, opts)
+    .map_err(|e| format!("Error while reading disk image: {:?}", &e))?;
+  crate::sqlite::record_or_quit(cli_matches, "hash", disk_file_name, Record::Hashes(&hashes.rows()));
+  let outcome = if hashes.short_count() > 0 { Outcome::Warn } else { Outcome::Ok };
+  if text {
+    if let Err(e) = crate::hash::write_report(hashes, None, OutputFormat::Text, &mut io::stdout()) {
+      crate::output::quit_write_error(&e);
+    }
+    return Ok((outcome, None, ));
+  }
+  Ok((outcome, Some(to_value(hashes.into_json())?), ))
+}
+
+/// Convert a result to a JSON value
+fn to_value<T: Serialize>(result: T) -> Result<Value, String> {
+  serde_json::to_value(result).map_err(|e| format!("{:?}", &e))
+}
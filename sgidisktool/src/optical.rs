@@ -0,0 +1,241 @@
+//! Reading IRIX CDs directly from a CD-ROM drive, either cooked (the drive returns the 2048 byte
+//! user data of each sector) or raw (whole 2352 byte sectors, from which the user data is taken).
+//! Reads which fail are retried, as a scratched or dirty disc often reads on a later attempt.
+
+use std::fs::File;
+use std::io;
+use std::io::{Read, Seek, SeekFrom};
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::thread;
+use std::time::Duration;
+
+use tracing::warn;
+
+/// Whether --cd-mode was given, so that devices are read as optical drives
+static OPTICAL: AtomicBool = AtomicBool::new(false);
+/// Whether sectors are read raw rather than cooked
+static RAW_MODE: AtomicBool = AtomicBool::new(false);
+/// Number of times a failed read is retried
+static RETRIES: AtomicU32 = AtomicU32::new(DEFAULT_RETRIES);
+
+/// Default number of times a failed read is retried
+pub(crate) const DEFAULT_RETRIES: u32 = 3;
+/// Size of the user data of a sector
+const SECTOR_SZ: u64 = 2048;
+/// Size of a raw sector
+const RAW_SECTOR_SZ: usize = 2352;
+/// Number of sectors read at once
+const READ_SECTORS: u64 = 32;
+/// Wait before retrying a failed read, giving the drive time to recalibrate
+const RETRY_DELAY: Duration = Duration::from_millis(250);
+
+/// How sectors are read from an optical drive
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub(crate) enum SectorMode {
+  /// The drive returns the user data of each sector
+  Cooked,
+  /// The drive returns whole raw sectors, including their headers
+  Raw,
+}
+
+/// CD-ROM drive opened read-only, reading the user data of its sectors
+#[derive(Debug)]
+pub(crate) struct OpticalDrive {
+  file: File,
+  mode: SectorMode,
+  retries: u32,
+  /// Size of the user data on the disc, in bytes
+  size: u64,
+  /// Position within the user data
+  pos: u64,
+  /// Number of the first sector last read, and the user data of the sectors read
+  chunk: Option<(u64, Vec<u8>, )>,
+}
+
+/// Set up reading devices as optical drives in a sector mode (if given), retrying failed reads
+pub(crate) fn init(mode: Option<SectorMode>, retries: u32) {
+  OPTICAL.store(mode.is_some(), Ordering::Relaxed);
+  RAW_MODE.store(mode == Some(SectorMode::Raw), Ordering::Relaxed);
+  RETRIES.store(retries, Ordering::Relaxed);
+}
+
+/// Whether a device is an optical drive: if --cd-mode was given, or the drive says so
+pub(crate) fn is_optical_drive(path: &str) -> bool {
+  OPTICAL.load(Ordering::Relaxed) || reports_optical(path)
+}
+
+/// Whether a device answers a CD-ROM drive capability request
+#[cfg(target_os = "linux")]
+fn reports_optical(path: &str) -> bool {
+  use std::os::unix::io::AsRawFd;
+  /// ioctl getting the capabilities of a CD-ROM drive
+  const CDROM_GET_CAPABILITY: libc::c_ulong = 0x5331;
+  match File::open(path) {
+    Ok(file) => unsafe { libc::ioctl(file.as_raw_fd(), CDROM_GET_CAPABILITY as _) >= 0 },
+    Err(_) => false
+  }
+}
+
+/// Whether a device is named as a CD-ROM drive, e.g. \\.\CdRom0
+#[cfg(windows)]
+fn reports_optical(path: &str) -> bool {
+  path.to_lowercase().starts_with(r"\\.\cdrom")
+}
+
+/// Optical drives can't be recognised on other systems without --cd-mode
+#[cfg(not(any(target_os = "linux", windows)))]
+fn reports_optical(_path: &str) -> bool {
+  false
+}
+
+impl OpticalDrive {
+  /// Open a CD-ROM drive read-only, in the sector mode and with the retries set up
+  pub(crate) fn open(path: &str) -> io::Result<Self> {
+    let mut file = File::open(path)?;
+    let size = match crate::device::media_size(&file) {
+      Some(size) => size,
+      None => file.seek(SeekFrom::End(0))?
+    };
+    let mode = if RAW_MODE.load(Ordering::Relaxed) { SectorMode::Raw } else { SectorMode::Cooked };
+    Ok(OpticalDrive {
+      file,
+      mode,
+      retries: RETRIES.load(Ordering::Relaxed),
+      size,
+      pos: 0,
+      chunk: None,
+    })
+  }
+
+  /// Sector mode the drive is read in
+  pub(crate) fn mode(&self) -> SectorMode {
+    self.mode
+  }
+
+  /// Size of the user data on the disc, in bytes
+  pub(crate) fn size(&self) -> u64 {
+    self.size
+  }
+
+  /// Read the user data of sectors starting from one, retrying if the read fails
+  fn read_chunk(&mut self, first: u64) -> io::Result<()> {
+    let sectors = READ_SECTORS.min((self.size + SECTOR_SZ - 1) / SECTOR_SZ - first);
+    let mut attempt = 0;
+    loop {
+      match self.read_sectors(first, sectors) {
+        Ok(data) => {
+          self.chunk = Some((first, data, ));
+          return Ok(());
+        }
+        Err(e) if attempt < self.retries => {
+          attempt += 1;
+          warn!("Error reading sectors {}..{}, retrying ({} of {}): {:?}", first, first + sectors, attempt, self.retries, &e);
+          thread::sleep(RETRY_DELAY);
+        }
+        Err(e) => return Err(io::Error::new(e.kind(), format!("Unable to read sectors {}..{} after {} attempts: {}", first, first + sectors, attempt + 1, &e)))
+      }
+    }
+  }
+
+  /// Read the user data of sectors starting from one, once
+  fn read_sectors(&self, first: u64, sectors: u64) -> io::Result<Vec<u8>> {
+    let mut data = vec![0u8; (sectors * SECTOR_SZ) as usize];
+    match self.mode {
+      SectorMode::Cooked => {
+        let mut filled = 0;
+        while filled < data.len() {
+          match crate::positional::read_at(&self.file, &mut data[filled..], first * SECTOR_SZ + filled as u64)? {
+            0 => break,
+            n => filled += n
+          }
+        }
+        data.truncate(filled);
+      }
+      SectorMode::Raw => {
+        for (sector, user_data, ) in (first..first + sectors).zip(data.chunks_mut(SECTOR_SZ as usize)) {
+          let raw = read_raw_sector(&self.file, sector)?;
+          user_data.copy_from_slice(raw_user_data(&raw, sector)?);
+        }
+      }
+    }
+    Ok(data)
+  }
+}
+
+impl Read for OpticalDrive {
+  fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+    if self.pos >= self.size || buf.is_empty() {
+      return Ok(0);
+    }
+    let sector = self.pos / SECTOR_SZ;
+    let cached = match &self.chunk {
+      Some((first, data, )) => sector >= *first && self.pos < first * SECTOR_SZ + data.len() as u64,
+      None => false
+    };
+    if !cached {
+      self.read_chunk(sector)?;
+    }
+
+    let (first, data, ) = self.chunk.as_ref().unwrap();
+    let offset = (self.pos - first * SECTOR_SZ) as usize;
+    if offset >= data.len() {
+      return Ok(0);
+    }
+    let n = buf.len().min(data.len() - offset);
+    buf[..n].copy_from_slice(&data[offset..offset + n]);
+    self.pos += n as u64;
+    Ok(n)
+  }
+}
+
+impl Seek for OpticalDrive {
+  fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+    let new_pos = match pos {
+      SeekFrom::Start(offset) => Some(offset),
+      SeekFrom::Current(delta) => crate::positional::offset_by(self.pos, delta),
+      SeekFrom::End(delta) => crate::positional::offset_by(self.size, delta),
+    };
+    match new_pos {
+      Some(offset) => {
+        self.pos = offset;
+        Ok(offset)
+      }
+      None => Err(io::Error::new(io::ErrorKind::InvalidInput, "Invalid seek to a negative or overflowing position"))
+    }
+  }
+}
+
+/// User data of a raw sector: after the header of a mode 1 sector, or the subheader of a mode 2
+/// form 1 sector
+fn raw_user_data(raw: &[u8; RAW_SECTOR_SZ], sector: u64) -> io::Result<&[u8]> {
+  let offset = match raw[15] {
+    1 => 16,
+    2 => 24,
+    mode => return Err(io::Error::new(io::ErrorKind::InvalidData, format!("Sector {} is not a data sector (mode {})", sector, mode)))
+  };
+  Ok(&raw[offset..offset + SECTOR_SZ as usize])
+}
+
+/// Read a whole raw sector, addressed by its logical block address
+#[cfg(target_os = "linux")]
+fn read_raw_sector(file: &File, sector: u64) -> io::Result<[u8; RAW_SECTOR_SZ]> {
+  use std::os::unix::io::AsRawFd;
+  /// ioctl reading a raw sector, addressed by minute, second and frame at the start of the buffer
+  const CDROMREADRAW: libc::c_ulong = 0x5314;
+  // Logical block 0 is 2 seconds (150 frames of 75 per second) into the disc
+  let frames = sector + 150;
+  let mut raw = [0u8; RAW_SECTOR_SZ];
+  raw[0] = (frames / (60 * 75)) as u8;
+  raw[1] = ((frames / 75) % 60) as u8;
+  raw[2] = (frames % 75) as u8;
+  match unsafe { libc::ioctl(file.as_raw_fd(), CDROMREADRAW as _, raw.as_mut_ptr()) } {
+    -1 => Err(io::Error::last_os_error()),
+    _ => Ok(raw)
+  }
+}
+
+/// Raw sectors can only be read on Linux
+#[cfg(not(target_os = "linux"))]
+fn read_raw_sector(_file: &File, _sector: u64) -> io::Result<[u8; RAW_SECTOR_SZ]> {
+  Err(io::Error::new(io::ErrorKind::Unsupported, "Reading raw sectors from an optical drive is only supported on Linux; use --cd-mode cooked"))
+}
@@ -0,0 +1,308 @@
+use std::fs;
+use std::fs::File;
+use std::io;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::process::exit;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use chrono::{DateTime, Local};
+use clap::ArgMatches;
+use chrono::format::{Item, StrftimeItems};
+use serde::Serialize;
+use serde_json;
+use serde_yaml;
+
+/// Version of the structure of JSON and YAML output: the envelope, sub-command results, batch
+/// results and JSON manifests. Fields may be added within a version; removing or renaming a
+/// field, or changing its type or meaning, bumps the version.
+pub(crate) const JSON_SCHEMA_VERSION: u32 = 1;
+
+/// Whether structured output is written as YAML rather than JSON
+static YAML: AtomicBool = AtomicBool::new(false);
+
+/// Format of sub-command output
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub(crate) enum OutputFormat {
+  /// Human readable text and tables
+  Text,
+  /// JSON, in the standard envelope
+  Json,
+  /// YAML, in the same envelope as JSON
+  Yaml,
+  /// CSV, for sub-commands with tabular output; others write text
+  Csv,
+  /// Tab separated lines without a header, for sub-commands with tabular output; others write
+  /// text. The columns of each sub-command never change, so that scripts can rely on them.
+  Porcelain,
+}
+
+/// Default format of dates in human readable output
+pub(crate) const DEFAULT_DATE_FORMAT: &str = "%Y-%m-%d %H:%M";
+
+/// How dates are written in human readable output
+#[derive(Debug, Clone)]
+pub(crate) struct DateFormat {
+  /// strftime format
+  format: String,
+  /// Write dates in UTC rather than the local time zone
+  utc: bool,
+}
+
+/// Envelope wrapping the JSON output of every sub-command, so that all output has the same top
+/// level structure
+#[derive(Serialize)]
+struct JsonEnvelope<'a, T: Serialize> {
+  /// Version of the output structure (JSON_SCHEMA_VERSION)
+  schema_version: u32,
+  /// Sub-command which produced the output, e.g. "vh info"
+  command: &'a str,
+  /// Sub-command output
+  result: &'a T,
+}
+
+/// Print sub-command output to stdout, wrapped in the standard JSON envelope, as JSON or YAML
+pub(crate) fn print_json<T: Serialize>(command: &str, result: &T) {
+  let stdout = io::stdout();
+  if let Err(e) = write_json(&mut stdout.lock(), command, result) {
+    quit_write_error(&e);
+  }
+}
+
+/// Write sub-command output, wrapped in the standard JSON envelope, as JSON or YAML
+pub(crate) fn write_json<W: ?Sized, T: Serialize>(writer: &mut W, command: &str, result: &T) -> io::Result<()>
+  where W: Write {
+  let envelope = JsonEnvelope {
+    schema_version: JSON_SCHEMA_VERSION,
+    command,
+    result,
+  };
+  if YAML.load(Ordering::Relaxed) {
+    write!(writer, "{}", serde_yaml::to_string(&envelope).unwrap())?;
+  } else {
+    writeln!(writer, "{}", serde_json::to_string(&envelope).unwrap())?;
+  }
+  writer.flush()
+}
+
+impl OutputFormat {
+  /// Parse from the name used on the CLI
+  pub(crate) fn from_name(name: &str) -> Option<Self> {
+    match name {
+      "text" => Some(OutputFormat::Text),
+      "json" => Some(OutputFormat::Json),
+      "yaml" => Some(OutputFormat::Yaml),
+      "csv" => Some(OutputFormat::Csv),
+      "porcelain" => Some(OutputFormat::Porcelain),
+      _ => None
+    }
+  }
+
+  /// Whether the format is structured (JSON or YAML) rather than tabular
+  pub(crate) fn is_structured(&self) -> bool {
+    matches!(self, OutputFormat::Json | OutputFormat::Yaml)
+  }
+
+  /// Whether the format is tabular (CSV or porcelain)
+  pub(crate) fn is_tabular(&self) -> bool {
+    matches!(self, OutputFormat::Csv | OutputFormat::Porcelain)
+  }
+}
+
+/// Decide whether structured output is written as YAML rather than JSON
+pub(crate) fn init(format: OutputFormat) {
+  YAML.store(format == OutputFormat::Yaml, Ordering::Relaxed);
+}
+
+/// Print a table to stdout as CSV or porcelain lines
+pub(crate) fn print_table(format: OutputFormat, header: &[&str], rows: &[Vec<String>]) {
+  let stdout = io::stdout();
+  let mut writer = stdout.lock();
+  if let Err(e) = write_table(&mut writer, format, header, rows) {
+    quit_write_error(&e);
+  }
+}
+
+/// Write a table as CSV or porcelain lines. The columns of tables written as porcelain must never
+/// change.
+pub(crate) fn write_table<W: ?Sized>(writer: &mut W, format: OutputFormat, header: &[&str], rows: &[Vec<String>]) -> io::Result<()>
+  where W: Write {
+  match format {
+    OutputFormat::Porcelain => write_porcelain(writer, rows),
+    _ => write_csv(writer, header, rows)
+  }
+}
+
+/// Write a line for each row, with fields separated by tabs. Backslashes, tabs and line breaks in
+/// fields are escaped as \\, \t, \n and \r, so that each line splits into the same fields.
+fn write_porcelain<W: ?Sized>(writer: &mut W, rows: &[Vec<String>]) -> io::Result<()>
+  where W: Write {
+  for row in rows {
+    let fields: Vec<String> = row.iter()
+      .map(|f| f.replace('\\', "\\\\").replace('\t', "\\t").replace('\n', "\\n").replace('\r', "\\r"))
+      .collect();
+    writeln!(writer, "{}", fields.join("\t"))?;
+  }
+  writer.flush()
+}
+
+/// Write a CSV table: a header record and then a record for each row
+pub(crate) fn write_csv<W: ?Sized>(writer: &mut W, header: &[&str], rows: &[Vec<String>]) -> io::Result<()>
+  where W: Write {
+  let header: Vec<String> = header.iter().map(|h| h.to_string()).collect();
+  write_csv_record(writer, &header)?;
+  for row in rows {
+    write_csv_record(writer, row)?;
+  }
+  writer.flush()
+}
+
+/// Write one CSV record, quoting fields as RFC 4180 requires
+fn write_csv_record<W: ?Sized>(writer: &mut W, fields: &[String]) -> io::Result<()>
+  where W: Write {
+  let record: Vec<String> = fields.iter()
+    .map(|f| if f.contains(|c| c == ',' || c == '"' || c == '\r' || c == '\n') {
+      format!("\"{}\"", f.replace('"', "\"\""))
+    } else {
+      f.clone()
+    })
+    .collect();
+  write!(writer, "{}\r\n", record.join(","))
+}
+
+impl DateFormat {
+  /// Date format from a strftime format string, which must be valid
+  pub(crate) fn new(format: &str, utc: bool) -> Result<Self, String> {
+    if StrftimeItems::new(format).any(|item| matches!(item, Item::Error)) {
+      return Err(format!("Invalid date format '{}'", format));
+    }
+    Ok(DateFormat {
+      format: format.to_string(),
+      utc,
+    })
+  }
+
+  /// Format a date
+  pub(crate) fn format(&self, t: &DateTime<Local>) -> String {
+    if self.utc {
+      t.naive_utc().format(&self.format).to_string()
+    } else {
+      t.format(&self.format).to_string()
+    }
+  }
+}
+
+/// File written under a temporary name in the same directory, and renamed into place once
+/// complete, so that readers never see a partial file
+pub(crate) struct AtomicFile {
+  path: PathBuf,
+  temp_path: PathBuf,
+  writer: BufWriter<File>,
+}
+
+impl AtomicFile {
+  /// Create the temporary file for a file
+  pub(crate) fn create<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+    let path = path.as_ref().to_path_buf();
+    let file_name = path.file_name()
+      .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "Not a file name"))?;
+    let temp_path = path.with_file_name(format!(".{}.{}.tmp", file_name.to_string_lossy(), process::id()));
+    let writer = BufWriter::new(File::create(&temp_path)?);
+    Ok(AtomicFile {
+      path,
+      temp_path,
+      writer,
+    })
+  }
+
+  /// Flush the temporary file and rename it into place
+  pub(crate) fn commit(mut self) -> io::Result<()> {
+    self.writer.flush()?;
+    self.writer.get_ref().sync_all()?;
+    fs::rename(&self.temp_path, &self.path)
+  }
+}
+
+impl Write for AtomicFile {
+  fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+    self.writer.write(buf)
+  }
+
+  fn flush(&mut self) -> io::Result<()> {
+    self.writer.flush()
+  }
+}
+
+impl Drop for AtomicFile {
+  /// Remove the temporary file if it wasn't committed
+  fn drop(&mut self) {
+    let _ = fs::remove_file(&self.temp_path);
+  }
+}
+
+/// Destination of a report: stdout, or a file named with `--output` which is written atomically
+pub(crate) enum ReportOutput {
+  Stdout(io::Stdout),
+  File(AtomicFile),
+}
+
+impl ReportOutput {
+  /// Open the destination named by the `output` argument, or stdout, or quit if the file can't be
+  /// created. Reports written to files aren't colored.
+  pub(crate) fn open_or_quit(cli_matches: &ArgMatches) -> Self {
+    match cli_matches.value_of("output") {
+      Some(file_name) => match AtomicFile::create(file_name) {
+        Ok(file) => {
+          crate::color::init_stdout(false);
+          ReportOutput::File(file)
+        }
+        Err(e) => {
+          eprintln!("Error creating {}: {:?}", file_name, &e);
+          exit(crate::exit_codes::IO_ERR);
+        }
+      },
+      None => ReportOutput::Stdout(io::stdout())
+    }
+  }
+
+  /// Finish writing the report, renaming a file into place, or quit if there is an error
+  pub(crate) fn finish_or_quit(self) {
+    let finished = match self {
+      ReportOutput::Stdout(mut stdout) => stdout.flush(),
+      ReportOutput::File(file) => file.commit()
+    };
+    if let Err(e) = finished {
+      quit_write_error(&e);
+    }
+  }
+
+  /// Quit after an error writing the report, first removing a partly written file, which exiting
+  /// would otherwise leave behind
+  pub(crate) fn quit_write_error(self, e: &io::Error) -> ! {
+    drop(self);
+    quit_write_error(e)
+  }
+}
+
+impl Write for ReportOutput {
+  fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+    match self {
+      ReportOutput::Stdout(stdout) => stdout.write(buf),
+      ReportOutput::File(file) => file.write(buf)
+    }
+  }
+
+  fn flush(&mut self) -> io::Result<()> {
+    match self {
+      ReportOutput::Stdout(stdout) => stdout.flush(),
+      ReportOutput::File(file) => file.flush()
+    }
+  }
+}
+
+/// Quit after an error writing output
+pub(crate) fn quit_write_error(e: &io::Error) -> ! {
+  eprintln!("Error writing output: {:?}", e);
+  exit(crate::exit_codes::IO_ERR);
+}
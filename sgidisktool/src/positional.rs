@@ -0,0 +1,71 @@
+use std::fs::File;
+use std::io;
+use std::io::{Read, Seek, SeekFrom};
+
+/// Reader over a shared file using positional reads, which don't move the file's own cursor, so
+/// that several threads can read the same disk image independently
+pub(crate) struct PositionalReader<'a> {
+  file: &'a File,
+  /// Offset of the next read
+  pos: u64,
+}
+
+impl<'a> PositionalReader<'a> {
+  /// Create a reader positioned at the beginning of the file
+  pub(crate) fn new(file: &'a File) -> Self {
+    PositionalReader {
+      file,
+      pos: 0,
+    }
+  }
+}
+
+impl<'a> Read for PositionalReader<'a> {
+  fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+    let n = read_at(self.file, buf, self.pos)?;
+    self.pos += n as u64;
+    Ok(n)
+  }
+}
+
+impl<'a> Seek for PositionalReader<'a> {
+  fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+    let new_pos = match pos {
+      SeekFrom::Start(offset) => Some(offset),
+      SeekFrom::Current(delta) => offset_by(self.pos, delta),
+      SeekFrom::End(delta) => offset_by(self.file.metadata()?.len(), delta),
+    };
+
+    match new_pos {
+      Some(offset) => {
+        self.pos = offset;
+        Ok(offset)
+      }
+      None => Err(io::Error::new(io::ErrorKind::InvalidInput, "Invalid seek to a negative or overflowing position"))
+    }
+  }
+}
+
+/// Apply a signed offset to a position
+pub(crate) fn offset_by(pos: u64, delta: i64) -> Option<u64> {
+  if delta >= 0 {
+    pos.checked_add(delta as u64)
+  } else {
+    pos.checked_sub(delta.unsigned_abs())
+  }
+}
+
+/// Read from a file at an offset without using its cursor
+#[cfg(unix)]
+pub(crate) fn read_at(file: &File, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+  use std::os::unix::fs::FileExt;
+  file.read_at(buf, offset)
+}
+
+/// Read from a file at an offset. This moves the file's cursor on Windows, but reads made
+/// through PositionalReader don't depend on it.
+#[cfg(windows)]
+pub(crate) fn read_at(file: &File, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+  use std::os::windows::fs::FileExt;
+  file.seek_read(buf, offset)
+}
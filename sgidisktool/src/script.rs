@@ -0,0 +1,209 @@
+use std::cell::RefCell;
+use std::path::Path;
+use std::process::exit;
+use std::rc::Rc;
+
+use clap::ArgMatches;
+use rhai::{Array, Dynamic, Engine, EvalAltResult, Map, Scope};
+
+use sgidisklib::efs::{Efs, Inode};
+use sgidisklib::efs::dir::Directory;
+use sgidisklib::progress::CancellationToken;
+use sgidisklib::volhdr::{PartitionType, SgidiskVolume};
+
+use crate::efs::OpenEfs;
+use crate::image::DiskImage;
+use crate::logging::TracingReader;
+use crate::OpenVolume;
+
+/// Disk image shared by the functions a script calls
+struct ScriptImage {
+  disk_file: TracingReader<DiskImage>,
+  volume_header: SgidiskVolume,
+  /// EFS filesystem scripts read, if there is one
+  efs: Option<Efs>,
+}
+
+/// Result of a function called by a script
+type ScriptResult<T> = Result<T, Box<EvalAltResult>>;
+
+/// Script tool entry point: run a Rhai script with functions to read the disk image's Volume
+/// Header and walk and read an EFS filesystem on it
+pub(crate) fn subcommand(disk_file_name: &str, cli_matches: &ArgMatches) {
+  let script = cli_matches.value_of("script").unwrap();
+  let mut vol = OpenVolume::open_or_quit(disk_file_name);
+
+  // The partition given, or else the first EFS partition, if there is one
+  let partition_id = match cli_matches.value_of("partition") {
+    Some(partition) => match partition.parse::<usize>() {
+      Ok(id) => Some(id),
+      Err(_) => {
+        eprintln!("Invalid partition ID '{}'", partition);
+        exit(crate::exit_codes::CLI_ARG_ERROR);
+      }
+    },
+    None => vol.volume_header.partitions.iter()
+      .position(|p| p.in_use() && p.partition_type == PartitionType::Efs)
+  };
+  let efs = partition_id.map(|id| {
+    let OpenEfs { efs, .. } = OpenEfs::open_or_quit(&mut vol, id);
+    efs
+  });
+  let OpenVolume { disk_file, volume_header, .. } = vol;
+  let image = Rc::new(RefCell::new(ScriptImage {
+    disk_file,
+    volume_header,
+    efs,
+  }));
+
+  let engine = engine(&image);
+  let mut scope = Scope::new();
+  let args: Array = cli_matches.values_of("args").into_iter().flatten()
+    .map(|arg| Dynamic::from(arg.to_string()))
+    .collect();
+  scope.push_constant("ARGS", args);
+  if let Err(e) = engine.run_file_with_scope(&mut scope, Path::new(script).to_path_buf()) {
+    eprintln!("Error running script '{}': {}", script, &e);
+    exit(crate::exit_codes::SCRIPT_ERR);
+  }
+}
+
+/// Scripting engine with the functions scripts call to read the disk image
+fn engine(image: &Rc<RefCell<ScriptImage>>) -> Engine {
+  let mut engine = Engine::new();
+
+  let img = image.clone();
+  engine.register_fn("volume", move || volume_map(&img.borrow().volume_header));
+
+  let img = image.clone();
+  engine.register_fn("walk", move || -> ScriptResult<Array> {
+    let mut img = img.borrow_mut();
+    let ScriptImage { disk_file, efs, .. } = &mut *img;
+    let efs = efs_or_err(efs)?;
+    let mut entries = Array::new();
+    let walk = efs.walk(disk_file, |path, inode_id, inode| entries.push(Dynamic::from_map(inode_map(path, inode_id, inode))),
+                        &CancellationToken::new(), |_| {});
+    if let Some((path, e, )) = walk.unreadable.first() {
+      return Err(format!("Unable to read directory '{}': {:?}", path, e).into());
+    }
+    Ok(entries)
+  });
+
+  let img = image.clone();
+  engine.register_fn("stat", move |path: &str| -> ScriptResult<Map> {
+    let mut img = img.borrow_mut();
+    let ScriptImage { disk_file, efs, .. } = &mut *img;
+    let efs = efs_or_err(efs)?;
+    let (inode_id, inode, ) = efs.lookup_path(disk_file, path)
+      .map_err(|e| format!("Unable to look up '{}': {:?}", path, &e))?;
+    Ok(inode_map(path, inode_id, &inode))
+  });
+
+  let img = image.clone();
+  engine.register_fn("ls", move |path: &str| -> ScriptResult<Array> {
+    let mut img = img.borrow_mut();
+    let ScriptImage { disk_file, efs, .. } = &mut *img;
+    let efs = efs_or_err(efs)?;
+    let dir = efs.lookup_path(disk_file, path)
+      .and_then(|(inode_id, _, )| Directory::read_dir(disk_file, efs, inode_id))
+      .map_err(|e| format!("Unable to list '{}': {:?}", path, &e))?;
+    Ok(dir.entries.iter()
+      .filter(|(name, _, )| *name != "." && *name != "..")
+      .map(|(name, (inode_id, inode, ), )| Dynamic::from_map(inode_map(&format!("{}/{}", path.trim_end_matches('/'), name), *inode_id, inode)))
+      .collect())
+  });
+
+  let img = image.clone();
+  engine.register_fn("read", move |path: &str| -> ScriptResult<Dynamic> {
+    read_file(&img, path).map(Dynamic::from_blob)
+  });
+
+  let img = image.clone();
+  engine.register_fn("read_text", move |path: &str| -> ScriptResult<String> {
+    read_file(&img, path).map(|contents| String::from_utf8_lossy(&contents).to_string())
+  });
+
+  let img = image.clone();
+  engine.register_fn("read_link", move |path: &str| -> ScriptResult<String> {
+    let mut img = img.borrow_mut();
+    let ScriptImage { disk_file, efs, .. } = &mut *img;
+    let efs = efs_or_err(efs)?;
+    efs.lookup_path(disk_file, path)
+      .and_then(|(_, inode, )| efs.read_link(disk_file, &inode))
+      .map_err(|e| format!("Unable to read link '{}': {:?}", path, &e).into())
+  });
+
+  engine
+}
+
+/// EFS filesystem of the image, or an error for the script if there isn't one
+fn efs_or_err(efs: &Option<Efs>) -> ScriptResult<&Efs> {
+  efs.as_ref().ok_or_else(|| "The disk image has no EFS partition; give one with --partition".into())
+}
+
+/// Read the contents of a file
+fn read_file(image: &Rc<RefCell<ScriptImage>>, path: &str) -> ScriptResult<Vec<u8>> {
+  let mut img = image.borrow_mut();
+  let ScriptImage { disk_file, efs, .. } = &mut *img;
+  let efs = efs_or_err(efs)?;
+  let mut contents = Vec::new();
+  efs.lookup_path(disk_file, path)
+    .and_then(|(_, inode, )| efs.read_file(disk_file, &inode, &mut contents))
+    .map_err(|e| format!("Unable to read '{}': {:?}", path, &e))?;
+  Ok(contents)
+}
+
+/// Script representation of the Volume Header
+fn volume_map(vh: &SgidiskVolume) -> Map {
+  let partitions: Array = vh.partitions.iter().enumerate()
+    .filter(|(_, p, )| p.in_use())
+    .map(|(id, p, )| {
+      let mut m = Map::new();
+      m.insert("id".into(), (id as i64).into());
+      m.insert("type".into(), p.partition_type.to_string().into());
+      m.insert("start_block".into(), (p.block_start as i64).into());
+      m.insert("size_blocks".into(), (p.block_sz as i64).into());
+      m.insert("size_bytes".into(), (p.byte_len() as i64).into());
+      Dynamic::from_map(m)
+    })
+    .collect();
+  let files: Array = vh.files.iter()
+    .filter(|f| f.in_use())
+    .map(|f| {
+      let mut m = Map::new();
+      m.insert("name".into(), f.file_name.clone().unwrap_or_default().into());
+      m.insert("start_block".into(), (f.block_start as i64).into());
+      m.insert("size_bytes".into(), (f.file_sz as i64).into());
+      Dynamic::from_map(m)
+    })
+    .collect();
+
+  let mut m = Map::new();
+  m.insert("sector_sz".into(), (vh.sector_sz as i64).into());
+  m.insert("root_partition".into(), (vh.root_partition as i64).into());
+  m.insert("swap_partition".into(), (vh.swap_partition as i64).into());
+  m.insert("boot_file".into(), vh.boot_file.clone().map_or(Dynamic::UNIT, Dynamic::from));
+  m.insert("partitions".into(), Dynamic::from_array(partitions));
+  m.insert("files".into(), Dynamic::from_array(files));
+  m
+}
+
+/// Script representation of an entry's inode metadata
+fn inode_map(path: &str, inode_id: u64, inode: &Inode) -> Map {
+  let mut m = Map::new();
+  m.insert("path".into(), path.to_string().into());
+  m.insert("inode".into(), (inode_id as i64).into());
+  m.insert("type".into(), crate::efs::type_char(inode.inode_type).to_string().into());
+  m.insert("mode".into(), (inode.unix_mode as i64).into());
+  m.insert("uid".into(), (inode.owner_uid as i64).into());
+  m.insert("gid".into(), (inode.owner_gid as i64).into());
+  m.insert("nlink".into(), (inode.nlink as i64).into());
+  m.insert("size".into(), (inode.size as i64).into());
+  m.insert("atime".into(), inode.atime_raw.into());
+  m.insert("mtime".into(), inode.mtime_raw.into());
+  m.insert("ctime".into(), inode.ctime_raw.into());
+  m.insert("extents".into(), (inode.num_extents as i64).into());
+  m.insert("version".into(), (inode.version as i64).into());
+  m.insert("spare".into(), (inode.spare as i64).into());
+  m
+}
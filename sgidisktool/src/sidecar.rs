@@ -0,0 +1,114 @@
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io;
+use std::io::{BufWriter, Write};
+
+use serde::Serialize;
+
+use crate::hash::{HashAlgorithm, MultiHash, MultiHashResult};
+
+/// Identifier of the sidecar schema; changed whenever fields are changed or removed
+pub(crate) const SIDECAR_SCHEMA: &str = "sgidisk-fixity/1";
+
+/// Fixity record of an extraction: the digests of the disk image and of each extracted file,
+/// with the tool and parameters which produced them
+#[derive(Serialize)]
+pub(crate) struct Sidecar {
+  schema: &'static str,
+  tool: SidecarTool,
+  /// Time the record was written, in ISO 8601 UTC
+  created: String,
+  image: SidecarImage,
+  parameters: SidecarParameters,
+  files: Vec<SidecarFile>,
+}
+
+/// Tool which wrote a sidecar
+#[derive(Serialize)]
+struct SidecarTool {
+  name: &'static str,
+  version: &'static str,
+}
+
+/// Disk image which files were extracted from
+#[derive(Serialize)]
+struct SidecarImage {
+  file: String,
+  /// Size in bytes; for an EWF container, the size of its media
+  size_bytes: u64,
+  hash: MultiHashResult,
+  /// IRIX release identified on the filesystem files were extracted from, if any
+  #[serde(skip_serializing_if = "Option::is_none")]
+  irix_release: Option<String>,
+}
+
+/// Parameters of the command which extracted the files
+#[derive(Serialize)]
+pub(crate) struct SidecarParameters {
+  /// Sub-command, e.g. "efs cp"
+  pub(crate) command: String,
+  pub(crate) partition: usize,
+  pub(crate) src: String,
+  pub(crate) dest: String,
+  /// Options affecting what was extracted, by name
+  pub(crate) options: BTreeMap<String, String>,
+}
+
+/// Digests of one extracted file
+#[derive(Serialize)]
+struct SidecarFile {
+  /// Path within the EFS
+  src: String,
+  /// Host path of the extracted file
+  dest: String,
+  size_bytes: u64,
+  hash: MultiHashResult,
+}
+
+impl Sidecar {
+  /// Build a sidecar, hashing the disk image and each extracted (src, dest) file
+  pub(crate) fn new(disk_file_name: &str, algorithms: &[HashAlgorithm], parameters: SidecarParameters, files: &[(&str, &str, )], irix_release: Option<String>) -> io::Result<Self> {
+    let mut vol = match crate::StreamVolume::open(disk_file_name) {
+      Ok(vol) => vol,
+      Err(e) => return Err(io::Error::new(io::ErrorKind::Other, e.message))
+    };
+    let (size_bytes, hash, ) = MultiHash::hash_reader(&mut vol.reader, algorithms)?;
+
+    let files = files.iter()
+      .map(|(src, dest, )| {
+        let (size_bytes, hash, ) = MultiHash::hash_reader(&mut File::open(dest)?, algorithms)?;
+        Ok(SidecarFile {
+          src: src.to_string(),
+          dest: dest.to_string(),
+          size_bytes,
+          hash,
+        })
+      })
+      .collect::<io::Result<Vec<SidecarFile>>>()?;
+
+    Ok(Sidecar {
+      schema: SIDECAR_SCHEMA,
+      tool: SidecarTool {
+        name: env!("CARGO_PKG_NAME"),
+        version: env!("CARGO_PKG_VERSION"),
+      },
+      created: chrono::Utc::now().format("%Y-%m-%dT%H:%M:%SZ").to_string(),
+      image: SidecarImage {
+        file: disk_file_name.to_string(),
+        size_bytes,
+        hash,
+        irix_release,
+      },
+      parameters,
+      files,
+    })
+  }
+
+  /// Write as JSON to a file
+  pub(crate) fn write(&self, file_name: &str) -> io::Result<()> {
+    let mut writer = BufWriter::new(File::create(file_name)?);
+    serde_json::to_writer_pretty(&mut writer, self)?;
+    writeln!(writer)?;
+    writer.flush()
+  }
+}
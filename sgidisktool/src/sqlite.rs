@@ -0,0 +1,155 @@
+//! Recording results in a SQLite database given with --sqlite, so a large collection can be
+//! queried with SQL. Each command run on a disk image adds a row to `runs`, and its results to
+//! the table for them, referring to the run. Tables are created if the database doesn't have them.
+//!
+//! Schema (version 1, kept in `PRAGMA user_version`):
+//!
+//! - `runs(id, command, image, tool_version, created)`: a command (`hash`, `validate` or `efs cp`)
+//!   run on a disk image, named as given, at a time in ISO 8601 UTC
+//! - `hashes(run_id, item_type, item, algorithm, hash, short)`: a digest written by `hash`, as in
+//!   its CSV output; `short` is the number of bytes an item was short of its listed size, if any
+//! - `findings(run_id, check_name, target, status, detail)`: the outcome of a check run by
+//!   `validate`, where status is pass, warn or fail
+//! - `manifest_entries(run_id, src, path, inode, inode_type, mode, uid, gid, size_bytes, mtime,
+//!   device_major, device_minor, link, content_type, reason)`: an entry `efs cp` didn't extract,
+//!   as in its manifest
+
+use std::process::exit;
+
+use clap::ArgMatches;
+
+use crate::efs::manifest::ManifestEntry;
+use crate::validate::JsonCheck;
+
+/// Results of a command to record
+#[cfg_attr(not(feature = "sqlite"), allow(dead_code))]
+pub(crate) enum Record<'a> {
+  /// Records of a table of hashes, as `VolumeHashes::rows`
+  Hashes(&'a [Vec<String>]),
+  Findings(&'a [JsonCheck]),
+  Manifest(&'a [ManifestEntry]),
+}
+
+/// Record a command's results on a disk image in the database given with --sqlite, if any, or
+/// quit if they can't be
+pub(crate) fn record_or_quit(cli_matches: &ArgMatches, command: &str, disk_file_name: &str, record: Record) {
+  let db_file_name = match cli_matches.value_of("sqlite") {
+    Some(db_file_name) => db_file_name,
+    None => return
+  };
+  if cli_matches.is_present("dry-run") {
+    return;
+  }
+  #[cfg(feature = "sqlite")]
+  if let Err(e) = db::record(db_file_name, command, disk_file_name, record) {
+    eprintln!("Error writing SQLite database {}: {}", db_file_name, &e);
+    exit(crate::exit_codes::IO_ERR);
+  }
+  #[cfg(not(feature = "sqlite"))]
+  {
+    let _ = (db_file_name, command, disk_file_name, record, );
+    eprintln!("This build of sgidisktool can't write SQLite databases; rebuild it with the sqlite feature");
+    exit(crate::exit_codes::CLI_ARG_ERROR);
+  }
+}
+
+#[cfg(feature = "sqlite")]
+mod db {
+  use rusqlite::{params, Connection, Transaction};
+
+  use super::Record;
+
+  /// Version of the schema, bumped by any change to existing tables
+  const SCHEMA_VERSION: i64 = 1;
+
+  /// Tables and indexes, created if missing
+  const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS runs (
+      id INTEGER PRIMARY KEY,
+      command TEXT NOT NULL,
+      image TEXT NOT NULL,
+      tool_version TEXT NOT NULL,
+      created TEXT NOT NULL
+    );
+    CREATE TABLE IF NOT EXISTS hashes (
+      run_id INTEGER NOT NULL REFERENCES runs(id),
+      item_type TEXT NOT NULL,
+      item TEXT NOT NULL,
+      algorithm TEXT NOT NULL,
+      hash TEXT NOT NULL,
+      short INTEGER
+    );
+    CREATE INDEX IF NOT EXISTS hashes_hash ON hashes(hash);
+    CREATE TABLE IF NOT EXISTS findings (
+      run_id INTEGER NOT NULL REFERENCES runs(id),
+      check_name TEXT NOT NULL,
+      target TEXT NOT NULL,
+      status TEXT NOT NULL,
+      detail TEXT
+    );
+    CREATE TABLE IF NOT EXISTS manifest_entries (
+      run_id INTEGER NOT NULL REFERENCES runs(id),
+      src TEXT NOT NULL,
+      path TEXT NOT NULL,
+      inode INTEGER NOT NULL,
+      inode_type TEXT NOT NULL,
+      mode TEXT NOT NULL,
+      uid INTEGER NOT NULL,
+      gid INTEGER NOT NULL,
+      size_bytes INTEGER NOT NULL,
+      mtime INTEGER NOT NULL,
+      device_major INTEGER,
+      device_minor INTEGER,
+      link TEXT,
+      content_type TEXT,
+      reason TEXT NOT NULL
+    );
+  ";
+
+  /// Open or create a database and record a run's results in one transaction
+  pub(super) fn record(db_file_name: &str, command: &str, disk_file_name: &str, record: Record) -> Result<(), String> {
+    let mut conn = Connection::open(db_file_name).map_err(|e| e.to_string())?;
+    let version: i64 = conn.query_row("PRAGMA user_version", [], |row| row.get(0)).map_err(|e| e.to_string())?;
+    if version > SCHEMA_VERSION {
+      return Err(format!("Database has schema version {}, but this tool writes up to version {}", version, SCHEMA_VERSION));
+    }
+    let tx = conn.transaction().map_err(|e| e.to_string())?;
+    insert(&tx, command, disk_file_name, record)
+      .and_then(|_| tx.commit())
+      .map_err(|e| e.to_string())
+  }
+
+  /// Create any missing tables and insert a run and its results
+  fn insert(tx: &Transaction, command: &str, disk_file_name: &str, record: Record) -> rusqlite::Result<()> {
+    tx.execute_batch(SCHEMA)?;
+    tx.pragma_update(None, "user_version", SCHEMA_VERSION)?;
+    tx.execute("INSERT INTO runs (command, image, tool_version, created) VALUES (?1, ?2, ?3, ?4)",
+               params![command, disk_file_name, env!("CARGO_PKG_VERSION"), chrono::Utc::now().format("%Y-%m-%dT%H:%M:%SZ").to_string()])?;
+    let run_id = tx.last_insert_rowid();
+
+    match record {
+      Record::Hashes(rows) => {
+        let mut stmt = tx.prepare("INSERT INTO hashes (run_id, item_type, item, algorithm, hash, short) VALUES (?1, ?2, ?3, ?4, ?5, ?6)")?;
+        for row in rows {
+          stmt.execute(params![run_id, row[0], row[1], row[2], row[3], row[4].parse::<i64>().ok()])?;
+        }
+      }
+      Record::Findings(checks) => {
+        let mut stmt = tx.prepare("INSERT INTO findings (run_id, check_name, target, status, detail) VALUES (?1, ?2, ?3, ?4, ?5)")?;
+        for c in checks {
+          stmt.execute(params![run_id, c.check, c.target, format!("{:?}", c.status).to_lowercase(), c.detail])?;
+        }
+      }
+      Record::Manifest(entries) => {
+        let mut stmt = tx.prepare("INSERT INTO manifest_entries (run_id, src, path, inode, inode_type, mode, uid, gid, size_bytes, mtime, \
+                                   device_major, device_minor, link, content_type, reason) \
+                                   VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15)")?;
+        for e in entries {
+          stmt.execute(params![run_id, e.src, e.path, e.inode as i64, e.inode_type, e.mode, e.uid, e.gid, e.size_bytes as i64, e.mtime,
+                               e.device_major, e.device_minor, e.link, e.content_type, e.reason])?;
+        }
+      }
+    }
+    Ok(())
+  }
+}
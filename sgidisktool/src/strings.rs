@@ -0,0 +1,130 @@
+use std::io;
+use std::io::{Read, Seek, SeekFrom};
+use std::process::exit;
+
+use clap::ArgMatches;
+use serde::Serialize;
+
+use sgidisklib::efs::InodeType;
+use sgidisklib::strings::{DEFAULT_MIN_LEN, FoundString, StringScanner};
+
+use crate::config::Config;
+use crate::efs::OpenEfs;
+use crate::exit_codes::CommandError;
+use crate::OpenVolume;
+
+/// JSON representation of the strings found in a source
+#[derive(Serialize)]
+struct JsonStrings {
+  /// What was scanned, e.g. "partition 7 unallocated space"
+  source: String,
+  min_len: usize,
+  strings: Vec<JsonString>,
+}
+
+/// JSON representation of one string
+#[derive(Serialize)]
+struct JsonString {
+  offset: u64,
+  text: String,
+}
+
+/// Strings entry point: list the printable strings in a partition, volume file, EFS file or the
+/// unallocated blocks of an EFS
+pub(crate) fn subcommand(config: &Config, disk_file_name: &str, cli_matches: &ArgMatches) {
+  let format = config.format(cli_matches);
+  let min_len = match cli_matches.value_of("min-len").map(|n| n.parse::<usize>()) {
+    None => DEFAULT_MIN_LEN,
+    Some(Ok(n)) if n > 0 => n,
+    Some(_) => {
+      eprintln!("Invalid minimum string length: {}", cli_matches.value_of("min-len").unwrap());
+      exit(crate::exit_codes::CLI_ARG_ERROR);
+    }
+  };
+
+  let mut vol = OpenVolume::open_or_quit(disk_file_name);
+  let (source, found, ) = match scan(&mut vol, cli_matches, min_len) {
+    Ok(scanned) => scanned,
+    Err(e) => e.quit()
+  };
+
+  if format.is_structured() {
+    crate::output::print_json("strings", &JsonStrings {
+      source,
+      min_len,
+      strings: found.into_iter().map(|s| JsonString { offset: s.offset, text: s.text }).collect(),
+    });
+  } else if format.is_tabular() {
+    let rows: Vec<Vec<String>> = found.into_iter().map(|s| vec![s.offset.to_string(), s.text]).collect();
+    crate::output::print_table(format, &["offset", "string"], &rows);
+  } else {
+    for s in found {
+      println!("{:>10} {}", s.offset, s.text);
+    }
+  }
+}
+
+/// Scan the source chosen on the command line, returning its description and the strings found
+fn scan(vol: &mut OpenVolume, cli_matches: &ArgMatches, min_len: usize) -> Result<(String, Vec<FoundString>, ), CommandError> {
+  // A volume file stands alone; the other sources are within a partition
+  if let Some(name) = cli_matches.value_of("volume-file") {
+    let file = vol.volume_header.files.iter()
+      .find(|f| f.file_name.as_deref() == Some(name))
+      .ok_or_else(|| CommandError::new(crate::exit_codes::CLI_ARG_ERROR, format!("No volume file named '{}'", name)))?;
+    let (start, len, ) = (file.byte_start(), file.file_sz, );
+    let found = scan_region(vol, start, len, min_len)?;
+    return Ok((format!("volume file {}", name), found, ));
+  }
+
+  let partition = cli_matches.value_of("partition")
+    .ok_or_else(|| CommandError::new(crate::exit_codes::CLI_ARG_ERROR, "A partition (-p) or volume file (--volume-file) must be given".to_string()))?;
+  let partition_id = partition.parse::<usize>()
+    .map_err(|_| CommandError::new(crate::exit_codes::CLI_ARG_ERROR, format!("Invalid partition ID '{}'", partition)))?;
+
+  if let Some(path) = cli_matches.value_of("efs-file") {
+    let mut efs_vol = OpenEfs::open(vol, partition_id)?;
+    let (_, inode, ) = efs_vol.efs.lookup_path(&mut efs_vol.vol.disk_file, path)
+      .map_err(|e| CommandError::new(crate::exit_codes::for_lib_error(&e), format!("Unable to look up '{}': {:?}", path, &e)))?;
+    if inode.inode_type != InodeType::RegularFile {
+      return Err(CommandError::new(crate::exit_codes::CLI_ARG_ERROR, format!("'{}' is not a regular file", path)));
+    }
+    let mut scanner = StringScanner::new(0, min_len);
+    efs_vol.efs.read_file(&mut efs_vol.vol.disk_file, &inode, &mut scanner)
+      .map_err(|e| CommandError::new(crate::exit_codes::for_lib_error(&e), format!("Unable to read '{}': {:?}", path, &e)))?;
+    Ok((format!("partition {} file {}", partition_id, path), scanner.finish(), ))
+  } else if cli_matches.is_present("unallocated") {
+    let mut efs_vol = OpenEfs::open(vol, partition_id)?;
+    let bitmap = efs_vol.efs.read_bitmap(&mut efs_vol.vol.disk_file)
+      .map_err(|e| CommandError::new(crate::exit_codes::for_lib_error(&e), format!("Unable to read the free block bitmap: {:?}", &e)))?;
+    let mut scanner = StringScanner::new(0, min_len);
+    for range in efs_vol.efs.free_byte_ranges(&bitmap) {
+      scanner.seek(range.start);
+      copy_region(&mut efs_vol.vol.disk_file, range.start, range.end - range.start, &mut scanner)?;
+    }
+    Ok((format!("partition {} unallocated space", partition_id), scanner.finish(), ))
+  } else {
+    let (start, len, ) = match vol.volume_header.partitions.get(partition_id) {
+      Some(p) if p.in_use() => (p.byte_start(), p.byte_len(), ),
+      _ => return Err(CommandError::new(crate::exit_codes::CLI_ARG_ERROR, format!("Partition {} is not in use", partition_id)))
+    };
+    let found = scan_region(vol, start, len, min_len)?;
+    Ok((format!("partition {}", partition_id), found, ))
+  }
+}
+
+/// Strings in a region of the disk image, with their offsets in the image
+fn scan_region(vol: &mut OpenVolume, start: u64, len: u64, min_len: usize) -> Result<Vec<FoundString>, CommandError> {
+  let mut scanner = StringScanner::new(start, min_len);
+  copy_region(&mut vol.disk_file, start, len, &mut scanner)?;
+  Ok(scanner.finish())
+}
+
+/// Copy a region of the disk image to a scanner. A region running past the end of the image is
+/// scanned as far as the image goes.
+fn copy_region<R: ?Sized>(reader: &mut R, start: u64, len: u64, scanner: &mut StringScanner) -> Result<(), CommandError>
+  where R: Read + Seek {
+  reader.seek(SeekFrom::Start(start))
+    .and_then(|_| io::copy(&mut (&mut *reader).take(len), scanner))
+    .map(|_| ())
+    .map_err(|e| CommandError::new(crate::exit_codes::IO_ERR, format!("Error reading disk image: {:?}", &e)))
+}
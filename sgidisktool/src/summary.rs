@@ -0,0 +1,189 @@
+use std::process::exit;
+
+use clap::ArgMatches;
+use serde::Serialize;
+use tabled::{Table, Tabled};
+
+use sgidisklib::efs::{Efs, InodeType};
+use sgidisklib::progress::CancellationToken;
+use sgidisklib::volhdr::PartitionType;
+
+use crate::color::{paint, paint_table_rows, Style};
+use crate::config::Config;
+use crate::detect::{Container, JsonDetect};
+use crate::OpenVolume;
+
+/// JSON representation of an image summary
+#[derive(Serialize)]
+struct JsonSummary {
+  image: JsonDetect,
+  boot: Option<JsonBoot>,
+  filesystems: Vec<JsonEfsCounts>,
+}
+
+/// JSON representation of the boot configuration in the Volume Header
+#[derive(Serialize)]
+struct JsonBoot {
+  boot_file: Option<String>,
+  root_partition: usize,
+  swap_partition: usize,
+}
+
+/// JSON representation of the totals of an EFS
+#[derive(Default, Serialize)]
+struct JsonEfsCounts {
+  partition: usize,
+  directories: usize,
+  files: usize,
+  symlinks: usize,
+  /// Devices, FIFOs and sockets
+  special: usize,
+  /// Total size of regular files
+  file_bytes: u64,
+  /// Directories which couldn't be read, so whose contents aren't counted
+  unreadable_directories: usize,
+  /// Error reading the filesystem, if it couldn't be read at all
+  error: Option<String>,
+}
+
+/// Image summary entry point: a one-screen overview of the container, Volume Header, partitions,
+/// boot configuration and EFS contents
+pub(crate) fn subcommand(config: &Config, disk_file_name: &str, cli_matches: &ArgMatches) {
+  let json = config.json(cli_matches);
+  if disk_file_name == crate::STDIN_FILE_NAME {
+    eprintln!("The summary needs to seek within the disk image, so it can't be read from stdin");
+    exit(crate::exit_codes::CLI_ARG_ERROR);
+  }
+
+  let image = match crate::detect::detect(disk_file_name) {
+    Ok(image) => image,
+    Err(e) => {
+      eprintln!("Error while reading disk image '{}': {:?}", disk_file_name, &e);
+      exit(crate::exit_codes::IO_ERR);
+    }
+  };
+
+  // Volume Header details and filesystems can be read from images with a Volume Header at the
+  // start or in a PC partition, in a container which can be opened for random access
+  let readable = matches!(image.container, Container::Raw | Container::Ewf)
+    && image.volume_header.as_ref().map(|vh| vh.offset == 0 || vh.pc_partition.is_some()).unwrap_or(false);
+  let (boot, filesystems, ) = if readable {
+    let mut vol = OpenVolume::open_or_quit(disk_file_name);
+    let vh = &vol.volume_header;
+    let boot = JsonBoot {
+      boot_file: vh.boot_file.clone(),
+      root_partition: vh.root_partition,
+      swap_partition: vh.swap_partition,
+    };
+    (Some(boot), count_filesystems(&mut vol), )
+  } else {
+    (None, Vec::new(), )
+  };
+
+  let summary = JsonSummary {
+    image,
+    boot,
+    filesystems,
+  };
+  if json {
+    crate::output::print_json("info", &summary);
+  } else {
+    print_summary(&summary);
+  }
+}
+
+/// Count the entries of every EFS partition
+fn count_filesystems(vol: &mut OpenVolume) -> Vec<JsonEfsCounts> {
+  let sector_sz = vol.volume_header.sector_sz as u64;
+  let efs_partitions: Vec<(usize, u64, )> = vol.volume_header.partitions.iter().enumerate()
+    .filter(|(_, p, )| p.in_use() && p.partition_type == PartitionType::Efs)
+    .map(|(id, p, )| (id, p.byte_start(), ))
+    .collect();
+
+  efs_partitions.into_iter()
+    .map(|(partition, partition_start, )| {
+      let mut counts = JsonEfsCounts {
+        partition,
+        ..Default::default()
+      };
+      match Efs::read(&mut vol.disk_file, sector_sz, partition_start) {
+        Ok(efs) => count_entries(vol, &efs, &mut counts),
+        Err(e) => counts.error = Some(format!("{:?}", &e))
+      }
+      counts
+    })
+    .collect()
+}
+
+/// Count the entries of an EFS, walking its directories from the root
+fn count_entries(vol: &mut OpenVolume, efs: &Efs, counts: &mut JsonEfsCounts) {
+  let walk = efs.walk(&mut vol.disk_file, |_, _, inode| match inode.inode_type {
+    InodeType::Directory => {}
+    InodeType::RegularFile => {
+      counts.files += 1;
+      counts.file_bytes += inode.size;
+    }
+    InodeType::SymbolicLink => counts.symlinks += 1,
+    _ => counts.special += 1
+  }, &CancellationToken::new(), |_| {});
+  counts.directories = walk.directories as usize;
+  counts.unreadable_directories = walk.unreadable.len();
+}
+
+/// Print the summary
+fn print_summary(summary: &JsonSummary) {
+  crate::detect::print_detected(&summary.image);
+
+  if let Some(boot) = &summary.boot {
+    println!();
+    println!("{}", paint("Boot configuration:", Style::Heading));
+    println!("Boot file: {}", boot.boot_file.as_deref().unwrap_or("(none)"));
+    println!("Root partition ID: {}", boot.root_partition);
+    println!("Swap partition ID: {}", boot.swap_partition);
+  }
+
+  if summary.filesystems.is_empty() {
+    return;
+  }
+
+  #[derive(Tabled)]
+  struct DisplayEfs {
+    #[header("Partition")]
+    partition: usize,
+    #[header("Directories")]
+    directories: usize,
+    #[header("Files")]
+    files: usize,
+    #[header("Symlinks")]
+    symlinks: usize,
+    #[header("Special")]
+    special: usize,
+    #[header("File Bytes")]
+    file_bytes: u64,
+    #[header("Unreadable")]
+    unreadable: String,
+  }
+
+  let styles = summary.filesystems.iter()
+    .map(|fs| if fs.error.is_some() || fs.unreadable_directories > 0 { Some(Style::Warning) } else { Some(Style::Filesystem) })
+    .collect::<Vec<Option<Style>>>();
+  let tab = summary.filesystems.iter()
+    .map(|fs| DisplayEfs {
+      partition: fs.partition,
+      directories: fs.directories,
+      files: fs.files,
+      symlinks: fs.symlinks,
+      special: fs.special,
+      file_bytes: fs.file_bytes,
+      unreadable: match &fs.error {
+        Some(e) => format!("whole filesystem: {}", e),
+        None => format!("{} directories", fs.unreadable_directories)
+      },
+    })
+    .collect::<Vec<DisplayEfs>>();
+
+  println!();
+  println!("{}", paint("EFS contents:", Style::Heading));
+  let table = Table::new(tab).with(crate::table_fmt()).to_string();
+  print!("{}", paint_table_rows(&table, &styles));
+}
@@ -0,0 +1,490 @@
+use std::io::{Read, Seek, SeekFrom};
+use std::process::exit;
+
+use clap::ArgMatches;
+use serde::Serialize;
+use tabled::{Table, Tabled};
+
+use sgidisklib::efs::Efs;
+use sgidisklib::efs::bitmap::BlockDiscrepancy;
+use sgidisklib::efs::consistency::{DirectoryCheck, DuplicateName};
+use sgidisklib::efs::magic::ContentType;
+use sgidisklib::volhdr::{PartitionType, SgidiskVolume};
+use sgidisklib::volhdr::size::SizeVerdict;
+
+use crate::bootcd::JsonCdVerdict;
+use crate::color::{paint, paint_table_rows, Style};
+use crate::config::Config;
+use crate::sqlite::Record;
+use crate::OpenVolume;
+
+/// Outcome of a check
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum Status {
+  Pass,
+  /// Suspicious, but the image is still readable
+  Warn,
+  Fail,
+}
+
+/// JSON representation of one check
+#[derive(Serialize)]
+pub(crate) struct JsonCheck {
+  /// Name of the validator, e.g. "vh-checksum"
+  pub(crate) check: &'static str,
+  /// What was checked, e.g. "partition 7"
+  pub(crate) target: String,
+  pub(crate) status: Status,
+  pub(crate) detail: Option<String>,
+}
+
+/// JSON representation of a validation report
+#[derive(Serialize)]
+pub(crate) struct JsonValidation {
+  pub(crate) status: Status,
+  pub(crate) checks: Vec<JsonCheck>,
+  /// Whether an installation CD should boot, and on what, with --install-cd
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub(crate) install_cd: Option<JsonCdVerdict>,
+}
+
+/// Validation report being built
+#[derive(Default)]
+pub(crate) struct Report {
+  pub(crate) checks: Vec<JsonCheck>,
+}
+
+impl Report {
+  /// Record the outcome of a check
+  pub(crate) fn record(&mut self, check: &'static str, target: &str, status: Status, detail: Option<String>) {
+    self.checks.push(JsonCheck {
+      check,
+      target: target.to_string(),
+      status,
+      detail,
+    });
+  }
+
+  /// Worst outcome of any check
+  pub(crate) fn status(&self) -> Status {
+    self.checks.iter().map(|c| c.status).max().unwrap_or(Status::Pass)
+  }
+}
+
+/// Validate tool entry point: run every validator over the disk image and report the outcomes
+pub(crate) fn subcommand(config: &Config, disk_file_name: &str, cli_matches: &ArgMatches) {
+  let json = config.json(cli_matches);
+  let mut vol = OpenVolume::open_or_quit(disk_file_name);
+  let file_sz = match vol.disk_file.get_ref().len() {
+    Ok(sz) => sz,
+    Err(e) => {
+      eprintln!("Error while reading disk image: {:?}", &e);
+      exit(crate::exit_codes::IO_ERR);
+    }
+  };
+
+  let mut report = run_checks(&mut vol, file_sz);
+  let install_cd = match cli_matches.is_present("install-cd") {
+    true => Some(crate::bootcd::run_checks(&mut vol, &mut report)),
+    false => None
+  };
+  crate::sqlite::record_or_quit(cli_matches, "validate", disk_file_name, Record::Findings(&report.checks));
+  let status = report.status();
+  if json {
+    crate::output::print_json("validate", &JsonValidation {
+      status,
+      checks: report.checks,
+      install_cd,
+    });
+  } else {
+    print_report(report.checks, status);
+    if let Some(verdict) = &install_cd {
+      crate::bootcd::print_verdict(verdict);
+    }
+  }
+
+  match status {
+    Status::Fail => exit(crate::exit_codes::VALIDATE_ERR),
+    Status::Warn if config.strict(cli_matches) => exit(crate::exit_codes::STRICT_ERR),
+    _ => {}
+  }
+}
+
+/// Run every validator over a disk image of a given size
+pub(crate) fn run_checks(vol: &mut OpenVolume, file_sz: u64) -> Report {
+  let mut report = Report::default();
+  check_vh_checksum(vol, &mut report);
+  check_partition_layout(&vol.volume_header, file_sz, &mut report);
+  check_partition_alignment(&vol.volume_header, &mut report);
+  check_image_size(&vol.volume_header, file_sz, &mut report);
+  check_volume_files(&vol.volume_header, file_sz, &mut report);
+  check_boot_config(vol, &mut report);
+  check_efs(vol, &mut report);
+  report
+}
+
+/// Check the Volume Header's checksum
+fn check_vh_checksum(vol: &mut OpenVolume, report: &mut Report) {
+  let mut header = vec![0; SgidiskVolume::SIZE];
+  let read = vol.disk_file.seek(SeekFrom::Start(0))
+    .and_then(|_| vol.disk_file.read_exact(&mut header));
+  match read {
+    Ok(()) if SgidiskVolume::checksum_ok(&header) => report.record("vh-checksum", "volume header", Status::Pass, None),
+    Ok(()) => report.record("vh-checksum", "volume header", Status::Fail, Some("Checksum doesn't match".to_string())),
+    Err(e) => report.record("vh-checksum", "volume header", Status::Fail, Some(format!("Unable to read: {:?}", &e)))
+  }
+}
+
+/// Check that partitions lie within the image and the entire volume, and that filesystems don't
+/// overlap. Other overlaps are normal: e.g. partition 7 usually covers 0, 1 and 6.
+fn check_partition_layout(vh: &SgidiskVolume, file_sz: u64, report: &mut Report) {
+  let entire = vh.partitions.iter()
+    .find(|p| p.in_use() && p.partition_type == PartitionType::EntireVolume)
+    .map(|p| p.byte_range());
+  if entire.is_none() {
+    report.record("partition-layout", "volume header", Status::Warn, Some("No entire volume partition".to_string()));
+  }
+
+  let is_fs = |t: PartitionType| matches!(t, PartitionType::Efs | PartitionType::Xfs | PartitionType::XfsLog);
+  for (id, p, ) in vh.partitions.iter().enumerate().filter(|(_, p, )| p.in_use()) {
+    let target = format!("partition {}", id);
+    let range = p.byte_range();
+    let mut problems = Vec::new();
+    let mut status = Status::Pass;
+    if range.end > file_sz {
+      problems.push(format!("runs past the end of the disk image by {} bytes", range.end - file_sz));
+      status = Status::Fail;
+    }
+    if let Some(entire) = &entire {
+      if range.start < entire.start || range.end > entire.end {
+        problems.push("lies outside the entire volume partition".to_string());
+        status = status.max(Status::Warn);
+      }
+    }
+    if is_fs(p.partition_type) {
+      let overlapping: Vec<String> = vh.partitions.iter().enumerate()
+        .filter(|(other_id, other, )| *other_id != id && other.in_use() && is_fs(other.partition_type))
+        .filter(|(_, other, )| other.byte_start() < range.end && range.start < other.byte_range().end)
+        .map(|(other_id, _, )| other_id.to_string())
+        .collect();
+      if !overlapping.is_empty() {
+        problems.push(format!("overlaps filesystem partitions {}", overlapping.join(", ")));
+        status = status.max(Status::Warn);
+      }
+    }
+    let detail = if problems.is_empty() { None } else { Some(problems.join("; ")) };
+    report.record("partition-layout", &target, status, detail);
+  }
+}
+
+/// Check that partitions start on a cylinder boundary of the geometry in the Volume Header, as
+/// the header format asks. Some old firmware and tools misbehave on unaligned partitions.
+fn check_partition_alignment(vh: &SgidiskVolume, report: &mut Report) {
+  let geometry = match vh.geometry() {
+    Some(geometry) => geometry,
+    None => {
+      report.record("partition-alignment", "volume header", Status::Pass, Some("No geometry recorded to align to".to_string()));
+      return;
+    }
+  };
+
+  for (id, p, ) in vh.partitions.iter().enumerate().filter(|(_, p, )| p.in_use()) {
+    let target = format!("partition {}", id);
+    if geometry.is_aligned(p.block_start) {
+      report.record("partition-alignment", &target, Status::Pass, None);
+    } else {
+      report.record("partition-alignment", &target, Status::Warn,
+                    Some(format!("Starts at CHS {} of {}; the nearest cylinders start at blocks {} and {}", geometry.lba_to_chs(p.block_start),
+                                 geometry, geometry.align_down(p.block_start), geometry.align_up(p.block_start))));
+    }
+  }
+}
+
+/// Check the disk image's length against the volume size in the label, and the partitions against
+/// the recorded drive capacity
+fn check_image_size(vh: &SgidiskVolume, file_sz: u64, report: &mut Report) {
+  let check = vh.check_size(file_sz);
+  let (mut status, mut detail, ) = match check.verdict {
+    SizeVerdict::Exact => (Status::Pass, None, ),
+    SizeVerdict::Truncated { missing_bytes } => (Status::Fail, Some(format!("Image is truncated, {} bytes short of the volume", missing_bytes)), ),
+    SizeVerdict::Padded { extra_bytes } => (Status::Warn, Some(format!("Image is padded, {} bytes longer than the volume", extra_bytes)), ),
+    SizeVerdict::Unknown => (Status::Pass, Some("No volume size recorded to compare with".to_string()), )
+  };
+  if let Some(excess) = check.oversized_label {
+    let oversized = format!("Partitions run past the drive capacity by {} bytes", excess);
+    detail = Some(match detail {
+      Some(detail) => format!("{}; {}", detail, oversized),
+      None => oversized
+    });
+    status = status.max(Status::Warn);
+  }
+  report.record("image-size", "volume header", status, detail);
+}
+
+/// Check that volume files lie within the image and the volume header partition
+fn check_volume_files(vh: &SgidiskVolume, file_sz: u64, report: &mut Report) {
+  let vh_partition = vh.partitions.iter()
+    .find(|p| p.in_use() && p.partition_type == PartitionType::VolumeHeader)
+    .map(|p| p.byte_range());
+
+  for f in vh.files.iter().filter(|f| f.in_use()) {
+    let target = format!("volume file {}", f.file_name.as_deref().unwrap_or_default());
+    let start = f.byte_start();
+    let end = start + f.file_sz;
+    if end > file_sz {
+      report.record("volume-file-bounds", &target, Status::Fail, Some(format!("Runs past the end of the disk image by {} bytes", end - file_sz)));
+    } else if vh_partition.as_ref().map(|r| start < r.start || end > r.end).unwrap_or(false) {
+      report.record("volume-file-bounds", &target, Status::Warn, Some("Lies outside the volume header partition".to_string()));
+    } else {
+      report.record("volume-file-bounds", &target, Status::Pass, None);
+    }
+  }
+}
+
+/// Check that the boot file is in the volume directory and is an executable, and that the root
+/// and swap partitions are in use and of types that can hold them
+fn check_boot_config(vol: &mut OpenVolume, report: &mut Report) {
+  check_boot_partition(&vol.volume_header, "root-partition", "root", vol.volume_header.root_partition,
+                       &[PartitionType::Efs, PartitionType::Xfs, PartitionType::LogicalVolume, PartitionType::Xlv, PartitionType::Xvm], report);
+  check_boot_partition(&vol.volume_header, "swap-partition", "swap", vol.volume_header.swap_partition,
+                       &[PartitionType::Raw, PartitionType::LogicalVolume, PartitionType::RawLogicalVolume, PartitionType::Xlv, PartitionType::Xvm], report);
+
+  let boot_file = match &vol.volume_header.boot_file {
+    Some(boot_file) => boot_file.clone(),
+    None => {
+      report.record("boot-file", "volume header", Status::Pass, Some("No boot file named".to_string()));
+      return;
+    }
+  };
+  let target = format!("boot file {}", boot_file);
+  // A path, such as /unix, is loaded by sash from the root partition rather than the volume header
+  let file = vol.volume_header.files.iter()
+    .find(|f| f.file_name.as_deref() == Some(boot_file.as_str()))
+    .map(|f| (f.byte_start(), f.file_sz, ));
+  let (start, file_sz, ) = match file {
+    Some(file) => file,
+    None if boot_file.contains('/') => {
+      report.record("boot-file", &target, Status::Warn, Some("Not in the volume directory; it can only be loaded from the root partition".to_string()));
+      return;
+    }
+    None => {
+      report.record("boot-file", &target, Status::Fail, Some("Not in the volume directory".to_string()));
+      return;
+    }
+  };
+  report.record("boot-file", &target, Status::Pass, None);
+
+  let mut head = vec![0; file_sz.min(ContentType::SNIFF_SZ as u64) as usize];
+  let read = vol.disk_file.seek(SeekFrom::Start(start))
+    .and_then(|_| vol.disk_file.read_exact(&mut head));
+  match read.map(|_| ContentType::sniff(&head)) {
+    Ok(ContentType::Elf | ContentType::MipsCoff) => report.record("boot-executable", &target, Status::Pass, None),
+    Ok(content_type) => report.record("boot-executable", &target, Status::Fail,
+                                      Some(format!("Contents are {}, not an ELF or MIPS COFF executable", content_type.name()))),
+    Err(e) => report.record("boot-executable", &target, Status::Fail, Some(format!("Unable to read: {:?}", &e)))
+  }
+}
+
+/// Check that the root or swap partition index names an in-use partition of one of the expected
+/// types. Other types are unusual rather than wrong, since any partition can be mounted by hand.
+fn check_boot_partition(vh: &SgidiskVolume, check: &'static str, role: &str, id: usize, expected: &[PartitionType], report: &mut Report) {
+  let target = format!("{} partition {}", role, id);
+  match vh.partitions.get(id) {
+    None => report.record(check, &target, Status::Fail, Some(format!("No such partition; the table has {}", vh.partitions.len()))),
+    Some(p) if !p.in_use() => report.record(check, &target, Status::Fail, Some("Partition is not in use".to_string())),
+    Some(p) if !expected.contains(&p.partition_type) =>
+      report.record(check, &target, Status::Warn, Some(format!("Unexpected partition type {} for {}", p.partition_type, role))),
+    Some(_) => report.record(check, &target, Status::Pass, None)
+  }
+}
+
+/// Check the superblock of every EFS partition, and that every directory can be read
+fn check_efs(vol: &mut OpenVolume, report: &mut Report) {
+  let sector_sz = vol.volume_header.sector_sz as u64;
+  let efs_partitions: Vec<(usize, u64, u64, )> = vol.volume_header.partitions.iter().enumerate()
+    .filter(|(_, p, )| p.in_use() && p.partition_type == PartitionType::Efs)
+    .map(|(id, p, )| (id, p.byte_start(), p.byte_len(), ))
+    .collect();
+
+  for (id, partition_start, partition_len, ) in efs_partitions {
+    let target = format!("partition {}", id);
+    let efs = match Efs::read(&mut vol.disk_file, sector_sz, partition_start) {
+      Ok(efs) => efs,
+      Err(e) => {
+        report.record("efs-superblock", &target, Status::Fail, Some(format!("Unable to read: {:?}", &e)));
+        continue;
+      }
+    };
+    match efs.superblock_checksum_ok(&mut vol.disk_file) {
+      Ok(true) => report.record("efs-superblock", &target, Status::Pass, None),
+      Ok(false) => report.record("efs-superblock", &target, Status::Fail, Some("Checksum doesn't match".to_string())),
+      Err(e) => report.record("efs-superblock", &target, Status::Fail, Some(format!("Unable to read: {:?}", &e)))
+    }
+    check_efs_geometry(&vol.volume_header, &efs, partition_len, &target, report);
+    check_efs_bitmap(vol, &efs, &target, report);
+
+    let check = efs.check_directories(&mut vol.disk_file);
+    let walk = &check.walk;
+    let directories = walk.directories as usize + walk.unreadable.len();
+    match walk.unreadable.first() {
+      None => report.record("efs-directories", &target, Status::Pass, Some(format!("{} directories read", directories))),
+      Some((path, e, )) => report.record("efs-directories", &target, Status::Fail,
+                                         Some(format!("{} of {} directories unreadable, first {}: {:?}", walk.unreadable.len(), directories, path, e)))
+    }
+    check_efs_duplicate_names(&check.duplicate_names, &target, report);
+    check_efs_link_counts(&check, &target, report);
+  }
+}
+
+/// Check that no directory holds a name more than once. IRIX never creates such entries, and only
+/// the first can be looked up, so the others' files are hidden.
+fn check_efs_duplicate_names(duplicates: &[DuplicateName], target: &str, report: &mut Report) {
+  match duplicates.first() {
+    None => report.record("efs-duplicate-names", target, Status::Pass, None),
+    Some(first) => {
+      let ids: Vec<String> = first.inode_ids.iter().map(|id| id.to_string()).collect();
+      let path = format!("{}/{}", first.directory.trim_end_matches('/'), first.name);
+      report.record("efs-duplicate-names", target, Status::Fail,
+                    Some(format!("{} names held by more than one entry, first {} (inodes {})", duplicates.len(), path, ids.join(", "))))
+    }
+  }
+}
+
+/// Check an EFS's size against its partition, and the geometry it was made for against the
+/// Volume Header's device parameters. Mismatches usually mean the image was cut from the wrong
+/// offset or to the wrong length, or the filesystem was copied from another disk.
+fn check_efs_geometry(vh: &SgidiskVolume, efs: &Efs, partition_len: u64, target: &str, report: &mut Report) {
+  if efs.size > partition_len {
+    report.record("efs-geometry", target, Status::Fail,
+                  Some(format!("Filesystem size of {} bytes runs past the end of the partition by {} bytes", efs.size, efs.size - partition_len)));
+    return;
+  }
+
+  // Geometry fields left zero by either side can't be compared
+  let differs = |efs_value: u16, vh_value: u16| efs_value != 0 && vh_value != 0 && efs_value != vh_value;
+  let mut mismatches = Vec::new();
+  if differs(efs.track_sectors, vh.compat_sect) {
+    mismatches.push(format!("made for {} sectors per track (Volume Header has {})", efs.track_sectors, vh.compat_sect));
+  }
+  if differs(efs.heads, vh.compat_heads) {
+    mismatches.push(format!("made for {} heads (Volume Header has {})", efs.heads, vh.compat_heads));
+  }
+  if efs.size < partition_len {
+    mismatches.push(format!("size of {} bytes is {} bytes smaller than the partition", efs.size, partition_len - efs.size));
+  }
+
+  if mismatches.is_empty() {
+    report.record("efs-geometry", target, Status::Pass, None);
+  } else {
+    report.record("efs-geometry", target, Status::Warn, Some(format!("Filesystem {}", mismatches.join(", "))));
+  }
+}
+
+/// Check the free data block bitmap against the blocks used by every inode. Blocks marked free
+/// but in use could be handed out again and overwritten; blocks marked used but not in use are
+/// only lost space, left behind by a crash.
+fn check_efs_bitmap(vol: &mut OpenVolume, efs: &Efs, target: &str, report: &mut Report) {
+  let check = match efs.check_bitmap(&mut vol.disk_file) {
+    Ok(check) => check,
+    Err(e) => {
+      report.record("efs-bitmap", target, Status::Fail, Some(format!("Unable to read: {:?}", &e)));
+      return;
+    }
+  };
+
+  let describe = |what: &str, blocks: &BlockDiscrepancy| {
+    let examples: Vec<String> = blocks.examples.iter().map(|b| b.to_string()).collect();
+    let more = if blocks.count > blocks.examples.len() as u64 { ", ..." } else { "" };
+    format!("{} blocks marked {} (e.g. {}{})", blocks.count, what, examples.join(", "), more)
+  };
+  let mut problems = Vec::new();
+  if check.free_but_referenced.count > 0 {
+    problems.push(describe("free but in use", &check.free_but_referenced));
+  }
+  if check.used_but_unreferenced.count > 0 {
+    problems.push(describe("used but not in use", &check.used_but_unreferenced));
+  }
+  if !check.unreadable_inodes.is_empty() {
+    problems.push(format!("{} inodes unreadable, first {}", check.unreadable_inodes.len(), check.unreadable_inodes[0].0));
+  }
+
+  let status = if check.free_but_referenced.count > 0 {
+    Status::Fail
+  } else if problems.is_empty() {
+    Status::Pass
+  } else {
+    Status::Warn
+  };
+  let detail = match problems.is_empty() {
+    true => format!("{} of {} data blocks free", check.free_blocks, check.data_blocks),
+    false => problems.join("; ")
+  };
+  report.record("efs-bitmap", target, status, Some(detail));
+}
+
+/// Check that each inode's link count matches the directory entries referring to it. A count too
+/// low means the inode is freed while entries still refer to it, so its blocks can be handed out
+/// again; one too high only leaves it allocated once its entries are gone.
+fn check_efs_link_counts(check: &DirectoryCheck, target: &str, report: &mut Report) {
+  if !check.walk.unreadable.is_empty() {
+    report.record("efs-link-counts", target, Status::Warn,
+                  Some(format!("Not checked, as {} directories couldn't be read", check.walk.unreadable.len())));
+    return;
+  }
+
+  let mismatches = &check.link_count_mismatches;
+  let mut problems = Vec::new();
+  if let Some(first) = mismatches.first() {
+    problems.push(format!("{} inodes with the wrong link count, first {} (count {}, {} entries)", mismatches.len(), first.inode_id, first.nlink, first.references));
+  }
+  if !check.unreadable_inodes.is_empty() {
+    problems.push(format!("{} inodes unreadable, first {}", check.unreadable_inodes.len(), check.unreadable_inodes[0].0));
+  }
+
+  let status = if mismatches.iter().any(|m| (m.nlink as i64) < m.references as i64) {
+    Status::Fail
+  } else if problems.is_empty() {
+    Status::Pass
+  } else {
+    Status::Warn
+  };
+  let detail = if problems.is_empty() { None } else { Some(problems.join("; ")) };
+  report.record("efs-link-counts", target, status, detail);
+}
+
+/// Print the report as a table, followed by the overall outcome
+pub(crate) fn print_report(checks: Vec<JsonCheck>, status: Status) {
+  #[derive(Tabled)]
+  struct DisplayCheck {
+    #[header("Check")]
+    check: &'static str,
+    #[header("Target")]
+    target: String,
+    #[header("Status")]
+    status: String,
+    #[header("Detail")]
+    detail: String,
+  }
+
+  let style = |status: Status| match status {
+    Status::Pass => Style::Good,
+    Status::Warn => Style::Warning,
+    Status::Fail => Style::Error,
+  };
+  let styles = checks.iter()
+    .map(|c| if c.status == Status::Pass { None } else { Some(style(c.status)) })
+    .collect::<Vec<Option<Style>>>();
+  let tab = checks.into_iter()
+    .map(|c| DisplayCheck {
+      check: c.check,
+      target: c.target,
+      status: format!("{:?}", c.status).to_lowercase(),
+      detail: c.detail.unwrap_or_default(),
+    })
+    .collect::<Vec<DisplayCheck>>();
+
+  let table = Table::new(tab).with(crate::table_fmt()).to_string();
+  print!("{}", paint_table_rows(&table, &styles));
+  println!("Overall: {}", paint(&format!("{:?}", status).to_lowercase(), style(status)));
+}
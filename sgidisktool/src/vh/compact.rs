@@ -0,0 +1,118 @@
+use std::fs::OpenOptions;
+use std::path::Path;
+use std::process::exit;
+
+use clap::ArgMatches;
+use serde::Serialize;
+use tabled::{Table, Tabled};
+
+use sgidisklib::volhdr::compact::{compact, Compaction};
+
+use crate::color::{paint, Style};
+use crate::config::Config;
+use crate::detect::Container;
+
+/// JSON representation of a compaction
+#[derive(Serialize)]
+struct JsonCompaction {
+  moves: Vec<JsonMovedFile>,
+  reclaimed_blocks: u64,
+  reclaimed_bytes: u64,
+  /// Whether the disk image was left unchanged, as this was a dry run
+  dry_run: bool,
+}
+
+/// JSON representation of a moved volume file
+#[derive(Serialize, Tabled)]
+struct JsonMovedFile {
+  #[header("Name")]
+  file_name: String,
+  #[header("From Block")]
+  from_block: u64,
+  #[header("To Block")]
+  to_block: u64,
+  #[header("Size")]
+  size_bytes: u64,
+}
+
+/// Volume Header compaction entry point: move the volume files to close the gaps between them,
+/// rewriting the disk image in place
+pub(crate) fn subcommand(config: &Config, disk_file_name: &str, cli_matches: &ArgMatches) {
+  let json = config.json(cli_matches);
+  let dry_run = cli_matches.is_present("dry-run");
+
+  // Only a plain image (or device) with the Volume Header at its start can be written in place
+  match crate::detect::detect(disk_file_name) {
+    Ok(image) if image.container == Container::Raw && image.volume_header.as_ref().map_or(false, |vh| vh.offset == 0) => {}
+    Ok(_) => {
+      eprintln!("'{}' isn't a raw disk image starting with a Volume Header, so it can't be compacted in place", disk_file_name);
+      exit(crate::exit_codes::CLI_ARG_ERROR);
+    }
+    Err(e) => {
+      eprintln!("Error while reading disk image '{}': {:?}", disk_file_name, &e);
+      exit(crate::exit_codes::IO_ERR);
+    }
+  }
+
+  let vol = crate::OpenVolume::open_or_quit(disk_file_name);
+  let block_sz = vol.volume_header.block_sz();
+  let compaction = if dry_run {
+    vol.volume_header.plan_compaction()
+  } else {
+    drop(vol);
+    crate::confirm::check_device_or_quit(Path::new(disk_file_name));
+    let warnings = vec![format!("'{}' is modified in place; an interruption can leave volume files damaged, so keep a copy", disk_file_name)];
+    crate::confirm::confirm_or_quit(&format!("Compacting the volume files of '{}'", disk_file_name), &warnings, cli_matches.is_present("force"));
+    match OpenOptions::new().read(true).write(true).open(disk_file_name) {
+      Ok(mut file) => compact(&mut file),
+      Err(e) => {
+        eprintln!("Error opening disk image '{}' for writing: {:?}", disk_file_name, &e);
+        exit(crate::exit_codes::IO_ERR);
+      }
+    }
+  };
+  let compaction = match compaction {
+    Ok(compaction) => compaction,
+    Err(e) => {
+      eprintln!("Error compacting volume files: {}", &e);
+      exit(crate::exit_codes::for_lib_error(&e));
+    }
+  };
+
+  let json_compaction = JsonCompaction::from_compaction(compaction, block_sz, dry_run);
+  if json {
+    crate::output::print_json("vh compact", &json_compaction);
+  } else {
+    print_compaction(&json_compaction);
+  }
+}
+
+impl JsonCompaction {
+  fn from_compaction(compaction: Compaction, block_sz: u64, dry_run: bool) -> Self {
+    JsonCompaction {
+      moves: compaction.moves.into_iter()
+        .map(|m| JsonMovedFile {
+          file_name: m.file_name,
+          from_block: m.from_block,
+          to_block: m.to_block,
+          size_bytes: m.file_sz,
+        })
+        .collect(),
+      reclaimed_blocks: compaction.reclaimed_blocks,
+      reclaimed_bytes: compaction.reclaimed_blocks * block_sz,
+      dry_run,
+    }
+  }
+}
+
+/// Print the moved files and the space reclaimed
+fn print_compaction(compaction: &JsonCompaction) {
+  if compaction.moves.is_empty() {
+    println!("Volume files are already packed, nothing to move.");
+    return;
+  }
+  let heading = if compaction.dry_run { "Volume files which would be moved:" } else { "Volume files moved:" };
+  println!("{}", paint(heading, Style::Heading));
+  print!("{}", Table::new(&compaction.moves).with(crate::table_fmt()));
+  println!("Reclaimed {} blocks ({} bytes) after the last volume file", compaction.reclaimed_blocks, compaction.reclaimed_bytes);
+}
@@ -1,82 +1,193 @@
 use std::fs;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::process::exit;
 
 use clap::ArgMatches;
 use glob::Pattern;
+use serde::Serialize;
 
+use sgidisklib::SgidiskLibReadError;
+use sgidisklib::progress::CancellationToken;
+
+use crate::config::Config;
+use crate::exit_codes::CommandError;
+use crate::hash::HashAlgorithm;
 use crate::OpenVolume;
 
 /// Volume Header File copy entry point
-pub(crate) fn subcommand(disk_file_name: &str, cli_matches: &ArgMatches) {
-  let verbose = cli_matches.is_present("verbose");
+pub(crate) fn subcommand(config: &Config, disk_file_name: &str, cli_matches: &ArgMatches) {
+  let json = config.json(cli_matches);
+  let dry_run = cli_matches.is_present("dry-run");
+  // A dry run always lists what would be copied
+  let verbose = (crate::logging::verbosity(cli_matches) > 0 || dry_run) && !json;
+  // Verify copies using the configured hash algorithms
+  let verify = if cli_matches.is_present("verify") {
+    Some(config.hash.algorithms.as_slice())
+  } else {
+    None
+  };
 
-  // Compile glob pattern from source argument
   let src = cli_matches.value_of("src").unwrap();
+  let dest = cli_matches.value_of("dest").unwrap();
+  let mut vol = crate::OpenVolume::open_or_quit(disk_file_name);
+
+  // Refuse to write to devices without --force-device, and confirm before overwriting devices or
+  // the disk image
+  crate::confirm::check_device_or_quit(Path::new(dest));
+  let warnings = crate::confirm::destination_warnings(Path::new(dest), disk_file_name);
+  if !dry_run && !warnings.is_empty() {
+    let summary = format!("Copying volume header files matching '{}' to '{}'", src, dest);
+    crate::confirm::confirm_or_quit(&summary, &warnings, cli_matches.is_present("force"));
+  }
+
+  let copied = match copy_matching(&mut vol, src, dest, verbose, verify, dry_run) {
+    Ok(copied) => copied,
+    Err(e) => e.quit()
+  };
+
+  if json {
+    crate::output::print_json("vh cp", &copied);
+  }
+
+  // Fail if any copy did not verify
+  if copied.iter().any(|c| c.verified == Some(false)) {
+    exit(crate::exit_codes::VERIFY_ERR);
+  }
+  let failed = copied.iter().filter(|c| c.error.is_some()).count();
+  if let Some(exit_code) = crate::exit_codes::for_failures(failed, copied.len()) {
+    exit(exit_code);
+  }
+}
+
+/// Copy the volume header files matching a glob pattern to a destination file or directory. A
+/// dry run finds the files and destinations without writing anything.
+pub(crate) fn copy_matching(vol: &mut OpenVolume, src: &str, dest: &str, verbose: bool, verify: Option<&[HashAlgorithm]>, dry_run: bool) -> Result<Vec<JsonCopiedFile>, CommandError> {
+  // Compile glob pattern from source argument
   let src_pattern = match Pattern::new(src) {
     Ok(p) => p,
-    Err(e) => {
-      eprintln!("Error compiling glob pattern from '{}': {:?}", src, e);
-      exit(crate::exit_codes::GLOB_ERR);
-    }
+    Err(e) => return Err(CommandError::new(crate::exit_codes::GLOB_ERR, format!("Error compiling glob pattern from '{}': {:?}", src, e)))
   };
 
   // Figure out whether dest argument is a directory
-  let dest = cli_matches.value_of("dest").unwrap();
   let dest_is_dir = match fs::metadata(dest) {
     Ok(meta) => meta.is_dir(),
     Err(_) => false
   };
 
-  // Open volume and find matching volume header files
-  let mut vol = crate::OpenVolume::open_or_quit(disk_file_name);
-  let matches = matches(&vol, &src_pattern);
+  // Find matching volume header files
+  let matches = matches(vol, &src_pattern);
   let num_matches = matches.len();
 
   // If there is more than one matching file, they need to go to a named directory
   if num_matches > 1 && !dest_is_dir {
-    eprintln!("There were {} matching files but '{}' is not a directory!", num_matches, dest);
-    exit(crate::exit_codes::CLI_ARG_ERROR);
+    return Err(CommandError::new(crate::exit_codes::CLI_ARG_ERROR, format!("There were {} matching files but '{}' is not a directory!", num_matches, dest)));
   }
 
   // Copy files out
-  for id in matches {
-    cp(&mut vol, id, dest, dest_is_dir, verbose);
-  }
+  Ok(matches.into_iter()
+    .map(|id| if dry_run {
+      dry_run_cp(vol, id, dest, dest_is_dir, verbose)
+    } else {
+      cp(vol, id, dest, dest_is_dir, verbose, verify)
+    })
+    .collect::<Vec<JsonCopiedFile>>())
 }
 
-// Copy indicated file to destination
-fn cp(vol: &mut OpenVolume, id: usize, dest: &str, dest_is_dir: bool, verbose: bool) {
-  let vol_file = &mut vol.disk_file;
+/// JSON representation of one copied volume header file
+#[derive(Serialize)]
+pub(crate) struct JsonCopiedFile {
+  src: String,
+  dest: String,
+  size_bytes: u64,
+  pub(crate) verified: Option<bool>,
+  pub(crate) error: Option<String>,
+}
+
+/// Report the copy of the indicated file that would be made, without copying it
+fn dry_run_cp(vol: &OpenVolume, id: usize, dest: &str, dest_is_dir: bool, verbose: bool) -> JsonCopiedFile {
   let vh_file = &vol.volume_header.files[id];
   let vh_file_name = vh_file.file_name.as_ref().unwrap();
+  let path = dest_path(vh_file_name, dest, dest_is_dir);
 
-  // If destination is directory then append VH file name, otherwise use dest verbatim
+  let src_start = vh_file.byte_start();
+  if verbose {
+    println!("{} -> {} (bytes {}..{} of disk image)", vh_file_name, path.to_string_lossy(), src_start, src_start + vh_file.file_sz);
+  }
+
+  JsonCopiedFile {
+    src: vh_file_name.clone(),
+    dest: path.to_string_lossy().to_string(),
+    size_bytes: vh_file.file_sz,
+    verified: None,
+    error: None,
+  }
+}
+
+/// Destination path of a copied file. If destination is directory then append VH file name,
+/// otherwise use dest verbatim.
+fn dest_path(vh_file_name: &str, dest: &str, dest_is_dir: bool) -> PathBuf {
   let mut path = PathBuf::with_capacity(2);
   path.push(dest);
   if dest_is_dir {
     path.push(vh_file_name);
   }
+  path
+}
 
-  // Open destination file for writing
-  let mut dest_file = match fs::File::create(&path) {
-    Ok(f) => f,
-    Err(e) => {
-      eprintln!("Error opening {:?}: {:?}", &path, e);
-      exit(crate::exit_codes::IO_ERR);
-    }
-  };
+// Copy indicated file to destination
+fn cp(vol: &mut OpenVolume, id: usize, dest: &str, dest_is_dir: bool, verbose: bool, verify: Option<&[HashAlgorithm]>) -> JsonCopiedFile {
+  let vol_file = &mut vol.disk_file;
+  let vh_file = &vol.volume_header.files[id];
+  let vh_file_name = vh_file.file_name.as_ref().unwrap();
+
+  let path = dest_path(vh_file_name, dest, dest_is_dir);
 
-  // Perform copy
-  let src_start = vh_file.block_start * sgidisklib::efs::EFS_BLOCK_SZ as u64;
+  // Open destination file for writing, then perform copy
+  let src_start = vh_file.byte_start();
   let src_len = vh_file.file_sz;
-  match crate::cp(vol_file, src_start, src_len, &mut dest_file, 0) {
-    Ok(_) => if verbose {
-      println!("{} -> {}", vh_file_name, path.to_string_lossy());
-    },
+  let mut verified = None;
+  let copied = fs::File::create(&path)
+    .map_err(SgidiskLibReadError::from)
+    .and_then(|mut dest_file| sgidisklib::copy::volume_file(vol_file, vh_file, &mut dest_file, &CancellationToken::new(), |_| {}));
+  let error = match copied {
+    Ok(_) => {
+      // Optionally re-read source and destination to check the copy
+      if let Some(algorithms) = verify {
+        match crate::verify_copy(vol_file, src_start, src_len, &path, algorithms) {
+          Ok(matched) => {
+            if !matched {
+              eprintln!("Verification failed: {} -> {:?} differ", vh_file_name, &path);
+            }
+            verified = Some(matched);
+          }
+          Err(e) => {
+            eprintln!("Error verifying {} -> {:?}: {:?}", vh_file_name, &path, &e);
+            verified = Some(false);
+          }
+        }
+      }
+      if verbose {
+        let note = match verified {
+          Some(true) => " (verified)",
+          Some(false) => " (verification FAILED)",
+          None => ""
+        };
+        println!("{} -> {}{}", vh_file_name, path.to_string_lossy(), note);
+      }
+      None
+    }
     Err(e) => {
       eprintln!("Error: {} -> {:?}: {:?}", vh_file_name, &path, &e);
+      Some(format!("{:?}", &e))
     }
+  };
+
+  JsonCopiedFile {
+    src: vh_file_name.clone(),
+    dest: path.to_string_lossy().to_string(),
+    size_bytes: src_len,
+    verified,
+    error,
   }
 }
 
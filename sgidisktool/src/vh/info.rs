@@ -1,32 +1,63 @@
 use std::collections::BTreeMap;
+use std::process::exit;
 use clap::ArgMatches;
 use tabled::{Tabled, Table};
 use serde::Serialize;
-use serde_json;
+use tracing::warn;
 
-use sgidisklib::volhdr::{Partition, PartitionType, VolumeFile};
-use crate::OpenVolume;
+use sgidisklib::content::probe_partition;
+use sgidisklib::volhdr::{Partition, SgidiskVolume, VolumeDirectoryCapacity, VolumeFile};
+use sgidisklib::volhdr::geometry::{Chs, Geometry};
+use sgidisklib::volhdr::size::{SizeCheck, SizeVerdict};
+
+use crate::color::{paint, paint_table_rows, Style};
+use crate::config::Config;
+use crate::output::OutputFormat;
 
 /// Volume Header info entry point
-pub(crate) fn subcommand(disk_file_name: &str, cli_matches: &ArgMatches) {
-  let json = cli_matches.is_present("json");
+pub(crate) fn subcommand(config: &Config, disk_file_name: &str, cli_matches: &ArgMatches) {
+  let format = config.format(cli_matches);
 
-  let vol = crate::OpenVolume::open_or_quit(disk_file_name);
-  let json_vol_info = JsonVolumeInfo::from(&vol);
+  let mut vol = crate::StreamVolume::open_or_quit(disk_file_name);
+  let file_sz = match vol.disk_file_sz() {
+    Ok(sz) => sz,
+    Err(e) => {
+      eprintln!("Error while reading disk image: {:?}", &e);
+      exit(crate::exit_codes::IO_ERR);
+    }
+  };
+  let mut json_vol_info = JsonVolumeInfo::from(&vol.volume_header, file_sz);
+  // Contents can only be probed if the image can be read again, which a stream can't
+  if disk_file_name != crate::STDIN_FILE_NAME {
+    json_vol_info.probe_contents(&vol.volume_header, disk_file_name, file_sz);
+  }
+  let over_length = json_vol_info.over_length_count();
 
-  if json {
-    println!("{}", serde_json::to_string(&json_vol_info).unwrap())
+  if format.is_structured() {
+    crate::output::print_json("vh info", &json_vol_info);
+  } else if format.is_tabular() {
+    print_table(&json_vol_info, format);
   } else {
-    print_vh(json_vol_info, &vol);
+    print_vh(json_vol_info, &vol.volume_header, file_sz);
+  }
+
+  if config.strict(cli_matches) && over_length > 0 {
+    eprintln!("{} volume files or partitions run past the end of the disk image", over_length);
+    exit(crate::exit_codes::STRICT_ERR);
   }
 }
 
 /// Formatted print of Volume Header information
-fn print_vh(info: JsonVolumeInfo, vol: &OpenVolume) {
+fn print_vh(info: JsonVolumeInfo, vh: &SgidiskVolume, file_sz: u64) {
   println!("Sector size: {} bytes", info.sector_sz);
   println!("Command Tag Queueing: {} (depth {})", info.ctq_enabled, info.ctq_depth);
   println!("Root partition ID: {}", info.root_partition);
   println!("Swap partition ID: {}", info.swap_partition);
+  match &info.geometry {
+    Some(g) => println!("Geometry: {} cylinders, {} heads, {} sectors per track ({} blocks per cylinder)",
+                        g.cylinders, g.heads, g.sectors, g.blocks_per_cylinder),
+    None => println!("Geometry: not recorded")
+  }
 
   println!();
   if let Some(boot_file) = &info.boot_file {
@@ -34,26 +65,30 @@ fn print_vh(info: JsonVolumeInfo, vol: &OpenVolume) {
   } else {
     println!("No boot file listed.");
   }
-  println!("Volume Directory:");
+  println!("{}", paint("Volume Directory:", Style::Heading));
   print_voldir(info.vh_files);
+  if let Some(capacity) = &info.volume_directory {
+    println!("{} of {} directory slots free; {} of {} blocks free in the volume header partition, the largest run {} blocks ({} bytes)",
+             capacity.slots_free, capacity.slots_total, capacity.free_blocks, capacity.partition_blocks,
+             capacity.largest_free_blocks, capacity.largest_free_bytes);
+  }
 
   println!();
-  println!("Partitions:");
-  print_partitions(info.partitions);
-  let vh = &vol.volume_header;
-  if vh.partitions.len() > 10 && vh.partitions[10].partition_type == PartitionType::EntireVolume {
-    let p = &vh.partitions[10];
-    let vol_end = (p.block_start + p.block_sz) * sgidisklib::efs::EFS_BLOCK_SZ as u64;
-    let file_sz = vol.disk_file_meta.len();
-
-    let comparison = if vol_end > file_sz {
-      format!("past end of disk image by {} bytes!", vol_end - file_sz)
-    } else if vol_end < file_sz {
-      format!("smaller than disk image by {} bytes", file_sz - vol_end)
-    } else {
-      format!("equal to the disk image size at {} bytes", vol_end)
-    };
-    println!("Entire Volume (partition 10) is {}", comparison);
+  println!("{}", paint("Partitions:", Style::Heading));
+  print_partitions(info.partitions, vh);
+
+  let check = vh.check_size(file_sz);
+  match (check.verdict, check.volume_bytes, ) {
+    (SizeVerdict::Truncated { missing_bytes }, _, ) =>
+      println!("{}", paint(&format!("Volume runs past the end of the disk image by {} bytes!", missing_bytes), Style::Error)),
+    (SizeVerdict::Padded { extra_bytes }, _, ) =>
+      println!("{}", paint(&format!("Volume is smaller than the disk image by {} bytes", extra_bytes), Style::Warning)),
+    (SizeVerdict::Exact, Some(volume_bytes), ) =>
+      println!("{}", paint(&format!("Volume is equal to the disk image size at {} bytes", volume_bytes), Style::Good)),
+    _ => println!("No volume size recorded to compare with the disk image")
+  }
+  if let Some(excess) = check.oversized_label {
+    println!("{}", paint(&format!("Partitions run past the recorded drive capacity by {} bytes", excess), Style::Warning));
   }
 }
 
@@ -73,6 +108,11 @@ fn print_voldir(info: BTreeMap<usize, JsonVhFileInfo>) {
     over_length: String,
   }
 
+  // Highlight files which go past the end of the disk image
+  let styles = info.values()
+    .map(|file| file.over_length.map(|_| Style::Warning))
+    .collect::<Vec<Option<Style>>>();
+
   let file_tab = info.into_iter()
     .map(|(id, file, )| DisplayFile {
       id,
@@ -86,11 +126,12 @@ fn print_voldir(info: BTreeMap<usize, JsonVhFileInfo>) {
     })
     .collect::<Vec<DisplayFile>>();
 
-  print!("{}", Table::new(file_tab).with(crate::table_fmt()));
+  let table = Table::new(file_tab).with(crate::table_fmt()).to_string();
+  print!("{}", paint_table_rows(&table, &styles));
 }
 
 /// Print partition table nicely
-fn print_partitions(info: BTreeMap<usize, JsonPartitionInfo>) {
+fn print_partitions(info: BTreeMap<usize, JsonPartitionInfo>, vh: &SgidiskVolume) {
   #[derive(Tabled)]
   struct DisplayPartition {
     #[header("Id")]
@@ -103,10 +144,22 @@ fn print_partitions(info: BTreeMap<usize, JsonPartitionInfo>) {
     end_block: u64,
     #[header("Size (blocks)")]
     size_blocks: u64,
+    #[header("Cylinders")]
+    cylinders: String,
+    #[header("Contents")]
+    content: String,
     #[header("Over Length? (bytes)")]
     over_length: String,
   }
 
+  // Highlight partitions which go past the end of the disk image, otherwise color by type
+  let styles = info.iter()
+    .map(|(id, p, )| match p.over_length {
+      Some(_) => Some(Style::Warning),
+      None => Some(Style::for_partition_type(vh.partitions[*id].partition_type))
+    })
+    .collect::<Vec<Option<Style>>>();
+
   let part_tab = info.into_iter()
     .map(|(id, p, )| DisplayPartition {
       id,
@@ -114,6 +167,17 @@ fn print_partitions(info: BTreeMap<usize, JsonPartitionInfo>) {
       start_block: p.start_block,
       end_block: p.end_block,
       size_blocks: p.sz_blocks,
+      // Unaligned starts are marked, with their offset into the cylinder
+      cylinders: match (&p.start_chs, &p.end_chs, ) {
+        (Some(start), Some(end), ) if start.head == 0 && start.sector == 0 => format!("{}-{}", start.cylinder, end.cylinder),
+        (Some(start), Some(end), ) => format!("{}-{} (starts at {}/{})", start.cylinder, end.cylinder, start.head, start.sector),
+        _ => "".to_string()
+      },
+      content: match (p.content, p.content_description, ) {
+        (Some(content), Some(description), ) => format!("{} ({})", content, description),
+        (Some(content), None, ) => content.to_string(),
+        (None, _, ) => "".to_string()
+      },
       over_length: match p.over_length {
         Some(b) => format!("Yes ({})", b),
         None => "No".to_string()
@@ -121,28 +185,51 @@ fn print_partitions(info: BTreeMap<usize, JsonPartitionInfo>) {
     })
     .collect::<Vec<DisplayPartition>>();
 
-  print!("{}", Table::new(part_tab).with(crate::table_fmt()));
+  let table = Table::new(part_tab).with(crate::table_fmt()).to_string();
+  print!("{}", paint_table_rows(&table, &styles));
+}
+
+/// Print the volume directory and partition table as one CSV or porcelain table, with a record per
+/// volume file, partition or free run of the volume header partition
+fn print_table(info: &JsonVolumeInfo, format: OutputFormat) {
+  let opt = |v: Option<u64>| v.map(|v| v.to_string()).unwrap_or_default();
+  let files = info.vh_files.iter()
+    .map(|(id, f, )| vec!["volume_file".to_string(), id.to_string(), f.file_name.clone(), f.start_block.to_string(),
+                          String::new(), f.size_bytes.to_string(), opt(f.over_length)]);
+  let partitions = info.partitions.iter()
+    .map(|(id, p, )| vec!["partition".to_string(), id.to_string(), p.partition_type.clone(), p.start_block.to_string(),
+                          p.end_block.to_string(), p.size_bytes.to_string(), opt(p.over_length)]);
+  // Free runs of the volume header partition, where new volume files could go
+  let free = info.volume_directory.iter()
+    .flat_map(|c| &c.free_runs)
+    .map(|r| vec!["free".to_string(), String::new(), String::new(), r.start_block.to_string(),
+                  r.end_block.to_string(), r.size_bytes.to_string(), String::new()]);
+  let rows: Vec<Vec<String>> = files.chain(partitions).chain(free).collect();
+  crate::output::print_table(format, &["item", "id", "name", "start_block", "end_block", "size_bytes", "over_length"], &rows);
 }
 
 /// JSON representation of volume information
 #[derive(Serialize)]
-struct JsonVolumeInfo {
+pub(crate) struct JsonVolumeInfo {
   sector_sz: usize,
   ctq_enabled: bool,
   ctq_depth: u8,
   root_partition: usize,
   swap_partition: usize,
   boot_file: Option<String>,
+  /// Cylinders, heads and sectors, if recorded
+  geometry: Option<JsonGeometry>,
   vh_files: BTreeMap<usize, JsonVhFileInfo>,
+  /// Space for more volume files, if there is a volume header partition
+  volume_directory: Option<JsonDirectoryCapacity>,
   partitions: BTreeMap<usize, JsonPartitionInfo>,
+  /// Volume size described by the label against the disk image's length
+  image_size: JsonSizeCheck,
 }
 
 impl JsonVolumeInfo {
-  /// Create JsonVolumeInfo from OpenVolume
-  fn from(vol: &OpenVolume) -> Self {
-    let vh = &vol.volume_header;
-    let file_sz = vol.disk_file_meta.len();
-
+  /// Create JsonVolumeInfo from a Volume Header and the size of its disk image
+  pub(crate) fn from(vh: &SgidiskVolume, file_sz: u64) -> Self {
     let vh_files = vh.files.iter().enumerate()
       .filter(|(_id, vh_file, )| vh_file.in_use())
       .map(|(id, vh_file, )| (id, JsonVhFileInfo::from(vh_file, file_sz), ))
@@ -150,7 +237,7 @@ impl JsonVolumeInfo {
 
     let partitions = vh.partitions.iter().enumerate()
       .filter(|(_id, p, )| p.in_use())
-      .map(|(id, p, )| (id, JsonPartitionInfo::from(p, file_sz), ))
+      .map(|(id, p, )| (id, JsonPartitionInfo::from(p, file_sz, vh.geometry()), ))
       .collect::<BTreeMap<usize, JsonPartitionInfo>>();
 
     Self {
@@ -160,10 +247,50 @@ impl JsonVolumeInfo {
       root_partition: vh.root_partition,
       swap_partition: vh.swap_partition,
       boot_file: vh.boot_file.clone(),
+      geometry: vh.geometry().map(|g| JsonGeometry {
+        cylinders: g.cylinders,
+        heads: g.heads,
+        sectors: g.sectors,
+        blocks_per_cylinder: g.blocks_per_cylinder(),
+      }),
       vh_files,
+      volume_directory: vh.directory_capacity().map(|c| JsonDirectoryCapacity::from(&c, vh.block_sz())),
       partitions,
+      image_size: JsonSizeCheck::from(&vh.check_size(file_sz)),
+    }
+  }
+
+  /// Identify the contents of each partition which starts within the disk image, with the
+  /// content handlers built in or registered
+  pub(crate) fn probe_contents(&mut self, vh: &SgidiskVolume, disk_file_name: &str, file_sz: u64) {
+    let mut reader = match crate::image::DiskImage::open(disk_file_name) {
+      Ok(image) => image.with_offset(crate::image::offset()),
+      Err(e) => {
+        warn!("Unable to open disk image '{}' to probe partition contents: {}", disk_file_name, &e);
+        return;
+      }
+    };
+    for (id, info, ) in self.partitions.iter_mut() {
+      let start = vh.partitions[*id].byte_start();
+      if start >= file_sz {
+        continue;
+      }
+      match probe_partition(&mut reader, start, vh.partitions[*id].partition_type) {
+        Ok(identified) => {
+          info.content = identified.as_ref().map(|i| i.name);
+          info.content_description = identified.and_then(|i| i.description);
+        }
+        Err(e) => warn!("Unable to probe contents of partition {}: {:?}", id, &e)
+      }
     }
   }
+
+  /// Number of volume files and partitions which run past the end of the disk image
+  pub(crate) fn over_length_count(&self) -> usize {
+    let files = self.vh_files.values().filter(|f| f.over_length.is_some()).count();
+    let partitions = self.partitions.values().filter(|p| p.over_length.is_some()).count();
+    files + partitions
+  }
 }
 
 /// JSON representation of information for one volume header file
@@ -178,7 +305,7 @@ struct JsonVhFileInfo {
 impl JsonVhFileInfo {
   /// Create JsonVhFileInfo from VolumeFile
   fn from(f: &VolumeFile, file_sz: u64) -> Self {
-    let end_bytes = (f.block_start * sgidisklib::efs::EFS_BLOCK_SZ as u64) + f.file_sz;
+    let end_bytes = f.byte_range().end;
     let over_length = if end_bytes > file_sz {
       Some(end_bytes - file_sz)
     } else {
@@ -196,6 +323,118 @@ impl JsonVhFileInfo {
   }
 }
 
+/// JSON representation of the disk geometry
+#[derive(Serialize)]
+struct JsonGeometry {
+  cylinders: u16,
+  heads: u16,
+  sectors: u16,
+  blocks_per_cylinder: u64,
+}
+
+/// JSON representation of a cylinder, head, sector address
+#[derive(Serialize)]
+struct JsonChs {
+  cylinder: u64,
+  head: u64,
+  sector: u64,
+}
+
+impl JsonChs {
+  /// Create JsonChs from Chs
+  fn from(chs: Chs) -> Self {
+    Self {
+      cylinder: chs.cylinder,
+      head: chs.head,
+      sector: chs.sector,
+    }
+  }
+}
+
+/// JSON representation of the space for volume files
+#[derive(Serialize)]
+struct JsonDirectoryCapacity {
+  slots_used: usize,
+  slots_free: usize,
+  slots_total: usize,
+  partition_blocks: u64,
+  used_blocks: u64,
+  free_blocks: u64,
+  /// Largest volume file which fits without compacting
+  largest_free_blocks: u64,
+  largest_free_bytes: u64,
+  free_runs: Vec<JsonFreeRun>,
+}
+
+/// JSON representation of a run of free blocks in the volume header partition
+#[derive(Serialize)]
+struct JsonFreeRun {
+  start_block: u64,
+  end_block: u64,
+  size_bytes: u64,
+}
+
+impl JsonDirectoryCapacity {
+  /// Create JsonDirectoryCapacity from VolumeDirectoryCapacity, with blocks of a size
+  fn from(c: &VolumeDirectoryCapacity, block_sz: u64) -> Self {
+    Self {
+      slots_used: c.slots_used,
+      slots_free: c.slots_total - c.slots_used,
+      slots_total: c.slots_total,
+      partition_blocks: c.partition_blocks,
+      used_blocks: c.used_blocks,
+      free_blocks: c.free_blocks(),
+      largest_free_blocks: c.largest_free_blocks(),
+      largest_free_bytes: c.largest_free_blocks() * block_sz,
+      free_runs: c.free_runs.iter()
+        .map(|r| JsonFreeRun {
+          start_block: r.start,
+          end_block: r.end,
+          size_bytes: (r.end - r.start) * block_sz,
+        })
+        .collect(),
+    }
+  }
+}
+
+/// JSON representation of the volume size check
+#[derive(Serialize)]
+struct JsonSizeCheck {
+  image_bytes: u64,
+  drivecap_bytes: Option<u64>,
+  entire_volume_bytes: Option<u64>,
+  partitions_end_bytes: u64,
+  volume_bytes: Option<u64>,
+  /// "exact", "truncated", "padded" or "unknown"
+  verdict: &'static str,
+  /// Bytes missing from a truncated image, or extra in a padded one
+  difference_bytes: Option<u64>,
+  /// Bytes by which the partitions run past the drive capacity
+  oversized_label_bytes: Option<u64>,
+}
+
+impl JsonSizeCheck {
+  /// Create JsonSizeCheck from SizeCheck
+  fn from(check: &SizeCheck) -> Self {
+    let (verdict, difference_bytes, ) = match check.verdict {
+      SizeVerdict::Exact => ("exact", None, ),
+      SizeVerdict::Truncated { missing_bytes } => ("truncated", Some(missing_bytes), ),
+      SizeVerdict::Padded { extra_bytes } => ("padded", Some(extra_bytes), ),
+      SizeVerdict::Unknown => ("unknown", None, )
+    };
+    Self {
+      image_bytes: check.image_bytes,
+      drivecap_bytes: check.drivecap_bytes,
+      entire_volume_bytes: check.entire_volume_bytes,
+      partitions_end_bytes: check.partitions_end_bytes,
+      volume_bytes: check.volume_bytes,
+      verdict,
+      difference_bytes,
+      oversized_label_bytes: check.oversized_label,
+    }
+  }
+}
+
 /// JSON representation of information for one partition
 #[derive(Serialize)]
 struct JsonPartitionInfo {
@@ -203,14 +442,23 @@ struct JsonPartitionInfo {
   start_block: u64,
   end_block: u64,
   sz_blocks: u64,
+  size_bytes: u64,
   over_length: Option<u64>,
+  /// CHS address of the first block, if the geometry is recorded
+  start_chs: Option<JsonChs>,
+  /// CHS address of the last block, if the geometry is recorded
+  end_chs: Option<JsonChs>,
+  /// Name of the content handler which recognised the partition's contents, if probed
+  content: Option<&'static str>,
+  /// Description of the contents from their handler, e.g. a label
+  content_description: Option<String>,
 }
 
 impl JsonPartitionInfo {
-  /// Create JsonPartitionInfo from Partition
-  fn from(p: &Partition, file_sz: u64) -> Self {
+  /// Create JsonPartitionInfo from Partition, and the geometry to place it in cylinders
+  fn from(p: &Partition, file_sz: u64, geometry: Option<Geometry>) -> Self {
     let end_block = p.block_start + p.block_sz;
-    let end_byte = end_block * sgidisklib::efs::EFS_BLOCK_SZ as u64;
+    let end_byte = p.byte_range().end;
     let over_length = if end_byte > file_sz {
       Some(end_byte - file_sz)
     } else {
@@ -222,7 +470,12 @@ impl JsonPartitionInfo {
       start_block: p.block_start,
       end_block,
       sz_blocks: p.block_sz,
+      size_bytes: p.byte_len(),
       over_length,
+      start_chs: geometry.map(|g| JsonChs::from(g.lba_to_chs(p.block_start))),
+      end_chs: geometry.map(|g| JsonChs::from(g.lba_to_chs(end_block - 1))),
+      content: None,
+      content_description: None,
     }
   }
 }
\ No newline at end of file
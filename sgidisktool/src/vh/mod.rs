@@ -1,15 +1,19 @@
 use std::process::exit;
 use clap::ArgMatches;
 
-mod info;
-mod cp;
+use crate::config::Config;
+
+pub(crate) mod info;
+pub(crate) mod cp;
+mod compact;
 
 /// Volume Header tool entry point
-pub(crate) fn subcommand(disk_file_name: &str, cli_matches: &ArgMatches) {
+pub(crate) fn subcommand(config: &Config, disk_file_name: &str, cli_matches: &ArgMatches) {
   match cli_matches.subcommand_name() {
     // Volume Header tool
-    Some("info") => info::subcommand(disk_file_name, cli_matches.subcommand_matches("info").unwrap()),
-    Some("cp") => cp::subcommand(disk_file_name, cli_matches.subcommand_matches("cp").unwrap()),
+    Some("info") => info::subcommand(config, disk_file_name, cli_matches.subcommand_matches("info").unwrap()),
+    Some("cp") => cp::subcommand(config, disk_file_name, cli_matches.subcommand_matches("cp").unwrap()),
+    Some("compact") => compact::subcommand(config, disk_file_name, cli_matches.subcommand_matches("compact").unwrap()),
 
     // Unimplemented / unknown sub-command
     Some(subcommand_name) => {